@@ -0,0 +1,94 @@
+//! Sending of a 100 keystroke sequence on a loopback connection,
+//! run with `cargo bench --bench input`
+//!
+//! Compare one write per event with a batch of events, then the batch
+//! of an activated client
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rdp::core::client::{Connector, RdpClient};
+use rdp::core::event::{KeyboardEvent, RdpEvent};
+use rdp::core::input::write_fast_path_input;
+
+const KEYSTROKES: u16 = 100;
+
+/// Press and release of each key
+fn keystrokes() -> Vec<RdpEvent> {
+    (0..KEYSTROKES)
+        .flat_map(|i| [true, false].map(|down| RdpEvent::Key(KeyboardEvent { code: 0x10 + i % 26, down })))
+        .collect()
+}
+
+/// Connected stream whose data is read and dropped by a thread
+fn loopback() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    std::thread::spawn(move || std::io::copy(&mut server, &mut std::io::sink()));
+    stream
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)).unwrap()
+}
+
+/// Client of a server replaying a session without encryption
+/// from the connection confirm to the font map
+fn activated_client() -> RdpClient<TcpStream> {
+    let mut replay = fixture("x224_confirm_rdp.bin");
+    replay.extend(fixture("mcs_connect_response_no_encryption.bin"));
+    // attach user confirm then join confirm of the user and I/O channels
+    replay.extend([3, 0, 0, 11, 2, 0xf0, 0x80, 0x2e, 0, 0, 6]);
+    replay.extend([3, 0, 0, 15, 2, 0xf0, 0x80, 0x3e, 0, 0, 6, 3, 0xef, 3, 0xef]);
+    replay.extend([3, 0, 0, 15, 2, 0xf0, 0x80, 0x3e, 0, 0, 6, 3, 0xeb, 3, 0xeb]);
+    replay.extend(fixture("license_valid_client.bin"));
+    replay.extend(fixture("server_activation.bin"));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut server, _) = listener.accept().unwrap();
+        server.write_all(&replay).unwrap();
+        std::io::copy(&mut server, &mut std::io::sink())
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut client = Connector::new().disable_encryption().connect(stream).unwrap();
+    // demand active, synchronize, cooperate, granted control and font map
+    for _ in 0..5 {
+        client.read(|_| ()).unwrap();
+    }
+    client
+}
+
+fn bench_keystrokes(c: &mut Criterion) {
+    let events = keystrokes();
+    let mut stream = loopback();
+    let mut group = c.benchmark_group("100 keystrokes");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function("per event", |b| {
+        b.iter(|| {
+            for event in black_box(&events) {
+                write_fast_path_input(std::slice::from_ref(event), &mut stream).unwrap();
+            }
+        })
+    });
+    group.bench_function("batch", |b| b.iter(|| write_fast_path_input(black_box(&events), &mut stream).unwrap()));
+    group.finish();
+}
+
+fn bench_inject_event_batch(c: &mut Criterion) {
+    let events = keystrokes();
+    let mut client = activated_client();
+    let mut group = c.benchmark_group("100 keystrokes");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function("inject_event_batch", |b| b.iter(|| client.inject_event_batch(black_box(&events)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_keystrokes, bench_inject_event_batch);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+//! Parsing of a component from a buffer,
+//! run with `cargo bench --bench message`
+//!
+//! Compare a read through a `Cursor` with `Message::read_from_bytes`
+
+#[macro_use]
+extern crate rdp;
+
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rdp::model::data::{Component, DynOption, Message, MessageOption, U16, U32};
+
+/// Header followed by a payload of its length
+fn pdu() -> Component {
+    component![
+        "flags" => U32::LE(0),
+        "length" => DynOption::new(U16::LE(0), |length| MessageOption::Size("payload".to_string(), length.inner() as usize)),
+        "payload" => Vec::<u8>::new()
+    ]
+}
+
+fn bench_read(c: &mut Criterion) {
+    let mut data = vec![1, 0, 0, 0, 0, 4];
+    data.extend((0..1024).map(|i| i as u8));
+    let mut group = c.benchmark_group("component read");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("cursor", |b| {
+        b.iter(|| {
+            let mut message = pdu();
+            message.read(&mut Cursor::new(black_box(&data))).unwrap();
+            message
+        })
+    });
+    group.bench_function("read_from_bytes", |b| {
+        b.iter(|| {
+            let mut message = pdu();
+            message.read_from_bytes(black_box(&data)).unwrap();
+            message
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_read);
+criterion_main!(benches);
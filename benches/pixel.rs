@@ -0,0 +1,33 @@
+//! Pixel format conversion of a full HD frame,
+//! run with `cargo bench --bench pixel`
+//!
+//! Throughput is reported in pixels per second
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rdp::codec::rle::{rgb565torgb32, rgb565torgb32_scalar};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+
+/// Frame of pseudo random 16 bpp pixels
+fn full_hd_frame() -> Vec<u16> {
+    let mut seed: u32 = 0x1234_5678;
+    (0..WIDTH * HEIGHT)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u16
+        })
+        .collect()
+}
+
+fn bench_rgb565torgb32(c: &mut Criterion) {
+    let frame = full_hd_frame();
+    let mut group = c.benchmark_group("rgb565torgb32 1920x1080");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    group.bench_function("scalar", |b| b.iter(|| rgb565torgb32_scalar(black_box(&frame))));
+    group.bench_function("simd", |b| b.iter(|| rgb565torgb32(black_box(&frame))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_rgb565torgb32);
+criterion_main!(benches);
@@ -0,0 +1,69 @@
+//! RemoteFX decoding must stay under 50 ms for a full HD frame
+//! on a modern desktop, run with `cargo bench --bench rfx`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rdp::codec::rfx::rfx_decompress;
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+
+/// Size of the entropy encoded data of each tile component
+/// around 600 KB for the whole frame
+const COMPONENT_LENGTH: usize = 384;
+
+fn block(block_type: u16, body: &[u8]) -> Vec<u8> {
+    let mut result = block_type.to_le_bytes().to_vec();
+    result.extend_from_slice(&(body.len() as u32 + 6).to_le_bytes());
+    result.extend_from_slice(body);
+    result
+}
+
+/// Build a full HD frame with pseudo random coefficients
+fn full_hd_frame() -> Vec<u8> {
+    let tiles_x = (WIDTH as usize).div_ceil(64);
+    let tiles_y = (HEIGHT as usize).div_ceil(64);
+    let mut seed: u32 = 0x1234_5678;
+
+    let mut tiles = Vec::new();
+    for y in 0..tiles_y {
+        for x in 0..tiles_x {
+            let mut body = vec![0, 0, 0];
+            body.extend_from_slice(&(x as u16).to_le_bytes());
+            body.extend_from_slice(&(y as u16).to_le_bytes());
+            for _ in 0..3 {
+                body.extend_from_slice(&(COMPONENT_LENGTH as u16).to_le_bytes());
+            }
+            for _ in 0..3 * COMPONENT_LENGTH {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                body.push((seed >> 16) as u8);
+            }
+            tiles.extend(block(0xcac3, &body));
+        }
+    }
+
+    let mut tileset = vec![1, 0, 0xc2, 0xca, 0, 0, 0x51, 0x04, 1, 0x40];
+    tileset.extend_from_slice(&((tiles_x * tiles_y) as u16).to_le_bytes());
+    tileset.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+    tileset.extend_from_slice(&[0x66, 0x66, 0x77, 0x88, 0x98]);
+    tileset.extend(tiles);
+
+    let mut region = vec![1, 0, 1, 1, 0, 0, 0, 0, 0];
+    region.extend_from_slice(&(WIDTH as u16).to_le_bytes());
+    region.extend_from_slice(&(HEIGHT as u16).to_le_bytes());
+    region.extend_from_slice(&[0xc1, 0xca, 1, 0]);
+
+    let mut frame = block(0xccc6, &region);
+    frame.extend(block(0xccc7, &tileset));
+    frame
+}
+
+fn bench_rfx_decompress(c: &mut Criterion) {
+    let frame = full_hd_frame();
+    let mut output = vec![0; WIDTH as usize * HEIGHT as usize * 4];
+    c.bench_function("rfx_decompress 1920x1080", |b| {
+        b.iter(|| rfx_decompress(black_box(&frame), WIDTH, HEIGHT, &mut output).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_rfx_decompress);
+criterion_main!(benches);
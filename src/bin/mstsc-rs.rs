@@ -11,22 +11,32 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread::JoinHandle;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{mem, ptr, thread};
 
 use clap::Parser;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use libc::{fd_set, select, FD_SET};
 use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
-use rdp::core::client::{Connector, RdpClient};
-use rdp::core::event::{BitmapEvent, KeyboardEvent, PointerButton, PointerEvent, RdpEvent};
+use rdp::core::client::{set_tcp_keepalive, Connector, RdpClient};
+use rdp::core::event::{BitmapEvent, KeyboardEvent, PointerButton, PointerEvent, RdpEvent, Rect, SystemPointer};
 use rdp::core::gcc::KeyboardLayout;
+use rdp::core::global::WHEEL_DELTA;
+#[cfg(feature = "audio")]
+use rdp::core::rdpsnd::CpalOutput;
+use rdp::core::socks::ProxyConfig;
 use rdp::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 #[cfg(target_os = "windows")]
 use winapi::um::winsock2::{fd_set, select};
 
 const APPLICATION_NAME: &str = "mstsc-rs";
 
+/// Delay without window size change before resizing the desktop
+const RESIZE_DELAY: Duration = Duration::from_millis(500);
+
+/// Unanswered keep-alive probes before closing the connection
+const KEEPALIVE_RETRIES: u32 = 3;
+
 /// This is a function just to check if data
 /// is available on socket to work only in one thread
 #[cfg(target_os = "windows")]
@@ -66,29 +76,33 @@ pub unsafe fn transmute_vec<S, T>(mut vec: Vec<S>) -> Vec<T> {
 /// This function use unsafe copy
 /// to accelerate data transfer
 fn fast_bitmap_transfer(buffer: &mut Vec<u32>, width: usize, bitmap: BitmapEvent) -> RdpResult<()> {
-    let bitmap_dest_left = bitmap.dest_left as usize;
-    let bitmap_dest_right = bitmap.dest_right as usize;
-    let bitmap_dest_bottom = bitmap.dest_bottom as usize;
-    let bitmap_dest_top = bitmap.dest_top as usize;
-    let bitmap_width = bitmap.width as usize;
+    let dest = bitmap.dest_rect();
+    let src = bitmap.src_rect();
+    let invalid_size = || Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Image have invalide size"));
+    let last = |size: usize| u16::try_from(size).ok().and_then(|size| size.checked_sub(1));
+    let screen = match (last(width), last(buffer.len() / width.max(1))) {
+        (Some(right), Some(bottom)) => Rect { left: 0, top: 0, right, bottom },
+        _ => return Err(invalid_size()),
+    };
+    let bitmap_width = src.width() as usize;
 
     let data = bitmap.decompress()?;
+    if !screen.contains_rect(&dest)
+        || dest.width() > src.width()
+        || dest.height() > src.height()
+        || data.len() / 4 < bitmap_width * src.height() as usize
+    {
+        return Err(invalid_size());
+    }
 
     // Use some unsafe method to faster
     // data transfer between buffers
     unsafe {
         let data_aligned: Vec<u32> = transmute_vec(data);
-        for i in 0..(bitmap_dest_bottom - bitmap_dest_top + 1) {
-            let dest_i = (i + bitmap_dest_top) * width + bitmap_dest_left;
+        let count = dest.width() as usize;
+        for i in 0..dest.height() as usize {
+            let dest_i = (i + dest.top as usize) * width + dest.left as usize;
             let src_i = i * bitmap_width;
-            let count = bitmap_dest_right - bitmap_dest_left + 1;
-            if dest_i > buffer.len()
-                || dest_i + count > buffer.len()
-                || src_i > data_aligned.len()
-                || src_i + count > data_aligned.len()
-            {
-                return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Image have invalide size")));
-            }
             copy_nonoverlapping(
                 data_aligned.as_ptr().offset((src_i) as isize),
                 buffer.as_mut_ptr().offset(dest_i as isize),
@@ -100,20 +114,65 @@ fn fast_bitmap_transfer(buffer: &mut Vec<u32>, width: usize, bitmap: BitmapEvent
     Ok(())
 }
 
+/// Pointer shape sent by the server
+/// minifb only has system cursors so it's drawn over the desktop
+struct CursorShape {
+    hotspot_x: usize,
+    hotspot_y: usize,
+    width: usize,
+    height: usize,
+    /// RGBA pixels
+    pixels: Vec<u8>,
+}
+
+/// Blend the cursor over a copy of the window buffer
+/// (x, y) is the mouse position, where the hotspot is drawn
+fn draw_cursor(buffer: &[u32], width: usize, cursor: &CursorShape, x: usize, y: usize) -> Vec<u32> {
+    let mut result = buffer.to_vec();
+    let height = buffer.len() / width.max(1);
+    for row in 0..cursor.height {
+        for column in 0..cursor.width {
+            let (Some(dest_x), Some(dest_y)) =
+                ((x + column).checked_sub(cursor.hotspot_x), (y + row).checked_sub(cursor.hotspot_y))
+            else {
+                continue;
+            };
+            if dest_x >= width || dest_y >= height {
+                continue;
+            }
+            let pixel = &cursor.pixels[(row * cursor.width + column) * 4..][..4];
+            let alpha = u32::from(pixel[3]);
+            let dest = &mut result[dest_y * width + dest_x];
+            let blend = |source: u8, shift: u32| {
+                ((u32::from(source) * alpha + ((*dest >> shift) & 0xff) * (255 - alpha)) / 255) << shift
+            };
+            *dest = blend(pixel[0], 16) | blend(pixel[1], 8) | blend(pixel[2], 0);
+        }
+    }
+    result
+}
+
+/// minifb doesn't expose the minimized state
+/// but a minimized window has an empty client area
+fn is_minimized(window: &Window) -> bool {
+    let (width, height) = window.get_size();
+    width == 0 || height == 0
+}
+
 /// Translate minifb mouse to rdp-rs
+/// The first pressed button wins
 fn get_rdp_pointer_down(window: &Window) -> PointerButton {
-    if window.get_mouse_down(MouseButton::Left) {
-        PointerButton::Left
-    } else if window.get_mouse_down(MouseButton::Middle) {
-        PointerButton::Middle
-    } else if window.get_mouse_down(MouseButton::Right) {
-        PointerButton::Right
-    } else {
-        PointerButton::None
-    }
+    [MouseButton::Left, MouseButton::Middle, MouseButton::Right]
+        .into_iter()
+        .find(|button| window.get_mouse_down(*button))
+        .map_or(PointerButton::None, PointerButton::from)
 }
 
+/// Unknown key, never sent to the server
+const UNKNOWN_SCANCODE: u16 = 0x0000;
+
 /// Translate minifb key to scancode
+/// Every key of minifb is mapped, keys it may add later are unknown
 fn to_scancode(key: Key) -> u16 {
     match key {
         Key::Escape => 0x0001,
@@ -222,18 +281,57 @@ fn to_scancode(key: Key) -> u16 {
         Key::LeftSuper => 0xE05B,
         Key::RightSuper => 0xE05C,
         Key::Menu => 0xE05D,
-        _ => panic!("foo"),
+        Key::Unknown | Key::Count => UNKNOWN_SCANCODE,
+        #[allow(unreachable_patterns)]
+        _ => UNKNOWN_SCANCODE,
+    }
+}
+
+/// Parse the proxy from main args
+/// Expected format is socks5://host:port, socks4://host:port or http://host:port
+fn proxy_from_args(cli: &Cli) -> RdpResult<Option<ProxyConfig>> {
+    let proxy = match &cli.proxy {
+        Some(proxy) => proxy,
+        None => return Ok(None),
+    };
+    let invalid =
+        || Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Invalid proxy address [{}]", proxy)));
+    let (scheme, address) = proxy.split_once("://").ok_or_else(invalid)?;
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.to_string();
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+    match scheme {
+        "socks5" => Ok(Some(ProxyConfig::Socks5 {
+            host,
+            port,
+            username: cli.proxy_user.clone(),
+            password: cli.proxy_password.clone(),
+        })),
+        "socks4" => Ok(Some(ProxyConfig::Socks4 { host, port, user_id: cli.proxy_user.clone() })),
+        "http" => Ok(Some(ProxyConfig::Http {
+            host,
+            port,
+            auth: cli.proxy_user.clone().map(|user| (user, cli.proxy_password.clone().unwrap_or_default())),
+        })),
+        _ => Err(invalid()),
     }
 }
 
 /// Create a tcp stream from main args
 fn tcp_from_args(cli: &Cli) -> RdpResult<TcpStream> {
     // TCP connection
-    let addr = SocketAddr::new(cli.host, cli.port);
-    let tcp = TcpStream::connect(&addr).unwrap();
+    let tcp = match proxy_from_args(cli)? {
+        Some(proxy) => proxy.connect(&cli.host.to_string(), cli.port)?,
+        None => TcpStream::connect(SocketAddr::new(cli.host, cli.port))?,
+    };
     tcp.set_nodelay(true).map_err(|e| {
         Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Unable to set no delay option [{}]", e)))
     })?;
+    tcp.set_read_timeout(cli.read_timeout.map(Duration::from_millis))?;
+    tcp.set_write_timeout(cli.write_timeout.map(Duration::from_millis))?;
+    if let Some(keepalive) = cli.keepalive {
+        set_tcp_keepalive(&tcp, Duration::from_secs(keepalive), KEEPALIVE_RETRIES)?;
+    }
     Ok(tcp)
 }
 
@@ -248,8 +346,21 @@ fn rdp_from_args<S: Read + Write>(cli: &Cli, stream: S) -> RdpResult<RdpClient<S
         .blank_creds(cli.blank_creds)
         .layout(cli.layout)
         .check_certificate(cli.check_certificate)
-        .name(cli.name.to_string())
-        .use_nla(use_nla);
+        .socket_timeouts(cli.read_timeout.is_some() || cli.write_timeout.is_some())
+        .name_from_hostname()
+        .client_build(cli.build)
+        .client_product_id(cli.product_id)
+        .use_nla(use_nla)
+        .gfx(cli.gfx);
+
+    if let Some(name) = &cli.name {
+        rdp_connector = rdp_connector.name(name.clone());
+    }
+
+    #[cfg(feature = "audio")]
+    if !cli.no_audio {
+        rdp_connector = rdp_connector.audio(CpalOutput::new());
+    }
 
     if let Some(hash) = cli.hash.as_ref() {
         rdp_connector = rdp_connector.set_password_hash(hex::decode(hash).map_err(|e| {
@@ -270,7 +381,7 @@ fn window_from_args(cli: &Cli) -> RdpResult<Window> {
         "mstsc-rs Remote Desktop in Rust",
         usize::from(cli.width),
         usize::from(cli.height),
-        WindowOptions::default(),
+        WindowOptions { resize: true, ..WindowOptions::default() },
     )
     .map_err(|e| Error::RdpError(RdpError::new(RdpErrorKind::Unknown, &format!("Unable to create window [{}]", e))))?;
 
@@ -281,26 +392,40 @@ fn window_from_args(cli: &Cli) -> RdpResult<Window> {
 /// of receiving event (mostly bitmap event)
 /// And send back to the gui thread
 fn launch_rdp_thread<S: 'static + Read + Write + Send>(
-    handle: usize, rdp_client: Arc<Mutex<RdpClient<S>>>, sync: Arc<AtomicBool>, bitmap_channel: Sender<BitmapEvent>,
+    handle: usize, rdp_client: Arc<Mutex<RdpClient<S>>>, sync: Arc<AtomicBool>, display_channel: Sender<RdpEvent>,
 ) -> RdpResult<JoinHandle<()>> {
     // Create the rdp thread
     Ok(thread::spawn(move || {
         while wait_for_fd(handle as usize) && sync.load(Ordering::Relaxed) {
             let mut guard = rdp_client.lock().unwrap();
+            let mut disconnected = false;
             if let Err(Error::RdpError(e)) = guard.read(|event| match event {
-                RdpEvent::Bitmap(bitmap) => {
-                    bitmap_channel.send(bitmap).unwrap();
+                RdpEvent::Bitmap(_)
+                | RdpEvent::PointerShape { .. }
+                | RdpEvent::SystemPointer(_)
+                | RdpEvent::PointerPosition { .. }
+                | RdpEvent::Reactivated { .. } => {
+                    display_channel.send(event).unwrap();
+                }
+                RdpEvent::Reactivating => println!("{}: Server deactivated the session, waiting", APPLICATION_NAME),
+                RdpEvent::Disconnect { reason, error_info } => {
+                    println!("{}: Server closed the session {:?}: {}", APPLICATION_NAME, reason, error_info);
+                    disconnected = true;
                 }
+                RdpEvent::ErrorInfo(error_info) => println!("{}: {}", APPLICATION_NAME, error_info),
                 _ => println!("{}: ignore event", APPLICATION_NAME),
             }) {
                 match e.kind() {
                     RdpErrorKind::Disconnect => {
-                        println!("{}: Server ask for disconnect", APPLICATION_NAME);
+                        println!("{}: Server ask for disconnect: {}", APPLICATION_NAME, guard.error_info());
                     }
                     _ => println!("{}: {:?}", APPLICATION_NAME, e),
                 }
                 break;
             }
+            if disconnected {
+                break;
+            }
         }
     }))
 }
@@ -310,9 +435,9 @@ fn launch_rdp_thread<S: 'static + Read + Write + Send>(
 /// to RDP
 fn main_gui_loop<S: Read + Write>(
     mut window: Window, rdp_client: Arc<Mutex<RdpClient<S>>>, sync: Arc<AtomicBool>,
-    bitmap_receiver: Receiver<BitmapEvent>,
+    display_receiver: Receiver<RdpEvent>,
 ) -> RdpResult<()> {
-    let (width, height) = window.get_size();
+    let (mut width, mut height) = window.get_size();
     // Now we continue with the graphical main thread
     // Limit to max ~60 fps update rate
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
@@ -326,14 +451,88 @@ fn main_gui_loop<S: Read + Write>(
     // state for keyboard keys
     let mut last_keys = vec![];
 
+    // Pointer shape drawn over the desktop, None for the system cursor
+    let mut cursor: Option<CursorShape> = None;
+
+    // Last local mouse position sent to the server
+    let mut last_mouse_position = None;
+
+    // Pointer moved by the server, until the local mouse moves
+    // minifb can't move the system cursor so only the drawn shape follows it
+    let mut remote_pointer: Option<(usize, usize)> = None;
+
+    // Display updates are suppressed while minimized
+    let mut minimized = false;
+
+    // Window size waiting to be stable before resizing the desktop
+    let mut pending_resize: Option<((usize, usize), Instant)> = None;
+
     // Start the refresh loop
     while window.is_open() && sync.load(Ordering::Relaxed) {
         let now = Instant::now();
 
+        // Pause rendering on server side while minimized
+        if is_minimized(&window) != minimized {
+            let mut rdp_client_guard = rdp_client.lock().unwrap();
+            match rdp_client_guard.suppress_output(minimized) {
+                Ok(()) => minimized = !minimized,
+                Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::InvalidAutomata => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Resize the remote desktop once the user stopped resizing the window
+        let size = window.get_size();
+        if !minimized && size != (width, height) {
+            match pending_resize {
+                Some((pending, since)) if pending == size => {
+                    if since.elapsed() >= RESIZE_DELAY {
+                        pending_resize = None;
+                        (width, height) = size;
+                        buffer = vec![0; width * height];
+                        // The server only accepts an even width
+                        let result = rdp_client.lock().unwrap().resize_desktop(width as u16 & !1, height as u16);
+                        if let Err(e) = result {
+                            println!("{}: Unable to resize the desktop {:?}", APPLICATION_NAME, e);
+                        }
+                    }
+                }
+                _ => pending_resize = Some((size, Instant::now())),
+            }
+        }
+
         // Refresh loop must faster than 30 Hz
         while now.elapsed().as_micros() < 16600 * 2 {
-            match bitmap_receiver.try_recv() {
-                Ok(bitmap) => fast_bitmap_transfer(&mut buffer, width, bitmap)?,
+            match display_receiver.try_recv() {
+                Ok(RdpEvent::Bitmap(bitmap)) => match fast_bitmap_transfer(&mut buffer, width, bitmap) {
+                    // Updates sent before a desktop resize may not fit anymore
+                    Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::InvalidSize => (),
+                    result => result?,
+                },
+                Ok(RdpEvent::PointerShape {
+                    hotspot_x,
+                    hotspot_y,
+                    width: cursor_width,
+                    height: cursor_height,
+                    pixels,
+                }) => {
+                    window.set_cursor_visibility(false);
+                    cursor = Some(CursorShape {
+                        hotspot_x: hotspot_x as usize,
+                        hotspot_y: hotspot_y as usize,
+                        width: cursor_width as usize,
+                        height: cursor_height as usize,
+                        pixels,
+                    });
+                }
+                Ok(RdpEvent::SystemPointer(pointer)) => {
+                    window.set_cursor_visibility(pointer == SystemPointer::Default);
+                    cursor = None;
+                }
+                Ok(RdpEvent::PointerPosition { x, y }) => remote_pointer = Some((x as usize, y as usize)),
+                // The server redraws the whole desktop
+                Ok(RdpEvent::Reactivated { .. }) => buffer.fill(0),
+                Ok(_) => (),
                 Err(mpsc::TryRecvError::Empty) => break,
                 Err(mpsc::TryRecvError::Disconnected) => {
                     sync.store(false, Ordering::Relaxed);
@@ -342,8 +541,11 @@ fn main_gui_loop<S: Read + Write>(
             };
         }
 
+        // The server refuses input while display updates are suppressed
+        let input_allowed = !rdp_client.lock().is_ok_and(|client| client.is_output_suppressed());
+
         // Mouse position input
-        if let Some((x, y)) = window.get_mouse_pos(MouseMode::Clamp) {
+        if let Some((x, y)) = window.get_mouse_pos(MouseMode::Clamp).filter(|_| input_allowed) {
             let mut rdp_client_guard = rdp_client.lock().map_err(|e| {
                 Error::RdpError(RdpError::new(
                     RdpErrorKind::Unknown,
@@ -353,34 +555,61 @@ fn main_gui_loop<S: Read + Write>(
 
             // Button is down if not 0
             let current_button = get_rdp_pointer_down(&window);
-            rdp_client_guard.try_write(RdpEvent::Pointer(PointerEvent {
-                x: x as u16,
-                y: y as u16,
-                button: if last_button == current_button {
-                    PointerButton::None
-                } else {
-                    PointerButton::try_from(last_button as u8 | current_button as u8).unwrap()
-                },
-                down: (last_button != current_button) && last_button == PointerButton::None,
-            }))?;
+            // Only send changes to not cancel pointer moves of the server
+            let moved = last_mouse_position != Some((x, y));
+            if moved || last_button != current_button {
+                rdp_client_guard.try_write(RdpEvent::Pointer(PointerEvent {
+                    x: x as u16,
+                    y: y as u16,
+                    button: if last_button == current_button {
+                        PointerButton::None
+                    } else {
+                        PointerButton::try_from(last_button as u8 | current_button as u8).unwrap()
+                    },
+                    down: (last_button != current_button) && last_button == PointerButton::None,
+                    wheel_delta: 0,
+                }))?;
+            }
+            if moved {
+                remote_pointer = None;
+            }
 
             last_button = current_button;
+            last_mouse_position = Some((x, y));
+
+            // Scroll wheel, minifb gives a positive value to scroll up or right
+            if let Some((scroll_x, scroll_y)) = window.get_scroll_wheel() {
+                for (delta, positive, negative) in [
+                    (scroll_y, PointerButton::ScrollUp, PointerButton::ScrollDown),
+                    (scroll_x, PointerButton::ScrollRight, PointerButton::ScrollLeft),
+                ] {
+                    if delta != 0.0 {
+                        rdp_client_guard.try_write(RdpEvent::Pointer(PointerEvent {
+                            x: x as u16,
+                            y: y as u16,
+                            button: if delta > 0.0 { positive } else { negative },
+                            down: false,
+                            wheel_delta: (delta.abs() * WHEEL_DELTA as f32).min(i16::MAX as f32) as i16,
+                        }))?;
+                    }
+                }
+            }
         }
 
         // Keyboard inputs
         {
             let keys = window.get_keys();
-            if !keys.is_empty() {
+            if !keys.is_empty() && input_allowed {
                 let mut rdp_client_guard = rdp_client.lock().unwrap();
 
-                for key in last_keys.iter() {
+                for key in last_keys.iter().filter(|key| to_scancode(**key) != UNKNOWN_SCANCODE) {
                     if !keys.contains(key) {
                         rdp_client_guard
                             .try_write(RdpEvent::Key(KeyboardEvent { code: to_scancode(*key), down: false }))?
                     }
                 }
 
-                for key in keys.iter() {
+                for key in keys.iter().filter(|key| to_scancode(**key) != UNKNOWN_SCANCODE) {
                     if window.is_key_pressed(*key, KeyRepeat::Yes) {
                         rdp_client_guard
                             .try_write(RdpEvent::Key(KeyboardEvent { code: to_scancode(*key), down: true }))?
@@ -393,7 +622,16 @@ fn main_gui_loop<S: Read + Write>(
 
         // We unwrap here as we want this code to exit if it fails. Real applications
         // may want to handle this in a different way
-        window.update_with_buffer(&buffer, width, height).map_err(|e| {
+        let with_cursor;
+        let local_pointer = window.get_mouse_pos(MouseMode::Discard).map(|(x, y)| (x as usize, y as usize));
+        let frame = match (&cursor, remote_pointer.or(local_pointer)) {
+            (Some(cursor), Some((x, y))) => {
+                with_cursor = draw_cursor(&buffer, width, cursor, x, y);
+                &with_cursor
+            }
+            _ => &buffer,
+        };
+        window.update_with_buffer(frame, width, height).map_err(|e| {
             Error::RdpError(RdpError::new(RdpErrorKind::Unknown, &format!("Unable to update screen buffer [{}]", e)))
         })?;
     }
@@ -443,7 +681,7 @@ struct Cli {
     admin: bool,
 
     #[clap(long, default_value = "us")]
-    /// Keyboard layout: "us" or "fr"
+    /// Keyboard layout: name like "us" or identifier like "0x0415"
     layout: KeyboardLayout,
 
     #[clap(long = "auto", default_value_t = false, action)]
@@ -462,15 +700,59 @@ struct Cli {
     /// Disable Network Level Authentication and only use SSL
     disable_nla: bool,
 
-    #[clap(long, default_value_t=String::from("mstsc-rs"))]
-    /// Name of the client send to the server
-    name: String,
+    #[clap(long)]
+    /// Name of the client send to the server, the host name by default
+    name: Option<String>,
+
+    #[clap(long, default_value_t = 3790)]
+    /// Windows build number send to the server
+    build: u32,
+
+    #[clap(long = "product-id", default_value_t = 1)]
+    /// Product id send to the server
+    product_id: u16,
+
+    #[clap(long)]
+    /// Proxy: "socks5://host:port", "socks4://host:port" or "http://host:port"
+    proxy: Option<String>,
+
+    #[clap(long)]
+    /// Proxy username
+    proxy_user: Option<String>,
+
+    #[clap(long)]
+    /// Proxy password
+    proxy_password: Option<String>,
+
+    #[clap(long)]
+    /// Socket read timeout in milliseconds
+    read_timeout: Option<u64>,
+
+    #[clap(long)]
+    /// Socket write timeout in milliseconds
+    write_timeout: Option<u64>,
+
+    #[clap(long)]
+    /// Interval of TCP keep-alive probes in seconds
+    keepalive: Option<u64>,
+
+    #[clap(long, default_value_t = false, action)]
+    /// Use the graphics pipeline when the server support it
+    gfx: bool,
+
+    #[cfg(feature = "audio")]
+    #[clap(long, default_value_t = false, action)]
+    /// Leave the audio on the server
+    no_audio: bool,
 }
 
 fn main() {
     // Parse arguments
     let cli = Cli::parse();
 
+    // Print the diagnostic messages of the library
+    tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).init();
+
     // Create a tcp stream from args
     let tcp = tcp_from_args(&cli).unwrap();
 
@@ -488,7 +770,7 @@ fn main() {
 
     // All relative to sync
     // channel use by the back channel to send bitmap to main GUI thread
-    let (bitmap_sender, bitmap_receiver) = mpsc::channel();
+    let (display_sender, display_receiver) = mpsc::channel();
 
     // Once connected we will create safe thread variable
     let rdp_client_mutex = Arc::new(Mutex::new(rdp_client));
@@ -498,10 +780,10 @@ fn main() {
 
     // launch RDP thread
     let rdp_thread =
-        launch_rdp_thread(handle as usize, Arc::clone(&rdp_client_mutex), Arc::clone(&sync), bitmap_sender).unwrap();
+        launch_rdp_thread(handle as usize, Arc::clone(&rdp_client_mutex), Arc::clone(&sync), display_sender).unwrap();
 
     // Launch the GUI
-    main_gui_loop(window, rdp_client_mutex, sync, bitmap_receiver).unwrap();
+    main_gui_loop(window, rdp_client_mutex, sync, display_receiver).unwrap();
 
     rdp_thread.join().unwrap();
 }
@@ -0,0 +1,169 @@
+/// Coefficients of the inverse irreversible color transform
+/// scaled by 2^16
+const CR_TO_R: i64 = 91881;
+const CB_TO_G: i64 = 22553;
+const CR_TO_G: i64 = 46802;
+const CB_TO_B: i64 = 116130;
+
+/// Components carry 5 fractional bits
+const FRACTIONAL_BITS: u32 = 5;
+
+/// Offset of the luma which is centered on zero
+const Y_OFFSET: i64 = 128 << FRACTIONAL_BITS;
+
+/// Total shift of a channel back to 8 bits
+const SHIFT: u32 = 16 + FRACTIONAL_BITS;
+
+/// Convert a YCbCr pixel to BGRA
+/// Components use 11.5 fixed point values centered on zero
+///
+/// R = Y + 1.402 Cr
+/// G = Y - 0.344136 Cb - 0.714136 Cr
+/// B = Y + 1.772 Cb
+pub fn ycbcr_to_bgra(y: i16, cb: i16, cr: i16) -> [u8; 4] {
+    // Rounded to the nearest value
+    let y = ((i64::from(y) + Y_OFFSET) << 16) + (1 << (SHIFT - 1));
+    let cb = i64::from(cb);
+    let cr = i64::from(cr);
+    let r = (y + cr * CR_TO_R) >> SHIFT;
+    let g = (y - cb * CB_TO_G - cr * CR_TO_G) >> SHIFT;
+    let b = (y + cb * CB_TO_B) >> SHIFT;
+    [b.clamp(0, 0xff) as u8, g.clamp(0, 0xff) as u8, r.clamp(0, 0xff) as u8, 0xff]
+}
+
+/// Convert YCbCr planes to 32 bpp BGRA pixels
+/// as output by the RemoteFX inverse DWT
+///
+/// Components use 11.5 fixed point values centered on zero,
+/// the conversion stops at the shortest plane or output
+///
+/// # Example
+/// ```
+/// use rdp::codec::colorspace::ycbcr_to_bgra32;
+/// // black then white
+/// let mut output = [0; 8];
+/// ycbcr_to_bgra32(&[-4096, 4064], &[0, 0], &[0, 0], &mut output);
+/// assert_eq!(output, [0, 0, 0, 255, 255, 255, 255, 255]);
+/// ```
+pub fn ycbcr_to_bgra32(y: &[i16], cb: &[i16], cr: &[i16], dst: &mut [u8]) {
+    for (((pixel, y), cb), cr) in dst.chunks_exact_mut(4).zip(y).zip(cb).zip(cr) {
+        pixel.copy_from_slice(&ycbcr_to_bgra(*y, *cb, *cr));
+    }
+}
+
+/// Convert 32 bpp BGRA pixels to planar YUV 4:2:0
+/// Y plane then U and V planes subsampled by two in both directions
+///
+/// Uses BT.601 studio range, each chroma sample
+/// is the average of up to four pixels
+///
+/// # Example
+/// ```
+/// use rdp::codec::colorspace::bgra_to_yuv420;
+/// // white then black
+/// let planes = bgra_to_yuv420(&[255, 255, 255, 255, 0, 0, 0, 255], 2, 1);
+/// assert_eq!(planes, [235, 16, 128, 128]);
+/// ```
+pub fn bgra_to_yuv420(bgra: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let mut planes = vec![0; width * height + 2 * chroma_width * chroma_height];
+    let (luma, chroma) = planes.split_at_mut(width * height);
+    let (u, v) = chroma.split_at_mut(chroma_width * chroma_height);
+    let rgb = |x: usize, y: usize| {
+        let pixel = &bgra[(y * width + x) * 4..];
+        [i32::from(pixel[2]), i32::from(pixel[1]), i32::from(pixel[0])]
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = rgb(x, y);
+            luma[y * width + x] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        }
+    }
+    for y in 0..chroma_height {
+        for x in 0..chroma_width {
+            let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+            for (px, py) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+                if px < width && py < height {
+                    let [pr, pg, pb] = rgb(px, py);
+                    (r, g, b, count) = (r + pr, g + pg, b + pb, count + 1);
+                }
+            }
+            let (r, g, b) = (r / count, g / count, b / count);
+            u[y * chroma_width + x] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v[y * chroma_width + x] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+    planes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// JPEG full range YCbCr to 11.5 fixed point centered on zero
+    fn fixed(y: u8, cb: u8, cr: u8) -> (i16, i16, i16) {
+        let center = |value: u8| (i16::from(value) - 128) << FRACTIONAL_BITS;
+        (center(y), center(cb), center(cr))
+    }
+
+    #[test]
+    fn test_ycbcr_to_bgra_jpeg() {
+        // YCbCr of the primary colors rounded to 8 bits, see JFIF
+        let colors = [
+            ((0, 128, 128), [0, 0, 0]),
+            ((255, 128, 128), [255, 255, 255]),
+            ((76, 85, 255), [255, 0, 0]),
+            ((150, 44, 21), [0, 255, 0]),
+            ((29, 255, 107), [0, 0, 255]),
+            ((226, 1, 149), [255, 255, 0]),
+            ((179, 171, 1), [0, 255, 255]),
+            ((105, 212, 235), [255, 0, 255]),
+        ];
+        for ((y, cb, cr), rgb) in colors {
+            let (y, cb, cr) = fixed(y, cb, cr);
+            let [b, g, r, a] = ycbcr_to_bgra(y, cb, cr);
+            assert_eq!(a, 0xff);
+            for (channel, expected) in [r, g, b].into_iter().zip(rgb) {
+                assert!(channel.abs_diff(expected) <= 1, "{:?} {:?}", [r, g, b], rgb);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ycbcr_to_bgra_clamp() {
+        assert_eq!(ycbcr_to_bgra(i16::MAX, 0, 0), [255, 255, 255, 255]);
+        assert_eq!(ycbcr_to_bgra(i16::MIN, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(ycbcr_to_bgra(0, i16::MAX, i16::MAX), [255, 0, 255, 255]);
+        assert_eq!(ycbcr_to_bgra(0, i16::MIN, i16::MIN), [0, 255, 0, 255]);
+        assert_eq!(ycbcr_to_bgra(0, 0, 0), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_ycbcr_to_bgra32_shortest() {
+        let mut output = [0; 12];
+        ycbcr_to_bgra32(&[0, 0, 0], &[0, 0], &[0, 0, 0], &mut output);
+        assert_eq!(output[..8], [128, 128, 128, 255, 128, 128, 128, 255]);
+        assert_eq!(output[8..], [0; 4]);
+    }
+
+    #[test]
+    fn test_bgra_to_yuv420() {
+        // red, green, blue, white on two rows and a black column
+        let bgra = [
+            [0, 0, 255, 255],
+            [0, 255, 0, 255],
+            [0, 0, 0, 255],
+            [255, 0, 0, 255],
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+        ]
+        .concat();
+        let planes = bgra_to_yuv420(&bgra, 3, 2);
+        assert_eq!(planes[..6], [82, 144, 16, 41, 235, 16]);
+        // the first sample averages to gray, the second one is black
+        assert_eq!(planes[6..], [128, 128, 128, 128]);
+        assert_eq!(bgra_to_yuv420(&[0, 0, 255, 255], 1, 1), [82, 90, 240]);
+    }
+}
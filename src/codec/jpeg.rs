@@ -0,0 +1,104 @@
+#[cfg(feature = "jpeg")]
+use jpeg_decoder::{Decoder, PixelFormat};
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Codec id assigned by the client to JPEG
+/// in its bitmap codecs capability
+pub const JPEG_CODEC_ID: u8 = 2;
+
+fn jpeg_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("JPEG: {}", message)))
+}
+
+/// Decode a JPEG tile into 32 bpp BGRA pixels
+/// The tile must have the size of the bitmap,
+/// the frame header is checked before anything is decoded
+///
+/// Only available with the `jpeg` feature,
+/// `RdpErrorKind::NotImplemented` is returned otherwise
+#[cfg(feature = "jpeg")]
+pub fn jpeg_decompress(input: &[u8], width: u32, height: u32, output: &mut [u8]) -> RdpResult<()> {
+    let mut decoder = Decoder::new(input);
+    decoder.read_info().map_err(|e| jpeg_error(RdpErrorKind::InvalidData, &e.to_string()))?;
+    let info = decoder.info().ok_or_else(|| jpeg_error(RdpErrorKind::InvalidData, "Missing frame header"))?;
+    if u32::from(info.width) != width || u32::from(info.height) != height {
+        return Err(jpeg_error(RdpErrorKind::InvalidSize, "Tile size doesn't match the bitmap"));
+    }
+    let bytes_per_pixel = match info.pixel_format {
+        PixelFormat::L8 => 1,
+        PixelFormat::RGB24 => 3,
+        format => {
+            return Err(jpeg_error(RdpErrorKind::NotImplemented, &format!("Unsupported pixel format {:?}", format)))
+        }
+    };
+    if output.len() < width as usize * height as usize * 4 {
+        return Err(jpeg_error(RdpErrorKind::InvalidSize, "Output buffer too small"));
+    }
+    decoder.set_max_decoding_buffer_size(width as usize * height as usize * bytes_per_pixel);
+    let pixels = decoder.decode().map_err(|e| jpeg_error(RdpErrorKind::InvalidData, &e.to_string()))?;
+    for (src, dst) in pixels.chunks_exact(bytes_per_pixel).zip(output.chunks_exact_mut(4)) {
+        match src {
+            [luma] => dst.copy_from_slice(&[*luma, *luma, *luma, 0xff]),
+            [red, green, blue] => dst.copy_from_slice(&[*blue, *green, *red, 0xff]),
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// Decode a JPEG tile into 32 bpp BGRA pixels
+/// The crate is built without the `jpeg` feature
+#[cfg(not(feature = "jpeg"))]
+pub fn jpeg_decompress(_input: &[u8], _width: u32, _height: u32, _output: &mut [u8]) -> RdpResult<()> {
+    Err(jpeg_error(RdpErrorKind::NotImplemented, "Decoding needs the jpeg feature"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::mock_server::fixture;
+
+    /// 16x8 tile, red on the left half and blue on the right half
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_jpeg_decompress() {
+        let mut output = vec![0; 16 * 8 * 4];
+        jpeg_decompress(&fixture("jpeg_tile.jpg"), 16, 8, &mut output).unwrap();
+        for pixel in [0, 16 * 7] {
+            let bgra = &output[pixel * 4..pixel * 4 + 4];
+            assert!(bgra[2] > 0xf0 && bgra[0] < 0x10 && bgra[3] == 0xff);
+        }
+        let bgra = &output[15 * 4..16 * 4];
+        assert!(bgra[0] > 0xf0 && bgra[2] < 0x10);
+        match jpeg_decompress(&fixture("jpeg_tile.jpg"), 8, 8, &mut output) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidSize),
+            result => panic!("expected a size error {:?}", result),
+        }
+        assert!(jpeg_decompress(&[0xff, 0xd8, 0x00], 16, 8, &mut output).is_err());
+    }
+
+    /// The frame header announces a 65535x65535 image
+    /// but nothing is decoded
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_jpeg_oversized_frame() {
+        let mut tile = fixture("jpeg_tile.jpg");
+        let sof = tile.windows(2).position(|marker| marker == [0xff, 0xc0]).unwrap();
+        tile[sof + 5..sof + 9].copy_from_slice(&[0xff; 4]);
+        match jpeg_decompress(&tile, 16, 8, &mut [0; 16 * 8 * 4]) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidSize),
+            result => panic!("expected a size error {:?}", result),
+        }
+    }
+
+    #[cfg(not(feature = "jpeg"))]
+    #[test]
+    fn test_jpeg_feature_disabled() {
+        let mut output = vec![0; 16 * 8 * 4];
+        match jpeg_decompress(&fixture("jpeg_tile.jpg"), 16, 8, &mut output) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::NotImplemented),
+            _ => panic!("JPEG decoding must need the feature"),
+        }
+    }
+}
@@ -1 +1,6 @@
+pub mod colorspace;
+pub mod jpeg;
+pub mod nscodec;
+pub mod rfx;
 pub mod rle;
+pub mod zgfx;
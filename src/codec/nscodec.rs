@@ -0,0 +1,195 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Codec id of NSCodec in the bitmap codecs capability
+/// of the client, fixed by MS-RDPBCGR 2.2.7.2.10.1.1
+pub const NSCODEC_ID: u8 = 1;
+
+/// Run length decoding of a single plane
+///
+/// The last four bytes of a plane are always sent raw
+fn rle_decode(input: &[u8], output: &mut [u8]) -> RdpResult<()> {
+    let mut input_cursor = Cursor::new(input);
+    let mut left = output.len();
+    let mut out = 0;
+
+    while left > 4 {
+        let value = input_cursor.read_u8()?;
+        if left == 5 {
+            output[out] = value;
+            out += 1;
+            left -= 1;
+            continue;
+        }
+
+        let position = input_cursor.position();
+        if input_cursor.read_u8()? == value {
+            let len = match input_cursor.read_u8()? {
+                0xff => input_cursor.read_u32::<LittleEndian>()? as usize,
+                e => e as usize + 2,
+            };
+            if len > left {
+                return Err(Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "NSCODEC: Run exceeds plane size")));
+            }
+            output[out..out + len].fill(value);
+            out += len;
+            left -= len;
+        } else {
+            input_cursor.set_position(position);
+            output[out] = value;
+            out += 1;
+            left -= 1;
+        }
+    }
+
+    input_cursor.read_exact(&mut output[out..])?;
+    Ok(())
+}
+
+/// Decompress a NSCodec bitmap stream into 32 bpp BGRA pixels
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpnsc/0eac0ba8-7bdd-4300-ab8d-9bc784c0a669
+///
+/// Planes are in order luma (Y), orange chroma (Co),
+/// green chroma (Cg) and alpha
+///
+/// # Example
+/// ```
+/// use rdp::codec::nscodec::nscodec_decompress;
+/// let input = [
+///     1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // plane byte counts
+///     1, 0, 0, 0, // color loss level, chroma subsampling, reserved
+///     100, 0, 0 // luma, orange chroma, green chroma
+/// ];
+/// let mut output = vec![0; 4];
+/// nscodec_decompress(&input, 1, 1, &mut output).unwrap();
+/// assert_eq!(output, [100, 100, 100, 255]);
+/// ```
+pub fn nscodec_decompress(input: &[u8], width: u32, height: u32, output: &mut [u8]) -> RdpResult<()> {
+    let mut input_cursor = Cursor::new(input);
+
+    let mut plane_byte_count = [0_u32; 4];
+    for count in plane_byte_count.iter_mut() {
+        *count = input_cursor.read_u32::<LittleEndian>()?;
+    }
+    let color_loss_level = input_cursor.read_u8()?;
+    let chroma_subsampling = input_cursor.read_u8()? != 0;
+    let _reserved = input_cursor.read_u16::<LittleEndian>()?;
+
+    if !(1..=7).contains(&color_loss_level) {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "NSCODEC: Invalid color loss level")));
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+    if output.len() < width * height * 4 {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "NSCODEC: Output buffer too small")));
+    }
+
+    // chroma planes are super sampled on a 8x2 aligned surface
+    let scan_width = (width + 7) & !7;
+    let original_size = if chroma_subsampling {
+        let chroma_size = (scan_width >> 1) * (((height + 1) & !1) >> 1);
+        [scan_width * height, chroma_size, chroma_size, width * height]
+    } else {
+        [width * height; 4]
+    };
+
+    let mut planes = Vec::with_capacity(4);
+    for (plane_size, original_size) in plane_byte_count.iter().zip(original_size) {
+        // a raw plane has the size of the bitmap, a RLE plane is smaller
+        let plane_size = *plane_size as usize;
+        if plane_size > original_size {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidSize,
+                "NSCODEC: Plane larger than the bitmap",
+            )));
+        }
+        let mut plane = vec![0xff_u8; original_size];
+        let mut data = vec![0_u8; plane_size];
+        input_cursor.read_exact(&mut data)?;
+
+        if data.is_empty() {
+            // empty plane means all bits are set
+        } else if data.len() < original_size {
+            rle_decode(&data, &mut plane)?;
+        } else {
+            plane.copy_from_slice(&data);
+        }
+        planes.push(plane);
+    }
+
+    let shift = u32::from(color_loss_level - 1);
+    for y in 0..height {
+        let (luma_line, chroma_line) =
+            if chroma_subsampling { (y * scan_width, (y >> 1) * (scan_width >> 1)) } else { (y * width, y * width) };
+
+        for x in 0..width {
+            let chroma = if chroma_subsampling { chroma_line + (x >> 1) } else { chroma_line + x };
+            let luma = i16::from(planes[0][luma_line + x]);
+            let co = i16::from(planes[1][chroma].wrapping_shl(shift) as i8);
+            let cg = i16::from(planes[2][chroma].wrapping_shl(shift) as i8);
+
+            let pixel = (y * width + x) * 4;
+            output[pixel] = (luma - co - cg).clamp(0, 0xff) as u8;
+            output[pixel + 1] = (luma + cg).clamp(0, 0xff) as u8;
+            output[pixel + 2] = (luma + co - cg).clamp(0, 0xff) as u8;
+            output[pixel + 3] = planes[3][y * width + x];
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nscodec_decompress_raw_planes() {
+        let input =
+            [4, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 100, 100, 100, 100, 10, 0, 0, 0, 5, 0, 0, 0];
+        let mut output = vec![0; 16];
+        nscodec_decompress(&input, 2, 2, &mut output).unwrap();
+        assert_eq!(output, [85, 105, 105, 255, 100, 100, 100, 255, 100, 100, 100, 255, 100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn test_nscodec_decompress_rle_subsampling() {
+        let input = [
+            7, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 3, 1, 0, 0, 50, 50, 10, 50, 50, 50, 50, 4, 0, 0, 0, 0, 0,
+            0, 0,
+        ];
+        let mut output = vec![0; 8 * 2 * 4];
+        nscodec_decompress(&input, 8, 2, &mut output).unwrap();
+        for y in 0..2 {
+            for x in 0..8 {
+                let pixel = (y * 8 + x) * 4;
+                let expected: [u8; 4] = if x < 2 { [34, 50, 66, 255] } else { [50, 50, 50, 255] };
+                assert_eq!(output[pixel..pixel + 4], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nscodec_plane_larger_than_bitmap() {
+        // the byte count is checked before the plane is read
+        for (input, width) in [
+            (&[5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 2, 3, 4, 5][..], 2),
+            (&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0], 1),
+        ] {
+            match nscodec_decompress(input, width, width, &mut [0; 16]) {
+                Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidSize),
+                result => panic!("expected a size error {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_nscodec_invalid_color_loss_level() {
+        let input = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(nscodec_decompress(&input, 1, 1, &mut [0; 4]).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::codec::colorspace::ycbcr_to_bgra32;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Codec id assigned by the client to RemoteFX
+/// in its bitmap codecs capability
+pub const RFX_CODEC_ID: u8 = 3;
+
+/// Size in pixel of a tile side
+const TILE_SIZE: usize = 64;
+
+/// Number of coefficients of a tile component
+const TILE_COEFFICIENTS: usize = TILE_SIZE * TILE_SIZE;
+
+/// Adaptive run length Golomb Rice parameters
+const KPMAX: i32 = 80;
+const LSGR: i32 = 3;
+const UP_GR: i32 = 4;
+const DN_GR: i32 = 6;
+const UQ_GR: i32 = 3;
+const DQ_GR: i32 = 3;
+
+/// Type of RemoteFX blocks
+/// See MS-RDPRFX 2.2.2.1.1
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum BlockType {
+    Sync = 0xCCC0,
+    CodecVersions = 0xCCC1,
+    Channels = 0xCCC2,
+    Context = 0xCCC3,
+    FrameBegin = 0xCCC4,
+    FrameEnd = 0xCCC5,
+    Region = 0xCCC6,
+    Extension = 0xCCC7,
+    TileSet = 0xCAC2,
+    Tile = 0xCAC3,
+}
+
+/// Entropy algorithm used to encode coefficients
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum EntropyMode {
+    Rlgr1,
+    Rlgr3,
+}
+
+/// Read bits of an entropy encoded stream
+/// from the most significant bit
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Next byte to load into the accumulator
+    offset: usize,
+    /// Bits not yet consumed, aligned on the most significant bit
+    accumulator: u64,
+    /// Number of valid bits into the accumulator
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self { BitReader { data, offset: 0, accumulator: 0, count: 0 } }
+
+    fn refill(&mut self) {
+        while self.count <= 56 && self.offset < self.data.len() {
+            self.accumulator |= u64::from(self.data[self.offset]) << (56 - self.count);
+            self.count += 8;
+            self.offset += 1;
+        }
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.accumulator = self.accumulator.checked_shl(count).unwrap_or(0);
+        self.count -= count;
+    }
+
+    /// Read up to 32 bits
+    fn read_bits(&mut self, count: u32) -> Option<u32> {
+        if count == 0 {
+            return Some(0);
+        }
+        if self.count < count {
+            self.refill();
+            if self.count < count {
+                return None;
+            }
+        }
+        let value = (self.accumulator >> (64 - count)) as u32;
+        self.consume(count);
+        Some(value)
+    }
+
+    /// Count bits equal to `bit` until the first different one
+    /// The terminating bit is consumed too
+    fn read_unary(&mut self, bit: bool) -> Option<u32> {
+        let mut total = 0;
+        loop {
+            self.refill();
+            if self.count == 0 {
+                return None;
+            }
+            let same = if bit { self.accumulator.leading_ones() } else { self.accumulator.leading_zeros() };
+            if same < self.count {
+                self.consume(same + 1);
+                return Some(total + same);
+            }
+            total += self.count;
+            self.consume(self.count);
+        }
+    }
+}
+
+/// Read a Golomb Rice code and adapt its parameter
+fn read_gr_code(bits: &mut BitReader, krp: &mut i32) -> Option<u32> {
+    let kr = (*krp >> LSGR) as u32;
+    let vk = bits.read_unary(true)?;
+    let mag = bits.read_bits(kr)? | vk.wrapping_shl(kr);
+
+    if vk == 0 {
+        *krp = (*krp - 2).max(0);
+    } else if vk != 1 {
+        *krp = (*krp + vk.min(KPMAX as u32) as i32).min(KPMAX);
+    }
+    Some(mag)
+}
+
+/// Value coded as 2 * magnitude - sign
+fn from_two_mag_sign(value: u32) -> i16 {
+    if value & 1 != 0 {
+        -(((value + 1) >> 1) as i16)
+    } else {
+        (value >> 1) as i16
+    }
+}
+
+/// Decode coefficients encoded with RLGR1 or RLGR3
+/// See MS-RDPRFX 3.1.8.1.7
+///
+/// Output must be zero filled, decoding stops once
+/// the output is full or the input is exhausted
+fn rlgr_decode(mode: EntropyMode, input: &[u8], output: &mut [i16]) {
+    let mut bits = BitReader::new(input);
+    let mut kp = 1 << LSGR;
+    let mut krp = 1 << LSGR;
+    let mut out = 0;
+
+    while out < output.len() {
+        let k = (kp >> LSGR) as u32;
+        if k != 0 {
+            // run length mode
+            let zeros = match bits.read_unary(false) {
+                Some(zeros) => zeros,
+                None => return,
+            };
+            for _ in 0..zeros {
+                out += 1 << (kp >> LSGR);
+                kp = (kp + UP_GR).min(KPMAX);
+                if out >= output.len() {
+                    return;
+                }
+            }
+            let k = (kp >> LSGR) as u32;
+            let run = match bits.read_bits(k) {
+                Some(run) => run as usize,
+                None => return,
+            };
+            out += run;
+            let (sign, mag) = match (bits.read_bits(1), read_gr_code(&mut bits, &mut krp)) {
+                (Some(sign), Some(mag)) => (sign, mag + 1),
+                _ => return,
+            };
+            if let Some(value) = output.get_mut(out) {
+                *value = if sign != 0 { -(mag as i16) } else { mag as i16 };
+            }
+            out += 1;
+            kp = (kp - DN_GR).max(0);
+        } else {
+            let mag = match read_gr_code(&mut bits, &mut krp) {
+                Some(mag) => mag,
+                None => return,
+            };
+            match mode {
+                EntropyMode::Rlgr1 => {
+                    output[out] = from_two_mag_sign(mag);
+                    out += 1;
+                    kp = if mag == 0 { (kp + UQ_GR).min(KPMAX) } else { (kp - DQ_GR).max(0) };
+                }
+                EntropyMode::Rlgr3 => {
+                    let val1 = match bits.read_bits(32 - mag.leading_zeros()) {
+                        Some(val1) => val1,
+                        None => return,
+                    };
+                    let val2 = mag.wrapping_sub(val1);
+                    if val1 != 0 && val2 != 0 {
+                        kp = (kp - 2 * DQ_GR).max(0);
+                    } else if val1 == 0 && val2 == 0 {
+                        kp = (kp + 2 * UQ_GR).min(KPMAX);
+                    }
+                    output[out] = from_two_mag_sign(val1);
+                    out += 1;
+                    if let Some(value) = output.get_mut(out) {
+                        *value = from_two_mag_sign(val2);
+                    }
+                    out += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Inverse DWT of one level
+/// Sub bands are stored in HL, LH, HH, LL order
+fn idwt_block(buffer: &mut [i16], temp: &mut [i16], subband_width: usize) {
+    let sw = subband_width;
+    let tw = sw * 2;
+    let b = |buffer: &[i16], i: usize| i32::from(buffer[i]);
+
+    // horizontal pass, L and H are stored into temp
+    for y in 0..sw {
+        let hl = y * sw;
+        let lh = sw * sw + y * sw;
+        let hh = 2 * sw * sw + y * sw;
+        let ll = 3 * sw * sw + y * sw;
+        let l_dst = y * tw;
+        let h_dst = sw * tw + y * tw;
+
+        temp[l_dst] = (b(buffer, ll) - ((b(buffer, hl) * 2 + 1) >> 1)) as i16;
+        temp[h_dst] = (b(buffer, lh) - ((b(buffer, hh) * 2 + 1) >> 1)) as i16;
+        for n in 1..sw {
+            let x = n << 1;
+            temp[l_dst + x] = (b(buffer, ll + n) - ((b(buffer, hl + n - 1) + b(buffer, hl + n) + 1) >> 1)) as i16;
+            temp[h_dst + x] = (b(buffer, lh + n) - ((b(buffer, hh + n - 1) + b(buffer, hh + n) + 1) >> 1)) as i16;
+        }
+        for n in 0..sw - 1 {
+            let x = n << 1;
+            temp[l_dst + x + 1] =
+                ((b(buffer, hl + n) << 1) + ((b(temp, l_dst + x) + b(temp, l_dst + x + 2)) >> 1)) as i16;
+            temp[h_dst + x + 1] =
+                ((b(buffer, hh + n) << 1) + ((b(temp, h_dst + x) + b(temp, h_dst + x + 2)) >> 1)) as i16;
+        }
+        let x = (sw - 1) << 1;
+        temp[l_dst + x + 1] = ((b(buffer, hl + sw - 1) << 1) + b(temp, l_dst + x)) as i16;
+        temp[h_dst + x + 1] = ((b(buffer, hh + sw - 1) << 1) + b(temp, h_dst + x)) as i16;
+    }
+
+    // vertical pass, result is stored back into buffer
+    for x in 0..tw {
+        let mut l = x;
+        let mut h = x + sw * tw;
+        let mut dst = x;
+        buffer[dst] = (b(temp, l) - ((b(temp, h) * 2 + 1) >> 1)) as i16;
+        for _ in 1..sw {
+            l += tw;
+            h += tw;
+            buffer[dst + 2 * tw] = (b(temp, l) - ((b(temp, h - tw) + b(temp, h) + 1) >> 1)) as i16;
+            buffer[dst + tw] = ((b(temp, h - tw) << 1) + ((b(buffer, dst) + b(buffer, dst + 2 * tw)) >> 1)) as i16;
+            dst += 2 * tw;
+        }
+        buffer[dst + tw] = ((b(temp, h) << 1) + b(buffer, dst)) as i16;
+    }
+}
+
+/// Decode one component of a tile
+///
+/// quant values are in order LL3, LH3, HL3, HH3, LH2, HL2, HH2, LH1, HL1, HH1
+fn decode_component(mode: EntropyMode, data: &[u8], quant: &[u8; 10], buffer: &mut [i16], temp: &mut [i16]) {
+    buffer.fill(0);
+    rlgr_decode(mode, data, buffer);
+
+    // LL3 band is differential encoded
+    for i in 4033..TILE_COEFFICIENTS {
+        buffer[i] = buffer[i].wrapping_add(buffer[i - 1]);
+    }
+
+    // bands are stored in HL1, LH1, HH1, HL2, LH2, HH2, HL3, LH3, HH3, LL3 order
+    let bands = [
+        (0, 1024, quant[8]),
+        (1024, 1024, quant[7]),
+        (2048, 1024, quant[9]),
+        (3072, 256, quant[5]),
+        (3328, 256, quant[4]),
+        (3584, 256, quant[6]),
+        (3840, 64, quant[2]),
+        (3904, 64, quant[1]),
+        (3968, 64, quant[3]),
+        (4032, 64, quant[0]),
+    ];
+    for (offset, length, factor) in bands {
+        let shift = u32::from(factor.saturating_sub(1));
+        buffer[offset..offset + length].iter_mut().for_each(|value| *value = value.wrapping_shl(shift));
+    }
+
+    idwt_block(&mut buffer[3840..], temp, 8);
+    idwt_block(&mut buffer[3072..], temp, 16);
+    idwt_block(buffer, temp, 32);
+}
+
+/// Read the region block
+/// Return visible rectangles as left, top, right, bottom
+fn read_region(block: &[u8]) -> RdpResult<Vec<(usize, usize, usize, usize)>> {
+    let mut cursor = Cursor::new(block);
+    let _codec_id = cursor.read_u8()?;
+    let _channel_id = cursor.read_u8()?;
+    let _region_flags = cursor.read_u8()?;
+    let num_rects = cursor.read_u16::<LittleEndian>()?;
+    let mut rects = Vec::with_capacity(num_rects as usize);
+    for _ in 0..num_rects {
+        let x = cursor.read_u16::<LittleEndian>()? as usize;
+        let y = cursor.read_u16::<LittleEndian>()? as usize;
+        let width = cursor.read_u16::<LittleEndian>()? as usize;
+        let height = cursor.read_u16::<LittleEndian>()? as usize;
+        rects.push((x, y, x + width, y + height));
+    }
+    Ok(rects)
+}
+
+/// Decode all tiles of a tileset block
+fn read_tileset(
+    block: &[u8], rects: &[(usize, usize, usize, usize)], width: usize, height: usize, output: &mut [u8],
+) -> RdpResult<()> {
+    let mut cursor = Cursor::new(block);
+    let _codec_id = cursor.read_u8()?;
+    let _channel_id = cursor.read_u8()?;
+    if cursor.read_u16::<LittleEndian>()? != BlockType::TileSet as u16 {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "RFX: Invalid tileset subtype")));
+    }
+    let _idx = cursor.read_u16::<LittleEndian>()?;
+    let properties = cursor.read_u16::<LittleEndian>()?;
+    let num_quant = cursor.read_u8()?;
+    let _tile_size = cursor.read_u8()?;
+    let num_tiles = cursor.read_u16::<LittleEndian>()?;
+    let _tiles_data_size = cursor.read_u32::<LittleEndian>()?;
+
+    let mode = match (properties >> 10) & 0xf {
+        0x01 => EntropyMode::Rlgr1,
+        0x04 => EntropyMode::Rlgr3,
+        _ => return Err(Error::RdpError(RdpError::new(RdpErrorKind::NotImplemented, "RFX: Unknown entropy mode"))),
+    };
+
+    let mut quants = Vec::with_capacity(num_quant as usize);
+    for _ in 0..num_quant {
+        let mut quant = [0_u8; 10];
+        for i in 0..5 {
+            let value = cursor.read_u8()?;
+            quant[i * 2] = value & 0x0f;
+            quant[i * 2 + 1] = value >> 4;
+        }
+        quants.push(quant);
+    }
+
+    let whole = [(0, 0, width, height)];
+    let rects = if rects.is_empty() { &whole[..] } else { rects };
+
+    let mut planes = vec![0_i16; TILE_COEFFICIENTS * 3];
+    let mut temp = vec![0_i16; TILE_COEFFICIENTS];
+    for _ in 0..num_tiles {
+        let start = cursor.position() as usize;
+        let block_type = cursor.read_u16::<LittleEndian>()?;
+        let block_len = cursor.read_u32::<LittleEndian>()? as usize;
+        if block_type != BlockType::Tile as u16 || start + block_len > block.len() {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "RFX: Invalid tile block")));
+        }
+
+        let quant_idx = [cursor.read_u8()?, cursor.read_u8()?, cursor.read_u8()?];
+        let x_idx = cursor.read_u16::<LittleEndian>()? as usize;
+        let y_idx = cursor.read_u16::<LittleEndian>()? as usize;
+        let mut offset = cursor.position() as usize + 6;
+        for (component, plane) in planes.chunks_exact_mut(TILE_COEFFICIENTS).enumerate() {
+            let length = cursor.read_u16::<LittleEndian>()? as usize;
+            let data = block.get(offset..offset + length).ok_or_else(|| {
+                Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "RFX: Tile data exceeds block"))
+            })?;
+            let quant = quants.get(quant_idx[component] as usize).ok_or_else(|| {
+                Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "RFX: Invalid quantization index"))
+            })?;
+            decode_component(mode, data, quant, plane, &mut temp);
+            offset += length;
+        }
+
+        let (tile_x, tile_y) = (x_idx * TILE_SIZE, y_idx * TILE_SIZE);
+        for (left, top, right, bottom) in rects {
+            let x0 = tile_x.max(*left);
+            let x1 = (tile_x + TILE_SIZE).min(*right).min(width);
+            let y0 = tile_y.max(*top);
+            let y1 = (tile_y + TILE_SIZE).min(*bottom).min(height);
+            if x1 <= x0 {
+                continue;
+            }
+            for y in y0..y1 {
+                let index = (y - tile_y) * TILE_SIZE + x0 - tile_x;
+                let pixel = (y * width + x0) * 4;
+                let row = index..index + x1 - x0;
+                ycbcr_to_bgra32(
+                    &planes[row.clone()],
+                    &planes[TILE_COEFFICIENTS..][row.clone()],
+                    &planes[2 * TILE_COEFFICIENTS..][row],
+                    &mut output[pixel..(y * width + x1) * 4],
+                );
+            }
+        }
+        cursor.set_position((start + block_len) as u64);
+    }
+    Ok(())
+}
+
+/// Decompress a RemoteFX message into 32 bpp BGRA pixels
+/// See MS-RDPRFX 3.1.8
+///
+/// Only the tiles covered by the region are written
+///
+/// # Example
+/// ```
+/// use rdp::codec::rfx::rfx_decompress;
+/// let mut output = vec![0; 64 * 64 * 4];
+/// // a message without tileset doesn't change anything
+/// rfx_decompress(&[0xc0, 0xcc, 12, 0, 0, 0, 0xca, 0xac, 0xcc, 0xca, 0, 1], 64, 64, &mut output).unwrap();
+/// assert!(output.iter().all(|p| *p == 0));
+/// ```
+pub fn rfx_decompress(input: &[u8], width: u32, height: u32, output: &mut [u8]) -> RdpResult<()> {
+    let width = width as usize;
+    let height = height as usize;
+    if output.len() < width * height * 4 {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "RFX: Output buffer too small")));
+    }
+
+    let mut cursor = Cursor::new(input);
+    let mut rects = Vec::new();
+    while cursor.position() as usize + 6 <= input.len() {
+        let start = cursor.position() as usize;
+        let block_type = cursor.read_u16::<LittleEndian>()?;
+        let block_len = cursor.read_u32::<LittleEndian>()? as usize;
+        if block_len < 6 || start + block_len > input.len() {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "RFX: Invalid block length")));
+        }
+
+        let block = &input[start + 6..start + block_len];
+        match BlockType::try_from(block_type) {
+            Ok(BlockType::Region) => rects = read_region(block)?,
+            Ok(BlockType::Extension) => read_tileset(block, &rects, width, height, output)?,
+            // other blocks don't carry anything needed for decoding
+            _ => (),
+        }
+        cursor.set_position((start + block_len) as u64);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rlgr1_decode() {
+        let mut output = [0_i16; 8];
+        rlgr_decode(EntropyMode::Rlgr1, &[0x88], &mut output);
+        assert_eq!(output, [2, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut output = [0_i16; 8];
+        rlgr_decode(EntropyMode::Rlgr1, &[0xa8], &mut output);
+        assert_eq!(output, [-2, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rfx_decompress_empty_tile() {
+        let mut tileset = vec![1, 0, 0xc2, 0xca, 0, 0, 0x51, 0x04, 1, 0x40, 1, 0, 19, 0, 0, 0];
+        tileset.extend_from_slice(&[0x66, 0x66, 0x77, 0x88, 0x98]);
+        tileset.extend_from_slice(&[0xc3, 0xca, 19, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut input = vec![0xc7, 0xcc];
+        input.extend_from_slice(&(tileset.len() as u32 + 6).to_le_bytes());
+        input.extend(tileset);
+
+        let mut output = vec![0; 64 * 64 * 4];
+        rfx_decompress(&input, 64, 64, &mut output).unwrap();
+        assert!(output.chunks_exact(4).all(|p| p == [128, 128, 128, 255]));
+    }
+}
@@ -1,4 +1,5 @@
-use std::io::Cursor;
+use std::io::{Cursor, Read as _};
+use std::ops::BitXor;
 
 use byteorder::{LittleEndian, ReadBytesExt as _};
 
@@ -7,8 +8,12 @@ use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 /// All this decompression code is directly inspired from the source code of
 /// rdesktop and directly ported to Rust.
 
-fn process_plane(input: &mut Cursor<&[u8]>, width: u32, height: u32, output: &mut [u8]) -> RdpResult<()> {
-    let mut last_line: u32 = 0;
+fn run_error() -> Error { Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "Run exceeds the line")) }
+
+/// Decode one color plane
+/// The caller checks that the output holds `width * height` pixels
+fn process_plane(input: &mut Cursor<&[u8]>, width: usize, height: usize, output: &mut [u8]) -> RdpResult<()> {
+    let mut last_line: usize = 0;
 
     for indexh in 0..height {
         let mut out = (height - indexh - 1) * width * 4;
@@ -25,15 +30,18 @@ fn process_plane(input: &mut Cursor<&[u8]>, width: u32, height: u32, output: &mu
                     replen = revcode;
                     collen = 0;
                 }
+                if indexw + usize::from(collen) + usize::from(replen) > width {
+                    return Err(run_error());
+                }
                 while collen > 0 {
                     color = input.read_u8()?;
-                    output[out as usize] = color;
+                    output[out] = color;
                     out += 4;
                     indexw += 1;
                     collen -= 1;
                 }
                 while replen > 0 {
-                    output[out as usize] = color;
+                    output[out] = color;
                     out += 4;
                     indexw += 1;
                     replen -= 1;
@@ -50,18 +58,21 @@ fn process_plane(input: &mut Cursor<&[u8]>, width: u32, height: u32, output: &mu
                     replen = revcode;
                     collen = 0;
                 }
+                if indexw + usize::from(collen) + usize::from(replen) > width {
+                    return Err(run_error());
+                }
                 while collen > 0 {
                     let x = input.read_u8()?;
                     color = if x & 1 != 0 { -i32::from((x >> 1) + 1) as i8 } else { (x >> 1) as i8 };
-                    let v = (i32::from(output[(last_line + (indexw * 4)) as usize]) + i32::from(color)) as u8;
-                    output[out as usize] = v;
+                    let v = (i32::from(output[last_line + (indexw * 4)]) + i32::from(color)) as u8;
+                    output[out] = v;
                     out += 4;
                     indexw += 1;
                     collen -= 1;
                 }
                 while replen > 0 {
-                    let v = (i32::from(output[(last_line + (indexw * 4)) as usize]) + i32::from(color)) as u8;
-                    output[out as usize] = v;
+                    let v = (i32::from(output[last_line + (indexw * 4)]) + i32::from(color)) as u8;
+                    output[out] = v;
                     out += 4;
                     indexw += 1;
                     replen -= 1;
@@ -74,12 +85,21 @@ fn process_plane(input: &mut Cursor<&[u8]>, width: u32, height: u32, output: &mu
 }
 
 /// Run length encoding decoding function for 32 bpp
+/// The output must hold `width * height` pixels
 pub fn rle_32_decompress(input: &[u8], width: u32, height: u32, output: &mut [u8]) -> RdpResult<()> {
+    let (width, height) = (width as usize, height as usize);
+    if width.checked_mul(height).and_then(|pixels| pixels.checked_mul(4)).is_none_or(|size| output.len() < size) {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "output buffer too small")));
+    }
+
     let mut input_cursor = Cursor::new(input);
 
     if input_cursor.read_u8()? != 0x10 {
         return Err(Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "Bad header")));
     }
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
 
     process_plane(&mut input_cursor, width, height, &mut output[3..])?;
     process_plane(&mut input_cursor, width, height, &mut output[2..])?;
@@ -124,7 +144,56 @@ macro_rules! repeat {
     };
 }
 
-pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output: &mut [u16]) -> RdpResult<()> {
+/// Pixel of the interleaved RLE codec
+/// Color values are read with the size of the pixel
+trait RlePixel: Copy + BitXor<Output = Self> {
+    const BLACK: Self;
+    const WHITE: Self;
+
+    fn read(input: &mut Cursor<&[u8]>) -> RdpResult<Self>;
+}
+
+impl RlePixel for u8 {
+    const BLACK: Self = 0;
+    const WHITE: Self = 0xff;
+
+    fn read(input: &mut Cursor<&[u8]>) -> RdpResult<Self> { Ok(input.read_u8()?) }
+}
+
+impl RlePixel for u16 {
+    const BLACK: Self = 0;
+    const WHITE: Self = 0xffff;
+
+    fn read(input: &mut Cursor<&[u8]>) -> RdpResult<Self> { Ok(input.read_u16::<LittleEndian>()?) }
+}
+
+/// 24 bpp pixel, components are stored blue first
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+struct Rgb24([u8; 3]);
+
+impl BitXor for Rgb24 {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self {
+        let [b, g, r] = self.0;
+        let [rhs_b, rhs_g, rhs_r] = rhs.0;
+        Rgb24([b ^ rhs_b, g ^ rhs_g, r ^ rhs_r])
+    }
+}
+
+impl RlePixel for Rgb24 {
+    const BLACK: Self = Rgb24([0; 3]);
+    const WHITE: Self = Rgb24([0xff; 3]);
+
+    fn read(input: &mut Cursor<&[u8]>) -> RdpResult<Self> {
+        let mut pixel = [0; 3];
+        input.read_exact(&mut pixel)?;
+        Ok(Rgb24(pixel))
+    }
+}
+
+/// Interleaved RLE decoding shared by 8, 16 and 24 bpp
+fn rle_decompress<P: RlePixel>(input: &[u8], width: usize, mut height: usize, output: &mut [P]) -> RdpResult<()> {
     let mut input_cursor = Cursor::new(input);
 
     let mut lastopcode: u8 = 0xFF;
@@ -132,9 +201,9 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
     let mut x: usize = width;
     let mut prevline: Option<usize> = None;
     let mut line: Option<usize> = None;
-    let mut colour1 = 0;
-    let mut colour2 = 0;
-    let mut mix = 0xffff;
+    let mut colour1 = P::BLACK;
+    let mut colour2 = P::BLACK;
+    let mut mix = P::WHITE;
     let mut mask: u8 = 0;
     let mut bicolour = false;
 
@@ -181,14 +250,14 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                 }
             }
             8 => {
-                colour1 = input_cursor.read_u16::<LittleEndian>()?;
-                colour2 = input_cursor.read_u16::<LittleEndian>()?;
+                colour1 = P::read(&mut input_cursor)?;
+                colour2 = P::read(&mut input_cursor)?;
             }
             3 => {
-                colour2 = input_cursor.read_u16::<LittleEndian>()?;
+                colour2 = P::read(&mut input_cursor)?;
             }
             6 | 7 => {
-                mix = input_cursor.read_u16::<LittleEndian>()?;
+                mix = P::read(&mut input_cursor)?;
                 opcode -= 5;
             }
             9 => {
@@ -211,7 +280,7 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                 if height == 0 {
                     return Err(Error::RdpError(RdpError::new(
                         RdpErrorKind::RleDecode,
-                        "count > 0 but all values already written during rle_16 decompress",
+                        "count > 0 but all values already written during rle decompress",
                     )));
                 }
                 x = 0;
@@ -220,7 +289,7 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                 line = Some(height * width);
             }
             let line = line.ok_or_else(|| {
-                Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "line unset during rle_16 decompress"))
+                Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "line unset during rle decompress"))
             })?;
 
             match opcode {
@@ -235,7 +304,7 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                     if let Some(e) = prevline {
                         repeat!(output[line + x] = output[e + x], count, x, width);
                     } else {
-                        repeat!(output[line + x] = 0, count, x, width);
+                        repeat!(output[line + x] = P::BLACK, count, x, width);
                     }
                 }
                 1 => {
@@ -269,7 +338,7 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                                     mask = if fom_mask != 0 { fom_mask } else { input_cursor.read_u8()? };
                                     mixmask = 1;
                                 }
-                                output[line + x] = if (mask & mixmask) != 0 { mix } else { 0 };
+                                output[line + x] = if (mask & mixmask) != 0 { mix } else { P::BLACK };
                             },
                             count,
                             x,
@@ -281,7 +350,7 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                     repeat!(output[line + x] = colour2, count, x, width);
                 }
                 4 => {
-                    repeat!(output[line + x] = input_cursor.read_u16::<LittleEndian>()?, count, x, width);
+                    repeat!(output[line + x] = P::read(&mut input_cursor)?, count, x, width);
                 }
                 8 => {
                     repeat!(
@@ -299,10 +368,10 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
                     );
                 }
                 0xd => {
-                    repeat!(output[line + x] = 0xffff, count, x, width);
+                    repeat!(output[line + x] = P::WHITE, count, x, width);
                 }
                 0xe => {
-                    repeat!(output[line + x] = 0, count, x, width);
+                    repeat!(output[line + x] = P::BLACK, count, x, width);
                 }
                 _ => return Err(Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "invalid opcode"))),
             }
@@ -312,15 +381,259 @@ pub fn rle_16_decompress(input: &[u8], width: usize, mut height: usize, output:
     Ok(())
 }
 
+/// Run length encoding decoding function for 16 bpp
+pub fn rle_16_decompress(input: &[u8], width: usize, height: usize, output: &mut [u16]) -> RdpResult<()> {
+    rle_decompress(input, width, height, output)
+}
+
+/// Run length encoding decoding function for 8 bpp
+/// Output pixels are palette indexes
+///
+/// # Example
+/// ```
+/// use rdp::codec::rle::rle_8_decompress;
+/// let mut output = [0; 4];
+/// // color image of 4 pixels
+/// rle_8_decompress(&[0x84, 1, 2, 3, 4], 4, 1, &mut output).unwrap();
+/// assert_eq!(output, [1, 2, 3, 4]);
+/// ```
+pub fn rle_8_decompress(input: &[u8], width: usize, height: usize, output: &mut [u8]) -> RdpResult<()> {
+    rle_decompress(input, width, height, output)
+}
+
+/// Run length encoding decoding function for 24 bpp
+/// Output pixels are 3 bytes, blue first
+///
+/// # Example
+/// ```
+/// use rdp::codec::rle::rle_24_decompress;
+/// let mut output = [0; 6];
+/// // color image of 2 pixels
+/// rle_24_decompress(&[0x82, 1, 2, 3, 4, 5, 6], 2, 1, &mut output).unwrap();
+/// assert_eq!(output, [1, 2, 3, 4, 5, 6]);
+/// ```
+pub fn rle_24_decompress(input: &[u8], width: usize, height: usize, output: &mut [u8]) -> RdpResult<()> {
+    if output.len() < width * height * 3 {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::RleDecode, "output buffer too small")));
+    }
+    let mut pixels = vec![Rgb24::BLACK; width * height];
+    rle_decompress(input, width, height, &mut pixels)?;
+    for (dest, pixel) in output.chunks_exact_mut(3).zip(pixels) {
+        dest.copy_from_slice(&pixel.0);
+    }
+    Ok(())
+}
+
+/// Expand 24 bpp pixels into 32 bpp BGRA pixels
+pub fn rgb24torgb32(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 3 * 4);
+    output.extend(input.chunks_exact(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xff]));
+    output
+}
+
+/// Expand RGB 565 pixels into 32 bpp BGRA pixels
+/// Eight pixels are converted at once with SSE2 on x86_64
+///
+/// # Example
+/// ```
+/// use rdp::codec::rle::rgb565torgb32;
+/// assert_eq!(rgb565torgb32(&[0xf800, 0x07e0]), [0, 0, 0xff, 0xff, 0, 0xff, 0, 0xff]);
+/// ```
 pub fn rgb565torgb32(input: &[u16]) -> Vec<u8> {
-    let mut output = Vec::with_capacity(input.len() * 4);
-    output.extend(input.iter().copied().flat_map(|v| {
-        [
+    let mut output = vec![0; input.len() * 4];
+    #[cfg(target_arch = "x86_64")]
+    let done = {
+        let done = input.len() & !7;
+        // SSE2 is part of the x86_64 baseline
+        unsafe { sse2::rgb565torgb32(&input[..done], &mut output[..done * 4]) };
+        done
+    };
+    #[cfg(not(target_arch = "x86_64"))]
+    let done = 0;
+    rgb565torgb32_scalar_into(&input[done..], &mut output[done * 4..]);
+    output
+}
+
+/// Expand RGB 565 pixels one at a time
+/// Same result as `rgb565torgb32` without SIMD
+pub fn rgb565torgb32_scalar(input: &[u16]) -> Vec<u8> {
+    let mut output = vec![0; input.len() * 4];
+    rgb565torgb32_scalar_into(input, &mut output);
+    output
+}
+
+fn rgb565torgb32_scalar_into(input: &[u16], output: &mut [u8]) {
+    for (v, pixel) in input.iter().copied().zip(output.chunks_exact_mut(4)) {
+        pixel.copy_from_slice(&[
             ((((v & 0x1f) * 527) + 23) >> 6) as u8,
             (((((v >> 5) & 0x3f) * 259) + 33) >> 6) as u8,
             (((((v >> 11) & 0x1f) * 527) + 23) >> 6) as u8,
             0xff,
-        ]
+        ]);
+    }
+}
+
+/// Expand RGB 555 pixels into 32 bpp BGRA pixels
+/// The unused top bit is ignored
+///
+/// # Example
+/// ```
+/// use rdp::codec::rle::rgb555torgb32;
+/// assert_eq!(rgb555torgb32(&[0x7c00, 0x03e0]), [0, 0, 0xff, 0xff, 0, 0xff, 0, 0xff]);
+/// ```
+pub fn rgb555torgb32(input: &[u16]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 4);
+    let scale = |v: u16| ((((v & 0x1f) * 527) + 23) >> 6) as u8;
+    output.extend(input.iter().flat_map(|v| [scale(*v), scale(v >> 5), scale(v >> 10), 0xff]));
+    output
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    /// Convert eight pixels per iteration
+    /// The input length must be a multiple of 8
+    /// and the output four times larger
+    ///
+    /// Components use the same rounding as the scalar code
+    /// and fit in 16 bits lanes
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn rgb565torgb32(input: &[u16], output: &mut [u8]) {
+        debug_assert!(input.len().is_multiple_of(8) && output.len() == input.len() * 4);
+        let mask5 = _mm_set1_epi16(0x1f);
+        let mask6 = _mm_set1_epi16(0x3f);
+        let scale5 = _mm_set1_epi16(527);
+        let scale6 = _mm_set1_epi16(259);
+        let round5 = _mm_set1_epi16(23);
+        let round6 = _mm_set1_epi16(33);
+        let alpha = _mm_set1_epi16(0xff00_u16 as i16);
+        for (pixels, dest) in input.chunks_exact(8).zip(output.chunks_exact_mut(32)) {
+            let v = _mm_loadu_si128(pixels.as_ptr() as *const __m128i);
+            let blue = _mm_and_si128(v, mask5);
+            let green = _mm_and_si128(_mm_srli_epi16(v, 5), mask6);
+            let red = _mm_and_si128(_mm_srli_epi16(v, 11), mask5);
+            let blue = _mm_srli_epi16(_mm_add_epi16(_mm_mullo_epi16(blue, scale5), round5), 6);
+            let green = _mm_srli_epi16(_mm_add_epi16(_mm_mullo_epi16(green, scale6), round6), 6);
+            let red = _mm_srli_epi16(_mm_add_epi16(_mm_mullo_epi16(red, scale5), round5), 6);
+            // blue and green in the low word of each pixel, red and alpha in the high word
+            let blue_green = _mm_or_si128(blue, _mm_slli_epi16(green, 8));
+            let red_alpha = _mm_or_si128(red, alpha);
+            let dest = dest.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(dest, _mm_unpacklo_epi16(blue_green, red_alpha));
+            _mm_storeu_si128(dest.add(1), _mm_unpackhi_epi16(blue_green, red_alpha));
+        }
+    }
+}
+
+/// Colors of an 8 bpp session
+/// Entries are red, green and blue components
+pub type Palette = [[u8; 3]; 256];
+
+/// Expand palette indexes into 32 bpp BGRA pixels
+///
+/// # Example
+/// ```
+/// use rdp::codec::rle::palette_to_rgb32;
+/// let mut palette = [[0; 3]; 256];
+/// palette[1] = [0x10, 0x20, 0x30];
+/// assert_eq!(palette_to_rgb32(&[1, 0], &palette), [0x30, 0x20, 0x10, 0xff, 0, 0, 0, 0xff]);
+/// ```
+pub fn palette_to_rgb32(indexed: &[u8], palette: &Palette) -> Vec<u8> {
+    let mut output = Vec::with_capacity(indexed.len() * 4);
+    output.extend(indexed.iter().flat_map(|index| {
+        let [red, green, blue] = palette[usize::from(*index)];
+        [blue, green, red, 0xff]
     }));
     output
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rle_8_color_image() {
+        // the first decoded line is the bottom one
+        let mut output = [0; 4];
+        rle_8_decompress(&[0x82, 5, 6, 0x82, 7, 8], 2, 2, &mut output).unwrap();
+        assert_eq!(output, [7, 8, 5, 6]);
+    }
+
+    #[test]
+    fn test_rle_8_fill() {
+        // background fill copies the previous line
+        let mut output = [0; 4];
+        rle_8_decompress(&[0x82, 5, 6, 0x02], 2, 2, &mut output).unwrap();
+        assert_eq!(output, [5, 6, 5, 6]);
+    }
+
+    #[test]
+    fn test_rle_8_color_and_mix() {
+        // color run then mix run, the first line mix is white
+        let mut output = [0; 4];
+        rle_8_decompress(&[0x62, 9, 0x22], 4, 1, &mut output).unwrap();
+        assert_eq!(output, [9, 9, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_rle_8_special() {
+        // white and black single pixels
+        let mut output = [7; 2];
+        rle_8_decompress(&[0xfd, 0xfe], 2, 1, &mut output).unwrap();
+        assert_eq!(output, [0xff, 0]);
+    }
+
+    #[test]
+    fn test_rle_8_overflow() {
+        let mut output = [0; 2];
+        assert!(rle_8_decompress(&[0x84, 1, 2, 3, 4], 2, 1, &mut output).is_err());
+    }
+
+    #[test]
+    fn test_rle_24_fill_and_mix() {
+        // color image on the bottom line then a fill and a mix run
+        let mut output = [0; 12];
+        rle_24_decompress(&[0x82, 1, 2, 3, 4, 5, 6, 0x22], 2, 2, &mut output).unwrap();
+        assert_eq!(output, [0xfe, 0xfd, 0xfc, 0xfb, 0xfa, 0xf9, 1, 2, 3, 4, 5, 6]);
+
+        let mut output = [0; 12];
+        rle_24_decompress(&[0x82, 1, 2, 3, 4, 5, 6, 0x02], 2, 2, &mut output).unwrap();
+        assert_eq!(output, [1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_rle_24_set_color() {
+        // set mix color then mix run
+        let mut output = [0; 9];
+        rle_24_decompress(&[0xf6, 3, 0, 0x10, 0x20, 0x30], 3, 1, &mut output).unwrap();
+        assert_eq!(output, [0x10, 0x20, 0x30, 0x10, 0x20, 0x30, 0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn test_rle_24_short_output() {
+        let mut output = [0; 3];
+        assert!(rle_24_decompress(&[0x82, 1, 2, 3, 4, 5, 6], 2, 1, &mut output).is_err());
+        assert_eq!(rgb24torgb32(&[1, 2, 3]), [1, 2, 3, 0xff]);
+    }
+
+    #[test]
+    fn test_rgb565_simd_matches_scalar() {
+        // every color, with a length which is not a multiple of 8
+        let input: Vec<u16> = (0..=u16::MAX).chain([0x1234, 0xffff, 0]).collect();
+        assert_eq!(rgb565torgb32(&input), rgb565torgb32_scalar(&input));
+        assert_eq!(rgb565torgb32(&input[..5]), rgb565torgb32_scalar(&input[..5]));
+    }
+
+    #[test]
+    fn test_rgb555() {
+        assert_eq!(rgb555torgb32(&[0x7fff]), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(rgb555torgb32(&[0x0000]), [0, 0, 0, 0xff]);
+        assert_eq!(rgb555torgb32(&[0x7c00]), [0, 0, 0xff, 0xff]);
+        assert_eq!(rgb555torgb32(&[0x001f]), [0xff, 0, 0, 0xff]);
+        // the top bit is not part of the color
+        assert_eq!(rgb555torgb32(&[0xffff]), rgb555torgb32(&[0x7fff]));
+        // 0x10 scales to the middle of the range
+        assert_eq!(rgb555torgb32(&[0x4210]), [0x84, 0x84, 0x84, 0xff]);
+    }
+}
@@ -0,0 +1,278 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Size of the history buffer shared by all segments
+const HISTORY_SIZE: usize = 2_500_000;
+
+/// Segmented data descriptors
+/// See MS-RDPEGFX 2.2.5.1
+const SEGMENTED_SINGLE: u8 = 0xe0;
+const SEGMENTED_MULTIPART: u8 = 0xe1;
+
+/// Bulk data header flags
+/// See MS-RDPEGFX 2.2.5.3
+const PACKET_COMPR_TYPE_RDP8: u8 = 0x04;
+const PACKET_COMPRESSED: u8 = 0x20;
+
+/// Token of the RDP8 bulk compression
+/// (prefix length, prefix code, value bits, is match, value base)
+struct Token(u32, u32, u32, bool, u32);
+
+/// See MS-RDPEGFX 3.1.9.1
+const TOKENS: [Token; 40] = [
+    Token(1, 0b0, 8, false, 0),
+    Token(5, 0b10001, 5, true, 0),
+    Token(5, 0b10010, 7, true, 32),
+    Token(5, 0b10011, 9, true, 160),
+    Token(5, 0b10100, 10, true, 672),
+    Token(5, 0b10101, 12, true, 1696),
+    Token(5, 0b11000, 0, false, 0x00),
+    Token(5, 0b11001, 0, false, 0x01),
+    Token(6, 0b101100, 14, true, 5792),
+    Token(6, 0b101101, 15, true, 22176),
+    Token(6, 0b110100, 0, false, 0x02),
+    Token(6, 0b110101, 0, false, 0x03),
+    Token(6, 0b110110, 0, false, 0xff),
+    Token(7, 0b1011100, 18, true, 54944),
+    Token(7, 0b1011101, 20, true, 317_088),
+    Token(7, 0b1101110, 0, false, 0x04),
+    Token(7, 0b1101111, 0, false, 0x05),
+    Token(7, 0b1110000, 0, false, 0x06),
+    Token(7, 0b1110001, 0, false, 0x07),
+    Token(7, 0b1110010, 0, false, 0x08),
+    Token(7, 0b1110011, 0, false, 0x09),
+    Token(7, 0b1110100, 0, false, 0x0a),
+    Token(7, 0b1110101, 0, false, 0x0b),
+    Token(7, 0b1110110, 0, false, 0x3a),
+    Token(7, 0b1110111, 0, false, 0x3b),
+    Token(7, 0b1111000, 0, false, 0x3c),
+    Token(7, 0b1111001, 0, false, 0x3d),
+    Token(7, 0b1111010, 0, false, 0x3e),
+    Token(7, 0b1111011, 0, false, 0x3f),
+    Token(7, 0b1111100, 0, false, 0x40),
+    Token(7, 0b1111101, 0, false, 0x80),
+    Token(8, 0b10111100, 20, true, 1_365_664),
+    Token(8, 0b10111101, 21, true, 2_414_240),
+    Token(8, 0b11111100, 0, false, 0x0c),
+    Token(8, 0b11111101, 0, false, 0x38),
+    Token(8, 0b11111110, 0, false, 0x39),
+    Token(8, 0b11111111, 0, false, 0x66),
+    Token(9, 0b101111100, 22, true, 4_511_392),
+    Token(9, 0b101111101, 23, true, 8_705_696),
+    Token(9, 0b101111110, 24, true, 17_094_304),
+];
+
+fn zgfx_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("ZGFX: {}", message)))
+}
+
+/// Most significant bit first reader
+/// The last byte of a segment is the number of padding bits
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    current: u32,
+    current_bits: u32,
+    remaining: i64,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(segment: &'a [u8]) -> RdpResult<Self> {
+        let (padding, data) = segment.split_last().ok_or_else(|| zgfx_error("Empty segment"))?;
+        Ok(BitReader {
+            data,
+            position: 0,
+            current: 0,
+            current_bits: 0,
+            remaining: 8 * data.len() as i64 - *padding as i64,
+        })
+    }
+
+    fn bits(&mut self, count: u32) -> RdpResult<u32> {
+        if self.remaining < count as i64 {
+            return Err(zgfx_error("Unexpected end of segment"));
+        }
+        while self.current_bits < count {
+            self.current <<= 8;
+            if let Some(byte) = self.data.get(self.position) {
+                self.current |= u32::from(*byte);
+                self.position += 1;
+            }
+            self.current_bits += 8;
+        }
+        self.remaining -= count as i64;
+        self.current_bits -= count;
+        let value = self.current >> self.current_bits;
+        self.current &= (1 << self.current_bits) - 1;
+        Ok(value)
+    }
+
+    /// Drop the bits left into the current byte
+    /// and read raw bytes
+    fn bytes(&mut self, count: usize) -> RdpResult<&'a [u8]> {
+        self.remaining -= self.current_bits as i64;
+        self.current = 0;
+        self.current_bits = 0;
+        if self.position + count > self.data.len() {
+            return Err(zgfx_error("Unencoded bytes exceed segment"));
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        self.remaining -= 8 * count as i64;
+        Ok(bytes)
+    }
+}
+
+/// RDP8 bulk decompressor used by the graphics pipeline
+///
+/// The history is kept from one message to the next
+/// so a single instance must be used by channel
+pub struct Zgfx {
+    history: Vec<u8>,
+    index: usize,
+}
+
+impl Zgfx {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self { Zgfx { history: vec![0; HISTORY_SIZE], index: 0 } }
+
+    fn push(&mut self, byte: u8, output: &mut Vec<u8>) {
+        output.push(byte);
+        self.history[self.index] = byte;
+        self.index = (self.index + 1) % HISTORY_SIZE;
+    }
+
+    /// Decompress a single bulk encoded segment
+    fn segment(&mut self, segment: &[u8], output: &mut Vec<u8>) -> RdpResult<()> {
+        let (header, data) = segment.split_first().ok_or_else(|| zgfx_error("Empty segment"))?;
+        if header & 0x0f != PACKET_COMPR_TYPE_RDP8 {
+            return Err(zgfx_error("Invalid compression type"));
+        }
+        if header & PACKET_COMPRESSED == 0 {
+            for byte in data {
+                self.push(*byte, output);
+            }
+            return Ok(());
+        }
+
+        let mut reader = BitReader::new(data)?;
+        while reader.remaining > 0 {
+            let mut prefix = 0;
+            let mut prefix_length = 0;
+            let token = TOKENS
+                .iter()
+                .find_map(|token| {
+                    while prefix_length < token.0 {
+                        match reader.bits(1) {
+                            Ok(bit) => prefix = prefix << 1 | bit,
+                            Err(e) => return Some(Err(e)),
+                        }
+                        prefix_length += 1;
+                    }
+                    (prefix == token.1).then_some(Ok(token))
+                })
+                .ok_or_else(|| zgfx_error("Invalid token"))??;
+
+            if !token.3 {
+                let literal = token.4 + reader.bits(token.2)?;
+                self.push(literal as u8, output);
+                continue;
+            }
+
+            let distance = (token.4 + reader.bits(token.2)?) as usize;
+            if distance == 0 {
+                let count = reader.bits(15)? as usize;
+                for byte in reader.bytes(count)? {
+                    self.push(*byte, output);
+                }
+                continue;
+            }
+            if distance > HISTORY_SIZE {
+                return Err(zgfx_error("Match distance exceeds history"));
+            }
+
+            let count = if reader.bits(1)? == 0 {
+                3
+            } else {
+                let mut count = 4;
+                let mut extra = 2;
+                while reader.bits(1)? == 1 {
+                    count *= 2;
+                    extra += 1;
+                    if count > HISTORY_SIZE {
+                        return Err(zgfx_error("Match length exceeds history"));
+                    }
+                }
+                count + reader.bits(extra)? as usize
+            };
+            for _ in 0..count {
+                let byte = self.history[(self.index + HISTORY_SIZE - distance) % HISTORY_SIZE];
+                self.push(byte, output);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompress a segmented data message
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::codec::zgfx::Zgfx;
+    /// let mut zgfx = Zgfx::new();
+    /// assert_eq!(zgfx.decompress(&[0xe0, 0x04, 0x61, 0x62]).unwrap(), b"ab");
+    /// ```
+    pub fn decompress(&mut self, input: &[u8]) -> RdpResult<Vec<u8>> {
+        let mut stream = Cursor::new(input);
+        let mut output = Vec::new();
+        match stream.read_u8()? {
+            SEGMENTED_SINGLE => self.segment(&input[1..], &mut output)?,
+            SEGMENTED_MULTIPART => {
+                let count = stream.read_u16::<LittleEndian>()?;
+                let uncompressed_size = stream.read_u32::<LittleEndian>()? as usize;
+                output.reserve(uncompressed_size);
+                for _ in 0..count {
+                    let size = stream.read_u32::<LittleEndian>()? as usize;
+                    let mut segment = vec![0; size];
+                    stream.read_exact(&mut segment)?;
+                    self.segment(&segment, &mut output)?;
+                }
+                if output.len() != uncompressed_size {
+                    return Err(zgfx_error("Invalid uncompressed size"));
+                }
+            }
+            _ => return Err(zgfx_error("Invalid segmented data descriptor")),
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_literals() {
+        let mut zgfx = Zgfx::new();
+        assert_eq!(zgfx.decompress(&[0xe0, 0x24, 0x30, 0x98, 0x80, 0x06]).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn test_match() {
+        let mut zgfx = Zgfx::new();
+        assert_eq!(zgfx.decompress(&[0xe0, 0x24, 0x30, 0x98, 0x8c, 0x71, 0x18, 0x02]).unwrap(), b"abcabc");
+        // history is kept between messages
+        assert_eq!(zgfx.decompress(&[0xe0, 0x24, 0x89, 0x80, 0x05]).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_multipart() {
+        let mut zgfx = Zgfx::new();
+        let input = [0xe1, 2, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0x04, 0x61, 1, 0, 0, 0, 0x04];
+        assert!(zgfx.decompress(&input).is_err());
+        let input = [0xe1, 2, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0x04, 0x61, 1, 0, 0, 0, 0x04];
+        assert_eq!(zgfx.decompress(&input).unwrap(), b"a");
+    }
+}
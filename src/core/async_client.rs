@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::core::client::{Connector, RdpClient};
+use crate::core::event::RdpEvent;
+use crate::model::error::{Error, RdpResult};
+use crate::model::link::{is_would_block, FrameWait};
+
+/// Size of the chunk read from the async stream
+/// when waiting for new data from the server
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Bytes exchanged between the protocol stack and the async stream
+#[derive(Debug, Default)]
+struct Buffers {
+    /// Received and not read by the protocol stack yet
+    inbox: VecDeque<u8>,
+    /// Written by the protocol stack and not sent yet
+    outbox: VecDeque<u8>,
+    /// Set once the stream is closed by the peer
+    closed: bool,
+}
+
+fn lock_buffers(buffers: &Mutex<Buffers>) -> io::Result<MutexGuard<'_, Buffers>> {
+    buffers.lock().map_err(|_| io::Error::other("ASYNC: Poisoned buffers"))
+}
+
+/// `Read + Write` stream given to the protocol stack
+///
+/// It never blocks, a read without received data returns `WouldBlock`
+/// and writes are kept until `AsyncStream::send`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AsyncBridge {
+    buffers: Arc<Mutex<Buffers>>,
+}
+
+impl Read for AsyncBridge {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffers = lock_buffers(&self.buffers)?;
+        if buffers.inbox.is_empty() && !buffers.closed && !buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "ASYNC: No data received"));
+        }
+        buffers.inbox.read(buf)
+    }
+}
+
+impl Write for AsyncBridge {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock_buffers(&self.buffers)?.outbox.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Async stream moving the bytes of an `AsyncBridge`
+///
+/// Both directions are cancel safe, bytes are only
+/// removed from or added to the buffers once transferred
+#[derive(Debug)]
+struct AsyncStream<S> {
+    stream: S,
+    buffers: Arc<Mutex<Buffers>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncStream<S> {
+    /// Stream of the bridge
+    fn new(stream: S, bridge: &AsyncBridge) -> Self { AsyncStream { stream, buffers: Arc::clone(&bridge.buffers) } }
+
+    /// Send the bytes written by the protocol stack
+    async fn send(&mut self) -> RdpResult<()> {
+        loop {
+            let chunk = {
+                let mut buffers = lock_buffers(&self.buffers)?;
+                if buffers.outbox.is_empty() {
+                    break;
+                }
+                buffers.outbox.make_contiguous().to_vec()
+            };
+            let size = self.stream.write(&chunk).await?;
+            if size == 0 {
+                return Err(Error::Io(io::ErrorKind::WriteZero.into()));
+            }
+            lock_buffers(&self.buffers)?.outbox.drain(..size);
+        }
+        Ok(self.stream.flush().await?)
+    }
+
+    /// Wait for new bytes of the server
+    async fn receive(&mut self) -> RdpResult<()> {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let size = self.stream.read(&mut chunk).await?;
+        let mut buffers = lock_buffers(&self.buffers)?;
+        if size == 0 {
+            buffers.closed = true;
+        } else {
+            buffers.inbox.extend(&chunk[..size]);
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> FrameWait for AsyncStream<S> {
+    /// Pending writes are sent first, the server may wait for them
+    /// Bytes already received can hold the next frame
+    async fn frame(&mut self) -> RdpResult<()> {
+        self.send().await?;
+        if lock_buffers(&self.buffers)?.inbox.is_empty() {
+            self.receive().await?;
+        }
+        Ok(())
+    }
+
+    async fn data(&mut self) -> RdpResult<()> {
+        self.send().await?;
+        self.receive().await
+    }
+}
+
+/// Async version of the RDP client
+///
+/// The protocol stack runs on the calling task over buffers,
+/// it only waits on the async stream, never on a thread
+pub struct AsyncRdpClient<S> {
+    /// Client reading and writing the buffers of the bridge
+    client: RdpClient<AsyncBridge>,
+    /// Stream of the server behind the bridge
+    stream: AsyncStream<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRdpClient<S> {
+    /// Read a payload from the server
+    /// Same callback pattern than `RdpClient::read`
+    ///
+    /// This function is cancel safe and can be used in a `tokio::select!`
+    /// branch, a payload partially received is read again by the next call
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::async_client::connect_async;
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::event::RdpEvent;
+    /// # async fn run() {
+    /// let tcp = tokio::net::TcpStream::connect("127.0.0.1:3389").await.unwrap();
+    /// let connector = Connector::new()
+    ///     .screen(800, 600)
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connect_async(connector, tcp).await.unwrap();
+    /// client.read(|rdp_event| {
+    ///     match rdp_event {
+    ///         RdpEvent::Bitmap(bitmap) => {
+    ///             // do something with bitmap
+    ///         }
+    ///          _ => println!("Unhandled event")
+    ///     }
+    /// }).await.unwrap()
+    /// # }
+    /// ```
+    pub async fn read<T>(&mut self, mut callback: T) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        self.stream.frame().await?;
+        loop {
+            let result = self.client.read(&mut callback);
+            if !is_would_block(&result) {
+                result?;
+                // Answers of the client to the payload
+                return self.stream.send().await;
+            }
+            self.stream.data().await?;
+        }
+    }
+
+    /// Write an event to the server
+    /// Same as `RdpClient::write`
+    ///
+    /// If the future is dropped, the event is sent by the next call
+    pub async fn write(&mut self, event: RdpEvent) -> RdpResult<()> {
+        self.client.write(event)?;
+        self.stream.send().await
+    }
+
+    /// Write an event to the server
+    /// ignoring input event until the global channel is connected
+    /// Same as `RdpClient::try_write`
+    pub async fn try_write(&mut self, event: RdpEvent) -> RdpResult<()> {
+        self.client.try_write(event)?;
+        self.stream.send().await
+    }
+
+    /// Close the connection
+    pub async fn shutdown(&mut self) -> RdpResult<()> {
+        self.client.shutdown()?;
+        self.stream.send().await?;
+        Ok(self.stream.stream.shutdown().await?)
+    }
+}
+
+/// Connect to a target server from an async task
+/// Same as `Connector::connect`
+///
+/// The handshake waits for each answer of the server on the async stream
+/// The returned future is not `Send` as authentication contexts are not,
+/// run it with `block_on` or in a `LocalSet`, the client can then be moved
+///
+/// # Example
+/// ```no_run
+/// use rdp::core::async_client::connect_async;
+/// use rdp::core::client::Connector;
+/// # async fn run() {
+/// let tcp = tokio::net::TcpStream::connect("127.0.0.1:3389").await.unwrap();
+/// let connector = Connector::new()
+///     .screen(800, 600)
+///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+/// let mut client = connect_async(connector, tcp).await.unwrap();
+/// # }
+/// ```
+pub async fn connect_async<S>(mut connector: Connector, stream: S) -> RdpResult<AsyncRdpClient<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let bridge = AsyncBridge::default();
+    let mut stream = AsyncStream::new(stream, &bridge);
+    let client = connector.connect_with(bridge, &mut stream).await?;
+    // Last PDUs of the connection sequence
+    stream.send().await?;
+    Ok(AsyncRdpClient { client, stream })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::tpkt::{self, Payload};
+    use crate::model::link::{Link, Stream};
+    use crate::test_utils::mock_server::{fixture, MockRdpServer};
+
+    #[test]
+    fn test_async_bridge() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (client, mut server) = tokio::io::duplex(64);
+            let mut bridge = AsyncBridge::default();
+            let mut stream = AsyncStream::new(client, &bridge);
+            let mut result = vec![0; 4];
+            assert_eq!(bridge.read(&mut result).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+            server.write_all(&[1, 2, 3, 4]).await.unwrap();
+            bridge.write_all(&[5, 6]).unwrap();
+            stream.frame().await.unwrap();
+            bridge.read_exact(&mut result).unwrap();
+            assert_eq!(result, [1, 2, 3, 4]);
+
+            let mut written = vec![0; 2];
+            server.read_exact(&mut written).await.unwrap();
+            assert_eq!(written, [5, 6]);
+
+            drop(server);
+            stream.data().await.unwrap();
+            assert_eq!(bridge.read(&mut result).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_async_bridge_frame_split() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (client, mut server) = tokio::io::duplex(64);
+            let bridge = AsyncBridge::default();
+            let mut stream = AsyncStream::new(client, &bridge);
+            let mut link = Link::new(Stream::Raw(bridge));
+            link.set_resumable_reads(true);
+            let mut tpkt = tpkt::Client::new(link);
+
+            server.write_all(&[3, 0, 0, 8, 1]).await.unwrap();
+            stream.frame().await.unwrap();
+            assert!(is_would_block(&tpkt.read()));
+
+            server.write_all(&[2, 3, 4]).await.unwrap();
+            stream.data().await.unwrap();
+            match tpkt.read().unwrap() {
+                Payload::Raw(payload) => assert_eq!(payload.into_inner(), [1, 2, 3, 4]),
+                _ => panic!("Expected a raw payload"),
+            }
+        });
+    }
+
+    /// The TLS handshake and the NLA negotiate are driven by the task
+    #[test]
+    fn test_connect_async_nla_negotiate() {
+        let server = MockRdpServer::new()
+            .expect_receive(|pdu| pdu.len() == 19 && pdu[5] == 0xe0)
+            .send(&fixture("x224_confirm_hybrid.bin"))
+            .start_tls()
+            .expect_receive(|pdu| pdu[0] == 0x30 && pdu.windows(12).any(|w| w == b"NTLMSSP\0\x01\0\0\0"));
+        let address = server.address();
+        let server = server.run();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let result = runtime.block_on(async {
+            let tcp = tokio::net::TcpStream::connect(address).await.unwrap();
+            let connector =
+                Connector::new().credentials("domain".to_string(), "user".to_string(), "password".to_string());
+            connect_async(connector, tcp).await.map(|_| ())
+        });
+        // the server closes the connection instead of sending its challenge
+        assert!(result.is_err());
+        server.join().unwrap().unwrap();
+    }
+
+    fn is_send<T: Send>() {}
+
+    #[test]
+    fn test_async_client_is_send() { is_send::<AsyncRdpClient<tokio::io::DuplexStream>>(); }
+}
@@ -0,0 +1,275 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::sec::{PerformanceFlags, SecurityFlag};
+use crate::core::{mcs, tpkt};
+use crate::model::data::{Component, Message, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::time::Instant;
+
+/// Type of an auto-detect request
+const TYPE_ID_AUTODETECT_REQUEST: u8 = 0x00;
+
+/// Type of an auto-detect response
+const TYPE_ID_AUTODETECT_RESPONSE: u8 = 0x01;
+
+/// Below this bandwidth the visual effects are disabled
+const LOW_BANDWIDTH_KBPS: u32 = 2000;
+
+/// Auto-detect request sent by the server
+/// See MS-RDPBCGR 2.2.14.1
+#[repr(u16)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum RequestType {
+    RttContinuous = 0x0001,
+    RttConnectTime = 0x1001,
+    BwStartContinuous = 0x0014,
+    BwStartUdp = 0x0114,
+    BwStartConnectTime = 0x1014,
+    BwPayload = 0x0002,
+    BwStopContinuous = 0x002b,
+    BwStopUdp = 0x0429,
+    BwStopConnectTime = 0x0629,
+    NetcharResultRtt = 0x0840,
+    NetcharResultBandwidth = 0x0880,
+    NetcharResultAll = 0x08c0,
+}
+
+/// Auto-detect response sent by the client
+/// See MS-RDPBCGR 2.2.14.2
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ResponseType {
+    Rtt = 0x0000,
+    BwResultsConnectTime = 0x0003,
+    BwResultsContinuous = 0x000b,
+}
+
+/// Network characteristics of the session
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct BandwidthStats {
+    /// Kilobits per second
+    pub bandwidth_kbps: u32,
+    /// Lowest round trip time, known once the server sends its results
+    pub base_rtt: Option<Duration>,
+    /// Average round trip time, known once the server sends its results
+    pub average_rtt: Option<Duration>,
+}
+
+impl BandwidthStats {
+    /// Visual effects suited to the measured bandwidth
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::autodetect::BandwidthStats;
+    /// use rdp::core::sec::PerformanceFlags;
+    /// let modem = BandwidthStats { bandwidth_kbps: 56, ..Default::default() };
+    /// assert_eq!(modem.performance_flags(), PerformanceFlags::LOW_BANDWIDTH);
+    /// let lan = BandwidthStats { bandwidth_kbps: 100_000, ..Default::default() };
+    /// assert_eq!(lan.performance_flags(), PerformanceFlags::empty());
+    /// ```
+    pub fn performance_flags(&self) -> PerformanceFlags {
+        if self.bandwidth_kbps < LOW_BANDWIDTH_KBPS {
+            PerformanceFlags::LOW_BANDWIDTH
+        } else {
+            PerformanceFlags::empty()
+        }
+    }
+}
+
+/// Auto-detect response header
+fn autodetect_response(sequence_number: u16, response_type: ResponseType, body: Component) -> Component {
+    component![
+        "headerLength" => (6 + body.length()) as u8,
+        "headerTypeId" => TYPE_ID_AUTODETECT_RESPONSE,
+        "sequenceNumber" => U16::LE(sequence_number),
+        "responseType" => U16::LE(response_type as u16),
+        "body" => body
+    ]
+}
+
+fn autodetect_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("AUTODETECT: {}", message)))
+}
+
+/// Answer the network auto-detection of the server
+/// Requests come with the connect-time or continuous request types
+///
+/// See MS-RDPBCGR 3.2.5.14
+#[derive(Debug, Default)]
+pub struct AutoDetector {
+    /// Start of the bandwidth measure
+    /// with the number of bytes received at that time
+    measure: Option<(Instant, u64)>,
+    /// Last measure or results of the server
+    stats: Option<BandwidthStats>,
+}
+
+impl AutoDetector {
+    pub fn new() -> Self { Self::default() }
+
+    /// None until a bandwidth measure is done
+    pub fn bandwidth_estimate(&self) -> Option<BandwidthStats> { self.stats }
+
+    /// Read a PDU of the message channel
+    /// Heartbeats are ignored
+    pub fn read<S: Read + Write>(&mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let mut stream = try_let!(tpkt::Payload::Raw, payload)?;
+        let flags = stream.read_u16::<LittleEndian>()?;
+        let _flags_hi = stream.read_u16::<LittleEndian>()?;
+        if flags & SecurityFlag::SecAutodetectReq as u16 == 0 {
+            log_debug!("AUTODETECT: message channel PDU not handled {:#x}", flags);
+            return Ok(());
+        }
+        self.read_request(&mcs::MESSAGE_CHANNEL_NAME.to_string(), &mut stream, mcs)
+    }
+
+    /// Read a request after its security header
+    /// and answer on the same channel
+    pub fn read_request<S: Read + Write>(
+        &mut self, channel_name: &String, stream: &mut dyn Read, mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        let (_, received) = mcs.get_traffic();
+        if let Some(response) = self.process(stream, received, Instant::now())? {
+            mcs.write_with_security_header(channel_name, SecurityFlag::SecAutodetectRsp as u16, response)?;
+        }
+        Ok(())
+    }
+
+    /// Process a request
+    /// `received` is the number of bytes received so far
+    /// Return the response if one is expected
+    fn process(&mut self, stream: &mut dyn Read, received: u64, now: Instant) -> RdpResult<Option<Component>> {
+        let _header_length = stream.read_u8()?;
+        if stream.read_u8()? != TYPE_ID_AUTODETECT_REQUEST {
+            return Err(autodetect_error("Invalid request header"));
+        }
+        let sequence_number = stream.read_u16::<LittleEndian>()?;
+        let request_type = stream.read_u16::<LittleEndian>()?;
+
+        let request_type = match RequestType::try_from(request_type) {
+            Ok(request_type) => request_type,
+            Err(_) => {
+                log_debug!("AUTODETECT: request not handled {:#x}", request_type);
+                return Ok(None);
+            }
+        };
+
+        match request_type {
+            RequestType::RttContinuous | RequestType::RttConnectTime => {
+                Ok(Some(autodetect_response(sequence_number, ResponseType::Rtt, component![])))
+            }
+            RequestType::BwStartContinuous | RequestType::BwStartUdp | RequestType::BwStartConnectTime => {
+                self.measure = Some((now, received));
+                Ok(None)
+            }
+            // Payloads are only counted as received bytes
+            RequestType::BwPayload => Ok(None),
+            RequestType::BwStopContinuous | RequestType::BwStopUdp | RequestType::BwStopConnectTime => {
+                let (start, start_received) = match self.measure.take() {
+                    Some(measure) => measure,
+                    None => {
+                        log_debug!("AUTODETECT: bandwidth measure stopped before its start");
+                        return Ok(None);
+                    }
+                };
+                let time_delta = now.duration_since(start).as_millis().min(u128::from(u32::MAX)) as u32;
+                let byte_count = received.saturating_sub(start_received).min(u64::from(u32::MAX)) as u32;
+                // bits per millisecond are kilobits per second
+                let bandwidth_kbps = (u64::from(byte_count) * 8 / u64::from(time_delta.max(1))) as u32;
+                let stats = self.stats.get_or_insert_with(BandwidthStats::default);
+                stats.bandwidth_kbps = bandwidth_kbps;
+                let response_type = if request_type == RequestType::BwStopConnectTime {
+                    ResponseType::BwResultsConnectTime
+                } else {
+                    ResponseType::BwResultsContinuous
+                };
+                Ok(Some(autodetect_response(
+                    sequence_number,
+                    response_type,
+                    component![
+                        "timeDelta" => U32::LE(time_delta),
+                        "byteCount" => U32::LE(byte_count)
+                    ],
+                )))
+            }
+            // See MS-RDPBCGR 2.2.14.1.5
+            RequestType::NetcharResultRtt | RequestType::NetcharResultBandwidth | RequestType::NetcharResultAll => {
+                let stats = self.stats.get_or_insert_with(BandwidthStats::default);
+                let read_rtt = |stream: &mut dyn Read| -> RdpResult<Duration> {
+                    Ok(Duration::from_millis(u64::from(stream.read_u32::<LittleEndian>()?)))
+                };
+                match request_type {
+                    RequestType::NetcharResultRtt => {
+                        stats.base_rtt = Some(read_rtt(stream)?);
+                        stats.average_rtt = Some(read_rtt(stream)?);
+                    }
+                    RequestType::NetcharResultBandwidth => {
+                        stats.bandwidth_kbps = stream.read_u32::<LittleEndian>()?;
+                        stats.average_rtt = Some(read_rtt(stream)?);
+                    }
+                    _ => {
+                        stats.base_rtt = Some(read_rtt(stream)?);
+                        stats.bandwidth_kbps = stream.read_u32::<LittleEndian>()?;
+                        stats.average_rtt = Some(read_rtt(stream)?);
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::model::data::to_vec;
+
+    fn response_bytes(response: Option<Component>) -> Option<Vec<u8>> { response.map(|response| to_vec(&response)) }
+
+    #[test]
+    fn test_rtt_response() {
+        let mut detector = AutoDetector::new();
+        let request = [6, 0, 7, 0, 0x01, 0x10];
+        let response = detector.process(&mut Cursor::new(request), 0, Instant::now()).unwrap();
+        assert_eq!(response_bytes(response), Some(vec![6, 1, 7, 0, 0, 0]));
+        assert_eq!(detector.bandwidth_estimate(), None);
+    }
+
+    #[test]
+    fn test_bandwidth_measure() {
+        let mut detector = AutoDetector::new();
+        let start = Instant::now();
+        assert!(detector.process(&mut Cursor::new([6, 0, 1, 0, 0x14, 0x10]), 100, start).unwrap().is_none());
+        let payload = [8, 0, 2, 0, 0x02, 0, 2, 0, 0xaa, 0xbb];
+        assert!(detector.process(&mut Cursor::new(payload), 200, start).unwrap().is_none());
+        let stop = [8, 0, 3, 0, 0x29, 0x06, 0, 0];
+        let response = detector.process(&mut Cursor::new(stop), 25_100, start + Duration::from_millis(100)).unwrap();
+        // 25000 bytes in 100 ms
+        assert_eq!(response_bytes(response), Some(vec![14, 1, 3, 0, 3, 0, 100, 0, 0, 0, 0xa8, 0x61, 0, 0]));
+        let stats = detector.bandwidth_estimate().unwrap();
+        assert_eq!(stats.bandwidth_kbps, 2000);
+        assert_eq!(stats.performance_flags(), PerformanceFlags::empty());
+
+        // results of the server replace the measure
+        let result = [18, 0, 4, 0, 0xc0, 0x08, 10, 0, 0, 0, 0x38, 0, 0, 0, 20, 0, 0, 0];
+        assert!(detector.process(&mut Cursor::new(result), 0, start).unwrap().is_none());
+        let stats = detector.bandwidth_estimate().unwrap();
+        assert_eq!(stats.bandwidth_kbps, 56);
+        assert_eq!(stats.base_rtt, Some(Duration::from_millis(10)));
+        assert_eq!(stats.average_rtt, Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let mut detector = AutoDetector::new();
+        assert!(detector.process(&mut Cursor::new([6, 1, 0, 0, 1, 0]), 0, Instant::now()).is_err());
+        // stop without start
+        assert!(detector.process(&mut Cursor::new([6, 0, 0, 0, 0x2b, 0]), 0, Instant::now()).unwrap().is_none());
+    }
+}
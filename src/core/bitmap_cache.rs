@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt as _, WriteBytesExt as _};
+
+use crate::core::capability::BITMAP_CACHE_ENTRIES;
+use crate::core::orders::CachedBitmap;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Header of the cache file
+const CACHE_FILE_MAGIC: &[u8; 8] = b"RDPBMC01";
+
+fn cache_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("BITMAP CACHE: {}", message)))
+}
+
+/// Bitmaps kept between sessions
+/// Keyed by the 64 bits key sent with cache bitmap revision 2 orders
+///
+/// The keys are sent back to the server at the next connection
+/// so it can draw these bitmaps without sending them again
+///
+/// See MS-RDPBCGR 2.2.1.17 and MS-RDPEGDI 2.2.2.2.1.2.3
+#[derive(Debug, Default, Clone)]
+pub struct PersistentBitmapCache {
+    entries: HashMap<u64, (u8, CachedBitmap)>,
+    /// File the cache was loaded from
+    path: Option<PathBuf>,
+}
+
+impl PersistentBitmapCache {
+    pub fn new() -> Self { Self::default() }
+
+    /// Load the cache of a previous session
+    /// A missing or invalid file gives an empty cache, saved there later
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::bitmap_cache::PersistentBitmapCache;
+    /// let cache = PersistentBitmapCache::open("/nonexistent/bitmaps.cache").unwrap();
+    /// assert!(cache.is_empty());
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> RdpResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut cache = match File::open(&path) {
+            Ok(file) => Self::read(&mut BufReader::new(file)).unwrap_or_else(|e| {
+                log_warn!("BITMAP CACHE: Ignore invalid cache file {:?}", e);
+                Self::new()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::new(),
+            Err(e) => return Err(e.into()),
+        };
+        cache.path = Some(path);
+        Ok(cache)
+    }
+
+    /// Write the cache in the file it was opened from
+    /// Nothing is done for a cache created in memory
+    pub fn save(&self) -> RdpResult<()> {
+        if let Some(path) = &self.path {
+            let mut file = BufWriter::new(File::create(path)?);
+            self.write(&mut file)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Read the entries written by `write`
+    pub fn read(stream: &mut dyn Read) -> RdpResult<Self> {
+        let mut magic = [0; 8];
+        stream.read_exact(&mut magic)?;
+        if &magic != CACHE_FILE_MAGIC {
+            return Err(cache_error("Invalid file header"));
+        }
+        let mut cache = Self::new();
+        for _ in 0..stream.read_u32::<LittleEndian>()? {
+            let key = stream.read_u64::<LittleEndian>()?;
+            let cache_id = stream.read_u8()?;
+            let width = stream.read_u16::<LittleEndian>()?;
+            let height = stream.read_u16::<LittleEndian>()?;
+            // The size is checked before anything is allocated
+            let length = usize::from(width) * usize::from(height) * 4;
+            let mut data = Vec::new();
+            stream.take(length as u64).read_to_end(&mut data)?;
+            if data.len() != length {
+                return Err(cache_error("Truncated bitmap"));
+            }
+            cache.insert(key, cache_id, CachedBitmap { width, height, data });
+        }
+        Ok(cache)
+    }
+
+    /// Write the entries, bitmaps are 32 bpp BGRA
+    pub fn write(&self, stream: &mut dyn Write) -> RdpResult<()> {
+        stream.write_all(CACHE_FILE_MAGIC)?;
+        stream.write_u32::<LittleEndian>(self.entries.len() as u32)?;
+        for (key, (cache_id, bitmap)) in &self.entries {
+            stream.write_u64::<LittleEndian>(*key)?;
+            stream.write_u8(*cache_id)?;
+            stream.write_u16::<LittleEndian>(bitmap.width)?;
+            stream.write_u16::<LittleEndian>(bitmap.height)?;
+            stream.write_all(&bitmap.data)?;
+        }
+        Ok(())
+    }
+
+    /// Keep a bitmap sent with a persistent key
+    /// Bitmaps beyond the size of their cache are dropped
+    ///
+    /// Return true if the bitmap is kept
+    pub fn insert(&mut self, key: u64, cache_id: u8, bitmap: CachedBitmap) -> bool {
+        let capacity = BITMAP_CACHE_ENTRIES.get(usize::from(cache_id)).copied().unwrap_or(0) as usize;
+        let valid = bitmap.fits_cell(cache_id);
+        let is_full = || self.entries.values().filter(|(id, _)| *id == cache_id).count() >= capacity;
+        if !valid || (!self.entries.contains_key(&key) && is_full()) {
+            return false;
+        }
+        self.entries.insert(key, (cache_id, bitmap));
+        true
+    }
+
+    /// Bitmap and cache id of a key
+    pub fn get(&self, key: u64) -> Option<(u8, &CachedBitmap)> {
+        self.entries.get(&key).map(|(cache_id, bitmap)| (*cache_id, bitmap))
+    }
+
+    /// Keys of a cache in the order of the key list PDUs
+    /// The position of a key is its index in the cache
+    pub fn keys(&self, cache_id: u8) -> Vec<u64> {
+        let mut keys: Vec<u64> =
+            self.entries.iter().filter(|(_, (id, _))| *id == cache_id).map(|(key, _)| *key).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Number of kept bitmaps
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitmap(value: u8) -> CachedBitmap { CachedBitmap { width: 2, height: 1, data: vec![value; 8] } }
+
+    #[test]
+    fn test_persistent_bitmap_cache_roundtrip() {
+        let mut cache = PersistentBitmapCache::new();
+        assert!(cache.insert(0x2000_0000_0000_0001, 1, bitmap(1)));
+        assert!(cache.insert(7, 1, bitmap(2)));
+        assert!(cache.insert(3, 0, bitmap(3)));
+        // data of the wrong size
+        assert!(!cache.insert(4, 0, CachedBitmap { width: 2, height: 2, data: vec![0; 8] }));
+
+        let mut stream = Vec::new();
+        cache.write(&mut stream).unwrap();
+        let cache = PersistentBitmapCache::read(&mut stream.as_slice()).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.keys(1), [7, 0x2000_0000_0000_0001]);
+        assert_eq!(cache.keys(0), [3]);
+        let (cache_id, bitmap) = cache.get(3).unwrap();
+        assert_eq!((cache_id, bitmap.data.as_slice()), (0, &[3; 8][..]));
+
+        assert!(PersistentBitmapCache::read(&mut &b"RDPBMC00\0\0\0\0"[..]).is_err());
+        assert!(PersistentBitmapCache::read(&mut &stream[..stream.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_persistent_bitmap_cache_capacity() {
+        let mut cache = PersistentBitmapCache::new();
+        for key in 0..BITMAP_CACHE_ENTRIES[0] as u64 {
+            assert!(cache.insert(key, 0, bitmap(0)));
+        }
+        assert!(!cache.insert(u64::MAX, 0, bitmap(0)));
+        // replacing a bitmap is always possible
+        assert!(cache.insert(0, 0, bitmap(1)));
+        assert!(!cache.insert(u64::MAX - 1, 5, bitmap(0)));
+    }
+
+    #[test]
+    fn test_persistent_bitmap_cache_file() {
+        let path = std::env::temp_dir().join(format!("rdp-rs-bitmaps-{}.cache", std::process::id()));
+        let mut cache = PersistentBitmapCache::open(&path).unwrap();
+        assert!(cache.is_empty());
+        cache.insert(42, 2, bitmap(4));
+        cache.save().unwrap();
+        let cache = PersistentBitmapCache::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cache.keys(2), [42]);
+    }
+}
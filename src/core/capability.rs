@@ -1,8 +1,13 @@
 use std::convert::TryFrom;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use indexmap::IndexMap;
 use num_enum::TryFromPrimitive;
 
+use crate::codec::jpeg::JPEG_CODEC_ID;
+use crate::codec::nscodec::NSCODEC_ID;
+use crate::codec::rfx::RFX_CODEC_ID;
 use crate::core::gcc::{KeyboardLayout, KeyboardType};
 use crate::model::data::{to_vec, Check, Component, DataType, DynOption, Message, MessageOption, Trame, U16, U32};
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
@@ -82,18 +87,24 @@ impl Capability {
     /// ```
     pub fn from_capability_set(capability_set: &Component) -> RdpResult<Capability> {
         let cap_type = CapabilitySetType::try_from(cast!(DataType::U16, capability_set["capabilitySetType"])?)?;
+        let data = cast!(DataType::Slice, capability_set["capabilitySet"])?;
         let mut capability = match cap_type {
             CapabilitySetType::General => ts_general_capability_set(None),
             CapabilitySetType::Bitmap => ts_bitmap_capability_set(None, None, None),
             CapabilitySetType::Order => ts_order_capability_set(None),
             CapabilitySetType::Bitmapcache => ts_bitmap_cache_capability_set(),
+            CapabilitySetType::BitmapcacheRev2 => ts_bitmap_cache_rev2_capability_set(&[]),
+            // pointerCacheSize is only present with new pointer support
+            CapabilitySetType::Pointer if data.len() >= 6 => ts_new_pointer_capability_set(0),
             CapabilitySetType::Pointer => ts_pointer_capability_set(),
+            CapabilitySetType::LargePointer => ts_large_pointer_capability_set(0),
             CapabilitySetType::Input => ts_input_capability_set(None, None),
             CapabilitySetType::Brush => ts_brush_capability_set(),
             CapabilitySetType::Glyphcache => ts_glyph_capability_set(),
             CapabilitySetType::Offscreencache => ts_offscreen_capability_set(),
             CapabilitySetType::Virtualchannel => ts_virtualchannel_capability_set(),
             CapabilitySetType::Sound => ts_sound_capability_set(),
+            CapabilitySetType::Font => ts_font_capability_set(),
             CapabilitySetType::Multifragmentupdate => ts_multifragment_update_capability_ts(),
             _ => {
                 return Err(Error::RdpError(RdpError::new(
@@ -102,7 +113,7 @@ impl Capability {
                 )))
             }
         };
-        capability.message.read(&mut Cursor::new(cast!(DataType::Slice, capability_set["capabilitySet"])?))?;
+        capability.message.read_from_bytes(data)?;
         Ok(capability)
     }
 }
@@ -263,6 +274,45 @@ pub enum OrderFlag {
 /// assert_eq!(to_vec(&capability_set), vec![3, 0, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 20, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 132, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0])
 /// ```
 pub fn ts_order_capability_set(order_flags: Option<u16>) -> Capability {
+    ts_order_capability_set_with_orders(order_flags, &[])
+}
+
+/// Index of a drawing order in the order support array
+/// See MS-RDPBCGR 2.2.7.1.3
+#[repr(usize)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+pub enum OrderSupportIndex {
+    DstBlt = 0x00,
+    PatBlt = 0x01,
+    ScrBlt = 0x02,
+    MemBlt = 0x03,
+    Mem3Blt = 0x04,
+    DrawNineGrid = 0x07,
+    LineTo = 0x08,
+    MultiDrawNineGrid = 0x09,
+    OpaqueRect = 0x0A,
+}
+
+/// Order capability announcing the supported drawing orders
+///
+/// # Example
+/// ```
+/// #[macro_use]
+/// # extern crate rdp;
+/// # use rdp::model::data::DataType;
+/// # use rdp::model::error::{Error, RdpError, RdpResult, RdpErrorKind};
+/// fn main() {
+///     use rdp::core::capability::{ts_order_capability_set_with_orders, OrderSupportIndex};
+///     let capability = ts_order_capability_set_with_orders(None, &[OrderSupportIndex::MemBlt]);
+///     assert_eq!(cast!(DataType::Slice, capability.message["orderSupport"]).unwrap()[0..4], [0, 0, 0, 1])
+/// }
+/// ```
+pub fn ts_order_capability_set_with_orders(order_flags: Option<u16>, orders: &[OrderSupportIndex]) -> Capability {
+    let mut order_support = vec![0_u8; 32];
+    for order in orders {
+        order_support[*order as usize] = 1;
+    }
     Capability {
         cap_type: CapabilitySetType::Order,
         message: component![
@@ -274,7 +324,7 @@ pub fn ts_order_capability_set(order_flags: Option<u16>) -> Capability {
             "maximumOrderLevel" => U16::LE(1),
             "numberFonts" => U16::LE(0),
             "orderFlags" => U16::LE(order_flags.unwrap_or(OrderFlag::NegotiateOrderSupport as u16)),
-            "orderSupport" => vec![0_u8; 32],
+            "orderSupport" => order_support,
             "textFlags" => U16::LE(0),
             "orderSupportExFlags" => U16::LE(0),
             "pad4octetsB" => U32::LE(0),
@@ -318,6 +368,104 @@ pub fn ts_bitmap_cache_capability_set() -> Capability {
     }
 }
 
+/// Number of entries of each bitmap cache
+/// Cells of the caches are 16x16, 32x32 and 64x64 pixels
+pub const BITMAP_CACHE_ENTRIES: [u32; 3] = [600, 600, 2048];
+
+/// Number of bitmaps of the draw nine grid cache
+pub const NINE_GRID_CACHE_ENTRIES: u16 = 256;
+
+/// Size of the draw nine grid cache in kilobytes
+pub const NINE_GRID_CACHE_SIZE: u16 = 2560;
+
+/// The client supports the draw nine grid and multi draw nine grid orders
+/// See MS-RDPEGDI 2.2.1.2
+const DRAW_NINEGRID_SUPPORTED_REV2: u32 = 0x0000_0002;
+
+/// Draw nine grid cache capability
+/// Sent with the draw nine grid orders in the order capability
+///
+/// See MS-RDPEGDI 2.2.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_draw_nine_grid_cache_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_draw_nine_grid_cache_capability_set(true)));
+/// assert_eq!(to_vec(&capability_set), [0x15, 0, 12, 0, 2, 0, 0, 0, 0, 0x0a, 0, 1])
+/// ```
+pub fn ts_draw_nine_grid_cache_capability_set(supported: bool) -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::Drawninegridcache,
+        message: component![
+            "drawNineGridSupportLevel" => U32::LE(if supported { DRAW_NINEGRID_SUPPORTED_REV2 } else { 0 }),
+            "drawNineGridCacheSize" => U16::LE(NINE_GRID_CACHE_SIZE),
+            "drawNineGridCacheEntries" => U16::LE(NINE_GRID_CACHE_ENTRIES)
+        ],
+    }
+}
+
+/// The client sends persistent key list PDUs
+/// See MS-RDPBCGR 2.2.7.1.4.2
+const PERSISTENT_KEYS_EXPECTED_FLAG: u16 = 0x0001;
+
+/// Bit of the cell info of a persistent cache
+/// See MS-RDPBCGR 2.2.7.1.4.2.1
+const CELL_INFO_PERSISTENT: u32 = 0x8000_0000;
+
+fn bitmap_cache_rev2(cache_entries: &[u32], persistent: bool) -> Capability {
+    let persistent_bit = if persistent { CELL_INFO_PERSISTENT } else { 0 };
+    let cell_info = |index: usize| match cache_entries.get(index) {
+        Some(entries) => U32::LE(entries & 0x7fff_ffff | persistent_bit),
+        None => U32::LE(0),
+    };
+    Capability {
+        cap_type: CapabilitySetType::BitmapcacheRev2,
+        message: component![
+            "cacheFlags" => U16::LE(if persistent { PERSISTENT_KEYS_EXPECTED_FLAG } else { 0 }),
+            "pad2" => 0_u8,
+            "numCellCaches" => cache_entries.len().min(5) as u8,
+            "bitmapCache0CellInfo" => cell_info(0),
+            "bitmapCache1CellInfo" => cell_info(1),
+            "bitmapCache2CellInfo" => cell_info(2),
+            "bitmapCache3CellInfo" => cell_info(3),
+            "bitmapCache4CellInfo" => cell_info(4),
+            "pad3" => vec![0_u8; 12]
+        ],
+    }
+}
+
+/// Revision 2 of the bitmap cache capability
+/// Caches are not persistent
+///
+/// See MS-RDPBCGR 2.2.7.1.4.2
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_bitmap_cache_rev2_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_bitmap_cache_rev2_capability_set(&[600])));
+/// assert_eq!(to_vec(&capability_set)[0..12], [0x13, 0, 40, 0, 0, 0, 0, 1, 0x58, 2, 0, 0])
+/// ```
+pub fn ts_bitmap_cache_rev2_capability_set(cache_entries: &[u32]) -> Capability {
+    bitmap_cache_rev2(cache_entries, false)
+}
+
+/// Revision 2 of the bitmap cache capability
+/// All caches are persistent and the client sends
+/// the keys of the bitmaps kept from previous sessions
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_persistent_bitmap_cache_rev2_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_persistent_bitmap_cache_rev2_capability_set(&[600])));
+/// assert_eq!(to_vec(&capability_set)[0..16], [0x13, 0, 40, 0, 1, 0, 0, 1, 0x58, 2, 0, 0x80, 0, 0, 0, 0])
+/// ```
+pub fn ts_persistent_bitmap_cache_rev2_capability_set(cache_entries: &[u32]) -> Capability {
+    bitmap_cache_rev2(cache_entries, true)
+}
+
 /// Pointer capability
 /// send by both client and server
 ///
@@ -340,6 +488,60 @@ pub fn ts_pointer_capability_set() -> Capability {
     }
 }
 
+/// Pointer capability with new pointer support
+/// Pointers may then be sent with any color depth
+///
+/// See MS-RDPBCGR 2.2.7.1.5
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_new_pointer_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_new_pointer_capability_set(25)));
+/// assert_eq!(to_vec(&capability_set), vec![8, 0, 10, 0, 1, 0, 20, 0, 25, 0])
+/// ```
+pub fn ts_new_pointer_capability_set(pointer_cache_size: u16) -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::Pointer,
+        message: component![
+            "colorPointerFlag" => U16::LE(1),
+            "colorPointerCacheSize" => U16::LE(20),
+            "pointerCacheSize" => U16::LE(pointer_cache_size)
+        ],
+    }
+}
+
+/// Large pointer support flags
+/// See MS-RDPBCGR 2.2.7.2.7
+#[repr(u16)]
+#[derive(Debug, Copy, Clone)]
+pub enum LargePointerFlag {
+    /// Pointers up to 96x96 pixels
+    Support96x96 = 0x0001,
+    /// Pointers up to 384x384 pixels
+    Support384x384 = 0x0002,
+}
+
+/// Large pointer capability
+///
+/// See MS-RDPBCGR 2.2.7.2.7
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_large_pointer_capability_set, LargePointerFlag};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_large_pointer_capability_set(LargePointerFlag::Support96x96 as u16)));
+/// assert_eq!(to_vec(&capability_set), vec![27, 0, 6, 0, 1, 0])
+/// ```
+pub fn ts_large_pointer_capability_set(flags: u16) -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::LargePointer,
+        message: component![
+            "largePointerSupportFlags" => U16::LE(flags)
+        ],
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, Copy, Clone)]
 pub enum InputFlags {
@@ -352,13 +554,15 @@ pub enum InputFlags {
     /// This feature is supported by rdp-rs
     Mousex = 0x0004,
     /// The capability to send fastpath input
-    /// This feature is NOT supported by rdp-rs
+    /// rdp-rs only checks the second flag
     FastpathInput = 0x0008,
     /// In order to send keyboard scancode
     /// We can send directly UNICODE code of char
     /// Usefull if we want to send script
     /// This feature is supported by rdp-rs
     Unicode = 0x0010,
+    /// Fastpath input announced by the server
+    /// This feature is supported by rdp-rs
     FastpathInput2 = 0x0020,
     Unused1 = 0x0040,
     MouseRelative = 0x0080,
@@ -386,7 +590,7 @@ pub fn ts_input_capability_set(input_flags: Option<u16>, keyboard_layout: Option
         message: component![
             "inputFlags" => U16::LE(input_flags.unwrap_or(0)),
             "pad2octetsA" => U16::LE(0),
-            "keyboardLayout" => U32::LE(keyboard_layout.unwrap_or(KeyboardLayout::French) as u32),
+            "keyboardLayout" => U32::LE(keyboard_layout.unwrap_or(KeyboardLayout::French).code()),
             "keyboardType" => U32::LE(KeyboardType::Ibm101102Keys as u32),
             "keyboardSubType" => U32::LE(0),
             "keyboardFunctionKey" => U32::LE(12),
@@ -524,6 +728,72 @@ pub fn ts_sound_capability_set() -> Capability {
     }
 }
 
+/// Font capability
+/// Its fields are optional, some servers send an empty set
+///
+/// See MS-RDPBCGR 2.2.7.2.5
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_font_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_font_capability_set()));
+/// assert_eq!(to_vec(&capability_set), vec![14, 0, 8, 0, 1, 0, 0, 0])
+/// ```
+pub fn ts_font_capability_set() -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::Font,
+        message: component![
+            "fontSupportFlags" => Some(U16::LE(FONTSUPPORT_FONTLIST)),
+            "pad2octets" => Some(U16::LE(0))
+        ],
+    }
+}
+
+/// Remote programs capability
+/// Announce the RemoteApp support of the client
+///
+/// See MS-RDPERP 2.2.1.1.1
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_rail_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_rail_capability_set()));
+/// assert_eq!(to_vec(&capability_set), vec![23, 0, 8, 0, 1, 0, 0, 0])
+/// ```
+pub fn ts_rail_capability_set() -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::Rail,
+        message: component![
+            "RailSupportLevel" => U32::LE(RAIL_LEVEL_SUPPORTED)
+        ],
+    }
+}
+
+/// Window list capability
+/// Needed to receive the window orders of RemoteApp
+///
+/// See MS-RDPERP 2.2.1.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_window_capability_set};
+/// use rdp::model::data::to_vec;
+/// let capability_set = capability_set(Some(ts_window_capability_set()));
+/// assert_eq!(to_vec(&capability_set), vec![24, 0, 11, 0, 1, 0, 0, 0, 3, 12, 0])
+/// ```
+pub fn ts_window_capability_set() -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::Window,
+        message: component![
+            "WndSupportLevel" => U32::LE(WINDOW_LEVEL_SUPPORTED),
+            "NumIconCaches" => 3_u8,
+            "NumIconCacheEntries" => U16::LE(12)
+        ],
+    }
+}
+
 /// Multi fragment capability
 /// send by both side (client, server)
 ///
@@ -545,3 +815,884 @@ pub fn ts_multifragment_update_capability_ts() -> Capability {
         ],
     }
 }
+
+/// Surface commands accepted by the client
+/// See MS-RDPBCGR 2.2.7.2.9
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+pub enum SurfaceCommandFlag {
+    SetSurfaceBits = 0x0000_0002,
+    FrameMarker = 0x0000_0010,
+    StreamSurfaceBits = 0x0000_0040,
+}
+
+/// Surface commands capability
+///
+/// See MS-RDPBCGR 2.2.7.2.9
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_surface_commands_capability_set, SurfaceCommandFlag};
+/// use rdp::model::data::to_vec;
+/// let flags = SurfaceCommandFlag::SetSurfaceBits as u32 | SurfaceCommandFlag::StreamSurfaceBits as u32;
+/// let capability_set = capability_set(Some(ts_surface_commands_capability_set(flags)));
+/// assert_eq!(to_vec(&capability_set), vec![28, 0, 12, 0, 0x42, 0, 0, 0, 0, 0, 0, 0])
+/// ```
+pub fn ts_surface_commands_capability_set(cmd_flags: u32) -> Capability {
+    Capability {
+        cap_type: CapabilitySetType::SurfaceCommands,
+        message: component![
+            "cmdFlags" => U32::LE(cmd_flags),
+            "reserved" => U32::LE(0)
+        ],
+    }
+}
+
+/// Bitmap codecs of the bitmap codecs capability
+/// See MS-RDPBCGR 2.2.7.2.10.1.1
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BitmapCodec {
+    NsCodec,
+    RemoteFx,
+    Jpeg,
+}
+
+impl BitmapCodec {
+    /// GUID of the codec as sent on the wire
+    fn guid(self) -> [u8; 16] {
+        match self {
+            // CODEC_GUID_NSCODEC {CA8D1BB9-000F-154F-589F-AE2D1A87E2D6}
+            BitmapCodec::NsCodec => {
+                [0xb9, 0x1b, 0x8d, 0xca, 0x0f, 0x00, 0x4f, 0x15, 0x58, 0x9f, 0xae, 0x2d, 0x1a, 0x87, 0xe2, 0xd6]
+            }
+            // CODEC_GUID_REMOTEFX {76772F12-BD72-4463-AFB3-B73C9C6F7886}
+            BitmapCodec::RemoteFx => {
+                [0x12, 0x2f, 0x77, 0x76, 0x72, 0xbd, 0x63, 0x44, 0xaf, 0xb3, 0xb7, 0x3c, 0x9c, 0x6f, 0x78, 0x86]
+            }
+            // CODEC_GUID_JPEG {1BAF4CE6-9EED-430C-869A-CB8B37B66237}
+            BitmapCodec::Jpeg => {
+                [0xe6, 0x4c, 0xaf, 0x1b, 0xed, 0x9e, 0x0c, 0x43, 0x86, 0x9a, 0xcb, 0x8b, 0x37, 0xb6, 0x62, 0x37]
+            }
+        }
+    }
+
+    fn from_guid(guid: &[u8; 16]) -> Option<Self> {
+        [BitmapCodec::NsCodec, BitmapCodec::RemoteFx, BitmapCodec::Jpeg].into_iter().find(|codec| codec.guid() == *guid)
+    }
+
+    /// Id assigned by the client in the confirm active PDU
+    /// The server uses it in the surface bits commands
+    pub fn id(self) -> u8 {
+        match self {
+            BitmapCodec::NsCodec => NSCODEC_ID,
+            BitmapCodec::RemoteFx => RFX_CODEC_ID,
+            BitmapCodec::Jpeg => JPEG_CODEC_ID,
+        }
+    }
+
+    /// The client can decode the codec
+    /// JPEG needs the `jpeg` feature
+    pub fn is_supported(self) -> bool { self != BitmapCodec::Jpeg || cfg!(feature = "jpeg") }
+
+    /// Codec properties sent by the client
+    fn client_properties(self) -> Vec<u8> {
+        match self {
+            // dynamic fidelity, subsampling and color loss level 3
+            // See MS-RDPNSC 2.2.1
+            BitmapCodec::NsCodec => vec![1, 1, 3],
+            // TS_RFX_CLNT_CAPS_CONTAINER with a single capset
+            // of RLGR1 and RLGR3 64x64 tiles, See MS-RDPRFX 2.2.1.1
+            BitmapCodec::RemoteFx => {
+                let mut properties = Vec::new();
+                properties.extend(49_u32.to_le_bytes());
+                // CARDP_CAPS_CAPTURE_NON_CAC
+                properties.extend(1_u32.to_le_bytes());
+                properties.extend(37_u32.to_le_bytes());
+                // TS_RFX_CAPS
+                properties.extend(0xcbc0_u16.to_le_bytes());
+                properties.extend(8_u32.to_le_bytes());
+                properties.extend(1_u16.to_le_bytes());
+                // TS_RFX_CAPSET
+                properties.extend(0xcbc1_u16.to_le_bytes());
+                properties.extend(29_u32.to_le_bytes());
+                properties.push(1);
+                properties.extend(0xcfc0_u16.to_le_bytes());
+                properties.extend(2_u16.to_le_bytes());
+                properties.extend(8_u16.to_le_bytes());
+                for entropy in [0x01, 0x04] {
+                    // TS_RFX_ICAP
+                    properties.extend(0x0100_u16.to_le_bytes());
+                    properties.extend(64_u16.to_le_bytes());
+                    properties.extend([0, 1, 1, entropy]);
+                }
+                properties
+            }
+            // quality of the tiles
+            BitmapCodec::Jpeg => vec![75],
+        }
+    }
+}
+
+fn codecs_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("CAPABILITY: {}", message)))
+}
+
+/// Read the codecs of a bitmap codecs capability set
+/// Codecs unknown to the client are skipped
+///
+/// See MS-RDPBCGR 2.2.7.2.10
+pub fn read_bitmap_codecs(data: &[u8]) -> RdpResult<Vec<BitmapCodec>> {
+    let mut stream = Cursor::new(data);
+    let mut codecs = Vec::new();
+    for _ in 0..stream.read_u8()? {
+        let mut guid = [0; 16];
+        stream.read_exact(&mut guid)?;
+        let _codec_id = stream.read_u8()?;
+        let properties_length = u64::from(stream.read_u16::<LittleEndian>()?);
+        let position = stream.position() + properties_length;
+        if position > data.len() as u64 {
+            return Err(codecs_error("Truncated bitmap codec properties"));
+        }
+        stream.set_position(position);
+        codecs.extend(BitmapCodec::from_guid(&guid));
+    }
+    Ok(codecs)
+}
+
+/// Codecs confirmed to a server which lists `server_codecs`
+/// Only the codecs the client can decode are kept
+pub fn confirmed_bitmap_codecs(server_codecs: &[BitmapCodec]) -> Vec<BitmapCodec> {
+    let mut codecs = Vec::new();
+    for codec in server_codecs {
+        if codec.is_supported() && !codecs.contains(codec) {
+            codecs.push(*codec);
+        }
+    }
+    codecs
+}
+
+/// Bitmap codecs capability
+/// Each codec is confirmed with its id
+///
+/// See MS-RDPBCGR 2.2.7.2.10
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{capability_set, ts_bitmap_codecs_capability_set, BitmapCodec};
+/// use rdp::model::data::to_vec;
+/// let capability_set = to_vec(&capability_set(Some(ts_bitmap_codecs_capability_set(&[BitmapCodec::NsCodec]))));
+/// assert_eq!(capability_set[..5], [0x1d, 0, 27, 0, 1]);
+/// assert_eq!(capability_set[21..], [1, 3, 0, 1, 1, 3]);
+/// ```
+pub fn ts_bitmap_codecs_capability_set(codecs: &[BitmapCodec]) -> Capability {
+    let mut array = Vec::new();
+    for codec in codecs {
+        let properties = codec.client_properties();
+        array.extend(to_vec(&component![
+            "codecGUID" => codec.guid().to_vec(),
+            "codecID" => codec.id(),
+            "codecPropertiesLength" => U16::LE(properties.len() as u16),
+            "codecProperties" => properties
+        ]));
+    }
+    Capability {
+        cap_type: CapabilitySetType::BitmapCodecs,
+        message: component![
+            "bitmapCodecCount" => codecs.len() as u8,
+            "bitmapCodecArray" => array
+        ],
+    }
+}
+
+/// General capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneralCapability {
+    pub protocol_version: u16,
+    /// Combination of `GeneralExtraFlag`
+    pub extra_flags: u16,
+    pub refresh_rect_support: bool,
+    pub suppress_output_support: bool,
+}
+
+/// Bitmap capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitmapCapability {
+    pub preferred_bits_per_pixel: u16,
+    pub desktop_width: u16,
+    pub desktop_height: u16,
+    pub desktop_resize: bool,
+}
+
+/// Order capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderCapability {
+    /// Combination of `OrderFlag`
+    pub order_flags: u16,
+    /// One byte per drawing order, nonzero if supported
+    pub order_support: Vec<u8>,
+}
+
+impl OrderCapability {
+    /// Check if the server supports a drawing order
+    pub fn supports(&self, order: OrderSupportIndex) -> bool {
+        self.order_support.get(order as usize).is_some_and(|support| *support != 0)
+    }
+}
+
+/// Pointer capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerCapability {
+    pub color_pointer: bool,
+    pub color_pointer_cache_size: u16,
+    /// Size of the cache of new pointers
+    /// None if the server doesn't support new pointers
+    pub pointer_cache_size: Option<u16>,
+    /// Combination of `LargePointerFlag`
+    /// Zero if the server doesn't send large pointers
+    pub large_pointer_flags: u16,
+}
+
+/// Input capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputCapability {
+    /// Combination of `InputFlags`
+    pub input_flags: u16,
+}
+
+impl InputCapability {
+    /// Check if the server accepts fast path input PDUs
+    pub fn supports_fast_path(&self) -> bool { self.input_flags & InputFlags::FastpathInput2 as u16 != 0 }
+}
+
+/// Sound capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundCapability {
+    /// The server can send beeps
+    pub beeps: bool,
+}
+
+/// Virtual channel capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualChannelCapability {
+    pub flags: u32,
+    /// Largest chunk of static virtual channel data
+    /// None if the server doesn't announce it
+    pub chunk_size: Option<u32>,
+}
+
+/// Font capability of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontCapability {
+    /// None if the server sends an empty set
+    pub font_support_flags: Option<u16>,
+}
+
+/// Server supports the font list PDU
+pub const FONTSUPPORT_FONTLIST: u16 = 0x0001;
+
+/// Client supports RemoteApp (TS_RAIL_LEVEL_SUPPORTED)
+const RAIL_LEVEL_SUPPORTED: u32 = 0x0000_0001;
+
+/// Client supports the window orders (TS_WINDOW_LEVEL_SUPPORTED)
+const WINDOW_LEVEL_SUPPORTED: u32 = 0x0000_0001;
+
+/// Server can send beeps
+const SOUND_BEEPS_FLAG: u16 = 0x0001;
+
+/// A capability set of the demand active PDU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerCapabilitySet {
+    General(GeneralCapability),
+    Bitmap(BitmapCapability),
+    Order(OrderCapability),
+    Pointer(PointerCapability),
+    /// Combination of `LargePointerFlag`
+    LargePointer(u16),
+    Input(InputCapability),
+    Sound(SoundCapability),
+    VirtualChannel(VirtualChannelCapability),
+    Font(FontCapability),
+    /// Codecs listed by the server, unknown codecs are skipped
+    BitmapCodecs(Vec<BitmapCodec>),
+    /// Set not decoded by the client
+    Other {
+        cap_type: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl ServerCapabilitySet {
+    /// Decode a capability set of the server
+    /// Unknown or invalid sets are kept raw
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::capability::{capability_set, ts_sound_capability_set, ServerCapabilitySet, SoundCapability};
+    /// let set = ServerCapabilitySet::from_capability_set(&capability_set(Some(ts_sound_capability_set()))).unwrap();
+    /// assert_eq!(set, ServerCapabilitySet::Sound(SoundCapability { beeps: false }));
+    /// ```
+    pub fn from_capability_set(capability_set: &Component) -> RdpResult<Self> {
+        let cap_type = cast!(DataType::U16, capability_set["capabilitySetType"])?;
+        let other = || -> RdpResult<Self> {
+            Ok(ServerCapabilitySet::Other {
+                cap_type,
+                data: cast!(DataType::Slice, capability_set["capabilitySet"])?.to_vec(),
+            })
+        };
+        // The codecs of the set have a variable size
+        if cap_type == CapabilitySetType::BitmapCodecs as u16 {
+            return match read_bitmap_codecs(cast!(DataType::Slice, capability_set["capabilitySet"])?) {
+                Ok(codecs) => Ok(ServerCapabilitySet::BitmapCodecs(codecs)),
+                Err(e) => {
+                    log_debug!("CAPABILITY: Keep raw capability {:#x} {:?}", cap_type, e);
+                    other()
+                }
+            };
+        }
+        match Capability::from_capability_set(capability_set) {
+            Ok(capability) => match Self::from_capability(&capability) {
+                Ok(Some(set)) => Ok(set),
+                _ => other(),
+            },
+            Err(e) => {
+                log_debug!("CAPABILITY: Keep raw capability {:#x} {:?}", cap_type, e);
+                other()
+            }
+        }
+    }
+
+    /// Decode a parsed capability
+    /// None for the sets without typed representation
+    pub fn from_capability(capability: &Capability) -> RdpResult<Option<Self>> {
+        let message = &capability.message;
+        Ok(Some(match capability.cap_type {
+            CapabilitySetType::General => ServerCapabilitySet::General(GeneralCapability {
+                protocol_version: cast!(DataType::U16, message["protocolVersion"])?,
+                extra_flags: cast!(DataType::U16, message["extraFlags"])?,
+                refresh_rect_support: cast!(DataType::U8, message["refreshRectSupport"])? != 0,
+                suppress_output_support: cast!(DataType::U8, message["suppressOutputSupport"])? != 0,
+            }),
+            CapabilitySetType::Bitmap => ServerCapabilitySet::Bitmap(BitmapCapability {
+                preferred_bits_per_pixel: cast!(DataType::U16, message["preferredBitsPerPixel"])?,
+                desktop_width: cast!(DataType::U16, message["desktopWidth"])?,
+                desktop_height: cast!(DataType::U16, message["desktopHeight"])?,
+                desktop_resize: cast!(DataType::U16, message["desktopResizeFlag"])? != 0,
+            }),
+            CapabilitySetType::Order => ServerCapabilitySet::Order(OrderCapability {
+                order_flags: cast!(DataType::U16, message["orderFlags"])?,
+                order_support: cast!(DataType::Slice, message["orderSupport"])?.to_vec(),
+            }),
+            CapabilitySetType::Pointer => ServerCapabilitySet::Pointer(PointerCapability {
+                color_pointer: cast!(DataType::U16, message["colorPointerFlag"])? != 0,
+                color_pointer_cache_size: cast!(DataType::U16, message["colorPointerCacheSize"])?,
+                pointer_cache_size: optional_u16(message, "pointerCacheSize")?,
+                large_pointer_flags: 0,
+            }),
+            CapabilitySetType::LargePointer => {
+                ServerCapabilitySet::LargePointer(cast!(DataType::U16, message["largePointerSupportFlags"])?)
+            }
+            CapabilitySetType::Input => ServerCapabilitySet::Input(InputCapability {
+                input_flags: cast!(DataType::U16, message["inputFlags"])?,
+            }),
+            CapabilitySetType::Sound => ServerCapabilitySet::Sound(SoundCapability {
+                beeps: cast!(DataType::U16, message["soundFlags"])? & SOUND_BEEPS_FLAG != 0,
+            }),
+            CapabilitySetType::Virtualchannel => ServerCapabilitySet::VirtualChannel(VirtualChannelCapability {
+                flags: cast!(DataType::U32, message["flags"])?,
+                chunk_size: optional_u32(message, "VCChunkSize")?,
+            }),
+            CapabilitySetType::Font => ServerCapabilitySet::Font(FontCapability {
+                font_support_flags: optional_u16(message, "fontSupportFlags")?,
+            }),
+            _ => return Ok(None),
+        }))
+    }
+}
+
+/// Value of an optional field, None if the set was too short
+fn optional_u16(message: &Component, name: &str) -> RdpResult<Option<u16>> {
+    match message.get(name) {
+        Some(field) if !is_none!(field) => Ok(Some(cast!(DataType::U16, field)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Value of an optional field, None if the set was too short
+fn optional_u32(message: &Component, name: &str) -> RdpResult<Option<u32>> {
+    match message.get(name) {
+        Some(field) if !is_none!(field) => Ok(Some(cast!(DataType::U32, field)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Capabilities announced by the server in the demand active PDU
+/// The sets useful to adapt the client are decoded
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{ts_bitmap_capability_set, ts_general_capability_set, ServerCapabilities};
+/// let capabilities = ServerCapabilities::from_capabilities(&[
+///     ts_general_capability_set(Some(1)),
+///     ts_bitmap_capability_set(Some(16), Some(1024), Some(768)),
+/// ])
+/// .unwrap();
+/// assert_eq!(capabilities.general.unwrap().extra_flags, 1);
+/// assert_eq!(capabilities.bitmap.unwrap().desktop_width, 1024);
+/// assert!(capabilities.pointer.is_none());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub general: Option<GeneralCapability>,
+    pub bitmap: Option<BitmapCapability>,
+    pub order: Option<OrderCapability>,
+    pub pointer: Option<PointerCapability>,
+    pub input: Option<InputCapability>,
+    pub sound: Option<SoundCapability>,
+    pub virtual_channel: Option<VirtualChannelCapability>,
+    pub font: Option<FontCapability>,
+    pub bitmap_codecs: Option<Vec<BitmapCodec>>,
+    /// All the sets in the order of the PDU
+    pub sets: Vec<ServerCapabilitySet>,
+}
+
+impl ServerCapabilities {
+    /// Decode capabilities parsed with `Capability::from_capability_set`
+    pub fn from_capabilities(capabilities: &[Capability]) -> RdpResult<Self> {
+        let mut sets = Vec::new();
+        for capability in capabilities {
+            sets.extend(ServerCapabilitySet::from_capability(capability)?);
+        }
+        Ok(Self::from_sets(sets))
+    }
+
+    /// Gather the capability sets of the server
+    pub fn from_sets(sets: Vec<ServerCapabilitySet>) -> Self {
+        let mut result = ServerCapabilities::default();
+        let mut large_pointer_flags = 0;
+        for set in &sets {
+            match set {
+                ServerCapabilitySet::General(general) => result.general = Some(general.clone()),
+                ServerCapabilitySet::Bitmap(bitmap) => result.bitmap = Some(bitmap.clone()),
+                ServerCapabilitySet::Order(order) => result.order = Some(order.clone()),
+                ServerCapabilitySet::Pointer(pointer) => result.pointer = Some(pointer.clone()),
+                ServerCapabilitySet::LargePointer(flags) => large_pointer_flags = *flags,
+                ServerCapabilitySet::Input(input) => result.input = Some(input.clone()),
+                ServerCapabilitySet::Sound(sound) => result.sound = Some(sound.clone()),
+                ServerCapabilitySet::VirtualChannel(channel) => result.virtual_channel = Some(channel.clone()),
+                ServerCapabilitySet::Font(font) => result.font = Some(font.clone()),
+                ServerCapabilitySet::BitmapCodecs(codecs) => result.bitmap_codecs = Some(codecs.clone()),
+                ServerCapabilitySet::Other { .. } => (),
+            }
+        }
+        // The large pointer capability can come before the pointer one
+        if let Some(pointer) = &mut result.pointer {
+            pointer.large_pointer_flags = large_pointer_flags;
+        }
+        result.sets = sets;
+        result
+    }
+
+    /// Check if the server sends fast path updates
+    pub fn supports_fast_path_output(&self) -> bool {
+        self.general
+            .as_ref()
+            .is_some_and(|general| general.extra_flags & GeneralExtraFlag::FastpathOutputSupported as u16 != 0)
+    }
+
+    /// Check if the server announces a 32 bpp session
+    pub fn supports_32bpp(&self) -> bool {
+        self.bitmap.as_ref().is_some_and(|bitmap| bitmap.preferred_bits_per_pixel == 32)
+    }
+}
+
+/// Handle a capability set of the capability exchange
+/// The server set comes from the demand active PDU,
+/// the client set is sent in the confirm active PDU
+pub trait CapabilityHandler: Send {
+    /// Capability set type
+    fn id(&self) -> u16;
+
+    /// Parse the set announced by the server
+    fn parse(&mut self, data: &[u8]) -> RdpResult<()>;
+
+    /// Set of the client, without the capability header
+    fn write_confirm(&self) -> RdpResult<Vec<u8>>;
+}
+
+/// Client capability set sent whatever the server announces
+#[derive(Debug, Clone)]
+pub struct StaticCapability {
+    id: u16,
+    data: Vec<u8>,
+}
+
+impl From<Capability> for StaticCapability {
+    fn from(capability: Capability) -> Self {
+        StaticCapability { id: capability.cap_type as u16, data: to_vec(&capability.message) }
+    }
+}
+
+impl CapabilityHandler for StaticCapability {
+    fn id(&self) -> u16 { self.id }
+
+    fn parse(&mut self, _data: &[u8]) -> RdpResult<()> { Ok(()) }
+
+    fn write_confirm(&self) -> RdpResult<Vec<u8>> { Ok(self.data.clone()) }
+}
+
+/// General capability of the client
+/// Fast path output is only requested if the server supports it
+#[derive(Debug, Clone)]
+pub struct GeneralCapabilityHandler {
+    /// Combination of `GeneralExtraFlag`
+    extra_flags: u16,
+    server_extra_flags: u16,
+}
+
+impl GeneralCapabilityHandler {
+    pub fn new(extra_flags: u16) -> Self { GeneralCapabilityHandler { extra_flags, server_extra_flags: 0 } }
+}
+
+impl CapabilityHandler for GeneralCapabilityHandler {
+    fn id(&self) -> u16 { CapabilitySetType::General as u16 }
+
+    fn parse(&mut self, data: &[u8]) -> RdpResult<()> {
+        let mut capability = ts_general_capability_set(None);
+        capability.message.read_from_bytes(data)?;
+        self.server_extra_flags = cast!(DataType::U16, capability.message["extraFlags"])?;
+        Ok(())
+    }
+
+    fn write_confirm(&self) -> RdpResult<Vec<u8>> {
+        let fast_path = GeneralExtraFlag::FastpathOutputSupported as u16;
+        let extra_flags = (self.extra_flags & !fast_path) | (self.extra_flags & self.server_extra_flags & fast_path);
+        Ok(to_vec(&ts_general_capability_set(Some(extra_flags)).message))
+    }
+}
+
+/// Bitmap capability of the client
+/// The desktop size follows the server, 32 bpp is used if the server
+/// prefers it
+#[derive(Debug, Clone)]
+pub struct BitmapCapabilityHandler {
+    bits_per_pixel: u16,
+    width: u16,
+    height: u16,
+}
+
+impl BitmapCapabilityHandler {
+    pub fn new(width: u16, height: u16) -> Self { BitmapCapabilityHandler { bits_per_pixel: 24, width, height } }
+}
+
+impl CapabilityHandler for BitmapCapabilityHandler {
+    fn id(&self) -> u16 { CapabilitySetType::Bitmap as u16 }
+
+    fn parse(&mut self, data: &[u8]) -> RdpResult<()> {
+        let mut capability = ts_bitmap_capability_set(None, None, None);
+        capability.message.read_from_bytes(data)?;
+        self.bits_per_pixel =
+            if cast!(DataType::U16, capability.message["preferredBitsPerPixel"])? == 32 { 32 } else { 24 };
+        self.width = cast!(DataType::U16, capability.message["desktopWidth"])?;
+        self.height = cast!(DataType::U16, capability.message["desktopHeight"])?;
+        Ok(())
+    }
+
+    fn write_confirm(&self) -> RdpResult<Vec<u8>> {
+        Ok(to_vec(&ts_bitmap_capability_set(Some(self.bits_per_pixel), Some(self.width), Some(self.height)).message))
+    }
+}
+
+/// Bitmap codecs capability of the client
+/// The codecs listed by the server which the client can decode
+/// are confirmed, none until the server set is parsed
+#[derive(Debug, Clone, Default)]
+pub struct BitmapCodecsCapabilityHandler {
+    codecs: Vec<BitmapCodec>,
+}
+
+impl BitmapCodecsCapabilityHandler {
+    pub fn new() -> Self { Self::default() }
+}
+
+impl CapabilityHandler for BitmapCodecsCapabilityHandler {
+    fn id(&self) -> u16 { CapabilitySetType::BitmapCodecs as u16 }
+
+    fn parse(&mut self, data: &[u8]) -> RdpResult<()> {
+        self.codecs = confirmed_bitmap_codecs(&read_bitmap_codecs(data)?);
+        Ok(())
+    }
+
+    fn write_confirm(&self) -> RdpResult<Vec<u8>> { Ok(to_vec(&ts_bitmap_codecs_capability_set(&self.codecs).message)) }
+}
+
+/// Capability sets exchanged with the server
+/// Sets are confirmed in the order of registration
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::{ts_brush_capability_set, CapabilityRegistry, StaticCapability};
+/// use rdp::model::data::to_vec;
+/// let mut registry = CapabilityRegistry::new();
+/// registry.register(Box::new(StaticCapability::from(ts_brush_capability_set())));
+/// assert!(!registry.parse(0x0017, &[]).unwrap());
+/// assert_eq!(to_vec(&registry.write_confirm().unwrap()), [15, 0, 8, 0, 0, 0, 0, 0]);
+/// ```
+#[derive(Default)]
+pub struct CapabilityRegistry {
+    handlers: IndexMap<u16, Box<dyn CapabilityHandler>>,
+}
+
+impl std::fmt::Debug for CapabilityRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CapabilityRegistry").field("handlers", &self.handlers.keys()).finish()
+    }
+}
+
+impl CapabilityRegistry {
+    pub fn new() -> Self { Self::default() }
+
+    /// Add the handler of a set
+    /// A handler of the same set is replaced at its position
+    pub fn register(&mut self, handler: Box<dyn CapabilityHandler>) { self.handlers.insert(handler.id(), handler); }
+
+    /// Give a server set to its handler
+    /// Return false if no handler is registered for this set
+    pub fn parse(&mut self, id: u16, data: &[u8]) -> RdpResult<bool> {
+        match self.handlers.get_mut(&id) {
+            Some(handler) => handler.parse(data).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Client sets of the confirm active PDU
+    pub fn write_confirm(&self) -> RdpResult<Trame> {
+        let mut sets = Trame::new();
+        for (id, handler) in &self.handlers {
+            let data = handler.write_confirm()?;
+            let length = u16::try_from(data.len() + 4).map_err(|_| {
+                Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "CAPABILITY: Capability set too large"))
+            })?;
+            sets.push(Box::new(component![
+                "capabilitySetType" => U16::LE(*id),
+                "lengthCapability" => U16::LE(length),
+                "capabilitySet" => data
+            ]));
+        }
+        Ok(sets)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_server_pointer_capabilities() {
+        let large_pointer = ts_large_pointer_capability_set(LargePointerFlag::Support96x96 as u16);
+        let pointer =
+            Capability::from_capability_set(&capability_set(Some(ts_new_pointer_capability_set(25)))).unwrap();
+        let capabilities = ServerCapabilities::from_capabilities(&[large_pointer, pointer]).unwrap();
+        assert_eq!(
+            capabilities.pointer,
+            Some(PointerCapability {
+                color_pointer: true,
+                color_pointer_cache_size: 20,
+                pointer_cache_size: Some(25),
+                large_pointer_flags: LargePointerFlag::Support96x96 as u16
+            })
+        );
+    }
+
+    #[test]
+    fn test_server_pointer_capabilities_without_new_pointer() {
+        let pointer = Capability::from_capability_set(&capability_set(Some(ts_pointer_capability_set()))).unwrap();
+        let capabilities = ServerCapabilities::from_capabilities(&[pointer]).unwrap();
+        assert_eq!(capabilities.pointer.unwrap().pointer_cache_size, None);
+    }
+
+    #[test]
+    fn test_server_input_capabilities() {
+        let input = ts_input_capability_set(Some(InputFlags::FastpathInput2 as u16), None);
+        let capabilities = ServerCapabilities::from_capabilities(&[input]).unwrap();
+        assert!(capabilities.input.unwrap().supports_fast_path());
+        let input = ts_input_capability_set(Some(InputFlags::Scancodes as u16), None);
+        assert!(!ServerCapabilities::from_capabilities(&[input]).unwrap().input.unwrap().supports_fast_path());
+    }
+
+    #[test]
+    fn test_server_order_capabilities() {
+        let order = ts_order_capability_set_with_orders(None, &[OrderSupportIndex::MemBlt]);
+        let order = Capability::from_capability_set(&capability_set(Some(order))).unwrap();
+        let capabilities = ServerCapabilities::from_capabilities(&[order]).unwrap();
+        let order = capabilities.order.unwrap();
+        assert!(order.supports(OrderSupportIndex::MemBlt));
+        assert!(!order.supports(OrderSupportIndex::PatBlt));
+    }
+
+    #[test]
+    fn test_server_capability_sets() {
+        let sets: Vec<ServerCapabilitySet> = [
+            capability_set(Some(ts_general_capability_set(Some(GeneralExtraFlag::FastpathOutputSupported as u16)))),
+            capability_set(Some(ts_bitmap_capability_set(Some(32), Some(1024), Some(768)))),
+            capability_set(Some(ts_virtualchannel_capability_set())),
+            capability_set(Some(ts_font_capability_set())),
+            capability_set(Some(ts_brush_capability_set())),
+        ]
+        .iter()
+        .map(|set| ServerCapabilitySet::from_capability_set(set).unwrap())
+        .collect();
+        assert_eq!(
+            sets[2],
+            ServerCapabilitySet::VirtualChannel(VirtualChannelCapability { flags: 0, chunk_size: Some(0) })
+        );
+        assert_eq!(
+            sets[3],
+            ServerCapabilitySet::Font(FontCapability { font_support_flags: Some(FONTSUPPORT_FONTLIST) })
+        );
+        assert_eq!(sets[4], ServerCapabilitySet::Other { cap_type: CapabilitySetType::Brush as u16, data: vec![0; 4] });
+
+        let capabilities = ServerCapabilities::from_sets(sets);
+        assert!(capabilities.supports_fast_path_output());
+        assert!(capabilities.supports_32bpp());
+        assert_eq!(capabilities.sets.len(), 5);
+        assert!(!ServerCapabilities::default().supports_fast_path_output());
+        assert!(!ServerCapabilities::default().supports_32bpp());
+    }
+
+    /// Old servers send a font set without fields
+    #[test]
+    fn test_server_empty_font_capability() {
+        let set = component![
+            "capabilitySetType" => U16::LE(CapabilitySetType::Font as u16),
+            "lengthCapability" => U16::LE(4),
+            "capabilitySet" => Vec::<u8>::new()
+        ];
+        assert_eq!(
+            ServerCapabilitySet::from_capability_set(&set).unwrap(),
+            ServerCapabilitySet::Font(FontCapability { font_support_flags: None })
+        );
+    }
+
+    /// Confirmed sets follow the server sets
+    #[test]
+    fn test_capability_registry() {
+        let mut registry = CapabilityRegistry::new();
+        let extra_flags =
+            GeneralExtraFlag::LongCredentialsSupported as u16 | GeneralExtraFlag::FastpathOutputSupported as u16;
+        registry.register(Box::new(GeneralCapabilityHandler::new(extra_flags)));
+        registry.register(Box::new(BitmapCapabilityHandler::new(800, 600)));
+        registry.register(Box::new(StaticCapability::from(ts_brush_capability_set())));
+
+        let confirm = |registry: &CapabilityRegistry| {
+            let mut stream = Cursor::new(to_vec(&registry.write_confirm().unwrap()));
+            let general = Capability::from_capability_set(&read_set(&mut stream)).unwrap();
+            let bitmap = Capability::from_capability_set(&read_set(&mut stream)).unwrap();
+            let brush = read_set(&mut stream);
+            assert_eq!(cast!(DataType::U16, brush["capabilitySetType"]).unwrap(), CapabilitySetType::Brush as u16);
+            (
+                cast!(DataType::U16, general.message["extraFlags"]).unwrap(),
+                cast!(DataType::U16, bitmap.message["preferredBitsPerPixel"]).unwrap(),
+                cast!(DataType::U16, bitmap.message["desktopWidth"]).unwrap(),
+            )
+        };
+        assert_eq!(confirm(&registry), (GeneralExtraFlag::LongCredentialsSupported as u16, 24, 800));
+
+        let general = ts_general_capability_set(Some(GeneralExtraFlag::FastpathOutputSupported as u16));
+        assert!(registry.parse(CapabilitySetType::General as u16, &to_vec(&general.message)).unwrap());
+        let bitmap = ts_bitmap_capability_set(Some(32), Some(1024), Some(768));
+        assert!(registry.parse(CapabilitySetType::Bitmap as u16, &to_vec(&bitmap.message)).unwrap());
+        assert!(!registry.parse(CapabilitySetType::Rail as u16, &[]).unwrap());
+        assert_eq!(confirm(&registry), (extra_flags, 32, 1024));
+
+        // replacing a handler keeps its position
+        registry.register(Box::new(GeneralCapabilityHandler::new(0)));
+        assert_eq!(confirm(&registry).0, 0);
+    }
+
+    /// Codecs unknown to the client are skipped
+    /// and JPEG is only confirmed with the jpeg feature
+    #[test]
+    fn test_bitmap_codecs_handler() {
+        let mut server = to_vec(&ts_bitmap_codecs_capability_set(&[BitmapCodec::Jpeg, BitmapCodec::RemoteFx]).message);
+        // CODEC_GUID_IGNORE without properties
+        server[0] += 1;
+        server.extend([0xa6, 0x51, 0x43, 0x9c, 0x35, 0x35, 0xae, 0x42, 0x91, 0x0c, 0xcd, 0xfc, 0xe5, 0x76, 0x0b, 0x58]);
+        server.extend([0, 0, 0]);
+        assert_eq!(read_bitmap_codecs(&server).unwrap(), [BitmapCodec::Jpeg, BitmapCodec::RemoteFx]);
+
+        let mut handler = BitmapCodecsCapabilityHandler::new();
+        assert_eq!(handler.write_confirm().unwrap(), [0]);
+        handler.parse(&server).unwrap();
+        let confirm = handler.write_confirm().unwrap();
+        let expected: &[BitmapCodec] =
+            if cfg!(feature = "jpeg") { &[BitmapCodec::Jpeg, BitmapCodec::RemoteFx] } else { &[BitmapCodec::RemoteFx] };
+        assert_eq!(read_bitmap_codecs(&confirm).unwrap(), expected);
+        // RemoteFX is confirmed with its id and a 49 bytes caps container
+        assert!(confirm.ends_with(&[
+            RFX_CODEC_ID,
+            49,
+            0,
+            49,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            37,
+            0,
+            0,
+            0,
+            0xc0,
+            0xcb,
+            8,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0xc1,
+            0xcb,
+            29,
+            0,
+            0,
+            0,
+            1,
+            0xc0,
+            0xcf,
+            2,
+            0,
+            8,
+            0,
+            0,
+            1,
+            64,
+            0,
+            0,
+            1,
+            1,
+            1,
+            0,
+            1,
+            64,
+            0,
+            0,
+            1,
+            1,
+            4
+        ]));
+
+        // the properties of a codec exceed the set
+        assert!(read_bitmap_codecs(&server[..server.len() - 1]).is_err());
+    }
+
+    fn read_set(stream: &mut Cursor<Vec<u8>>) -> Component {
+        let mut set = capability_set(None);
+        set.read(stream).unwrap();
+        set
+    }
+}
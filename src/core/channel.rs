@@ -0,0 +1,216 @@
+use std::io::{Read, Write};
+
+use crate::core::{mcs, tpkt};
+use crate::model::data::{Component, DataType, Message, Trame, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Max size of virtual channel data send in one PDU
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a8593178-80c0-4b80-876c-cb77e62cecfc
+pub const CHANNEL_CHUNK_LENGTH: usize = 1600;
+
+/// Options of a static virtual channel
+/// declared in the client network data
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a8593178-80c0-4b80-876c-cb77e62cecfc
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum ChannelOption {
+    Initialized = 0x8000_0000,
+    EncryptRdp = 0x4000_0000,
+    EncryptSc = 0x2000_0000,
+    EncryptCs = 0x1000_0000,
+    PriHigh = 0x0800_0000,
+    PriMed = 0x0400_0000,
+    PriLow = 0x0200_0000,
+    CompressRdp = 0x0080_0000,
+    Compress = 0x0040_0000,
+    ShowProtocol = 0x0020_0000,
+    RemoteControlPersistent = 0x0010_0000,
+}
+
+/// Set of `ChannelOption` of a static virtual channel
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChannelOptions(u32);
+
+impl ChannelOptions {
+    pub const COMPRESS: Self = ChannelOptions(ChannelOption::Compress as u32);
+    pub const COMPRESS_RDP: Self = ChannelOptions(ChannelOption::CompressRdp as u32);
+    pub const ENCRYPT_CS: Self = ChannelOptions(ChannelOption::EncryptCs as u32);
+    pub const ENCRYPT_RDP: Self = ChannelOptions(ChannelOption::EncryptRdp as u32);
+    pub const ENCRYPT_SC: Self = ChannelOptions(ChannelOption::EncryptSc as u32);
+    pub const INITIALIZED: Self = ChannelOptions(ChannelOption::Initialized as u32);
+    pub const PRI_HIGH: Self = ChannelOptions(ChannelOption::PriHigh as u32);
+    pub const PRI_LOW: Self = ChannelOptions(ChannelOption::PriLow as u32);
+    pub const PRI_MED: Self = ChannelOptions(ChannelOption::PriMed as u32);
+    pub const REMOTE_CONTROL_PERSISTENT: Self = ChannelOptions(ChannelOption::RemoteControlPersistent as u32);
+    pub const SHOW_PROTOCOL: Self = ChannelOptions(ChannelOption::ShowProtocol as u32);
+
+    /// No option
+    pub const fn empty() -> Self { ChannelOptions(0) }
+
+    /// Value sent in the channel definition
+    pub const fn bits(self) -> u32 { self.0 }
+
+    /// True if all options of `other` are set
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+/// Options of the channels opened by the client itself
+///
+/// # Example
+/// ```
+/// use rdp::core::channel::ChannelOptions;
+/// assert!(ChannelOptions::default().contains(ChannelOptions::INITIALIZED | ChannelOptions::ENCRYPT_RDP));
+/// assert_eq!(ChannelOptions::default().bits(), 0xc0a0_0000);
+/// ```
+impl Default for ChannelOptions {
+    fn default() -> Self { Self::INITIALIZED | Self::ENCRYPT_RDP | Self::COMPRESS_RDP | Self::SHOW_PROTOCOL }
+}
+
+impl std::ops::BitOr for ChannelOptions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self { ChannelOptions(self.0 | rhs.0) }
+}
+
+impl std::ops::BitOrAssign for ChannelOptions {
+    fn bitor_assign(&mut self, rhs: Self) { self.0 |= rhs.0; }
+}
+
+/// Static virtual channel asked in the client network data
+/// Names are limited to 7 characters
+///
+/// See MS-RDPBCGR 2.2.1.3.4.1
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StaticChannelDef {
+    pub name: String,
+    pub options: ChannelOptions,
+}
+
+impl StaticChannelDef {
+    /// Channel with the default options
+    pub fn new(name: &str) -> Self { StaticChannelDef { name: name.to_string(), options: ChannelOptions::default() } }
+}
+
+/// Handler of a static virtual channel defined by the user
+/// Messages are reassembled before being handled
+pub trait StaticChannelHandler {
+    /// Called for each message received on the channel
+    fn on_data(&mut self, data: &[u8]) -> RdpResult<()>;
+
+    /// Messages to send to the server
+    /// Called after each call to the handler
+    fn take_output(&mut self) -> Vec<Vec<u8>> { Vec::new() }
+}
+
+/// Flags of the channel PDU header
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f125c65e-6901-43c3-8071-d7d5aaee7ae4
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum ChannelFlag {
+    First = 0x0000_0001,
+    Last = 0x0000_0002,
+    ShowProtocol = 0x0000_0010,
+    Suspend = 0x0000_0020,
+    Resume = 0x0000_0040,
+    ShadowPersistent = 0x0000_0080,
+    PacketCompressed = 0x0020_0000,
+    PacketAtFront = 0x0040_0000,
+    PacketFlushed = 0x0080_0000,
+}
+
+/// Header of each chunk of a virtual channel
+///
+/// length is the total length of the data once reassembled
+fn channel_pdu_header(length: Option<u32>, flags: Option<u32>) -> Component {
+    component![
+        "length" => U32::LE(length.unwrap_or(0)),
+        "flags" => U32::LE(flags.unwrap_or(0))
+    ]
+}
+
+/// A static virtual channel
+///
+/// Handle the fragmentation of data
+/// into chunks of CHANNEL_CHUNK_LENGTH
+#[derive(Debug)]
+pub struct StaticChannel {
+    /// Name of the channel as negotiated by the MCS layer
+    name: String,
+    /// Data of a message not yet completed
+    buffer: Vec<u8>,
+}
+
+impl StaticChannel {
+    /// Create a new static channel
+    pub fn new(name: &str) -> Self { StaticChannel { name: name.to_string(), buffer: Vec::new() } }
+
+    /// Read a chunk from a channel
+    ///
+    /// Return the complete message once the last chunk is received
+    pub fn read(&mut self, payload: tpkt::Payload) -> RdpResult<Option<Vec<u8>>> {
+        let mut stream = try_let!(tpkt::Payload::Raw, payload)?;
+        let mut header = channel_pdu_header(None, None);
+        header.read(&mut stream)?;
+
+        let flags = cast!(DataType::U32, header["flags"])?;
+        if flags & ChannelFlag::First as u32 != 0 {
+            self.buffer.clear();
+        }
+        stream.read_to_end(&mut self.buffer)?;
+
+        if flags & ChannelFlag::Last as u32 == 0 {
+            return Ok(None);
+        }
+
+        if self.buffer.len() != cast!(DataType::U32, header["length"])? as usize {
+            self.buffer.clear();
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidSize,
+                &format!("CHANNEL: Invalid message length on channel {}", self.name),
+            )));
+        }
+        Ok(Some(std::mem::take(&mut self.buffer)))
+    }
+
+    /// Write a message to a channel
+    /// splitting it into chunks if needed
+    pub fn write<S: Read + Write>(&self, data: &[u8], mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let chunk_count = data.chunks(CHANNEL_CHUNK_LENGTH).count();
+        for (index, chunk) in data.chunks(CHANNEL_CHUNK_LENGTH).enumerate() {
+            let mut flags = 0;
+            if index == 0 {
+                flags |= ChannelFlag::First as u32;
+            }
+            if index == chunk_count - 1 {
+                flags |= ChannelFlag::Last as u32;
+            }
+            mcs.write(&self.name, trame![channel_pdu_header(Some(data.len() as u32), Some(flags)), chunk.to_vec()])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_fragmented_channel_data() {
+        let mut channel = StaticChannel::new("foo");
+        assert!(channel.read(tpkt::Payload::Raw(Cursor::new(vec![4, 0, 0, 0, 1, 0, 0, 0, 1, 2]))).unwrap().is_none());
+        assert_eq!(
+            channel.read(tpkt::Payload::Raw(Cursor::new(vec![4, 0, 0, 0, 2, 0, 0, 0, 3, 4]))).unwrap(),
+            Some(vec![1, 2, 3, 4])
+        );
+    }
+
+    #[test]
+    fn test_read_channel_invalid_length() {
+        let mut channel = StaticChannel::new("foo");
+        assert!(channel.read(tpkt::Payload::Raw(Cursor::new(vec![4, 0, 0, 0, 3, 0, 0, 0, 1, 2]))).is_err());
+    }
+}
@@ -1,21 +1,64 @@
+use std::collections::HashMap;
 use std::io::{Read, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::TcpStream;
+#[cfg(all(feature = "sync", any(unix, windows)))]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::core::event::{PointerButton, RdpEvent};
-use crate::core::gcc::KeyboardLayout;
-use crate::core::global::{ts_keyboard_event, ts_pointer_event, KeyboardFlag, PointerFlag};
-use crate::core::{global, mcs, sec, tpkt, x224};
-use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-use crate::model::link::{Link, Stream};
-use crate::nla::ntlm::Ntlm;
+#[cfg(not(target_arch = "wasm32"))]
+use native_tls::Identity;
+#[cfg(not(target_arch = "wasm32"))]
+use socket2::{SockRef, TcpKeepalive};
 
-impl From<&str> for KeyboardLayout {
-    fn from(e: &str) -> Self {
-        match e {
-            "fr" => KeyboardLayout::French,
-            _ => KeyboardLayout::US,
-        }
-    }
-}
+use crate::core::autodetect::{AutoDetector, BandwidthStats};
+use crate::core::bitmap_cache::PersistentBitmapCache;
+use crate::core::capability::{ts_rail_capability_set, ts_window_capability_set, ServerCapabilities, StaticCapability};
+use crate::core::channel::{StaticChannel, StaticChannelDef, StaticChannelHandler};
+use crate::core::cliprdr::{ClipboardChannel, CLIPRDR_CHANNEL_NAME};
+use crate::core::disp::{DisplayChannel, DISP_CHANNEL_NAME};
+use crate::core::dvc::{DvcManager, DRDYNVC_CHANNEL_NAME};
+use crate::core::error_info::ErrorInfoCode;
+use crate::core::event::{ClipboardEvent, ConnectionStats, RdpEvent, Rect};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::gateway::{GatewayConfig, GatewayStream};
+use crate::core::gcc::{ClientIdentity, EncryptionMethod, KeyboardLayout, MonitorLayout};
+use crate::core::gfx::{GfxChannel, GFX_CHANNEL_NAME};
+use crate::core::global::{ts_keyboard_event, ts_unicode_keyboard_event, KeyboardFlag};
+use crate::core::input::input_events;
+use crate::core::keymap::{alt_f4, ctrl_alt_del, ctrl_c, text_to_inputs, win_key, KeyInput};
+use crate::core::license::LicenseStore;
+use crate::core::mcs::{UltimatumReason, MESSAGE_CHANNEL_NAME};
+#[cfg(all(feature = "sync", any(unix, windows)))]
+use crate::core::named_pipe::PipeStream;
+#[cfg(feature = "pcap")]
+use crate::core::pcap::PcapWriter;
+use crate::core::preconnection::preconnection_pdu;
+use crate::core::rail::{RailChannel, RemoteAppConfig, RAIL_CHANNEL_NAME};
+use crate::core::rdpdr::{DriveAccess, DriveRedirector, PrinterInfo, RDPDR_CHANNEL_NAME};
+use crate::core::rdpsnd::{AudioChannel, AudioOutput, SharedAudioOutput, RDPSND_CHANNEL_NAME};
+use crate::core::recorder::{SessionRecorder, SharedRecorder};
+use crate::core::sec::{ClientInfo, PerformanceFlags, TimeZoneInfo};
+#[cfg(feature = "legacy-security")]
+use crate::core::security;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::socks::ProxyConfig;
+#[cfg(feature = "wasm")]
+use crate::core::websocket::WebSocketStream;
+#[cfg(feature = "experimental-wts")]
+use crate::core::wts::{enumerate_sessions_pdu, SessionChannel, SessionInfo, WTS_CHANNEL_NAME};
+use crate::core::{global, mcs, sec, toggle_keys, tpkt, x224};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult, RdpWarning, WarningHandler};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::link::TlsIdentity;
+use crate::model::link::{run_blocking, Blocking, FrameWait, Link, Stream};
+use crate::model::time::Instant;
+#[cfg(all(feature = "kerberos", unix))]
+use crate::nla::kerberos::Kerberos;
+use crate::nla::ntlm::Ntlm;
+use crate::nla::sspi::{AuthProtocol, AuthenticationProtocol};
 
 #[derive(Debug)]
 pub struct RdpClient<S> {
@@ -24,6 +67,189 @@ pub struct RdpClient<S> {
     mcs: mcs::Client<S>,
     /// Global channel that implement the basic layer
     global: global::Client,
+    /// Clipboard redirection channel
+    /// None if the server doesn't open it
+    cliprdr: Option<ClipboardChannel>,
+    /// Dynamic virtual channels
+    dvc: DvcManager,
+    /// Drive redirection channel
+    /// None if the server doesn't open it
+    rdpdr: Option<DriveRedirector>,
+    /// Graphics pipeline
+    /// None if not requested by the connector
+    gfx: Option<GfxChannel>,
+    /// Audio output channel
+    /// None without audio output or if the server doesn't open it
+    rdpsnd: Option<AudioChannel>,
+    /// RemoteApp channel
+    /// None without RemoteApp or if the server doesn't open it
+    rail: Option<RailChannel>,
+    /// Display control channel
+    /// None without dynamic virtual channels
+    disp: Option<DisplayChannel>,
+    /// Session management channel
+    /// None without dynamic virtual channels
+    #[cfg(feature = "experimental-wts")]
+    wts: Option<SessionChannel>,
+    /// Network auto-detection of the message channel
+    autodetect: AutoDetector,
+    /// Static channels defined by the user
+    /// with their registered handler
+    channels: HashMap<String, UserChannel>,
+    /// Rebuild the session after a network error
+    reconnector: Reconnector<S>,
+    /// Statistics of the session
+    stats: StatsTracker,
+    /// Video of the screen
+    /// None without `Connector::record_video`
+    recorder: Option<SharedRecorder>,
+}
+
+/// Static channel defined by the user
+struct UserChannel {
+    channel: StaticChannel,
+    handler: Box<dyn StaticChannelHandler + Send>,
+}
+
+impl std::fmt::Debug for UserChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserChannel").field("channel", &self.channel).finish()
+    }
+}
+
+/// Stream factory used to reconnect
+type RebuildStream<S> = Box<dyn FnMut() -> RdpResult<S> + Send>;
+
+/// State kept to open a new session
+/// when the connection is lost
+struct Reconnector<S> {
+    /// Copy of the connector with the credentials
+    /// None if reconnection is disabled
+    connector: Option<Connector>,
+    /// Open a new stream to the server
+    rebuild_stream: Option<RebuildStream<S>>,
+    /// Notify the caller once reconnected
+    on_reconnect: Option<Box<dyn Fn() + Send>>,
+}
+
+impl<S> Default for Reconnector<S> {
+    fn default() -> Self { Reconnector { connector: None, rebuild_stream: None, on_reconnect: None } }
+}
+
+impl<S> std::fmt::Debug for Reconnector<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reconnector")
+            .field("policy", &self.connector.as_ref().and_then(|connector| connector.reconnect))
+            .field("rebuild_stream", &self.rebuild_stream.is_some())
+            .finish()
+    }
+}
+
+/// Client name used when none is set
+const DEFAULT_CLIENT_NAME: &str = "rdp-rs";
+
+/// Length of the GCC client name without its null terminator
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_CLIENT_NAME_LENGTH: usize = 15;
+
+/// Interval between two statistics snapshots
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Statistics exposed to the caller
+/// Counters of the lower layers are never reset,
+/// the values at the last reset are subtracted
+#[derive(Debug)]
+struct StatsTracker {
+    stats: ConnectionStats,
+    origin: ConnectionStats,
+    last_snapshot: Instant,
+}
+
+impl Default for StatsTracker {
+    fn default() -> Self {
+        StatsTracker {
+            stats: ConnectionStats::default(),
+            origin: ConnectionStats::default(),
+            last_snapshot: Instant::now(),
+        }
+    }
+}
+
+/// Number of attempts and delay between them
+/// used to reconnect a lost session
+#[derive(Debug, Clone, Copy)]
+struct ReconnectPolicy {
+    max_attempts: u32,
+    delay: Duration,
+}
+
+/// Enable TCP keep-alive probes on a socket
+/// The first probe is sent after `interval` of inactivity,
+/// the connection is closed after `retries` unanswered probes
+///
+/// Platforms without the interval or retries options
+/// only get the idle time
+///
+/// # Example
+/// ```no_run
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+/// use rdp::core::client::set_tcp_keepalive;
+/// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+/// set_tcp_keepalive(&tcp, Duration::from_secs(30), 3).unwrap();
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_tcp_keepalive(tcp: &TcpStream, interval: Duration, retries: u32) -> RdpResult<()> {
+    let keepalive = TcpKeepalive::new().with_time(interval);
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    let keepalive = keepalive.with_interval(interval).with_retries(retries);
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    )))]
+    let _ = retries;
+    SockRef::from(tcp).set_tcp_keepalive(&keepalive)?;
+    Ok(())
+}
+
+/// Cut a name to the 15 characters of the GCC client name field
+/// Non-ASCII names are cut at 15 bytes of UTF-8,
+/// which always fits in the UTF-16 field
+#[cfg(not(target_arch = "wasm32"))]
+fn truncate_client_name(name: &str) -> String {
+    name.char_indices()
+        .take_while(|(index, c)| index + c.len_utf8() <= MAX_CLIENT_NAME_LENGTH)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// Errors which come from the network and not from the server
+/// A new connection can solve them
+fn is_network_error(error: &Error) -> bool {
+    match error {
+        Error::Io(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::UnexpectedEof
+        ),
+        Error::RdpError(e) => e.kind() == RdpErrorKind::Timeout,
+        _ => false,
+    }
 }
 
 impl<S: Read + Write> RdpClient<S> {
@@ -51,20 +277,199 @@ impl<S: Read + Write> RdpClient<S> {
     ///     }
     /// }).unwrap()
     /// ```
-    pub fn read<T>(&mut self, callback: T) -> RdpResult<()>
+    pub fn read<T>(&mut self, mut callback: T) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        // Bitmaps are drawn on the video before the application gets them
+        let recorder = self.recorder.clone();
+        let mut callback = |event: RdpEvent| {
+            if let (Some(recorder), RdpEvent::Bitmap(bitmap)) = (&recorder, &event) {
+                if let Err(e) = recorder.lock().and_then(|mut recorder| recorder.draw(bitmap.clone())) {
+                    log_debug!("RDPCLIENT: Bitmap not recorded {:?}", e);
+                }
+            }
+            callback(event)
+        };
+        let result = match self.read_message(&mut callback) {
+            Err(e) if self.reconnector.connector.is_some() && is_network_error(&e) => {
+                log_warn!("RDPCLIENT: Connection lost, reconnecting {:?}", e);
+                self.reconnect()?;
+                callback(RdpEvent::Reconnected);
+                Ok(())
+            }
+            result => result,
+        };
+        self.update_stats();
+        if self.stats.last_snapshot.elapsed() >= STATS_INTERVAL {
+            self.stats.last_snapshot = Instant::now();
+            callback(RdpEvent::StatsSnapshot(self.stats.stats));
+        }
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.lock().and_then(|mut recorder| recorder.write_frames(Instant::now())) {
+                log_warn!("RDPCLIENT: Video recording stopped {:?}", e);
+            }
+        }
+        result
+    }
+
+    /// Statistics of the session
+    /// Updated by `read` and `write`
+    pub fn stats(&self) -> &ConnectionStats { &self.stats.stats }
+
+    /// Restart the statistics counters
+    pub fn reset_stats(&mut self) {
+        self.update_stats();
+        self.stats.origin = ConnectionStats {
+            bytes_sent: self.stats.origin.bytes_sent + self.stats.stats.bytes_sent,
+            bytes_received: self.stats.origin.bytes_received + self.stats.stats.bytes_received,
+            frames_received: self.stats.origin.frames_received + self.stats.stats.frames_received,
+            last_rtt_ms: None,
+        };
+        self.update_stats();
+    }
+
+    /// Compute the statistics from the counters of the lower layers
+    fn update_stats(&mut self) {
+        let (bytes_sent, bytes_received) = self.mcs.get_traffic();
+        let origin = &self.stats.origin;
+        self.stats.stats = ConnectionStats {
+            bytes_sent: bytes_sent.saturating_sub(origin.bytes_sent),
+            bytes_received: bytes_received.saturating_sub(origin.bytes_received),
+            frames_received: self.global.get_frames_received().saturating_sub(origin.frames_received),
+            last_rtt_ms: self
+                .autodetect
+                .bandwidth_estimate()
+                .and_then(|stats| stats.average_rtt)
+                .map(|rtt| rtt.as_millis().min(u32::MAX as u128) as u32),
+        };
+    }
+
+    /// Read a payload and dispatch it to its channel
+    fn read_message<T>(&mut self, mut callback: T) -> RdpResult<()>
     where
         T: FnMut(RdpEvent),
     {
-        let (channel_name, message) = self.mcs.read()?;
+        let (channel_name, message) = match self.mcs.read() {
+            Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::Disconnect && self.mcs.is_disconnected() => {
+                callback(RdpEvent::Disconnect {
+                    reason: self.global.get_disconnect_reason(),
+                    error_info: self.global.get_error_info(),
+                });
+                return Ok(());
+            }
+            result => result?,
+        };
         match channel_name.as_str() {
             "global" => self.global.read(message, &mut self.mcs, callback),
-            _ => Err(Error::RdpError(RdpError::new(
+            CLIPRDR_CHANNEL_NAME => match &mut self.cliprdr {
+                Some(cliprdr) => cliprdr.read(message, &mut self.mcs, callback),
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    "RDPCLIENT: Clipboard channel not opened",
+                ))),
+            },
+            DRDYNVC_CHANNEL_NAME => {
+                self.dvc.read(message, &mut self.mcs)?;
+                // Graphics pipeline updates are notified as legacy bitmaps
+                if let Some(gfx) = &self.gfx {
+                    for bitmap in gfx.pipeline()?.take_updates() {
+                        callback(RdpEvent::Bitmap(bitmap));
+                    }
+                }
+                Ok(())
+            }
+            RDPDR_CHANNEL_NAME => match &mut self.rdpdr {
+                Some(rdpdr) => rdpdr.read(message, &mut self.mcs),
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    "RDPCLIENT: Drive redirection channel not opened",
+                ))),
+            },
+            RDPSND_CHANNEL_NAME => match &mut self.rdpsnd {
+                Some(rdpsnd) => rdpsnd.read(message, &mut self.mcs),
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    "RDPCLIENT: Audio output channel not opened",
+                ))),
+            },
+            MESSAGE_CHANNEL_NAME => self.autodetect.read(message, &mut self.mcs),
+            RAIL_CHANNEL_NAME => match &mut self.rail {
+                Some(rail) => rail.read(message, &mut self.mcs),
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    "RDPCLIENT: RemoteApp channel not opened",
+                ))),
+            },
+            name => match self.channels.get_mut(name) {
+                Some(user_channel) => {
+                    if let Some(data) = user_channel.channel.read(message)? {
+                        user_channel.handler.on_data(&data)?;
+                        for output in user_channel.handler.take_output() {
+                            user_channel.channel.write(&output, &mut self.mcs)?;
+                        }
+                    }
+                    Ok(())
+                }
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    &format!("Invalid channel name {:?}", channel_name),
+                ))),
+            },
+        }
+    }
+
+    /// Handle the messages of a channel asked with `Connector::channels`
+    /// Fails if the server didn't open the channel
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::channel::{StaticChannelDef, StaticChannelHandler};
+    /// use rdp::core::client::Connector;
+    /// use rdp::model::error::RdpResult;
+    /// struct Echo(Vec<Vec<u8>>);
+    /// impl StaticChannelHandler for Echo {
+    ///     fn on_data(&mut self, data: &[u8]) -> RdpResult<()> {
+    ///         self.0.push(data.to_vec());
+    ///         Ok(())
+    ///     }
+    ///     fn take_output(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.0) }
+    /// }
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new().channels(vec![StaticChannelDef::new("echo")]);
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// client.register_channel("echo", Echo(Vec::new())).unwrap();
+    /// ```
+    pub fn register_channel<H: StaticChannelHandler + Send + 'static>(
+        &mut self, name: &str, handler: H,
+    ) -> RdpResult<()> {
+        if !self.mcs.has_channel(name) {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::UnexpectedType,
+                &format!("RDPCLIENT: Channel {} not opened", name),
+            )));
+        }
+        self.channels
+            .insert(name.to_string(), UserChannel { channel: StaticChannel::new(name), handler: Box::new(handler) });
+        Ok(())
+    }
+
+    /// Send a message on a channel registered with `register_channel`
+    pub fn write_channel(&mut self, name: &str, data: &[u8]) -> RdpResult<()> {
+        match self.channels.get(name) {
+            Some(user_channel) => user_channel.channel.write(data, &mut self.mcs),
+            None => Err(Error::RdpError(RdpError::new(
                 RdpErrorKind::UnexpectedType,
-                &format!("Invalid channel name {:?}", channel_name),
+                &format!("RDPCLIENT: Channel {} not registered", name),
             ))),
         }
     }
 
+    /// Id given by the server to a static channel
+    /// None if the channel is not opened
+    pub fn channel_id(&self, name: &str) -> Option<u16> { self.mcs.get_channel_id(name) }
+
     /// Write an event to the server
     /// Typically is all about input event like mouse and keyboard
     ///
@@ -85,44 +490,26 @@ impl<S: Read + Write> RdpClient<S> {
     ///         x: 100 as u16,
     ///         y: 100 as u16,
     ///         button: PointerButton::Left,
-    ///         down: true
+    ///         down: true,
+    ///         wheel_delta: 0
     ///     }
     /// )).unwrap()
     /// ```
     pub fn write(&mut self, event: RdpEvent) -> RdpResult<()> {
-        match event {
-            // Pointer event
-            // Mouse position an d button position
-            RdpEvent::Pointer(pointer) => {
-                // Pointer are sent to global channel
-                // Compute flags
-                let mut flags: u16 = 0;
-                match pointer.button {
-                    PointerButton::Left => flags |= PointerFlag::Button1 as u16,
-                    PointerButton::Right => flags |= PointerFlag::Button2 as u16,
-                    PointerButton::Middle => flags |= PointerFlag::Button3 as u16,
-                    _ => flags |= PointerFlag::Move as u16,
-                }
-
-                if pointer.down {
-                    flags |= PointerFlag::Down as u16;
-                }
-
-                self.global
-                    .write_input_event(ts_pointer_event(Some(flags), Some(pointer.x), Some(pointer.y)), &mut self.mcs)
-            }
-            // Raw keyboard input
-            RdpEvent::Key(key) => {
-                let mut flags: u16 = 0;
-                if !key.down {
-                    flags |= KeyboardFlag::Release as u16;
-                }
-                self.global.write_input_event(ts_keyboard_event(Some(flags), Some(key.code)), &mut self.mcs)
-            }
-            _ => {
-                Err(Error::RdpError(RdpError::new(RdpErrorKind::UnexpectedType, "RDPCLIENT: This event can't be sent")))
-            }
-        }
+        let result = match event {
+            // Share text with the server clipboard
+            RdpEvent::Clipboard(ClipboardEvent::Text(text)) => match &mut self.cliprdr {
+                Some(cliprdr) => cliprdr.send_text(&text, &mut self.mcs),
+                None => Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::UnexpectedType,
+                    "RDPCLIENT: Clipboard channel not opened",
+                ))),
+            },
+            // Pointer and keyboard events are sent to global channel
+            event => self.global.write_input_events(input_events(&event)?, &mut self.mcs),
+        };
+        self.update_stats();
+        result
     }
 
     /// This function will ignore input event
@@ -140,11 +527,404 @@ impl<S: Read + Write> RdpClient<S> {
         }
     }
 
+    /// Send pointer and keyboard events at once
+    /// Input events are packed in as few PDUs as possible,
+    /// up to 15 events in each fast path PDU
+    ///
+    /// Nothing is sent if one of the events is not an input
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::event::{KeyboardEvent, RdpEvent};
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut client = Connector::new().connect(tcp).unwrap();
+    /// let events: Vec<RdpEvent> = [true, false]
+    ///     .into_iter()
+    ///     .map(|down| RdpEvent::Key(KeyboardEvent { code: 0x1e, down }))
+    ///     .collect();
+    /// client.inject_event_batch(&events).unwrap();
+    /// ```
+    pub fn inject_event_batch(&mut self, events: &[RdpEvent]) -> RdpResult<()> {
+        let mut inputs = Vec::new();
+        for event in events {
+            inputs.extend(input_events(event)?);
+        }
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let result = self.global.write_input_events(inputs, &mut self.mcs);
+        self.update_stats();
+        result
+    }
+
+    /// Type a text on the remote session
+    /// Characters are translated into scancodes of the keyboard layout,
+    /// unicode keyboard events are used for characters without key
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .screen(800, 600)
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// client.send_unicode_string("Hello world!\n").unwrap()
+    /// ```
+    pub fn send_unicode_string(&mut self, text: &str) -> RdpResult<()> {
+        let inputs = text_to_inputs(self.global.get_layout(), text);
+        self.send_inputs(inputs)
+    }
+
+    /// Send Ctrl+Alt+Del, for example to unlock the session
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .screen(800, 600)
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// client.send_ctrl_alt_del().unwrap()
+    /// ```
+    pub fn send_ctrl_alt_del(&mut self) -> RdpResult<()> { self.send_inputs(ctrl_alt_del()) }
+
+    /// Press and release the left Windows key
+    pub fn send_win_key(&mut self) -> RdpResult<()> { self.send_inputs(win_key()) }
+
+    /// Send Ctrl+C
+    pub fn send_ctrl_c(&mut self) -> RdpResult<()> { self.send_inputs(ctrl_c()) }
+
+    /// Send Alt+F4
+    pub fn send_alt_f4(&mut self) -> RdpResult<()> { self.send_inputs(alt_f4()) }
+
+    /// Send a sequence of keyboard inputs
+    fn send_inputs(&mut self, inputs: Vec<KeyInput>) -> RdpResult<()> {
+        let events = inputs
+            .into_iter()
+            .map(|input| match input {
+                KeyInput::Scancode { code, extended, down } => {
+                    let mut flags: u16 = 0;
+                    if extended {
+                        flags |= KeyboardFlag::Extended as u16;
+                    }
+                    if !down {
+                        flags |= KeyboardFlag::Release as u16;
+                    }
+                    ts_keyboard_event(Some(flags), Some(code))
+                }
+                KeyInput::Unicode { code, down } => {
+                    let flags = if down { 0 } else { KeyboardFlag::Release as u16 };
+                    ts_unicode_keyboard_event(Some(flags), Some(code))
+                }
+            })
+            .collect();
+        let result = self.global.write_input_events(events, &mut self.mcs);
+        self.update_stats();
+        result
+    }
+
+    /// Dynamic virtual channels manager
+    /// Use it to register handlers before the server open channels
+    pub fn dvc_manager(&mut self) -> &mut DvcManager { &mut self.dvc }
+
+    /// Send a message on an opened dynamic virtual channel
+    pub fn write_dvc(&mut self, name: &str, data: &[u8]) -> RdpResult<()> { self.dvc.write(name, data, &mut self.mcs) }
+
+    /// List the sessions of the server
+    /// through the session management dynamic channel
+    ///
+    /// Messages are read until the server answers,
+    /// their events are given to the callback like `read`
+    ///
+    /// Experimental, needs the `experimental-wts` feature: the format
+    /// of the channel messages isn't published and hasn't been checked
+    /// against a Windows server
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .screen(800, 600)
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// for session in client.enumerate_sessions(|_event| {}).unwrap() {
+    ///     println!("{} {} {:?}", session.session_id, session.username, session.state);
+    /// }
+    /// ```
+    #[cfg(feature = "experimental-wts")]
+    pub fn enumerate_sessions<T>(&mut self, mut callback: T) -> RdpResult<Vec<SessionInfo>>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let wts = match &self.wts {
+            Some(wts) if wts.is_opened()? => wts.clone(),
+            _ => {
+                return Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::InvalidAutomata,
+                    "RDPCLIENT: Session management channel not opened",
+                )))
+            }
+        };
+        // Drop any previous answer
+        wts.take_sessions()?;
+        self.dvc.write(WTS_CHANNEL_NAME, &enumerate_sessions_pdu(), &mut self.mcs)?;
+        loop {
+            if let Some(sessions) = wts.take_sessions()? {
+                return Ok(sessions);
+            }
+            // the disconnect event is given to the callback first
+            if !wts.is_opened()? || self.mcs.is_disconnected() {
+                return Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::Disconnect,
+                    "RDPCLIENT: Session management channel closed",
+                )));
+            }
+            self.read(&mut callback)?;
+        }
+    }
+
+    /// Allow or suppress display updates
+    /// Use it to save bandwidth while the display is not visible
+    /// Allowing updates again refresh the whole desktop
+    pub fn suppress_output(&mut self, allow: bool) -> RdpResult<()> {
+        self.global.write_suppress_output(allow, &mut self.mcs)
+    }
+
+    /// Ask the server to change the desktop size
+    /// The server reactivates the session with the new size,
+    /// then `screen_size` returns it
+    ///
+    /// The width must be even and both dimensions between 200 and 8192
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .screen(800, 600)
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// client.resize_desktop(1024, 768).unwrap()
+    /// ```
+    pub fn resize_desktop(&mut self, width: u16, height: u16) -> RdpResult<()> {
+        let disp = self.disp.as_ref().ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidAutomata, "RDPCLIENT: Display control not available"))
+        })?;
+        let pdu = disp.resize_pdu(width, height)?;
+        self.dvc.write(DISP_CHANNEL_NAME, &pdu, &mut self.mcs)?;
+        // A new session must keep the new size
+        if let Some(connector) = &mut self.reconnector.connector {
+            connector.width = width;
+            connector.height = height;
+        }
+        Ok(())
+    }
+
+    /// Set the function which opens a new stream to the server
+    /// Used with `Connector::reconnect_interval`,
+    /// `Connector::connect_tcp` sets it for you
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .reconnect_interval(5, Duration::from_secs(2));
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// client.set_rebuild_stream(|| Ok(TcpStream::connect("127.0.0.1:3389")?));
+    /// ```
+    pub fn set_rebuild_stream<F>(&mut self, f: F)
+    where
+        F: FnMut() -> RdpResult<S> + Send + 'static,
+    {
+        self.reconnector.rebuild_stream = Some(Box::new(f));
+    }
+
+    /// Call a function each time the session is reconnected
+    /// Use it to update the UI state
+    pub fn on_reconnect<F: Fn() + Send + 'static>(&mut self, f: F) {
+        self.reconnector.on_reconnect = Some(Box::new(f));
+    }
+
+    /// Open a new session with the same settings
+    /// The new stream comes from the rebuild function
+    fn reconnect(&mut self) -> RdpResult<()> {
+        // The new session loads the bitmaps of this one
+        if let Err(e) = self.save_bitmap_cache() {
+            log_warn!("RDPCLIENT: Bitmap cache not saved {:?}", e);
+        }
+        let mut reconnector = std::mem::take(&mut self.reconnector);
+        // Go back to the same session
+        if let (Some(connector), Some(session_id)) = (reconnector.connector.as_mut(), self.session_id()) {
+            connector.session_id.get_or_insert(session_id);
+        }
+        // The new session starts with the effects suited to the measured bandwidth
+        if let (Some(connector), Some(stats)) = (reconnector.connector.as_mut(), self.bandwidth_estimate()) {
+            connector.client_info.performance_flags |= stats.performance_flags();
+        }
+        let result = Self::reconnect_with(&mut reconnector);
+        match result {
+            Ok(mut client) => {
+                if let Some(on_reconnect) = &reconnector.on_reconnect {
+                    on_reconnect();
+                }
+                client.reconnector = reconnector;
+                // Handlers follow the channels still opened by the server
+                for (name, user_channel) in std::mem::take(&mut self.channels) {
+                    if client.mcs.has_channel(&name) {
+                        client
+                            .channels
+                            .insert(name.clone(), UserChannel { channel: StaticChannel::new(&name), ..user_channel });
+                    }
+                }
+                *self = client;
+                Ok(())
+            }
+            Err(e) => {
+                self.reconnector = reconnector;
+                Err(e)
+            }
+        }
+    }
+
+    fn reconnect_with(reconnector: &mut Reconnector<S>) -> RdpResult<RdpClient<S>> {
+        let connector = reconnector.connector.as_mut().ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidAutomata, "RDPCLIENT: Reconnection is disabled"))
+        })?;
+        let rebuild_stream = reconnector.rebuild_stream.as_mut().ok_or_else(|| {
+            Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "RDPCLIENT: No function to rebuild the stream",
+            ))
+        })?;
+        let policy = connector.reconnect.unwrap_or(ReconnectPolicy { max_attempts: 0, delay: Duration::ZERO });
+        let mut last_error =
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidAutomata, "RDPCLIENT: No reconnection attempt"));
+        for attempt in 1..=policy.max_attempts {
+            std::thread::sleep(policy.delay);
+            match rebuild_stream().and_then(|stream| connector.connect_blocking(stream)) {
+                Ok(client) => return Ok(client),
+                Err(e) => {
+                    log_warn!("RDPCLIENT: Reconnection attempt {} failed {:?}", attempt, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Reception time of the last server heartbeat
+    /// Use it to detect a dead server while no update is received
+    pub fn last_heartbeat(&self) -> Option<Instant> { self.global.get_last_heartbeat() }
+
+    /// Last error info sent by the server
+    /// Explains why the session ended
+    pub fn error_info(&self) -> ErrorInfoCode { self.global.get_error_info() }
+
+    /// Session assigned by the server
+    /// None until `RdpEvent::LogonInfo` is received
+    pub fn session_id(&self) -> Option<u32> { self.global.get_session_id() }
+
+    /// Size of the remote desktop
+    pub fn screen_size(&self) -> (u16, u16) { self.global.get_screen_size() }
+
+    /// Current monitor topology
+    /// None for a single screen session
+    pub fn monitor_layout(&self) -> Option<&MonitorLayout> { self.global.get_monitor_layout() }
+
+    /// True while the server suppresses display updates
+    /// `write` refuses input until `RdpEvent::DisplayResumed`
+    pub fn is_output_suppressed(&self) -> bool { self.global.is_output_suppressed() }
+
+    /// Bandwidth and round trip time measured by the server
+    /// None without `Connector::network_autodetect` or before a measure
+    pub fn bandwidth_estimate(&self) -> Option<BandwidthStats> { self.autodetect.bandwidth_estimate() }
+
+    /// Ask the server to move or resize a RemoteApp window
+    pub fn move_remote_window(&mut self, window_id: u32, rect: Rect) -> RdpResult<()> {
+        match &self.rail {
+            Some(rail) => rail.move_window(window_id, rect, &mut self.mcs),
+            None => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "RDPCLIENT: RemoteApp channel not opened",
+            ))),
+        }
+    }
+
+    /// Capabilities announced by the server
+    /// Use them to adapt to the features the server supports
+    pub fn server_capabilities(&self) -> Option<&ServerCapabilities> { self.global.get_server_capabilities() }
+
+    /// Graphics pipeline
+    /// None if not requested with `Connector::gfx`
+    pub fn gfx(&self) -> Option<&GfxChannel> { self.gfx.as_ref() }
+
+    /// Drive redirected to the server
+    /// Use it to exchange files with the remote session
+    /// once enabled with `Connector::drive_redirection`
+    /// None if the server doesn't open the rdpdr channel
+    pub fn drive_redirector(&mut self) -> Option<&mut DriveRedirector> { self.rdpdr.as_mut() }
+
+    /// Redirect printers to the session
+    /// Print jobs are refused, the printers are only listed
+    ///
+    /// Fails if the server doesn't open the rdpdr channel
+    pub fn announce_printers(&mut self, printers: &[PrinterInfo]) -> RdpResult<()> {
+        match &mut self.rdpdr {
+            Some(rdpdr) => rdpdr.announce_printers(printers, &mut self.mcs),
+            None => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::UnexpectedType,
+                "RDPCLIENT: Drive redirection channel not opened",
+            ))),
+        }
+    }
+
     /// Close client is indeed close the switch layer
-    pub fn shutdown(&mut self) -> RdpResult<()> { self.mcs.shutdown() }
+    pub fn shutdown(&mut self) -> RdpResult<()> { self.shutdown_with_reason(UltimatumReason::UserRequested) }
+
+    /// Close the connection telling the server why
+    /// Only the MCS Disconnect Provider Ultimatum is sent,
+    /// RDP has no client PDU carrying a disconnect reason code
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::mcs::UltimatumReason;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut client = Connector::new().connect(tcp).unwrap();
+    /// client.shutdown_with_reason(UltimatumReason::ProviderInitiated).unwrap();
+    /// ```
+    pub fn shutdown_with_reason(&mut self, reason: UltimatumReason) -> RdpResult<()> {
+        // The cache is saved even if the server is already gone
+        let saved = self.save_bitmap_cache();
+        self.mcs.shutdown_with_reason(reason)?;
+        saved
+    }
+
+    /// Write the persistent bitmap cache in its file
+    /// Done on shutdown and before a reconnection
+    pub fn save_bitmap_cache(&self) -> RdpResult<()> {
+        match self.global.get_persistent_bitmap_cache() {
+            Some(cache) => cache.save(),
+            None => Ok(()),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Connector {
     /// Screen width
     width: u16,
@@ -171,11 +951,102 @@ pub struct Connector {
     /// When using SSL check or not
     /// the certificate during SSL handshake
     check_certificate: bool,
+    /// Check the server certificate is issued for the host
+    /// even when the certificate chain is not checked
+    verify_hostname: bool,
+    /// Host name of the server, from `server_name` or `connect_tcp`
+    /// None if unknown, the host name is then not checked
+    server_host: Option<String>,
+    /// SHA-256 fingerprint of the expected server certificate
+    certificate_pin: Option<Vec<u8>>,
+    /// Certificate presented during the TLS handshake
+    #[cfg(not(target_arch = "wasm32"))]
+    client_certificate: Option<TlsIdentity>,
     /// Client name exposed to the server
-    name: String,
+    /// None for the default name
+    name: Option<String>,
+    /// Use the host name when no name is set
+    name_from_hostname: bool,
+    /// Build, product id and serial number exposed to the server
+    identity: ClientIdentity,
     /// Use network level authentication
     /// default TRUE
     use_nla: bool,
+    /// Connect through a Remote Desktop Gateway
+    #[cfg(not(target_arch = "wasm32"))]
+    gateway: Option<GatewayConfig>,
+    /// Authentication mechanism used by NLA
+    /// None will prefer Kerberos when available
+    auth_protocol: Option<AuthProtocol>,
+    /// Service principal name of the target server
+    /// use by Kerberos authentication
+    spn: Option<String>,
+    /// Monitor topology of the client
+    /// None for a single screen
+    monitors: Option<MonitorLayout>,
+    /// Existing session to reconnect to
+    session_id: Option<u32>,
+    /// Reach the server through a SOCKS or HTTP proxy
+    #[cfg(not(target_arch = "wasm32"))]
+    proxy: Option<ProxyConfig>,
+    /// Socket read timeout
+    read_timeout: Option<Duration>,
+    /// Socket write timeout
+    write_timeout: Option<Duration>,
+    /// The stream given to `connect` has socket timeouts
+    socket_timeouts: bool,
+    /// Keep-alive probes interval and count
+    /// None to disable them
+    #[cfg(not(target_arch = "wasm32"))]
+    tcp_keepalive: Option<(Duration, u32)>,
+    /// Use the graphics pipeline when the server support it
+    gfx: bool,
+    /// Cache bitmaps drawn with memory blit orders
+    bitmap_cache: bool,
+    /// File of the bitmaps kept between sessions
+    /// None to only cache bitmaps during the session
+    persistent_cache_path: Option<PathBuf>,
+    /// Play the session audio
+    /// None leave audio on the server
+    audio: Option<SharedAudioOutput>,
+    /// Reconnect after a network error
+    /// None to report the error
+    reconnect: Option<ReconnectPolicy>,
+    /// Callback of non fatal server messages
+    warnings: WarningHandler,
+    /// Keep the license issued by the server
+    license_store: Option<SharedLicenseStore>,
+    /// Shell, working directory and time zone of the session
+    client_info: ClientInfo,
+    /// Application started instead of the desktop
+    remote_app: Option<RemoteAppConfig>,
+    /// Answer the bandwidth and round trip time measures of the server
+    network_autodetect: bool,
+    /// Blob sent before the X.224 connection request
+    preconnection_blob: Option<String>,
+    /// Send input as fast path when the server supports it
+    use_fast_path_input: bool,
+    /// Send the state of the local lock keys at connection
+    sync_toggle_keys: bool,
+    /// Static channels asked by the user
+    channels: Vec<StaticChannelDef>,
+    /// Access of the server to the redirected drive, None doesn't redirect it
+    drive: Option<DriveAccess>,
+    /// Connect without TLS, see `disable_encryption`
+    disable_encryption: bool,
+    /// Capture file of the decrypted traffic
+    #[cfg(feature = "pcap")]
+    pcap_path: Option<PathBuf>,
+    /// Video of the screen, see `record_video`
+    video: Option<SharedRecorder>,
+}
+
+/// License store shared between connections
+#[derive(Clone)]
+struct SharedLicenseStore(Arc<Mutex<dyn LicenseStore + Send>>);
+
+impl std::fmt::Debug for SharedLicenseStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("SharedLicenseStore") }
 }
 
 impl Connector {
@@ -204,8 +1075,47 @@ impl Connector {
             auto_logon: false,
             blank_creds: false,
             check_certificate: false,
-            name: "rdp-rs".to_string(),
+            verify_hostname: true,
+            server_host: None,
+            certificate_pin: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            client_certificate: None,
+            name: None,
+            name_from_hostname: false,
+            identity: ClientIdentity::default(),
             use_nla: true,
+            #[cfg(not(target_arch = "wasm32"))]
+            gateway: None,
+            auth_protocol: None,
+            spn: None,
+            monitors: None,
+            session_id: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            proxy: None,
+            read_timeout: None,
+            write_timeout: None,
+            socket_timeouts: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            tcp_keepalive: None,
+            gfx: false,
+            bitmap_cache: false,
+            persistent_cache_path: None,
+            audio: None,
+            reconnect: None,
+            warnings: WarningHandler::default(),
+            license_store: None,
+            client_info: ClientInfo::default(),
+            remote_app: None,
+            network_autodetect: false,
+            preconnection_blob: None,
+            use_fast_path_input: true,
+            sync_toggle_keys: true,
+            channels: Vec::new(),
+            drive: None,
+            disable_encryption: false,
+            #[cfg(feature = "pcap")]
+            pcap_path: None,
+            video: None,
         }
     }
 
@@ -224,53 +1134,400 @@ impl Connector {
     ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
     /// let mut client = connector.connect(tcp).unwrap();
     /// ```
-    pub fn connect<S: Read + Write>(&mut self, stream: S) -> RdpResult<RdpClient<S>> {
-        // Create a wrapper around the stream
-        let tcp = Link::new(Stream::Raw(stream));
+    #[cfg(feature = "sync")]
+    pub fn connect<S: Read + Write>(&mut self, stream: S) -> RdpResult<RdpClient<S>> { self.connect_blocking(stream) }
 
-        // Compute authentication method
-        let mut authentication = if let Some(hash) = &self.password_hash {
-            Ntlm::from_hash(self.domain.clone(), self.username.clone(), hash)
-        } else {
-            Ntlm::new(self.domain.clone(), self.username.clone(), self.password.clone())
+    /// Connection sequence blocking on each read of the stream
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub(crate) fn connect_blocking<S: Read + Write>(&mut self, stream: S) -> RdpResult<RdpClient<S>> {
+        run_blocking(self.connect_with(stream, &mut Blocking))
+    }
+
+    /// Connect over a browser WebSocket
+    /// The sequence waits for each frame of the server
+    /// instead of blocking the event loop
+    ///
+    /// TLS and NLA aren't available in the browser,
+    /// the `wss://` connection protects the session
+    ///
+    /// # Example
+    /// ```no_run
+    /// # async fn connect() -> rdp::model::error::RdpResult<()> {
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::websocket::WebSocketStream;
+    /// let stream = WebSocketStream::connect("wss://proxy.example.com/rdp").await?;
+    /// let mut client = Connector::new().disable_encryption().connect_websocket(stream).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "wasm")]
+    pub async fn connect_websocket(&mut self, stream: WebSocketStream) -> RdpResult<RdpClient<WebSocketStream>> {
+        let mut wait = stream.clone();
+        self.connect_with(stream, &mut wait).await
+    }
+
+    /// Connection sequence reading each PDU of the server once `wait` resolves
+    pub(crate) async fn connect_with<S: Read + Write>(
+        &mut self, stream: S, wait: &mut impl FrameWait,
+    ) -> RdpResult<RdpClient<S>> {
+        if let Some(monitors) = &self.monitors {
+            monitors.validate()?;
+        }
+        let name = self.client_name();
+        // The gateway handshake reads its answers without waiting
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.gateway.is_some() && !wait.is_blocking() {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::NotImplemented,
+                "CLIENT: RD Gateway needs a blocking stream",
+            )));
+        }
+        // Create a wrapper around the stream
+        // tunneled through the gateway if any
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut tcp = match &self.gateway {
+            Some(gateway) => Link::new(Stream::Gateway(Box::new(GatewayStream::connect(
+                stream,
+                gateway,
+                &name,
+                self.check_certificate,
+            )?))),
+            None => Link::new(Stream::Raw(stream)),
         };
-        // Create the x224 layer
-        // With all negotiated security stuff and credentials
-        let mut protocols = x224::Protocols::ProtocolSSL as u32;
-        if self.use_nla {
-            protocols |= x224::Protocols::ProtocolHybrid as u32;
+        #[cfg(target_arch = "wasm32")]
+        let mut tcp = Link::new(Stream::Raw(stream));
+        if wait.is_blocking() {
+            let timeouts = self.read_timeout.is_some() || self.write_timeout.is_some();
+            tcp.set_socket_timeouts(self.socket_timeouts || timeouts);
+        } else {
+            // WouldBlock only means the frame is not fully received yet
+            tcp.set_resumable_reads(true);
+        }
+        if let Some(sha256) = &self.certificate_pin {
+            tcp.set_certificate_pin(sha256.clone());
+        }
+        if let Some(host) = self.server_host.as_ref().filter(|_| self.verify_hostname) {
+            tcp.set_expected_hostname(host.clone());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(identity) = &self.client_certificate {
+            tcp.set_client_identity(identity.clone());
+        }
+        #[cfg(feature = "pcap")]
+        if let Some(path) = &self.pcap_path {
+            tcp.set_recorder(PcapWriter::create(path)?);
+        }
+        // The preconnection PDU must be the very first bytes
+        if let Some(blob) = &self.preconnection_blob {
+            tcp.write_msg(&preconnection_pdu(0, blob))?;
+        }
+
+        // Compute authentication method
+        let mut authentication = self.authentication()?;
+        // Create the x224 layer
+        // With all negotiated security stuff and credentials
+        let mut protocols = x224::Protocols::ProtocolSSL as u32;
+        if self.use_nla {
+            protocols |= x224::Protocols::ProtocolHybrid as u32;
+        }
+        if self.disable_encryption {
+            log_warn!("CLIENT: TLS is disabled, the session is not protected");
+            protocols = x224::Protocols::ProtocolRDP as u32;
         }
 
-        let x224 = x224::Client::connect(
+        let x224 = x224::Client::connect_with(
             tpkt::Client::new(tcp),
             protocols,
             self.check_certificate,
-            Some(&mut authentication),
+            Some(authentication.as_mut()),
             self.restricted_admin_mode,
             self.blank_creds,
-        )?;
+            wait,
+        )
+        .await?;
+        let selected_protocol = x224.get_selected_protocols();
+
+        // With several monitors the desktop cover all of them
+        let (width, height) = match &self.monitors {
+            Some(monitors) => monitors.desktop_size(),
+            None => (self.width, self.height),
+        };
 
         // Create MCS layer and connect it
         let mut mcs = mcs::Client::new(x224);
-        mcs.connect(self.name.clone(), self.width, self.height, self.layout)?;
-        // state less connection for old secure layer
-        if self.restricted_admin_mode {
-            sec::connect(&mut mcs, &String::new(), &String::new(), &String::new(), self.auto_logon)?;
+        mcs.set_support_gfx(self.gfx);
+        mcs.set_identity(self.identity);
+        mcs.set_support_network_autodetect(self.network_autodetect);
+        if let Some(session_id) = self.session_id {
+            mcs.set_redirected_session_id(session_id);
+        }
+        mcs.set_warning_handler(self.warnings.clone());
+        let mut static_channels = vec![
+            StaticChannelDef::new(CLIPRDR_CHANNEL_NAME),
+            StaticChannelDef::new(DRDYNVC_CHANNEL_NAME),
+            StaticChannelDef::new(RDPDR_CHANNEL_NAME),
+        ];
+        if self.audio.is_some() {
+            static_channels.push(StaticChannelDef::new(RDPSND_CHANNEL_NAME));
+        }
+        if self.remote_app.is_some() {
+            static_channels.push(StaticChannelDef::new(RAIL_CHANNEL_NAME));
+        }
+        // Channels of the user replace the options of the built-in ones
+        for channel in &self.channels {
+            match static_channels.iter_mut().find(|builtin| builtin.name == channel.name) {
+                Some(builtin) => builtin.options = channel.options,
+                None => static_channels.push(channel.clone()),
+            }
+        }
+        mcs.connect_with(name.clone(), width, height, self.layout, &static_channels, self.monitors.as_ref(), wait)
+            .await?;
+        // Servers without TLS select an encryption method
+        if mcs.get_server_security().encryption_method == EncryptionMethod::None as u32 {
+            if matches!(selected_protocol, x224::Protocols::ProtocolRDP) {
+                mcs.warn(RdpWarning::UnencryptedSession);
+            }
         } else {
-            sec::connect(&mut mcs, &self.domain, &self.username, &self.password, self.auto_logon)?;
+            #[cfg(feature = "legacy-security")]
+            security::connect(&mut mcs)?;
+            #[cfg(not(feature = "legacy-security"))]
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::NotImplemented,
+                "CLIENT: Standard RDP security needs the legacy-security feature",
+            )));
         }
+        // state less connection for old secure layer
+        let mut license_store = match &self.license_store {
+            Some(SharedLicenseStore(store)) => Some(store.lock().map_err(|_| {
+                Error::RdpError(RdpError::new(RdpErrorKind::Unknown, "CLIENT: Poisoned license store"))
+            })?),
+            None => None,
+        };
+        let license_store = license_store.as_mut().map(|store| &mut **store as &mut dyn LicenseStore);
+        let mut autodetect = AutoDetector::new();
+        let (domain, username, password) = if self.restricted_admin_mode {
+            ("", "", "")
+        } else {
+            (self.domain.as_str(), self.username.as_str(), self.password.as_str())
+        };
+        sec::connect_with(
+            &mut mcs,
+            domain,
+            username,
+            password,
+            self.auto_logon,
+            &self.client_info,
+            license_store,
+            &mut autodetect,
+            wait,
+        )
+        .await?;
 
         // Now the global channel
-        let global = global::Client::new(
-            mcs.get_user_id(),
-            mcs.get_global_channel_id(),
-            self.width,
-            self.height,
-            self.layout,
-            &self.name,
-        );
+        let mut global =
+            global::Client::new(mcs.get_user_id(), mcs.get_global_channel_id(), width, height, self.layout, &name);
+        if let Some(monitors) = &self.monitors {
+            global.set_monitor_layout(monitors.clone());
+        }
+        global.set_bitmap_cache(self.bitmap_cache);
+        if let Some(path) = self.persistent_cache_path.as_ref().filter(|_| self.bitmap_cache) {
+            global.set_persistent_bitmap_cache(PersistentBitmapCache::open(path)?);
+        }
+        global.set_fast_path_input(self.use_fast_path_input);
+        if self.sync_toggle_keys {
+            global.set_toggle_flags(Some(toggle_keys::local_toggle_flags()));
+        }
+        global.set_warning_handler(self.warnings.clone());
+        if self.remote_app.is_some() {
+            global.register_capability_handler(Box::new(StaticCapability::from(ts_rail_capability_set())));
+            global.register_capability_handler(Box::new(StaticCapability::from(ts_window_capability_set())));
+        }
+
+        let cliprdr = if mcs.has_channel(CLIPRDR_CHANNEL_NAME) { Some(ClipboardChannel::new()) } else { None };
+
+        let rdpdr = mcs.has_channel(RDPDR_CHANNEL_NAME).then(|| match self.drive {
+            Some(access) => DriveRedirector::new(&name).drive(access),
+            None => DriveRedirector::new(&name),
+        });
+
+        // Servers which don't open the graphics pipeline keep sending bitmap updates
+        let mut dvc = DvcManager::new();
+        let gfx = if self.gfx && mcs.has_channel(DRDYNVC_CHANNEL_NAME) {
+            let gfx = GfxChannel::new(u32::from(width), u32::from(height));
+            dvc.register(GFX_CHANNEL_NAME, gfx.clone());
+            Some(gfx)
+        } else {
+            None
+        };
+
+        // Desktop resizing is always available with dynamic channels
+        let disp = if mcs.has_channel(DRDYNVC_CHANNEL_NAME) {
+            let disp = DisplayChannel::new();
+            dvc.register(DISP_CHANNEL_NAME, disp.clone());
+            Some(disp)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "experimental-wts")]
+        let wts = if mcs.has_channel(DRDYNVC_CHANNEL_NAME) {
+            let wts = SessionChannel::new();
+            dvc.register(WTS_CHANNEL_NAME, wts.clone());
+            Some(wts)
+        } else {
+            None
+        };
+
+        let rdpsnd = match &self.audio {
+            Some(output) if mcs.has_channel(RDPSND_CHANNEL_NAME) => Some(AudioChannel::new(output.clone())),
+            _ => None,
+        };
+
+        let rail = match &self.remote_app {
+            Some(config) if mcs.has_channel(RAIL_CHANNEL_NAME) => Some(RailChannel::new(config.clone(), width, height)),
+            _ => None,
+        };
+
+        // A reconnection goes on with the same video
+        if let Some(video) = &self.video {
+            if let Err(e) = video.lock().and_then(|mut recorder| recorder.start(width, height, Instant::now())) {
+                log_warn!("RDPCLIENT: Video recording stopped {:?}", e);
+            }
+        }
+
+        let reconnector = Reconnector { connector: self.reconnect.map(|_| self.clone()), ..Default::default() };
 
-        Ok(RdpClient { mcs, global })
+        Ok(RdpClient {
+            mcs,
+            global,
+            cliprdr,
+            dvc,
+            rdpdr,
+            gfx,
+            rdpsnd,
+            rail,
+            disp,
+            #[cfg(feature = "experimental-wts")]
+            wts,
+            autodetect,
+            channels: HashMap::new(),
+            reconnector,
+            stats: StatsTracker::default(),
+            recorder: self.video.clone(),
+        })
+    }
+
+    /// Open the TCP connection to the server
+    /// through the proxy if any, then connect
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::socks::ProxyConfig;
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .proxy(ProxyConfig::Socks5 { host: "127.0.0.1".to_string(), port: 1080, username: None, password: None });
+    /// let mut client = connector.connect_tcp("rdp.example.com", 3389).unwrap();
+    /// ```
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    pub fn connect_tcp(&mut self, host: &str, port: u16) -> RdpResult<RdpClient<TcpStream>> {
+        // The certificate is checked against this host unless a server name is set
+        let server_host = self.server_host.clone();
+        self.server_host.get_or_insert_with(|| host.to_string());
+        let result = self.open_tcp(host, port).and_then(|tcp| self.connect(tcp));
+        self.server_host = server_host;
+        let mut client = result?;
+        if self.reconnect.is_some() {
+            let connector = self.clone();
+            let host = host.to_string();
+            client.set_rebuild_stream(move || connector.open_tcp(&host, port));
+        }
+        Ok(client)
+    }
+
+    /// Connect to a virtual machine of this host through
+    /// a named pipe, a Unix socket or a `vsock://cid:port` address
+    ///
+    /// See `PipeStream`, read and write timeouts are applied to sockets
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::client::Connector;
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+    /// let mut client = connector.connect_pipe(r"\\.\pipe\vmrdp").unwrap();
+    /// ```
+    #[cfg(all(feature = "sync", any(unix, windows)))]
+    pub fn connect_pipe<P: AsRef<Path>>(&mut self, path: P) -> RdpResult<RdpClient<PipeStream>> {
+        let mut client = self.connect(self.open_pipe(path.as_ref())?)?;
+        if self.reconnect.is_some() {
+            let connector = self.clone();
+            let path = path.as_ref().to_path_buf();
+            client.set_rebuild_stream(move || connector.open_pipe(&path));
+        }
+        Ok(client)
+    }
+
+    /// Open a pipe stream with the timeouts
+    #[cfg(all(feature = "sync", any(unix, windows)))]
+    fn open_pipe(&self, path: &Path) -> RdpResult<PipeStream> {
+        let pipe = PipeStream::connect(path)?;
+        pipe.set_timeouts(self.read_timeout, self.write_timeout)?;
+        Ok(pipe)
+    }
+
+    /// Open a TCP stream with the socket options
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    fn open_tcp(&self, host: &str, port: u16) -> RdpResult<TcpStream> {
+        let tcp = match &self.proxy {
+            Some(proxy) => proxy.connect(host, port)?,
+            None => TcpStream::connect((host, port))?,
+        };
+        tcp.set_nodelay(true)?;
+        tcp.set_read_timeout(self.read_timeout)?;
+        tcp.set_write_timeout(self.write_timeout)?;
+        if let Some((interval, retries)) = self.tcp_keepalive {
+            set_tcp_keepalive(&tcp, interval, retries)?;
+        }
+        Ok(tcp)
+    }
+
+    /// Select the authentication layer used by NLA
+    /// Kerberos is preferred when a service principal name is known
+    /// and a ticket can be obtained, otherwise fallback to NTLM
+    fn authentication(&self) -> RdpResult<Box<dyn AuthenticationProtocol>> {
+        if let Some(hash) = &self.password_hash {
+            return Ok(Box::new(Ntlm::from_hash(self.domain.clone(), self.username.clone(), hash)));
+        }
+        let ntlm = Box::new(Ntlm::new(self.domain.clone(), self.username.clone(), self.password.clone()));
+        match (self.auth_protocol, &self.spn) {
+            (Some(AuthProtocol::Kerberos), Some(spn)) => self.kerberos(spn),
+            (Some(AuthProtocol::Kerberos), None) => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidData,
+                "NLA: Kerberos authentication needs a service principal name",
+            ))),
+            (None, Some(spn)) if cfg!(all(feature = "kerberos", unix)) => match self.kerberos(spn) {
+                Ok(kerberos) => Ok(kerberos),
+                Err(e) => {
+                    log_warn!("NLA: Kerberos unavailable, fallback to NTLM {:?}", e);
+                    Ok(ntlm)
+                }
+            },
+            _ => Ok(ntlm),
+        }
+    }
+
+    #[cfg(all(feature = "kerberos", unix))]
+    fn kerberos(&self, spn: &str) -> RdpResult<Box<dyn AuthenticationProtocol>> {
+        Ok(Box::new(Kerberos::new(spn, self.domain.clone(), self.username.clone(), self.password.clone())?))
+    }
+
+    #[cfg(not(all(feature = "kerberos", unix)))]
+    fn kerberos(&self, _spn: &str) -> RdpResult<Box<dyn AuthenticationProtocol>> {
+        Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::NotImplemented,
+            "NLA: Kerberos authentication needs the kerberos feature",
+        )))
     }
 
     /// Configure the screen size of the session
@@ -297,11 +1554,28 @@ impl Connector {
     }
 
     /// Try authenticate using NTLM hashes and restricted admin mode
+    /// The hash is the NT hash of the password, it must be used with
+    /// `set_restricted_admin_mode` as no password can be delegated to the
+    /// server
     pub fn set_password_hash(mut self, password_hash: Vec<u8>) -> Self {
         self.password_hash = Some(password_hash);
         self
     }
 
+    /// Authenticate with the NT hash of the password in restricted admin mode
+    /// The server logs on the user without receiving any credentials
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), String::new())
+    ///     .use_restricted_admin_mode_with_hash([0; 16]);
+    /// ```
+    pub fn use_restricted_admin_mode_with_hash(self, nt_hash: [u8; 16]) -> Self {
+        self.set_restricted_admin_mode(true).set_password_hash(nt_hash.to_vec())
+    }
+
     /// Set the keyboard layout
     pub fn layout(mut self, layout: KeyboardLayout) -> Self {
         self.layout = layout;
@@ -326,15 +1600,1007 @@ impl Connector {
         self
     }
 
+    /// Check the server certificate is issued for the server host,
+    /// enabled by default
+    ///
+    /// The host comes from `server_name` or `connect_tcp`,
+    /// it is matched against the subject alternative names
+    /// or the common name of the certificate.
+    /// This works without `check_certificate`,
+    /// so self-signed certificates are still bound to their host.
+    /// A certificate pinned with `certificate_pinning` is not checked,
+    /// disable it to reach a server by IP address without pinning
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::client::Connector;
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .check_certificate(false)
+    ///     .verify_hostname(true);
+    /// let mut client = connector.connect_tcp("rdp.example.com", 3389).unwrap();
+    /// ```
+    pub fn verify_hostname(mut self, verify_hostname: bool) -> Self {
+        self.verify_hostname = verify_hostname;
+        self
+    }
+
+    /// Pin the SHA-256 fingerprint of the server certificate
+    /// Any other certificate is rejected with
+    /// `RdpErrorKind::CertificateInvalid`
+    ///
+    /// This works without `check_certificate`,
+    /// so self-signed certificates can be trusted
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let fingerprint = vec![0x5a; 32];
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .certificate_pinning(fingerprint);
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// ```
+    pub fn certificate_pinning(mut self, sha256: Vec<u8>) -> Self {
+        self.certificate_pin = Some(sha256);
+        self
+    }
+
+    /// Present a client certificate during the TLS handshake
+    /// Without it, servers asking for one are reported
+    /// with `RdpErrorKind::CertificateRequired`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use native_tls::Identity;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let identity = Identity::from_pkcs12(&std::fs::read("client.pfx").unwrap(), "password").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .client_certificate(identity);
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn client_certificate(mut self, cert: Identity) -> Self {
+        self.client_certificate = Some(TlsIdentity(cert));
+        self
+    }
+
+    /// Reconnect to an existing session of the server
+    /// The session id comes from `RdpEvent::LogonInfo`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .session_id(2);
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// ```
+    pub fn session_id(mut self, session_id: u32) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    /// Windows build number send to the server
+    /// Default is 3790
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// // Windows 10 2004
+    /// let connector = Connector::new().client_build(19041);
+    /// ```
+    pub fn client_build(mut self, build: u32) -> Self {
+        self.identity.build = build;
+        self
+    }
+
+    /// Product id send to the server
+    /// Default is 1
+    pub fn client_product_id(mut self, id: u16) -> Self {
+        self.identity.product_id = id;
+        self
+    }
+
+    /// Serial number send to the server
+    /// Default is 0
+    pub fn serial_number(mut self, n: u32) -> Self {
+        self.identity.serial_number = n;
+        self
+    }
+
     /// Set the default name send to server
+    /// It takes precedence over `name_from_hostname`
     pub fn name(mut self, name: String) -> Self {
-        self.name = name;
+        self.name = Some(name);
         self
     }
 
+    /// Send the host name of the machine as client name
+    /// unless a name is set with `name`
+    ///
+    /// The name is cut to fit the GCC client name field,
+    /// the default name is kept on wasm32 where the browser
+    /// doesn't expose the host name
+    pub fn name_from_hostname(mut self) -> Self {
+        self.name_from_hostname = true;
+        self
+    }
+
+    /// Name exposed to the server
+    fn client_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            None if self.name_from_hostname => truncate_client_name(&gethostname::gethostname().to_string_lossy()),
+            None => DEFAULT_CLIENT_NAME.to_string(),
+        }
+    }
+
     /// Enable or disable Network Level Authentication
     pub fn use_nla(mut self, use_nla: bool) -> Self {
         self.use_nla = use_nla;
         self
     }
+
+    /// Connect through a Remote Desktop Gateway
+    /// The stream given to `connect` must then be connected to the gateway
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::gateway::GatewayConfig;
+    /// let tcp = TcpStream::connect("gateway.example.com:443").unwrap();
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .gateway(GatewayConfig {
+    ///         host: "gateway.example.com".to_string(),
+    ///         port: 443,
+    ///         username: "domain\\username".to_string(),
+    ///         password: "password".to_string(),
+    ///         target_host: "target.example.com".to_string(),
+    ///         target_port: 3389,
+    ///     });
+    /// let mut client = connector.connect(tcp).unwrap();
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn gateway(mut self, config: GatewayConfig) -> Self {
+        self.gateway = Some(config);
+        self
+    }
+
+    /// Fail with `RdpErrorKind::Timeout` when the server
+    /// doesn't send anything during this delay
+    /// Only use by `connect_tcp` and `connect_pipe`, set it directly
+    /// on streams given to `connect` with `socket_timeouts`
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail with `RdpErrorKind::Timeout` when the server
+    /// doesn't accept data during this delay
+    /// Only use by `connect_tcp` and `connect_pipe`, wrap sockets given
+    /// to `connect` in a `WriteTimeoutStream`
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// The stream given to `connect` has a read or write timeout,
+    /// its `WouldBlock` errors are reported as `RdpErrorKind::Timeout`
+    /// Default is false, nonblocking streams keep their `WouldBlock` errors
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    /// use rdp::core::client::Connector;
+    /// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+    /// tcp.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    /// let mut client = Connector::new().socket_timeouts(true).connect(tcp).unwrap();
+    /// ```
+    pub fn socket_timeouts(mut self, enabled: bool) -> Self {
+        self.socket_timeouts = enabled;
+        self
+    }
+
+    /// Send TCP keep-alive probes after `interval` of inactivity
+    /// so NAT devices and firewalls don't drop idle sessions
+    /// Disabled by default
+    /// Only use by `connect_tcp`, use `set_tcp_keepalive` on streams given to
+    /// `connect`
+    ///
+    /// Probes don't reset the idle timeout of the server,
+    /// an idle session is still disconnected by its policy
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tcp_keepalive(mut self, interval: Duration, retries: u32) -> Self {
+        self.tcp_keepalive = Some((interval, retries));
+        self
+    }
+
+    /// Reach the server through a SOCKS or HTTP proxy
+    /// Only use by `connect_tcp`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn proxy(mut self, config: ProxyConfig) -> Self {
+        self.proxy = Some(config);
+        self
+    }
+
+    /// Use several monitors
+    /// The desktop size is the bounding rectangle of all monitors
+    /// and replace the size given by `screen`
+    /// The layout is checked by `MonitorLayout::validate` when connecting
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::gcc::{Monitor, MonitorLayout};
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .monitors(MonitorLayout {
+    ///         monitors: vec![
+    ///             Monitor { left: 0, top: 0, right: 1919, bottom: 1079, is_primary: true },
+    ///             Monitor { left: 1920, top: 0, right: 3199, bottom: 1023, is_primary: false },
+    ///         ],
+    ///     });
+    /// ```
+    pub fn monitors(mut self, layout: MonitorLayout) -> Self {
+        self.monitors = Some(layout);
+        self
+    }
+
+    /// Play the session audio on this output
+    /// The server keeps the audio without output
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::rdpsnd::{AudioFormat, AudioOutput};
+    /// use rdp::model::error::RdpResult;
+    /// struct Silent;
+    /// impl AudioOutput for Silent {
+    ///     fn open(&mut self, format: &AudioFormat) -> RdpResult<()> { Ok(()) }
+    ///     fn play(&mut self, data: &[u8]) -> RdpResult<()> { Ok(()) }
+    /// }
+    /// let connector = Connector::new().audio(Silent);
+    /// ```
+    pub fn audio<O: AudioOutput + Send + 'static>(mut self, output: O) -> Self {
+        self.audio = Some(SharedAudioOutput(Arc::new(Mutex::new(output))));
+        self
+    }
+
+    /// Ask for the graphics pipeline (MS-RDPEGFX)
+    /// Its updates are notified as `RdpEvent::Bitmap`
+    /// Only uncompressed and RemoteFX surface commands are decoded
+    pub fn gfx(mut self, gfx: bool) -> Self {
+        self.gfx = gfx;
+        self
+    }
+
+    /// Reconnect automatically when the connection is lost
+    /// `RdpClient::read` reopens the session with the same settings,
+    /// up to `max_attempts` times waiting `delay` before each attempt,
+    /// then notify `RdpEvent::Reconnected`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use rdp::core::client::Connector;
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .reconnect_interval(5, Duration::from_secs(2));
+    /// let mut client = connector.connect_tcp("127.0.0.1", 3389).unwrap();
+    /// ```
+    pub fn reconnect_interval(mut self, max_attempts: u32, delay: Duration) -> Self {
+        self.reconnect = Some(ReconnectPolicy { max_attempts, delay });
+        self
+    }
+
+    /// Announce the bitmap cache with the memory blit and nine grid orders
+    /// Cache bitmap orders fill it up to the announced cache sizes
+    /// Default is false, the server then only sends bitmap updates
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().bitmap_cache(true);
+    /// ```
+    pub fn bitmap_cache(mut self, bitmap_cache: bool) -> Self {
+        self.bitmap_cache = bitmap_cache;
+        self
+    }
+
+    /// Keep the cached bitmaps in this file between sessions
+    /// The server doesn't send them again at the next connection
+    /// Only used with `bitmap_cache`
+    ///
+    /// The file is written by `RdpClient::shutdown`
+    /// or `RdpClient::save_bitmap_cache`
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use std::path::PathBuf;
+    /// let connector = Connector::new().bitmap_cache(true).persistent_cache_path(PathBuf::from("bitmaps.cache"));
+    /// ```
+    pub fn persistent_cache_path(mut self, path: PathBuf) -> Self {
+        self.persistent_cache_path = Some(path);
+        self
+    }
+
+    /// Start this program instead of the desktop shell
+    /// The session ends when the program exits
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().alternate_shell("notepad.exe").working_dir("C:\\Users");
+    /// ```
+    pub fn alternate_shell(mut self, program: &str) -> Self {
+        self.client_info.alternate_shell = Some(program.to_string());
+        self
+    }
+
+    /// Working directory of the shell
+    pub fn working_dir(mut self, path: &str) -> Self {
+        self.client_info.working_dir = Some(path.to_string());
+        self
+    }
+
+    /// Time zone announced to the server
+    /// UTC is announced by default
+    pub fn time_zone(mut self, time_zone: TimeZoneInfo) -> Self {
+        self.client_info.time_zone = Some(time_zone);
+        self
+    }
+
+    /// Visual effects disabled or enabled in the session
+    /// No flag is sent by default, the server keeps its settings
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::sec::PerformanceFlags;
+    /// let connector = Connector::new()
+    ///     .set_performance_flags(PerformanceFlags::DISABLE_WALLPAPER | PerformanceFlags::ENABLE_FONT_SMOOTHING);
+    /// ```
+    pub fn set_performance_flags(mut self, flags: PerformanceFlags) -> Self {
+        self.client_info.performance_flags = flags;
+        self
+    }
+
+    /// Disable the visual effects expensive on slow connections
+    /// Same as `set_performance_flags(PerformanceFlags::LOW_BANDWIDTH)`
+    pub fn optimize_for_bandwidth(self) -> Self { self.set_performance_flags(PerformanceFlags::LOW_BANDWIDTH) }
+
+    /// Answer the network auto-detection of the server
+    /// through the message channel
+    /// The result is given by `RdpClient::bandwidth_estimate`,
+    /// a slow connection disables visual effects when reconnecting
+    pub fn network_autodetect(mut self, enabled: bool) -> Self {
+        self.network_autodetect = enabled;
+        self
+    }
+
+    /// Start a single application in seamless windows
+    /// instead of the whole desktop
+    /// Windows are notified with `RdpEvent::RemoteWindow`
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::rail::RemoteAppConfig;
+    /// let connector = Connector::new().remote_app(RemoteAppConfig {
+    ///     exe_path: "||notepad".to_string(),
+    ///     working_dir: String::new(),
+    ///     arguments: String::new(),
+    /// });
+    /// ```
+    pub fn remote_app(mut self, config: RemoteAppConfig) -> Self {
+        self.client_info.remote_app = true;
+        self.remote_app = Some(config);
+        self
+    }
+
+    /// Send a preconnection blob before the X.224 connection request
+    /// Hyper-V and Azure hosts use it to select the virtual machine
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().preconnection_blob("0d9b4b4b-2f7e-4c5e-9e4c-8b5e0c9f3a1d");
+    /// ```
+    pub fn preconnection_blob(mut self, blob: &str) -> Self {
+        self.preconnection_blob = Some(blob.to_string());
+        self
+    }
+
+    /// Send keyboard and pointer input as fast path PDUs
+    /// when the server supports them, which saves around
+    /// 40 bytes per event
+    /// Default is true
+    pub fn use_fast_path_input(mut self, use_fast_path_input: bool) -> Self {
+        self.use_fast_path_input = use_fast_path_input;
+        self
+    }
+
+    /// Ask the server for more static virtual channels
+    /// Register their handler with `RdpClient::register_channel`
+    /// once connected
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::channel::{ChannelOptions, StaticChannelDef};
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().channels(vec![
+    ///     StaticChannelDef::new("echo"),
+    ///     StaticChannelDef { name: "urgent".to_string(), options: ChannelOptions::INITIALIZED | ChannelOptions::PRI_HIGH },
+    /// ]);
+    /// ```
+    pub fn channels(mut self, channels: Vec<StaticChannelDef>) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Expose an in memory drive to the session under \\tsclient\RDPRS
+    /// Exchange its files with `RdpClient::drive_redirector`
+    /// Default doesn't redirect any drive
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::rdpdr::DriveAccess;
+    /// let connector = Connector::new().drive_redirection(DriveAccess::ReadOnly);
+    /// ```
+    pub fn drive_redirection(mut self, access: DriveAccess) -> Self {
+        self.drive = Some(access);
+        self
+    }
+
+    /// Synchronize Caps Lock, Num Lock and Scroll Lock of the server
+    /// with the local keyboard once connected
+    /// Disable it to get the same session whatever the local keyboard,
+    /// e.g. for automated testing
+    /// Default is true
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().sync_toggle_keys(false);
+    /// ```
+    pub fn sync_toggle_keys(mut self, sync_toggle_keys: bool) -> Self {
+        self.sync_toggle_keys = sync_toggle_keys;
+        self
+    }
+
+    /// Record the session into a pcap file for Wireshark
+    /// The TLS layer is removed so the RDP PDUs can be dissected,
+    /// the capture contains the credentials of the session
+    ///
+    /// A reconnection starts a new capture
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().record_to_pcap("session.pcap");
+    /// ```
+    #[cfg(feature = "pcap")]
+    pub fn record_to_pcap<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.pcap_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Record the screen as a YUV4MPEG2 video
+    /// `fps` frames per second are written, zero uses the default of 15
+    ///
+    /// The video has the size of the screen at connection
+    /// Frames are written by `RdpClient::read`, the screen
+    /// is repeated for the time spent waiting for data
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::fs::File;
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().record_video(File::create("session.y4m").unwrap(), 15);
+    /// ```
+    pub fn record_video<W: Write + Send + 'static>(mut self, output: W, fps: u8) -> Self {
+        self.video = Some(SharedRecorder(Arc::new(Mutex::new(SessionRecorder::new(output).fps(fps)))));
+        self
+    }
+
+    /// Connect over plain TCP without TLS for old servers, lab servers
+    /// and devices which only support the standard RDP security
+    ///
+    /// **Insecure**: the server is not authenticated and NLA is disabled.
+    /// The session is in clear text when the server selects no encryption.
+    /// The deprecated RC4 and FIPS encryption of the standard security
+    /// layer need the `legacy-security` feature, RC4 is broken anyway.
+    /// `RdpWarning::UnencryptedSession` is sent when nothing is encrypted
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().disable_encryption();
+    /// ```
+    pub fn disable_encryption(mut self) -> Self {
+        self.disable_encryption = true;
+        self
+    }
+
+    /// Save the license issued by the server in this store
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// use rdp::core::license::LicenseStore;
+    /// use rdp::model::error::RdpResult;
+    /// struct Discard;
+    /// impl LicenseStore for Discard {
+    ///     fn store(&mut self, blob: &[u8]) -> RdpResult<()> { Ok(()) }
+    ///     fn load(&self) -> Option<Vec<u8>> { None }
+    /// }
+    /// let connector = Connector::new().license_store(Discard);
+    /// ```
+    pub fn license_store<L: LicenseStore + Send + 'static>(mut self, store: L) -> Self {
+        self.license_store = Some(SharedLicenseStore(Arc::new(Mutex::new(store))));
+        self
+    }
+
+    /// Receive the non fatal messages of the server
+    /// like license alerts, error info or deactivation
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::client::Connector;
+    /// let connector = Connector::new().on_warning(|warning| eprintln!("{}", warning));
+    /// ```
+    pub fn on_warning<F: Fn(RdpWarning) + Send + 'static>(mut self, f: F) -> Self {
+        self.warnings = WarningHandler::new(f);
+        self
+    }
+
+    /// Name of the target server
+    /// Use to build the Kerberos service principal name `TERMSRV/<server_name>`
+    /// so NLA can prefer Kerberos over NTLM,
+    /// and to check the host of the server certificate
+    pub fn server_name(mut self, server_name: &str) -> Self {
+        self.spn = Some(format!("TERMSRV/{}", server_name));
+        self.server_host = Some(server_name.to_string());
+        self
+    }
+
+    /// Only authenticate using Kerberos during NLA
+    /// Tickets are taken from the default credential cache
+    /// Need the `kerberos` feature
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::client::Connector;
+    /// let mut connector = Connector::new()
+    ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string())
+    ///     .force_kerberos("TERMSRV/server.example.com");
+    /// ```
+    pub fn force_kerberos(mut self, spn: &str) -> Self {
+        self.auth_protocol = Some(AuthProtocol::Kerberos);
+        self.spn = Some(spn.to_string());
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(target_arch = "wasm32"))]
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread::JoinHandle;
+
+    use super::*;
+    use crate::core::event::{DisconnectReason, KeyboardEvent};
+    use crate::core::global::test::activation;
+    use crate::model::unicode::Unicode;
+    use crate::test_utils::mock_server::{fixture, is_x224_data, MockRdpServer};
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_set_tcp_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(!SockRef::from(&tcp).keepalive().unwrap());
+        set_tcp_keepalive(&tcp, Duration::from_secs(30), 3).unwrap();
+        assert!(SockRef::from(&tcp).keepalive().unwrap());
+    }
+
+    /// X224 connection request asking for the given protocols
+    fn is_connection_request(protocols: u32) -> impl Fn(&[u8]) -> bool + Send {
+        move |pdu| pdu.len() == 19 && pdu[5] == 0xe0 && pdu[15..19] == protocols.to_le_bytes()
+    }
+
+    #[test]
+    fn test_connect_negotiation_failure() {
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(x224::Protocols::ProtocolSSL as u32))
+            .send(&fixture("x224_confirm_failure.bin"));
+        let address = server.address();
+        let server = server.run();
+
+        let result = Connector::new().use_nla(false).connect_blocking(TcpStream::connect(address).unwrap());
+        match result {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::ProtocolNegFailure),
+            _ => panic!("expected a negotiation failure"),
+        }
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_connect_disable_encryption() {
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(x224::Protocols::ProtocolRDP as u32))
+            .send(&fixture("x224_confirm_failure.bin"));
+        let address = server.address();
+        let server = server.run();
+
+        let result = Connector::new().disable_encryption().connect_blocking(TcpStream::connect(address).unwrap());
+        assert!(result.is_err());
+        server.join().unwrap().unwrap();
+    }
+
+    /// The layout is rejected before anything is sent
+    #[test]
+    fn test_connect_invalid_monitors() {
+        let mut stream = std::io::Cursor::new(Vec::new());
+        match Connector::new().monitors(MonitorLayout::default()).connect_blocking(&mut stream) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidData),
+            _ => panic!("expected an invalid layout"),
+        }
+        assert!(stream.get_ref().is_empty());
+    }
+
+    /// Channel join confirm of the user 1007
+    fn channel_join_confirm(channel_id: u16) -> Vec<u8> {
+        let [high, low] = channel_id.to_be_bytes();
+        vec![3, 0, 0, 15, 2, 0xf0, 0x80, 0x3e, 0, 0, 6, high, low, high, low]
+    }
+
+    /// A server selecting the standard RDP security without encryption
+    #[test]
+    fn test_connect_disable_encryption_session() {
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(x224::Protocols::ProtocolRDP as u32))
+            .send(&fixture("x224_confirm_rdp.bin"))
+            // connect initial then connect response without encryption
+            .expect_receive(is_x224_data(&[0x7f, 0x65]))
+            .send(&fixture("mcs_connect_response_no_encryption.bin"))
+            .expect_receive(is_x224_data(&[4, 1, 0, 1, 0]))
+            .expect_receive(is_x224_data(&[0x28]))
+            .send(&[3, 0, 0, 11, 2, 0xf0, 0x80, 0x2e, 0, 0, 6])
+            // the user channel then the I/O channel
+            .expect_receive(is_x224_data(&[0x38, 0, 6, 0x03, 0xef]))
+            .send(&channel_join_confirm(1007))
+            .expect_receive(is_x224_data(&[0x38, 0, 6, 0x03, 0xeb]))
+            .send(&channel_join_confirm(1003))
+            // client info PDU in clear text
+            .expect_receive(|pdu| {
+                is_x224_data(&[0x64, 0, 6, 0x03, 0xeb, 0x70])(pdu)
+                    && pdu[14..18] == [0x40, 0, 0, 0]
+                    && pdu.windows(16).any(|window| window == "password".to_utf16_le())
+            })
+            .send(&fixture("license_valid_client.bin"));
+        let address = server.address();
+        let server = server.run();
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let received = warnings.clone();
+        let result = Connector::new()
+            .credentials("domain".to_string(), "user".to_string(), "password".to_string())
+            .disable_encryption()
+            .on_warning(move |warning| received.lock().unwrap().push(warning))
+            .connect_blocking(TcpStream::connect(address).unwrap());
+        server.join().unwrap().unwrap();
+        let client = result.unwrap();
+        assert!(client.mcs.has_channel("global") && client.mcs.has_channel("user"));
+        assert!(warnings.lock().unwrap().contains(&RdpWarning::UnencryptedSession));
+    }
+
+    #[cfg(feature = "pcap")]
+    #[test]
+    fn test_connect_record_to_pcap() {
+        use crate::core::pcap::replay;
+
+        let confirm = fixture("x224_confirm_failure.bin");
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(x224::Protocols::ProtocolSSL as u32))
+            .send(&confirm);
+        let address = server.address();
+        let server = server.run();
+
+        let path = std::env::temp_dir().join(format!("rdp-rs-connect-{}.pcap", std::process::id()));
+        let result = Connector::new()
+            .use_nla(false)
+            .record_to_pcap(&path)
+            .connect_blocking(TcpStream::connect(address).unwrap());
+        assert!(result.is_err());
+        server.join().unwrap().unwrap();
+
+        let capture = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (sent, received) = replay(&capture);
+        assert!(is_connection_request(x224::Protocols::ProtocolSSL as u32)(&sent));
+        assert_eq!(received, confirm);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_connect_nla_negotiate() {
+        // the TS request must carry a NTLM negotiate message
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(
+                x224::Protocols::ProtocolSSL as u32 | x224::Protocols::ProtocolHybrid as u32,
+            ))
+            .send(&fixture("x224_confirm_hybrid.bin"))
+            .start_tls()
+            .expect_receive(|pdu| pdu[0] == 0x30 && pdu.windows(12).any(|w| w == b"NTLMSSP\0\x01\0\0\0"));
+        let address = server.address();
+        let server = server.run();
+
+        let result = Connector::new()
+            .credentials("domain".to_string(), "user".to_string(), "password".to_string())
+            .connect_blocking(TcpStream::connect(address).unwrap());
+        // the server closes the connection instead of sending its challenge
+        assert!(result.is_err());
+        server.join().unwrap().unwrap();
+    }
+
+    /// Connect with TLS to a server with a self-signed certificate for
+    /// localhost
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    fn connect_self_signed(connector: Connector) -> RdpResult<()> {
+        // the hybrid confirm selecting SSL only
+        let mut confirm = fixture("x224_confirm_hybrid.bin");
+        confirm[15] = x224::Protocols::ProtocolSSL as u8;
+        let server = MockRdpServer::new()
+            .expect_receive(is_connection_request(x224::Protocols::ProtocolSSL as u32))
+            .send(&confirm)
+            .start_tls();
+        let port = server.address().port();
+        let server = server.run();
+        let result = connector.use_nla(false).connect_tcp("127.0.0.1", port).map(|_| ());
+        server.join().unwrap().unwrap();
+        result
+    }
+
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    fn is_certificate_invalid(result: RdpResult<()>) -> bool {
+        matches!(result, Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::CertificateInvalid)
+    }
+
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_verify_hostname() {
+        // the certificate is issued for localhost, not 127.0.0.1
+        assert!(is_certificate_invalid(connect_self_signed(Connector::new())));
+        // the server name takes precedence over the host given to connect_tcp
+        assert!(is_certificate_invalid(connect_self_signed(Connector::new().server_name("rdp.example.com"))));
+    }
+
+    /// A server which accept the connection and never answer
+    #[cfg(all(feature = "sync", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || listener.accept().unwrap());
+
+        let result =
+            Connector::new().use_nla(false).read_timeout(Duration::from_millis(100)).connect_tcp("127.0.0.1", port);
+        match result {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::Timeout),
+            Err(e) => panic!("expected a timeout error {:?}", e),
+            Ok(_) => panic!("expected a timeout error"),
+        }
+        drop(server.join().unwrap());
+    }
+
+    /// The timeout is set by the caller on the stream given to connect
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_socket_timeouts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || listener.accept().unwrap());
+
+        let tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        match Connector::new().use_nla(false).socket_timeouts(true).connect_blocking(tcp) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::Timeout),
+            Err(e) => panic!("expected a timeout error {:?}", e),
+            Ok(_) => panic!("expected a timeout error"),
+        }
+        drop(server.join().unwrap());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_client_name() {
+        assert_eq!(truncate_client_name("workstation-0123456789"), "workstation-012");
+        assert_eq!(truncate_client_name("poste-développeur"), "poste-développ");
+        assert_eq!(truncate_client_name("短い"), "短い");
+        assert_eq!(Connector::new().client_name(), "rdp-rs");
+        let hostname = Connector::new().name_from_hostname().client_name();
+        assert!(!hostname.is_empty() && hostname.len() <= MAX_CLIENT_NAME_LENGTH);
+        // An explicit name wins whatever the order
+        let connector = Connector::new().name("client".to_string()).name_from_hostname();
+        assert_eq!(connector.client_name(), "client");
+    }
+
+    #[test]
+    fn test_performance_flags() {
+        assert_eq!(Connector::new().client_info.performance_flags, PerformanceFlags::empty());
+        let flags = Connector::new().optimize_for_bandwidth().client_info.performance_flags;
+        assert_eq!(flags.bits(), 0x2f);
+        assert!(!flags.contains(PerformanceFlags::ENABLE_FONT_SMOOTHING));
+    }
+
+    #[test]
+    fn test_network_errors() {
+        assert!(is_network_error(&Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset))));
+        assert!(is_network_error(&Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut))));
+        assert!(!is_network_error(&Error::Io(std::io::Error::from(std::io::ErrorKind::PermissionDenied))));
+        assert!(!is_network_error(&Error::RdpError(RdpError::new(RdpErrorKind::Disconnect, "closed"))));
+    }
+
+    /// Client of a mock server past the X224 negotiation
+    /// `steps` follow the connection confirm, `channels` are joined
+    /// with the global channel 1003 by the user 1007
+    fn mock_client(
+        steps: impl FnOnce(MockRdpServer) -> MockRdpServer, channels: &[(&str, u16)],
+    ) -> (RdpClient<TcpStream>, JoinHandle<RdpResult<()>>) {
+        let server =
+            steps(MockRdpServer::new().expect_receive(is_connection_request(0)).send(&fixture("x224_confirm_rdp.bin")));
+        let address = server.address();
+        let server = server.run();
+
+        let link = Link::new(Stream::Raw(TcpStream::connect(address).unwrap()));
+        let x224 = x224::Client::connect(tpkt::Client::new(link), 0, false, None, false, false).unwrap();
+        let channels: Vec<(&str, u16)> = [("global", 1003)].iter().chain(channels).copied().collect();
+        let client = RdpClient {
+            mcs: mcs::Client::connected(x224, 1007, &channels),
+            global: global::Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs"),
+            cliprdr: None,
+            dvc: DvcManager::new(),
+            rdpdr: None,
+            gfx: None,
+            rdpsnd: None,
+            rail: None,
+            disp: None,
+            #[cfg(feature = "experimental-wts")]
+            wts: None,
+            autodetect: AutoDetector::new(),
+            channels: HashMap::new(),
+            reconnector: Reconnector::default(),
+            stats: StatsTracker::default(),
+            recorder: None,
+        };
+        (client, server)
+    }
+
+    #[test]
+    fn test_disconnect_event() {
+        // disconnect provider ultimatum
+        let (mut client, server) =
+            mock_client(|server| server.send(&[0x03, 0x00, 0x00, 0x09, 0x02, 0xf0, 0x80, 0x21, 0x80]), &[]);
+        server.join().unwrap().unwrap();
+
+        let mut events = Vec::new();
+        client.read(|event| events.push(event)).unwrap();
+        assert_eq!(
+            events,
+            [RdpEvent::Disconnect { reason: DisconnectReason::ServerInitiated, error_info: ErrorInfoCode::None }]
+        );
+    }
+
+    /// Reply to each message with its bytes reversed
+    struct ReverseHandler(Vec<Vec<u8>>);
+
+    impl StaticChannelHandler for ReverseHandler {
+        fn on_data(&mut self, data: &[u8]) -> RdpResult<()> {
+            self.0.push(data.iter().rev().copied().collect());
+            Ok(())
+        }
+
+        fn take_output(&mut self) -> Vec<Vec<u8>> { std::mem::take(&mut self.0) }
+    }
+
+    #[test]
+    fn test_user_channel() {
+        // send data indication from the user 1002 on the channel 1004
+        let data_indication =
+            [3, 0, 0, 24, 2, 0xf0, 0x80, 0x68, 0, 1, 0x03, 0xec, 0x70, 10, 2, 0, 0, 0, 3, 0, 0, 0, 1, 2];
+        let (mut client, server) = mock_client(
+            |server| server.send(&data_indication).expect_receive(|pdu| pdu.ends_with(&[2, 0, 0, 0, 3, 0, 0, 0, 2, 1])),
+            &[("echo", 1004)],
+        );
+        assert_eq!(client.channel_id("echo"), Some(1004));
+        assert!(client.register_channel("other", ReverseHandler(Vec::new())).is_err());
+        client.register_channel("echo", ReverseHandler(Vec::new())).unwrap();
+        client.read_message(|_| ()).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_inject_event_batch() {
+        let (mut client, server) = mock_client(|server| server, &[]);
+        server.join().unwrap().unwrap();
+
+        let key = |down| RdpEvent::Key(KeyboardEvent { code: 0x1e, down });
+        client.inject_event_batch(&[]).unwrap();
+        // the whole batch is rejected
+        assert!(client.inject_event_batch(&[key(true), RdpEvent::Reconnected]).is_err());
+        // the session is not activated yet
+        match client.inject_event_batch(&[key(true), key(false)]) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidAutomata),
+            result => panic!("expected an automata error {:?}", result),
+        }
+    }
+
+    /// Once activated a batch is sent as fast path PDUs
+    /// of at most 15 events
+    #[test]
+    fn test_inject_event_batch_fast_path() {
+        let (mut client, server) = mock_client(
+            |server| {
+                activation(server)
+                    .expect_receive(|pdu| {
+                        pdu[..2] == [0x3c, 32]
+                            && pdu[2..].chunks(2).enumerate().all(|(i, event)| event == [(i % 2) as u8, 0x1e])
+                    })
+                    .expect_receive(|pdu| pdu == [0x04, 4, 1, 0x1e])
+            },
+            &[],
+        );
+        for _ in 0..5 {
+            client.read(|_| ()).unwrap();
+        }
+
+        let events: Vec<RdpEvent> =
+            (0..16).map(|i| RdpEvent::Key(KeyboardEvent { code: 0x1e, down: i % 2 == 0 })).collect();
+        client.inject_event_batch(&events).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    /// The server closes the connection and can't be reached anymore
+    #[test]
+    fn test_reconnect_attempts() {
+        let (mut client, server) = mock_client(|server| server, &[]);
+        client.reconnector.connector = Some(Connector::new().reconnect_interval(3, Duration::from_millis(1)));
+        server.join().unwrap().unwrap();
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        client.set_rebuild_stream(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err(Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)))
+        });
+        let reconnected = Arc::new(AtomicU32::new(0));
+        let notified = reconnected.clone();
+        client.on_reconnect(move || {
+            notified.fetch_add(1, Ordering::SeqCst);
+        });
+
+        match client.read(|_| panic!("no event expected")) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused),
+            _ => panic!("expected the error of the last attempt"),
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(reconnected.load(Ordering::SeqCst), 0);
+    }
 }
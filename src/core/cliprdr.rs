@@ -0,0 +1,232 @@
+use std::io::{Read, Write};
+
+use num_enum::TryFromPrimitive;
+
+use crate::core::channel::StaticChannel;
+use crate::core::event::{ClipboardEvent, RdpEvent};
+use crate::core::{mcs, tpkt};
+use crate::model::data::{to_vec, Array, Component, DataType, DynOption, Message, MessageOption, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::unicode::Unicode;
+
+/// Name of the clipboard static virtual channel
+pub const CLIPRDR_CHANNEL_NAME: &str = "cliprdr";
+
+/// Clipboard PDU type
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpeclip/08b3ff63-4f00-4bfc-9a8c-ba2b1d7c3ef4
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum MessageType {
+    MonitorReady = 0x0001,
+    FormatList = 0x0002,
+    FormatListResponse = 0x0003,
+    FormatDataRequest = 0x0004,
+    FormatDataResponse = 0x0005,
+    TempDirectory = 0x0006,
+    ClipCaps = 0x0007,
+    FileContentsRequest = 0x0008,
+    FileContentsResponse = 0x0009,
+    LockClipdata = 0x000A,
+    UnlockClipdata = 0x000B,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+enum MessageFlag {
+    ResponseOk = 0x0001,
+    ResponseFail = 0x0002,
+    AsciiNames = 0x0004,
+}
+
+/// Standard clipboard formats
+/// https://docs.microsoft.com/en-us/windows/win32/dataxchg/standard-clipboard-formats
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+enum ClipboardFormat {
+    Text = 1,
+    UnicodeText = 13,
+}
+
+/// Header of all clipboard PDU
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpeclip/08b3ff63-4f00-4bfc-9a8c-ba2b1d7c3ef4
+fn cliprdr_header(msg_type: Option<MessageType>, msg_flags: Option<u16>, data: Option<Vec<u8>>) -> Component {
+    let data = data.unwrap_or_default();
+    component![
+        "msgType" => U16::LE(msg_type.unwrap_or(MessageType::MonitorReady) as u16),
+        "msgFlags" => U16::LE(msg_flags.unwrap_or(0)),
+        "dataLen" => DynOption::new(U32::LE(data.len() as u32), |length| MessageOption::Size("data".to_string(), length.inner() as usize)),
+        "data" => data
+    ]
+}
+
+/// Clipboard general capability
+/// We only support short format names
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpeclip/5bb87e82-9fa4-4c4b-8d0b-86a9e1ec3dd5
+fn clipboard_capabilities() -> Component {
+    component![
+        "cCapabilitiesSets" => U16::LE(1),
+        "pad1" => U16::LE(0),
+        "capabilitySetType" => U16::LE(1),
+        "lengthCapability" => U16::LE(12),
+        "version" => U32::LE(2),
+        "generalFlags" => U32::LE(0)
+    ]
+}
+
+/// Short format name used into the format list
+/// Name is empty for standard formats
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpeclip/7a6ba3ff-1ab4-4ff7-a3d4-c45cfa9ff2d1
+fn short_format_name(format_id: Option<u32>) -> Component {
+    component![
+        "formatId" => U32::LE(format_id.unwrap_or(0)),
+        "formatName" => vec![0_u8; 32]
+    ]
+}
+
+/// Clipboard redirection channel
+///
+/// Only unicode text is exchanged with the server
+#[derive(Debug)]
+pub struct ClipboardChannel {
+    /// Underlying static virtual channel
+    channel: StaticChannel,
+    /// Text to share with the server
+    local_text: Option<String>,
+    /// True once the server sent the monitor ready PDU
+    ready: bool,
+}
+
+impl ClipboardChannel {
+    /// Create a new clipboard channel
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        ClipboardChannel { channel: StaticChannel::new(CLIPRDR_CHANNEL_NAME), local_text: None, ready: false }
+    }
+
+    /// Write a clipboard PDU
+    fn write_pdu<S: Read + Write>(
+        &self, msg_type: MessageType, msg_flags: u16, data: Vec<u8>, mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        self.channel.write(&to_vec(&cliprdr_header(Some(msg_type), Some(msg_flags), Some(data))), mcs)
+    }
+
+    /// Announce to the server the formats available locally
+    fn write_format_list<S: Read + Write>(&self, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let data = if self.local_text.is_some() {
+            to_vec(&short_format_name(Some(ClipboardFormat::UnicodeText as u32)))
+        } else {
+            Vec::new()
+        };
+        self.write_pdu(MessageType::FormatList, 0, data, mcs)
+    }
+
+    /// Send the local text when server ask for it
+    fn write_format_data_response<S: Read + Write>(&self, data: &[u8], mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let mut format_id = U32::LE(0);
+        format_id.read_from_bytes(data)?;
+
+        match (&self.local_text, format_id.inner()) {
+            (Some(text), id) if id == ClipboardFormat::UnicodeText as u32 => {
+                let mut response = text.to_utf16_le();
+                response.extend_from_slice(&[0, 0]);
+                self.write_pdu(MessageType::FormatDataResponse, MessageFlag::ResponseOk as u16, response, mcs)
+            }
+            _ => self.write_pdu(MessageType::FormatDataResponse, MessageFlag::ResponseFail as u16, Vec::new(), mcs),
+        }
+    }
+
+    /// Read a clipboard PDU
+    /// Text copied on the server side is sent to the callback
+    pub fn read<S: Read + Write, T>(
+        &mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>, mut callback: T,
+    ) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let message = match self.channel.read(payload)? {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+
+        let mut pdu = cliprdr_header(None, None, None);
+        pdu.read_from_bytes(&message)?;
+        let data = cast!(DataType::Slice, pdu["data"])?;
+
+        match MessageType::try_from(cast!(DataType::U16, pdu["msgType"])?)? {
+            MessageType::MonitorReady => {
+                self.ready = true;
+                self.write_pdu(MessageType::ClipCaps, 0, to_vec(&clipboard_capabilities()), mcs)?;
+                self.write_format_list(mcs)?;
+            }
+            MessageType::FormatList => {
+                self.write_pdu(MessageType::FormatListResponse, MessageFlag::ResponseOk as u16, Vec::new(), mcs)?;
+
+                let mut formats = Array::new(|| short_format_name(None));
+                formats.read_from_bytes(data)?;
+                for format in formats.inner() {
+                    let format_id = cast!(DataType::U32, cast!(DataType::Component, format)?["formatId"])?;
+                    if format_id == ClipboardFormat::UnicodeText as u32 {
+                        self.write_pdu(MessageType::FormatDataRequest, 0, to_vec(&U32::LE(format_id)), mcs)?;
+                        break;
+                    }
+                }
+            }
+            MessageType::FormatDataRequest => self.write_format_data_response(data, mcs)?,
+            MessageType::FormatDataResponse => {
+                if cast!(DataType::U16, pdu["msgFlags"])? & MessageFlag::ResponseOk as u16 != 0 {
+                    let text: Vec<u16> = data
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .take_while(|c| *c != 0)
+                        .collect();
+                    callback(RdpEvent::Clipboard(ClipboardEvent::Text(String::from_utf16_lossy(&text))));
+                }
+            }
+            // capabilities only announce long format names support
+            MessageType::ClipCaps | MessageType::FormatListResponse => (),
+            msg_type => log_debug!("CLIPRDR: message not handled {:?}", msg_type),
+        }
+        Ok(())
+    }
+
+    /// Share a text with the server clipboard
+    pub fn send_text<S: Read + Write>(&mut self, text: &str, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        self.local_text = Some(text.to_string());
+        if !self.ready {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "CLIPRDR: Clipboard channel is not ready",
+            )));
+        }
+        self.write_format_list(mcs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cliprdr_header() {
+        assert_eq!(
+            to_vec(&cliprdr_header(Some(MessageType::FormatDataRequest), None, Some(vec![13, 0, 0, 0]))),
+            [4, 0, 0, 0, 4, 0, 0, 0, 13, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_read_format_list() {
+        let mut pdu = cliprdr_header(None, None, None);
+        let mut stream = vec![2, 0, 0, 0, 36, 0, 0, 0, 13, 0, 0, 0];
+        stream.extend_from_slice(&[0; 32]);
+        pdu.read_from_bytes(&stream).unwrap();
+        let mut formats = Array::new(|| short_format_name(None));
+        formats.read_from_bytes(cast!(DataType::Slice, pdu["data"]).unwrap()).unwrap();
+        assert_eq!(formats.inner().len(), 1);
+    }
+}
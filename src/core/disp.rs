@@ -0,0 +1,201 @@
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::dvc::DvcHandler;
+use crate::model::data::{to_vec, Message, Trame, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Name of the display control dynamic channel
+pub const DISP_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::DisplayControl";
+
+/// Bounds of a monitor size
+/// See MS-RDPEDISP 2.2.2.2.1
+const MIN_MONITOR_SIZE: u16 = 200;
+const MAX_MONITOR_SIZE: u16 = 8192;
+
+/// Size of a monitor definition
+const MONITOR_LAYOUT_SIZE: u32 = 40;
+
+/// Flag of the primary monitor
+const DISPLAYCONTROL_MONITOR_PRIMARY: u32 = 0x0000_0001;
+
+/// Display control PDU types
+/// See MS-RDPEDISP 2.2.1.1
+#[repr(u32)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum PduType {
+    MonitorLayout = 0x0000_0002,
+    Caps = 0x0000_0005,
+}
+
+fn disp_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("DISP: {}", message)))
+}
+
+/// Limits announced by the server
+/// See MS-RDPEDISP 2.2.2.1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DisplayCaps {
+    pub max_num_monitors: u32,
+    pub max_monitor_area_factor_a: u32,
+    pub max_monitor_area_factor_b: u32,
+}
+
+impl DisplayCaps {
+    /// Largest desktop area accepted by the server
+    fn max_area(&self) -> u64 {
+        u64::from(self.max_num_monitors)
+            * u64::from(self.max_monitor_area_factor_a)
+            * u64::from(self.max_monitor_area_factor_b)
+    }
+}
+
+/// Check the size of a monitor
+/// The width must be even and both dimensions between 200 and 8192
+fn check_size(width: u16, height: u16) -> RdpResult<()> {
+    if !width.is_multiple_of(2)
+        || !(MIN_MONITOR_SIZE..=MAX_MONITOR_SIZE).contains(&width)
+        || !(MIN_MONITOR_SIZE..=MAX_MONITOR_SIZE).contains(&height)
+    {
+        return Err(disp_error(RdpErrorKind::InvalidSize, &format!("Invalid desktop size {}x{}", width, height)));
+    }
+    Ok(())
+}
+
+/// Monitor layout PDU with a single primary monitor
+///
+/// See MS-RDPEDISP 2.2.2.2
+///
+/// # Example
+/// ```
+/// use rdp::core::disp::monitor_layout_pdu;
+/// let pdu = monitor_layout_pdu(1024, 768).unwrap();
+/// assert_eq!(pdu.len(), 56);
+/// assert!(monitor_layout_pdu(1023, 768).is_err());
+/// ```
+pub fn monitor_layout_pdu(width: u16, height: u16) -> RdpResult<Vec<u8>> {
+    check_size(width, height)?;
+    let body = trame![
+        U32::LE(MONITOR_LAYOUT_SIZE),
+        U32::LE(1),
+        U32::LE(DISPLAYCONTROL_MONITOR_PRIMARY),
+        // left and top
+        U32::LE(0),
+        U32::LE(0),
+        U32::LE(u32::from(width)),
+        U32::LE(u32::from(height)),
+        // physical size, orientation and scale factors are unknown
+        U32::LE(0),
+        U32::LE(0),
+        U32::LE(0),
+        U32::LE(100),
+        U32::LE(100)
+    ];
+    Ok(to_vec(&trame![U32::LE(PduType::MonitorLayout as u32), U32::LE(8 + body.length() as u32), body]))
+}
+
+/// Display control channel
+/// Used to change the desktop size during the session
+///
+/// Clones share the same state
+#[derive(Clone, Default)]
+pub struct DisplayChannel {
+    caps: Arc<Mutex<Option<DisplayCaps>>>,
+}
+
+impl std::fmt::Debug for DisplayChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DisplayChannel").finish_non_exhaustive()
+    }
+}
+
+impl DisplayChannel {
+    pub fn new() -> Self { Self::default() }
+
+    fn lock(&self) -> RdpResult<MutexGuard<'_, Option<DisplayCaps>>> {
+        self.caps.lock().map_err(|_| disp_error(RdpErrorKind::Unknown, "Poisoned state"))
+    }
+
+    /// Limits announced by the server
+    /// None until the server opened the channel
+    pub fn caps(&self) -> RdpResult<Option<DisplayCaps>> { Ok(*self.lock()?) }
+
+    /// Monitor layout PDU requesting a new desktop size
+    /// Fails if the server is not ready or refuses such a size
+    pub fn resize_pdu(&self, width: u16, height: u16) -> RdpResult<Vec<u8>> {
+        let caps = self
+            .caps()?
+            .ok_or_else(|| disp_error(RdpErrorKind::InvalidAutomata, "Display control channel not opened"))?;
+        if u64::from(width) * u64::from(height) > caps.max_area() {
+            return Err(disp_error(RdpErrorKind::InvalidSize, "Desktop size exceeds the server limits"));
+        }
+        monitor_layout_pdu(width, height)
+    }
+}
+
+impl DvcHandler for DisplayChannel {
+    fn on_data(&mut self, _channel_id: u32, data: &[u8]) -> RdpResult<()> {
+        let mut stream = Cursor::new(data);
+        let pdu_type = stream.read_u32::<LittleEndian>()?;
+        let _length = stream.read_u32::<LittleEndian>()?;
+        match PduType::try_from(pdu_type) {
+            Ok(PduType::Caps) => {
+                *self.lock()? = Some(DisplayCaps {
+                    max_num_monitors: stream.read_u32::<LittleEndian>()?,
+                    max_monitor_area_factor_a: stream.read_u32::<LittleEndian>()?,
+                    max_monitor_area_factor_b: stream.read_u32::<LittleEndian>()?,
+                });
+            }
+            _ => log_debug!("DISP: Ignore PDU type {:#x}", pdu_type),
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, _channel_id: u32) {
+        if let Ok(mut caps) = self.lock() {
+            *caps = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn caps_pdu(max_num_monitors: u32, factor_a: u32, factor_b: u32) -> Vec<u8> {
+        to_vec(&trame![
+            U32::LE(PduType::Caps as u32),
+            U32::LE(20),
+            U32::LE(max_num_monitors),
+            U32::LE(factor_a),
+            U32::LE(factor_b)
+        ])
+    }
+
+    #[test]
+    fn test_monitor_layout_pdu() {
+        let pdu = monitor_layout_pdu(1280, 1024).unwrap();
+        assert_eq!(pdu[0..12], [2, 0, 0, 0, 56, 0, 0, 0, 40, 0, 0, 0]);
+        assert_eq!(pdu[28..36], [0, 5, 0, 0, 0, 4, 0, 0]);
+        assert!(monitor_layout_pdu(100, 768).is_err());
+        assert!(monitor_layout_pdu(1024, 9000).is_err());
+    }
+
+    #[test]
+    fn test_resize_needs_caps() {
+        let mut channel = DisplayChannel::new();
+        assert!(channel.resize_pdu(1024, 768).is_err());
+        channel.on_data(1, &caps_pdu(1, 1024, 768)).unwrap();
+        assert_eq!(
+            channel.caps().unwrap(),
+            Some(DisplayCaps { max_num_monitors: 1, max_monitor_area_factor_a: 1024, max_monitor_area_factor_b: 768 })
+        );
+        assert!(channel.resize_pdu(1024, 768).is_ok());
+        assert!(channel.resize_pdu(1280, 1024).is_err());
+        channel.on_close(1);
+        assert!(channel.resize_pdu(1024, 768).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::channel::{StaticChannel, CHANNEL_CHUNK_LENGTH};
+use crate::core::{mcs, tpkt};
+use crate::model::data::{to_vec, Trame, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Name of the dynamic virtual channel static channel
+pub const DRDYNVC_CHANNEL_NAME: &str = "drdynvc";
+
+/// Status send when no handler is registered for a channel
+const STATUS_UNSUCCESSFUL: u32 = 0xC000_0001;
+
+/// Highest version of the protocol supported
+/// Version 3 need compression which is not supported
+const MAX_DVC_VERSION: u16 = 2;
+
+/// Command of a dynamic virtual channel PDU
+/// See MS-RDPEDYC 2.2
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum Command {
+    Create = 0x01,
+    DataFirst = 0x02,
+    Data = 0x03,
+    Close = 0x04,
+    Capability = 0x05,
+    DataFirstCompressed = 0x06,
+    DataCompressed = 0x07,
+    SoftSyncRequest = 0x08,
+    SoftSyncResponse = 0x09,
+}
+
+/// Handler of a dynamic virtual channel
+///
+/// Handlers are registered by name on the `DvcManager`
+/// and called each time the server send a complete message
+pub trait DvcHandler {
+    /// Called when the server opened the channel
+    fn on_open(&mut self, _channel_id: u32) -> RdpResult<()> { Ok(()) }
+
+    /// Called for each message received on the channel
+    fn on_data(&mut self, channel_id: u32, data: &[u8]) -> RdpResult<()>;
+
+    /// Called when the server closed the channel
+    fn on_close(&mut self, _channel_id: u32) {}
+
+    /// Messages to send to the server
+    /// Called after each call to the handler
+    fn take_output(&mut self) -> Vec<Vec<u8>> { Vec::new() }
+}
+
+/// Code used into the header to encode
+/// the size of a variable length field
+fn var_size(value: u32) -> u8 {
+    if value <= 0xff {
+        0
+    } else if value <= 0xffff {
+        1
+    } else {
+        2
+    }
+}
+
+/// Variable length field
+fn var_uint(value: u32) -> Vec<u8> {
+    match var_size(value) {
+        0 => vec![value as u8],
+        1 => to_vec(&U16::LE(value as u16)),
+        _ => to_vec(&U32::LE(value)),
+    }
+}
+
+fn read_var_uint(size: u8, stream: &mut dyn Read) -> RdpResult<u32> {
+    match size {
+        0 => Ok(u32::from(stream.read_u8()?)),
+        1 => Ok(u32::from(stream.read_u16::<LittleEndian>()?)),
+        2 => Ok(stream.read_u32::<LittleEndian>()?),
+        _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "DVC: Invalid field size"))),
+    }
+}
+
+/// Header of all dynamic virtual channel PDU
+/// See MS-RDPEDYC 2.2
+fn dvc_header(command: Command, sp: u8, cb_id: u8) -> u8 { (command as u8) << 4 | (sp & 0x3) << 2 | (cb_id & 0x3) }
+
+/// Write data to a dynamic channel
+/// splitting it into DataFirst and Data PDU if needed
+fn write_data<S: Read + Write>(
+    channel: &StaticChannel, channel_id: u32, data: &[u8], mcs: &mut mcs::Client<S>,
+) -> RdpResult<()> {
+    let cb_id = var_size(channel_id);
+    let id_length = var_uint(channel_id).len();
+    let max_data = CHANNEL_CHUNK_LENGTH - 1 - id_length;
+
+    if data.len() <= max_data {
+        return channel
+            .write(&to_vec(&trame![dvc_header(Command::Data, 0, cb_id), var_uint(channel_id), data.to_vec()]), mcs);
+    }
+
+    let total_length = data.len() as u32;
+    let first_length = max_data - var_uint(total_length).len();
+    channel.write(
+        &to_vec(&trame![
+            dvc_header(Command::DataFirst, var_size(total_length), cb_id),
+            var_uint(channel_id),
+            var_uint(total_length),
+            data[..first_length].to_vec()
+        ]),
+        mcs,
+    )?;
+    for chunk in data[first_length..].chunks(max_data) {
+        channel
+            .write(&to_vec(&trame![dvc_header(Command::Data, 0, cb_id), var_uint(channel_id), chunk.to_vec()]), mcs)?;
+    }
+    Ok(())
+}
+
+/// Dynamic virtual channel manager
+///
+/// Dynamic channels are opened by the server after connection
+/// through the drdynvc static channel (MS-RDPEDYC)
+pub struct DvcManager {
+    /// Underlying static virtual channel
+    channel: StaticChannel,
+    /// Handlers by channel name
+    handlers: HashMap<String, Box<dyn DvcHandler + Send>>,
+    /// Names of opened channels by id
+    channels: HashMap<u32, String>,
+    /// Expected length and data of fragmented messages
+    fragments: HashMap<u32, (usize, Vec<u8>)>,
+}
+
+impl std::fmt::Debug for DvcManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DvcManager")
+            .field("handlers", &self.handlers.keys().collect::<Vec<_>>())
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+impl DvcManager {
+    /// Create a new manager without any handler
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        DvcManager {
+            channel: StaticChannel::new(DRDYNVC_CHANNEL_NAME),
+            handlers: HashMap::new(),
+            channels: HashMap::new(),
+            fragments: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for a dynamic channel name
+    /// Channels without handler are refused
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::dvc::{DvcHandler, DvcManager};
+    /// use rdp::model::error::RdpResult;
+    /// struct Echo;
+    /// impl DvcHandler for Echo {
+    ///     fn on_data(&mut self, channel_id: u32, data: &[u8]) -> RdpResult<()> {
+    ///         println!("{} {:?}", channel_id, data);
+    ///         Ok(())
+    ///     }
+    /// }
+    /// let mut manager = DvcManager::new();
+    /// manager.register("ECHO", Echo);
+    /// ```
+    pub fn register<H: DvcHandler + Send + 'static>(&mut self, name: &str, handler: H) {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+    }
+
+    /// Send a message on an opened dynamic channel
+    pub fn write<S: Read + Write>(&self, name: &str, data: &[u8], mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let channel_id =
+            self.channels.iter().find(|(_, n)| n.as_str() == name).map(|(id, _)| *id).ok_or_else(|| {
+                Error::RdpError(RdpError::new(
+                    RdpErrorKind::InvalidAutomata,
+                    &format!("DVC: Channel {} is not opened", name),
+                ))
+            })?;
+        write_data(&self.channel, channel_id, data, mcs)
+    }
+
+    /// Read a PDU from the drdynvc static channel
+    pub fn read<S: Read + Write>(&mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let message = match self.channel.read(payload)? {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+
+        let mut stream = Cursor::new(message);
+        let header = stream.read_u8()?;
+        let cb_id = header & 0x3;
+        let sp = (header >> 2) & 0x3;
+
+        match Command::try_from(header >> 4)? {
+            Command::Capability => {
+                let _pad = stream.read_u8()?;
+                let version = stream.read_u16::<LittleEndian>()?;
+                self.channel.write(
+                    &to_vec(&trame![
+                        dvc_header(Command::Capability, 0, 0),
+                        0_u8,
+                        U16::LE(version.min(MAX_DVC_VERSION))
+                    ]),
+                    mcs,
+                )?;
+            }
+            Command::Create => {
+                let channel_id = read_var_uint(cb_id, &mut stream)?;
+                let mut name = Vec::new();
+                stream.read_to_end(&mut name)?;
+                let name = String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or_default()).to_string();
+
+                let status = if self.handlers.contains_key(&name) { 0 } else { STATUS_UNSUCCESSFUL };
+                self.channel.write(
+                    &to_vec(&trame![
+                        dvc_header(Command::Create, 0, var_size(channel_id)),
+                        var_uint(channel_id),
+                        U32::LE(status)
+                    ]),
+                    mcs,
+                )?;
+
+                if let Some(handler) = self.handlers.get_mut(&name) {
+                    self.channels.insert(channel_id, name);
+                    handler.on_open(channel_id)?;
+                    self.flush(channel_id, mcs)?;
+                }
+            }
+            Command::DataFirst => {
+                let channel_id = read_var_uint(cb_id, &mut stream)?;
+                let length = read_var_uint(sp, &mut stream)? as usize;
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                self.fragments.insert(channel_id, (length, data));
+                self.dispatch_fragment(channel_id, mcs)?;
+            }
+            Command::Data => {
+                let channel_id = read_var_uint(cb_id, &mut stream)?;
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                match self.fragments.get_mut(&channel_id) {
+                    Some((_, buffer)) => {
+                        buffer.extend(data);
+                        self.dispatch_fragment(channel_id, mcs)?;
+                    }
+                    None => self.dispatch(channel_id, &data, mcs)?,
+                }
+            }
+            Command::Close => {
+                let channel_id = read_var_uint(cb_id, &mut stream)?;
+                self.fragments.remove(&channel_id);
+                if let Some(handler) = self.channels.remove(&channel_id).and_then(|name| self.handlers.get_mut(&name)) {
+                    handler.on_close(channel_id);
+                }
+                self.channel.write(
+                    &to_vec(&trame![dvc_header(Command::Close, 0, var_size(channel_id)), var_uint(channel_id)]),
+                    mcs,
+                )?;
+            }
+            command => log_debug!("DVC: command not handled {:?}", command),
+        }
+        Ok(())
+    }
+
+    /// Dispatch a fragmented message once all fragments are received
+    fn dispatch_fragment<S: Read + Write>(&mut self, channel_id: u32, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        match self.fragments.get(&channel_id) {
+            Some((length, buffer)) if buffer.len() >= *length => {
+                if buffer.len() > *length {
+                    self.fragments.remove(&channel_id);
+                    return Err(Error::RdpError(RdpError::new(
+                        RdpErrorKind::InvalidSize,
+                        "DVC: Fragmented message is bigger than announced",
+                    )));
+                }
+                let (_, data) = self.fragments.remove(&channel_id).unwrap_or_default();
+                self.dispatch(channel_id, &data, mcs)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Call the handler of a channel with a complete message
+    fn dispatch<S: Read + Write>(&mut self, channel_id: u32, data: &[u8], mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        match self.channels.get(&channel_id).and_then(|name| self.handlers.get_mut(name)) {
+            Some(handler) => handler.on_data(channel_id, data)?,
+            None => log_debug!("DVC: data received on unknown channel {}", channel_id),
+        }
+        self.flush(channel_id, mcs)
+    }
+
+    /// Send messages produced by the handler of a channel
+    fn flush<S: Read + Write>(&mut self, channel_id: u32, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        let output = match self.channels.get(&channel_id).and_then(|name| self.handlers.get_mut(name)) {
+            Some(handler) => handler.take_output(),
+            None => return Ok(()),
+        };
+        for data in output {
+            write_data(&self.channel, channel_id, &data, mcs)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dvc_header() {
+        assert_eq!(dvc_header(Command::DataFirst, 1, 2), 0x26);
+        assert_eq!(dvc_header(Command::Capability, 0, 0), 0x50);
+    }
+
+    #[test]
+    fn test_var_uint() {
+        assert_eq!(var_uint(0x12), [0x12]);
+        assert_eq!(var_uint(0x1234), [0x34, 0x12]);
+        assert_eq!(var_uint(0x12345), [0x45, 0x23, 0x01, 0x00]);
+        assert_eq!(read_var_uint(1, &mut Cursor::new(vec![0x34, 0x12])).unwrap(), 0x1234);
+        assert!(read_var_uint(3, &mut Cursor::new(vec![0; 4])).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// Error info codes sent by the server
+/// before closing the session
+///
+/// See MS-RDPBCGR 2.2.5.1.1
+#[repr(u32)]
+#[derive(Debug, FromPrimitive, IntoPrimitive, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorInfoCode {
+    None = 0x0000_0000,
+    RpcInitiatedDisconnect = 0x0000_0001,
+    RpcInitiatedLogoff = 0x0000_0002,
+    IdleTimeout = 0x0000_0003,
+    LogonTimeout = 0x0000_0004,
+    DisconnectedByOtherConnection = 0x0000_0005,
+    OutOfMemory = 0x0000_0006,
+    ServerDeniedConnection = 0x0000_0007,
+    ServerInsufficientPrivileges = 0x0000_0009,
+    ServerFreshCredentialsRequired = 0x0000_000A,
+    RpcInitiatedDisconnectByUser = 0x0000_000B,
+    LogoffByUser = 0x0000_000C,
+    CloseStackOnDriverNotReady = 0x0000_000F,
+    ServerDwmCrash = 0x0000_0010,
+    CloseStackOnDriverFailure = 0x0000_0011,
+    CloseStackOnDriverIfaceFailure = 0x0000_0012,
+    ServerWinlogonCrash = 0x0000_0017,
+    ServerCsrssCrash = 0x0000_0018,
+    ServerShutdown = 0x0000_0019,
+    ServerReboot = 0x0000_001A,
+    LicenseInternal = 0x0000_0100,
+    LicenseNoLicenseServer = 0x0000_0101,
+    LicenseNoLicense = 0x0000_0102,
+    LicenseBadClientMsg = 0x0000_0103,
+    LicenseHwidDoesntMatchLicense = 0x0000_0104,
+    LicenseBadClientLicense = 0x0000_0105,
+    LicenseCantFinishProtocol = 0x0000_0106,
+    LicenseClientEndedProtocol = 0x0000_0107,
+    LicenseBadClientEncryption = 0x0000_0108,
+    LicenseCantUpgradeLicense = 0x0000_0109,
+    LicenseNoRemoteConnections = 0x0000_010A,
+    CbDestinationNotFound = 0x0000_0400,
+    CbLoadingDestination = 0x0000_0402,
+    CbRedirectingToDestination = 0x0000_0404,
+    CbSessionOnlineVmWake = 0x0000_0405,
+    CbSessionOnlineVmBoot = 0x0000_0406,
+    CbSessionOnlineVmNoDns = 0x0000_0407,
+    CbDestinationPoolNotFree = 0x0000_0408,
+    CbConnectionCancelled = 0x0000_0409,
+    CbConnectionErrorInvalidSettings = 0x0000_0410,
+    CbSessionOnlineVmBootTimeout = 0x0000_0411,
+    CbSessionOnlineVmSessmonFailed = 0x0000_0412,
+    UnknownPduType2 = 0x0000_10C9,
+    UnknownPduType = 0x0000_10CA,
+    DataPduSequence = 0x0000_10CB,
+    ControlPduSequence = 0x0000_10CD,
+    InvalidControlPduAction = 0x0000_10CE,
+    InvalidInputPduType = 0x0000_10CF,
+    InvalidInputPduMouse = 0x0000_10D0,
+    InvalidRefreshRectPdu = 0x0000_10D1,
+    CreateUserDataFailed = 0x0000_10D2,
+    ConnectFailed = 0x0000_10D3,
+    ConfirmActiveWrongShareId = 0x0000_10D4,
+    ConfirmActiveWrongOriginator = 0x0000_10D5,
+    PersistentKeyPduBadLength = 0x0000_10DA,
+    PersistentKeyPduIllegalFirst = 0x0000_10DB,
+    PersistentKeyPduTooManyTotalKeys = 0x0000_10DC,
+    PersistentKeyPduTooManyCacheKeys = 0x0000_10DD,
+    InputPduBadLength = 0x0000_10DE,
+    BitmapCacheErrorPduBadLength = 0x0000_10DF,
+    SecurityDataTooShort = 0x0000_10E0,
+    VChannelDataTooShort = 0x0000_10E1,
+    ShareDataTooShort = 0x0000_10E2,
+    BadSuppressOutputPdu = 0x0000_10E3,
+    ConfirmActivePduTooShort = 0x0000_10E5,
+    CapabilitySetTooSmall = 0x0000_10E7,
+    CapabilitySetTooLarge = 0x0000_10E8,
+    NoCursorCache = 0x0000_10E9,
+    BadCapabilities = 0x0000_10EA,
+    VirtualChannelDecompressionErr = 0x0000_10EC,
+    InvalidVcCompressionType = 0x0000_10ED,
+    InvalidChannelId = 0x0000_10EF,
+    VChannelsTooMany = 0x0000_10F0,
+    RemoteAppsNotEnabled = 0x0000_10F3,
+    CacheCapNotSet = 0x0000_10F4,
+    BitmapCacheErrorPduBadLength2 = 0x0000_10F5,
+    OffscrCacheErrorPduBadLength = 0x0000_10F6,
+    DngCacheErrorPduBadLength = 0x0000_10F7,
+    GdiplusPduBadLength = 0x0000_10F8,
+    SecurityDataTooShort2 = 0x0000_1111,
+    SecurityDataTooShort3 = 0x0000_1112,
+    SecurityDataTooShort4 = 0x0000_1113,
+    SecurityDataTooShort5 = 0x0000_1114,
+    SecurityDataTooShort6 = 0x0000_1115,
+    SecurityDataTooShort7 = 0x0000_1116,
+    SecurityDataTooShort8 = 0x0000_1117,
+    SecurityDataTooShort9 = 0x0000_1118,
+    SecurityDataTooShort10 = 0x0000_1119,
+    SecurityDataTooShort11 = 0x0000_111A,
+    SecurityDataTooShort12 = 0x0000_111B,
+    SecurityDataTooShort13 = 0x0000_111C,
+    SecurityDataTooShort14 = 0x0000_111D,
+    SecurityDataTooShort15 = 0x0000_111E,
+    SecurityDataTooShort16 = 0x0000_111F,
+    SecurityDataTooShort17 = 0x0000_1120,
+    SecurityDataTooShort18 = 0x0000_1121,
+    SecurityDataTooShort19 = 0x0000_1122,
+    SecurityDataTooShort20 = 0x0000_1123,
+    SecurityDataTooShort21 = 0x0000_1124,
+    SecurityDataTooShort22 = 0x0000_1125,
+    SecurityDataTooShort23 = 0x0000_1126,
+    BadMonitorData = 0x0000_1129,
+    VcDecompressedReassembleFailed = 0x0000_112A,
+    VcDataTooLong = 0x0000_112B,
+    BadFrameAckData = 0x0000_112C,
+    GraphicsModeNotSupported = 0x0000_112D,
+    GraphicsSubsystemResetFailed = 0x0000_112E,
+    GraphicsSubsystemFailed = 0x0000_112F,
+    TimezoneKeyNameLengthTooShort = 0x0000_1130,
+    TimezoneKeyNameLengthTooLong = 0x0000_1131,
+    DynamicDstDisabledFieldMissing = 0x0000_1132,
+    VcDecodingError = 0x0000_1133,
+    VirtualDesktopTooLarge = 0x0000_1134,
+    MonitorGeometryValidationFailed = 0x0000_1135,
+    InvalidMonitorCount = 0x0000_1136,
+    UpdateSessionKeyFailed = 0x0000_1191,
+    DecryptFailed = 0x0000_1192,
+    EncryptFailed = 0x0000_1193,
+    EncPkgMismatch = 0x0000_1194,
+    DecryptFailed2 = 0x0000_1195,
+    /// Any code not defined by the specification
+    #[num_enum(catch_all)]
+    Unknown(u32),
+}
+
+impl ErrorInfoCode {
+    /// Code sent by the server
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::error_info::ErrorInfoCode;
+    /// assert_eq!(ErrorInfoCode::from(0x0000_0004), ErrorInfoCode::LogonTimeout);
+    /// assert_eq!(ErrorInfoCode::LogonTimeout.code(), 4);
+    /// assert_eq!(ErrorInfoCode::from(0x0000_2000).code(), 0x2000);
+    /// ```
+    pub fn code(&self) -> u32 { u32::from(*self) }
+
+    /// Human readable description
+    /// Protocol errors of the server give a generic message
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorInfoCode::None => "No error",
+            ErrorInfoCode::RpcInitiatedDisconnect => "The session was disconnected by an administrative tool",
+            ErrorInfoCode::RpcInitiatedLogoff => "The session was logged off by an administrative tool",
+            ErrorInfoCode::IdleTimeout => "The idle session limit timer expired",
+            ErrorInfoCode::LogonTimeout => "The active session limit timer expired",
+            ErrorInfoCode::DisconnectedByOtherConnection => "Another user connected to the session",
+            ErrorInfoCode::OutOfMemory => "The server ran out of memory",
+            ErrorInfoCode::ServerDeniedConnection => "The server denied the connection",
+            ErrorInfoCode::ServerInsufficientPrivileges => "The user doesn't have the privilege to log on",
+            ErrorInfoCode::ServerFreshCredentialsRequired => "The server requires the credentials to be entered",
+            ErrorInfoCode::RpcInitiatedDisconnectByUser => "The user disconnected the session from the server",
+            ErrorInfoCode::LogoffByUser => "The user logged off",
+            ErrorInfoCode::CloseStackOnDriverNotReady
+            | ErrorInfoCode::CloseStackOnDriverFailure
+            | ErrorInfoCode::CloseStackOnDriverIfaceFailure => "The display driver of the server failed",
+            ErrorInfoCode::ServerDwmCrash => "The window manager of the server crashed",
+            ErrorInfoCode::ServerWinlogonCrash => "The logon process of the server crashed",
+            ErrorInfoCode::ServerCsrssCrash => "The client server runtime process of the server crashed",
+            ErrorInfoCode::ServerShutdown => "The server is shutting down",
+            ErrorInfoCode::ServerReboot => "The server is rebooting",
+            ErrorInfoCode::LicenseInternal => "Internal error of the licensing protocol",
+            ErrorInfoCode::LicenseNoLicenseServer => "No license server is available",
+            ErrorInfoCode::LicenseNoLicense => "No client access license is available",
+            ErrorInfoCode::LicenseNoRemoteConnections => "The server doesn't accept remote connections",
+            ErrorInfoCode::LicenseBadClientMsg
+            | ErrorInfoCode::LicenseHwidDoesntMatchLicense
+            | ErrorInfoCode::LicenseBadClientLicense
+            | ErrorInfoCode::LicenseCantFinishProtocol
+            | ErrorInfoCode::LicenseClientEndedProtocol
+            | ErrorInfoCode::LicenseBadClientEncryption
+            | ErrorInfoCode::LicenseCantUpgradeLicense => "The license of the client was rejected",
+            ErrorInfoCode::CbDestinationNotFound => "The connection broker found no target session",
+            ErrorInfoCode::CbLoadingDestination
+            | ErrorInfoCode::CbRedirectingToDestination
+            | ErrorInfoCode::CbSessionOnlineVmWake
+            | ErrorInfoCode::CbSessionOnlineVmBoot => "The connection broker is preparing the target session",
+            ErrorInfoCode::CbSessionOnlineVmNoDns
+            | ErrorInfoCode::CbDestinationPoolNotFree
+            | ErrorInfoCode::CbConnectionCancelled
+            | ErrorInfoCode::CbConnectionErrorInvalidSettings
+            | ErrorInfoCode::CbSessionOnlineVmBootTimeout
+            | ErrorInfoCode::CbSessionOnlineVmSessmonFailed => {
+                "The connection broker failed to reach the target session"
+            }
+            ErrorInfoCode::Unknown(_) => "Unknown error",
+            _ => "The server rejected a PDU of the client",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorInfoCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({:?}, 0x{:08x})", self.description(), self, self.code())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_info_code() {
+        for code in [0x0000_0005, 0x0000_0104, 0x0000_0412, 0x0000_10c9, 0x0000_1195] {
+            assert_eq!(ErrorInfoCode::from(code).code(), code);
+        }
+        assert_eq!(ErrorInfoCode::from(0x0000_0005), ErrorInfoCode::DisconnectedByOtherConnection);
+        assert_eq!(ErrorInfoCode::from(0x0000_0008), ErrorInfoCode::Unknown(8));
+        assert_eq!(
+            ErrorInfoCode::IdleTimeout.to_string(),
+            "The idle session limit timer expired (IdleTimeout, 0x00000003)"
+        );
+    }
+}
@@ -1,15 +1,124 @@
+#[cfg(feature = "image")]
+use std::path::Path;
+use std::sync::Arc;
+
 use num_enum::TryFromPrimitive;
 
-use crate::codec::rle::{rgb565torgb32, rle_16_decompress, rle_32_decompress};
+use crate::codec::jpeg::jpeg_decompress;
+use crate::codec::nscodec::nscodec_decompress;
+use crate::codec::rfx::rfx_decompress;
+use crate::codec::rle::{
+    palette_to_rgb32, rgb24torgb32, rgb555torgb32, rgb565torgb32, rle_16_decompress, rle_24_decompress,
+    rle_32_decompress, rle_8_decompress, Palette,
+};
+use crate::core::capability::BitmapCodec;
+use crate::core::error_info::ErrorInfoCode;
+use crate::core::gcc::MonitorLayout;
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 
+/// Rectangle with inclusive bounds
+/// as used by the bitmap updates
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl Rect {
+    /// Number of columns, 0 if right is before left
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::event::Rect;
+    /// assert_eq!(Rect { left: 10, top: 0, right: 19, bottom: 0 }.width(), 10);
+    /// ```
+    pub fn width(&self) -> u16 {
+        if self.right < self.left {
+            0
+        } else {
+            (self.right - self.left).saturating_add(1)
+        }
+    }
+
+    /// Number of rows, 0 if bottom is above top
+    pub fn height(&self) -> u16 {
+        if self.bottom < self.top {
+            0
+        } else {
+            (self.bottom - self.top).saturating_add(1)
+        }
+    }
+
+    /// Check if a pixel is inside the rectangle
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.left && x <= self.right && y >= self.top && y <= self.bottom
+    }
+
+    /// Check if another rectangle is entirely inside this one
+    /// An empty rectangle is never contained
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.width() > 0
+            && other.height() > 0
+            && self.contains(other.left, other.top)
+            && self.contains(other.right, other.bottom)
+    }
+}
+
+/// Bitmap data as a base64 string
+#[cfg(feature = "serde")]
+mod serde_base64 {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Palette as a list of 256 colors
+/// serde doesn't handle arrays of this size
+#[cfg(feature = "serde")]
+mod serde_palette {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::codec::rle::Palette;
+
+    pub fn serialize<S: Serializer>(palette: &Option<Arc<Palette>>, serializer: S) -> Result<S::Ok, S::Error> {
+        palette.as_ref().map(|palette| palette.as_slice()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Arc<Palette>>, D::Error> {
+        match Option::<Vec<[u8; 3]>>::deserialize(deserializer)? {
+            Some(colors) => {
+                let palette: Palette = colors
+                    .try_into()
+                    .map_err(|colors: Vec<[u8; 3]>| serde::de::Error::invalid_length(colors.len(), &"256 colors"))?;
+                Ok(Some(Arc::new(palette)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
 /// A bitmap event is used
 /// to notify client that it received
 /// an old school bitmap data
 ///
 /// If bitmap is compress you can use the
 /// decompress function to handle it
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BitmapEvent {
     /// Pixel position from left of the left top angle
     pub dest_left: u16,
@@ -29,11 +138,37 @@ pub struct BitmapEvent {
     pub bpp: u16,
     /// true if bitmap buffer is compressed using RLE
     pub is_compress: bool,
+    /// Codec of the bitmap, negotiated by the bitmap codecs capability
+    /// None for legacy RLE or raw bitmap
+    pub codec: Option<BitmapCodec>,
+    /// Index of the monitor which contains the top left corner
+    /// None for a single screen session
+    pub monitor: Option<usize>,
+    /// Colors of an 8 bpp bitmap
+    /// Last palette sent by the server
+    #[cfg_attr(feature = "serde", serde(with = "serde_palette"))]
+    pub palette: Option<Arc<Palette>>,
     /// Bitmap data
+    /// Serialized as a base64 string
+    #[cfg_attr(feature = "serde", serde(with = "serde_base64"))]
     pub data: Vec<u8>,
 }
 
 impl BitmapEvent {
+    /// Area of the screen covered by the bitmap
+    pub fn dest_rect(&self) -> Rect {
+        Rect { left: self.dest_left, top: self.dest_top, right: self.dest_right, bottom: self.dest_bottom }
+    }
+
+    /// Area of the decompressed bitmap buffer
+    pub fn src_rect(&self) -> Rect {
+        match (self.width.checked_sub(1), self.height.checked_sub(1)) {
+            (Some(right), Some(bottom)) => Rect { left: 0, top: 0, right, bottom },
+            // Empty bitmap
+            _ => Rect { left: 1, top: 1, right: 0, bottom: 0 },
+        }
+    }
+
     /// Decompress a bitmap which has been encoded by the RLE algorithm
     ///
     /// # Example
@@ -62,6 +197,25 @@ impl BitmapEvent {
     /// }).unwrap()
     /// ```
     pub fn decompress(self) -> RdpResult<Vec<u8>> {
+        match self.codec {
+            Some(BitmapCodec::NsCodec) => {
+                let mut result = vec![0_u8; self.width as usize * self.height as usize * 4];
+                nscodec_decompress(&self.data, u32::from(self.width), u32::from(self.height), &mut result)?;
+                return Ok(result);
+            }
+            Some(BitmapCodec::RemoteFx) => {
+                let mut result = vec![0_u8; self.width as usize * self.height as usize * 4];
+                rfx_decompress(&self.data, u32::from(self.width), u32::from(self.height), &mut result)?;
+                return Ok(result);
+            }
+            Some(BitmapCodec::Jpeg) => {
+                let mut result = vec![0_u8; self.width as usize * self.height as usize * 4];
+                jpeg_decompress(&self.data, u32::from(self.width), u32::from(self.height), &mut result)?;
+                return Ok(result);
+            }
+            None => (),
+        }
+
         // actually only handle 32 bpp
         match self.bpp {
             32 => {
@@ -74,24 +228,77 @@ impl BitmapEvent {
                     self.data
                 })
             }
-            16 => {
-                // 16 bpp is more consumer
+            24 => {
+                // 24 bpp pixels are expanded with an opaque alpha
+                let (width, height) = (self.width as usize, self.height as usize);
+                let mut result = vec![0_u8; width * height * 3];
+                if self.is_compress {
+                    rle_24_decompress(&self.data, width, height, &mut result)?;
+                } else {
+                    let stride = width * 3;
+                    if self.data.len() < stride * height {
+                        return Err(Error::RdpError(RdpError::new(
+                            RdpErrorKind::InvalidSize,
+                            "Bitmap data is too small",
+                        )));
+                    }
+                    for i in 0..height {
+                        let src = (height - i - 1) * stride;
+                        result[i * stride..(i + 1) * stride].copy_from_slice(&self.data[src..src + stride]);
+                    }
+                }
+                Ok(rgb24torgb32(&result))
+            }
+            15 | 16 => {
+                // 16 bpp is more consumer, 15 bpp uses the same compression
                 let result_16bpp = if self.is_compress {
                     let mut result = vec![0_u16; self.width as usize * self.height as usize];
                     rle_16_decompress(&self.data, self.width as usize, self.height as usize, &mut result)?;
                     result
                 } else {
-                    let mut result = vec![0_u16; self.width as usize * self.height as usize];
-                    for i in 0..self.height {
-                        for j in 0..self.width {
-                            let src = (((self.height - i - 1) * self.width + j) * 2) as usize;
-                            result[(i * self.width + j) as usize] =
-                                u16::from(self.data[src + 1]) << 8 | u16::from(self.data[src]);
+                    let (width, height) = (self.width as usize, self.height as usize);
+                    if self.data.len() < width * height * 2 {
+                        return Err(Error::RdpError(RdpError::new(
+                            RdpErrorKind::InvalidSize,
+                            "Bitmap data is too small",
+                        )));
+                    }
+                    let mut result = vec![0_u16; width * height];
+                    for i in 0..height {
+                        for j in 0..width {
+                            let src = ((height - i - 1) * width + j) * 2;
+                            result[i * width + j] = u16::from(self.data[src + 1]) << 8 | u16::from(self.data[src]);
                         }
                     }
                     result
                 };
-                Ok(rgb565torgb32(&result_16bpp))
+                Ok(if self.bpp == 15 { rgb555torgb32(&result_16bpp) } else { rgb565torgb32(&result_16bpp) })
+            }
+            8 => {
+                // colors come from the palette
+                let palette = self.palette.as_ref().ok_or_else(|| {
+                    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "No palette for 8 bpp bitmap"))
+                })?;
+                let result_8bpp = if self.is_compress {
+                    let mut result = vec![0_u8; self.width as usize * self.height as usize];
+                    rle_8_decompress(&self.data, self.width as usize, self.height as usize, &mut result)?;
+                    result
+                } else {
+                    let (width, height) = (self.width as usize, self.height as usize);
+                    if self.data.len() < width * height {
+                        return Err(Error::RdpError(RdpError::new(
+                            RdpErrorKind::InvalidSize,
+                            "Bitmap data is too small",
+                        )));
+                    }
+                    let mut result = vec![0_u8; width * height];
+                    for i in 0..height {
+                        let src = (height - i - 1) * width;
+                        result[i * width..(i + 1) * width].copy_from_slice(&self.data[src..src + width]);
+                    }
+                    result
+                };
+                Ok(palette_to_rgb32(&result_8bpp, palette))
             }
             _ => Err(Error::RdpError(RdpError::new(
                 RdpErrorKind::NotImplemented,
@@ -99,10 +306,61 @@ impl BitmapEvent {
             ))),
         }
     }
+
+    /// Decode the bitmap as an opaque RGBA image
+    /// Compressed bitmaps are decompressed first
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::event::BitmapEvent;
+    /// let bitmap = BitmapEvent {
+    ///     dest_left: 0,
+    ///     dest_top: 0,
+    ///     dest_right: 0,
+    ///     dest_bottom: 0,
+    ///     width: 1,
+    ///     height: 1,
+    ///     bpp: 32,
+    ///     is_compress: false,
+    ///     codec: None,
+    ///     monitor: None,
+    ///     palette: None,
+    ///     data: vec![1, 2, 3, 0],
+    /// };
+    /// let image = bitmap.to_image().unwrap();
+    /// assert_eq!(image.to_rgba8().get_pixel(0, 0).0, [3, 2, 1, 255]);
+    /// ```
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> RdpResult<image::DynamicImage> {
+        let (width, height) = (u32::from(self.width), u32::from(self.height));
+        let pixels = self.clone().decompress()?;
+        Ok(image::DynamicImage::ImageRgba8(bgra_to_image(width, height, pixels)?))
+    }
+
+    /// Save the bitmap as a PNG file
+    /// Useful to debug a session
+    #[cfg(feature = "image")]
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> RdpResult<()> {
+        self.to_image()?.save_with_format(path, image::ImageFormat::Png).map_err(|e| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("BITMAP: PNG encoding failed {}", e)))
+        })
+    }
+}
+
+/// Build an opaque RGBA image from 32 bpp BGRA pixels
+#[cfg(feature = "image")]
+pub(crate) fn bgra_to_image(width: u32, height: u32, mut pixels: Vec<u8>) -> RdpResult<image::RgbaImage> {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 0xff;
+    }
+    image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "BITMAP: Invalid bitmap size")))
 }
 
 #[repr(u8)]
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PointerButton {
     /// No button but a move
     None = 0,
@@ -112,10 +370,35 @@ pub enum PointerButton {
     Right = 2,
     /// Wheel mouse button
     Middle = 3,
+    /// Vertical wheel rotated forward
+    ScrollUp = 4,
+    /// Vertical wheel rotated backward
+    ScrollDown = 5,
+    /// Horizontal wheel tilted left
+    ScrollLeft = 6,
+    /// Horizontal wheel tilted right
+    ScrollRight = 7,
+    /// First extended button, usually back
+    X1 = 8,
+    /// Second extended button, usually forward
+    X2 = 9,
+}
+
+/// Button of a minifb window
+#[cfg(feature = "minifb")]
+impl From<minifb::MouseButton> for PointerButton {
+    fn from(button: minifb::MouseButton) -> Self {
+        match button {
+            minifb::MouseButton::Left => PointerButton::Left,
+            minifb::MouseButton::Middle => PointerButton::Middle,
+            minifb::MouseButton::Right => PointerButton::Right,
+        }
+    }
 }
 
 /// A mouse pointer event
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointerEvent {
     /// horizontal position from top left angle of the window
     pub x: u16,
@@ -125,12 +408,17 @@ pub struct PointerEvent {
     pub button: PointerButton,
     /// true if it's a down press action
     pub down: bool,
+    /// Wheel rotation of scroll buttons, 120 for a notch
+    /// Only the magnitude is used, the direction comes from the button
+    /// 0 means a single notch
+    pub wheel_delta: i16,
 }
 
 /// Keyboard event
 /// It's a raw event using Scancode
 /// to inform which key is pressed
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeyboardEvent {
     /// Scancode of the key
     pub code: u16,
@@ -138,8 +426,130 @@ pub struct KeyboardEvent {
     pub down: bool,
 }
 
+/// Unicode keyboard event
+/// Type a character which has no scancode
+/// on the keyboard layout of the session
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnicodeKeyboardEvent {
+    /// Character to type
+    pub character: char,
+    /// State of the key
+    pub down: bool,
+}
+
+/// Clipboard event
+/// Exchanged through the clipboard redirection channel
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClipboardEvent {
+    /// Unicode text copied on one side
+    Text(String),
+}
+
 /// All event handle by RDP protocol implemented by rdp-rs
-#[derive(Debug, Clone)]
+/// Brush of a pattern blit
+/// See MS-RDPEGDI 2.2.2.2.1.1.2.3
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Brush {
+    /// Origin of the pattern
+    pub x: i8,
+    pub y: i8,
+    /// Solid, hatched or pattern brush
+    pub style: u8,
+    /// Hatch style or first line of the pattern
+    pub hatch: u8,
+    /// Next lines of the pattern
+    pub extra: [u8; 7],
+}
+
+/// Drawing order painted by the server
+/// Rectangles are already clipped by the order bounds
+///
+/// Colors are encoded with the color depth of the session,
+/// a palette index for 8 bpp
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DrawOrderEvent {
+    /// Fill a rectangle with a solid color
+    OpaqueRect { rect: Rect, color: u32 },
+    /// Paint a rectangle with a brush and a raster operation
+    PatBlt { rect: Rect, rop: u8, back_color: u32, fore_color: u32, brush: Brush },
+}
+
+/// Why the server closed the session
+/// Decoded from the last error info PDU
+///
+/// See MS-RDPBCGR 2.2.5.1.1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisconnectReason {
+    /// The user disconnected the session from the server
+    UserInitiated,
+    /// An administrator or another connection closed the session
+    ServerInitiated,
+    /// The session was idle too long
+    IdleTimeout,
+    /// The session or its logon lasted too long
+    SessionTimeout,
+    /// The user logged off
+    LogoffByUser,
+    /// Any other error info code
+    Unknown(u32),
+}
+
+impl From<u32> for DisconnectReason {
+    /// Map the error info code sent before the disconnection
+    /// A zero code means no error info was sent
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::event::DisconnectReason;
+    /// assert_eq!(DisconnectReason::from(0x0c), DisconnectReason::LogoffByUser);
+    /// assert_eq!(DisconnectReason::from(0x10c9), DisconnectReason::Unknown(0x10c9));
+    /// ```
+    fn from(error_info: u32) -> Self {
+        match error_info {
+            0x0000_0000 | 0x0000_0001 | 0x0000_0002 | 0x0000_0005 => DisconnectReason::ServerInitiated,
+            0x0000_0003 => DisconnectReason::IdleTimeout,
+            0x0000_0004 => DisconnectReason::SessionTimeout,
+            0x0000_000b => DisconnectReason::UserInitiated,
+            0x0000_000c => DisconnectReason::LogoffByUser,
+            code => DisconnectReason::Unknown(code),
+        }
+    }
+}
+
+/// Pointer drawn by the operating system
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SystemPointer {
+    /// No pointer is drawn
+    Hidden,
+    /// Default arrow pointer
+    Default,
+}
+
+/// Statistics of a session
+/// Counters restart with a new session
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionStats {
+    /// Bytes written to the transport, before TLS
+    pub bytes_sent: u64,
+    /// Bytes read from the transport, after TLS
+    pub bytes_received: u64,
+    /// Fast path update PDUs decoded
+    pub frames_received: u64,
+    /// Average round trip time measured by the server
+    /// None without `Connector::network_autodetect`
+    /// or before the server sends its results
+    pub last_rtt_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RdpEvent {
     /// Classic bitmap event
     Bitmap(BitmapEvent),
@@ -147,4 +557,177 @@ pub enum RdpEvent {
     Pointer(PointerEvent),
     /// Keyboard event
     Key(KeyboardEvent),
+    /// Unicode keyboard event
+    UnicodeKey(UnicodeKeyboardEvent),
+    /// Clipboard event
+    Clipboard(ClipboardEvent),
+    /// Server changed the monitor topology
+    MonitorLayout(MonitorLayout),
+    /// The connection was lost and a new session is opened
+    /// Every channel state is reset
+    Reconnected,
+    /// Primary drawing order other than a memory blit
+    DrawOrder(DrawOrderEvent),
+    /// The server closed the session
+    /// No more event follows
+    Disconnect { reason: DisconnectReason, error_info: ErrorInfoCode },
+    /// Shape of the pointer sent by the server
+    /// Pixels are RGBA and top-down
+    PointerShape {
+        hotspot_x: u16,
+        hotspot_y: u16,
+        width: u16,
+        height: u16,
+        #[cfg_attr(feature = "serde", serde(with = "serde_base64"))]
+        pixels: Vec<u8>,
+    },
+    /// Use a system pointer instead of a pointer shape
+    SystemPointer(SystemPointer),
+    /// The server deactivated the session, for a resolution change
+    /// or a reconnection, no update comes until `Reactivated`
+    Reactivating,
+    /// The connection sequence is done again
+    /// The framebuffer must be redrawn with the desktop size
+    Reactivated { width: u16, height: u16 },
+    /// The server moved the pointer
+    /// Coordinates are relative to the desktop
+    PointerPosition { x: u16, y: u16 },
+    /// Statistics of the session, sent once per second
+    StatsSnapshot(ConnectionStats),
+    /// The user is logged on the session
+    LogonInfo { session_id: u32, domain: String, user: String },
+    /// Error info sent by the server, often before a disconnection
+    ErrorInfo(ErrorInfoCode),
+    /// The server allows display updates again
+    /// Input can be sent again
+    DisplayResumed,
+    /// A RemoteApp window was created or updated
+    /// A deleted window is notified as not visible
+    RemoteWindow { window_id: u32, title: String, rect: Rect, visible: bool },
+    /// The server sent every update of a frame
+    /// The framebuffer can be captured without partial updates
+    SyncPoint,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "minifb")]
+    #[test]
+    fn test_pointer_button_from_minifb() {
+        assert_eq!(PointerButton::from(minifb::MouseButton::Left), PointerButton::Left);
+        assert_eq!(PointerButton::from(minifb::MouseButton::Middle), PointerButton::Middle);
+        assert_eq!(PointerButton::from(minifb::MouseButton::Right), PointerButton::Right);
+    }
+
+    fn bitmap_8bpp(is_compress: bool, data: Vec<u8>, palette: Option<Arc<Palette>>) -> BitmapEvent {
+        BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: 1,
+            dest_bottom: 1,
+            width: 2,
+            height: 2,
+            bpp: 8,
+            is_compress,
+            codec: None,
+            monitor: None,
+            palette,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_decompress_8bpp() {
+        let mut palette = [[0; 3]; 256];
+        palette[1] = [0xff, 0, 0];
+        palette[2] = [0, 0, 0xff];
+        let palette = Some(Arc::new(palette));
+        let red = [0, 0, 0xff, 0xff];
+        let blue = [0xff, 0, 0, 0xff];
+
+        // raw bitmaps are bottom up
+        let raw = bitmap_8bpp(false, vec![1, 1, 2, 2], palette.clone()).decompress().unwrap();
+        assert_eq!(raw, [blue, blue, red, red].concat());
+
+        let compressed = bitmap_8bpp(true, vec![0x82, 1, 1, 0x82, 2, 2], palette).decompress().unwrap();
+        assert_eq!(compressed, raw);
+
+        assert!(bitmap_8bpp(false, vec![1, 1, 2, 2], None).decompress().is_err());
+    }
+
+    #[test]
+    fn test_decompress_24bpp() {
+        let pixels = vec![1, 2, 3, 4, 5, 6];
+        let bitmap = BitmapEvent { bpp: 24, width: 2, height: 1, dest_bottom: 0, ..bitmap_8bpp(false, pixels, None) };
+        assert_eq!(bitmap.clone().decompress().unwrap(), [1, 2, 3, 0xff, 4, 5, 6, 0xff]);
+
+        let compressed = BitmapEvent { is_compress: true, data: vec![0x82, 1, 2, 3, 4, 5, 6], ..bitmap.clone() };
+        assert_eq!(compressed.decompress().unwrap(), bitmap.clone().decompress().unwrap());
+
+        let truncated = BitmapEvent { data: vec![1, 2, 3], ..bitmap };
+        assert!(truncated.decompress().is_err());
+    }
+
+    #[test]
+    fn test_decompress_15bpp() {
+        // red and green pixels
+        let pixels = vec![0x00, 0x7c, 0xe0, 0x03];
+        let bitmap = BitmapEvent { bpp: 15, width: 2, height: 1, dest_bottom: 0, ..bitmap_8bpp(false, pixels, None) };
+        assert_eq!(bitmap.decompress().unwrap(), [0, 0, 0xff, 0xff, 0, 0xff, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_rect() {
+        let rect = Rect { left: 2, top: 3, right: 5, bottom: 3 };
+        assert_eq!((rect.width(), rect.height()), (4, 1));
+        assert!(rect.contains(2, 3) && rect.contains(5, 3));
+        assert!(!rect.contains(6, 3) && !rect.contains(2, 4));
+        assert!(Rect { left: 0, top: 0, right: 10, bottom: 10 }.contains_rect(&rect));
+        assert!(!rect.contains_rect(&Rect { left: 2, top: 3, right: 6, bottom: 3 }));
+        assert_eq!(Rect { left: 1, top: 0, right: 0, bottom: 0 }.width(), 0);
+
+        let bitmap = bitmap_8bpp(false, vec![], None);
+        assert!(bitmap.src_rect().contains_rect(&bitmap.dest_rect()));
+        let empty = BitmapEvent { width: 0, ..bitmap };
+        assert_eq!(empty.src_rect().width(), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let mut palette = [[0; 3]; 256];
+        palette[1] = [1, 2, 3];
+        let bitmap = bitmap_8bpp(true, vec![0x82, 1, 1, 0x82, 2, 2], Some(Arc::new(palette)));
+        let json = serde_json::to_string(&bitmap).unwrap();
+        assert!(json.contains("\"data\":\"ggEBggIC\""));
+        assert_eq!(serde_json::from_str::<BitmapEvent>(&json).unwrap(), bitmap);
+
+        let events = vec![
+            RdpEvent::Bitmap(bitmap_8bpp(false, vec![1, 2, 3, 4], None)),
+            RdpEvent::Pointer(PointerEvent { x: 10, y: 20, button: PointerButton::X1, down: true, wheel_delta: -120 }),
+            RdpEvent::Key(KeyboardEvent { code: 0x1d, down: false }),
+            RdpEvent::UnicodeKey(UnicodeKeyboardEvent { character: 'é', down: true }),
+            RdpEvent::Clipboard(ClipboardEvent::Text("text".to_string())),
+            RdpEvent::Reconnected,
+            RdpEvent::StatsSnapshot(ConnectionStats { bytes_sent: 1, last_rtt_ms: Some(20), ..Default::default() }),
+            RdpEvent::LogonInfo { session_id: 2, domain: "domain".to_string(), user: "user".to_string() },
+            RdpEvent::ErrorInfo(ErrorInfoCode::LogonTimeout),
+            RdpEvent::ErrorInfo(ErrorInfoCode::Unknown(0x2000)),
+        ];
+        for event in events {
+            let json = serde_json::to_string(&event).unwrap();
+            assert_eq!(serde_json::from_str::<RdpEvent>(&json).unwrap(), event);
+        }
+
+        let error = RdpError::new(RdpErrorKind::Timeout, "no answer");
+        let error: RdpError = serde_json::from_str(&serde_json::to_string(&error).unwrap()).unwrap();
+        assert_eq!(error.kind(), RdpErrorKind::Timeout);
+        assert_eq!(error.to_string(), "Timeout: no answer");
+
+        // a palette has 256 colors
+        let json = json.replacen("[[0,0,0],[1,2,3]", "[[1,2,3]", 1);
+        assert!(serde_json::from_str::<BitmapEvent>(&json).is_err());
+    }
 }
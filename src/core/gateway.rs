@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use native_tls::{TlsConnector, TlsStream};
+use num_enum::TryFromPrimitive;
+
+use crate::model::data::{Component, DataType, DynOption, Message, MessageOption, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::rnd::random;
+use crate::model::unicode::Unicode;
+use crate::nla::ntlm::Ntlm;
+use crate::nla::sspi::AuthenticationProtocol;
+
+/// Max size of data carried by one tunnel data packet
+const MAX_DATA_PACKET_LENGTH: usize = 0x8000;
+
+/// Configuration of a Remote Desktop Gateway
+///
+/// The stream given to `Connector::connect` must be connected
+/// to the gateway, the gateway then opens a channel to the target
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// Gateway host name, used for SNI and HTTP Host header
+    pub host: String,
+    /// Gateway port, usually 443
+    pub port: u16,
+    /// Gateway user name, can be in the form DOMAIN\user
+    pub username: String,
+    /// Gateway password
+    pub password: String,
+    /// Target RDP server as seen from the gateway
+    pub target_host: String,
+    /// Target RDP port
+    pub target_port: u16,
+}
+
+/// Type of the tunnel packets
+/// See MS-TSGU HTTP_PACKET_HEADER
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum PacketType {
+    HandshakeRequest = 0x0001,
+    HandshakeResponse = 0x0002,
+    ExtendedAuthMessage = 0x0003,
+    TunnelCreate = 0x0004,
+    TunnelResponse = 0x0005,
+    TunnelAuth = 0x0006,
+    TunnelAuthResponse = 0x0007,
+    ChannelCreate = 0x0008,
+    ChannelResponse = 0x0009,
+    Data = 0x000A,
+    ServiceMessage = 0x000B,
+    ReauthMessage = 0x000C,
+    Keepalive = 0x000D,
+    CloseChannel = 0x0010,
+    CloseChannelResponse = 0x0011,
+}
+
+/// Websocket frame opcodes
+/// https://www.rfc-editor.org/rfc/rfc6455#section-5.2
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xA,
+}
+
+/// Header of all tunnel packets
+/// See MS-TSGU HTTP_PACKET_HEADER
+fn http_packet(packet_type: Option<PacketType>, body: Option<Vec<u8>>) -> Component {
+    let body = body.unwrap_or_default();
+    component![
+        "packetType" => U16::LE(packet_type.unwrap_or(PacketType::Data) as u16),
+        "reserved" => U16::LE(0),
+        "packetLength" => DynOption::new(U32::LE(body.len() as u32 + 8), |length| MessageOption::Size("body".to_string(), length.inner() as usize - 8)),
+        "body" => body
+    ]
+}
+
+/// First packet send by the client
+/// No extended authentication is used
+fn handshake_request() -> Component {
+    component![
+        "verMajor" => 1_u8,
+        "verMinor" => 0_u8,
+        "clientVersion" => U16::LE(0),
+        "extendedAuth" => U16::LE(0)
+    ]
+}
+
+/// Ask the gateway to create a tunnel
+/// No optional capabilities are announced
+fn tunnel_create() -> Component {
+    component![
+        "capsFlags" => U32::LE(0),
+        "fieldsPresent" => U16::LE(0),
+        "reserved" => U16::LE(0)
+    ]
+}
+
+/// Authorize the tunnel for a client name
+fn tunnel_auth(client_name: &str) -> Component {
+    let mut name = client_name.to_utf16_le();
+    name.extend_from_slice(&[0, 0]);
+    component![
+        "fieldsPresent" => U16::LE(0),
+        "cbClientName" => U16::LE(name.len() as u16),
+        "clientName" => name
+    ]
+}
+
+/// Ask the gateway to open a channel to the target server
+fn channel_create(target_host: &str, target_port: u16) -> Component {
+    let mut resource = target_host.to_utf16_le();
+    resource.extend_from_slice(&[0, 0]);
+    component![
+        "numResources" => 1_u8,
+        "numAlternateResource" => 0_u8,
+        "port" => U16::LE(target_port),
+        "protocol" => U16::LE(3),
+        "cbResourceName" => U16::LE(resource.len() as u16),
+        "resourceName" => resource
+    ]
+}
+
+/// Data packet carrying the RDP stream
+fn data_packet(data: Option<Vec<u8>>) -> Component {
+    let data = data.unwrap_or_default();
+    component![
+        "cbDataLen" => DynOption::new(U16::LE(data.len() as u16), |length| MessageOption::Size("data".to_string(), length.inner() as usize)),
+        "data" => data
+    ]
+}
+
+/// Check the status code at the start of a gateway response
+fn check_status(body: &[u8], offset: usize, message: &str) -> RdpResult<()> {
+    let mut status = U32::LE(0);
+    status.read_from_bytes(body.get(offset..).unwrap_or_default())?;
+    if status.inner() != 0 {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::RejectedByServer,
+            &format!("GATEWAY: {} (0x{:08x})", message, status.inner()),
+        )));
+    }
+    Ok(())
+}
+
+/// Stream tunneled through a Remote Desktop Gateway
+///
+/// Use the HTTP transport of MS-TSGU over a websocket
+/// The legacy RPC over HTTP transport is not supported
+#[derive(Debug)]
+pub struct GatewayStream<S> {
+    /// TLS stream with the gateway
+    stream: TlsStream<S>,
+    /// Websocket payload not yet parsed as tunnel packet
+    tunnel: VecDeque<u8>,
+    /// Data of the last data packet not yet read
+    data: VecDeque<u8>,
+}
+
+impl<S: Read + Write> GatewayStream<S> {
+    /// Open a tunnel to the target server
+    ///
+    /// The outer TLS handshake is done here, the inner
+    /// security negotiation is done by the x224 layer
+    pub fn connect(stream: S, config: &GatewayConfig, client_name: &str, check_certificate: bool) -> RdpResult<Self> {
+        let mut builder = TlsConnector::builder();
+        builder.danger_accept_invalid_certs(!check_certificate);
+        let connector = builder.build()?;
+
+        let mut gateway = GatewayStream {
+            stream: connector.connect(&config.host, stream)?,
+            tunnel: VecDeque::new(),
+            data: VecDeque::new(),
+        };
+        gateway.upgrade(config)?;
+
+        gateway.write_packet(PacketType::HandshakeRequest, &handshake_request())?;
+        let body = gateway.expect_packet(PacketType::HandshakeResponse)?;
+        check_status(&body, 0, "Handshake refused")?;
+
+        gateway.write_packet(PacketType::TunnelCreate, &tunnel_create())?;
+        let body = gateway.expect_packet(PacketType::TunnelResponse)?;
+        check_status(&body, 2, "Tunnel creation refused")?;
+
+        gateway.write_packet(PacketType::TunnelAuth, &tunnel_auth(client_name))?;
+        let body = gateway.expect_packet(PacketType::TunnelAuthResponse)?;
+        check_status(&body, 0, "Tunnel authorization refused")?;
+
+        gateway.write_packet(PacketType::ChannelCreate, &channel_create(&config.target_host, config.target_port))?;
+        let body = gateway.expect_packet(PacketType::ChannelResponse)?;
+        check_status(&body, 0, "Channel creation refused")?;
+
+        Ok(gateway)
+    }
+
+    /// Upgrade the HTTP connection to a websocket
+    /// authenticated with NTLM
+    fn upgrade(&mut self, config: &GatewayConfig) -> RdpResult<()> {
+        let (domain, username) = match config.username.split_once('\\') {
+            Some((domain, username)) => (domain.to_string(), username.to_string()),
+            None => (String::new(), config.username.clone()),
+        };
+        let mut authentication = Ntlm::new(domain, username, config.password.clone());
+        let connection_id = random(16);
+
+        let negotiate = authentication.create_negotiate_message()?;
+        self.write_http_request(config, &connection_id, &negotiate)?;
+        let (status, headers) = self.read_http_response()?;
+        if status != 401 {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidRespond,
+                &format!("GATEWAY: Unexpected HTTP status {} during authentication", status),
+            )));
+        }
+
+        let challenge = headers
+            .iter()
+            .filter(|(name, _)| name == "www-authenticate")
+            .find_map(|(_, value)| value.strip_prefix("NTLM "))
+            .ok_or_else(|| {
+                Error::RdpError(RdpError::new(RdpErrorKind::InvalidRespond, "GATEWAY: NTLM authentication not offered"))
+            })?;
+        let challenge = BASE64.decode(challenge.trim()).map_err(|_| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "GATEWAY: Invalid NTLM challenge"))
+        })?;
+
+        let authenticate = authentication.read_challenge_message(&challenge)?;
+        self.write_http_request(config, &connection_id, &authenticate)?;
+        let (status, _) = self.read_http_response()?;
+        match status {
+            101 => Ok(()),
+            401 | 403 => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::RejectedByServer,
+                "GATEWAY: Authentication refused by gateway",
+            ))),
+            _ => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidRespond,
+                &format!("GATEWAY: Unexpected HTTP status {} during upgrade", status),
+            ))),
+        }
+    }
+
+    /// Write the websocket upgrade request
+    fn write_http_request(&mut self, config: &GatewayConfig, connection_id: &[u8], token: &[u8]) -> RdpResult<()> {
+        let id = connection_id.iter().map(|b| format!("{:02X}", b)).collect::<String>();
+        let request = format!(
+            "RDG_OUT_DATA /remoteDesktopGateway/ HTTP/1.1\r\n\
+             Host: {}:{}\r\n\
+             Accept: */*\r\n\
+             Cache-Control: no-cache\r\n\
+             Pragma: no-cache\r\n\
+             User-Agent: rdp-rs\r\n\
+             RDG-Connection-Id: {{{}-{}-{}-{}-{}}}\r\n\
+             Connection: Upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Authorization: NTLM {}\r\n\
+             Content-Length: 0\r\n\r\n",
+            config.host,
+            config.port,
+            &id[0..8],
+            &id[8..12],
+            &id[12..16],
+            &id[16..20],
+            &id[20..32],
+            BASE64.encode(random(16)),
+            BASE64.encode(token)
+        );
+        self.stream.write_all(request.as_bytes())?;
+        Ok(())
+    }
+
+    /// Read an HTTP response
+    ///
+    /// Return the status code and the headers with lower case names
+    /// The body, if any, is skipped
+    fn read_http_response(&mut self) -> RdpResult<(u16, Vec<(String, String)>)> {
+        let mut raw = Vec::new();
+        while !raw.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            raw.push(byte[0]);
+        }
+
+        let response = String::from_utf8_lossy(&raw);
+        let mut lines = response.split("\r\n");
+        let status =
+            lines.next().and_then(|line| line.split(' ').nth(1)).and_then(|code| code.parse::<u16>().ok()).ok_or_else(
+                || Error::RdpError(RdpError::new(RdpErrorKind::InvalidRespond, "GATEWAY: Invalid HTTP response")),
+            )?;
+
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+
+        let content_length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        std::io::copy(&mut (&mut self.stream).take(content_length), &mut std::io::sink())?;
+
+        Ok((status, headers))
+    }
+
+    /// Write a masked binary websocket frame
+    fn write_frame(&mut self, opcode: Opcode, payload: &[u8]) -> RdpResult<()> {
+        let mut frame = vec![0x80 | opcode as u8];
+        match payload.len() {
+            length if length < 126 => frame.push(0x80 | length as u8),
+            length if length <= 0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        let mask = random(4);
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Read websocket frames until some data are received
+    fn read_frame(&mut self) -> RdpResult<()> {
+        loop {
+            let mut header = [0; 2];
+            self.stream.read_exact(&mut header)?;
+            let length = match header[1] & 0x7f {
+                126 => {
+                    let mut length = [0; 2];
+                    self.stream.read_exact(&mut length)?;
+                    u64::from(u16::from_be_bytes(length))
+                }
+                127 => {
+                    let mut length = [0; 8];
+                    self.stream.read_exact(&mut length)?;
+                    u64::from_be_bytes(length)
+                }
+                length => u64::from(length),
+            };
+            let mut mask = [0; 4];
+            if header[1] & 0x80 != 0 {
+                self.stream.read_exact(&mut mask)?;
+            }
+            let mut payload = Vec::new();
+            (&mut self.stream).take(length).read_to_end(&mut payload)?;
+            if payload.len() as u64 != length {
+                return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+
+            match Opcode::try_from(header[0] & 0x0f)? {
+                Opcode::Continuation | Opcode::Binary | Opcode::Text => {
+                    self.tunnel.extend(payload);
+                    return Ok(());
+                }
+                Opcode::Ping => self.write_frame(Opcode::Pong, &payload)?,
+                Opcode::Pong => (),
+                Opcode::Close => {
+                    return Err(Error::RdpError(RdpError::new(RdpErrorKind::Disconnect, "GATEWAY: Websocket closed")))
+                }
+            }
+        }
+    }
+
+    /// Write a tunnel packet
+    fn write_packet(&mut self, packet_type: PacketType, message: &dyn Message) -> RdpResult<()> {
+        let mut body = Vec::new();
+        message.write(&mut body)?;
+        let mut packet = Vec::new();
+        http_packet(Some(packet_type), Some(body)).write(&mut packet)?;
+        self.write_frame(Opcode::Binary, &packet)
+    }
+
+    /// Read a tunnel packet
+    fn read_packet(&mut self) -> RdpResult<(PacketType, Vec<u8>)> {
+        while self.tunnel.len() < 8 {
+            self.read_frame()?;
+        }
+        let length = u32::from_le_bytes([self.tunnel[4], self.tunnel[5], self.tunnel[6], self.tunnel[7]]) as usize;
+        if length < 8 {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "GATEWAY: Invalid packet length")));
+        }
+        while self.tunnel.len() < length {
+            self.read_frame()?;
+        }
+
+        let mut packet = http_packet(None, None);
+        packet.read_from_bytes(&self.tunnel.drain(..length).collect::<Vec<u8>>())?;
+        let packet_type = PacketType::try_from(cast!(DataType::U16, packet["packetType"])?)?;
+        Ok((packet_type, cast!(DataType::Slice, packet["body"])?.to_vec()))
+    }
+
+    /// Read a tunnel packet of a particular type
+    fn expect_packet(&mut self, expected: PacketType) -> RdpResult<Vec<u8>> {
+        loop {
+            match self.read_packet()? {
+                (packet_type, body) if packet_type == expected => return Ok(body),
+                (PacketType::Keepalive, _) => (),
+                (packet_type, _) => {
+                    return Err(Error::RdpError(RdpError::new(
+                        RdpErrorKind::InvalidRespond,
+                        &format!("GATEWAY: Unexpected packet {:?}", packet_type),
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Wait for the next data packet
+    ///
+    /// Return false once the gateway closed the channel
+    fn read_data(&mut self) -> RdpResult<bool> {
+        loop {
+            match self.read_packet()? {
+                (PacketType::Data, body) => {
+                    let mut packet = data_packet(None);
+                    packet.read_from_bytes(&body)?;
+                    self.data.extend(cast!(DataType::Slice, packet["data"])?);
+                    return Ok(true);
+                }
+                (PacketType::CloseChannel, _) => return Ok(false),
+                (packet_type, _) => log_debug!("GATEWAY: packet not handled {:?}", packet_type),
+            }
+        }
+    }
+
+    /// Close the channel and the tunnel
+    pub fn shutdown(&mut self) -> std::io::Result<()> {
+        self.write_packet(PacketType::CloseChannel, &U32::LE(0))
+            .map_err(|_| std::io::Error::other("GATEWAY: Unable to close channel"))?;
+        self.stream.shutdown()
+    }
+}
+
+impl<S: Read + Write> Read for GatewayStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.data.is_empty() {
+            if !self.read_data().map_err(|e| std::io::Error::other(format!("{:?}", e)))? {
+                return Ok(0);
+            }
+        }
+        self.data.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for GatewayStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = buf.len().min(MAX_DATA_PACKET_LENGTH);
+        self.write_packet(PacketType::Data, &data_packet(Some(buf[..length].to_vec())))
+            .map_err(|_| std::io::Error::other("GATEWAY: Unable to write data packet"))?;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { self.stream.flush() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    #[test]
+    fn test_write_handshake_request() {
+        assert_eq!(
+            to_vec(&http_packet(Some(PacketType::HandshakeRequest), Some(to_vec(&handshake_request())))),
+            [1, 0, 0, 0, 14, 0, 0, 0, 1, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_read_data_packet() {
+        let mut packet = http_packet(None, None);
+        packet.read_from_bytes(&[10, 0, 0, 0, 12, 0, 0, 0, 2, 0, 1, 2]).unwrap();
+        let mut data = data_packet(None);
+        data.read_from_bytes(cast!(DataType::Slice, packet["body"]).unwrap()).unwrap();
+        assert_eq!(cast!(DataType::Slice, data["data"]).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_check_status_error() {
+        assert!(check_status(&[0, 0, 5, 0, 0, 0], 2, "foo").is_err());
+        assert!(check_status(&[0, 0, 0, 0], 0, "foo").is_ok());
+    }
+}
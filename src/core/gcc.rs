@@ -1,5 +1,8 @@
 use std::collections::HashMap;
 use std::io::{Cursor, Read};
+use std::str::FromStr;
+
+use num_enum::TryFromPrimitive;
 
 use crate::core::per;
 use crate::model::data::{
@@ -8,9 +11,13 @@ use crate::model::data::{
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 use crate::model::unicode::Unicode;
 
-const T124_02_98_OID: [u8; 6] = [0, 0, 20, 124, 0, 1];
+const T124_02_98_OID: [u32; 6] = [0, 0, 20, 124, 0, 1];
 const H221_CS_KEY: [u8; 4] = *b"Duca";
 const H221_SC_KEY: [u8; 4] = *b"McDn";
+
+/// Connection type measured by the network auto-detection
+const CONNECTION_TYPE_AUTODETECT: u8 = 0x07;
+
 /// RDP protocol version
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
 #[repr(u32)]
@@ -51,30 +58,199 @@ enum Sequence {
     RnsUdSasDel = 0xAA03,
 }
 
-/// Keyboard layout
-/// https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-vista/cc766503(v=ws.10)?redirectedfrom=MSDN
-#[repr(u32)]
-#[derive(Clone, Copy, Debug)]
-pub enum KeyboardLayout {
-    Arabic = 0x0000_0401,
-    Bulgarian = 0x0000_0402,
-    ChineseUsKeyboard = 0x0000_0404,
-    Czech = 0x0000_0405,
-    Danish = 0x0000_0406,
-    German = 0x0000_0407,
-    Greek = 0x0000_0408,
-    US = 0x0000_0409,
-    Spanish = 0x0000_040a,
-    Finnish = 0x0000_040b,
-    French = 0x0000_040c,
-    Hebrew = 0x0000_040d,
-    Hungarian = 0x0000_040e,
-    Icelandic = 0x0000_040f,
-    Italian = 0x0000_0410,
-    Japanese = 0x0000_0411,
-    Korean = 0x0000_0412,
-    Dutch = 0x0000_0413,
-    Norwegian = 0x0000_0414,
+/// Define keyboard layouts with their identifier and short name
+macro_rules! keyboard_layouts {
+    ($($name: ident = $value: expr => $short: expr,)*) => {
+        /// Keyboard layout
+        /// Unknown layouts are kept as `Other` to be round-tripped
+        ///
+        /// See MS-LCID
+        #[repr(u32)]
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum KeyboardLayout {
+            $($name = $value,)*
+            Other(u32) = 0,
+        }
+
+        impl KeyboardLayout {
+            /// Windows identifier of the layout
+            pub fn code(&self) -> u32 {
+                match self {
+                    $(KeyboardLayout::$name => $value,)*
+                    KeyboardLayout::Other(code) => *code,
+                }
+            }
+
+            /// Find the layout of a Windows identifier
+            pub fn from_code(code: u32) -> Self {
+                match code {
+                    $($value => KeyboardLayout::$name,)*
+                    _ => KeyboardLayout::Other(code),
+                }
+            }
+
+            /// Short name accepted by `from_str`
+            pub fn short_name(&self) -> Option<&'static str> {
+                match self {
+                    $(KeyboardLayout::$name => Some($short),)*
+                    KeyboardLayout::Other(_) => None,
+                }
+            }
+
+            fn from_short_name(name: &str) -> Option<Self> {
+                match name {
+                    $($short => Some(KeyboardLayout::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keyboard_layouts! {
+    Arabic = 0x0000_0401 => "ar",
+    Bulgarian = 0x0000_0402 => "bg",
+    ChineseUsKeyboard = 0x0000_0404 => "zh-tw",
+    Czech = 0x0000_0405 => "cs",
+    Danish = 0x0000_0406 => "da",
+    German = 0x0000_0407 => "de",
+    Greek = 0x0000_0408 => "el",
+    US = 0x0000_0409 => "us",
+    Spanish = 0x0000_040a => "es",
+    Finnish = 0x0000_040b => "fi",
+    French = 0x0000_040c => "fr",
+    Hebrew = 0x0000_040d => "he",
+    Hungarian = 0x0000_040e => "hu",
+    Icelandic = 0x0000_040f => "is",
+    Italian = 0x0000_0410 => "it",
+    Japanese = 0x0000_0411 => "ja",
+    Korean = 0x0000_0412 => "ko",
+    Dutch = 0x0000_0413 => "nl",
+    Norwegian = 0x0000_0414 => "no",
+    Polish = 0x0000_0415 => "pl",
+    PortugueseBrazil = 0x0000_0416 => "pt-br",
+    Romanian = 0x0000_0418 => "ro",
+    Russian = 0x0000_0419 => "ru",
+    Croatian = 0x0000_041a => "hr",
+    Slovak = 0x0000_041b => "sk",
+    Albanian = 0x0000_041c => "sq",
+    Swedish = 0x0000_041d => "sv",
+    Thai = 0x0000_041e => "th",
+    Turkish = 0x0000_041f => "tr",
+    Urdu = 0x0000_0420 => "ur",
+    Ukrainian = 0x0000_0422 => "uk",
+    Belarusian = 0x0000_0423 => "be",
+    Slovenian = 0x0000_0424 => "sl",
+    Estonian = 0x0000_0425 => "et",
+    Latvian = 0x0000_0426 => "lv",
+    Lithuanian = 0x0000_0427 => "lt",
+    Tajik = 0x0000_0428 => "tg",
+    Persian = 0x0000_0429 => "fa",
+    Vietnamese = 0x0000_042a => "vi",
+    Armenian = 0x0000_042b => "hy",
+    AzerbaijaniLatin = 0x0000_042c => "az",
+    UpperSorbian = 0x0000_042e => "hsb",
+    Macedonian = 0x0000_042f => "mk",
+    Setswana = 0x0000_0432 => "tn",
+    IsiXhosa = 0x0000_0434 => "xh",
+    IsiZulu = 0x0000_0435 => "zu",
+    Afrikaans = 0x0000_0436 => "af",
+    Georgian = 0x0000_0437 => "ka",
+    Faroese = 0x0000_0438 => "fo",
+    Hindi = 0x0000_0439 => "hi",
+    Maltese = 0x0000_043a => "mt",
+    SamiNorway = 0x0000_043b => "se",
+    Kazakh = 0x0000_043f => "kk",
+    Kyrgyz = 0x0000_0440 => "ky",
+    Swahili = 0x0000_0441 => "sw",
+    Turkmen = 0x0000_0442 => "tk",
+    UzbekLatin = 0x0000_0443 => "uz",
+    Tatar = 0x0000_0444 => "tt",
+    Bangla = 0x0000_0445 => "bn",
+    Punjabi = 0x0000_0446 => "pa",
+    Gujarati = 0x0000_0447 => "gu",
+    Odia = 0x0000_0448 => "or",
+    Tamil = 0x0000_0449 => "ta",
+    Telugu = 0x0000_044a => "te",
+    Kannada = 0x0000_044b => "kn",
+    Malayalam = 0x0000_044c => "ml",
+    Assamese = 0x0000_044d => "as",
+    Marathi = 0x0000_044e => "mr",
+    MongolianCyrillic = 0x0000_0450 => "mn",
+    Tibetan = 0x0000_0451 => "bo",
+    Welsh = 0x0000_0452 => "cy",
+    Khmer = 0x0000_0453 => "km",
+    Lao = 0x0000_0454 => "lo",
+    Galician = 0x0000_0456 => "gl",
+    Syriac = 0x0000_045a => "syr",
+    Sinhala = 0x0000_045b => "si",
+    Cherokee = 0x0000_045c => "chr",
+    Inuktitut = 0x0000_045d => "iu",
+    Nepali = 0x0000_0461 => "ne",
+    Frisian = 0x0000_0462 => "fy",
+    Pashto = 0x0000_0463 => "ps",
+    Filipino = 0x0000_0464 => "fil",
+    Divehi = 0x0000_0465 => "dv",
+    Hausa = 0x0000_0468 => "ha",
+    Yoruba = 0x0000_046a => "yo",
+    SesothoSaLeboa = 0x0000_046c => "nso",
+    Bashkir = 0x0000_046d => "ba",
+    Luxembourgish = 0x0000_046e => "lb",
+    Greenlandic = 0x0000_046f => "kl",
+    Igbo = 0x0000_0470 => "ig",
+    Guarani = 0x0000_0474 => "gn",
+    Hawaiian = 0x0000_0475 => "haw",
+    Uyghur = 0x0000_0480 => "ug",
+    Maori = 0x0000_0481 => "mi",
+    Sakha = 0x0000_0485 => "sah",
+    Wolof = 0x0000_0488 => "wo",
+    CentralKurdish = 0x0000_0492 => "ckb",
+    ChinesePrc = 0x0000_0804 => "zh-cn",
+    SwissGerman = 0x0000_0807 => "de-ch",
+    UnitedKingdom = 0x0000_0809 => "gb",
+    LatinAmerican = 0x0000_080a => "es-419",
+    BelgianFrench = 0x0000_080c => "fr-be",
+    BelgianPeriod = 0x0000_0813 => "nl-be",
+    Portuguese = 0x0000_0816 => "pt",
+    SerbianLatin = 0x0000_081a => "sr-latn",
+    AzerbaijaniCyrillic = 0x0000_082c => "az-cyrl",
+    SwedishWithSami = 0x0000_083b => "se-se",
+    UzbekCyrillic = 0x0000_0843 => "uz-cyrl",
+    MongolianScript = 0x0000_0850 => "mn-mong",
+    InuktitutLatin = 0x0000_085d => "iu-latn",
+    ChineseHongKong = 0x0000_0c04 => "zh-hk",
+    CanadianFrenchLegacy = 0x0000_0c0c => "fr-ca-legacy",
+    SerbianCyrillic = 0x0000_0c1a => "sr",
+    ChineseSingapore = 0x0000_1004 => "zh-sg",
+    CanadianFrench = 0x0000_1009 => "fr-ca",
+    SwissFrench = 0x0000_100c => "fr-ch",
+    Irish = 0x0000_1809 => "ga",
+    BosnianCyrillic = 0x0000_201a => "bs-cyrl",
+}
+
+impl FromStr for KeyboardLayout {
+    type Err = Error;
+
+    /// Parse a short name like "fr" or an hexadecimal identifier like "0x040c"
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::gcc::KeyboardLayout;
+    /// assert_eq!("pl".parse::<KeyboardLayout>().unwrap(), KeyboardLayout::Polish);
+    /// assert_eq!("0x0419".parse::<KeyboardLayout>().unwrap(), KeyboardLayout::Russian);
+    /// assert_eq!("0x00010415".parse::<KeyboardLayout>().unwrap(), KeyboardLayout::Other(0x0001_0415));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        if let Some(hex) = name.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16).map(KeyboardLayout::from_code).map_err(|_| {
+                Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Invalid keyboard layout {}", s)))
+            });
+        }
+        KeyboardLayout::from_short_name(&name).ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Unknown keyboard layout {}", s)))
+        })
+    }
 }
 
 /// Keyboard type
@@ -139,8 +315,9 @@ enum CapabilityFlag {
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
 #[repr(u32)]
 #[allow(dead_code)]
-#[derive(Clone, Copy, Debug)]
-enum EncryptionMethod {
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum EncryptionMethod {
+    None = 0x0000_0000,
     EncryptionFlag40bit = 0x0000_0001,
     EncryptionFlag128bit = 0x0000_0002,
     EncryptionFlag56bit = 0x0000_0008,
@@ -166,12 +343,14 @@ pub enum MessageType {
     ScCore = 0x0C01,
     ScSecurity = 0x0C02,
     ScNet = 0x0C03,
+    ScMcsMsgChannel = 0x0C04,
     //client -> server
     CsCore = 0xC001,
     CsSecurity = 0xC002,
     CsNet = 0xC003,
     CsCluster = 0xC004,
     CsMonitor = 0xC005,
+    CsMcsMsgChannel = 0xC006,
     Unknown = 0,
 }
 
@@ -181,17 +360,33 @@ impl From<u16> for MessageType {
             0x0C01 => MessageType::ScCore,
             0x0C02 => MessageType::ScSecurity,
             0x0C03 => MessageType::ScNet,
+            0x0C04 => MessageType::ScMcsMsgChannel,
             0xC001 => MessageType::CsCore,
             0xC002 => MessageType::CsSecurity,
             0xC003 => MessageType::CsNet,
             0xC004 => MessageType::CsCluster,
             0xC005 => MessageType::CsMonitor,
+            0xC006 => MessageType::CsMcsMsgChannel,
             _ => MessageType::Unknown,
         }
     }
 }
 
 /// In case of client
+/// Windows build and product announced by the client
+/// Some servers use them to fingerprint clients
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ClientIdentity {
+    /// Windows build number, 3790 is Windows Server 2003
+    pub build: u32,
+    pub product_id: u16,
+    pub serial_number: u32,
+}
+
+impl Default for ClientIdentity {
+    fn default() -> Self { ClientIdentity { build: 3790, product_id: 1, serial_number: 0 } }
+}
+
 /// This is all mandatory fields need by client core data
 #[derive(Clone, Debug)]
 pub struct ClientData {
@@ -201,6 +396,14 @@ pub struct ClientData {
     pub server_selected_protocol: u32,
     pub rdp_version: Version,
     pub name: String,
+    /// Client can handle monitor layout PDU
+    pub support_monitor_layout: bool,
+    /// Client can handle the graphics pipeline channel
+    pub support_gfx: bool,
+    /// Build, product id and serial number of the client
+    pub identity: ClientIdentity,
+    /// Client answers the network auto-detection requests
+    pub support_network_autodetect: bool,
 }
 
 /// This is the first client specific data
@@ -216,30 +419,60 @@ pub fn client_core_data(parameter: Option<ClientData>) -> Component {
         server_selected_protocol: 0,
         rdp_version: Version::RdpVersion5plus,
         name: String::new(),
+        support_monitor_layout: false,
+        support_gfx: false,
+        identity: ClientIdentity::default(),
+        support_network_autodetect: false,
     });
 
-    let client_name = if client_parameter.name.len() >= 16 {
-        client_parameter.name[0..16].to_string()
+    let mut early_capability_flags =
+        CapabilityFlag::RnsUdCsSupportErrinfoPDU as u16 | CapabilityFlag::RnsUdCsSupportHeartbeatPDU as u16;
+    if client_parameter.support_monitor_layout {
+        early_capability_flags |= CapabilityFlag::RnsUdCsSupportMonitorLayoutPDU as u16;
+    }
+    if client_parameter.support_gfx {
+        // the graphics pipeline needs a 32 bpp session
+        early_capability_flags |=
+            CapabilityFlag::RnsUdCsSupportDynvcGFXProtocol as u16 | CapabilityFlag::RnsUdCsWant32BPPSession as u16;
+    }
+    // the server measures the connection instead of a fixed connection type
+    let connection_type = if client_parameter.support_network_autodetect {
+        early_capability_flags |=
+            CapabilityFlag::RnsUdCsSupportNetcharAutodetect as u16 | CapabilityFlag::RnsUdCsValidConnectionType as u16;
+        CONNECTION_TYPE_AUTODETECT
     } else {
-        client_parameter.name.clone() + &"\x00".repeat(16 - client_parameter.name.len())
+        0
     };
 
+    // 15 characters and a null terminator
+    let mut length = 0;
+    let mut client_name: Vec<u8> = client_parameter
+        .name
+        .chars()
+        .take_while(|c| {
+            length += c.len_utf16();
+            length < 16
+        })
+        .collect::<String>()
+        .to_utf16_le();
+    client_name.resize(32, 0);
+
     component![
         "version" => U32::LE(client_parameter.rdp_version as u32),
         "desktopWidth" => U16::LE(client_parameter.width),
         "desktopHeight" => U16::LE(client_parameter.height),
         "colorDepth" => U16::LE(ColorDepth::RnsUdColor8BPP as u16),
         "sasSequence" => U16::LE(Sequence::RnsUdSasDel as u16),
-        "kbdLayout" => U32::LE(client_parameter.layout as u32),
-        "clientBuild" => U32::LE(3790),
-        "clientName" => client_name.to_string().to_utf16_le(),
+        "kbdLayout" => U32::LE(client_parameter.layout.code()),
+        "clientBuild" => U32::LE(client_parameter.identity.build),
+        "clientName" => client_name,
         "keyboardType" => U32::LE(KeyboardType::Ibm101102Keys as u32),
         "keyboardSubType" => U32::LE(0),
         "keyboardFnKeys" => U32::LE(12),
         "imeFileName" => vec![0_u8; 64],
         "postBeta2ColorDepth" => U16::LE(ColorDepth::RnsUdColor8BPP as u16),
-        "clientProductId" => U16::LE(1),
-        "serialNumber" => U32::LE(0),
+        "clientProductId" => U16::LE(client_parameter.identity.product_id),
+        "serialNumber" => U32::LE(client_parameter.identity.serial_number),
         "highColorDepth" => U16::LE(HighColor::HighColor24BPP as u16),
         "supportedColorDepths" => U16::LE(
             //Support::RnsUd15BPP as u16 |
@@ -247,14 +480,180 @@ pub fn client_core_data(parameter: Option<ClientData>) -> Component {
             //Support::RnsUd24BPP as u16 |
             Support::RnsUd32BPP as u16
             ),
-        "earlyCapabilityFlags" => U16::LE(CapabilityFlag::RnsUdCsSupportErrinfoPDU as u16),
+        "earlyCapabilityFlags" => U16::LE(early_capability_flags),
         "clientDigProductId" => vec![0; 64],
-        "connectionType" => 0_u8,
+        "connectionType" => connection_type,
         "pad1octet" => 0_u8,
         "serverSelectedProtocol" => U32::LE(client_parameter.server_selected_protocol)
     ]
 }
 
+/// Flags of a monitor definition
+#[repr(u32)]
+#[derive(Clone, Copy, Debug)]
+enum MonitorFlag {
+    Primary = 0x0000_0001,
+}
+
+/// A monitor of the client desktop
+/// Coordinates are inclusive and relative to the primary monitor
+///
+/// See MS-RDPBCGR 2.2.1.3.6.1
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Monitor {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub is_primary: bool,
+}
+
+impl Monitor {
+    /// Read a monitor from a TS_MONITOR_DEF component
+    pub fn from_component(monitor_def: &Component) -> RdpResult<Self> {
+        Ok(Monitor {
+            left: cast!(DataType::U32, monitor_def["left"])? as i32,
+            top: cast!(DataType::U32, monitor_def["top"])? as i32,
+            right: cast!(DataType::U32, monitor_def["right"])? as i32,
+            bottom: cast!(DataType::U32, monitor_def["bottom"])? as i32,
+            is_primary: cast!(DataType::U32, monitor_def["flags"])? & MonitorFlag::Primary as u32 != 0,
+        })
+    }
+}
+
+/// Number of monitors of the client monitor data
+const MAX_MONITORS: usize = 16;
+
+/// Largest width or height of the virtual desktop
+const MAX_DESKTOP_SIZE: i64 = 32766;
+
+/// Monitor topology of the client desktop
+/// The protocol is limited to 16 monitors
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonitorLayout {
+    pub monitors: Vec<Monitor>,
+}
+
+impl MonitorLayout {
+    /// Bounding rectangle of all monitors
+    /// as left, top, right and bottom inclusive coordinates
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let first = self.monitors.first()?;
+        Some(self.monitors.iter().fold((first.left, first.top, first.right, first.bottom), |bounds, monitor| {
+            (
+                bounds.0.min(monitor.left),
+                bounds.1.min(monitor.top),
+                bounds.2.max(monitor.right),
+                bounds.3.max(monitor.bottom),
+            )
+        }))
+    }
+
+    /// Size of the virtual desktop covering all monitors
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::gcc::{Monitor, MonitorLayout};
+    /// let layout = MonitorLayout {
+    ///     monitors: vec![
+    ///         Monitor { left: 0, top: 0, right: 1919, bottom: 1079, is_primary: true },
+    ///         Monitor { left: -1280, top: 0, right: -1, bottom: 1023, is_primary: false },
+    ///     ],
+    /// };
+    /// assert_eq!(layout.desktop_size(), (3200, 1080));
+    /// ```
+    ///
+    /// Sizes above 65535 are saturated, `validate` rejects them
+    pub fn desktop_size(&self) -> (u16, u16) {
+        let size = |first: i32, last: i32| u16::try_from(i64::from(last) - i64::from(first) + 1).unwrap_or(u16::MAX);
+        match self.bounds() {
+            Some((left, top, right, bottom)) => (size(left, right), size(top, bottom)),
+            None => (0, 0),
+        }
+    }
+
+    /// Check the rules of the client monitor data
+    /// See MS-RDPBCGR 2.2.1.3.6
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::gcc::{Monitor, MonitorLayout};
+    /// let primary = Monitor { left: 0, top: 0, right: 799, bottom: 599, is_primary: true };
+    /// let layout = MonitorLayout { monitors: vec![primary] };
+    /// assert!(layout.validate().is_ok());
+    /// assert!(MonitorLayout::default().validate().is_err());
+    /// ```
+    pub fn validate(&self) -> RdpResult<()> {
+        let invalid = |message: &str| Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, message)));
+        if self.monitors.is_empty() {
+            return invalid("GCC: No monitor in the layout");
+        }
+        if self.monitors.len() > MAX_MONITORS {
+            return invalid("GCC: More than 16 monitors");
+        }
+        if self.monitors.iter().any(|monitor| monitor.right < monitor.left || monitor.bottom < monitor.top) {
+            return invalid("GCC: Monitor with a negative size");
+        }
+        let mut primaries = self.monitors.iter().filter(|monitor| monitor.is_primary);
+        match (primaries.next(), primaries.next()) {
+            (Some(primary), None) if primary.left == 0 && primary.top == 0 => (),
+            (Some(_), None) => return invalid("GCC: Primary monitor not at the origin"),
+            _ => return invalid("GCC: The layout needs a single primary monitor"),
+        }
+        match self.bounds() {
+            Some((left, top, right, bottom))
+                if i64::from(right) - i64::from(left) < MAX_DESKTOP_SIZE
+                    && i64::from(bottom) - i64::from(top) < MAX_DESKTOP_SIZE =>
+            {
+                Ok(())
+            }
+            _ => invalid("GCC: Desktop larger than 32766 pixels"),
+        }
+    }
+
+    /// Index of the monitor which contains a point of the virtual desktop
+    /// Desktop coordinates start at the top left corner of the bounding
+    /// rectangle
+    pub fn monitor_at(&self, x: u16, y: u16) -> Option<usize> {
+        let (left, top, _, _) = self.bounds()?;
+        let (x, y) = (left + x as i32, top + y as i32);
+        self.monitors
+            .iter()
+            .position(|monitor| monitor.left <= x && x <= monitor.right && monitor.top <= y && y <= monitor.bottom)
+    }
+}
+
+/// Monitor definition
+/// Use by both client monitor data and server monitor layout PDU
+pub fn ts_monitor_def(monitor: Option<&Monitor>) -> Component {
+    let monitor = monitor.copied().unwrap_or(Monitor { left: 0, top: 0, right: 0, bottom: 0, is_primary: false });
+    component![
+        "left" => U32::LE(monitor.left as u32),
+        "top" => U32::LE(monitor.top as u32),
+        "right" => U32::LE(monitor.right as u32),
+        "bottom" => U32::LE(monitor.bottom as u32),
+        "flags" => U32::LE(if monitor.is_primary { MonitorFlag::Primary as u32 } else { 0 })
+    ]
+}
+
+/// Client monitor data
+/// Describe the monitor topology of the client
+///
+/// See MS-RDPBCGR 2.2.1.3.6
+pub fn client_monitor_data(layout: &MonitorLayout) -> Component {
+    let mut monitor_def_array = trame![];
+    for monitor in &layout.monitors {
+        monitor_def_array.push(Box::new(ts_monitor_def(Some(monitor))));
+    }
+    component![
+        "flags" => U32::LE(0),
+        "monitorCount" => U32::LE(layout.monitors.len() as u32),
+        "monitorDefArray" => to_vec(&monitor_def_array)
+    ]
+}
+
 pub fn server_core_data() -> Component {
     component![
         "rdpVersion" => U32::LE(0),
@@ -263,15 +662,53 @@ pub fn server_core_data() -> Component {
     ]
 }
 
+/// Flags of the client cluster data
+/// See MS-RDPBCGR 2.2.1.3.5
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClusterFlag {
+    RedirectionSupported = 0x0000_0001,
+    RedirectedSessionIdFieldValid = 0x0000_0002,
+    /// Session redirection version 4
+    RedirectionVersion4 = 0x0000_000C,
+}
+
+/// Client cluster data
+/// Ask the server to reconnect to an existing session
+///
+/// See MS-RDPBCGR 2.2.1.3.5
+pub fn client_cluster_data(redirected_session_id: u32) -> Component {
+    component![
+        "flags" => U32::LE(
+            ClusterFlag::RedirectionSupported as u32
+                | ClusterFlag::RedirectedSessionIdFieldValid as u32
+                | ClusterFlag::RedirectionVersion4 as u32
+        ),
+        "redirectedSessionId" => U32::LE(redirected_session_id)
+    ]
+}
+
+/// Client message channel data
+/// Ask for the channel of the auto-detection and heartbeat PDUs
+///
+/// See MS-RDPBCGR 2.2.1.3.7
+pub fn client_message_channel_data() -> Component {
+    component![
+        "flags" => U32::LE(0)
+    ]
+}
+
 /// Client security releated to deprecated RDP security layer
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
 pub fn client_security_data() -> Component {
+    let mut encryption_methods = EncryptionMethod::EncryptionFlag40bit as u32
+        | EncryptionMethod::EncryptionFlag56bit as u32
+        | EncryptionMethod::EncryptionFlag128bit as u32;
+    if cfg!(feature = "legacy-security") {
+        encryption_methods |= EncryptionMethod::FipsEncryptionFlag as u32;
+    }
     component![
-        "encryptionMethods" => U32::LE(
-            EncryptionMethod::EncryptionFlag40bit as u32 |
-            EncryptionMethod::EncryptionFlag56bit as u32 |
-            EncryptionMethod::EncryptionFlag128bit as u32
-         ),
+        "encryptionMethods" => U32::LE(encryption_methods),
         "extEncryptionMethods" => U32::LE(0)
     ]
 }
@@ -285,15 +722,60 @@ pub fn server_security_data() -> Component {
     ]
 }
 
+/// Security settings selected by the server
+/// Random and certificate are only sent
+/// when the legacy security layer is used
+#[derive(Clone, Debug, Default)]
+pub struct ServerSecurityData {
+    /// One of `EncryptionMethod`
+    pub encryption_method: u32,
+    pub encryption_level: u32,
+    pub server_random: Vec<u8>,
+    pub server_certificate: Vec<u8>,
+}
+
+/// Read the server security data block
+///
+/// See MS-RDPBCGR 2.2.1.4.3
+fn read_server_security_data(buffer: &[u8]) -> RdpResult<ServerSecurityData> {
+    let mut stream = Cursor::new(buffer);
+    let mut header = server_security_data();
+    header.read(&mut stream)?;
+    let mut result = ServerSecurityData {
+        encryption_method: cast!(DataType::U32, header["encryptionMethod"])?,
+        encryption_level: cast!(DataType::U32, header["encryptionLevel"])?,
+        ..Default::default()
+    };
+    if result.encryption_method != 0 && result.encryption_level != 0 {
+        let mut lengths = component![
+            "serverRandomLen" => U32::LE(0),
+            "serverCertLen" => U32::LE(0)
+        ];
+        lengths.read(&mut stream)?;
+        result.server_random = vec![0; cast!(DataType::U32, lengths["serverRandomLen"])? as usize];
+        stream.read_exact(&mut result.server_random)?;
+        result.server_certificate = vec![0; cast!(DataType::U32, lengths["serverCertLen"])? as usize];
+        stream.read_exact(&mut result.server_certificate)?;
+    }
+    Ok(result)
+}
+
 /// Actually we have no more classic channel
-pub fn channel_def(name: &String, options: u32) -> Component {
+/// Static virtual channel definition
+/// Name is a null terminated ANSI string of 8 bytes
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a8593178-80c0-4b80-876c-cb77e62cecfc
+pub fn channel_def(name: &str, options: u32) -> Component {
+    let mut name = name.as_bytes().to_vec();
+    name.resize(8, 0);
+    name[7] = 0;
     component![
-        "name"=> name.as_bytes().to_vec(),
+        "name"=> name,
         "options" => U32::LE(options)
     ]
 }
 
-/// Actually we have no more channel than the classic one
+/// List of static virtual channels requested by the client
 pub fn client_network_data(channel_def_array: Trame) -> Component {
     component![
         "channelCount" => U32::LE(channel_def_array.len() as u32),
@@ -336,12 +818,24 @@ pub fn write_conference_create_request(user_data: &[u8]) -> RdpResult<Vec<u8>> {
 pub struct ServerData {
     pub channel_ids: Vec<u16>,
     pub rdp_version: Version,
+    /// Encryption selected by the server
+    pub security: ServerSecurityData,
+    /// Type of the blocks which are not decoded
+    pub unknown_blocks: Vec<u16>,
+    /// None if the server doesn't open a message channel
+    pub message_channel_id: Option<u16>,
 }
 
 /// Read conference create response
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
 pub fn read_conference_create_response(cc_response: &mut dyn Read) -> RdpResult<ServerData> {
     per::read_choice(cc_response)?;
-    per::read_object_identifier(&T124_02_98_OID, cc_response)?;
+    if !per::read_object_identifier(&T124_02_98_OID, cc_response)? {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidConst,
+            "GCC: Conference create response is not T.124",
+        )));
+    }
     per::read_length(cc_response)?;
     per::read_choice(cc_response)?;
     per::read_integer_16(1001, cc_response)?;
@@ -353,6 +847,9 @@ pub fn read_conference_create_response(cc_response: &mut dyn Read) -> RdpResult<
 
     let length = per::read_length(cc_response)?;
     let mut result = HashMap::new();
+    let mut unknown_blocks = Vec::new();
+    let mut security = ServerSecurityData::default();
+    let mut message_channel_id = None;
     let mut sub = cc_response.take(u64::from(length));
     loop {
         let mut header = block_header(None, None);
@@ -367,20 +864,26 @@ pub fn read_conference_create_response(cc_response: &mut dyn Read) -> RdpResult<
         match MessageType::from(cast!(DataType::U16, header["type"])?) {
             MessageType::ScCore => {
                 let mut server_core = server_core_data();
-                server_core.read(&mut Cursor::new(buffer))?;
+                server_core.read_from_bytes(&buffer)?;
                 result.insert(MessageType::ScCore, server_core);
             }
-            MessageType::ScSecurity => {
-                let mut server_security = server_security_data();
-                server_security.read(&mut Cursor::new(buffer))?;
-                result.insert(MessageType::ScSecurity, server_security);
+            MessageType::ScSecurity => security = read_server_security_data(&buffer)?,
+            // See MS-RDPBCGR 2.2.1.4.5
+            MessageType::ScMcsMsgChannel => {
+                let mut server_message_channel = component!["MCSChannelID" => U16::LE(0)];
+                server_message_channel.read_from_bytes(&buffer)?;
+                message_channel_id = Some(cast!(DataType::U16, server_message_channel["MCSChannelID"])?);
             }
             MessageType::ScNet => {
                 let mut server_net = server_network_data();
-                server_net.read(&mut Cursor::new(buffer))?;
+                server_net.read_from_bytes(&buffer)?;
                 result.insert(MessageType::ScNet, server_net);
             }
-            _ => println!("GCC: Unknown server block {:?}", cast!(DataType::U16, header["type"])?),
+            _ => {
+                let block_type = cast!(DataType::U16, header["type"])?;
+                log_debug!("GCC: Unknown server block {:?}", block_type);
+                unknown_blocks.push(block_type);
+            }
         }
     }
 
@@ -388,8 +891,185 @@ pub fn read_conference_create_response(cc_response: &mut dyn Read) -> RdpResult<
     Ok(ServerData {
         channel_ids: cast!(DataType::Trame, result[&MessageType::ScNet]["channelIdArray"])?
             .iter()
-            .map(|x| cast!(DataType::U16, x).unwrap())
-            .collect(),
+            .map(|x| cast!(DataType::U16, x))
+            .collect::<RdpResult<_>>()?,
         rdp_version: Version::from(cast!(DataType::U32, result[&MessageType::ScCore]["rdpVersion"])?),
+        security,
+        unknown_blocks,
+        message_channel_id,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_client_cluster_data() {
+        assert_eq!(to_vec(&client_cluster_data(5)), [0x0f, 0, 0, 0, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_keyboard_layout_code() {
+        assert_eq!(KeyboardLayout::Polish.code(), 0x0415);
+        assert_eq!(KeyboardLayout::from_code(0x0809), KeyboardLayout::UnitedKingdom);
+        assert_eq!(KeyboardLayout::from_code(0x0001_0409), KeyboardLayout::Other(0x0001_0409));
+        assert_eq!(KeyboardLayout::Other(0x0001_0409).code(), 0x0001_0409);
+    }
+
+    #[test]
+    fn test_keyboard_layout_from_str() {
+        assert_eq!("RU".parse::<KeyboardLayout>().unwrap(), KeyboardLayout::Russian);
+        assert_eq!("0x0415".parse::<KeyboardLayout>().unwrap(), KeyboardLayout::Polish);
+        assert!("xx".parse::<KeyboardLayout>().is_err());
+        assert!("0xzz".parse::<KeyboardLayout>().is_err());
+    }
+
+    fn dual_layout() -> MonitorLayout {
+        MonitorLayout {
+            monitors: vec![
+                Monitor { left: 0, top: 0, right: 1919, bottom: 1079, is_primary: true },
+                Monitor { left: -1280, top: 0, right: -1, bottom: 1023, is_primary: false },
+            ],
+        }
+    }
+
+    fn is_invalid_layout(monitors: Vec<Monitor>) -> bool {
+        let result = MonitorLayout { monitors }.validate();
+        matches!(result, Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn test_validate_monitor_layout() {
+        let monitor = |left: i32, top: i32, is_primary: bool| Monitor {
+            left,
+            top,
+            right: left + 799,
+            bottom: top + 599,
+            is_primary,
+        };
+        assert!(dual_layout().validate().is_ok());
+        assert!(is_invalid_layout(vec![]));
+        assert!(is_invalid_layout((0..17).map(|i| monitor(i * 800, 0, i == 0)).collect()));
+        assert!(MonitorLayout { monitors: (0..16).map(|i| monitor(i * 800, 0, i == 0)).collect() }.validate().is_ok());
+        assert!(is_invalid_layout(vec![monitor(0, 0, false)]));
+        assert!(is_invalid_layout(vec![monitor(0, 0, true), monitor(800, 0, true)]));
+        assert!(is_invalid_layout(vec![monitor(800, 0, true)]));
+        assert!(is_invalid_layout(vec![Monitor { left: 0, top: 0, right: -1, bottom: 599, is_primary: true }]));
+        // the desktop must fit in 32766 pixels
+        let wide = vec![monitor(0, 0, true), monitor(i32::MIN, 0, false)];
+        assert_eq!(MonitorLayout { monitors: wide.clone() }.desktop_size().0, u16::MAX);
+        assert!(is_invalid_layout(wide));
+    }
+
+    #[test]
+    fn test_client_monitor_data() {
+        let layout =
+            MonitorLayout { monitors: vec![Monitor { left: -1, top: 0, right: 799, bottom: 599, is_primary: true }] };
+        assert_eq!(
+            to_vec(&client_monitor_data(&layout)),
+            [0, 0, 0, 0, 1, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 31, 3, 0, 0, 87, 2, 0, 0, 1, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_read_monitor_def() {
+        let monitor = Monitor { left: -1280, top: 0, right: -1, bottom: 1023, is_primary: false };
+        let mut monitor_def = ts_monitor_def(None);
+        monitor_def.read_from_bytes(&to_vec(&ts_monitor_def(Some(&monitor)))).unwrap();
+        assert_eq!(Monitor::from_component(&monitor_def).unwrap(), monitor);
+    }
+
+    #[test]
+    fn test_monitor_at() {
+        let layout = dual_layout();
+        assert_eq!(layout.monitor_at(0, 0), Some(1));
+        assert_eq!(layout.monitor_at(1280, 0), Some(0));
+        assert_eq!(layout.monitor_at(100, 1050), None);
+    }
+
+    #[test]
+    fn test_monitor_layout_early_capability() {
+        let core = client_core_data(Some(ClientData {
+            width: 800,
+            height: 600,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: String::new(),
+            support_monitor_layout: true,
+            support_gfx: false,
+            identity: ClientIdentity::default(),
+            support_network_autodetect: false,
+        }));
+        assert_eq!(
+            cast!(DataType::U16, core["earlyCapabilityFlags"]).unwrap(),
+            CapabilityFlag::RnsUdCsSupportErrinfoPDU as u16
+                | CapabilityFlag::RnsUdCsSupportHeartbeatPDU as u16
+                | CapabilityFlag::RnsUdCsSupportMonitorLayoutPDU as u16
+        );
+    }
+
+    #[test]
+    fn test_network_autodetect_early_capability() {
+        let core = client_core_data(Some(ClientData {
+            width: 800,
+            height: 600,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: String::new(),
+            support_monitor_layout: false,
+            support_gfx: false,
+            identity: ClientIdentity::default(),
+            support_network_autodetect: true,
+        }));
+        let flags = cast!(DataType::U16, core["earlyCapabilityFlags"]).unwrap();
+        assert_ne!(flags & CapabilityFlag::RnsUdCsSupportNetcharAutodetect as u16, 0);
+        assert_ne!(flags & CapabilityFlag::RnsUdCsValidConnectionType as u16, 0);
+        assert_eq!(cast!(DataType::U8, core["connectionType"]).unwrap(), CONNECTION_TYPE_AUTODETECT);
+    }
+
+    #[test]
+    fn test_client_identity() {
+        let core = client_core_data(None);
+        assert_eq!(cast!(DataType::U32, core["clientBuild"]).unwrap(), 3790);
+        assert_eq!(cast!(DataType::U16, core["clientProductId"]).unwrap(), 1);
+
+        let core = client_core_data(Some(ClientData {
+            width: 800,
+            height: 600,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: String::new(),
+            support_monitor_layout: false,
+            support_gfx: false,
+            identity: ClientIdentity { build: 19041, product_id: 2, serial_number: 42 },
+            support_network_autodetect: false,
+        }));
+        assert_eq!(cast!(DataType::U32, core["clientBuild"]).unwrap(), 19041);
+        assert_eq!(cast!(DataType::U16, core["clientProductId"]).unwrap(), 2);
+        assert_eq!(cast!(DataType::U32, core["serialNumber"]).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_client_name_null_terminated() {
+        let core = client_core_data(Some(ClientData {
+            width: 800,
+            height: 600,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: "workstation-0123456789".to_string(),
+            support_monitor_layout: false,
+            support_gfx: false,
+            identity: ClientIdentity::default(),
+            support_network_autodetect: false,
+        }));
+        let name = cast!(DataType::Slice, core["clientName"]).unwrap();
+        assert_eq!(name.len(), 32);
+        assert_eq!(name[..30], "workstation-012".to_utf16_le()[..]);
+        assert_eq!(name[30..], [0, 0]);
+    }
+}
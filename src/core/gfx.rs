@@ -0,0 +1,807 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::codec::rfx::rfx_decompress;
+use crate::codec::zgfx::Zgfx;
+use crate::core::dvc::DvcHandler;
+use crate::core::event::BitmapEvent;
+use crate::model::data::{to_vec, Component, Message, Trame, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Name of the graphics pipeline dynamic channel
+pub const GFX_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Graphics";
+
+/// Capability versions advertised by the client
+/// See MS-RDPEGFX 2.2.3
+const CAPVERSION_8: u32 = 0x0008_0004;
+const CAPVERSION_81: u32 = 0x0008_0105;
+
+/// Size of the graphics pipeline header
+const HEADER_LENGTH: usize = 8;
+
+/// Largest width or height of the output
+/// See MS-RDPEGFX 2.2.2.14
+const MAX_OUTPUT_SIZE: u32 = 32766;
+
+/// Largest number of surfaces alive at the same time
+const MAX_SURFACES: usize = 64;
+
+/// Largest size in bytes of the output or of all the surfaces
+const MAX_SURFACES_SIZE: usize = 256 * 1024 * 1024;
+
+/// Number of cache slots without the small cache flag
+/// See MS-RDPEGFX 2.2.3.1
+const MAX_CACHE_SLOTS: u16 = 4096;
+
+/// Largest size in bytes of all the cached pixels
+/// which is the size of a cache without the small cache flag
+const MAX_CACHE_SIZE: usize = 100 * 1024 * 1024;
+
+/// Graphics pipeline commands
+/// See MS-RDPEGFX 2.2.1.5
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum CommandId {
+    WireToSurface1 = 0x0001,
+    WireToSurface2 = 0x0002,
+    DeleteEncodingContext = 0x0003,
+    SolidFill = 0x0004,
+    SurfaceToSurface = 0x0005,
+    SurfaceToCache = 0x0006,
+    CacheToSurface = 0x0007,
+    EvictCacheEntry = 0x0008,
+    CreateSurface = 0x0009,
+    DeleteSurface = 0x000a,
+    StartFrame = 0x000b,
+    EndFrame = 0x000c,
+    FrameAcknowledge = 0x000d,
+    ResetGraphics = 0x000e,
+    MapSurfaceToOutput = 0x000f,
+    CacheImportOffer = 0x0010,
+    CacheImportReply = 0x0011,
+    CapsAdvertise = 0x0012,
+    CapsConfirm = 0x0013,
+    MapSurfaceToWindow = 0x0015,
+    QoeFrameAcknowledge = 0x0016,
+    MapSurfaceToScaledOutput = 0x0017,
+    MapSurfaceToScaledWindow = 0x0018,
+}
+
+/// Codecs used by the wire to surface commands
+/// See MS-RDPEGFX 2.2.1.6
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum CodecId {
+    Uncompressed = 0x0000,
+    CaVideo = 0x0003,
+    ClearCodec = 0x0008,
+    Progressive = 0x0009,
+    Planar = 0x000a,
+    Avc420 = 0x000b,
+    Alpha = 0x000c,
+    Avc444 = 0x000e,
+    Avc444v2 = 0x000f,
+}
+
+fn gfx_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("GFX: {}", message)))
+}
+
+/// Header of all graphics pipeline PDU
+/// See MS-RDPEGFX 2.2.1.5
+fn gfx_pdu(command: CommandId, body: Component) -> Vec<u8> {
+    to_vec(&component![
+        "cmdId" => U16::LE(command as u16),
+        "flags" => U16::LE(0),
+        "pduLength" => U32::LE((body.length() as usize + HEADER_LENGTH) as u32),
+        "body" => body
+    ])
+}
+
+/// Capability set of the client
+/// No flags means the client can handle a full size cache
+fn caps_set(version: u32) -> Component {
+    component![
+        "version" => U32::LE(version),
+        "capsDataLength" => U32::LE(4),
+        "flags" => U32::LE(0)
+    ]
+}
+
+/// Client capabilities sent when the channel is opened
+/// See MS-RDPEGFX 2.2.2.18
+fn caps_advertise_pdu() -> Vec<u8> {
+    let caps = trame![caps_set(CAPVERSION_8), caps_set(CAPVERSION_81)];
+    gfx_pdu(
+        CommandId::CapsAdvertise,
+        component![
+            "capsSetCount" => U16::LE(caps.len() as u16),
+            "capsSets" => caps
+        ],
+    )
+}
+
+/// Acknowledge a frame to let the server send the next ones
+/// See MS-RDPEGFX 2.2.2.13
+fn frame_acknowledge_pdu(frame_id: u32, total_frames_decoded: u32) -> Vec<u8> {
+    gfx_pdu(
+        CommandId::FrameAcknowledge,
+        component![
+            "queueDepth" => U32::LE(0),
+            "frameId" => U32::LE(frame_id),
+            "totalFramesDecoded" => U32::LE(total_frames_decoded)
+        ],
+    )
+}
+
+/// Rectangle with exclusive right and bottom bounds
+/// See MS-RDPEGFX 2.2.1.2
+fn read_rect16(stream: &mut dyn Read) -> RdpResult<(u16, u16, u16, u16)> {
+    let left = stream.read_u16::<LittleEndian>()?;
+    let top = stream.read_u16::<LittleEndian>()?;
+    let right = stream.read_u16::<LittleEndian>()?;
+    let bottom = stream.read_u16::<LittleEndian>()?;
+    if right < left || bottom < top {
+        return Err(gfx_error(RdpErrorKind::InvalidData, "Invalid rectangle"));
+    }
+    Ok((left, top, right, bottom))
+}
+
+fn read_point16(stream: &mut dyn Read) -> RdpResult<(u16, u16)> {
+    Ok((stream.read_u16::<LittleEndian>()?, stream.read_u16::<LittleEndian>()?))
+}
+
+/// An offscreen surface created by the server
+///
+/// Pixels are stored as 32 bpp BGRA
+#[derive(Debug, Clone)]
+pub struct GfxSurface {
+    pub id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub pixel_format: u8,
+    /// Position of the surface into the output
+    /// None until the server map it
+    pub output_origin: Option<(u32, u32)>,
+    pub data: Vec<u8>,
+}
+
+impl GfxSurface {
+    pub fn new(id: u16, width: u16, height: u16, pixel_format: u8) -> Self {
+        GfxSurface {
+            id,
+            width,
+            height,
+            pixel_format,
+            output_origin: None,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Copy pixels into the surface
+    /// Parts outside of the surface are ignored
+    pub fn blit(&mut self, left: u16, top: u16, width: u16, height: u16, pixels: &[u8]) -> RdpResult<()> {
+        if pixels.len() < width as usize * height as usize * 4 {
+            return Err(gfx_error(RdpErrorKind::InvalidSize, "Pixels smaller than their destination"));
+        }
+        let count = (width as usize).min((self.width as usize).saturating_sub(left as usize));
+        for row in 0..(height as usize).min((self.height as usize).saturating_sub(top as usize)) {
+            let src = row * width as usize * 4;
+            let dest = ((top as usize + row) * self.width as usize + left as usize) * 4;
+            self.data[dest..dest + count * 4].copy_from_slice(&pixels[src..src + count * 4]);
+        }
+        Ok(())
+    }
+
+    /// The rectangle is inside the surface
+    pub fn contains(&self, left: u16, top: u16, width: u16, height: u16) -> bool {
+        u32::from(left) + u32::from(width) <= u32::from(self.width)
+            && u32::from(top) + u32::from(height) <= u32::from(self.height)
+    }
+
+    /// Copy pixels out of the surface
+    /// Parts outside of the surface are black
+    pub fn region(&self, left: u16, top: u16, width: u16, height: u16) -> Vec<u8> {
+        let mut result = vec![0; width as usize * height as usize * 4];
+        let count = (width as usize).min((self.width as usize).saturating_sub(left as usize));
+        for row in 0..(height as usize).min((self.height as usize).saturating_sub(top as usize)) {
+            let src = ((top as usize + row) * self.width as usize + left as usize) * 4;
+            let dest = row * width as usize * 4;
+            result[dest..dest + count * 4].copy_from_slice(&self.data[src..src + count * 4]);
+        }
+        result
+    }
+
+    /// Fill a rectangle with a single BGRA color
+    /// Parts outside of the surface are ignored
+    pub fn fill(&mut self, left: u16, top: u16, width: u16, height: u16, color: [u8; 4]) -> RdpResult<()> {
+        let width = width.min(self.width.saturating_sub(left));
+        let height = height.min(self.height.saturating_sub(top));
+        let pixels = color.repeat(width as usize * height as usize);
+        self.blit(left, top, width, height, &pixels)
+    }
+}
+
+/// State of the graphics pipeline
+///
+/// Surfaces are composited onto the output framebuffer
+/// and each updated area is notified as a bitmap at the end of a frame
+pub struct GfxPipeline {
+    width: u32,
+    height: u32,
+    /// 32 bpp BGRA output
+    framebuffer: Vec<u8>,
+    surfaces: HashMap<u16, GfxSurface>,
+    /// Cached pixels by slot with their size
+    cache: HashMap<u16, (u16, u16, Vec<u8>)>,
+    /// Output areas updated during the current frame
+    dirty: Vec<(u32, u32, u32, u32)>,
+    /// Bitmaps ready to be notified
+    updates: Vec<BitmapEvent>,
+    /// PDU to send to the server
+    output: Vec<Vec<u8>>,
+    zgfx: Zgfx,
+    frames_decoded: u32,
+}
+
+impl GfxPipeline {
+    pub fn new(width: u32, height: u32) -> Self {
+        GfxPipeline {
+            width,
+            height,
+            framebuffer: vec![0; width as usize * height as usize * 4],
+            surfaces: HashMap::new(),
+            cache: HashMap::new(),
+            dirty: Vec::new(),
+            updates: Vec::new(),
+            output: Vec::new(),
+            zgfx: Zgfx::new(),
+            frames_decoded: 0,
+        }
+    }
+
+    /// Size of the output
+    pub fn size(&self) -> (u32, u32) { (self.width, self.height) }
+
+    /// Output as 32 bpp BGRA pixels
+    pub fn framebuffer(&self) -> &[u8] { &self.framebuffer }
+
+    pub fn surface(&self, id: u16) -> Option<&GfxSurface> { self.surfaces.get(&id) }
+
+    /// Bitmaps of the areas updated by the completed frames
+    pub fn take_updates(&mut self) -> Vec<BitmapEvent> { std::mem::take(&mut self.updates) }
+
+    fn surface_mut(&mut self, id: u16) -> RdpResult<&mut GfxSurface> {
+        self.surfaces
+            .get_mut(&id)
+            .ok_or_else(|| gfx_error(RdpErrorKind::InvalidData, &format!("Unknown surface {}", id)))
+    }
+
+    /// Pixels of a rectangle which must be inside the surface
+    fn surface_region(&mut self, id: u16, left: u16, top: u16, width: u16, height: u16) -> RdpResult<Vec<u8>> {
+        let surface = self.surface_mut(id)?;
+        if !surface.contains(left, top, width, height) {
+            return Err(gfx_error(RdpErrorKind::InvalidSize, "Rectangle outside of the surface"));
+        }
+        Ok(surface.region(left, top, width, height))
+    }
+
+    /// Process a message received on the channel
+    pub fn process(&mut self, data: &[u8]) -> RdpResult<()> {
+        let data = self.zgfx.decompress(data)?;
+        let mut stream = Cursor::new(data.as_slice());
+        while (stream.position() as usize) < data.len() {
+            let start = stream.position() as usize;
+            let command = stream.read_u16::<LittleEndian>()?;
+            let _flags = stream.read_u16::<LittleEndian>()?;
+            let length = stream.read_u32::<LittleEndian>()? as usize;
+            if length < HEADER_LENGTH || start + length > data.len() {
+                return Err(gfx_error(RdpErrorKind::InvalidSize, "Invalid PDU length"));
+            }
+            let body = &data[start + HEADER_LENGTH..start + length];
+            match CommandId::try_from(command) {
+                Ok(command) => self.process_command(command, &mut Cursor::new(body))?,
+                Err(_) => log_debug!("GFX: unknown command {:#x}", command),
+            }
+            stream.set_position((start + length) as u64);
+        }
+        Ok(())
+    }
+
+    fn process_command(&mut self, command: CommandId, stream: &mut Cursor<&[u8]>) -> RdpResult<()> {
+        match command {
+            CommandId::CapsConfirm => {
+                let version = stream.read_u32::<LittleEndian>()?;
+                log_debug!("GFX: server confirmed capability version {:#x}", version);
+            }
+            CommandId::ResetGraphics => {
+                let width = stream.read_u32::<LittleEndian>()?;
+                let height = stream.read_u32::<LittleEndian>()?;
+                if width > MAX_OUTPUT_SIZE || height > MAX_OUTPUT_SIZE {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Output larger than 32766 pixels"));
+                }
+                if width as usize * height as usize * 4 > MAX_SURFACES_SIZE {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Output too large"));
+                }
+                self.width = width;
+                self.height = height;
+                self.framebuffer = vec![0; self.width as usize * self.height as usize * 4];
+                self.surfaces.clear();
+                self.cache.clear();
+                self.dirty.clear();
+            }
+            CommandId::CreateSurface => {
+                let id = stream.read_u16::<LittleEndian>()?;
+                let width = stream.read_u16::<LittleEndian>()?;
+                let height = stream.read_u16::<LittleEndian>()?;
+                let pixel_format = stream.read_u8()?;
+                if u32::from(width) > MAX_OUTPUT_SIZE || u32::from(height) > MAX_OUTPUT_SIZE {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Surface larger than 32766 pixels"));
+                }
+                if self.surfaces.len() >= MAX_SURFACES && !self.surfaces.contains_key(&id) {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Too many surfaces"));
+                }
+                // a surface with the same id is replaced
+                let others: usize =
+                    self.surfaces.values().filter(|surface| surface.id != id).map(|surface| surface.data.len()).sum();
+                if others + width as usize * height as usize * 4 > MAX_SURFACES_SIZE {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Surfaces too large"));
+                }
+                self.surfaces.insert(id, GfxSurface::new(id, width, height, pixel_format));
+            }
+            CommandId::DeleteSurface => {
+                self.surfaces.remove(&stream.read_u16::<LittleEndian>()?);
+            }
+            CommandId::MapSurfaceToOutput => {
+                let id = stream.read_u16::<LittleEndian>()?;
+                let _reserved = stream.read_u16::<LittleEndian>()?;
+                let x = stream.read_u32::<LittleEndian>()?;
+                let y = stream.read_u32::<LittleEndian>()?;
+                let surface = self.surface_mut(id)?;
+                surface.output_origin = Some((x, y));
+                let (width, height) = (surface.width, surface.height);
+                self.surface_to_output(id, 0, 0, width, height)?;
+            }
+            CommandId::StartFrame => {
+                let _timestamp = stream.read_u32::<LittleEndian>()?;
+                let _frame_id = stream.read_u32::<LittleEndian>()?;
+            }
+            CommandId::EndFrame => {
+                let frame_id = stream.read_u32::<LittleEndian>()?;
+                self.end_frame()?;
+                self.frames_decoded = self.frames_decoded.wrapping_add(1);
+                self.output.push(frame_acknowledge_pdu(frame_id, self.frames_decoded));
+            }
+            CommandId::WireToSurface1 => {
+                let id = stream.read_u16::<LittleEndian>()?;
+                let codec = stream.read_u16::<LittleEndian>()?;
+                let _pixel_format = stream.read_u8()?;
+                let (left, top, right, bottom) = read_rect16(stream)?;
+                let length = stream.read_u32::<LittleEndian>()? as usize;
+                let body = *stream.get_ref();
+                let start = stream.position() as usize;
+                if length > body.len() - start {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Bitmap data longer than the PDU"));
+                }
+                stream.set_position((start + length) as u64);
+                let bitmap = &body[start..start + length];
+                self.wire_to_surface(id, codec, left, top, right - left, bottom - top, bitmap)?;
+            }
+            CommandId::SolidFill => {
+                let id = stream.read_u16::<LittleEndian>()?;
+                let mut color = [0_u8; 4];
+                stream.read_exact(&mut color)?;
+                color[3] = 0xff;
+                for _ in 0..stream.read_u16::<LittleEndian>()? {
+                    let (left, top, right, bottom) = read_rect16(stream)?;
+                    self.surface_mut(id)?.fill(left, top, right - left, bottom - top, color)?;
+                    self.surface_to_output(id, left, top, right - left, bottom - top)?;
+                }
+            }
+            CommandId::SurfaceToSurface => {
+                let src = stream.read_u16::<LittleEndian>()?;
+                let dest = stream.read_u16::<LittleEndian>()?;
+                let (left, top, right, bottom) = read_rect16(stream)?;
+                let (width, height) = (right - left, bottom - top);
+                let pixels = self.surface_region(src, left, top, width, height)?;
+                for _ in 0..stream.read_u16::<LittleEndian>()? {
+                    let (x, y) = read_point16(stream)?;
+                    self.surface_mut(dest)?.blit(x, y, width, height, &pixels)?;
+                    self.surface_to_output(dest, x, y, width, height)?;
+                }
+            }
+            CommandId::SurfaceToCache => {
+                let id = stream.read_u16::<LittleEndian>()?;
+                let _cache_key = stream.read_u64::<LittleEndian>()?;
+                // See MS-RDPEGFX 2.2.2.7
+                let slot = stream.read_u16::<LittleEndian>()?;
+                if slot == 0 || slot > MAX_CACHE_SLOTS {
+                    return Err(gfx_error(RdpErrorKind::InvalidData, &format!("Invalid cache slot {}", slot)));
+                }
+                let (left, top, right, bottom) = read_rect16(stream)?;
+                let (width, height) = (right - left, bottom - top);
+                let pixels = self.surface_region(id, left, top, width, height)?;
+                // the entry of the slot is replaced
+                let others: usize =
+                    self.cache.iter().filter(|(other, _)| **other != slot).map(|(_, entry)| entry.2.len()).sum();
+                if others + pixels.len() > MAX_CACHE_SIZE {
+                    return Err(gfx_error(RdpErrorKind::InvalidSize, "Cache too large"));
+                }
+                self.cache.insert(slot, (width, height, pixels));
+            }
+            CommandId::CacheToSurface => {
+                let slot = stream.read_u16::<LittleEndian>()?;
+                let id = stream.read_u16::<LittleEndian>()?;
+                let (width, height, pixels) = self
+                    .cache
+                    .get(&slot)
+                    .cloned()
+                    .ok_or_else(|| gfx_error(RdpErrorKind::InvalidData, &format!("Unknown cache slot {}", slot)))?;
+                for _ in 0..stream.read_u16::<LittleEndian>()? {
+                    let (x, y) = read_point16(stream)?;
+                    self.surface_mut(id)?.blit(x, y, width, height, &pixels)?;
+                    self.surface_to_output(id, x, y, width, height)?;
+                }
+            }
+            CommandId::EvictCacheEntry => {
+                self.cache.remove(&stream.read_u16::<LittleEndian>()?);
+            }
+            command => log_debug!("GFX: command not handled {:?}", command),
+        }
+        Ok(())
+    }
+
+    /// Decode a bitmap into a surface
+    #[allow(clippy::too_many_arguments)]
+    fn wire_to_surface(
+        &mut self, id: u16, codec: u16, left: u16, top: u16, width: u16, height: u16, bitmap: &[u8],
+    ) -> RdpResult<()> {
+        let surface = self.surface_mut(id)?;
+        if !surface.contains(left, top, width, height) {
+            return Err(gfx_error(RdpErrorKind::InvalidSize, "Rectangle outside of the surface"));
+        }
+        match CodecId::try_from(codec) {
+            Ok(CodecId::Uncompressed) => surface.blit(left, top, width, height, bitmap)?,
+            Ok(CodecId::CaVideo) => {
+                // RemoteFX only updates the tiles of its region
+                let mut pixels = surface.region(left, top, width, height);
+                rfx_decompress(bitmap, u32::from(width), u32::from(height), &mut pixels)?;
+                surface.blit(left, top, width, height, &pixels)?;
+            }
+            _ => {
+                log_debug!("GFX: codec not handled {:#x}", codec);
+                return Ok(());
+            }
+        }
+        self.surface_to_output(id, left, top, width, height)
+    }
+
+    /// Composite an area of a surface onto the output
+    /// if the surface is mapped
+    ///
+    /// The area is clipped to the surface and to the output
+    fn surface_to_output(&mut self, id: u16, left: u16, top: u16, width: u16, height: u16) -> RdpResult<()> {
+        let surface = match self.surfaces.get(&id) {
+            Some(surface) => surface,
+            None => return Ok(()),
+        };
+        let (x, y) = match surface.output_origin {
+            Some(origin) => origin,
+            None => return Ok(()),
+        };
+        let width = width.min(surface.width.saturating_sub(left));
+        let height = height.min(surface.height.saturating_sub(top));
+        let outside = || gfx_error(RdpErrorKind::InvalidSize, "Surface area outside of the output coordinates");
+        let out_left = x.checked_add(u32::from(left)).ok_or_else(outside)?;
+        let out_top = y.checked_add(u32::from(top)).ok_or_else(outside)?;
+        let out_right = out_left.checked_add(u32::from(width)).ok_or_else(outside)?.min(self.width);
+        let out_bottom = out_top.checked_add(u32::from(height)).ok_or_else(outside)?.min(self.height);
+        if out_left >= out_right || out_top >= out_bottom {
+            return Ok(());
+        }
+
+        let pixels = surface.region(left, top, width, height);
+        let count = (out_right - out_left) as usize;
+        for row in 0..(out_bottom - out_top) as usize {
+            let src = row * width as usize * 4;
+            let dest = ((out_top as usize + row) * self.width as usize + out_left as usize) * 4;
+            self.framebuffer[dest..dest + count * 4].copy_from_slice(&pixels[src..src + count * 4]);
+        }
+        self.dirty.push((out_left, out_top, out_right, out_bottom));
+        Ok(())
+    }
+
+    /// Notify all areas updated during the frame
+    fn end_frame(&mut self) -> RdpResult<()> {
+        let coordinate =
+            |value: u32| u16::try_from(value).map_err(|_| gfx_error(RdpErrorKind::InvalidSize, "Output too large"));
+        for (left, top, right, bottom) in std::mem::take(&mut self.dirty) {
+            let width = (right - left) as usize;
+            let mut data = Vec::with_capacity(width * (bottom - top) as usize * 4);
+            for row in top..bottom {
+                let start = (row as usize * self.width as usize + left as usize) * 4;
+                data.extend_from_slice(&self.framebuffer[start..start + width * 4]);
+            }
+            self.updates.push(BitmapEvent {
+                dest_left: coordinate(left)?,
+                dest_top: coordinate(top)?,
+                dest_right: coordinate(right - 1)?,
+                dest_bottom: coordinate(bottom - 1)?,
+                width: coordinate(right - left)?,
+                height: coordinate(bottom - top)?,
+                bpp: 32,
+                is_compress: false,
+                codec: None,
+                monitor: None,
+                palette: None,
+                data,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Graphics pipeline dynamic channel
+///
+/// The pipeline is shared with the client
+/// which notifies its updates as bitmap events
+///
+/// # Example
+/// ```
+/// use rdp::core::dvc::DvcManager;
+/// use rdp::core::gfx::{GfxChannel, GFX_CHANNEL_NAME};
+/// let gfx = GfxChannel::new(800, 600);
+/// let mut manager = DvcManager::new();
+/// manager.register(GFX_CHANNEL_NAME, gfx.clone());
+/// assert_eq!(gfx.pipeline().unwrap().size(), (800, 600));
+/// ```
+#[derive(Clone)]
+pub struct GfxChannel {
+    pipeline: Arc<Mutex<GfxPipeline>>,
+}
+
+impl std::fmt::Debug for GfxChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GfxChannel").finish_non_exhaustive()
+    }
+}
+
+impl GfxChannel {
+    pub fn new(width: u32, height: u32) -> Self {
+        GfxChannel { pipeline: Arc::new(Mutex::new(GfxPipeline::new(width, height))) }
+    }
+
+    /// Lock the pipeline state
+    pub fn pipeline(&self) -> RdpResult<MutexGuard<'_, GfxPipeline>> {
+        self.pipeline.lock().map_err(|_| gfx_error(RdpErrorKind::Unknown, "Poisoned pipeline"))
+    }
+}
+
+impl DvcHandler for GfxChannel {
+    fn on_open(&mut self, _channel_id: u32) -> RdpResult<()> {
+        self.pipeline()?.output.push(caps_advertise_pdu());
+        Ok(())
+    }
+
+    fn on_data(&mut self, _channel_id: u32, data: &[u8]) -> RdpResult<()> { self.pipeline()?.process(data) }
+
+    fn take_output(&mut self) -> Vec<Vec<u8>> {
+        match self.pipeline() {
+            Ok(mut pipeline) => std::mem::take(&mut pipeline.output),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Wrap PDU into an uncompressed segment
+    fn segment(pdus: &[Vec<u8>]) -> Vec<u8> {
+        let mut result = vec![0xe0, 0x04];
+        for pdu in pdus {
+            result.extend(pdu);
+        }
+        result
+    }
+
+    #[test]
+    fn test_caps_advertise_pdu() {
+        assert_eq!(
+            caps_advertise_pdu(),
+            [0x12, 0, 0, 0, 34, 0, 0, 0, 2, 0, 4, 0, 8, 0, 4, 0, 0, 0, 0, 0, 0, 0, 5, 1, 8, 0, 4, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_surface_to_output() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        let create = gfx_pdu(
+            CommandId::CreateSurface,
+            component!["id" => U16::LE(1), "width" => U16::LE(2), "height" => U16::LE(2), "format" => 0x20_u8],
+        );
+        let map = gfx_pdu(
+            CommandId::MapSurfaceToOutput,
+            component!["id" => U16::LE(1), "reserved" => U16::LE(0), "x" => U32::LE(2), "y" => U32::LE(1)],
+        );
+        let fill = gfx_pdu(
+            CommandId::SolidFill,
+            component![
+                "id" => U16::LE(1),
+                "color" => vec![1_u8, 2, 3, 0],
+                "count" => U16::LE(1),
+                "rect" => vec![1_u8, 0, 0, 0, 2, 0, 1, 0]
+            ],
+        );
+        let end = gfx_pdu(CommandId::EndFrame, component!["frameId" => U32::LE(7)]);
+        pipeline.process(&segment(&[create, map, fill, end])).unwrap();
+
+        let offset = (4 + 3) * 4;
+        assert_eq!(pipeline.framebuffer()[offset..offset + 4], [1, 2, 3, 0xff]);
+        let updates = pipeline.take_updates();
+        assert_eq!(updates.len(), 2);
+        assert_eq!((updates[1].dest_left, updates[1].dest_top, updates[1].width), (3, 1, 1));
+        assert_eq!(pipeline.output, [frame_acknowledge_pdu(7, 1)]);
+    }
+
+    #[test]
+    fn test_unknown_surface() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        let delete = gfx_pdu(CommandId::DeleteSurface, component!["id" => U16::LE(1)]);
+        pipeline.process(&segment(&[delete])).unwrap();
+        let fill = gfx_pdu(
+            CommandId::SolidFill,
+            component!["id" => U16::LE(1), "color" => vec![0_u8; 4], "count" => U16::LE(0)],
+        );
+        pipeline.process(&segment(&[fill])).unwrap();
+        let map = gfx_pdu(
+            CommandId::MapSurfaceToOutput,
+            component!["id" => U16::LE(1), "reserved" => U16::LE(0), "x" => U32::LE(0), "y" => U32::LE(0)],
+        );
+        assert!(pipeline.process(&segment(&[map])).is_err());
+    }
+
+    fn is_invalid_size(result: RdpResult<()>) -> bool {
+        matches!(result, Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::InvalidSize)
+    }
+
+    #[test]
+    fn test_reset_graphics_too_large() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        let reset = gfx_pdu(
+            CommandId::ResetGraphics,
+            component!["width" => U32::LE(0xffff_ffff), "height" => U32::LE(0xffff_ffff), "monitorCount" => U32::LE(0)],
+        );
+        assert!(is_invalid_size(pipeline.process(&segment(&[reset]))));
+        assert_eq!(pipeline.size(), (4, 4));
+
+        let reset = gfx_pdu(
+            CommandId::ResetGraphics,
+            component!["width" => U32::LE(32766), "height" => U32::LE(2), "monitorCount" => U32::LE(0)],
+        );
+        pipeline.process(&segment(&[reset])).unwrap();
+        assert_eq!(pipeline.size(), (32766, 2));
+    }
+
+    fn create_surface_pdu(id: u16, width: u16, height: u16) -> Vec<u8> {
+        gfx_pdu(
+            CommandId::CreateSurface,
+            component![
+                "id" => U16::LE(id),
+                "width" => U16::LE(width),
+                "height" => U16::LE(height),
+                "format" => 0x20_u8
+            ],
+        )
+    }
+
+    #[test]
+    fn test_create_surface_limits() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        assert!(is_invalid_size(pipeline.process(&segment(&[create_surface_pdu(1, 0xffff, 0xffff)]))));
+        assert!(pipeline.surface(1).is_none());
+
+        let creates: Vec<_> = (0..MAX_SURFACES as u16).map(|id| create_surface_pdu(id, 1, 1)).collect();
+        pipeline.process(&segment(&creates)).unwrap();
+        assert!(is_invalid_size(pipeline.process(&segment(&[create_surface_pdu(MAX_SURFACES as u16, 1, 1)]))));
+        // an existing surface can still be replaced
+        pipeline.process(&segment(&[create_surface_pdu(0, 2, 2)])).unwrap();
+        assert_eq!(pipeline.surface(0).map(|surface| surface.width), Some(2));
+    }
+
+    #[test]
+    fn test_surfaces_size() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        // 256 MB
+        pipeline.process(&segment(&[create_surface_pdu(0, 8192, 8192)])).unwrap();
+        assert!(is_invalid_size(pipeline.process(&segment(&[create_surface_pdu(1, 1, 1)]))));
+        // the size of a replaced surface is released
+        pipeline.process(&segment(&[create_surface_pdu(0, 1, 1), create_surface_pdu(1, 1, 1)])).unwrap();
+    }
+
+    fn surface_to_cache_pdu(slot: u16, width: u16, height: u16) -> Vec<u8> {
+        gfx_pdu(
+            CommandId::SurfaceToCache,
+            component![
+                "id" => U16::LE(0),
+                "cacheKey" => vec![0_u8; 8],
+                "cacheSlot" => U16::LE(slot),
+                "rect" => trame![U16::LE(0), U16::LE(0), U16::LE(width), U16::LE(height)]
+            ],
+        )
+    }
+
+    #[test]
+    fn test_surface_to_cache_limits() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        // 100 MB
+        pipeline.process(&segment(&[create_surface_pdu(0, 8192, 3200)])).unwrap();
+        for slot in [0, MAX_CACHE_SLOTS + 1] {
+            let result = pipeline.process(&segment(&[surface_to_cache_pdu(slot, 1, 1)]));
+            assert!(matches!(result, Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::InvalidData));
+        }
+        pipeline.process(&segment(&[surface_to_cache_pdu(MAX_CACHE_SLOTS, 8192, 3200)])).unwrap();
+        assert!(is_invalid_size(pipeline.process(&segment(&[surface_to_cache_pdu(1, 1, 1)]))));
+        // the entry of a slot is replaced
+        let replace = [surface_to_cache_pdu(MAX_CACHE_SLOTS, 1, 1), surface_to_cache_pdu(1, 1, 1)];
+        pipeline.process(&segment(&replace)).unwrap();
+    }
+
+    #[test]
+    fn test_wire_to_surface_length() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        let wire = gfx_pdu(
+            CommandId::WireToSurface1,
+            component![
+                "id" => U16::LE(1),
+                "codec" => U16::LE(0),
+                "format" => 0x20_u8,
+                "rect" => vec![0_u8, 0, 0, 0, 1, 0, 1, 0],
+                "length" => U32::LE(0xffff_fff0),
+                "data" => vec![0_u8; 4]
+            ],
+        );
+        assert!(is_invalid_size(pipeline.process(&segment(&[wire]))));
+    }
+
+    #[test]
+    fn test_surface_to_output_overflow() {
+        let mut pipeline = GfxPipeline::new(4, 4);
+        let create = gfx_pdu(
+            CommandId::CreateSurface,
+            component!["id" => U16::LE(1), "width" => U16::LE(2), "height" => U16::LE(2), "format" => 0x20_u8],
+        );
+        let map = gfx_pdu(
+            CommandId::MapSurfaceToOutput,
+            component!["id" => U16::LE(1), "reserved" => U16::LE(0), "x" => U32::LE(0xffff_ffff), "y" => U32::LE(0)],
+        );
+        assert!(is_invalid_size(pipeline.process(&segment(&[create, map]))));
+
+        // an area past the surface is clipped to it
+        let map = gfx_pdu(
+            CommandId::MapSurfaceToOutput,
+            component!["id" => U16::LE(1), "reserved" => U16::LE(0), "x" => U32::LE(0), "y" => U32::LE(0)],
+        );
+        let fill = gfx_pdu(
+            CommandId::SolidFill,
+            component![
+                "id" => U16::LE(1),
+                "color" => vec![1_u8, 2, 3, 0],
+                "count" => U16::LE(1),
+                "rect" => vec![1_u8, 0, 1, 0, 0xff, 0xff, 0xff, 0xff]
+            ],
+        );
+        let end = gfx_pdu(CommandId::EndFrame, component!["frameId" => U32::LE(1)]);
+        pipeline.process(&segment(&[map, fill, end])).unwrap();
+        let updates = pipeline.take_updates();
+        let last = updates.last().unwrap();
+        assert_eq!((last.dest_left, last.dest_top, last.width, last.height), (1, 1, 1, 1));
+    }
+}
@@ -1,21 +1,36 @@
 use std::convert::TryFrom;
 use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
 
+use byteorder::{LittleEndian, ReadBytesExt as _, WriteBytesExt as _};
 use num_enum::TryFromPrimitive;
 
-use crate::core::capability::{capability_set, Capability};
-use crate::core::event::{BitmapEvent, RdpEvent};
-use crate::core::gcc::KeyboardLayout;
-use crate::core::{capability, mcs, tpkt};
+use crate::codec::rle::Palette;
+use crate::core::bitmap_cache::PersistentBitmapCache;
+use crate::core::capability::{
+    capability_set, BitmapCapabilityHandler, BitmapCodec, BitmapCodecsCapabilityHandler, Capability, CapabilityHandler,
+    CapabilityRegistry, GeneralCapabilityHandler, OrderSupportIndex, ServerCapabilities, ServerCapabilitySet,
+    StaticCapability, BITMAP_CACHE_ENTRIES,
+};
+use crate::core::error_info::ErrorInfoCode;
+use crate::core::event::{BitmapEvent, DisconnectReason, RdpEvent, SystemPointer};
+use crate::core::gcc::{ts_monitor_def, KeyboardLayout, Monitor, MonitorLayout};
+use crate::core::input::{ts_fp_input_events, MAX_FAST_PATH_EVENTS};
+use crate::core::orders::OrderDecoder;
+use crate::core::pointer::PointerCache;
+use crate::core::{capability, logon, mcs, pointer, sec, surface, tpkt};
 use crate::model::data::{
     to_vec, Array, Check, Component, DataType, DynOption, Message, MessageOption, Trame, U16, U32,
 };
-use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult, RdpWarning, WarningHandler};
+use crate::model::time::Instant;
 
 /// Raw PDU type use by the protocol
+/// The protocol version 1 is in the high bits
 #[repr(u16)]
-#[derive(Copy, Clone, Eq, PartialEq, Debug, TryFromPrimitive)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, TryFromPrimitive)]
 enum PduType {
+    #[default]
     Demandactivepdu = 0x11,
     Confirmactivepdu = 0x13,
     Deactivateallpdu = 0x16,
@@ -35,28 +50,168 @@ struct Pdu {
 impl Pdu {
     /// Build a PDU structure from reading stream
     pub fn from_stream(stream: &mut dyn Read) -> RdpResult<Self> {
-        let mut header = share_control_header(None, None, None);
+        let mut header = ShareControlHeader::default();
         header.read(stream)?;
-        Pdu::from_control(&header)
+        let mut pdu = vec![0; header.pdu_length()];
+        stream.read_exact(&mut pdu)?;
+        Pdu::from_control(header.pdu_type, &pdu)
     }
 
-    /// Build a PDU data directly from a control message
-    pub fn from_control(control: &Component) -> RdpResult<Self> {
-        let pdu_type = cast!(DataType::U16, control["pduType"])?;
-        let mut pdu = match PduType::try_from(pdu_type)? {
+    /// Build a PDU from the message following its share control header
+    /// The share data header is kept in the message of a data PDU
+    pub fn from_control(pdu_type: PduType, message: &[u8]) -> RdpResult<Self> {
+        let mut pdu = match pdu_type {
             PduType::Demandactivepdu => ts_demand_active_pdu(),
-            PduType::Datapdu => share_data_header(None, None, None),
+            PduType::Datapdu => Pdu { pdu_type, message: component!["pduMessage" => Vec::<u8>::new()] },
             PduType::Confirmactivepdu => ts_confirm_active_pdu(None, None, None),
             PduType::Deactivateallpdu => ts_deactivate_all_pdu(),
             _ => {
                 return Err(Error::RdpError(RdpError::new(RdpErrorKind::NotImplemented, "GLOBAL: PDU not implemented")))
             }
         };
-        pdu.message.read(&mut Cursor::new(cast!(DataType::Slice, control["pduMessage"])?))?;
+        pdu.message.read_from_bytes(message)?;
         Ok(pdu)
     }
 }
 
+/// Header of all slow path PDUs
+/// The total length includes the header
+///
+/// See MS-RDPBCGR 2.2.8.1.1.1.1
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+struct ShareControlHeader {
+    total_length: u16,
+    pdu_type: PduType,
+    /// Channel id of the sender
+    pdu_source: u16,
+}
+
+impl ShareControlHeader {
+    const LENGTH: usize = 6;
+
+    /// `RdpErrorKind::InvalidSize` if the PDU doesn't fit in the 16 bits length
+    fn new(pdu_type: PduType, pdu_source: u16, pdu_length: usize) -> RdpResult<Self> {
+        let total_length = U16::try_from((pdu_length + Self::LENGTH) as u64)?.inner();
+        Ok(ShareControlHeader { total_length, pdu_type, pdu_source })
+    }
+
+    /// Length of the PDU following the header
+    fn pdu_length(&self) -> usize { usize::from(self.total_length).saturating_sub(Self::LENGTH) }
+}
+
+impl Message for ShareControlHeader {
+    fn write(&self, writer: &mut dyn Write) -> RdpResult<()> {
+        writer.write_u16::<LittleEndian>(self.total_length)?;
+        writer.write_u16::<LittleEndian>(self.pdu_type as u16)?;
+        writer.write_u16::<LittleEndian>(self.pdu_source)?;
+        Ok(())
+    }
+
+    fn read(&mut self, reader: &mut dyn Read) -> RdpResult<()> {
+        self.total_length = reader.read_u16::<LittleEndian>()?;
+        self.pdu_type = PduType::try_from(reader.read_u16::<LittleEndian>()?)?;
+        self.pdu_source = reader.read_u16::<LittleEndian>()?;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 { Self::LENGTH as u64 }
+
+    /// Fields are read from the struct
+    fn visit(&self) -> DataType<'_> { DataType::None }
+
+    fn options(&self) -> MessageOption { MessageOption::None }
+}
+
+/// Priority of a data PDU
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Default, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum StreamId {
+    #[default]
+    Undefined = 0x00,
+    Low = 0x01,
+    Med = 0x02,
+    Hi = 0x04,
+}
+
+/// Header of data PDUs following the share control header
+/// Compressed PDUs are not supported
+///
+/// See MS-RDPBCGR 2.2.8.1.1.1.2
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+struct ShareDataHeader {
+    share_id: u32,
+    stream_id: StreamId,
+    uncompressed_length: u16,
+    pdu_type2: PduType2,
+    compressed_type: u8,
+    compressed_length: u16,
+}
+
+impl ShareDataHeader {
+    const LENGTH: usize = 12;
+
+    /// The uncompressed length includes both headers
+    fn new(share_id: u32, pdu_type2: PduType2, pdu_length: usize) -> RdpResult<Self> {
+        let uncompressed_length = U16::try_from((pdu_length + ShareControlHeader::LENGTH + Self::LENGTH) as u64)?;
+        Ok(ShareDataHeader {
+            share_id,
+            stream_id: StreamId::Low,
+            uncompressed_length: uncompressed_length.inner(),
+            pdu_type2,
+            compressed_type: 0,
+            compressed_length: 0,
+        })
+    }
+}
+
+impl Message for ShareDataHeader {
+    fn write(&self, writer: &mut dyn Write) -> RdpResult<()> {
+        writer.write_u32::<LittleEndian>(self.share_id)?;
+        // pad1
+        writer.write_u8(0)?;
+        writer.write_u8(self.stream_id as u8)?;
+        writer.write_u16::<LittleEndian>(self.uncompressed_length)?;
+        writer.write_u8(self.pdu_type2 as u8)?;
+        writer.write_u8(self.compressed_type)?;
+        writer.write_u16::<LittleEndian>(self.compressed_length)?;
+        Ok(())
+    }
+
+    fn read(&mut self, reader: &mut dyn Read) -> RdpResult<()> {
+        self.share_id = reader.read_u32::<LittleEndian>()?;
+        reader.read_u8()?;
+        self.stream_id = StreamId::try_from(reader.read_u8()?)?;
+        self.uncompressed_length = reader.read_u16::<LittleEndian>()?;
+        self.pdu_type2 = PduType2::try_from(reader.read_u8()?)?;
+        self.compressed_type = reader.read_u8()?;
+        self.compressed_length = reader.read_u16::<LittleEndian>()?;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 { Self::LENGTH as u64 }
+
+    /// Fields are read from the struct
+    fn visit(&self) -> DataType<'_> { DataType::None }
+
+    fn options(&self) -> MessageOption { MessageOption::None }
+}
+
+/// PDU following its share control header
+fn share_control_pdu(pdu_type: PduType, pdu_source: u16, pdu: Vec<u8>) -> RdpResult<Trame> {
+    Ok(trame![ShareControlHeader::new(pdu_type, pdu_source, pdu.len())?, pdu])
+}
+
+/// Data PDU following its share control and share data headers
+fn share_data_pdu(message: DataPdu, share_id: Option<u32>, pdu_source: u16) -> RdpResult<Trame> {
+    let pdu = to_vec(&message.message);
+    Ok(trame![
+        ShareControlHeader::new(PduType::Datapdu, pdu_source, ShareDataHeader::LENGTH + pdu.len())?,
+        ShareDataHeader::new(share_id.unwrap_or(0), message.pdu_type, pdu.len())?,
+        pdu
+    ])
+}
+
 /// Demand Active PDU
 /// First PDU send from server to client
 /// This payload include all capabilities
@@ -117,37 +272,37 @@ fn ts_deactivate_all_pdu() -> Pdu {
     }
 }
 
-/// All Data PDU share the same layout
-fn share_data_header(share_id: Option<u32>, pdu_type_2: Option<PduType2>, message: Option<Vec<u8>>) -> Pdu {
-    let default_message = message.unwrap_or_default();
-    Pdu {
-        pdu_type: PduType::Datapdu,
-        message: component![
-            "shareId" => U32::LE(share_id.unwrap_or(0)),
-            "pad1" => 0_u8,
-            "streamId" => 1_u8,
-            "uncompressedLength" => DynOption::new(U16::LE(default_message.length() as u16 + 18), | size | MessageOption::Size("payload".to_string(), size.inner() as usize - 18)),
-            "pduType2" => pdu_type_2.unwrap_or(PduType2::ArcStatusPdu) as u8,
-            "compressedType" => 0_u8,
-            "compressedLength" => U16::LE(0),
-            "payload" => default_message
-        ],
-    }
-}
+/// Marker of a flow control PDU
+/// in place of the total length of a share control header
+const FLOW_MARKER: u16 = 0x8000;
+
+/// Pointer shapes the server can recall by cache index
+const POINTER_CACHE_SIZE: u16 = 25;
 
-/// This is the main PDU payload format
-/// It use the share control header to dispatch between all PDU
-fn share_control_header(pdu_type: Option<PduType>, pdu_source: Option<u16>, message: Option<Vec<u8>>) -> Component {
-    let default_message = message.unwrap_or_default();
+/// Values of pduTypeFlow
+/// A share control header can also start with the flow marker
+/// when its total length is 0x8000
+const FLOW_PDU_TYPES: std::ops::RangeInclusive<u8> = 0x41..=0x44;
+
+/// Flow control PDU
+/// Sent by old servers, a flow test asks for a flow response with the same number
+///
+/// See TS_FLOW_PDU in MS-RDPBCGR
+fn ts_flow_pdu() -> Component {
     component![
-        "totalLength" => DynOption::new(U16::LE(default_message.length() as u16 + 6), |total| MessageOption::Size("pduMessage".to_string(), total.inner() as usize - 6)),
-        "pduType" => U16::LE(pdu_type.unwrap_or(PduType::Demandactivepdu) as u16),
-        "PDUSource" => Some(U16::LE(pdu_source.unwrap_or(0))),
-        "pduMessage" => default_message
+        "flowMarker" => Check::new(U16::LE(FLOW_MARKER)),
+        "pduTypeFlow" => 0_u8,
+        "pad8bits" => 0_u8,
+        "flowIdentifier" => 0_u8,
+        "flowNumber" => 0_u8,
+        "pduSource" => U16::LE(0)
     ]
 }
 
-#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+/// Type of the PDU following a share data header
+///
+/// See MS-RDPBCGR 2.2.8.1.1.1.2
+#[derive(Debug, Default, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 enum PduType2 {
     Update = 0x02,
@@ -171,6 +326,7 @@ enum PduType2 {
     SetErrorInfoPdu = 0x2F,
     DrawninegridErrorPdu = 0x30,
     DrawgdiplusErrorPdu = 0x31,
+    #[default]
     ArcStatusPdu = 0x32,
     StatusInfoPdu = 0x36,
     MonitorLayoutPdu = 0x37,
@@ -189,13 +345,21 @@ impl DataPdu {
     /// User must check that the PDU is a DATA PDU
     /// If not this function will panic
     pub fn from_pdu(data_pdu: &Pdu) -> RdpResult<DataPdu> {
-        let pdu_type = PduType2::try_from(cast!(DataType::U8, data_pdu.message["pduType2"])?)?;
+        let mut message = cast!(DataType::Slice, data_pdu.message["pduMessage"])?;
+        let mut header = ShareDataHeader::default();
+        header.read(&mut message)?;
+        let pdu_type = header.pdu_type2;
         let mut result = match pdu_type {
             PduType2::Synchronize => ts_synchronize_pdu(None),
             PduType2::Control => ts_control_pdu(None),
             PduType2::Fontlist => ts_font_list_pdu(),
             PduType2::Fontmap => ts_font_map_pdu(),
             PduType2::SetErrorInfoPdu => ts_set_error_info_pdu(),
+            PduType2::MonitorLayoutPdu => ts_monitor_layout_pdu(),
+            PduType2::Update => ts_update_pdu(),
+            PduType2::Pointer => ts_pointer_pdu(),
+            PduType2::SaveSessionInfo => ts_save_session_info_pdu(),
+            PduType2::SuppressOutput => ts_suppress_output_pdu(None),
             _ => {
                 return Err(Error::RdpError(RdpError::new(
                     RdpErrorKind::NotImplemented,
@@ -203,7 +367,7 @@ impl DataPdu {
                 )))
             }
         };
-        result.message.read(&mut Cursor::new(cast!(DataType::Slice, data_pdu.message["payload"])?))?;
+        result.message.read_from_bytes(message)?;
         Ok(result)
     }
 }
@@ -236,6 +400,42 @@ fn ts_font_list_pdu() -> DataPdu {
     }
 }
 
+/// First and last PDUs of the persistent key list
+/// See MS-RDPBCGR 2.2.1.17.1
+const PERSIST_FIRST_PDU: u8 = 0x01;
+const PERSIST_LAST_PDU: u8 = 0x02;
+
+/// Maximum number of keys of a persistent key list PDU
+const MAX_PERSISTENT_KEYS_PER_PDU: usize = 169;
+
+/// Persistent key list PDU
+/// Keys of the bitmaps kept from previous sessions
+///
+/// See MS-RDPBCGR 2.2.1.17.1
+fn ts_bitmap_cache_persistent_list_pdu(
+    num_entries: [u16; 5], total_entries: [u16; 5], flags: u8, keys: &[u64],
+) -> DataPdu {
+    DataPdu {
+        pdu_type: PduType2::BitmapcachePersistentList,
+        message: component![
+            "numEntriesCache0" => U16::LE(num_entries[0]),
+            "numEntriesCache1" => U16::LE(num_entries[1]),
+            "numEntriesCache2" => U16::LE(num_entries[2]),
+            "numEntriesCache3" => U16::LE(num_entries[3]),
+            "numEntriesCache4" => U16::LE(num_entries[4]),
+            "totalEntriesCache0" => U16::LE(total_entries[0]),
+            "totalEntriesCache1" => U16::LE(total_entries[1]),
+            "totalEntriesCache2" => U16::LE(total_entries[2]),
+            "totalEntriesCache3" => U16::LE(total_entries[3]),
+            "totalEntriesCache4" => U16::LE(total_entries[4]),
+            "bBitMask" => flags,
+            "Pad2" => 0_u8,
+            "Pad3" => U16::LE(0),
+            "entries" => keys.iter().flat_map(|key| key.to_le_bytes()).collect::<Vec<u8>>()
+        ],
+    }
+}
+
 /// Error info PDU
 ///
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a21a1bd9-2303-49c1-90ec-3932435c248c
@@ -248,10 +448,88 @@ fn ts_set_error_info_pdu() -> DataPdu {
     }
 }
 
+/// Monitor layout PDU
+/// Sent by the server when the monitor topology change
+///
+/// See MS-RDPBCGR 2.2.12.1
+fn ts_monitor_layout_pdu() -> DataPdu {
+    DataPdu {
+        pdu_type: PduType2::MonitorLayoutPdu,
+        message: component![
+            "monitorCount" => DynOption::new(U32::LE(0), |count| MessageOption::Size("monitorDefArray".to_string(), count.inner() as usize * 20)),
+            "monitorDefArray" => Array::new(|| ts_monitor_def(None))
+        ],
+    }
+}
+
+/// Slow path update PDU
+/// The payload depends on the update type
+///
+/// See MS-RDPBCGR 2.2.9.1.1.3
+fn ts_update_pdu() -> DataPdu {
+    DataPdu {
+        pdu_type: PduType2::Update,
+        message: component![
+            "updateType" => U16::LE(0),
+            "payload" => Vec::<u8>::new()
+        ],
+    }
+}
+
+/// Slow path pointer update
+/// The message is decoded by the pointer module
+///
+/// See MS-RDPBCGR 2.2.9.1.1.4
+fn ts_pointer_pdu() -> DataPdu {
+    DataPdu {
+        pdu_type: PduType2::Pointer,
+        message: component![
+            "pointerData" => Vec::<u8>::new()
+        ],
+    }
+}
+
+/// Save session info PDU
+/// The payload depends on the information type
+///
+/// See MS-RDPBCGR 2.2.10.1
+fn ts_save_session_info_pdu() -> DataPdu {
+    DataPdu {
+        pdu_type: PduType2::SaveSessionInfo,
+        message: component![
+            "infoData" => Vec::<u8>::new()
+        ],
+    }
+}
+
+/// Types of the slow path updates
 #[repr(u16)]
-#[allow(dead_code)]
-#[derive(Clone, Copy, Debug)]
-enum Action {
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum UpdateType {
+    Orders = 0x0000,
+    Bitmap = 0x0001,
+    Palette = 0x0002,
+    Synchronize = 0x0003,
+}
+
+/// Drawing orders of a slow path update
+///
+/// See MS-RDPEGDI 2.2.2.1
+fn ts_update_orders() -> Component {
+    component![
+        "pad2OctetsA" => U16::LE(0),
+        "numberOrders" => U16::LE(0),
+        "pad2OctetsB" => U16::LE(0),
+        "orderData" => Vec::<u8>::new()
+    ]
+}
+
+/// Action of a control PDU
+///
+/// See MS-RDPBCGR 2.2.1.15.1
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ControlAction {
     RequestControl = 0x0001,
     GrantedControl = 0x0002,
     Detach = 0x0003,
@@ -261,11 +539,11 @@ enum Action {
 /// Control payload send during pdu handshake
 ///
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/0448f397-aa11-455d-81b1-f1265085239d
-fn ts_control_pdu(action: Option<Action>) -> DataPdu {
+fn ts_control_pdu(action: Option<ControlAction>) -> DataPdu {
     DataPdu {
         pdu_type: PduType2::Control,
         message: component![
-            "action" => U16::LE(action.unwrap_or(Action::Cooperate) as u16),
+            "action" => U16::LE(action.unwrap_or(ControlAction::Cooperate) as u16),
             "grantId" => U16::LE(0),
             "controlId" => U32::LE(0)
         ],
@@ -287,6 +565,153 @@ fn ts_font_map_pdu() -> DataPdu {
     }
 }
 
+/// Write a data PDU with its share headers
+fn write_share_data_pdu(
+    message: DataPdu, share_id: Option<u32>, user_id: u16, stream: &mut dyn Write,
+) -> RdpResult<()> {
+    share_data_pdu(message, share_id, user_id)?.write(stream)
+}
+
+/// Write the synchronize PDU starting the client finalization
+/// The target is the channel of the server
+///
+/// See MS-RDPBCGR 2.2.1.14
+///
+/// # Example
+/// ```
+/// use rdp::core::global::write_synchronize_pdu;
+/// let mut stream = Vec::new();
+/// write_synchronize_pdu(Some(0x103ea), 1007, 1002, &mut stream).unwrap();
+/// assert_eq!(stream[14..], [0x1f, 0, 0, 0, 1, 0, 0xea, 3]);
+/// ```
+pub fn write_synchronize_pdu(
+    share_id: Option<u32>, user_id: u16, channel_id: u16, stream: &mut dyn Write,
+) -> RdpResult<()> {
+    write_share_data_pdu(ts_synchronize_pdu(Some(channel_id)), share_id, user_id, stream)
+}
+
+/// Write a control PDU of the client finalization
+/// The client sends the cooperate then the request control actions
+///
+/// See MS-RDPBCGR 2.2.1.15
+///
+/// # Example
+/// ```
+/// use rdp::core::global::{write_control_pdu, ControlAction};
+/// let mut stream = Vec::new();
+/// write_control_pdu(Some(0x103ea), 1007, ControlAction::RequestControl, &mut stream).unwrap();
+/// assert_eq!(stream[14..], [0x14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+/// ```
+pub fn write_control_pdu(
+    share_id: Option<u32>, user_id: u16, action: ControlAction, stream: &mut dyn Write,
+) -> RdpResult<()> {
+    write_share_data_pdu(ts_control_pdu(Some(action)), share_id, user_id, stream)
+}
+
+/// Write the font list PDU ending the client finalization
+/// The list is empty as required by the specification
+///
+/// See MS-RDPBCGR 2.2.1.18
+///
+/// # Example
+/// ```
+/// use rdp::core::global::write_font_list_pdu;
+/// let mut stream = Vec::new();
+/// write_font_list_pdu(Some(0x103ea), 1007, &mut stream).unwrap();
+/// assert_eq!(stream[14..], [0x27, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0x32, 0]);
+/// ```
+pub fn write_font_list_pdu(share_id: Option<u32>, user_id: u16, stream: &mut dyn Write) -> RdpResult<()> {
+    write_share_data_pdu(ts_font_list_pdu(), share_id, user_id, stream)
+}
+
+/// Write the persistent key list PDUs of the client finalization
+/// Keys are given by cache, a PDU carries at most 169 keys
+/// Nothing is written without keys
+///
+/// See MS-RDPBCGR 2.2.1.17
+///
+/// # Example
+/// ```
+/// use rdp::core::global::write_persistent_key_list_pdus;
+/// let pdus = write_persistent_key_list_pdus(Some(0x103ea), 1007, &[vec![1, 2], vec![3]]).unwrap();
+/// assert_eq!(pdus.len(), 1);
+/// assert_eq!(pdus[0][18..42], [2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+/// assert_eq!(pdus[0][42..], [[1, 0, 0, 0, 0, 0, 0, 0], [2, 0, 0, 0, 0, 0, 0, 0], [3, 0, 0, 0, 0, 0, 0, 0]].concat());
+/// ```
+pub fn write_persistent_key_list_pdus(
+    share_id: Option<u32>, user_id: u16, keys: &[Vec<u64>],
+) -> RdpResult<Vec<Vec<u8>>> {
+    let mut total_entries = [0; 5];
+    let mut entries = Vec::new();
+    for (cache_id, cache_keys) in keys.iter().take(total_entries.len()).enumerate() {
+        total_entries[cache_id] = u16::try_from(cache_keys.len()).map_err(|_| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "GLOBAL: Too many persistent keys"))
+        })?;
+        entries.extend(cache_keys.iter().map(|key| (cache_id, *key)));
+    }
+    let chunks: Vec<&[(usize, u64)]> = entries.chunks(MAX_PERSISTENT_KEYS_PER_PDU).collect();
+    let mut pdus = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.iter().enumerate() {
+        let mut num_entries = [0; 5];
+        chunk.iter().for_each(|(cache_id, _)| num_entries[*cache_id] += 1);
+        let mut flags = 0;
+        if index == 0 {
+            flags |= PERSIST_FIRST_PDU;
+        }
+        if index + 1 == chunks.len() {
+            flags |= PERSIST_LAST_PDU;
+        }
+        let keys: Vec<u64> = chunk.iter().map(|(_, key)| *key).collect();
+        let mut pdu = Vec::new();
+        write_share_data_pdu(
+            ts_bitmap_cache_persistent_list_pdu(num_entries, total_entries, flags, &keys),
+            share_id,
+            user_id,
+            &mut pdu,
+        )?;
+        pdus.push(pdu);
+    }
+    Ok(pdus)
+}
+
+/// Read the font map PDU ending the server finalization
+/// Bitmap updates are only sent after it
+///
+/// This function return true if it read the expected PDU
+///
+/// See MS-RDPBCGR 2.2.1.22
+pub fn read_font_map_pdu(stream: &mut dyn Read) -> RdpResult<bool> {
+    let pdu = Pdu::from_stream(stream)?;
+    if pdu.pdu_type != PduType::Datapdu {
+        return Ok(false);
+    }
+    Ok(DataPdu::from_pdu(&pdu)?.pdu_type == PduType2::Fontmap)
+}
+
+/// Ask the server to stop or restart sending display updates
+/// The desktop rectangle is only present when updates are allowed
+///
+/// See MS-RDPBCGR 2.2.11.3
+fn ts_suppress_output_pdu(desktop: Option<(u16, u16)>) -> DataPdu {
+    let desktop_rect = match desktop {
+        Some((width, height)) => to_vec(&component![
+            "left" => U16::LE(0),
+            "top" => U16::LE(0),
+            "right" => U16::LE(width.saturating_sub(1)),
+            "bottom" => U16::LE(height.saturating_sub(1))
+        ]),
+        None => vec![],
+    };
+    DataPdu {
+        pdu_type: PduType2::SuppressOutput,
+        message: component![
+            "allowDisplayUpdates" => u8::from(desktop.is_some()),
+            "pad3Octets" => vec![0_u8; 3],
+            "desktopRect" => desktop_rect
+        ],
+    }
+}
+
 /// Send input event as slow path
 fn ts_input_pdu_data(events: Option<Array<Component>>) -> DataPdu {
     let default_events = events.unwrap_or(Array::new(|| ts_input_event(None, None)));
@@ -326,8 +751,8 @@ pub enum InputEventType {
 /// All Terminal Service Slow Path Input Event
 #[derive(Debug)]
 pub struct TSInputEvent {
-    event_type: InputEventType,
-    message: Component,
+    pub(crate) event_type: InputEventType,
+    pub(crate) message: Component,
 }
 
 /// All supported flags for pointer event
@@ -347,6 +772,45 @@ pub enum PointerFlag {
     Button3 = 0x4000,
 }
 
+/// Rotation of a single wheel notch
+pub const WHEEL_DELTA: i16 = 120;
+
+/// Pointer flags of a wheel rotation
+/// The rotation is a 9 bits two's complement value
+///
+/// # Example
+/// ```
+/// use rdp::core::global::wheel_flags;
+/// assert_eq!(wheel_flags(false, 120), 0x0278);
+/// assert_eq!(wheel_flags(false, -120), 0x0388);
+/// assert_eq!(wheel_flags(true, 1000), 0x04ff);
+/// ```
+pub fn wheel_flags(horizontal: bool, rotation: i16) -> u16 {
+    let flag = if horizontal { PointerFlag::WheelH } else { PointerFlag::WheelV };
+    flag as u16 | (rotation.clamp(-256, 255) as u16 & PointerFlag::RotationMask as u16)
+}
+
+/// Pointer flags of a wheel rotation larger than a single event
+/// The rotation is split into events of at most 255 units
+///
+/// # Example
+/// ```
+/// use rdp::core::global::wheel_steps;
+/// assert_eq!(wheel_steps(false, 120), vec![0x0278]);
+/// assert_eq!(wheel_steps(false, 600), vec![0x02ff, 0x02ff, 0x025a]);
+/// assert_eq!(wheel_steps(true, -300), vec![0x0501, 0x05d3]);
+/// ```
+pub fn wheel_steps(horizontal: bool, rotation: i16) -> Vec<u16> {
+    let mut remaining = i32::from(rotation);
+    let mut steps = Vec::new();
+    while remaining != 0 {
+        let step = remaining.clamp(-255, 255);
+        steps.push(wheel_flags(horizontal, step as i16));
+        remaining -= step;
+    }
+    steps
+}
+
 /// A pointer event
 ///
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/2c1ced34-340a-46cd-be6e-fc8cab7c3b17
@@ -361,6 +825,31 @@ pub fn ts_pointer_event(flags: Option<u16>, x: Option<u16>, y: Option<u16>) -> T
     }
 }
 
+/// Flags of the extended pointer event
+///
+/// See MS-RDPBCGR 2.2.8.1.1.3.1.1.4
+#[repr(u16)]
+#[derive(Clone, Copy, Debug)]
+pub enum PointerXFlag {
+    Down = 0x8000,
+    Button1 = 0x0001,
+    Button2 = 0x0002,
+}
+
+/// An extended pointer event for the X1 and X2 buttons
+///
+/// See MS-RDPBCGR 2.2.8.1.1.3.1.1.4
+pub fn ts_pointerx_event(flags: Option<u16>, x: Option<u16>, y: Option<u16>) -> TSInputEvent {
+    TSInputEvent {
+        event_type: InputEventType::Mousex,
+        message: component![
+            "pointerFlags" => U16::LE(flags.unwrap_or(0)),
+            "xPos" => U16::LE(x.unwrap_or(0)),
+            "yPos" => U16::LE(y.unwrap_or(0))
+        ],
+    }
+}
+
 #[repr(u16)]
 #[derive(Clone, Copy, Debug)]
 pub enum KeyboardFlag {
@@ -382,6 +871,47 @@ pub fn ts_keyboard_event(flags: Option<u16>, key_code: Option<u16>) -> TSInputEv
     }
 }
 
+/// Unicode keyboard event
+/// Use to send a UTF-16 code unit without scancode
+///
+/// See MS-RDPBCGR 2.2.8.1.1.3.1.1.2
+pub fn ts_unicode_keyboard_event(flags: Option<u16>, unicode_code: Option<u16>) -> TSInputEvent {
+    TSInputEvent {
+        event_type: InputEventType::Unicode,
+        message: component![
+            "keyboardFlags" => U16::LE(flags.unwrap_or(0)),
+            "unicodeCode" => U16::LE(unicode_code.unwrap_or(0)),
+            "pad2Octets" => U16::LE(0)
+        ],
+    }
+}
+
+/// Lock keys of the synchronize event
+///
+/// See MS-RDPBCGR 2.2.8.1.1.3.1.1.5
+#[repr(u32)]
+#[derive(Clone, Copy, Debug)]
+pub enum ToggleFlag {
+    ScrollLock = 0x0001,
+    NumLock = 0x0002,
+    CapsLock = 0x0004,
+    KanaLock = 0x0008,
+}
+
+/// Synchronize event
+/// Set the state of the lock keys on the server
+///
+/// See MS-RDPBCGR 2.2.8.1.1.3.1.1.5
+pub fn ts_sync_event(toggle_flags: Option<u32>) -> TSInputEvent {
+    TSInputEvent {
+        event_type: InputEventType::Sync,
+        message: component![
+            "pad2Octets" => U16::LE(0),
+            "toggleFlags" => U32::LE(toggle_flags.unwrap_or(0))
+        ],
+    }
+}
+
 /// Fast Path update (Not a PDU)
 ///
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a1c4caa8-00ed-45bb-a06e-5177473766d3
@@ -430,21 +960,26 @@ impl FastPathUpdate {
         let fp_update_type = FastPathUpdateType::try_from(cast!(DataType::U8, fast_path["updateHeader"])? & 0xf)?;
         let mut result = match fp_update_type {
             FastPathUpdateType::Bitmap => ts_fp_update_bitmap(),
-            FastPathUpdateType::Color => ts_colorpointerattribute(),
+            FastPathUpdateType::Color
+            | FastPathUpdateType::Cached
+            | FastPathUpdateType::Pointer
+            | FastPathUpdateType::LargePointer
+            | FastPathUpdateType::PtrNull
+            | FastPathUpdateType::PtrDefault
+            | FastPathUpdateType::PtrPosition => ts_fp_pointer(fp_update_type),
             FastPathUpdateType::Synchronize => ts_fp_update_synchronize(),
-            FastPathUpdateType::PtrNull => ts_fp_systempointerhiddenattribute(),
-            _ => {
-                return Err(Error::RdpError(RdpError::new(
-                    RdpErrorKind::NotImplemented,
-                    &format!("GLOBAL: Fast Path parsing not implemented {:?}", fp_update_type),
-                )))
-            }
+            FastPathUpdateType::Palette => ts_fp_update_palette(),
+            FastPathUpdateType::Orders => ts_fp_update_orders(),
+            FastPathUpdateType::Surfcmds => ts_fp_surface_commands(),
         };
-        result.message.read(&mut Cursor::new(cast!(DataType::Slice, fast_path["updateData"])?))?;
+        result.message.read_from_bytes(cast!(DataType::Slice, fast_path["updateData"])?)?;
         Ok(result)
     }
 }
 
+/// Compressed bitmap data header
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/9ae9d28a-2f1b-4d4b-8cf1-9aea01226e8f
 fn ts_cd_header() -> Component {
     component![
         "cbCompFirstRowSize" => Check::new(U16::LE(0)),
@@ -470,20 +1005,38 @@ fn ts_bitmap_data() -> Component {
         "width" => U16::LE(0),
         "height" => U16::LE(0),
         "bitsPerPixel" => U16::LE(0),
-        "flags" => DynOption::new(U16::LE(0), |flags| {
-            if flags.inner() & BitmapFlag::BitmapCompression as u16 == 0 || flags.inner() & BitmapFlag::NoBitmapCompressionHdr as u16 != 0 {
-                MessageOption::SkipField("bitmapComprHdr".to_string())
-            }
-            else {
-                MessageOption::None
-            }
-        }),
+        "flags" => U16::LE(0),
         "bitmapLength" => DynOption::new(U16::LE(0), | length | MessageOption::Size("bitmapDataStream".to_string(), length.inner() as usize)),
-        "bitmapComprHdr" => DynOption::new(ts_cd_header(), |header| MessageOption::Size("bitmapDataStream".to_string(), cast!(DataType::U16, header["cbCompMainBodySize"]).unwrap() as usize)),
         "bitmapDataStream" => Vec::<u8>::new()
     ]
 }
 
+/// Extract the payload to give to the decompressor
+///
+/// When the compression header is present it is part of
+/// the bitmapDataStream and bitmapLength fields, and only
+/// cbCompMainBodySize bytes are the compressed bitmap
+fn bitmap_data_stream(bitmap: &Component) -> RdpResult<&[u8]> {
+    let flags = cast!(DataType::U16, bitmap["flags"])?;
+    let stream = cast!(DataType::Slice, bitmap["bitmapDataStream"])?;
+    if flags & BitmapFlag::BitmapCompression as u16 == 0 || flags & BitmapFlag::NoBitmapCompressionHdr as u16 != 0 {
+        return Ok(stream);
+    }
+
+    let mut header = ts_cd_header();
+    let mut reader = Cursor::new(stream);
+    header.read(&mut reader)?;
+    let start = reader.position() as usize;
+    let end = start + cast!(DataType::U16, header["cbCompMainBodySize"])? as usize;
+    if end > stream.len() {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidSize,
+            "GLOBAL: cbCompMainBodySize is larger than bitmap data stream",
+        )));
+    }
+    Ok(&stream[start..end])
+}
+
 /// Fast Path bitmap update
 fn ts_fp_update_bitmap() -> FastPathUpdate {
     FastPathUpdate {
@@ -496,44 +1049,92 @@ fn ts_fp_update_bitmap() -> FastPathUpdate {
     }
 }
 
-/// A new pointer for mouse
+/// Fast Path orders update
+/// Drawing orders are decoded by the order decoder
 ///
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/71fad4fc-6ad4-4c7f-8103-a442bebaf7d2
-fn ts_colorpointerattribute() -> FastPathUpdate {
+/// See MS-RDPBCGR 2.2.9.1.2.1.2
+fn ts_fp_update_orders() -> FastPathUpdate {
     FastPathUpdate {
-        fp_type: FastPathUpdateType::Color,
+        fp_type: FastPathUpdateType::Orders,
         message: component![
-           "cacheIndex " => U16::LE(0),
-           "hotSpot " => U32::LE(0),
-           "width" => U16::LE(0),
-           "height" => U16::LE(0),
-           "lengthAndMask" => DynOption::new(U16::LE(0), |length| MessageOption::Size("andMaskData".to_string(), length.inner() as usize)),
-           "lengthXorMask" => DynOption::new(U16::LE(0), |length| MessageOption::Size("xorMaskData".to_string(), length.inner() as usize)),
-           "xorMaskData" => Vec::<u8>::new(),
-           "andMaskData" => Vec::<u8>::new(),
-           "pad" => Some(0_u8)
+            "numberOrders" => U16::LE(0),
+            "orderData" => Vec::<u8>::new()
         ],
     }
 }
 
-/// Empty fields
+/// Fast Path palette update
+/// Colors used by 8 bpp bitmaps
 ///
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/406dc477-c516-41cb-a8a0-ab4cc7119621
-fn ts_fp_update_synchronize() -> FastPathUpdate {
-    FastPathUpdate { fp_type: FastPathUpdateType::Synchronize, message: component![] }
+/// See MS-RDPBCGR 2.2.9.1.1.3.1.1.1
+fn ts_fp_update_palette() -> FastPathUpdate {
+    FastPathUpdate {
+        fp_type: FastPathUpdateType::Palette,
+        message: component![
+            "updateType" => Check::new(U16::LE(FastPathUpdateType::Palette as u16)),
+            "pad2Octets" => U16::LE(0),
+            "numberColors" => DynOption::new(U32::LE(0), |count| MessageOption::Size("paletteEntries".to_string(), count.inner() as usize * 3)),
+            "paletteEntries" => Vec::<u8>::new()
+        ],
+    }
+}
+
+/// Build a palette from the entries of a palette update
+fn read_palette(update: &Component) -> RdpResult<Palette> {
+    let entries = cast!(DataType::Slice, update["paletteEntries"])?;
+    if entries.len() != 256 * 3 {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidData,
+            "GLOBAL: Palette update must contain 256 colors",
+        )));
+    }
+    let mut palette = [[0_u8; 3]; 256];
+    for (color, entry) in palette.iter_mut().zip(entries.chunks_exact(3)) {
+        color.copy_from_slice(entry);
+    }
+    Ok(palette)
+}
+
+/// Pointer updates
+/// The attribute is decoded by the pointer module
+///
+/// See MS-RDPBCGR 2.2.9.1.2.1.4 to 2.2.9.1.2.1.11
+fn ts_fp_pointer(fp_type: FastPathUpdateType) -> FastPathUpdate {
+    FastPathUpdate {
+        fp_type,
+        message: component![
+            "pointerAttribute" => Vec::<u8>::new()
+        ],
+    }
+}
+
+/// Surface commands
+/// The commands are decoded by the surface module
+///
+/// See MS-RDPBCGR 2.2.9.1.2.1.10
+fn ts_fp_surface_commands() -> FastPathUpdate {
+    FastPathUpdate {
+        fp_type: FastPathUpdateType::Surfcmds,
+        message: component![
+            "surfaceCommands" => Vec::<u8>::new()
+        ],
+    }
 }
 
 /// Empty fields
 ///
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/406dc477-c516-41cb-a8a0-ab4cc7119621
-fn ts_fp_systempointerhiddenattribute() -> FastPathUpdate {
-    FastPathUpdate { fp_type: FastPathUpdateType::PtrNull, message: component![] }
+fn ts_fp_update_synchronize() -> FastPathUpdate {
+    FastPathUpdate { fp_type: FastPathUpdateType::Synchronize, message: component![] }
 }
 
 #[derive(Clone, Copy, Debug)]
 enum ClientState {
     /// Wait for demand active pdu from server
     DemandActivePDU,
+    /// Wait for demand active pdu after a deactivation
+    /// The connection sequence is run again
+    Reactivating,
     /// Wait for synchronize pdu from server
     SynchronizePDU,
     /// wait for control cooperate from server
@@ -547,6 +1148,29 @@ enum ClientState {
     Data,
 }
 
+/// Order capability of the client
+/// Memory blits draw bitmaps of the cache, nine grids
+/// draw bitmaps of the nine grid cache
+fn order_capability(bitmap_cache: bool) -> Capability {
+    let orders: &[OrderSupportIndex] = if bitmap_cache {
+        &[
+            OrderSupportIndex::PatBlt,
+            OrderSupportIndex::MemBlt,
+            OrderSupportIndex::DrawNineGrid,
+            OrderSupportIndex::MultiDrawNineGrid,
+            OrderSupportIndex::OpaqueRect,
+        ]
+    } else {
+        &[]
+    };
+    capability::ts_order_capability_set_with_orders(
+        Some(
+            capability::OrderFlag::NegotiateOrderSupport as u16 | capability::OrderFlag::ZeroBoundsDeltasSupport as u16,
+        ),
+        orders,
+    )
+}
+
 #[derive(Debug)]
 pub struct Client {
     /// Current state of the connection sequence
@@ -566,9 +1190,45 @@ pub struct Client {
     /// channel during connection sequence
     share_id: Option<u32>,
     /// Keep tracing of server capabilities
-    server_capabilities: Vec<Capability>,
+    server_capabilities: Option<ServerCapabilities>,
+    /// Capability sets of the confirm active PDU
+    capabilities: CapabilityRegistry,
     /// Name send to the server
     name: String,
+    /// Current monitor topology
+    /// None for a single screen session
+    monitor_layout: Option<MonitorLayout>,
+    /// Reception time of the last server heartbeat
+    last_heartbeat: Option<Instant>,
+    /// Colors of 8 bpp bitmaps
+    /// Sent by the server with a palette update
+    palette: Option<Arc<Palette>>,
+    /// Drawing orders and bitmap cache
+    /// None when the bitmap cache is disabled
+    orders: Option<OrderDecoder>,
+    /// Pointer shapes recalled by cache index
+    pointers: PointerCache,
+    /// A deactivation is in progress
+    /// `RdpEvent::Reactivated` is sent at the end of the finalization
+    reactivation: bool,
+    /// The server asked to stop sending input
+    output_suppressed: bool,
+    /// Non fatal server messages
+    warnings: WarningHandler,
+    /// Last error info sent by the server
+    /// Explains the next disconnection
+    error_info: u32,
+    /// Send input as fast path when the server supports it
+    fast_path_input: bool,
+    /// Fast path update PDUs decoded
+    frames_received: u64,
+    /// Session assigned by the server at logon
+    session_id: Option<u32>,
+    /// Lock keys synchronized at the end of each activation
+    toggle_flags: Option<u32>,
+    /// Bitmap codecs confirmed to the server
+    /// Their ids are used by the surface bits commands
+    bitmap_codecs: Vec<BitmapCodec>,
 }
 
 impl Client {
@@ -590,9 +1250,48 @@ impl Client {
     /// );
     /// ```
     pub fn new(user_id: u16, channel_id: u16, width: u16, height: u16, layout: KeyboardLayout, name: &str) -> Client {
+        let mut capabilities = CapabilityRegistry::new();
+        capabilities.register(Box::new(GeneralCapabilityHandler::new(
+            capability::GeneralExtraFlag::LongCredentialsSupported as u16
+                | capability::GeneralExtraFlag::NoBitmapCompressionHdr as u16
+                | capability::GeneralExtraFlag::EncSaltedChecksum as u16
+                | capability::GeneralExtraFlag::FastpathOutputSupported as u16,
+        )));
+        capabilities.register(Box::new(BitmapCapabilityHandler::new(width, height)));
+        for capability in [
+            order_capability(false),
+            capability::ts_bitmap_cache_capability_set(),
+            capability::ts_draw_nine_grid_cache_capability_set(false),
+            capability::ts_new_pointer_capability_set(POINTER_CACHE_SIZE),
+            capability::ts_large_pointer_capability_set(capability::LargePointerFlag::Support96x96 as u16),
+            capability::ts_surface_commands_capability_set(
+                capability::SurfaceCommandFlag::SetSurfaceBits as u32
+                    | capability::SurfaceCommandFlag::FrameMarker as u32
+                    | capability::SurfaceCommandFlag::StreamSurfaceBits as u32,
+            ),
+            capability::ts_sound_capability_set(),
+            capability::ts_input_capability_set(
+                Some(
+                    capability::InputFlags::Scancodes as u16
+                        | capability::InputFlags::Mousex as u16
+                        | capability::InputFlags::Unicode as u16
+                        | capability::InputFlags::MouseHwheel as u16,
+                ),
+                Some(layout),
+            ),
+            capability::ts_brush_capability_set(),
+            capability::ts_glyph_capability_set(),
+            capability::ts_offscreen_capability_set(),
+            capability::ts_virtualchannel_capability_set(),
+            capability::ts_multifragment_update_capability_ts(),
+        ] {
+            capabilities.register(Box::new(StaticCapability::from(capability)));
+        }
+        capabilities.register(Box::new(BitmapCodecsCapabilityHandler::new()));
         Client {
             state: ClientState::DemandActivePDU,
-            server_capabilities: Vec::new(),
+            server_capabilities: None,
+            capabilities,
             share_id: None,
             user_id,
             channel_id,
@@ -600,39 +1299,228 @@ impl Client {
             height,
             layout,
             name: String::from(name),
+            monitor_layout: None,
+            last_heartbeat: None,
+            palette: None,
+            orders: None,
+            pointers: PointerCache::new(),
+            reactivation: false,
+            output_suppressed: false,
+            warnings: WarningHandler::default(),
+            error_info: 0,
+            fast_path_input: true,
+            frames_received: 0,
+            session_id: None,
+            toggle_flags: None,
+            bitmap_codecs: Vec::new(),
         }
     }
 
-    /// Read demand Active payload
-    /// This message is sent from server to client
-    /// and inform about server capabilities
-    ///
-    /// This function return true if it read the expected PDU
-    fn read_demand_active_pdu(&mut self, stream: &mut dyn Read) -> RdpResult<bool> {
-        let pdu = Pdu::from_stream(stream)?;
-        if pdu.pdu_type == PduType::Demandactivepdu {
-            for capability_set in cast!(DataType::Trame, pdu.message["capabilitySets"])? {
-                match Capability::from_capability_set(cast!(DataType::Component, capability_set)?) {
-                    Ok(capability) => self.server_capabilities.push(capability),
-                    Err(e) => println!("GLOBAL: {:?}", e),
-                }
-            }
-            self.share_id = Some(cast!(DataType::U32, pdu.message["shareId"])?);
-            return Ok(true);
-        }
-        Ok(false)
+    /// Announce the bitmap cache, the memory blit and nine grid orders
+    /// Must be called before the capabilities exchange
+    pub fn set_bitmap_cache(&mut self, enabled: bool) {
+        self.orders = if enabled { Some(OrderDecoder::new()) } else { None };
+        let bitmap_cache = if enabled {
+            capability::ts_bitmap_cache_rev2_capability_set(&BITMAP_CACHE_ENTRIES)
+        } else {
+            capability::ts_bitmap_cache_capability_set()
+        };
+        self.capabilities.register(Box::new(StaticCapability::from(order_capability(enabled))));
+        self.capabilities.register(Box::new(StaticCapability::from(bitmap_cache)));
+        self.capabilities
+            .register(Box::new(StaticCapability::from(capability::ts_draw_nine_grid_cache_capability_set(enabled))));
     }
 
-    /// Read server synchronize pdu
-    /// This sent from server to client
+    /// Keep the bitmaps sent with a persistent key between sessions
+    /// Their keys are sent during the finalization
     ///
-    /// This function return true if it read the expected PDU
-    fn read_synchronize_pdu(&mut self, stream: &mut dyn Read) -> RdpResult<bool> {
-        let pdu = Pdu::from_stream(stream)?;
-        if pdu.pdu_type != PduType::Datapdu {
-            return Ok(false);
+    /// Must be called after `set_bitmap_cache`,
+    /// nothing is done when the bitmap cache is disabled
+    pub fn set_persistent_bitmap_cache(&mut self, cache: PersistentBitmapCache) {
+        if let Some(orders) = &mut self.orders {
+            orders.set_persistent_cache(cache);
+            self.capabilities.register(Box::new(StaticCapability::from(
+                capability::ts_persistent_bitmap_cache_rev2_capability_set(&BITMAP_CACHE_ENTRIES),
+            )));
         }
-        if DataPdu::from_pdu(&pdu)?.pdu_type != PduType2::Synchronize {
+    }
+
+    /// Bitmaps kept between sessions
+    /// None without persistent bitmap cache
+    pub fn get_persistent_bitmap_cache(&self) -> Option<&PersistentBitmapCache> {
+        self.orders.as_ref().and_then(OrderDecoder::persistent_cache)
+    }
+
+    /// Add or replace the handler of a capability set
+    /// Used by the next capability exchange
+    pub fn register_capability_handler(&mut self, handler: Box<dyn CapabilityHandler>) {
+        self.capabilities.register(handler);
+    }
+
+    /// Set the callback of non fatal server messages
+    pub fn set_warning_handler(&mut self, warnings: WarningHandler) { self.warnings = warnings; }
+
+    /// Send input as fast path once the server announced it
+    /// Enabled by default
+    pub fn set_fast_path_input(&mut self, enabled: bool) { self.fast_path_input = enabled; }
+
+    /// Send the state of the lock keys once the connection is finalized
+    /// None leaves the keys of the server untouched
+    pub fn set_toggle_flags(&mut self, toggle_flags: Option<u32>) { self.toggle_flags = toggle_flags; }
+
+    /// Check if inputs are sent as fast path
+    fn use_fast_path_input(&self) -> bool {
+        self.fast_path_input
+            && self
+                .server_capabilities
+                .as_ref()
+                .and_then(|capabilities| capabilities.input.as_ref())
+                .is_some_and(|input| input.supports_fast_path())
+    }
+
+    /// Move the connection sequence to its next state
+    fn set_state(&mut self, state: ClientState) {
+        log_debug!("GLOBAL: state {:?} -> {:?}", self.state, state);
+        self.state = state;
+    }
+
+    /// Set the monitor topology announced during connection
+    pub fn set_monitor_layout(&mut self, monitor_layout: MonitorLayout) { self.monitor_layout = Some(monitor_layout); }
+
+    /// Size of the desktop negotiated with the server
+    pub fn get_screen_size(&self) -> (u16, u16) { (self.width, self.height) }
+
+    /// Reception time of the last server heartbeat
+    /// None if the server never sent one
+    pub fn get_last_heartbeat(&self) -> Option<Instant> { self.last_heartbeat }
+
+    /// Session assigned by the server
+    /// None until the user is logged on
+    pub fn get_session_id(&self) -> Option<u32> { self.session_id }
+
+    /// Reason of a server disconnection
+    /// from the last error info PDU
+    pub fn get_disconnect_reason(&self) -> DisconnectReason { DisconnectReason::from(self.error_info) }
+
+    /// Last error info sent by the server
+    pub fn get_error_info(&self) -> ErrorInfoCode { ErrorInfoCode::from(self.error_info) }
+
+    /// Keyboard layout announced to the server
+    pub fn get_layout(&self) -> KeyboardLayout { self.layout }
+
+    /// Current monitor topology
+    /// Updated when the server send a monitor layout PDU
+    pub fn get_monitor_layout(&self) -> Option<&MonitorLayout> { self.monitor_layout.as_ref() }
+
+    /// Number of fast path update PDUs decoded
+    pub fn get_frames_received(&self) -> u64 { self.frames_received }
+
+    /// True while the server suppresses display updates
+    /// Input events are refused in this state
+    pub fn is_output_suppressed(&self) -> bool { self.output_suppressed }
+
+    /// Capabilities announced by the server
+    /// None before the demand active PDU
+    pub fn get_server_capabilities(&self) -> Option<&ServerCapabilities> { self.server_capabilities.as_ref() }
+
+    /// Wait for the server to run the connection sequence again
+    /// Capabilities, share id and caches are replaced by the next demand active
+    /// PDU
+    ///
+    /// See MS-RDPBCGR 1.3.1.3
+    fn handle_deactivate_all<T>(&mut self, callback: &mut T)
+    where
+        T: FnMut(RdpEvent),
+    {
+        self.reactivation = true;
+        self.set_state(ClientState::Reactivating);
+        callback(RdpEvent::Reactivating);
+    }
+
+    /// Update monitor topology from a monitor layout PDU
+    /// and notify the caller to resize its framebuffer
+    fn read_monitor_layout_pdu<T>(&mut self, data_pdu: &DataPdu, callback: &mut T) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let mut monitors = Vec::new();
+        for monitor_def in cast!(DataType::Trame, data_pdu.message["monitorDefArray"])? {
+            monitors.push(Monitor::from_component(cast!(DataType::Component, monitor_def)?)?);
+        }
+        let monitor_layout = MonitorLayout { monitors };
+        self.monitor_layout = Some(monitor_layout.clone());
+        callback(RdpEvent::MonitorLayout(monitor_layout));
+        Ok(())
+    }
+
+    /// Read demand Active payload
+    /// This message is sent from server to client
+    /// and inform about server capabilities
+    ///
+    /// Server can send the monitor layout PDU just before
+    ///
+    /// This function return true if it read the expected PDU
+    fn read_demand_active_pdu<T>(&mut self, stream: &mut dyn Read, callback: &mut T) -> RdpResult<bool>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let pdu = Pdu::from_stream(stream)?;
+        if pdu.pdu_type == PduType::Datapdu {
+            if let Ok(data_pdu) = DataPdu::from_pdu(&pdu) {
+                if data_pdu.pdu_type == PduType2::MonitorLayoutPdu {
+                    self.read_monitor_layout_pdu(&data_pdu, callback)?;
+                }
+            }
+            return Ok(false);
+        }
+        if pdu.pdu_type == PduType::Demandactivepdu {
+            // Capabilities are negotiated again on reactivation
+            let mut sets = Vec::new();
+            // and the server starts with empty caches
+            if let Some(orders) = &mut self.orders {
+                orders.reset();
+            }
+            self.pointers = PointerCache::new();
+            for capability_set in cast!(DataType::Trame, pdu.message["capabilitySets"])? {
+                let capability_set = cast!(DataType::Component, capability_set)?;
+                let cap_type = cast!(DataType::U16, capability_set["capabilitySetType"])?;
+                if let Err(e) =
+                    self.capabilities.parse(cap_type, cast!(DataType::Slice, capability_set["capabilitySet"])?)
+                {
+                    log_warn!("GLOBAL: Invalid capability set {:#x} {:?}", cap_type, e);
+                }
+                let set = ServerCapabilitySet::from_capability_set(capability_set)?;
+                match &set {
+                    // The desktop may have been resized
+                    ServerCapabilitySet::Bitmap(bitmap) => {
+                        self.width = bitmap.desktop_width;
+                        self.height = bitmap.desktop_height;
+                    }
+                    // Same codecs as the confirmed set
+                    ServerCapabilitySet::BitmapCodecs(codecs) => {
+                        self.bitmap_codecs = capability::confirmed_bitmap_codecs(codecs)
+                    }
+                    _ => (),
+                }
+                sets.push(set);
+            }
+            self.server_capabilities = Some(ServerCapabilities::from_sets(sets));
+            self.share_id = Some(cast!(DataType::U32, pdu.message["shareId"])?);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Read server synchronize pdu
+    /// This sent from server to client
+    ///
+    /// This function return true if it read the expected PDU
+    fn read_synchronize_pdu(&mut self, stream: &mut dyn Read) -> RdpResult<bool> {
+        let pdu = Pdu::from_stream(stream)?;
+        if pdu.pdu_type != PduType::Datapdu {
+            return Ok(false);
+        }
+        if DataPdu::from_pdu(&pdu)?.pdu_type != PduType2::Synchronize {
             return Ok(false);
         }
         Ok(true)
@@ -642,7 +1530,7 @@ impl Client {
     ///
     /// This function return true if it read the expected PDU with the expected
     /// action
-    fn read_control_pdu(&mut self, stream: &mut dyn Read, action: Action) -> RdpResult<bool> {
+    fn read_control_pdu(&mut self, stream: &mut dyn Read, action: ControlAction) -> RdpResult<bool> {
         let pdu = Pdu::from_stream(stream)?;
         if pdu.pdu_type != PduType::Datapdu {
             return Ok(false);
@@ -660,56 +1548,148 @@ impl Client {
         Ok(true)
     }
 
-    /// Read the server font data PDU
-    ///
-    /// This function return true if it read the expected PDU
-    fn read_font_map_pdu(&mut self, stream: &mut dyn Read) -> RdpResult<bool> {
-        let pdu = Pdu::from_stream(stream)?;
-        if pdu.pdu_type != PduType::Datapdu {
-            return Ok(false);
-        }
-        if DataPdu::from_pdu(&pdu)?.pdu_type != PduType2::Fontmap {
-            return Ok(false);
-        }
-        Ok(true)
-    }
-
     /// Expect data PDU
     /// This is the old school PDU for bitmap
     /// transfer. Now all version use Fast Path transfer PDU
-    fn read_data_pdu(&mut self, stream: &mut dyn Read) -> RdpResult<()> {
-        //let pdu = PDU::from_stream(stream)?;
-        let mut message = Array::new(|| share_control_header(None, None, None));
-        message.read(stream)?;
-
-        for pdu in message.inner() {
-            let pdu = Pdu::from_control(cast!(DataType::Component, pdu)?)?;
+    fn read_data_pdu<T>(&mut self, stream: &mut dyn Read, callback: &mut T) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        // Several PDUs can follow each other
+        let mut message = Vec::new();
+        stream.read_to_end(&mut message)?;
+        let mut message = message.as_slice();
+
+        while !message.is_empty() {
+            let mut header = ShareControlHeader::default();
+            let pdu = match header.read(&mut message) {
+                Ok(()) if message.len() >= header.pdu_length() => {
+                    let (pdu, next) = message.split_at(header.pdu_length());
+                    message = next;
+                    Pdu::from_control(header.pdu_type, pdu)?
+                }
+                result => {
+                    log_debug!("GLOBAL: Ignore invalid PDU {:?}", result);
+                    break;
+                }
+            };
 
             // Ask for a new handshake
             if pdu.pdu_type == PduType::Deactivateallpdu {
-                println!("GLOBAL: deactive/reactive sequence initiated");
-                self.state = ClientState::DemandActivePDU;
+                log_debug!("GLOBAL: deactive/reactive sequence initiated");
+                let source = cast!(DataType::Slice, pdu.message["sourceDescriptor"])?;
+                let source = String::from_utf8_lossy(source.split(|c| *c == 0).next().unwrap_or_default());
+                self.warnings
+                    .warn(RdpWarning::ServerInfoMessage(format!("Deactivation by {:?}, waiting reactivation", source)));
+                self.handle_deactivate_all(callback);
                 continue;
             }
             if pdu.pdu_type != PduType::Datapdu {
-                println!("GLOBAL: Ignore PDU {:?}", pdu.pdu_type);
+                log_debug!("GLOBAL: Ignore PDU {:?}", pdu.pdu_type);
                 continue;
             }
 
             match DataPdu::from_pdu(&pdu) {
                 Ok(data_pdu) => match data_pdu.pdu_type {
-                    PduType2::SetErrorInfoPdu => println!(
-                        "GLOBAL: Receive error PDU from server {:?}",
-                        cast!(DataType::U32, data_pdu.message["errorInfo"])?
-                    ),
-                    _ => println!("GLOBAL: Data PDU not handle {:?}", data_pdu.pdu_type),
+                    PduType2::SetErrorInfoPdu => {
+                        // A zero code clears the previous error
+                        let error_info = cast!(DataType::U32, data_pdu.message["errorInfo"])?;
+                        self.error_info = error_info;
+                        if error_info != 0 {
+                            self.warnings.warn(RdpWarning::ServerErrorInfo(error_info));
+                            callback(RdpEvent::ErrorInfo(ErrorInfoCode::from(error_info)));
+                        }
+                    }
+                    PduType2::MonitorLayoutPdu => self.read_monitor_layout_pdu(&data_pdu, callback)?,
+                    PduType2::Update => self.read_update_pdu(&data_pdu, callback)?,
+                    PduType2::Pointer => {
+                        match self.pointers.read_pointer_pdu(cast!(DataType::Slice, data_pdu.message["pointerData"])?) {
+                            Ok(event) => callback(event),
+                            Err(e) => log_warn!("GLOBAL: Pointer not decoded {:?}", e),
+                        }
+                    }
+                    PduType2::SaveSessionInfo => {
+                        if let Some(info) =
+                            logon::read_save_session_info(cast!(DataType::Slice, data_pdu.message["infoData"])?)?
+                        {
+                            self.session_id = Some(info.session_id);
+                            callback(RdpEvent::LogonInfo {
+                                session_id: info.session_id,
+                                domain: info.domain,
+                                user: info.user,
+                            });
+                        }
+                    }
+                    PduType2::SuppressOutput => {
+                        let allow = cast!(DataType::U8, data_pdu.message["allowDisplayUpdates"])? != 0;
+                        if allow && self.output_suppressed {
+                            callback(RdpEvent::DisplayResumed);
+                        }
+                        self.output_suppressed = !allow;
+                    }
+                    _ => log_debug!("GLOBAL: Data PDU not handle {:?}", data_pdu.pdu_type),
                 },
-                Err(e) => println!("GLOBAL: Parsing data PDU error {:?}", e),
+                Err(e) => log_warn!("GLOBAL: Parsing data PDU error {:?}", e),
             };
         }
         Ok(())
     }
 
+    /// Decode drawing orders with the bitmap cache
+    /// Nothing is done if the cache is disabled
+    fn read_orders<T>(&mut self, data: &[u8], number_orders: u16, callback: &mut T)
+    where
+        T: FnMut(RdpEvent),
+    {
+        if let Some(orders) = &mut self.orders {
+            let monitor_layout = &self.monitor_layout;
+            let result = orders.read_orders(data, number_orders, self.palette.as_ref(), |mut event| {
+                if let RdpEvent::Bitmap(bitmap) = &mut event {
+                    bitmap.monitor =
+                        monitor_layout.as_ref().and_then(|layout| layout.monitor_at(bitmap.dest_left, bitmap.dest_top));
+                }
+                callback(event)
+            });
+            if let Err(e) = result {
+                log_debug!("GLOBAL: Orders update partially decoded {:?}", e);
+            }
+        }
+    }
+
+    /// Slow path graphics update
+    /// Only drawing orders are handled, other updates come with fast path
+    fn read_update_pdu<T>(&mut self, data_pdu: &DataPdu, callback: &mut T) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        match UpdateType::try_from(cast!(DataType::U16, data_pdu.message["updateType"])?) {
+            Ok(UpdateType::Orders) => {
+                let mut update = ts_update_orders();
+                update.read_from_bytes(cast!(DataType::Slice, data_pdu.message["payload"])?)?;
+                self.read_orders(
+                    cast!(DataType::Slice, update["orderData"])?,
+                    cast!(DataType::U16, update["numberOrders"])?,
+                    callback,
+                );
+            }
+            Ok(UpdateType::Synchronize) => self.handle_sync_pdu(callback),
+            update_type => log_debug!("GLOBAL: Update not handled {:?}", update_type),
+        }
+        Ok(())
+    }
+
+    /// Synchronize update sent after the updates of a frame
+    /// Updates are notified as soon as they are decoded,
+    /// so they are all already sent to the callback
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.1.3.1.1 and 2.2.9.1.2.1.9
+    fn handle_sync_pdu<T>(&mut self, callback: &mut T)
+    where
+        T: FnMut(RdpEvent),
+    {
+        callback(RdpEvent::SyncPoint)
+    }
+
     /// Read fast path input data
     /// Reading is processed using a callback patterm
     /// This is where bitmap are received
@@ -723,34 +1703,79 @@ impl Client {
 
         for fp_message in fp_messages.inner() {
             match FastPathUpdate::from_fp(cast!(DataType::Component, fp_message)?) {
-                Ok(order) => {
-                    match order.fp_type {
-                        FastPathUpdateType::Bitmap => {
-                            for rectangle in cast!(DataType::Trame, order.message["rectangles"])? {
-                                let bitmap = cast!(DataType::Component, rectangle)?;
-                                callback(RdpEvent::Bitmap(BitmapEvent {
-                                    dest_left: cast!(DataType::U16, bitmap["destLeft"])?,
-                                    dest_top: cast!(DataType::U16, bitmap["destTop"])?,
-                                    dest_right: cast!(DataType::U16, bitmap["destRight"])?,
-                                    dest_bottom: cast!(DataType::U16, bitmap["destBottom"])?,
-                                    width: cast!(DataType::U16, bitmap["width"])?,
-                                    height: cast!(DataType::U16, bitmap["height"])?,
-                                    bpp: cast!(DataType::U16, bitmap["bitsPerPixel"])?,
-                                    is_compress: cast!(DataType::U16, bitmap["flags"])?
-                                        & BitmapFlag::BitmapCompression as u16
-                                        != 0,
-                                    data: cast!(DataType::Slice, bitmap["bitmapDataStream"])?.to_vec(),
-                                }));
-                            }
+                Ok(order) => match order.fp_type {
+                    FastPathUpdateType::Bitmap => {
+                        for rectangle in cast!(DataType::Trame, order.message["rectangles"])? {
+                            let bitmap = cast!(DataType::Component, rectangle)?;
+                            let dest_left = cast!(DataType::U16, bitmap["destLeft"])?;
+                            let dest_top = cast!(DataType::U16, bitmap["destTop"])?;
+                            callback(RdpEvent::Bitmap(BitmapEvent {
+                                dest_left,
+                                dest_top,
+                                dest_right: cast!(DataType::U16, bitmap["destRight"])?,
+                                dest_bottom: cast!(DataType::U16, bitmap["destBottom"])?,
+                                width: cast!(DataType::U16, bitmap["width"])?,
+                                height: cast!(DataType::U16, bitmap["height"])?,
+                                bpp: cast!(DataType::U16, bitmap["bitsPerPixel"])?,
+                                is_compress: cast!(DataType::U16, bitmap["flags"])?
+                                    & BitmapFlag::BitmapCompression as u16
+                                    != 0,
+                                codec: None,
+                                monitor: self
+                                    .monitor_layout
+                                    .as_ref()
+                                    .and_then(|layout| layout.monitor_at(dest_left, dest_top)),
+                                palette: self.palette.clone(),
+                                data: bitmap_data_stream(bitmap)?.to_vec(),
+                            }));
                         }
-                        // do nothing
-                        FastPathUpdateType::Color | FastPathUpdateType::PtrNull | FastPathUpdateType::Synchronize => (),
-                        _ => println!("GLOBAL: Fast Path order not handled {:?}", order.fp_type),
                     }
-                }
-                Err(e) => println!("GLOBAL: Unknown Fast Path order {:?}", e),
+                    FastPathUpdateType::Surfcmds => {
+                        for mut bitmap in surface::read_surface_commands(
+                            cast!(DataType::Slice, order.message["surfaceCommands"])?,
+                            &self.bitmap_codecs,
+                        )? {
+                            bitmap.monitor = self
+                                .monitor_layout
+                                .as_ref()
+                                .and_then(|layout| layout.monitor_at(bitmap.dest_left, bitmap.dest_top));
+                            callback(RdpEvent::Bitmap(bitmap));
+                        }
+                    }
+                    FastPathUpdateType::Palette => self.palette = Some(Arc::new(read_palette(&order.message)?)),
+                    FastPathUpdateType::Orders => self.read_orders(
+                        cast!(DataType::Slice, order.message["orderData"])?,
+                        cast!(DataType::U16, order.message["numberOrders"])?,
+                        &mut callback,
+                    ),
+                    FastPathUpdateType::Color
+                    | FastPathUpdateType::Cached
+                    | FastPathUpdateType::Pointer
+                    | FastPathUpdateType::LargePointer => {
+                        let attribute = cast!(DataType::Slice, order.message["pointerAttribute"])?;
+                        let event = match order.fp_type {
+                            FastPathUpdateType::Color => self.pointers.read_color_pointer(attribute),
+                            FastPathUpdateType::Cached => self.pointers.read_cached_pointer(attribute),
+                            FastPathUpdateType::Pointer => self.pointers.read_new_pointer(attribute),
+                            _ => self.pointers.read_large_pointer(attribute),
+                        };
+                        match event {
+                            Ok(event) => callback(event),
+                            Err(e) => log_warn!("GLOBAL: Pointer not decoded {:?}", e),
+                        }
+                    }
+                    FastPathUpdateType::PtrNull => callback(RdpEvent::SystemPointer(SystemPointer::Hidden)),
+                    FastPathUpdateType::PtrDefault => callback(RdpEvent::SystemPointer(SystemPointer::Default)),
+                    FastPathUpdateType::PtrPosition => callback(pointer::read_pointer_position(cast!(
+                        DataType::Slice,
+                        order.message["pointerAttribute"]
+                    )?)?),
+                    FastPathUpdateType::Synchronize => self.handle_sync_pdu(&mut callback),
+                },
+                Err(e) => log_debug!("GLOBAL: Unknown Fast Path order {:?}", e),
             };
         }
+        self.frames_received += 1;
 
         Ok(())
     }
@@ -761,63 +1786,43 @@ impl Client {
         let pdu = ts_confirm_active_pdu(
             self.share_id,
             Some(self.name.as_bytes().to_vec()),
-            Some(Array::from_trame(trame![
-                capability_set(Some(capability::ts_general_capability_set(Some(
-                    capability::GeneralExtraFlag::LongCredentialsSupported as u16
-                        | capability::GeneralExtraFlag::NoBitmapCompressionHdr as u16
-                        | capability::GeneralExtraFlag::EncSaltedChecksum as u16
-                        | capability::GeneralExtraFlag::FastpathOutputSupported as u16
-                )))),
-                capability_set(Some(capability::ts_bitmap_capability_set(
-                    Some(0x0018),
-                    Some(self.width),
-                    Some(self.height)
-                ))),
-                capability_set(Some(capability::ts_order_capability_set(Some(
-                    capability::OrderFlag::NegotiateOrderSupport as u16
-                        | capability::OrderFlag::ZeroBoundsDeltasSupport as u16
-                )))),
-                capability_set(Some(capability::ts_bitmap_cache_capability_set())),
-                capability_set(Some(capability::ts_pointer_capability_set())),
-                capability_set(Some(capability::ts_sound_capability_set())),
-                capability_set(Some(capability::ts_input_capability_set(
-                    Some(
-                        capability::InputFlags::Scancodes as u16
-                            | capability::InputFlags::Mousex as u16
-                            | capability::InputFlags::Unicode as u16
-                    ),
-                    Some(self.layout)
-                ))),
-                capability_set(Some(capability::ts_brush_capability_set())),
-                capability_set(Some(capability::ts_glyph_capability_set())),
-                capability_set(Some(capability::ts_offscreen_capability_set())),
-                capability_set(Some(capability::ts_virtualchannel_capability_set())),
-                capability_set(Some(capability::ts_multifragment_update_capability_ts()))
-            ])),
+            Some(Array::from_trame(self.capabilities.write_confirm()?)),
         );
         self.write_pdu(pdu, mcs)
     }
 
     /// This is the finalize connection sequence
     /// sent from client to server
+    /// The server answers once it received the font list
+    ///
+    /// The keys of the persistent bitmaps are sent before the font list
+    ///
+    /// See MS-RDPBCGR 1.3.1.1
     fn write_client_finalize<S: Read + Write>(&self, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
-        self.write_data_pdu(ts_synchronize_pdu(Some(self.channel_id)), mcs)?;
-        self.write_data_pdu(ts_control_pdu(Some(Action::Cooperate)), mcs)?;
-        self.write_data_pdu(ts_control_pdu(Some(Action::RequestControl)), mcs)?;
-        self.write_data_pdu(ts_font_list_pdu(), mcs)
+        let mut pdus = vec![Vec::new(); 4];
+        write_synchronize_pdu(self.share_id, self.user_id, self.channel_id, &mut pdus[0])?;
+        write_control_pdu(self.share_id, self.user_id, ControlAction::Cooperate, &mut pdus[1])?;
+        write_control_pdu(self.share_id, self.user_id, ControlAction::RequestControl, &mut pdus[2])?;
+        write_font_list_pdu(self.share_id, self.user_id, &mut pdus[3])?;
+        if let Some(cache) = self.get_persistent_bitmap_cache() {
+            // Same order as the bitmaps loaded in the cache
+            let keys: Vec<Vec<u64>> = (0..BITMAP_CACHE_ENTRIES.len() as u8).map(|id| cache.keys(id)).collect();
+            pdus.splice(3..3, write_persistent_key_list_pdus(self.share_id, self.user_id, &keys)?);
+        }
+        for pdu in pdus {
+            mcs.write(&"global".to_string(), pdu)?;
+        }
+        Ok(())
     }
 
     /// Send a classic PDU to the global channel
     fn write_pdu<S: Read + Write>(&self, message: Pdu, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
-        mcs.write(
-            &"global".to_string(),
-            share_control_header(Some(message.pdu_type), Some(self.user_id), Some(to_vec(&message.message))),
-        )
+        mcs.write(&"global".to_string(), share_control_pdu(message.pdu_type, self.user_id, to_vec(&message.message))?)
     }
 
     /// Send Data pdu
     fn write_data_pdu<S: Read + Write>(&self, message: DataPdu, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
-        self.write_pdu(share_data_header(self.share_id, Some(message.pdu_type), Some(to_vec(&message.message))), mcs)
+        mcs.write(&"global".to_string(), share_data_pdu(message, self.share_id, self.user_id)?)
     }
 
     /// Public interface to sent input event
@@ -844,14 +1849,76 @@ impl Client {
     /// )
     /// ```
     pub fn write_input_event<S: Read + Write>(&self, event: TSInputEvent, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        self.write_input_events(vec![event], mcs)
+    }
+
+    /// Send several input events at once
+    /// Fast path PDUs are used when the server supports them
+    pub fn write_input_events<S: Read + Write>(
+        &self, events: Vec<TSInputEvent>, mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        if self.output_suppressed {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidData,
+                "GLOBAL: Input is not allowed while display updates are suppressed",
+            )));
+        }
         match self.state {
-            ClientState::Data => Ok(self.write_data_pdu(
-                ts_input_pdu_data(Some(Array::from_trame(trame![ts_input_event(
-                    Some(event.event_type),
-                    Some(to_vec(&event.message))
-                )]))),
+            ClientState::Data if self.use_fast_path_input() => {
+                let mut pdus = Vec::new();
+                for chunk in events.chunks(MAX_FAST_PATH_EVENTS) {
+                    pdus.push((chunk.len() as u8, ts_fp_input_events(chunk)?));
+                }
+                mcs.write_fast_path_input(pdus)
+            }
+            ClientState::Data => self.write_data_pdu(
+                ts_input_pdu_data(Some(Array::from_trame(
+                    events
+                        .into_iter()
+                        .map(|event| {
+                            Box::new(ts_input_event(Some(event.event_type), Some(to_vec(&event.message))))
+                                as Box<dyn Message>
+                        })
+                        .collect(),
+                ))),
                 mcs,
-            )?),
+            ),
+            _ => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "You cannot send data once it's not connected",
+            ))),
+        }
+    }
+
+    /// Read a flow control PDU
+    /// The stream is left untouched and false is returned for other PDUs
+    fn read_flow_pdu(&mut self, stream: &mut Cursor<Vec<u8>>) -> RdpResult<bool> {
+        let position = stream.position() as usize;
+        let is_flow = match stream.get_ref().get(position..position + 3) {
+            Some(header) => header[..2] == FLOW_MARKER.to_le_bytes() && FLOW_PDU_TYPES.contains(&header[2]),
+            None => false,
+        };
+        if !is_flow {
+            return Ok(false);
+        }
+        let mut pdu = ts_flow_pdu();
+        pdu.read(stream)?;
+        log_debug!(
+            "GLOBAL: Ignore flow PDU {:#x} {}",
+            cast!(DataType::U8, pdu["pduTypeFlow"])?,
+            cast!(DataType::U8, pdu["flowNumber"])?
+        );
+        Ok(true)
+    }
+
+    /// Allow or suppress display updates from the server
+    /// Allowing updates again ask for a refresh of the whole desktop
+    pub fn write_suppress_output<S: Read + Write>(&self, allow: bool, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        match self.state {
+            ClientState::Data => self.write_data_pdu(
+                ts_suppress_output_pdu(if allow { Some((self.width, self.height)) } else { None }),
+                mcs,
+            ),
             _ => Err(Error::RdpError(RdpError::new(
                 RdpErrorKind::InvalidAutomata,
                 "You cannot send data once it's not connected",
@@ -875,53 +1942,73 @@ impl Client {
     /// }
     /// ```
     pub fn read<S: Read + Write, T>(
-        &mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>, callback: T,
+        &mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>, mut callback: T,
     ) -> RdpResult<()>
     where
         T: FnMut(RdpEvent),
     {
+        // Heartbeats can be received at any time
+        let payload = match payload {
+            tpkt::Payload::Raw(mut stream) => {
+                if sec::read_heartbeat(&mut stream)?.is_some() {
+                    self.last_heartbeat = Some(Instant::now());
+                    return Ok(());
+                }
+                if self.read_flow_pdu(&mut stream)? {
+                    return Ok(());
+                }
+                tpkt::Payload::Raw(stream)
+            }
+            fast_path => fast_path,
+        };
         match self.state {
-            ClientState::DemandActivePDU => {
-                if self.read_demand_active_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?)? {
+            ClientState::DemandActivePDU | ClientState::Reactivating => {
+                if self.read_demand_active_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?, &mut callback)? {
                     self.write_confirm_active_pdu(mcs)?;
                     self.write_client_finalize(mcs)?;
                     // now wait for server synchronize
-                    self.state = ClientState::SynchronizePDU;
+                    self.set_state(ClientState::SynchronizePDU);
                 }
                 Ok(())
             }
             ClientState::SynchronizePDU => {
                 if self.read_synchronize_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?)? {
                     // next state is control cooperate
-                    self.state = ClientState::ControlCooperate;
+                    self.set_state(ClientState::ControlCooperate);
                 }
                 Ok(())
             }
             ClientState::ControlCooperate => {
-                if self.read_control_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?, Action::Cooperate)? {
+                if self.read_control_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?, ControlAction::Cooperate)? {
                     // next state is control granted
-                    self.state = ClientState::ControlGranted;
+                    self.set_state(ClientState::ControlGranted);
                 }
                 Ok(())
             }
             ClientState::ControlGranted => {
-                if self.read_control_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?, Action::GrantedControl)? {
+                if self.read_control_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?, ControlAction::GrantedControl)? {
                     // next state is font map pdu
-                    self.state = ClientState::FontMap;
+                    self.set_state(ClientState::FontMap);
                 }
                 Ok(())
             }
             ClientState::FontMap => {
-                if self.read_font_map_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?)? {
+                if read_font_map_pdu(&mut try_let!(tpkt::Payload::Raw, payload)?)? {
                     // finish handshake now wait for sdata
-                    self.state = ClientState::Data;
+                    self.set_state(ClientState::Data);
+                    if let Some(toggle_flags) = self.toggle_flags {
+                        self.write_input_event(ts_sync_event(Some(toggle_flags)), mcs)?;
+                    }
+                    if std::mem::take(&mut self.reactivation) {
+                        callback(RdpEvent::Reactivated { width: self.width, height: self.height });
+                    }
                 }
                 Ok(())
             }
             ClientState::Data => {
                 // Now we can receive update data
                 match payload {
-                    tpkt::Payload::Raw(mut stream) => self.read_data_pdu(&mut stream),
+                    tpkt::Payload::Raw(mut stream) => self.read_data_pdu(&mut stream, &mut callback),
                     tpkt::Payload::FastPath(_sec_flag, mut stream) => self.read_fast_path(&mut stream, callback),
                 }
             }
@@ -930,34 +2017,69 @@ impl Client {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
+    use std::thread::JoinHandle;
+
     use super::*;
+    use crate::codec::jpeg::JPEG_CODEC_ID;
+    use crate::codec::nscodec::NSCODEC_ID;
+    use crate::core::event::{DrawOrderEvent, PointerButton, PointerEvent, Rect};
+    use crate::core::input::write_fast_path_input;
+    use crate::core::orders::test::{nine_grid_bitmap_orders, nine_grid_pixel};
+    use crate::core::orders::CachedBitmap;
+    #[cfg(not(target_arch = "wasm32"))]
+    use crate::core::write_timeout::WriteTimeoutStream;
+    use crate::core::{per, x224};
+    use crate::model::data::Trame;
+    use crate::model::link::{Link, Stream};
+    use crate::test_utils::mock_server::{fixture, MockRdpServer};
+
+    /// Demand active PDU of a Windows server
+    const DEMAND_ACTIVE: &[u8] = &[
+        234, 3, 1, 0, 4, 0, 179, 1, 82, 68, 80, 0, 17, 0, 0, 0, 9, 0, 8, 0, 234, 3, 0, 0, 1, 0, 24, 0, 1, 0, 3, 0, 0,
+        2, 0, 0, 0, 0, 29, 4, 0, 0, 0, 0, 0, 0, 1, 1, 20, 0, 12, 0, 2, 0, 0, 0, 64, 6, 0, 0, 10, 0, 8, 0, 6, 0, 0, 0,
+        8, 0, 10, 0, 1, 0, 25, 0, 25, 0, 27, 0, 6, 0, 3, 0, 14, 0, 8, 0, 1, 0, 0, 0, 2, 0, 28, 0, 32, 0, 1, 0, 1, 0, 1,
+        0, 32, 3, 88, 2, 0, 0, 1, 0, 1, 0, 0, 30, 1, 0, 0, 0, 29, 0, 96, 0, 4, 185, 27, 141, 202, 15, 0, 79, 21, 88,
+        159, 174, 45, 26, 135, 226, 214, 0, 3, 0, 1, 1, 3, 18, 47, 119, 118, 114, 189, 99, 68, 175, 179, 183, 60, 156,
+        111, 120, 134, 0, 4, 0, 0, 0, 0, 0, 166, 81, 67, 156, 53, 53, 174, 66, 145, 12, 205, 252, 229, 118, 11, 88, 0,
+        4, 0, 0, 0, 0, 0, 212, 204, 68, 39, 138, 157, 116, 78, 128, 60, 14, 203, 238, 161, 156, 84, 0, 4, 0, 0, 0, 0,
+        0, 3, 0, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 66, 15, 0, 1, 0, 20, 0, 0, 0, 1, 0, 0, 0,
+        170, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1, 0, 0, 0, 0, 161, 6,
+        6, 0, 64, 66, 15, 0, 64, 66, 15, 0, 1, 0, 0, 0, 0, 0, 0, 0, 18, 0, 8, 0, 1, 0, 0, 0, 13, 0, 88, 0, 117, 3, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 23, 0, 8, 0, 255, 0, 0, 0, 24, 0, 11, 0, 2, 0, 0, 0, 3, 12, 0, 26, 0, 8, 0, 43, 72, 9, 0,
+        28, 0, 12, 0, 82, 0, 0, 0, 0, 0, 0, 0, 30, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
 
     /// Test format message of demand active pdu
     #[test]
     fn test_demand_active_pdu() {
-        let mut stream = Cursor::new(vec![
-            234, 3, 1, 0, 4, 0, 179, 1, 82, 68, 80, 0, 17, 0, 0, 0, 9, 0, 8, 0, 234, 3, 0, 0, 1, 0, 24, 0, 1, 0, 3, 0,
-            0, 2, 0, 0, 0, 0, 29, 4, 0, 0, 0, 0, 0, 0, 1, 1, 20, 0, 12, 0, 2, 0, 0, 0, 64, 6, 0, 0, 10, 0, 8, 0, 6, 0,
-            0, 0, 8, 0, 10, 0, 1, 0, 25, 0, 25, 0, 27, 0, 6, 0, 3, 0, 14, 0, 8, 0, 1, 0, 0, 0, 2, 0, 28, 0, 32, 0, 1,
-            0, 1, 0, 1, 0, 32, 3, 88, 2, 0, 0, 1, 0, 1, 0, 0, 30, 1, 0, 0, 0, 29, 0, 96, 0, 4, 185, 27, 141, 202, 15,
-            0, 79, 21, 88, 159, 174, 45, 26, 135, 226, 214, 0, 3, 0, 1, 1, 3, 18, 47, 119, 118, 114, 189, 99, 68, 175,
-            179, 183, 60, 156, 111, 120, 134, 0, 4, 0, 0, 0, 0, 0, 166, 81, 67, 156, 53, 53, 174, 66, 145, 12, 205,
-            252, 229, 118, 11, 88, 0, 4, 0, 0, 0, 0, 0, 212, 204, 68, 39, 138, 157, 116, 78, 128, 60, 14, 203, 238,
-            161, 156, 84, 0, 4, 0, 0, 0, 0, 0, 3, 0, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 66, 15,
-            0, 1, 0, 20, 0, 0, 0, 1, 0, 0, 0, 170, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1,
-            1, 0, 1, 1, 1, 1, 0, 0, 0, 0, 161, 6, 6, 0, 64, 66, 15, 0, 64, 66, 15, 0, 1, 0, 0, 0, 0, 0, 0, 0, 18, 0, 8,
-            0, 1, 0, 0, 0, 13, 0, 88, 0, 117, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 23, 0, 8, 0, 255, 0, 0, 0, 24, 0, 11,
-            0, 2, 0, 0, 0, 3, 12, 0, 26, 0, 8, 0, 43, 72, 9, 0, 28, 0, 12, 0, 82, 0, 0, 0, 0, 0, 0, 0, 30, 0, 8, 0, 0,
-            0, 0, 0, 0, 0, 0, 0,
-        ]);
+        let mut stream = Cursor::new(DEMAND_ACTIVE.to_vec());
         let mut pdu = ts_demand_active_pdu();
         pdu.message.read(&mut stream).unwrap();
         assert_eq!(cast!(DataType::U16, pdu.message["numberCapabilities"]).unwrap(), 17);
     }
 
+    /// Every set of the demand active PDU is kept
+    #[test]
+    fn test_read_demand_active_capabilities() {
+        let mut stream =
+            Cursor::new(to_vec(&share_control_pdu(PduType::Demandactivepdu, 1002, DEMAND_ACTIVE.to_vec()).unwrap()));
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        assert!(global.read_demand_active_pdu(&mut stream, &mut |_| ()).unwrap());
+        let capabilities = global.get_server_capabilities().unwrap();
+        assert_eq!(capabilities.sets.len(), 17);
+        assert!(capabilities.sets.iter().any(|set| matches!(set, ServerCapabilitySet::VirtualChannel(_))));
+        assert!(capabilities.supports_fast_path_output());
+        assert!(capabilities.font.is_some());
+        assert_eq!(
+            (global.width, global.height),
+            (capabilities.bitmap.as_ref().unwrap().desktop_width, capabilities.bitmap.as_ref().unwrap().desktop_height)
+        );
+        assert_eq!(global.share_id, Some(0x0001_03ea));
+    }
+
     /// Test confirm active PDU format
     #[test]
     fn test_confirm_active_pdu() {
@@ -979,18 +2101,19 @@ mod test {
     #[test]
     fn test_share_control_header() {
         let mut stream = Cursor::new(vec![]);
-        share_control_header(
-            Some(PduType::Confirmactivepdu),
-            Some(12),
-            Some(to_vec(
+        share_control_pdu(
+            PduType::Confirmactivepdu,
+            12,
+            to_vec(
                 &ts_confirm_active_pdu(
                     Some(4),
                     Some(b"rdp-rs".to_vec()),
                     Some(Array::from_trame(trame![capability_set(Some(capability::ts_brush_capability_set()))])),
                 )
                 .message,
-            )),
+            ),
         )
+        .unwrap()
         .write(&mut stream)
         .unwrap();
 
@@ -1003,6 +2126,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_share_data_header() {
+        let data_pdu = DataPdu { pdu_type: PduType2::Fontlist, message: component!["numberFonts" => U16::LE(0)] };
+        let stream = to_vec(&share_data_pdu(data_pdu, Some(0x0001_03ea), 1007).unwrap());
+        assert_eq!(stream, [20, 0, 23, 0, 239, 3, 234, 3, 1, 0, 0, 1, 20, 0, 39, 0, 0, 0, 0, 0]);
+
+        let mut stream = stream.as_slice();
+        let mut header = ShareControlHeader::default();
+        header.read(&mut stream).unwrap();
+        assert_eq!(header, ShareControlHeader::new(PduType::Datapdu, 1007, 14).unwrap());
+        let mut header = ShareDataHeader::default();
+        header.read(&mut stream).unwrap();
+        assert_eq!(header, ShareDataHeader::new(0x0001_03ea, PduType2::Fontlist, 2).unwrap());
+        assert_eq!(header.stream_id, StreamId::Low);
+
+        // the total length is 16 bits
+        assert!(ShareControlHeader::new(PduType::Datapdu, 1007, 0xfff9).is_ok());
+        assert!(ShareControlHeader::new(PduType::Datapdu, 1007, 0xfffa).is_err());
+        assert!(ShareDataHeader::new(0, PduType2::Fontlist, 0xffff - 18).is_ok());
+        assert!(ShareDataHeader::new(0, PduType2::Fontlist, 0xffff - 17).is_err());
+
+        // unknown PDU types are rejected
+        assert!(ShareControlHeader::default().read(&mut &[6, 0, 0x15, 0, 0, 0][..]).is_err());
+        assert!(ShareDataHeader::default().read(&mut &[0, 0, 0, 0, 0, 1, 12, 0, 0x01, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn test_unicode_keyboard_event() {
+        let event = ts_unicode_keyboard_event(Some(KeyboardFlag::Release as u16), Some(0x4e2d));
+        assert_eq!(event.event_type as u16, 0x0005);
+        assert_eq!(to_vec(&event.message), [0x00, 0x80, 0x2d, 0x4e, 0x00, 0x00]);
+    }
+
     #[test]
     fn test_read_synchronize_pdu() {
         let mut stream = Cursor::new(vec![22, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 22, 0, 31, 0, 0, 0, 1, 0, 0, 0]);
@@ -1015,7 +2171,7 @@ mod test {
         let mut stream =
             Cursor::new(vec![26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 20, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
         let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
-        assert!(global.read_control_pdu(&mut stream, Action::Cooperate).unwrap());
+        assert!(global.read_control_pdu(&mut stream, ControlAction::Cooperate).unwrap());
     }
 
     #[test]
@@ -1023,14 +2179,533 @@ mod test {
         let mut stream =
             Cursor::new(vec![26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 20, 0, 0, 0, 2, 0, 236, 3, 234, 3, 0, 0]);
         let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
-        assert!(global.read_control_pdu(&mut stream, Action::GrantedControl).unwrap());
+        assert!(global.read_control_pdu(&mut stream, ControlAction::GrantedControl).unwrap());
+    }
+
+    #[test]
+    fn test_read_fast_path_bitmap_compression_header() {
+        let mut stream = Cursor::new(vec![
+            1, 36, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 1, 0, 14, 0, 0, 0, 4, 0, 4, 0, 4, 0, 16,
+            1, 2, 3, 0xff, 0xff,
+        ]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            RdpEvent::Bitmap(bitmap) => {
+                assert!(bitmap.is_compress);
+                assert_eq!(bitmap.data, [16, 1, 2, 3]);
+            }
+            _ => panic!("expected a bitmap event"),
+        }
+    }
+
+    #[test]
+    fn test_read_fast_path_bitmap_no_compression_header() {
+        let mut stream =
+            Cursor::new(vec![1, 26, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 1, 4, 4, 0, 16, 1, 2, 3]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        match &events[0] {
+            RdpEvent::Bitmap(bitmap) => assert_eq!(bitmap.data, [16, 1, 2, 3]),
+            _ => panic!("expected a bitmap event"),
+        }
+    }
+
+    #[test]
+    fn test_read_fast_path_pointer() {
+        // pointer position then hidden pointer
+        let mut stream = Cursor::new(vec![0x08, 4, 0, 10, 0, 20, 0, 0x05, 0, 0]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        assert_eq!(
+            events,
+            [RdpEvent::PointerPosition { x: 10, y: 20 }, RdpEvent::SystemPointer(SystemPointer::Hidden)]
+        );
+    }
+
+    #[test]
+    fn test_read_fast_path_cached_pointer() {
+        // new 1x1 pointer in slot 2 then the same pointer from the cache
+        let mut stream = Cursor::new(vec![
+            0x0b, 20, 0, 32, 0, 2, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 4, 0, 1, 2, 3, 4, 0x0a, 2, 0, 2, 0,
+        ]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        let shape =
+            RdpEvent::PointerShape { hotspot_x: 0, hotspot_y: 0, width: 1, height: 1, pixels: vec![3, 2, 1, 4] };
+        assert_eq!(events, [shape.clone(), shape]);
+    }
+
+    #[test]
+    fn test_read_fast_path_surface_bits() {
+        let surface_bits = [1, 0, 2, 0, 3, 0, 3, 0, 4, 0, 32, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4];
+        let mut stream =
+            Cursor::new([0x04, surface_bits.len() as u8, 0].into_iter().chain(surface_bits).collect::<Vec<u8>>());
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        match events.as_slice() {
+            [RdpEvent::Bitmap(bitmap)] => {
+                assert_eq!(bitmap.dest_rect(), Rect { left: 2, top: 3, right: 2, bottom: 3 });
+                assert_eq!(bitmap.codec, None);
+                assert_eq!(bitmap.data, [1, 2, 3, 4]);
+            }
+            events => panic!("expected a bitmap {:?}", events),
+        }
+    }
+
+    /// The codecs listed by a Windows server are confirmed with their ids
+    /// and the surface bits are decoded with them
+    #[test]
+    fn test_read_fast_path_negotiated_codec() {
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut stream =
+            Cursor::new(to_vec(&share_control_pdu(PduType::Demandactivepdu, 1002, DEMAND_ACTIVE.to_vec()).unwrap()));
+        assert!(global.read_demand_active_pdu(&mut stream, &mut |_| ()).unwrap());
+        let codecs = [BitmapCodec::NsCodec, BitmapCodec::RemoteFx];
+        assert_eq!(global.get_server_capabilities().unwrap().bitmap_codecs.as_deref(), Some(&codecs[..]));
+        assert_eq!(global.bitmap_codecs, codecs);
+
+        let confirm = to_vec(&global.capabilities.write_confirm().unwrap());
+        let set = confirm.windows(2).position(|cap_type| cap_type == [0x1d, 0]).unwrap();
+        assert_eq!(capability::read_bitmap_codecs(&confirm[set + 4..]).unwrap(), codecs);
+
+        // NSCodec then JPEG which isn't listed by the server
+        for (codec_id, codec) in [(NSCODEC_ID, Some(BitmapCodec::NsCodec)), (JPEG_CODEC_ID, None)] {
+            let surface_bits = [6, 0, 2, 0, 3, 0, 3, 0, 4, 0, 32, 0, 0, codec_id, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4];
+            let mut stream =
+                Cursor::new([0x04, surface_bits.len() as u8, 0].into_iter().chain(surface_bits).collect::<Vec<u8>>());
+            let mut events = Vec::new();
+            let result = global.read_fast_path(&mut stream, |event| events.push(event));
+            match (codec, events.as_slice()) {
+                (Some(codec), [RdpEvent::Bitmap(bitmap)]) => assert_eq!(bitmap.codec, Some(codec)),
+                (None, []) => assert!(result.is_err()),
+                (_, events) => panic!("unexpected events {:?}", events),
+            }
+        }
+    }
+
+    /// The nine grid orders are announced with their cache
+    /// and drawn by an orders update
+    #[test]
+    fn test_read_fast_path_draw_nine_grid() {
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        global.set_bitmap_cache(true);
+        let mut stream = Cursor::new(to_vec(&global.capabilities.write_confirm().unwrap()));
+        let mut confirm = Vec::new();
+        while (stream.position() as usize) < stream.get_ref().len() {
+            let mut set = capability_set(None);
+            set.read(&mut stream).unwrap();
+            confirm.extend(Capability::from_capability_set(&set));
+        }
+        let order = ServerCapabilities::from_capabilities(&confirm).unwrap().order.unwrap();
+        assert!(
+            order.supports(OrderSupportIndex::DrawNineGrid) && order.supports(OrderSupportIndex::MultiDrawNineGrid)
+        );
+        let nine_grid = to_vec(&capability_set(Some(capability::ts_draw_nine_grid_cache_capability_set(true))));
+        assert!(stream.get_ref().windows(nine_grid.len()).any(|set| set == nine_grid));
+
+        let mut orders = nine_grid_bitmap_orders();
+        orders.extend([
+            0x0d, 0x07, 0x1f, // type change, bounds, all fields
+            0x0f, 0x0a, 0x00, 0x0a, 0x00, 0x11, 0x00, 0x11, 0x00, // destination from (10, 10) to (17, 17)
+            0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x05, 0x00, // whole bitmap 5
+        ]);
+        let mut update = vec![0x00];
+        update.extend((orders.len() as u16 + 2).to_le_bytes());
+        update.extend(6_u16.to_le_bytes());
+        update.extend(orders);
+        let mut events = Vec::new();
+        global.read_fast_path(&mut Cursor::new(update), |event| events.push(event)).unwrap();
+        match events.as_slice() {
+            [RdpEvent::Bitmap(bitmap)] => {
+                assert_eq!(bitmap.dest_rect(), Rect { left: 10, top: 10, right: 17, bottom: 17 });
+                let expected: Vec<u8> = (0..8).flat_map(|y| (0..8).flat_map(move |x| nine_grid_pixel(x, y))).collect();
+                assert_eq!(bitmap.data, expected);
+            }
+            events => panic!("expected a bitmap {:?}", events),
+        }
+    }
+
+    #[test]
+    fn test_read_fast_path_synchronize() {
+        let surface_bits = [1, 0, 2, 0, 3, 0, 3, 0, 4, 0, 32, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4];
+        let mut update = vec![0x04, surface_bits.len() as u8, 0];
+        update.extend(surface_bits);
+        update.extend([0x03, 0, 0]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        global.read_fast_path(&mut Cursor::new(update), |event| events.push(event)).unwrap();
+        assert!(matches!(events.as_slice(), [RdpEvent::Bitmap(_), RdpEvent::SyncPoint]));
     }
 
     #[test]
     fn test_read_font_map_pdu() {
         let mut stream =
             Cursor::new(vec![26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 40, 0, 0, 0, 0, 0, 0, 0, 3, 0, 4, 0]);
+        assert!(read_font_map_pdu(&mut stream).unwrap());
+    }
+
+    /// Global channel PDU as sent by the server
+    fn server_pdu(data: &[u8]) -> Vec<u8> {
+        // send data indication from the user 1002 on the channel 1003
+        let mut domain_pdu = vec![0x68, 0, 1, 0x03, 0xeb, 0x70];
+        domain_pdu.extend(to_vec(&per::write_length(data.len() as u16).unwrap()));
+        domain_pdu.extend_from_slice(data);
+        let mut pdu = vec![3, 0];
+        pdu.extend(((domain_pdu.len() + 7) as u16).to_be_bytes());
+        pdu.extend([2, 0xf0, 0x80]);
+        pdu.extend(domain_pdu);
+        pdu
+    }
+
+    /// Demand active then finalization sequence of a server
+    /// The client sends synchronize, cooperate, request control and font list
+    pub(crate) fn activation(server: MockRdpServer) -> MockRdpServer { finalization(demand_active(server)) }
+
+    /// Demand active PDU of the server
+    /// The client confirms then sends synchronize, cooperate and request
+    /// control
+    fn demand_active(server: MockRdpServer) -> MockRdpServer {
+        let demand_active = share_control_pdu(PduType::Demandactivepdu, 1002, DEMAND_ACTIVE.to_vec()).unwrap();
+        server
+            .send(&server_pdu(&to_vec(&demand_active)))
+            .expect_receive(|pdu| has_share_id(pdu, PduType::Confirmactivepdu))
+            .expect_receive(|pdu| has_share_id(pdu, PduType::Datapdu) && pdu.ends_with(&[0x1f, 0, 0, 0, 1, 0, 0xeb, 3]))
+            .expect_receive(|pdu| {
+                has_share_id(pdu, PduType::Datapdu) && pdu.ends_with(&[0x14, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0])
+            })
+            .expect_receive(|pdu| {
+                has_share_id(pdu, PduType::Datapdu) && pdu.ends_with(&[0x14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0])
+            })
+    }
+
+    /// The client PDU carries the share id of the demand active
+    /// right after its share control header
+    fn has_share_id(pdu: &[u8], pdu_type: PduType) -> bool {
+        let mut header = vec![pdu_type as u8, 0, 0xef, 3];
+        header.extend_from_slice(&DEMAND_ACTIVE[..4]);
+        pdu.windows(header.len()).any(|window| window == header)
+    }
+
+    /// Font list of the client then server finalization
+    fn finalization(server: MockRdpServer) -> MockRdpServer {
+        server
+            .expect_receive(|pdu| {
+                has_share_id(pdu, PduType::Datapdu) && pdu.ends_with(&[0x27, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0x32, 0])
+            })
+            .send(&server_pdu(&[22, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 22, 0, 31, 0, 0, 0, 1, 0, 0, 0]))
+            .send(&server_pdu(&[26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 20, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]))
+            .send(&server_pdu(&[
+                26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 20, 0, 0, 0, 2, 0, 236, 3, 234, 3, 0, 0,
+            ]))
+            .send(&server_pdu(&[26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 40, 0, 0, 0, 0, 0, 0, 0, 3, 0, 4, 0]))
+    }
+
+    /// Fast path update with a single bitmap
+    fn fast_path_bitmap() -> Vec<u8> {
+        let bitmap = [1, 26, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 1, 4, 4, 0, 16, 1, 2, 3];
+        let mut fast_path = tpkt::fast_path_header(0, bitmap.len()).unwrap();
+        fast_path.extend(bitmap);
+        fast_path
+    }
+
+    /// Run the server and connect the mcs layer to it
+    fn connect_mock(server: MockRdpServer) -> (mcs::Client<std::net::TcpStream>, JoinHandle<RdpResult<()>>) {
+        let address = server.address();
+        let server = server.run();
+        let link = Link::new(Stream::Raw(std::net::TcpStream::connect(address).unwrap()));
+        let x224 = x224::Client::connect(tpkt::Client::new(link), 0, false, None, false, false).unwrap();
+        (mcs::Client::connected(x224, 1007, &[("global", 1003)]), server)
+    }
+
+    /// Read the demand active, synchronize, cooperate, granted and font map
+    /// PDUs
+    fn read_activation(global: &mut Client, mcs: &mut mcs::Client<std::net::TcpStream>, events: &mut Vec<RdpEvent>) {
+        for _ in 0..5 {
+            assert!(!matches!(global.state, ClientState::Data));
+            let (_, payload) = mcs.read().unwrap();
+            global.read(payload, mcs, |event| events.push(event)).unwrap();
+        }
+        assert!(matches!(global.state, ClientState::Data));
+    }
+
+    /// Replay the finalization sequence of a server
+    /// Bitmaps arrive once the font map is received
+    #[test]
+    fn test_finalization_sequence() {
+        let server = MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin"));
+        let (mut mcs, server) = connect_mock(activation(server).send(&fast_path_bitmap()));
+        let mut global = Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs");
+        let mut events = Vec::new();
+        read_activation(&mut global, &mut mcs, &mut events);
+        server.join().unwrap().unwrap();
+
+        let (_, payload) = mcs.read().unwrap();
+        global.read(payload, &mut mcs, |event| events.push(event)).unwrap();
+        assert!(matches!(events.last(), Some(RdpEvent::Bitmap(_))));
+    }
+
+    /// The lock keys are sent once the font map is received
+    #[test]
+    fn test_sync_toggle_keys() {
+        let server = MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin"));
+        let (mut mcs, server) = connect_mock(activation(server).expect_receive(|pdu| pdu.ends_with(&[0x66])));
+        let mut global = Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs");
+        global.set_toggle_flags(Some(ToggleFlag::NumLock as u32 | ToggleFlag::CapsLock as u32));
+        read_activation(&mut global, &mut mcs, &mut Vec::new());
+        server.join().unwrap().unwrap();
+    }
+
+    /// The keys of the persistent bitmaps are sent before the font list
+    #[test]
+    fn test_persistent_key_list() {
+        let mut cache = PersistentBitmapCache::new();
+        cache.insert(0x0807_0605_0403_0201, 2, CachedBitmap { width: 1, height: 1, data: vec![0; 4] });
+        let server = MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin"));
+        let server = demand_active(server).expect_receive(|pdu| {
+            pdu.windows(3).any(|w| w == [0x2b, 0, 0]) && pdu.ends_with(&[3, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8])
+        });
+        let (mut mcs, server) = connect_mock(finalization(server));
+        let mut global = Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs");
+        global.set_bitmap_cache(true);
+        global.set_persistent_bitmap_cache(cache);
+        read_activation(&mut global, &mut mcs, &mut Vec::new());
+        server.join().unwrap().unwrap();
+        assert_eq!(global.get_persistent_bitmap_cache().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_write_persistent_key_list_pdus() {
+        assert!(write_persistent_key_list_pdus(None, 1007, &[]).unwrap().is_empty());
+        let keys = [(0..100).collect::<Vec<u64>>(), Vec::new(), (0..100).collect()];
+        let pdus = write_persistent_key_list_pdus(None, 1007, &keys).unwrap();
+        assert_eq!(pdus.len(), 2);
+        // 100 keys of the first cache and 69 of the third one
+        assert_eq!(pdus[0][18..40], [100, 0, 0, 0, 69, 0, 0, 0, 0, 0, 100, 0, 0, 0, 100, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(pdus[1][18..40], [0, 0, 0, 0, 31, 0, 0, 0, 0, 0, 100, 0, 0, 0, 100, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(pdus[1].len(), 42 + 31 * 8);
+    }
+
+    /// The server deactivates the session then runs the connection sequence
+    /// again
+    #[test]
+    fn test_reactivation() {
+        let deactivate_all =
+            share_control_pdu(PduType::Deactivateallpdu, 1002, to_vec(&ts_deactivate_all_pdu().message)).unwrap();
+        let server = MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin"));
+        let server = activation(activation(server).send(&server_pdu(&to_vec(&deactivate_all))));
+        let (mut mcs, server) = connect_mock(server.send(&fast_path_bitmap()));
+        let mut global = Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs");
+        let mut events = Vec::new();
+        read_activation(&mut global, &mut mcs, &mut events);
+        assert!(!events.contains(&RdpEvent::Reactivating));
+
+        let (_, payload) = mcs.read().unwrap();
+        global.read(payload, &mut mcs, |event| events.push(event)).unwrap();
+        assert!(matches!(global.state, ClientState::Reactivating));
+        assert_eq!(events.last(), Some(&RdpEvent::Reactivating));
+
+        read_activation(&mut global, &mut mcs, &mut events);
+        let (width, height) = global.get_screen_size();
+        assert_eq!(events.last(), Some(&RdpEvent::Reactivated { width, height }));
+        server.join().unwrap().unwrap();
+
+        let (_, payload) = mcs.read().unwrap();
+        global.read(payload, &mut mcs, |event| events.push(event)).unwrap();
+        assert!(matches!(events.last(), Some(RdpEvent::Bitmap(_))));
+    }
+
+    #[test]
+    fn test_read_monitor_layout_pdu() {
+        let monitor = Monitor { left: 0, top: 0, right: 1023, bottom: 767, is_primary: true };
+        let layout_pdu = component![
+            "monitorCount" => U32::LE(1),
+            "monitorDefArray" => ts_monitor_def(Some(&monitor))
+        ];
+        let data_pdu = DataPdu { pdu_type: PduType2::MonitorLayoutPdu, message: layout_pdu };
+        let mut stream = Cursor::new(to_vec(&share_data_pdu(data_pdu, Some(0), 1002).unwrap()));
         let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
-        assert!(global.read_font_map_pdu(&mut stream).unwrap());
+        let mut events = Vec::new();
+        assert!(!global.read_demand_active_pdu(&mut stream, &mut |event| events.push(event)).unwrap());
+        let expected = MonitorLayout { monitors: vec![monitor] };
+        assert_eq!(global.get_monitor_layout(), Some(&expected));
+        match &events[0] {
+            RdpEvent::MonitorLayout(layout) => assert_eq!(layout, &expected),
+            _ => panic!("expected a monitor layout event"),
+        }
+    }
+
+    #[test]
+    fn test_read_fast_path_bitmap_monitor() {
+        let mut stream =
+            Cursor::new(vec![1, 26, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 1, 4, 4, 0, 16, 1, 2, 3]);
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        global.set_monitor_layout(MonitorLayout {
+            monitors: vec![
+                Monitor { left: 0, top: 0, right: 1919, bottom: 1079, is_primary: true },
+                Monitor { left: -1280, top: 0, right: -1, bottom: 1023, is_primary: false },
+            ],
+        });
+        let mut events = Vec::new();
+        global.read_fast_path(&mut stream, |event| events.push(event)).unwrap();
+        match &events[0] {
+            RdpEvent::Bitmap(bitmap) => assert_eq!(bitmap.monitor, Some(1)),
+            _ => panic!("expected a bitmap event"),
+        }
+    }
+
+    #[test]
+    fn test_read_slow_path_orders() {
+        // one opaque rectangle of 2x2 at (1, 1)
+        let update = component![
+            "updateType" => U16::LE(UpdateType::Orders as u16),
+            "pad2OctetsA" => U16::LE(0),
+            "numberOrders" => U16::LE(1),
+            "pad2OctetsB" => U16::LE(0),
+            "orderData" => vec![0x09_u8, 0x0a, 0x0f, 1, 0, 1, 0, 2, 0, 2, 0]
+        ];
+        let data_pdu = DataPdu { pdu_type: PduType2::Update, message: update };
+        let mut stream = Cursor::new(to_vec(&share_data_pdu(data_pdu, Some(0), 1002).unwrap()));
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        global.set_bitmap_cache(true);
+        let mut events = Vec::new();
+        global.read_data_pdu(&mut stream, &mut |event| events.push(event)).unwrap();
+        let rect = Rect { left: 1, top: 1, right: 2, bottom: 2 };
+        assert_eq!(events, [RdpEvent::DrawOrder(DrawOrderEvent::OpaqueRect { rect, color: 0 })]);
+    }
+
+    /// Input is refused until the server allows display updates again
+    #[test]
+    fn test_suppress_output_from_server() {
+        let server = MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin"));
+        let address = server.address();
+        let server = server.run();
+        let link = Link::new(Stream::Raw(std::net::TcpStream::connect(address).unwrap()));
+        let x224 = x224::Client::connect(tpkt::Client::new(link), 0, false, None, false, false).unwrap();
+        let mut mcs = mcs::Client::connected(x224, 1007, &[("global", 1003)]);
+        server.join().unwrap().unwrap();
+
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let mut events = Vec::new();
+        for allow in [None, Some((800, 600))] {
+            let pdu = share_data_pdu(ts_suppress_output_pdu(allow), Some(0), 1002).unwrap();
+            let mut stream = Cursor::new(to_vec(&pdu));
+            global.read_data_pdu(&mut stream, &mut |event| events.push(event)).unwrap();
+            if allow.is_none() {
+                assert!(global.is_output_suppressed());
+                let error = global.write_input_events(vec![ts_pointer_event(Some(0), Some(1), Some(1))], &mut mcs);
+                match error {
+                    Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidData),
+                    _ => panic!("input must be refused"),
+                }
+                assert!(events.is_empty());
+            }
+        }
+        assert!(!global.is_output_suppressed());
+        assert_eq!(events, [RdpEvent::DisplayResumed]);
+    }
+
+    /// Input sent to a server which stops reading
+    /// fails with a timeout instead of blocking
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_input_write_timeout() {
+        let (release, hold) = std::sync::mpsc::channel();
+        let server =
+            MockRdpServer::new().expect_receive(|pdu| pdu[5] == 0xe0).send(&fixture("x224_confirm_rdp.bin")).hold(hold);
+        let address = server.address();
+        let server = server.run();
+        let tcp = std::net::TcpStream::connect(address).unwrap();
+        socket2::SockRef::from(&tcp).set_send_buffer_size(4096).unwrap();
+        let stream = WriteTimeoutStream::new(tcp, std::time::Duration::from_millis(50)).unwrap();
+        let x224 =
+            x224::Client::connect(tpkt::Client::new(Link::new(Stream::Raw(stream))), 0, false, None, false, false)
+                .unwrap();
+        let mut mcs = mcs::Client::connected(x224, 1007, &[("global", 1003)]);
+
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        global.state = ClientState::Data;
+        let error = loop {
+            let events = (0..MAX_FAST_PATH_EVENTS).map(|_| ts_pointer_event(Some(0), Some(1), Some(1))).collect();
+            if let Err(e) = global.write_input_events(events, &mut mcs) {
+                break e;
+            }
+        };
+        match error {
+            Error::RdpError(e) => assert_eq!(e.kind(), RdpErrorKind::Timeout),
+            e => panic!("expected a timeout error {:?}", e),
+        }
+        drop(release);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_error_info_warning() {
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut global = Client::new(0, 0, 800, 600, KeyboardLayout::US, "foo");
+        let received = warnings.clone();
+        global.set_warning_handler(WarningHandler::new(move |warning| received.lock().unwrap().push(warning)));
+
+        let mut events = Vec::new();
+        for error_info in [0, 0x0c] {
+            let data_pdu = DataPdu {
+                pdu_type: PduType2::SetErrorInfoPdu,
+                message: component!["errorInfo" => U32::LE(error_info)],
+            };
+            let mut stream = Cursor::new(to_vec(&share_data_pdu(data_pdu, Some(0), 1002).unwrap()));
+            global.read_data_pdu(&mut stream, &mut |event| events.push(event)).unwrap();
+        }
+        assert_eq!(*warnings.lock().unwrap(), [RdpWarning::ServerErrorInfo(0x0c)]);
+        assert_eq!(events, [RdpEvent::ErrorInfo(ErrorInfoCode::LogoffByUser)]);
+        assert_eq!(global.get_disconnect_reason(), DisconnectReason::LogoffByUser);
+        assert_eq!(global.get_error_info(), ErrorInfoCode::LogoffByUser);
+    }
+
+    #[test]
+    fn test_suppress_output_pdu() {
+        assert_eq!(to_vec(&ts_suppress_output_pdu(None).message), [0, 0, 0, 0]);
+        assert_eq!(to_vec(&ts_suppress_output_pdu(Some((800, 600))).message), [1, 0, 0, 0, 0, 0, 0, 0, 31, 3, 87, 2]);
+    }
+
+    #[test]
+    fn test_read_flow_pdu() {
+        let mut global = Client::new(1007, 1003, 800, 600, KeyboardLayout::US, "rdp-rs");
+        let mut stream = Cursor::new(vec![0x12, 0x00, 0x17, 0x00]);
+        assert!(!global.read_flow_pdu(&mut stream).unwrap());
+        assert_eq!(stream.position(), 0);
+
+        // share control header of 0x8000 bytes with a demand active PDU
+        let mut stream = Cursor::new(vec![0x00, 0x80, 0x11, 0x00, 0xea, 0x03]);
+        assert!(!global.read_flow_pdu(&mut stream).unwrap());
+        assert_eq!(stream.position(), 0);
+
+        let mut stream = Cursor::new(vec![0x00, 0x80, 0x42, 0x00, 0x00, 0x02, 0xea, 0x03]);
+        assert!(global.read_flow_pdu(&mut stream).unwrap());
+        assert_eq!(stream.position(), 8);
+    }
+
+    /// A pointer move costs 9 bytes as fast path
+    /// instead of 48 bytes with the slow path headers
+    #[test]
+    fn test_fast_path_input_size() {
+        let slow_path = ts_input_pdu_data(Some(Array::from_trame(trame![ts_input_event(
+            Some(InputEventType::Mouse),
+            Some(to_vec(&ts_pointer_event(None, None, None).message))
+        )])));
+        let slow_path = to_vec(&share_data_pdu(slow_path, Some(0), 1007).unwrap());
+        // TPKT, X224 and MCS send data request headers
+        assert_eq!(slow_path.len() + 4 + 3 + 7, 48);
+
+        let mut fast_path = Vec::new();
+        let events =
+            [RdpEvent::Pointer(PointerEvent { x: 0, y: 0, button: PointerButton::None, down: false, wheel_delta: 0 })];
+        write_fast_path_input(&events, &mut fast_path).unwrap();
+        assert_eq!(fast_path.len(), 9);
     }
 }
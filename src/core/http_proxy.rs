@@ -0,0 +1,267 @@
+use std::io::{Read, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Largest header accepted from the proxy
+const MAX_HEADER_SIZE: usize = 8192;
+
+const STATUS_OK: u16 = 200;
+const STATUS_PROXY_AUTHENTICATION_REQUIRED: u16 = 407;
+
+/// Outcome of a CONNECT request
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectStatus {
+    /// The stream is a tunnel to the target
+    Established,
+    /// The proxy asks for credentials
+    /// It closes the connection when `keep_alive` is false
+    AuthenticationRequired { keep_alive: bool },
+}
+
+fn proxy_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("HTTP PROXY: {}", message)))
+}
+
+/// Authority of the target, IPv6 addresses are bracketed
+fn authority(target_host: &str, target_port: u16) -> String {
+    if target_host.contains(':') && !target_host.starts_with('[') {
+        format!("[{}]:{}", target_host, target_port)
+    } else {
+        format!("{}:{}", target_host, target_port)
+    }
+}
+
+/// CONNECT request with optional basic credentials
+///
+/// See RFC 7231 4.3.6 and RFC 7617
+fn connect_request(target_host: &str, target_port: u16, credentials: Option<(&str, &str)>) -> String {
+    let authority = authority(target_host, target_port);
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+    if let Some((username, password)) = credentials {
+        let token = BASE64.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    request
+}
+
+/// Read the reply header byte per byte
+/// so nothing of the tunneled stream is consumed
+fn read_header<S: Read>(stream: &mut S) -> RdpResult<String> {
+    let mut header = Vec::new();
+    let mut byte = [0_u8];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_HEADER_SIZE {
+            return Err(proxy_error(RdpErrorKind::InvalidSize, "Reply header too large"));
+        }
+        stream.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    String::from_utf8(header).map_err(|_| proxy_error(RdpErrorKind::InvalidData, "Reply header is not UTF-8"))
+}
+
+/// Send a CONNECT request and read the reply
+/// Any status other than 200 and 407 is an error
+fn send_connect<S: Read + Write>(
+    stream: &mut S, target_host: &str, target_port: u16, credentials: Option<(&str, &str)>,
+) -> RdpResult<ConnectStatus> {
+    stream.write_all(connect_request(target_host, target_port, credentials).as_bytes())?;
+    let header = read_header(stream)?;
+    let mut lines = header.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut fields = status_line.splitn(3, ' ');
+    let version = fields.next().unwrap_or_default();
+    let status = fields
+        .next()
+        .and_then(|status| status.parse::<u16>().ok())
+        .filter(|_| version.starts_with("HTTP/1."))
+        .ok_or_else(|| proxy_error(RdpErrorKind::InvalidData, &format!("Invalid status line {:?}", status_line)))?;
+
+    let mut content_length = 0;
+    let mut keep_alive = version != "HTTP/1.0";
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse::<u64>()
+                    .map_err(|_| proxy_error(RdpErrorKind::InvalidData, "Invalid content length"))?
+            }
+            "connection" | "proxy-connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+            _ => (),
+        }
+    }
+
+    match status {
+        STATUS_OK => Ok(ConnectStatus::Established),
+        STATUS_PROXY_AUTHENTICATION_REQUIRED => {
+            // The body must be skipped to send the next request
+            if keep_alive {
+                std::io::copy(&mut stream.take(content_length), &mut std::io::sink())?;
+            }
+            Ok(ConnectStatus::AuthenticationRequired { keep_alive })
+        }
+        _ => Err(proxy_error(RdpErrorKind::RejectedByServer, &format!("Connect request rejected: {}", status_line))),
+    }
+}
+
+/// Ask an HTTP proxy to open a tunnel to the target
+/// Credentials are only sent when the proxy asks for them
+/// with a 407 reply on a connection kept alive
+///
+/// # Example
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use rdp::core::http_proxy::{http_connect, ConnectStatus};
+/// struct Proxy(Cursor<Vec<u8>>, Vec<u8>);
+/// impl Read for Proxy {
+///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+/// }
+/// impl Write for Proxy {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.1.write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+/// let mut proxy = Proxy(Cursor::new(b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec()), vec![]);
+/// assert_eq!(http_connect(&mut proxy, "rdp", 3389, None).unwrap(), ConnectStatus::Established);
+/// assert_eq!(proxy.1, b"CONNECT rdp:3389 HTTP/1.1\r\nHost: rdp:3389\r\n\r\n");
+/// ```
+pub fn http_connect<S: Read + Write>(
+    stream: &mut S, target_host: &str, target_port: u16, credentials: Option<(&str, &str)>,
+) -> RdpResult<ConnectStatus> {
+    match send_connect(stream, target_host, target_port, None)? {
+        ConnectStatus::AuthenticationRequired { keep_alive: true } if credentials.is_some() => {
+            http_connect_with_credentials(stream, target_host, target_port, credentials)
+        }
+        status => Ok(status),
+    }
+}
+
+/// Send the credentials with the CONNECT request
+/// A second 407 reply means they are rejected
+pub fn http_connect_with_credentials<S: Read + Write>(
+    stream: &mut S, target_host: &str, target_port: u16, credentials: Option<(&str, &str)>,
+) -> RdpResult<ConnectStatus> {
+    match send_connect(stream, target_host, target_port, credentials)? {
+        ConnectStatus::Established => Ok(ConnectStatus::Established),
+        ConnectStatus::AuthenticationRequired { .. } => {
+            Err(proxy_error(RdpErrorKind::AuthenticationFailed, "Proxy credentials rejected"))
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test {
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::*;
+    use crate::core::socks::ProxyConfig;
+
+    const AUTHENTICATION_REQUIRED: &[u8] =
+        b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\nContent-Length: 4\r\n";
+
+    /// Read a request header and return the Proxy-Authorization value
+    fn read_request(stream: &mut BufReader<TcpStream>) -> Option<Option<String>> {
+        let mut authorization = None;
+        let mut line = String::new();
+        stream.read_line(&mut line).ok()?;
+        if !line.starts_with("CONNECT rdp.example.com:3389 HTTP/1.1") {
+            return None;
+        }
+        loop {
+            line.clear();
+            stream.read_line(&mut line).ok()?;
+            if line == "\r\n" {
+                return Some(authorization);
+            }
+            if let Some(value) = line.strip_prefix("Proxy-Authorization: ") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    /// Mock proxy asking for credentials then echoing the tunneled data
+    /// Each connection is closed after a 407 reply if `close` is set
+    fn mock_proxy(close: bool) -> (u16, thread::JoinHandle<Vec<Option<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            let (mut tcp, _) = listener.accept().unwrap();
+            loop {
+                let mut reader = BufReader::new(tcp.try_clone().unwrap());
+                let authorization = read_request(&mut reader).unwrap();
+                requests.push(authorization.clone());
+                if authorization.as_deref() == Some("Basic dXNlcjpwYXNz") {
+                    tcp.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap();
+                    let mut data = [0; 4];
+                    reader.read_exact(&mut data).unwrap();
+                    tcp.write_all(&data).unwrap();
+                    return requests;
+                }
+                tcp.write_all(AUTHENTICATION_REQUIRED).unwrap();
+                if close {
+                    tcp.write_all(b"Connection: close\r\n\r\ndeny").unwrap();
+                    tcp = listener.accept().unwrap().0;
+                } else {
+                    tcp.write_all(b"\r\ndeny").unwrap();
+                }
+            }
+        });
+        (port, server)
+    }
+
+    fn proxy_config(port: u16) -> ProxyConfig {
+        ProxyConfig::Http { host: "127.0.0.1".to_string(), port, auth: Some(("user".to_string(), "pass".to_string())) }
+    }
+
+    fn check_tunnel(mut tcp: TcpStream) {
+        tcp.write_all(b"rdp!").unwrap();
+        let mut echo = [0; 4];
+        tcp.read_exact(&mut echo).unwrap();
+        assert_eq!(&echo, b"rdp!");
+    }
+
+    #[test]
+    fn test_http_connect_authentication_keep_alive() {
+        let (port, server) = mock_proxy(false);
+        check_tunnel(proxy_config(port).connect("rdp.example.com", 3389).unwrap());
+        assert_eq!(server.join().unwrap(), [None, Some("Basic dXNlcjpwYXNz".to_string())]);
+    }
+
+    #[test]
+    fn test_http_connect_authentication_reconnect() {
+        let (port, server) = mock_proxy(true);
+        check_tunnel(proxy_config(port).connect("rdp.example.com", 3389).unwrap());
+        assert_eq!(server.join().unwrap(), [None, Some("Basic dXNlcjpwYXNz".to_string())]);
+    }
+
+    #[test]
+    fn test_http_connect_rejected() {
+        struct Proxy(std::io::Cursor<Vec<u8>>);
+        impl Read for Proxy {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+        }
+        impl Write for Proxy {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { Ok(buf.len()) }
+
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        let mut proxy = Proxy(std::io::Cursor::new(b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec()));
+        assert!(http_connect(&mut proxy, "rdp", 3389, None).is_err());
+        let mut proxy = Proxy(std::io::Cursor::new([AUTHENTICATION_REQUIRED, b"\r\ndeny"].concat()));
+        assert_eq!(
+            http_connect(&mut proxy, "rdp", 3389, None).unwrap(),
+            ConnectStatus::AuthenticationRequired { keep_alive: true }
+        );
+        assert_eq!(authority("::1", 3389), "[::1]:3389");
+        assert_eq!(authority("[::1]", 3389), "[::1]:3389");
+    }
+}
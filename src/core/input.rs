@@ -0,0 +1,288 @@
+use std::io::Write;
+
+use crate::core::event::{PointerButton, RdpEvent};
+use crate::core::global::{
+    ts_keyboard_event, ts_pointer_event, ts_pointerx_event, ts_unicode_keyboard_event, wheel_steps, InputEventType,
+    KeyboardFlag, PointerFlag, PointerXFlag, TSInputEvent, WHEEL_DELTA,
+};
+use crate::core::tpkt::fast_path_header;
+use crate::model::data::{to_vec, DataType};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// The number of events of a fast path input PDU
+/// is encoded on 4 bits in its header
+pub const MAX_FAST_PATH_EVENTS: usize = 15;
+
+/// Code of a fast path input event
+///
+/// See MS-RDPBCGR 2.2.8.1.2.2
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+enum FastPathInputEventCode {
+    Scancode = 0x0,
+    Mouse = 0x1,
+    Mousex = 0x2,
+    Sync = 0x3,
+    Unicode = 0x4,
+    Relmouse = 0x5,
+    QoeTimestamp = 0x6,
+}
+
+/// Keyboard flags of fast path scancode and unicode events
+///
+/// See MS-RDPBCGR 2.2.8.1.2.2.1
+#[repr(u8)]
+#[derive(Clone, Copy, Debug)]
+enum FastPathKeyboardFlag {
+    Release = 0x01,
+    Extended = 0x02,
+    Extended1 = 0x04,
+}
+
+/// Translate an event into slow path input events
+/// Large wheel rotations and surrogate pairs give several events
+///
+/// # Example
+/// ```
+/// use rdp::core::event::{KeyboardEvent, RdpEvent};
+/// use rdp::core::input::input_events;
+/// let events = input_events(&RdpEvent::Key(KeyboardEvent { code: 0x1e, down: true })).unwrap();
+/// assert_eq!(events.len(), 1);
+/// ```
+pub fn input_events(event: &RdpEvent) -> RdpResult<Vec<TSInputEvent>> {
+    match event {
+        // Mouse position and button state
+        RdpEvent::Pointer(pointer) => {
+            let rotation = match pointer.wheel_delta.unsigned_abs() {
+                0 => WHEEL_DELTA,
+                delta => delta.min(i16::MAX as u16) as i16,
+            };
+            let mut flags: u16 = match pointer.button {
+                PointerButton::Left => PointerFlag::Button1 as u16,
+                PointerButton::Right => PointerFlag::Button2 as u16,
+                PointerButton::Middle => PointerFlag::Button3 as u16,
+                // Large rotations don't fit in a single event
+                PointerButton::ScrollUp
+                | PointerButton::ScrollDown
+                | PointerButton::ScrollLeft
+                | PointerButton::ScrollRight => {
+                    let (horizontal, rotation) = match pointer.button {
+                        PointerButton::ScrollUp => (false, rotation),
+                        PointerButton::ScrollDown => (false, -rotation),
+                        PointerButton::ScrollLeft => (true, -rotation),
+                        _ => (true, rotation),
+                    };
+                    return Ok(wheel_steps(horizontal, rotation)
+                        .into_iter()
+                        .map(|flags| ts_pointer_event(Some(flags), Some(pointer.x), Some(pointer.y)))
+                        .collect());
+                }
+                // Extended buttons use their own event
+                PointerButton::X1 | PointerButton::X2 => {
+                    let mut flags = if pointer.button == PointerButton::X1 {
+                        PointerXFlag::Button1 as u16
+                    } else {
+                        PointerXFlag::Button2 as u16
+                    };
+                    if pointer.down {
+                        flags |= PointerXFlag::Down as u16;
+                    }
+                    return Ok(vec![ts_pointerx_event(Some(flags), Some(pointer.x), Some(pointer.y))]);
+                }
+                PointerButton::None => PointerFlag::Move as u16,
+            };
+
+            if pointer.down {
+                flags |= PointerFlag::Down as u16;
+            }
+            Ok(vec![ts_pointer_event(Some(flags), Some(pointer.x), Some(pointer.y))])
+        }
+        // Raw keyboard input
+        RdpEvent::Key(key) => {
+            let flags = if key.down { 0 } else { KeyboardFlag::Release as u16 };
+            Ok(vec![ts_keyboard_event(Some(flags), Some(key.code))])
+        }
+        // Characters are sent as UTF-16 code units
+        RdpEvent::UnicodeKey(key) => {
+            let flags = if key.down { 0 } else { KeyboardFlag::Release as u16 };
+            let mut buffer = [0; 2];
+            Ok(key
+                .character
+                .encode_utf16(&mut buffer)
+                .iter()
+                .map(|code| ts_unicode_keyboard_event(Some(flags), Some(*code)))
+                .collect())
+        }
+        _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::UnexpectedType, "RDPCLIENT: This event can't be sent"))),
+    }
+}
+
+/// Header of a fast path input event
+fn event_header(code: FastPathInputEventCode, flags: u8) -> u8 { (code as u8) << 5 | (flags & 0x1f) }
+
+/// Keyboard flags of a fast path event from slow path ones
+fn keyboard_flags(flags: u16) -> u8 {
+    let mut result = 0;
+    if flags & KeyboardFlag::Release as u16 != 0 {
+        result |= FastPathKeyboardFlag::Release as u8;
+    }
+    if flags & KeyboardFlag::Extended as u16 != 0 {
+        result |= FastPathKeyboardFlag::Extended as u8;
+    }
+    result
+}
+
+/// Encode a slow path input event as a fast path one
+///
+/// Fast path scancodes are a single byte,
+/// the 0xE0 and 0xE1 prefixes become extended flags
+///
+/// See MS-RDPBCGR 2.2.8.1.2.2
+fn ts_fp_input_event(event: &TSInputEvent) -> RdpResult<Vec<u8>> {
+    let message = &event.message;
+    match event.event_type {
+        InputEventType::Scancode => {
+            let key_code = cast!(DataType::U16, message["keyCode"])?;
+            let mut flags = keyboard_flags(cast!(DataType::U16, message["keyboardFlags"])?);
+            match key_code >> 8 {
+                0xe0 => flags |= FastPathKeyboardFlag::Extended as u8,
+                0xe1 => flags |= FastPathKeyboardFlag::Extended1 as u8,
+                _ => (),
+            }
+            Ok(vec![event_header(FastPathInputEventCode::Scancode, flags), key_code as u8])
+        }
+        InputEventType::Unicode => {
+            let flags = keyboard_flags(cast!(DataType::U16, message["keyboardFlags"])?);
+            let mut result = vec![event_header(FastPathInputEventCode::Unicode, flags)];
+            result.extend_from_slice(&cast!(DataType::U16, message["unicodeCode"])?.to_le_bytes());
+            Ok(result)
+        }
+        // Pointer events have the same payload
+        InputEventType::Mouse | InputEventType::Mousex => {
+            let code = match event.event_type {
+                InputEventType::Mouse => FastPathInputEventCode::Mouse,
+                _ => FastPathInputEventCode::Mousex,
+            };
+            let mut result = vec![event_header(code, 0)];
+            result.extend(to_vec(message));
+            Ok(result)
+        }
+        // The toggle flags fit in the event flags
+        InputEventType::Sync => {
+            let toggle_flags = cast!(DataType::U32, message["toggleFlags"])?;
+            Ok(vec![event_header(FastPathInputEventCode::Sync, toggle_flags as u8)])
+        }
+        InputEventType::Unused => {
+            Err(Error::RdpError(RdpError::new(RdpErrorKind::NotImplemented, "INPUT: Event not supported by fast path")))
+        }
+    }
+}
+
+/// Encode the events of a fast path input PDU
+/// The number of events goes in the header
+///
+/// # Example
+/// ```
+/// use rdp::core::global::ts_keyboard_event;
+/// use rdp::core::input::ts_fp_input_events;
+/// assert_eq!(ts_fp_input_events(&[ts_keyboard_event(Some(0x8000), Some(0xe01d))]).unwrap(), [0x03, 0x1d]);
+/// ```
+pub fn ts_fp_input_events(events: &[TSInputEvent]) -> RdpResult<Vec<u8>> {
+    let mut result = Vec::new();
+    for event in events {
+        result.extend(ts_fp_input_event(event)?);
+    }
+    Ok(result)
+}
+
+/// Write events as unencrypted fast path input PDUs
+/// Events are split into PDUs of at most 15 events
+///
+/// See MS-RDPBCGR 2.2.8.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::event::{PointerButton, PointerEvent, RdpEvent};
+/// use rdp::core::input::write_fast_path_input;
+/// let mut stream = Vec::new();
+/// let event = RdpEvent::Pointer(PointerEvent { x: 1, y: 2, button: PointerButton::None, down: false, wheel_delta: 0 });
+/// write_fast_path_input(&[event], &mut stream).unwrap();
+/// assert_eq!(stream, [0x04, 9, 0x20, 0, 0x08, 1, 0, 2, 0]);
+/// ```
+pub fn write_fast_path_input(events: &[RdpEvent], stream: &mut dyn Write) -> RdpResult<()> {
+    let mut inputs = Vec::new();
+    for event in events {
+        inputs.extend(input_events(event)?);
+    }
+    // All the PDUs are written at once
+    let mut pdus = Vec::new();
+    for chunk in inputs.chunks(MAX_FAST_PATH_EVENTS) {
+        let data = ts_fp_input_events(chunk)?;
+        pdus.extend(fast_path_header((chunk.len() as u8) << 2, data.len())?);
+        pdus.extend(data);
+    }
+    Ok(stream.write_all(&pdus)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::event::{KeyboardEvent, PointerEvent, UnicodeKeyboardEvent};
+    use crate::core::global::{ts_sync_event, ToggleFlag};
+
+    #[test]
+    fn test_fast_path_keyboard_events() {
+        let release = ts_keyboard_event(Some(KeyboardFlag::Release as u16), Some(0x1e));
+        assert_eq!(ts_fp_input_event(&release).unwrap(), [0x01, 0x1e]);
+        let pause = ts_keyboard_event(None, Some(0xe11d));
+        assert_eq!(ts_fp_input_event(&pause).unwrap(), [0x04, 0x1d]);
+        let unicode = ts_unicode_keyboard_event(Some(KeyboardFlag::Release as u16), Some(0x20ac));
+        assert_eq!(ts_fp_input_event(&unicode).unwrap(), [0x81, 0xac, 0x20]);
+    }
+
+    #[test]
+    fn test_fast_path_sync_event() {
+        let event = ts_sync_event(Some(ToggleFlag::NumLock as u32 | ToggleFlag::CapsLock as u32));
+        assert_eq!(ts_fp_input_event(&event).unwrap(), [0x66]);
+    }
+
+    #[test]
+    fn test_fast_path_pointerx_event() {
+        let event = ts_pointerx_event(Some(PointerXFlag::Button1 as u16), Some(3), Some(4));
+        assert_eq!(ts_fp_input_event(&event).unwrap(), [0x40, 1, 0, 3, 0, 4, 0]);
+    }
+
+    #[test]
+    fn test_write_fast_path_input_split() {
+        let events = vec![RdpEvent::Key(KeyboardEvent { code: 0x1e, down: true }); 16];
+        let mut stream = Vec::new();
+        write_fast_path_input(&events, &mut stream).unwrap();
+        // 15 events then a single one
+        assert_eq!(stream[..2], [15 << 2, 2 + 30]);
+        assert_eq!(stream[32..], [1 << 2, 4, 0, 0x1e]);
+    }
+
+    #[test]
+    fn test_write_fast_path_input_surrogate() {
+        let event = RdpEvent::UnicodeKey(UnicodeKeyboardEvent { character: '😀', down: true });
+        let mut stream = Vec::new();
+        write_fast_path_input(&[event], &mut stream).unwrap();
+        assert_eq!(stream, [2 << 2, 8, 0x80, 0x3d, 0xd8, 0x80, 0x00, 0xde]);
+    }
+
+    #[test]
+    fn test_write_fast_path_input_unexpected_event() {
+        let mut stream = Vec::new();
+        assert!(write_fast_path_input(&[RdpEvent::Reconnected], &mut stream).is_err());
+        let wheel = RdpEvent::Pointer(PointerEvent {
+            x: 0,
+            y: 0,
+            button: PointerButton::ScrollDown,
+            down: false,
+            wheel_delta: 0,
+        });
+        write_fast_path_input(&[wheel], &mut stream).unwrap();
+        assert_eq!(stream, [1 << 2, 9, 0x20, 0x88, 0x03, 0, 0, 0, 0]);
+    }
+}
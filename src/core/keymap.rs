@@ -0,0 +1,356 @@
+use crate::core::gcc::KeyboardLayout;
+
+/// Scancodes of the modifiers and control keys
+const SCANCODE_SHIFT: u16 = 0x2a;
+/// AltGr is the extended right Alt key
+const SCANCODE_ALTGR: u16 = 0x38;
+const SCANCODE_ENTER: u16 = 0x1c;
+const SCANCODE_TAB: u16 = 0x0f;
+const SCANCODE_BACKSPACE: u16 = 0x0e;
+const SCANCODE_ESCAPE: u16 = 0x01;
+const SCANCODE_CTRL: u16 = 0x1d;
+const SCANCODE_ALT: u16 = 0x38;
+/// Delete and the Windows key are extended keys
+const SCANCODE_DELETE: u16 = 0x53;
+const SCANCODE_WIN: u16 = 0x5b;
+const SCANCODE_C: u16 = 0x2e;
+const SCANCODE_F4: u16 = 0x3e;
+
+/// Row of consecutive keys
+/// (first scancode, characters, characters with shift)
+/// A nul character is a key without printable character, like a dead key
+type Row = (u16, &'static str, &'static str);
+
+/// Key pressed with AltGr
+/// (scancode, character)
+type AltGrKey = (u16, char);
+
+const US_ROWS: [Row; 7] = [
+    (0x29, "`", "~"),
+    (0x02, "1234567890-=", "!@#$%^&*()_+"),
+    (0x10, "qwertyuiop[]", "QWERTYUIOP{}"),
+    (0x1e, "asdfghjkl;'", "ASDFGHJKL:\""),
+    (0x2b, "\\", "|"),
+    (0x2c, "zxcvbnm,./", "ZXCVBNM<>?"),
+    (0x39, " ", ""),
+];
+
+const UK_ROWS: [Row; 8] = [
+    (0x29, "`", "¬"),
+    (0x02, "1234567890-=", "!\"£$%^&*()_+"),
+    (0x10, "qwertyuiop[]", "QWERTYUIOP{}"),
+    (0x1e, "asdfghjkl;'", "ASDFGHJKL:@"),
+    (0x2b, "#", "~"),
+    (0x56, "\\", "|"),
+    (0x2c, "zxcvbnm,./", "ZXCVBNM<>?"),
+    (0x39, " ", ""),
+];
+
+const UK_ALTGR: [AltGrKey; 1] = [(0x05, '€')];
+
+const FRENCH_ROWS: [Row; 9] = [
+    (0x29, "²", ""),
+    (0x02, "&é\"'(-è_çà)=", "1234567890°+"),
+    (0x10, "azertyuiop", "AZERTYUIOP"),
+    (0x1b, "$", "£"),
+    (0x1e, "qsdfghjklmù", "QSDFGHJKLM%"),
+    (0x2b, "*", "µ"),
+    (0x56, "<", ">"),
+    (0x2c, "wxcvbn,;:!", "WXCVBN?./§"),
+    (0x39, " ", ""),
+];
+
+const FRENCH_ALTGR: [AltGrKey; 9] = [
+    (0x04, '#'),
+    (0x05, '{'),
+    (0x06, '['),
+    (0x07, '|'),
+    (0x09, '\\'),
+    (0x0b, '@'),
+    (0x0c, ']'),
+    (0x0d, '}'),
+    (0x12, '€'),
+];
+
+const GERMAN_ROWS: [Row; 8] = [
+    (0x29, "\0", "°"),
+    (0x02, "1234567890ß", "!\"§$%&/()=?"),
+    (0x10, "qwertzuiopü+", "QWERTZUIOPÜ*"),
+    (0x1e, "asdfghjklöä", "ASDFGHJKLÖÄ"),
+    (0x2b, "#", "'"),
+    (0x56, "<", ">"),
+    (0x2c, "yxcvbnm,.-", "YXCVBNM;:_"),
+    (0x39, " ", ""),
+];
+
+const GERMAN_ALTGR: [AltGrKey; 12] = [
+    (0x03, '²'),
+    (0x04, '³'),
+    (0x08, '{'),
+    (0x09, '['),
+    (0x0a, ']'),
+    (0x0b, '}'),
+    (0x0c, '\\'),
+    (0x10, '@'),
+    (0x12, '€'),
+    (0x1b, '~'),
+    (0x32, 'µ'),
+    (0x56, '|'),
+];
+
+/// Key to press to type a character
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct KeyStroke {
+    pub scancode: u16,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+/// Low level keyboard input
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum KeyInput {
+    /// Scancode event, extended keys set the extended flag
+    Scancode { code: u16, extended: bool, down: bool },
+    /// UTF-16 code unit sent with a unicode keyboard event
+    Unicode { code: u16, down: bool },
+}
+
+fn tables(layout: KeyboardLayout) -> Option<(&'static [Row], &'static [AltGrKey])> {
+    match layout {
+        KeyboardLayout::US => Some((&US_ROWS, &[])),
+        KeyboardLayout::UnitedKingdom => Some((&UK_ROWS, &UK_ALTGR)),
+        KeyboardLayout::French => Some((&FRENCH_ROWS, &FRENCH_ALTGR)),
+        KeyboardLayout::German => Some((&GERMAN_ROWS, &GERMAN_ALTGR)),
+        _ => None,
+    }
+}
+
+/// Find the key producing a character with a keyboard layout
+///
+/// Only the US, United Kingdom, French and German layouts are known,
+/// None is returned for other layouts and characters without key
+///
+/// # Example
+/// ```
+/// use rdp::core::gcc::KeyboardLayout;
+/// use rdp::core::keymap::{to_keystroke, KeyStroke};
+/// assert_eq!(to_keystroke(KeyboardLayout::US, 'A'), Some(KeyStroke { scancode: 0x1e, shift: true, altgr: false }));
+/// assert_eq!(to_keystroke(KeyboardLayout::French, 'A'), Some(KeyStroke { scancode: 0x10, shift: true, altgr: false }));
+/// assert_eq!(to_keystroke(KeyboardLayout::US, 'é'), None);
+/// ```
+pub fn to_keystroke(layout: KeyboardLayout, c: char) -> Option<KeyStroke> {
+    if c == '\0' {
+        return None;
+    }
+    let (rows, altgr) = tables(layout)?;
+    for (first, normal, shifted) in rows {
+        for (shift, chars) in [(false, normal), (true, shifted)] {
+            if let Some(position) = chars.chars().position(|key| key == c) {
+                return Some(KeyStroke { scancode: first + position as u16, shift, altgr: false });
+            }
+        }
+    }
+    altgr.iter().find(|(_, key)| *key == c).map(|(scancode, _)| KeyStroke {
+        scancode: *scancode,
+        shift: false,
+        altgr: true,
+    })
+}
+
+/// Scancode of layout independent control characters
+fn control_scancode(c: char) -> Option<u16> {
+    match c {
+        '\n' | '\r' => Some(SCANCODE_ENTER),
+        '\t' => Some(SCANCODE_TAB),
+        '\x08' => Some(SCANCODE_BACKSPACE),
+        '\x1b' => Some(SCANCODE_ESCAPE),
+        _ => None,
+    }
+}
+
+/// Modifiers currently pressed while typing
+#[derive(Default)]
+struct Modifiers {
+    shift: bool,
+    altgr: bool,
+}
+
+impl Modifiers {
+    /// Press or release modifiers to match the expected state
+    fn set(&mut self, shift: bool, altgr: bool, inputs: &mut Vec<KeyInput>) {
+        if self.shift != shift {
+            inputs.push(KeyInput::Scancode { code: SCANCODE_SHIFT, extended: false, down: shift });
+            self.shift = shift;
+        }
+        if self.altgr != altgr {
+            inputs.push(KeyInput::Scancode { code: SCANCODE_ALTGR, extended: true, down: altgr });
+            self.altgr = altgr;
+        }
+    }
+}
+
+/// Translate a text into keyboard inputs
+///
+/// Characters are typed with scancodes when the layout allows it,
+/// others are sent as unicode keyboard events.
+/// Modifiers are only pressed when needed and are released at the end.
+/// A carriage return followed by a line feed is a single Enter
+///
+/// # Example
+/// ```
+/// use rdp::core::gcc::KeyboardLayout;
+/// use rdp::core::keymap::{text_to_inputs, KeyInput};
+/// assert_eq!(text_to_inputs(KeyboardLayout::US, "a"), vec![
+///     KeyInput::Scancode { code: 0x1e, extended: false, down: true },
+///     KeyInput::Scancode { code: 0x1e, extended: false, down: false },
+/// ]);
+/// ```
+pub fn text_to_inputs(layout: KeyboardLayout, text: &str) -> Vec<KeyInput> {
+    let mut inputs = Vec::new();
+    let mut modifiers = Modifiers::default();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\r' && chars.peek() == Some(&'\n') {
+            continue;
+        }
+        let key = match control_scancode(c) {
+            Some(scancode) => Some(KeyStroke { scancode, shift: false, altgr: false }),
+            None => to_keystroke(layout, c),
+        };
+        match key {
+            Some(key) => {
+                modifiers.set(key.shift, key.altgr, &mut inputs);
+                for down in [true, false] {
+                    inputs.push(KeyInput::Scancode { code: key.scancode, extended: false, down });
+                }
+            }
+            None => {
+                modifiers.set(false, false, &mut inputs);
+                let mut buffer = [0; 2];
+                for code in c.encode_utf16(&mut buffer) {
+                    for down in [true, false] {
+                        inputs.push(KeyInput::Unicode { code: *code, down });
+                    }
+                }
+            }
+        }
+    }
+    modifiers.set(false, false, &mut inputs);
+    inputs
+}
+
+/// Press keys in order then release them in reverse order
+/// Each key is a (scancode, extended) pair
+pub fn key_combination(keys: &[(u16, bool)]) -> Vec<KeyInput> {
+    let press = keys.iter().map(|&(code, extended)| KeyInput::Scancode { code, extended, down: true });
+    let release = keys.iter().rev().map(|&(code, extended)| KeyInput::Scancode { code, extended, down: false });
+    press.chain(release).collect()
+}
+
+/// Secure attention sequence to unlock a session
+pub fn ctrl_alt_del() -> Vec<KeyInput> {
+    key_combination(&[(SCANCODE_CTRL, false), (SCANCODE_ALT, false), (SCANCODE_DELETE, true)])
+}
+
+/// Left Windows key to open the start menu
+pub fn win_key() -> Vec<KeyInput> { key_combination(&[(SCANCODE_WIN, true)]) }
+
+/// Copy shortcut
+pub fn ctrl_c() -> Vec<KeyInput> { key_combination(&[(SCANCODE_CTRL, false), (SCANCODE_C, false)]) }
+
+/// Close the active window
+pub fn alt_f4() -> Vec<KeyInput> { key_combination(&[(SCANCODE_ALT, false), (SCANCODE_F4, false)]) }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(code: u16) -> [KeyInput; 2] {
+        [
+            KeyInput::Scancode { code, extended: false, down: true },
+            KeyInput::Scancode { code, extended: false, down: false },
+        ]
+    }
+
+    fn shift(down: bool) -> KeyInput { KeyInput::Scancode { code: SCANCODE_SHIFT, extended: false, down } }
+
+    fn altgr(down: bool) -> KeyInput { KeyInput::Scancode { code: SCANCODE_ALTGR, extended: true, down } }
+
+    fn unicode(code: u16) -> [KeyInput; 2] {
+        [KeyInput::Unicode { code, down: true }, KeyInput::Unicode { code, down: false }]
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut expected = vec![shift(true)];
+        expected.extend(key(0x23));
+        expected.extend(key(0x17));
+        expected.push(shift(false));
+        expected.extend(key(0x17));
+        expected.extend(key(0x39));
+        expected.extend(key(0x02));
+        assert_eq!(text_to_inputs(KeyboardLayout::US, "HIi 1"), expected);
+    }
+
+    #[test]
+    fn test_accented_latin() {
+        // Direct keys on the French layout
+        let mut expected = vec![];
+        expected.extend(key(0x03));
+        expected.extend(key(0x0a));
+        assert_eq!(text_to_inputs(KeyboardLayout::French, "éç"), expected);
+
+        // AltGr on the German layout
+        let mut expected = vec![];
+        expected.extend(key(0x28));
+        expected.push(altgr(true));
+        expected.extend(key(0x12));
+        expected.push(altgr(false));
+        assert_eq!(text_to_inputs(KeyboardLayout::German, "ä€"), expected);
+
+        // Unicode events without key on the layout
+        let mut expected = vec![];
+        expected.extend(key(0x2e));
+        expected.extend(unicode(0xe9));
+        expected.extend(unicode(0xd83d));
+        expected.extend(unicode(0xde00));
+        assert_eq!(text_to_inputs(KeyboardLayout::US, "cé😀"), expected);
+    }
+
+    #[test]
+    fn test_control_characters() {
+        let mut expected = vec![shift(true)];
+        expected.extend(key(0x1e));
+        expected.push(shift(false));
+        expected.extend(key(SCANCODE_ENTER));
+        expected.extend(key(SCANCODE_TAB));
+        expected.extend(key(SCANCODE_ENTER));
+        assert_eq!(text_to_inputs(KeyboardLayout::US, "A\n\t\r\n"), expected);
+    }
+
+    #[test]
+    fn test_unknown_layout() {
+        assert_eq!(to_keystroke(KeyboardLayout::Polish, 'a'), None);
+        assert_eq!(text_to_inputs(KeyboardLayout::Polish, "a"), unicode(0x61));
+        assert_eq!(to_keystroke(KeyboardLayout::German, '\0'), None);
+    }
+
+    #[test]
+    fn test_ctrl_alt_del() {
+        let scancode = |code, extended, down| KeyInput::Scancode { code, extended, down };
+        assert_eq!(
+            ctrl_alt_del(),
+            vec![
+                scancode(0x1d, false, true),
+                scancode(0x38, false, true),
+                scancode(0x53, true, true),
+                scancode(0x53, true, false),
+                scancode(0x38, false, false),
+                scancode(0x1d, false, false),
+            ]
+        );
+        assert_eq!(win_key(), vec![scancode(0x5b, true, true), scancode(0x5b, true, false)]);
+        assert_eq!(alt_f4()[1], scancode(0x3e, false, true));
+        assert_eq!(ctrl_c().len(), 4);
+    }
+}
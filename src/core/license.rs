@@ -3,15 +3,45 @@ use std::io::{Cursor, Read};
 
 use num_enum::TryFromPrimitive;
 
-use crate::model::data::{Check, Component, DataType, DynOption, Message, MessageOption, U16, U32};
+use crate::model::data::{to_vec, Check, Component, DataType, DynOption, Message, MessageOption, U16, U32};
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 
 #[derive(Debug)]
 pub enum LicenseMessage {
-    NewLicense,
+    /// Encrypted license info of a new license
+    NewLicense(Vec<u8>),
+    /// Encrypted license info replacing the license presented by the client
+    UpgradeLicense(Vec<u8>),
     ErrorAlert(Component),
 }
 
+/// Persist the license issued by the server between sessions
+///
+/// # Example
+/// ```
+/// use rdp::core::license::LicenseStore;
+/// use rdp::model::error::RdpResult;
+/// #[derive(Default)]
+/// struct MemoryStore(Option<Vec<u8>>);
+/// impl LicenseStore for MemoryStore {
+///     fn store(&mut self, blob: &[u8]) -> RdpResult<()> {
+///         self.0 = Some(blob.to_vec());
+///         Ok(())
+///     }
+///     fn load(&self) -> Option<Vec<u8>> { self.0.clone() }
+/// }
+/// let mut store = MemoryStore::default();
+/// store.store(&[1, 2, 3]).unwrap();
+/// assert_eq!(store.load(), Some(vec![1, 2, 3]));
+/// ```
+pub trait LicenseStore {
+    /// Save the license blob sent by the server
+    fn store(&mut self, blob: &[u8]) -> RdpResult<()>;
+
+    /// License blob saved by a previous session
+    fn load(&self) -> Option<Vec<u8>>;
+}
+
 /// License preamble
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
 #[repr(u8)]
@@ -42,6 +72,7 @@ pub enum MessageType {
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca?redirectedfrom=MSDN
 #[repr(u32)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrorCode {
     ErrInvalidServerCertificate = 0x0000_0001,
     ErrNoLicense = 0x0000_0002,
@@ -87,6 +118,128 @@ fn license_binary_blob() -> Component {
     ]
 }
 
+/// Blob type of the encrypted license info
+/// See MS-RDPELE 2.2.2.6
+const BB_ENCRYPTED_DATA_BLOB: u16 = 0x0009;
+
+/// Blob type of the client license
+/// See MS-RDPBCGR 2.2.1.12.1.3
+const BB_DATA_BLOB: u16 = 0x0001;
+
+/// New license and upgrade license messages
+/// share the same layout
+///
+/// See MS-RDPELE 2.2.2.6 and 2.2.2.7
+fn server_new_license() -> Component {
+    component![
+        "encryptedLicenseInfo" => license_binary_blob(),
+        "MACData" => vec![0_u8; 16]
+    ]
+}
+
+/// Blob type of the encrypted premaster secret
+const BB_RANDOM_BLOB: u16 = 0x0002;
+
+/// RSA key exchange of the licensing protocol
+const KEY_EXCHANGE_ALG_RSA: u32 = 0x0000_0001;
+
+/// Licensing binary blob with its content
+fn binary_blob(blob_type: u16, data: &[u8]) -> Component {
+    component![
+        "wBlobType" => U16::LE(blob_type),
+        "wBlobLen" => U16::LE(data.len() as u16),
+        "blobData" => data.to_vec()
+    ]
+}
+
+/// License info message presenting a stored license
+/// The premaster secret, hardware id and MAC come from
+/// the licensing key exchange
+///
+/// See MS-RDPELE 2.2.2.3
+pub fn license_info_message(
+    client_random: &[u8; 32], encrypted_premaster_secret: &[u8], license: &[u8], encrypted_hwid: &[u8],
+    mac_data: &[u8; 16],
+) -> Component {
+    let message = to_vec(&component![
+        "dwPreferredKeyExchangeAlg" => U32::LE(KEY_EXCHANGE_ALG_RSA),
+        "dwPlatformId" => U32::LE(0),
+        "ClientRandom" => client_random.to_vec(),
+        "EncryptedPreMasterSecret" => binary_blob(BB_RANDOM_BLOB, encrypted_premaster_secret),
+        "LicenseInfo" => binary_blob(BB_DATA_BLOB, license),
+        "EncryptedHWID" => binary_blob(BB_ENCRYPTED_DATA_BLOB, encrypted_hwid),
+        "MACData" => mac_data.to_vec()
+    ]);
+    component![
+        "bMsgtype" => MessageType::LicenseInfo as u8,
+        "flag" => Preamble::Version30 as u8,
+        "wMsgSize" => U16::LE(message.len() as u16 + 4),
+        "message" => message
+    ]
+}
+
+/// Decrypted content of a new or upgrade license
+///
+/// See MS-RDPELE 2.2.2.6.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewLicenseInfo {
+    pub version: u32,
+    pub scope: String,
+    pub company_name: String,
+    pub product_id: String,
+    /// Certificate of the license, presented on next connections
+    pub license_info: Vec<u8>,
+}
+
+/// Read a field prefixed by its length
+fn read_sized(s: &mut dyn Read) -> RdpResult<Vec<u8>> {
+    let mut size = U32::LE(0);
+    size.read(s)?;
+    let mut data = vec![0_u8; size.inner() as usize];
+    s.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl NewLicenseInfo {
+    /// Decode the license info once decrypted
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::license::NewLicenseInfo;
+    /// let data = [
+    ///     1, 0, 3, 0, 3, 0, 0, 0, b'a', b'b', 0, 4, 0, 0, 0, b'M', 0, 0, 0, 4, 0, 0, 0, b'A', 0, 0, 0, 2, 0, 0, 0, 9,
+    ///     8,
+    /// ];
+    /// let info = NewLicenseInfo::read(&data).unwrap();
+    /// assert_eq!(info.scope, "ab");
+    /// assert_eq!(info.company_name, "M");
+    /// assert_eq!(info.license_info, [9, 8]);
+    /// ```
+    pub fn read(data: &[u8]) -> RdpResult<Self> {
+        let mut stream = Cursor::new(data);
+        let mut version = U32::LE(0);
+        version.read(&mut stream)?;
+        let scope = read_sized(&mut stream)?;
+        let company_name = read_sized(&mut stream)?;
+        let product_id = read_sized(&mut stream)?;
+        let license_info = read_sized(&mut stream)?;
+        Ok(NewLicenseInfo {
+            version: version.inner(),
+            scope: String::from_utf8_lossy(scope.split(|c| *c == 0).next().unwrap_or_default()).into_owned(),
+            company_name: read_unicode(&company_name),
+            product_id: read_unicode(&product_id),
+            license_info,
+        })
+    }
+}
+
+/// Null terminated UTF-16 string
+pub(crate) fn read_unicode(data: &[u8]) -> String {
+    let units: Vec<u16> =
+        data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|c| *c != 0).collect();
+    String::from_utf16_lossy(&units)
+}
+
 /// Licensing error message
 /// use to inform state transition
 fn licensing_error_message() -> Component {
@@ -97,11 +250,21 @@ fn licensing_error_message() -> Component {
     ]
 }
 
+/// Read the encrypted license info of a new or upgrade license
+fn read_new_license(payload: &Component) -> RdpResult<Vec<u8>> {
+    let mut message = server_new_license();
+    message.read_from_bytes(cast!(DataType::Slice, payload["message"])?)?;
+    let blob = cast!(DataType::Component, message["encryptedLicenseInfo"])?;
+    Ok(cast!(DataType::Slice, blob["blobData"])?.to_vec())
+}
+
 /// Parse a payload that follow an preamble
-/// Actually we only accept payload with type `NewLicense` or `ErrorAlert`
+/// Actually we only accept payload with type `NewLicense`, `UpgradeLicense` or
+/// `ErrorAlert`
 fn parse_payload(payload: &Component) -> RdpResult<LicenseMessage> {
     match MessageType::try_from(cast!(DataType::U8, payload["bMsgtype"])?)? {
-        MessageType::NewLicense => Ok(LicenseMessage::NewLicense),
+        MessageType::NewLicense => Ok(LicenseMessage::NewLicense(read_new_license(payload)?)),
+        MessageType::UpgradeLicense => Ok(LicenseMessage::UpgradeLicense(read_new_license(payload)?)),
         MessageType::ErrorAlert => {
             let mut message = licensing_error_message();
             let mut stream = Cursor::new(cast!(DataType::Slice, payload["message"])?);
@@ -116,22 +279,30 @@ fn parse_payload(payload: &Component) -> RdpResult<LicenseMessage> {
 ///
 /// Actually we only accept valid client message
 /// without any license negotiation
+/// The accepted error alert code is returned
+/// New and upgraded licenses are saved in the store
 ///
 /// # Example
 /// ```
 /// ```
-pub fn client_connect(s: &mut dyn Read) -> RdpResult<()> {
+pub fn client_connect(s: &mut dyn Read, store: Option<&mut dyn LicenseStore>) -> RdpResult<Option<ErrorCode>> {
     let mut license_message = preamble();
     license_message.read(s)?;
 
     match parse_payload(&license_message)? {
-        LicenseMessage::NewLicense => Ok(()),
+        LicenseMessage::NewLicense(license) | LicenseMessage::UpgradeLicense(license) => {
+            if let Some(store) = store {
+                store.store(&license)?;
+            }
+            Ok(None)
+        }
         LicenseMessage::ErrorAlert(blob) => {
-            if ErrorCode::try_from(cast!(DataType::U32, blob["dwErrorCode"])?)? == ErrorCode::StatusValidClient
+            let error_code = ErrorCode::try_from(cast!(DataType::U32, blob["dwErrorCode"])?)?;
+            if error_code == ErrorCode::StatusValidClient
                 && StateTransition::try_from(cast!(DataType::U32, blob["dwStateTransition"])?)?
                     == StateTransition::NoTransition
             {
-                Ok(())
+                Ok(Some(error_code))
             } else {
                 Err(Error::RdpError(RdpError::new(
                     RdpErrorKind::InvalidRespond,
@@ -141,3 +312,47 @@ pub fn client_connect(s: &mut dyn Read) -> RdpResult<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryStore(Option<Vec<u8>>);
+
+    impl LicenseStore for MemoryStore {
+        fn store(&mut self, blob: &[u8]) -> RdpResult<()> {
+            self.0 = Some(blob.to_vec());
+            Ok(())
+        }
+
+        fn load(&self) -> Option<Vec<u8>> { self.0.clone() }
+    }
+
+    #[test]
+    fn test_upgrade_license_stored() {
+        let message = to_vec(&component![
+            "encryptedLicenseInfo" => binary_blob(BB_ENCRYPTED_DATA_BLOB, &[1, 2, 3]),
+            "MACData" => vec![0_u8; 16]
+        ]);
+        let mut stream = Cursor::new(to_vec(&component![
+            "bMsgtype" => MessageType::UpgradeLicense as u8,
+            "flag" => Preamble::Version30 as u8,
+            "wMsgSize" => U16::LE(message.len() as u16 + 4),
+            "message" => message
+        ]));
+        let mut store = MemoryStore::default();
+        assert_eq!(client_connect(&mut stream, Some(&mut store)).unwrap(), None);
+        assert_eq!(store.load(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_license_info_message() {
+        let message = to_vec(&license_info_message(&[0; 32], &[1; 8], &[2; 4], &[3; 2], &[4; 16]));
+        // preamble, algorithm, platform, random, three blob headers, blobs and MAC
+        assert_eq!(message.len(), 4 + 8 + 32 + 12 + 14 + 16);
+        assert_eq!(message[..4], [MessageType::LicenseInfo as u8, 3, 86, 0]);
+        // license blob follows the premaster secret
+        assert_eq!(message[56..64], [1, 0, 4, 0, 2, 2, 2, 2]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::license::read_unicode;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Type of the information sent in a save session info PDU
+/// See MS-RDPBCGR 2.2.10.1.1
+#[repr(u32)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum InfoType {
+    Logon = 0x0000_0000,
+    LogonLong = 0x0000_0001,
+    LogonPlainNotify = 0x0000_0002,
+    LogonExtendedInfo = 0x0000_0003,
+}
+
+/// Size of the domain and user name fields of TS_LOGON_INFO
+const LOGON_INFO_DOMAIN_SIZE: usize = 52;
+const LOGON_INFO_USER_NAME_SIZE: usize = 512;
+
+/// Padding of TS_LOGON_INFO_VERSION_2 before the names
+const LOGON_INFO_VERSION_2_PAD: usize = 558;
+
+/// Session assigned by the server once the user is logged on
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogonInfo {
+    pub session_id: u32,
+    pub domain: String,
+    pub user: String,
+}
+
+fn logon_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("LOGON: {}", message)))
+}
+
+/// Read a field of the given size
+fn read_field(s: &mut dyn Read, length: usize) -> RdpResult<Vec<u8>> {
+    let mut field = vec![0; length];
+    s.read_exact(&mut field)?;
+    Ok(field)
+}
+
+/// Read the logon information of a save session info PDU
+/// Other information types give None
+///
+/// See MS-RDPBCGR 2.2.10.1.1.1 and 2.2.10.1.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::logon::read_save_session_info;
+/// let mut data = vec![1, 0, 0, 0, 1, 0, 64, 2, 0, 0, 7, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0];
+/// data.extend(vec![0; 558]);
+/// data.extend([b'D', 0, 0, 0, b'U', 0, 0, 0]);
+/// let info = read_save_session_info(&data).unwrap().unwrap();
+/// assert_eq!((info.session_id, info.domain.as_str(), info.user.as_str()), (7, "D", "U"));
+/// ```
+pub fn read_save_session_info(data: &[u8]) -> RdpResult<Option<LogonInfo>> {
+    let mut stream = Cursor::new(data);
+    let info_type = stream.read_u32::<LittleEndian>()?;
+    match InfoType::try_from(info_type) {
+        Ok(InfoType::Logon) => {
+            let domain_length = stream.read_u32::<LittleEndian>()? as usize;
+            let domain = read_field(&mut stream, LOGON_INFO_DOMAIN_SIZE)?;
+            let user_length = stream.read_u32::<LittleEndian>()? as usize;
+            let user = read_field(&mut stream, LOGON_INFO_USER_NAME_SIZE)?;
+            let session_id = stream.read_u32::<LittleEndian>()?;
+            Ok(Some(LogonInfo {
+                session_id,
+                domain: read_unicode(&domain[..domain_length.min(LOGON_INFO_DOMAIN_SIZE)]),
+                user: read_unicode(&user[..user_length.min(LOGON_INFO_USER_NAME_SIZE)]),
+            }))
+        }
+        Ok(InfoType::LogonLong) => {
+            let _version = stream.read_u16::<LittleEndian>()?;
+            let _size = stream.read_u32::<LittleEndian>()?;
+            let session_id = stream.read_u32::<LittleEndian>()?;
+            let domain_length = stream.read_u32::<LittleEndian>()? as usize;
+            let user_length = stream.read_u32::<LittleEndian>()? as usize;
+            read_field(&mut stream, LOGON_INFO_VERSION_2_PAD)?;
+            let remaining = data.len() - stream.position() as usize;
+            if domain_length + user_length > remaining {
+                return Err(logon_error("Names longer than the PDU"));
+            }
+            let domain = read_field(&mut stream, domain_length)?;
+            let user = read_field(&mut stream, user_length)?;
+            Ok(Some(LogonInfo { session_id, domain: read_unicode(&domain), user: read_unicode(&user) }))
+        }
+        Ok(InfoType::LogonPlainNotify) | Ok(InfoType::LogonExtendedInfo) => Ok(None),
+        Err(_) => Err(logon_error(&format!("Unknown information type {}", info_type))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_logon_info_version_1() {
+        let mut data = vec![0, 0, 0, 0, 8, 0, 0, 0];
+        let mut domain = "DOM".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+        domain.resize(LOGON_INFO_DOMAIN_SIZE, 0);
+        data.extend(domain);
+        data.extend([10, 0, 0, 0]);
+        let mut user = "user".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+        user.resize(LOGON_INFO_USER_NAME_SIZE, 0);
+        data.extend(user);
+        data.extend([3, 0, 0, 0]);
+        assert_eq!(
+            read_save_session_info(&data).unwrap(),
+            Some(LogonInfo { session_id: 3, domain: "DOM".to_string(), user: "user".to_string() })
+        );
+    }
+
+    #[test]
+    fn test_read_logon_info_version_2_truncated() {
+        let mut data = vec![1, 0, 0, 0, 1, 0, 64, 2, 0, 0, 7, 0, 0, 0, 4, 0, 0, 0, 32, 0, 0, 0];
+        data.extend(vec![0; LOGON_INFO_VERSION_2_PAD + 4]);
+        assert!(read_save_session_info(&data).is_err());
+    }
+
+    #[test]
+    fn test_read_plain_notify() {
+        let mut data = vec![2, 0, 0, 0];
+        data.extend(vec![0; 576]);
+        assert_eq!(read_save_session_info(&data).unwrap(), None);
+    }
+}
@@ -4,13 +4,22 @@ use std::io::{BufRead, Cursor, Read, Write};
 use rasn::types::OctetString;
 use rasn::AsnType;
 
+use crate::core::channel::StaticChannelDef;
 use crate::core::gcc::{
-    block_header, client_core_data, client_network_data, client_security_data, read_conference_create_response,
-    write_conference_create_request, ClientData, KeyboardLayout, MessageType, ServerData, Version,
+    block_header, channel_def, client_cluster_data, client_core_data, client_message_channel_data, client_monitor_data,
+    client_network_data, client_security_data, read_conference_create_response, write_conference_create_request,
+    ClientData, ClientIdentity, KeyboardLayout, MessageType, MonitorLayout, ServerData, ServerSecurityData, Version,
 };
+#[cfg(feature = "legacy-security")]
+use crate::core::security::{SecurityContext, FASTPATH_INPUT_ENCRYPTED};
 use crate::core::{per, tpkt, x224};
-use crate::model::data::{to_vec, DataType, Message, Trame, U16};
-use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::data::{to_vec, Component, DataType, Message, Trame, U16};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult, RdpWarning, WarningHandler};
+use crate::model::link::{read_when_ready, run_blocking, Blocking, FrameWait};
+
+/// Name of the message channel
+/// It carries the auto-detection and heartbeat PDUs
+pub const MESSAGE_CHANNEL_NAME: &str = "mcsmsg";
 
 #[allow(dead_code)]
 #[repr(u8)]
@@ -26,6 +35,32 @@ enum DomainMCSPDU {
     SendDataIndication = 26,
 }
 
+/// Reason of a Disconnect Provider Ultimatum
+/// See T.125 section 7
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UltimatumReason {
+    /// The domain is torn down
+    DomainDisconnected = 0,
+    /// The provider closed the connection by itself
+    ProviderInitiated = 1,
+    TokenPurged = 2,
+    /// The user asked for the disconnection
+    UserRequested = 3,
+    ChannelPurged = 4,
+}
+
+/// Disconnect Provider Ultimatum
+/// The 3 bits of the reason follow the 6 bits of the PDU type
+fn disconnect_provider_ultimatum(reason: UltimatumReason) -> Trame {
+    let reason = reason as u8;
+    trame![
+        mcs_pdu_header(Some(DomainMCSPDU::DisconnectProviderUltimatum), Some(reason >> 1)),
+        (reason & 1) << 7,
+        b"\x00\x00\x00\x00\x00\x00".to_vec()
+    ]
+}
+
 /// ASN1 structure use by mcs layer
 /// to inform on conference capability
 #[allow(clippy::too_many_arguments)]
@@ -115,6 +150,16 @@ fn connect_initial(user_data: Option<Vec<u8>>) -> ConnectInitial {
     }
 }
 
+/// Block of the conference user data with its header
+/// The length of a block, header included, is encoded on 16 bits
+fn user_data_block(message_type: MessageType, data: Component) -> RdpResult<Trame> {
+    let length = u16::try_from(data.length())
+        .ok()
+        .filter(|length| length.checked_add(4).is_some())
+        .ok_or_else(|| Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "MCS: User data block too large")))?;
+    Ok(trame![block_header(Some(message_type), Some(length)), data])
+}
+
 /// Create a basic MCS PDU header
 fn mcs_pdu_header(pdu: Option<DomainMCSPDU>, options: Option<u8>) -> u8 {
     (pdu.unwrap_or(DomainMCSPDU::AttachUserConfirm) as u8) << 2 | options.unwrap_or(0)
@@ -224,13 +269,91 @@ pub struct Client<S> {
     user_id: Option<u16>,
     /// Map that translate channel name to channel id
     channel_ids: HashMap<String, u16>,
+    /// Static virtual channels requested by the client
+    static_channels: Vec<StaticChannelDef>,
+    /// Monitor topology announced to the server
+    monitor_layout: Option<MonitorLayout>,
+    /// Announce the graphics pipeline support
+    support_gfx: bool,
+    /// Build, product id and serial number announced to the server
+    identity: ClientIdentity,
+    /// Existing session to reconnect to
+    redirected_session_id: Option<u32>,
+    /// Announce the network auto-detection and ask for a message channel
+    support_network_autodetect: bool,
+    /// Non fatal server messages
+    warnings: WarningHandler,
+    /// Disconnect provider ultimatum received
+    disconnected: bool,
+    /// Encryption of the legacy security layer
+    #[cfg(feature = "legacy-security")]
+    security: Option<SecurityContext>,
 }
 
 impl<S: Read + Write> Client<S> {
     pub fn new(x224: x224::Client<S>) -> Self {
-        Client { server_data: None, x224, user_id: None, channel_ids: HashMap::new() }
+        Client {
+            server_data: None,
+            x224,
+            user_id: None,
+            channel_ids: HashMap::new(),
+            static_channels: Vec::new(),
+            monitor_layout: None,
+            support_gfx: false,
+            identity: ClientIdentity::default(),
+            redirected_session_id: None,
+            support_network_autodetect: false,
+            warnings: WarningHandler::default(),
+            disconnected: false,
+            #[cfg(feature = "legacy-security")]
+            security: None,
+        }
     }
 
+    /// Client already connected to the given channels
+    /// Used to test the upper layers without a connection sequence
+    #[cfg(test)]
+    pub(crate) fn connected(x224: x224::Client<S>, user_id: u16, channels: &[(&str, u16)]) -> Self {
+        let mut client = Self::new(x224);
+        client.user_id = Some(user_id);
+        client.channel_ids = channels.iter().map(|(name, id)| (name.to_string(), *id)).collect();
+        client.server_data = Some(ServerData {
+            channel_ids: client.channel_ids.values().copied().collect(),
+            rdp_version: Version::RdpVersion5plus,
+            security: ServerSecurityData::default(),
+            unknown_blocks: Vec::new(),
+            message_channel_id: None,
+        });
+        client
+    }
+
+    /// Announce the graphics pipeline support to the server
+    /// Must be called before connect
+    pub fn set_support_gfx(&mut self, support_gfx: bool) { self.support_gfx = support_gfx; }
+
+    /// Set the build, product id and serial number of the client
+    /// Must be called before connect
+    pub fn set_identity(&mut self, identity: ClientIdentity) { self.identity = identity; }
+
+    /// Ask the server for an existing session
+    /// Must be called before connect
+    pub fn set_redirected_session_id(&mut self, session_id: u32) { self.redirected_session_id = Some(session_id); }
+
+    /// Answer the network auto-detection of the server
+    /// Must be called before connect
+    pub fn set_support_network_autodetect(&mut self, enabled: bool) { self.support_network_autodetect = enabled; }
+
+    /// Set the callback of non fatal server messages
+    pub fn set_warning_handler(&mut self, warnings: WarningHandler) { self.warnings = warnings; }
+
+    /// Report a non fatal server message
+    pub fn warn(&self, warning: RdpWarning) { self.warnings.warn(warning); }
+
+    /// Encrypt and decrypt all the following PDUs
+    /// Set once the security exchange is done
+    #[cfg(feature = "legacy-security")]
+    pub fn set_security_context(&mut self, security: SecurityContext) { self.security = Some(security); }
+
     /// Write connection initial payload
     /// This payload include a lot of
     /// client specific config parameters
@@ -244,21 +367,32 @@ impl<S: Read + Write> Client<S> {
             server_selected_protocol: self.x224.get_selected_protocols() as u32,
             rdp_version: Version::RdpVersion5plus,
             name: client_name,
+            support_monitor_layout: self.monitor_layout.is_some(),
+            support_gfx: self.support_gfx,
+            identity: self.identity,
+            support_network_autodetect: self.support_network_autodetect,
         }));
         let client_security_data = client_security_data();
-        let client_network_data = client_network_data(trame![]);
-        let user_data = to_vec(&trame![
-            trame![block_header(Some(MessageType::CsCore), Some(client_core_data.length() as u16)), client_core_data],
-            trame![
-                block_header(Some(MessageType::CsSecurity), Some(client_security_data.length() as u16)),
-                client_security_data
-            ],
-            trame![
-                block_header(Some(MessageType::CsNet), Some(client_network_data.length() as u16)),
-                client_network_data
-            ]
-        ]);
-        let conference = write_conference_create_request(&user_data)?;
+        let mut channel_defs = trame![];
+        for channel in &self.static_channels {
+            channel_defs.push(Box::new(channel_def(&channel.name, channel.options.bits())));
+        }
+        let client_network_data = client_network_data(channel_defs);
+        let mut user_data = trame![
+            user_data_block(MessageType::CsCore, client_core_data)?,
+            user_data_block(MessageType::CsSecurity, client_security_data)?,
+            user_data_block(MessageType::CsNet, client_network_data)?
+        ];
+        if let Some(monitor_layout) = &self.monitor_layout {
+            user_data.push(Box::new(user_data_block(MessageType::CsMonitor, client_monitor_data(monitor_layout))?));
+        }
+        if let Some(session_id) = self.redirected_session_id {
+            user_data.push(Box::new(user_data_block(MessageType::CsCluster, client_cluster_data(session_id))?));
+        }
+        if self.support_network_autodetect {
+            user_data.push(Box::new(user_data_block(MessageType::CsMcsMsgChannel, client_message_channel_data())?));
+        }
+        let conference = write_conference_create_request(&to_vec(&user_data))?;
         let connect_initial = connect_initial(Some(conference));
         self.x224.write(rasn::der::encode(&connect_initial)?)?;
         Ok(())
@@ -272,7 +406,11 @@ impl<S: Read + Write> Client<S> {
         // Read conference create response
         let connect_response: ConnectResponse = rasn::ber::decode(payload.fill_buf()?)?;
         let cc_response = connect_response.user_data;
-        self.server_data = Some(read_conference_create_response(&mut Cursor::new(cc_response))?);
+        let server_data = read_conference_create_response(&mut Cursor::new(cc_response))?;
+        for block_type in &server_data.unknown_blocks {
+            self.warn(RdpWarning::UnknownBlock(*block_type));
+        }
+        self.server_data = Some(server_data);
         Ok(())
     }
 
@@ -280,38 +418,81 @@ impl<S: Read + Write> Client<S> {
     /// Ask connection for each channel requested
     /// and confirmed by server
     ///
+    /// Static virtual channel names are limited to 7 characters
+    /// The optional monitor layout is sent as client monitor data
+    ///
     /// # Example
     /// ```rust, ignore
     /// let mut mcs = mcs::Client(x224);
-    /// mcs.connect(800, 600, KeyboardLayout::French).unwrap()
+    /// mcs.connect("mstsc-rs".to_string(), 800, 600, KeyboardLayout::French, &[StaticChannelDef::new("cliprdr")], None).unwrap()
     /// ```
     pub fn connect(
         &mut self, client_name: String, screen_width: u16, screen_height: u16, keyboard_layout: KeyboardLayout,
+        static_channels: &[StaticChannelDef], monitor_layout: Option<&MonitorLayout>,
     ) -> RdpResult<()> {
+        run_blocking(self.connect_with(
+            client_name,
+            screen_width,
+            screen_height,
+            keyboard_layout,
+            static_channels,
+            monitor_layout,
+            &mut Blocking,
+        ))
+    }
+
+    /// Same as `connect`, each answer of the server is read once `wait`
+    /// resolves
+    #[allow(clippy::too_many_arguments)]
+    pub async fn connect_with(
+        &mut self, client_name: String, screen_width: u16, screen_height: u16, keyboard_layout: KeyboardLayout,
+        static_channels: &[StaticChannelDef], monitor_layout: Option<&MonitorLayout>, wait: &mut impl FrameWait,
+    ) -> RdpResult<()> {
+        self.static_channels = static_channels.to_vec();
+        self.monitor_layout = monitor_layout.cloned();
         self.write_connect_initial(screen_width, screen_height, keyboard_layout, client_name)?;
-        self.read_connect_response()?;
+        read_when_ready(wait, || self.read_connect_response()).await?;
         self.x224.write(erect_domain_request()?)?;
         self.x224.write(attach_user_request())?;
 
-        self.user_id = Some(read_attach_user_confirm(&mut try_let!(tpkt::Payload::Raw, self.x224.read()?)?)?);
+        let user_id =
+            read_when_ready(wait, || read_attach_user_confirm(&mut try_let!(tpkt::Payload::Raw, self.x224.read()?)?))
+                .await?;
+        self.user_id = Some(user_id);
+
+        // The user channel then the I/O channel are joined first
+        // See MS-RDPBCGR 1.3.1.1
+        let mut channels = vec![("user".to_string(), self.user_id.unwrap()), ("global".to_string(), 1003)];
 
-        // Add static channel
-        self.channel_ids.insert("global".to_string(), 1003);
-        self.channel_ids.insert("user".to_string(), self.user_id.unwrap());
+        // Server give back channel ids in the same order than requested
+        if let Some(server_data) = &self.server_data {
+            for (channel, channel_id) in self.static_channels.iter().zip(server_data.channel_ids.iter()) {
+                channels.push((channel.name.clone(), *channel_id));
+            }
+            if let Some(channel_id) = server_data.message_channel_id {
+                channels.push((MESSAGE_CHANNEL_NAME.to_string(), channel_id));
+            }
+        }
 
-        // Create list of requested channels
-        // Actually only the two static main channel are requested
-        for channel_id in self.channel_ids.values() {
+        // Join the channels in a stable order
+        let mut rejected_channels = Vec::new();
+        for (channel_name, channel_id) in &channels {
+            self.channel_ids.insert(channel_name.clone(), *channel_id);
             self.x224.write(channel_join_request(self.user_id, Some(*channel_id))?)?;
-            if !read_channel_join_confirm(
-                self.user_id.unwrap(),
-                *channel_id,
-                &mut try_let!(tpkt::Payload::Raw, self.x224.read()?)?,
-            )? {
-                println!("Server reject channel id {:?}", channel_id);
+            let joined = read_when_ready(wait, || {
+                read_channel_join_confirm(user_id, *channel_id, &mut try_let!(tpkt::Payload::Raw, self.x224.read()?)?)
+            })
+            .await?;
+            if !joined {
+                self.warnings.warn(RdpWarning::ChannelRejected(channel_name.clone()));
+                rejected_channels.push(channel_name.clone());
             }
         }
 
+        for channel_name in rejected_channels {
+            self.channel_ids.remove(&channel_name);
+        }
+
         Ok(())
     }
 
@@ -322,20 +503,61 @@ impl<S: Read + Write> Client<S> {
     /// # Example
     /// ```rust, ignore
     /// let mut mcs = mcs::Client(x224);
-    /// mcs.connect(800, 600, KeyboardLayout::French).unwrap();
+    /// mcs.connect("mstsc-rs".to_string(), 800, 600, KeyboardLayout::French, &[], None).unwrap();
     /// mcs.write("global".to_string(), trame![U16::LE(0)])
     /// ```
     pub fn write<T: 'static>(&mut self, channel_name: &String, message: T) -> RdpResult<()>
     where
         T: Message,
     {
+        #[cfg(feature = "legacy-security")]
+        if let Some(security) = &mut self.security {
+            let message = security.encrypt_pdu(0, &to_vec(&message))?;
+            return self.send_data_request(channel_name, message);
+        }
+        self.send_data_request(channel_name, message)
+    }
+
+    /// Write a message behind a basic security header
+    /// The header is encrypted with the legacy security layer
+    ///
+    /// See MS-RDPBCGR 2.2.8.1.1.2.1
+    pub fn write_with_security_header<T: Message + 'static>(
+        &mut self, channel_name: &String, flags: u16, message: T,
+    ) -> RdpResult<()> {
+        #[cfg(feature = "legacy-security")]
+        if let Some(security) = &mut self.security {
+            let message = security.encrypt_pdu(flags, &to_vec(&message))?;
+            return self.send_data_request(channel_name, message);
+        }
+        self.send_data_request(channel_name, trame![U16::LE(flags), U16::LE(0), message])
+    }
+
+    /// Send fast path input PDUs with a single write
+    /// each PDU is given with its number of events
+    /// They're encrypted with the legacy security layer
+    ///
+    /// See MS-RDPBCGR 2.2.8.1.2
+    pub fn write_fast_path_input(&mut self, pdus: Vec<(u8, Vec<u8>)>) -> RdpResult<()> {
+        #[cfg(feature = "legacy-security")]
+        if let Some(security) = &mut self.security {
+            let mut encrypted = Vec::with_capacity(pdus.len());
+            for (num_events, events) in pdus {
+                encrypted.push((FASTPATH_INPUT_ENCRYPTED << 6 | num_events << 2, security.encrypt_fast_path(&events)?));
+            }
+            return self.x224.write_fast_path(encrypted);
+        }
+        self.x224.write_fast_path(pdus.into_iter().map(|(num_events, events)| (num_events << 2, events)).collect())
+    }
+
+    /// Send data request PDU
+    fn send_data_request<T: Message + 'static>(&mut self, channel_name: &String, message: T) -> RdpResult<()> {
         self.x224.write(trame![
             mcs_pdu_header(Some(DomainMCSPDU::SendDataRequest), None),
             U16::BE(self.user_id.unwrap() - 1001),
             U16::BE(self.channel_ids[channel_name]),
             0x70_u8,
-            per::write_length(message.length() as u16)?,
-            message
+            per::write_fragmented(&to_vec(&message))
         ])
     }
 
@@ -346,7 +568,7 @@ impl<S: Read + Write> Client<S> {
     /// # Example
     /// ```rust, ignore
     /// let mut mcs = mcs::Client(x224);
-    /// mcs.connect(800, 600, KeyboardLayout::French).unwrap();
+    /// mcs.connect("mstsc-rs".to_string(), 800, 600, KeyboardLayout::French, &[], None).unwrap();
     /// let (channel_name, payload) = mcs.read().unwrap();
     /// match channel_name.as_str() {
     ///     "global" => println!("main channel");
@@ -360,6 +582,7 @@ impl<S: Read + Write> Client<S> {
                 let mut header = mcs_pdu_header(None, None);
                 header.read(&mut payload)?;
                 if header >> 2 == DomainMCSPDU::DisconnectProviderUltimatum as u8 {
+                    self.disconnected = true;
                     return Err(Error::RdpError(RdpError::new(
                         RdpErrorKind::Disconnect,
                         "MCS: Disconnect Provider Ultimatum",
@@ -381,24 +604,38 @@ impl<S: Read + Write> Client<S> {
                     .ok_or(Error::RdpError(RdpError::new(RdpErrorKind::Unknown, "MCS: unknown channel")))?;
 
                 per::read_enumerates(&mut payload)?;
-                per::read_length(&mut payload)?;
+                // User data of 16K or more is split in fragments
+                let remaining = payload.get_ref().len().saturating_sub(payload.position() as usize);
+                payload = Cursor::new(per::read_fragmented(remaining, &mut payload)?);
+
+                #[cfg(feature = "legacy-security")]
+                if let Some(security) = &mut self.security {
+                    payload = Cursor::new(security.decrypt_pdu(&payload.get_ref()[payload.position() as usize..])?);
+                }
 
                 Ok((channel.0.clone(), tpkt::Payload::Raw(payload)))
             }
             tpkt::Payload::FastPath(sec_flag, payload) => {
+                #[cfg(feature = "legacy-security")]
+                let payload = match &mut self.security {
+                    Some(security) => Cursor::new(security.decrypt_fast_path(sec_flag, payload.get_ref())?),
+                    None => payload,
+                };
                 // fastpath packet are dedicated to global channel
                 Ok(("global".to_string(), tpkt::Payload::FastPath(sec_flag, payload)))
             }
         }
     }
 
+    /// True once the server sent a disconnect provider ultimatum
+    pub fn is_disconnected(&self) -> bool { self.disconnected }
+
     /// Send a close event to server
-    pub fn shutdown(&mut self) -> RdpResult<()> {
-        self.x224.write(trame![
-            mcs_pdu_header(Some(DomainMCSPDU::DisconnectProviderUltimatum), Some(1)),
-            per::write_enumerates(0x80)?,
-            b"\x00\x00\x00\x00\x00\x00".to_vec()
-        ])?;
+    pub fn shutdown(&mut self) -> RdpResult<()> { self.shutdown_with_reason(UltimatumReason::UserRequested) }
+
+    /// Send a close event to server with the reason of the disconnection
+    pub fn shutdown_with_reason(&mut self, reason: UltimatumReason) -> RdpResult<()> {
+        self.x224.write(disconnect_provider_ultimatum(reason))?;
         self.x224.shutdown()
     }
 
@@ -411,13 +648,29 @@ impl<S: Read + Write> Client<S> {
     /// Getter of the user id negotiated during connection steps
     pub fn get_user_id(&self) -> u16 { self.user_id.unwrap() }
 
+    /// Getter of the security settings selected by the server
+    pub fn get_server_security(&self) -> &ServerSecurityData { &self.server_data.as_ref().unwrap().security }
+
+    /// Bytes sent and received by the link layer
+    pub fn get_traffic(&self) -> (u64, u64) { self.x224.get_traffic() }
+
     /// Getter of the global channel id
     pub fn get_global_channel_id(&self) -> u16 { self.channel_ids["global"] }
+
+    /// Check if a channel has been joined during connection
+    pub fn has_channel(&self, channel_name: &str) -> bool { self.channel_ids.contains_key(channel_name) }
+
+    /// Id given by the server to a joined channel
+    pub fn get_channel_id(&self, channel_name: &str) -> Option<u16> { self.channel_ids.get(channel_name).copied() }
 }
 
 #[cfg(test)]
 mod test {
+    use std::net::TcpStream;
+
     use super::*;
+    use crate::model::link::{Link, Stream};
+    use crate::test_utils::mock_server::{fixture, MockRdpServer};
 
     /// Server response with channel capacity
     fn connect_response(user_data: Option<Vec<u8>>) -> ConnectResponse {
@@ -441,6 +694,23 @@ mod test {
         assert_eq!(attach_user_request(), 40);
     }
 
+    /// The user requested reason gives the usual ultimatum
+    #[test]
+    fn test_disconnect_provider_ultimatum() {
+        assert_eq!(to_vec(&disconnect_provider_ultimatum(UltimatumReason::UserRequested))[..2], [0x21, 0x80]);
+        assert_eq!(to_vec(&disconnect_provider_ultimatum(UltimatumReason::ProviderInitiated))[..2], [0x20, 0x80]);
+        assert_eq!(to_vec(&disconnect_provider_ultimatum(UltimatumReason::ChannelPurged))[..2], [0x22, 0x00]);
+    }
+
+    /// The header of a user data block is part of its 16 bits length
+    #[test]
+    fn test_user_data_block_length() {
+        let block = user_data_block(MessageType::CsNet, component!["data" => vec![1_u8, 2]]).unwrap();
+        assert_eq!(to_vec(&block), [0x03, 0xc0, 6, 0, 1, 2]);
+        assert!(user_data_block(MessageType::CsNet, component!["data" => vec![0_u8; 0xfffc]]).is_err());
+        assert!(user_data_block(MessageType::CsNet, component!["data" => vec![0_u8; 0xfffb]]).is_ok());
+    }
+
     /// Test of the new domain request
     #[test]
     fn test_erect_domain_request() {
@@ -487,4 +757,33 @@ mod test {
             ]
         );
     }
+
+    /// User data of 16K and more is split in fragments
+    #[test]
+    fn test_read_long_send_data_indication() {
+        let data: Vec<u8> = (0..0x4001).map(|i| i as u8).collect();
+        let mut pdu = vec![2, 0xf0, 0x80, 0x68, 0, 1, 0x03, 0xeb, 0x70, 0xc1];
+        pdu.extend(&data[..0x4000]);
+        pdu.extend([0x01, data[0x4000]]);
+        let mut tpkt = vec![3, 0];
+        tpkt.extend((pdu.len() as u16 + 4).to_be_bytes());
+        tpkt.extend(pdu);
+        let server = MockRdpServer::new()
+            .expect_receive(|pdu| pdu[5] == 0xe0)
+            .send(&fixture("x224_confirm_rdp.bin"))
+            .send(&tpkt);
+        let address = server.address();
+        let server = server.run();
+
+        let link = Link::new(Stream::Raw(TcpStream::connect(address).unwrap()));
+        let x224 = x224::Client::connect(tpkt::Client::new(link), 0, false, None, false, false).unwrap();
+        let mut mcs = Client::connected(x224, 1007, &[("global", 1003)]);
+        let (channel, payload) = mcs.read().unwrap();
+        server.join().unwrap().unwrap();
+        assert_eq!(channel, "global");
+        match payload {
+            tpkt::Payload::Raw(payload) => assert_eq!(payload.get_ref()[payload.position() as usize..], data[..]),
+            _ => panic!("expected a slow path payload"),
+        }
+    }
 }
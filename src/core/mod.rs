@@ -1,11 +1,52 @@
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod autodetect;
+pub mod bitmap_cache;
 pub mod capability;
+pub mod channel;
 pub mod client;
+pub mod cliprdr;
+pub mod disp;
+pub mod dvc;
+pub mod error_info;
 pub mod event;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gateway;
 pub mod gcc;
+pub mod gfx;
 pub mod global;
+pub mod http_proxy;
+pub mod input;
+pub mod keymap;
 pub mod license;
+pub mod logon;
 pub mod mcs;
+#[cfg(any(unix, windows))]
+pub mod named_pipe;
+pub mod orders;
+#[cfg(feature = "pcap")]
+pub mod pcap;
 pub mod per;
+pub mod pointer;
+pub mod preconnection;
+pub mod rail;
+pub mod rdpdr;
+pub mod rdpsnd;
+pub mod recorder;
+pub mod screenshot;
 pub mod sec;
+#[cfg(feature = "legacy-security")]
+pub mod security;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod socks;
+pub mod surface;
+pub mod toggle_keys;
 pub mod tpkt;
+pub mod url;
+#[cfg(feature = "wasm")]
+pub mod websocket;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod write_timeout;
+#[cfg(feature = "experimental-wts")]
+pub mod wts;
 pub mod x224;
@@ -0,0 +1,162 @@
+#[cfg(windows)]
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use socket2::{Domain, SockAddr, Socket, Type};
+
+/// Prefix of the virtual socket addresses
+#[cfg(target_os = "linux")]
+const VSOCK_PREFIX: &str = "vsock://";
+
+/// Context id and port of a `vsock://cid:port` address
+#[cfg(target_os = "linux")]
+fn parse_vsock(path: &Path) -> Option<(u32, u32)> {
+    let (cid, port) = path.to_str()?.strip_prefix(VSOCK_PREFIX)?.split_once(':')?;
+    Some((cid.parse().ok()?, port.parse().ok()?))
+}
+
+#[derive(Debug)]
+enum Pipe {
+    #[cfg(windows)]
+    Windows(File),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(target_os = "linux")]
+    Vsock(Socket),
+}
+
+/// Local stream to the RDP stack of a virtual machine
+/// running on the same host, no TCP connection is needed
+///
+/// The path is a named pipe on Windows (`\\.\pipe\name`)
+/// and a Unix socket on Linux, a `vsock://cid:port` address
+/// opens an AF_VSOCK socket to the guest instead
+///
+/// # Example
+/// ```no_run
+/// use rdp::core::client::Connector;
+/// use rdp::core::named_pipe::PipeStream;
+/// let pipe = PipeStream::connect("/tmp/vmrdp.sock").unwrap();
+/// let mut client = Connector::new().use_nla(false).connect(pipe).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PipeStream(Pipe);
+
+impl PipeStream {
+    /// Open the pipe or socket at `path`
+    #[cfg(windows)]
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(PipeStream(Pipe::Windows(file)))
+    }
+
+    /// Open the pipe or socket at `path`
+    #[cfg(unix)]
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        if let Some((cid, port)) = parse_vsock(path.as_ref()) {
+            let socket = Socket::new(Domain::VSOCK, Type::STREAM, None)?;
+            socket.connect(&SockAddr::vsock(cid, port))?;
+            return Ok(PipeStream(Pipe::Vsock(socket)));
+        }
+        Ok(PipeStream(Pipe::Unix(UnixStream::connect(path)?)))
+    }
+
+    /// Set the timeouts of the socket
+    /// Windows pipes have no timeout, it is then ignored
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match &self.0 {
+            #[cfg(windows)]
+            Pipe::Windows(_) => {
+                let _ = (read, write);
+                Ok(())
+            }
+            #[cfg(unix)]
+            Pipe::Unix(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+            #[cfg(target_os = "linux")]
+            Pipe::Vsock(socket) => {
+                socket.set_read_timeout(read)?;
+                socket.set_write_timeout(write)
+            }
+        }
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            #[cfg(windows)]
+            Pipe::Windows(file) => file.read(buf),
+            #[cfg(unix)]
+            Pipe::Unix(stream) => stream.read(buf),
+            #[cfg(target_os = "linux")]
+            Pipe::Vsock(socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            #[cfg(windows)]
+            Pipe::Windows(file) => file.write(buf),
+            #[cfg(unix)]
+            Pipe::Unix(stream) => stream.write(buf),
+            #[cfg(target_os = "linux")]
+            Pipe::Vsock(socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            #[cfg(windows)]
+            Pipe::Windows(file) => file.flush(),
+            #[cfg(unix)]
+            Pipe::Unix(stream) => stream.flush(),
+            #[cfg(target_os = "linux")]
+            Pipe::Vsock(socket) => socket.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_vsock() {
+        assert_eq!(parse_vsock(Path::new("vsock://3:3389")), Some((3, 3389)));
+        assert_eq!(parse_vsock(Path::new("vsock://3")), None);
+        assert_eq!(parse_vsock(Path::new("vsock://host:3389")), None);
+        assert_eq!(parse_vsock(Path::new("/tmp/vmrdp.sock")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_pipe() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("rdp-rs-pipe-{}.sock", std::process::id()));
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut pipe = PipeStream::connect(&path).unwrap();
+        pipe.set_timeouts(Some(Duration::from_secs(5)), None).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        pipe.write_all(&[3, 0, 0, 4]).unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).unwrap();
+        server.write_all(&buffer[..2]).unwrap();
+        pipe.read_exact(&mut buffer[2..]).unwrap();
+        assert_eq!(buffer, [3, 0, 3, 0]);
+    }
+}
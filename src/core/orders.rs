@@ -0,0 +1,1569 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::codec::rle::Palette;
+use crate::core::bitmap_cache::PersistentBitmapCache;
+use crate::core::capability::{BITMAP_CACHE_ENTRIES, NINE_GRID_CACHE_ENTRIES, NINE_GRID_CACHE_SIZE};
+use crate::core::event::{BitmapEvent, Brush, DrawOrderEvent, RdpEvent, Rect};
+use crate::core::license::read_unicode;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Control flags of a drawing order
+/// See MS-RDPEGDI 2.2.2.2.1.1.2
+#[repr(u8)]
+#[derive(Debug, Copy, Clone)]
+enum ControlFlag {
+    Standard = 0x01,
+    Secondary = 0x02,
+    Bounds = 0x04,
+    TypeChange = 0x08,
+    DeltaCoordinates = 0x10,
+    ZeroBoundsDeltas = 0x20,
+    ZeroFieldByteBit0 = 0x40,
+    ZeroFieldByteBit1 = 0x80,
+}
+
+/// Primary drawing orders announced by the client
+/// See MS-RDPEGDI 2.2.2.2.1.1.2
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum PrimaryOrderType {
+    PatBlt = 0x01,
+    DrawNineGrid = 0x07,
+    MultiDrawNineGrid = 0x08,
+    OpaqueRect = 0x0a,
+    MemBlt = 0x0d,
+}
+
+impl PrimaryOrderType {
+    /// Number of bytes of the field flags
+    fn field_bytes(self) -> usize {
+        match self {
+            PrimaryOrderType::OpaqueRect | PrimaryOrderType::DrawNineGrid | PrimaryOrderType::MultiDrawNineGrid => 1,
+            PrimaryOrderType::PatBlt | PrimaryOrderType::MemBlt => 2,
+        }
+    }
+}
+
+/// Secondary drawing orders
+/// See MS-RDPEGDI 2.2.2.2.1.2.1.1
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum SecondaryOrderType {
+    CacheBitmapUncompressed = 0x00,
+    CacheColorTable = 0x01,
+    CacheBitmapCompressed = 0x02,
+    CacheGlyph = 0x03,
+    CacheBitmapUncompressedRev2 = 0x04,
+    CacheBitmapCompressedRev2 = 0x05,
+    CacheBrush = 0x07,
+    CacheBitmapCompressedRev3 = 0x08,
+}
+
+/// Alternate secondary orders, their type is in the control flags
+/// See MS-RDPEGDI 2.2.2.2.1.3.1.1
+#[repr(u8)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum AltSecOrderType {
+    SwitchSurface = 0x00,
+    CreateNineGridBitmap = 0x04,
+    Window = 0x0b,
+}
+
+/// Fields present in a window order
+/// See MS-RDPERP 2.2.1.3.1.1
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+enum WindowOrderFlag {
+    Owner = 0x0000_0002,
+    Title = 0x0000_0004,
+    Style = 0x0000_0008,
+    Show = 0x0000_0010,
+    ResizeMarginX = 0x0000_0080,
+    WndSize = 0x0000_0400,
+    WndOffset = 0x0000_0800,
+    ClientAreaOffset = 0x0000_4000,
+    WndClientDelta = 0x0000_8000,
+    ClientAreaSize = 0x0001_0000,
+    RpContent = 0x0002_0000,
+    RootParent = 0x0004_0000,
+    TypeWindow = 0x0100_0000,
+    ResizeMarginY = 0x0800_0000,
+    StateDeleted = 0x2000_0000,
+    Icon = 0x4000_0000,
+    CachedIcon = 0x8000_0000,
+}
+
+/// Show state of a hidden window (SW_HIDE)
+const WINDOW_SHOW_HIDE: u8 = 0;
+
+/// Flags of the nine grid bitmap info
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+pub enum NineGridFlag {
+    /// The interior is stretched
+    Stretch = 0x01,
+    /// The interior is tiled
+    Tile = 0x02,
+    /// Alpha of the bitmap is kept
+    PerPixelAlpha = 0x04,
+    /// Pixels of the transparent color are not drawn
+    Transparent = 0x08,
+    /// The result is mirrored horizontally
+    MustFlip = 0x10,
+    /// The bitmap is drawn without scaling
+    TrueSize = 0x20,
+}
+
+/// Flags of the cache bitmap revision 2 order
+#[repr(u16)]
+#[derive(Debug, Copy, Clone)]
+enum CacheBitmapRev2Flag {
+    HeightSameAsWidth = 0x01,
+    PersistentKeyPresent = 0x02,
+    NoBitmapCompressionHdr = 0x08,
+    DoNotCache = 0x10,
+}
+
+/// Index of a bitmap waiting to be cached
+const BITMAPCACHE_WAITING_LIST_INDEX: u16 = 0x7fff;
+
+/// Raster operation copying the source
+const ROP_SRCCOPY: u8 = 0xcc;
+
+/// Surface id of the screen in switch surface orders
+const SCREEN_BITMAP_SURFACE: u16 = 0xffff;
+
+/// Nine grid bitmaps are always 32 bpp
+const NINE_GRID_BITMAP_BPP: u8 = 32;
+
+/// Largest number of clipping rectangles of a multi order
+const MAX_DELTA_ENTRIES: u8 = 45;
+
+/// Largest area drawn by a nine grid order
+const MAX_NINE_GRID_PIXELS: usize = 4096 * 4096;
+
+fn orders_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("ORDERS: {}", message)))
+}
+
+/// Read a TWO_BYTE_UNSIGNED_ENCODING value
+fn read_two_byte_unsigned(stream: &mut dyn Read) -> RdpResult<u16> {
+    let first = stream.read_u8()?;
+    if first & 0x80 == 0 {
+        Ok(u16::from(first))
+    } else {
+        Ok(u16::from(first & 0x7f) << 8 | u16::from(stream.read_u8()?))
+    }
+}
+
+/// Read a coordinate field
+/// Delta coordinates are relative to the previous value
+fn read_coord(stream: &mut dyn Read, delta: bool, previous: i16) -> RdpResult<i16> {
+    Ok(if delta { previous.wrapping_add(i16::from(stream.read_i8()?)) } else { stream.read_i16::<LittleEndian>()? })
+}
+
+/// Read a TS_COLOR field
+fn read_color(stream: &mut dyn Read) -> RdpResult<u32> {
+    let mut color = [0; 4];
+    stream.read_exact(&mut color[..3])?;
+    Ok(u32::from_le_bytes(color))
+}
+
+/// Read a FOUR_BYTE_UNSIGNED_ENCODING value
+fn read_four_byte_unsigned(stream: &mut dyn Read) -> RdpResult<u32> {
+    let first = stream.read_u8()?;
+    let mut value = u32::from(first & 0x3f);
+    for _ in 0..first >> 6 {
+        value = value << 8 | u32::from(stream.read_u8()?);
+    }
+    Ok(value)
+}
+
+/// Read a delta encoded value of a clipping rectangle
+/// 6 or 14 bits signed values
+fn read_delta(stream: &mut dyn Read) -> RdpResult<i16> {
+    let first = stream.read_u8()?;
+    let mut value = if first & 0x40 != 0 { i16::from(first) | !0x3f } else { i16::from(first & 0x3f) };
+    if first & 0x80 != 0 {
+        value = value << 8 | i16::from(stream.read_u8()?);
+    }
+    Ok(value)
+}
+
+/// Read the clipping rectangles of a multi order
+/// Left and top are relative to the previous rectangle,
+/// absent fields keep the previous value
+fn read_delta_rects(stream: &mut dyn Read, number: u8) -> RdpResult<Vec<DeltaRect>> {
+    if number > MAX_DELTA_ENTRIES {
+        return Err(orders_error(&format!("Too many clipping rectangles {}", number)));
+    }
+    let mut zero_bits = vec![0; usize::from(number).div_ceil(2)];
+    stream.read_exact(&mut zero_bits)?;
+    let mut rects: Vec<DeltaRect> = Vec::with_capacity(usize::from(number));
+    for i in 0..usize::from(number) {
+        let flags = zero_bits[i / 2] << (4 * (i % 2));
+        let previous = rects.last().copied().unwrap_or_default();
+        let mut rect = previous;
+        rect.left = previous.left.wrapping_add(if flags & 0x80 == 0 { read_delta(stream)? } else { 0 });
+        rect.top = previous.top.wrapping_add(if flags & 0x40 == 0 { read_delta(stream)? } else { 0 });
+        if flags & 0x20 == 0 {
+            rect.width = read_delta(stream)?;
+        }
+        if flags & 0x10 == 0 {
+            rect.height = read_delta(stream)?;
+        }
+        rects.push(rect);
+    }
+    Ok(rects)
+}
+
+/// Describe how a nine grid bitmap is stretched
+/// Borders keep their size, the interior is stretched or tiled
+///
+/// See MS-RDPEGDI 2.2.2.2.1.3.4
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct NineGridBitmapInfo {
+    /// Combination of `NineGridFlag`
+    pub flags: u32,
+    pub left_width: u16,
+    pub right_width: u16,
+    pub top_height: u16,
+    pub bottom_height: u16,
+    /// Color as 0x00BBGGRR
+    pub transparent: u32,
+}
+
+impl NineGridBitmapInfo {
+    /// Read a NINEGRID_BITMAP_INFO structure
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use rdp::core::orders::NineGridBitmapInfo;
+    /// let info = NineGridBitmapInfo::read(&mut Cursor::new([1, 0, 0, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 0, 0])).unwrap();
+    /// assert_eq!((info.flags, info.left_width, info.bottom_height, info.transparent), (1, 2, 5, 6));
+    /// ```
+    pub fn read(stream: &mut dyn Read) -> RdpResult<Self> {
+        Ok(NineGridBitmapInfo {
+            flags: stream.read_u32::<LittleEndian>()?,
+            left_width: stream.read_u16::<LittleEndian>()?,
+            right_width: stream.read_u16::<LittleEndian>()?,
+            top_height: stream.read_u16::<LittleEndian>()?,
+            bottom_height: stream.read_u16::<LittleEndian>()?,
+            transparent: stream.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    fn has(&self, flag: NineGridFlag) -> bool { self.flags & flag as u32 != 0 }
+}
+
+/// Source column or row of a destination one
+/// Borders are shrunk when the destination is too small for them
+fn nine_grid_source(dest: usize, dest_size: usize, src_size: usize, first: usize, last: usize, tile: bool) -> usize {
+    let first = first.min(src_size);
+    let last = last.min(src_size - first);
+    if dest_size < first + last {
+        let first = first * dest_size / (first + last);
+        return if dest < first { dest } else { src_size - (dest_size - dest) };
+    }
+    if dest < first {
+        dest
+    } else if dest >= dest_size - last {
+        src_size - (dest_size - dest)
+    } else if src_size == first + last {
+        first.min(src_size - 1)
+    } else if tile {
+        first + (dest - first) % (src_size - first - last)
+    } else {
+        first + (dest - first) * (src_size - first - last) / (dest_size - first - last)
+    }
+}
+
+/// Draw the source area of a nine grid bitmap in a destination
+/// Only the area of the destination is returned as 32 bpp BGRA
+/// Transparent pixels get a zero alpha
+fn draw_nine_grid(bitmap: &NineGridBitmap, src: Bounds, dest_width: usize, dest_height: usize, area: Rect) -> Vec<u8> {
+    let info = &bitmap.info;
+    let image = &bitmap.bitmap;
+    let src_left = usize::try_from(src.left).unwrap_or(0).min(usize::from(image.width));
+    let src_top = usize::try_from(src.top).unwrap_or(0).min(usize::from(image.height));
+    let src_width = usize::try_from(src.right).unwrap_or(0).min(usize::from(image.width)).saturating_sub(src_left);
+    let src_height = usize::try_from(src.bottom).unwrap_or(0).min(usize::from(image.height)).saturating_sub(src_top);
+    let tile = info.has(NineGridFlag::Tile);
+    let [red, green, blue, _] = info.transparent.to_le_bytes();
+
+    let mut data = Vec::with_capacity(usize::from(area.width()) * usize::from(area.height()) * 4);
+    for y in usize::from(area.top)..=usize::from(area.bottom) {
+        for x in usize::from(area.left)..=usize::from(area.right) {
+            let x = if info.has(NineGridFlag::MustFlip) { dest_width - 1 - x } else { x };
+            let source = if info.has(NineGridFlag::TrueSize) {
+                Some((x, y)).filter(|(x, y)| *x < src_width && *y < src_height)
+            } else if src_width == 0 || src_height == 0 {
+                None
+            } else {
+                let left = usize::from(info.left_width);
+                let top = usize::from(info.top_height);
+                Some((
+                    nine_grid_source(x, dest_width, src_width, left, usize::from(info.right_width), tile),
+                    nine_grid_source(y, dest_height, src_height, top, usize::from(info.bottom_height), tile),
+                ))
+            };
+            let mut pixel = match source {
+                Some((x, y)) => {
+                    let start = ((src_top + y) * usize::from(image.width) + src_left + x) * 4;
+                    image.data.get(start..start + 4).map_or([0; 4], |pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+                }
+                None => [0; 4],
+            };
+            if !info.has(NineGridFlag::PerPixelAlpha) {
+                pixel[3] = 0xff;
+            }
+            if info.has(NineGridFlag::Transparent) && pixel[..3] == [blue, green, red] {
+                pixel[3] = 0;
+            }
+            data.extend_from_slice(&pixel);
+        }
+    }
+    data
+}
+
+/// Decoded bitmap of the cache
+/// Pixels are 32 bpp BGRA
+#[derive(Debug, Clone)]
+pub struct CachedBitmap {
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+impl CachedBitmap {
+    /// The bitmap fits in a cell of the cache,
+    /// cells are 16x16, 32x32 and 64x64 pixels
+    pub(crate) fn fits_cell(&self, cache_id: u8) -> bool {
+        usize::from(cache_id) < BITMAP_CACHE_ENTRIES.len()
+            && usize::from(self.width) * usize::from(self.height) <= 256 << (2 * cache_id)
+            && self.data.len() == usize::from(self.width) * usize::from(self.height) * 4
+    }
+}
+
+/// Bitmaps sent by the server with cache bitmap orders
+/// Entries are keyed by cache id and cache index
+#[derive(Debug, Default)]
+pub struct BitmapCache {
+    entries: HashMap<(u8, u16), CachedBitmap>,
+}
+
+impl BitmapCache {
+    pub fn new() -> Self { Self::default() }
+
+    /// Store a bitmap, replacing the previous one
+    /// Bitmaps outside of the announced caches or larger
+    /// than their cell are dropped
+    ///
+    /// Return true if the bitmap is kept
+    pub fn insert(&mut self, cache_id: u8, cache_index: u16, bitmap: CachedBitmap) -> bool {
+        let capacity = BITMAP_CACHE_ENTRIES.get(usize::from(cache_id)).copied().unwrap_or(0);
+        if u32::from(cache_index) >= capacity || !bitmap.fits_cell(cache_id) {
+            return false;
+        }
+        self.entries.insert((cache_id, cache_index), bitmap);
+        true
+    }
+
+    /// Bitmap previously stored at this place
+    pub fn get(&self, cache_id: u8, cache_index: u16) -> Option<&CachedBitmap> {
+        self.entries.get(&(cache_id, cache_index))
+    }
+
+    /// Number of cached bitmaps
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Forget all bitmaps
+    /// The server resets its caches on reactivation
+    pub fn clear(&mut self) { self.entries.clear() }
+}
+
+/// State of a RemoteApp window
+#[derive(Debug, Clone, Default)]
+struct RemoteWindow {
+    title: String,
+    show_state: u8,
+    left: i32,
+    top: i32,
+    width: u32,
+    height: u32,
+}
+
+impl RemoteWindow {
+    /// Bounds are clamped to the coordinates of a rect
+    fn event(&self, window_id: u32, visible: bool) -> RdpEvent {
+        let clamp = |value: i64| value.clamp(0, i64::from(u16::MAX)) as u16;
+        let (left, top) = (i64::from(self.left), i64::from(self.top));
+        let rect = Rect {
+            left: clamp(left),
+            top: clamp(top),
+            right: clamp(left + i64::from(self.width) - 1),
+            bottom: clamp(top + i64::from(self.height) - 1),
+        };
+        RdpEvent::RemoteWindow { window_id, title: self.title.clone(), rect, visible }
+    }
+}
+
+/// Skip fields not used by the decoder
+fn skip(stream: &mut Cursor<&[u8]>, length: u64) -> RdpResult<()> {
+    let position = stream.position() + length;
+    if position > stream.get_ref().len() as u64 {
+        return Err(orders_error("Truncated window order"));
+    }
+    stream.set_position(position);
+    Ok(())
+}
+
+/// Bitmap of a nine grid order
+/// Drawn by the orders following a switch surface
+#[derive(Debug, Clone)]
+struct NineGridBitmap {
+    info: NineGridBitmapInfo,
+    bitmap: CachedBitmap,
+}
+
+/// Clipping rectangle of primary orders
+/// Bounds are inclusive
+#[derive(Debug, Default, Copy, Clone)]
+struct Bounds {
+    left: i16,
+    top: i16,
+    right: i16,
+    bottom: i16,
+}
+
+/// Last values of the memory blit fields
+/// Each order only sends fields which changed
+#[derive(Debug, Default, Copy, Clone)]
+struct MemBlt {
+    cache_id: u16,
+    left: i16,
+    top: i16,
+    width: i16,
+    height: i16,
+    rop: u8,
+    x_src: i16,
+    y_src: i16,
+    cache_index: u16,
+}
+
+/// Last values of the opaque rectangle fields
+#[derive(Debug, Default, Copy, Clone)]
+struct OpaqueRect {
+    left: i16,
+    top: i16,
+    width: i16,
+    height: i16,
+    /// red or palette index, green and blue
+    color: [u8; 3],
+}
+
+/// Last values of the pattern blit fields
+#[derive(Debug, Default, Copy, Clone)]
+struct PatBlt {
+    left: i16,
+    top: i16,
+    width: i16,
+    height: i16,
+    rop: u8,
+    back_color: u32,
+    fore_color: u32,
+    brush: Brush,
+}
+
+/// Clipping rectangle of a multi order
+#[derive(Debug, Default, Copy, Clone)]
+struct DeltaRect {
+    left: i16,
+    top: i16,
+    width: i16,
+    height: i16,
+}
+
+/// Last values of the draw nine grid fields
+/// shared with the multi draw nine grid order
+#[derive(Debug, Default, Clone)]
+struct DrawNineGrid {
+    /// Right and bottom are excluded
+    src: Bounds,
+    bitmap_id: u16,
+    delta_entries: u8,
+    rects: Vec<DeltaRect>,
+}
+
+/// Area of the desktop covered by an order
+/// clipped by the bounds and the desktop origin
+fn clip(left: i32, top: i32, width: i32, height: i32, bounds: Option<Bounds>) -> Option<Rect> {
+    let (mut left, mut top, mut right, mut bottom) = (left, top, left + width, top + height);
+    if let Some(bounds) = bounds {
+        left = left.max(i32::from(bounds.left));
+        top = top.max(i32::from(bounds.top));
+        right = right.min(i32::from(bounds.right) + 1);
+        bottom = bottom.min(i32::from(bounds.bottom) + 1);
+    }
+    left = left.max(0);
+    top = top.max(0);
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some(Rect {
+        left: u16::try_from(left).ok()?,
+        top: u16::try_from(top).ok()?,
+        right: u16::try_from(right - 1).unwrap_or(u16::MAX),
+        bottom: u16::try_from(bottom - 1).unwrap_or(u16::MAX),
+    })
+}
+
+/// Decoder of the drawing orders
+/// Keeps the state shared by successive orders
+/// and draws memory blits from the bitmap cache
+///
+/// See MS-RDPEGDI 2.2.2.2
+#[derive(Debug)]
+pub struct OrderDecoder {
+    cache: BitmapCache,
+    order_type: PrimaryOrderType,
+    bounds: Bounds,
+    mem_blt: MemBlt,
+    opaque_rect: OpaqueRect,
+    pat_blt: PatBlt,
+    draw_nine_grid: DrawNineGrid,
+    multi_draw_nine_grid: DrawNineGrid,
+    nine_grid_bitmaps: HashMap<u16, NineGridBitmap>,
+    /// Offscreen bitmap drawn by the orders
+    /// None for the screen
+    surface: Option<u16>,
+    /// RemoteApp windows, orders only carry the changed fields
+    windows: HashMap<u32, RemoteWindow>,
+    /// Bitmaps kept between sessions
+    /// None without persistent cache
+    persistent: Option<PersistentBitmapCache>,
+}
+
+impl Default for OrderDecoder {
+    fn default() -> Self {
+        OrderDecoder {
+            cache: BitmapCache::new(),
+            order_type: PrimaryOrderType::PatBlt,
+            bounds: Bounds::default(),
+            mem_blt: MemBlt::default(),
+            opaque_rect: OpaqueRect::default(),
+            pat_blt: PatBlt::default(),
+            draw_nine_grid: DrawNineGrid::default(),
+            multi_draw_nine_grid: DrawNineGrid::default(),
+            nine_grid_bitmaps: HashMap::new(),
+            surface: None,
+            windows: HashMap::new(),
+            persistent: None,
+        }
+    }
+}
+
+impl OrderDecoder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Bitmaps cached so far
+    pub fn cache(&self) -> &BitmapCache { &self.cache }
+
+    /// Forget the state of a previous session
+    /// The persistent bitmaps are loaded again
+    pub fn reset(&mut self) {
+        let persistent = self.persistent.take();
+        *self = Self::default();
+        if let Some(persistent) = persistent {
+            self.set_persistent_cache(persistent);
+        }
+    }
+
+    /// Keep the bitmaps sent with a persistent key
+    /// Bitmaps of the previous sessions are loaded in the cache
+    /// at the index of their key in the key list PDUs
+    pub fn set_persistent_cache(&mut self, persistent: PersistentBitmapCache) {
+        for cache_id in 0..BITMAP_CACHE_ENTRIES.len() as u8 {
+            for (cache_index, key) in persistent.keys(cache_id).into_iter().enumerate() {
+                if let Some((_, bitmap)) = persistent.get(key) {
+                    self.cache.insert(cache_id, cache_index as u16, bitmap.clone());
+                }
+            }
+        }
+        self.persistent = Some(persistent);
+    }
+
+    /// Bitmaps kept between sessions
+    pub fn persistent_cache(&self) -> Option<&PersistentBitmapCache> { self.persistent.as_ref() }
+
+    /// Decode the orders of an orders update
+    /// Memory blits and nine grids are notified as uncompressed 32 bpp
+    /// bitmaps, other primary orders as draw order events
+    ///
+    /// Orders drawing a nine grid bitmap are not notified
+    ///
+    /// Decoding stops at the first unknown primary order
+    /// because its length can't be computed
+    pub fn read_orders<T>(
+        &mut self, data: &[u8], number_orders: u16, palette: Option<&Arc<Palette>>, mut callback: T,
+    ) -> RdpResult<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let mut stream = Cursor::new(data);
+        for _ in 0..number_orders {
+            let control_flags = stream.read_u8()?;
+            if control_flags & ControlFlag::Standard as u8 == 0 {
+                if let Some(event) = self.read_alt_sec_order(&mut stream, control_flags)? {
+                    callback(event);
+                }
+            } else if control_flags & ControlFlag::Secondary as u8 != 0 {
+                self.read_secondary_order(&mut stream, palette)?;
+            } else {
+                for event in self.read_primary_order(&mut stream, control_flags)? {
+                    match self.surface {
+                        None => callback(event),
+                        Some(surface) => self.draw_offscreen(surface, event),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Alternate secondary orders have no length
+    /// so unknown orders stop the decoding
+    fn read_alt_sec_order(&mut self, stream: &mut dyn Read, control_flags: u8) -> RdpResult<Option<RdpEvent>> {
+        let order_type = control_flags >> 2;
+        match AltSecOrderType::try_from(order_type) {
+            // See MS-RDPEGDI 2.2.2.2.1.3.3
+            Ok(AltSecOrderType::SwitchSurface) => {
+                let bitmap_id = stream.read_u16::<LittleEndian>()?;
+                self.surface = if bitmap_id == SCREEN_BITMAP_SURFACE { None } else { Some(bitmap_id) };
+            }
+            // See MS-RDPEGDI 2.2.2.2.1.3.4
+            Ok(AltSecOrderType::CreateNineGridBitmap) => {
+                let bpp = stream.read_u8()?;
+                if bpp != NINE_GRID_BITMAP_BPP {
+                    return Err(orders_error(&format!("Invalid nine grid bitmap bpp {}", bpp)));
+                }
+                let bitmap_id = stream.read_u16::<LittleEndian>()?;
+                let width = stream.read_u16::<LittleEndian>()?;
+                let height = stream.read_u16::<LittleEndian>()?;
+                let info = NineGridBitmapInfo::read(stream)?;
+                // Only the cache announced in the capability is kept
+                if bitmap_id >= NINE_GRID_CACHE_ENTRIES {
+                    return Err(orders_error(&format!("Invalid nine grid bitmap id {}", bitmap_id)));
+                }
+                let size = usize::from(width) * usize::from(height) * 4;
+                let cached: usize = self
+                    .nine_grid_bitmaps
+                    .iter()
+                    .filter(|(id, _)| **id != bitmap_id)
+                    .map(|(_, nine_grid)| nine_grid.bitmap.data.len())
+                    .sum();
+                if cached + size > usize::from(NINE_GRID_CACHE_SIZE) * 1024 {
+                    return Err(orders_error("Nine grid bitmap cache is full"));
+                }
+                let data = vec![0; size];
+                self.nine_grid_bitmaps
+                    .insert(bitmap_id, NineGridBitmap { info, bitmap: CachedBitmap { width, height, data } });
+            }
+            // See MS-RDPERP 2.2.1.3
+            Ok(AltSecOrderType::Window) => {
+                // The size includes the control flags
+                let order_size = stream.read_u16::<LittleEndian>()?;
+                let mut body = vec![0; usize::from(order_size).saturating_sub(3)];
+                stream.read_exact(&mut body)?;
+                return self.read_window_order(&body);
+            }
+            Err(_) => return Err(orders_error(&format!("Alternate secondary order not handled {:#x}", order_type))),
+        }
+        Ok(None)
+    }
+
+    /// Update the state of a RemoteApp window
+    /// Icon, notification area and desktop orders are ignored
+    ///
+    /// See MS-RDPERP 2.2.1.3.1
+    fn read_window_order(&mut self, body: &[u8]) -> RdpResult<Option<RdpEvent>> {
+        let mut stream = Cursor::new(body);
+        let fields = stream.read_u32::<LittleEndian>()?;
+        let has = |flag: WindowOrderFlag| fields & flag as u32 != 0;
+        if !has(WindowOrderFlag::TypeWindow) || has(WindowOrderFlag::Icon) || has(WindowOrderFlag::CachedIcon) {
+            log_debug!("ORDERS: window order not handled {:#x}", fields);
+            return Ok(None);
+        }
+        let window_id = stream.read_u32::<LittleEndian>()?;
+
+        if has(WindowOrderFlag::StateDeleted) {
+            let window = self.windows.remove(&window_id).unwrap_or_default();
+            return Ok(Some(window.event(window_id, false)));
+        }
+
+        let window = self.windows.entry(window_id).or_default();
+        if has(WindowOrderFlag::Owner) {
+            stream.read_u32::<LittleEndian>()?;
+        }
+        if has(WindowOrderFlag::Style) {
+            stream.read_u32::<LittleEndian>()?;
+            stream.read_u32::<LittleEndian>()?;
+        }
+        if has(WindowOrderFlag::Show) {
+            window.show_state = stream.read_u8()?;
+        }
+        if has(WindowOrderFlag::Title) {
+            let mut title = vec![0; usize::from(stream.read_u16::<LittleEndian>()?)];
+            stream.read_exact(&mut title)?;
+            window.title = read_unicode(&title);
+        }
+        if has(WindowOrderFlag::ClientAreaOffset) {
+            skip(&mut stream, 8)?;
+        }
+        if has(WindowOrderFlag::ClientAreaSize) {
+            skip(&mut stream, 8)?;
+        }
+        if has(WindowOrderFlag::ResizeMarginX) {
+            skip(&mut stream, 8)?;
+        }
+        if has(WindowOrderFlag::ResizeMarginY) {
+            skip(&mut stream, 8)?;
+        }
+        if has(WindowOrderFlag::RpContent) {
+            stream.read_u8()?;
+        }
+        if has(WindowOrderFlag::RootParent) {
+            stream.read_u32::<LittleEndian>()?;
+        }
+        if has(WindowOrderFlag::WndOffset) {
+            window.left = stream.read_i32::<LittleEndian>()?;
+            window.top = stream.read_i32::<LittleEndian>()?;
+        }
+        if has(WindowOrderFlag::WndClientDelta) {
+            skip(&mut stream, 8)?;
+        }
+        if has(WindowOrderFlag::WndSize) {
+            window.width = stream.read_u32::<LittleEndian>()?;
+            window.height = stream.read_u32::<LittleEndian>()?;
+        }
+        // Window rects and visibility follow
+        // but the order size already bounds them
+
+        let visible = window.show_state != WINDOW_SHOW_HIDE;
+        Ok(Some(window.event(window_id, visible)))
+    }
+
+    /// Draw an order on a nine grid bitmap
+    /// Only bitmaps and opaque rectangles are drawn
+    fn draw_offscreen(&mut self, surface: u16, event: RdpEvent) {
+        let bitmap = match self.nine_grid_bitmaps.get_mut(&surface) {
+            Some(nine_grid) => &mut nine_grid.bitmap,
+            None => {
+                log_debug!("ORDERS: order on unknown surface {}", surface);
+                return;
+            }
+        };
+        // pixels of the order as 32 bpp BGRA
+        let (rect, data) = match event {
+            RdpEvent::Bitmap(event) => (
+                Rect { left: event.dest_left, top: event.dest_top, right: event.dest_right, bottom: event.dest_bottom },
+                event.data,
+            ),
+            RdpEvent::DrawOrder(DrawOrderEvent::OpaqueRect { rect, color }) => {
+                let [red, green, blue, _] = color.to_le_bytes();
+                let pixels = usize::from(rect.width()) * usize::from(rect.height());
+                (rect, [blue, green, red, 0xff].repeat(pixels))
+            }
+            event => {
+                log_debug!("ORDERS: order not drawn on nine grid bitmap {:?}", event);
+                return;
+            }
+        };
+        if bitmap.width == 0 || bitmap.height == 0 {
+            return;
+        }
+        let width = usize::from(rect.width());
+        let right = usize::from(rect.right).min(usize::from(bitmap.width).saturating_sub(1));
+        let bottom = usize::from(rect.bottom).min(usize::from(bitmap.height).saturating_sub(1));
+        for y in usize::from(rect.top)..=bottom {
+            for x in usize::from(rect.left)..=right {
+                let start = (y * usize::from(bitmap.width) + x) * 4;
+                let source = ((y - usize::from(rect.top)) * width + x - usize::from(rect.left)) * 4;
+                if let Some(pixel) = data.get(source..source + 4) {
+                    bitmap.data[start..start + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+
+    /// Secondary orders fill caches
+    /// Their length is known so unknown orders are skipped
+    fn read_secondary_order(&mut self, stream: &mut Cursor<&[u8]>, palette: Option<&Arc<Palette>>) -> RdpResult<()> {
+        let order_length = stream.read_i16::<LittleEndian>()?;
+        let extra_flags = stream.read_u16::<LittleEndian>()?;
+        let order_type = stream.read_u8()?;
+        let length = usize::try_from(isize::from(order_length) + 7)
+            .map_err(|_| orders_error(&format!("Invalid secondary order length {}", order_length)))?;
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body)?;
+
+        match SecondaryOrderType::try_from(order_type) {
+            Ok(SecondaryOrderType::CacheBitmapUncompressedRev2) => {
+                self.read_cache_bitmap_rev2(&body, extra_flags, false, palette)
+            }
+            Ok(SecondaryOrderType::CacheBitmapCompressedRev2) => {
+                self.read_cache_bitmap_rev2(&body, extra_flags, true, palette)
+            }
+            _ => {
+                log_debug!("ORDERS: secondary order not handled {:#x}", order_type);
+                Ok(())
+            }
+        }
+    }
+
+    /// Cache bitmap revision 2 order
+    /// See MS-RDPEGDI 2.2.2.2.1.2.3
+    fn read_cache_bitmap_rev2(
+        &mut self, body: &[u8], extra_flags: u16, is_compress: bool, palette: Option<&Arc<Palette>>,
+    ) -> RdpResult<()> {
+        let cache_id = (extra_flags & 0x7) as u8;
+        let bpp = match (extra_flags >> 3) & 0xf {
+            0x3 => 8,
+            0x4 => 16,
+            0x5 => 24,
+            0x6 => 32,
+            id => return Err(orders_error(&format!("Invalid bits per pixel id {:#x}", id))),
+        };
+        let flags = extra_flags >> 7;
+
+        let mut stream = Cursor::new(body);
+        // key1 then key2
+        let key = if flags & CacheBitmapRev2Flag::PersistentKeyPresent as u16 != 0 {
+            Some(stream.read_u64::<LittleEndian>()?)
+        } else {
+            None
+        };
+        let width = read_two_byte_unsigned(&mut stream)?;
+        let height = if flags & CacheBitmapRev2Flag::HeightSameAsWidth as u16 != 0 {
+            width
+        } else {
+            read_two_byte_unsigned(&mut stream)?
+        };
+        let mut bitmap_length = read_four_byte_unsigned(&mut stream)? as usize;
+        let cache_index = read_two_byte_unsigned(&mut stream)?;
+        if is_compress && flags & CacheBitmapRev2Flag::NoBitmapCompressionHdr as u16 == 0 {
+            // the compression header doesn't give more information
+            let mut header = [0; 8];
+            stream.read_exact(&mut header)?;
+            bitmap_length = bitmap_length.saturating_sub(header.len());
+        }
+        let mut data = vec![0; bitmap_length];
+        stream.read_exact(&mut data)?;
+
+        if flags & CacheBitmapRev2Flag::DoNotCache as u16 != 0 || cache_index == BITMAPCACHE_WAITING_LIST_INDEX {
+            return Ok(());
+        }
+
+        let bitmap = BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: width.saturating_sub(1),
+            dest_bottom: height.saturating_sub(1),
+            width,
+            height,
+            bpp,
+            is_compress,
+            codec: None,
+            monitor: None,
+            palette: palette.cloned(),
+            data,
+        };
+        match bitmap.decompress() {
+            Ok(data) => {
+                let bitmap = CachedBitmap { width, height, data };
+                if let (Some(key), Some(persistent)) = (key, &mut self.persistent) {
+                    persistent.insert(key, cache_id, bitmap.clone());
+                }
+                if !self.cache.insert(cache_id, cache_index, bitmap) {
+                    log_debug!("ORDERS: bitmap outside of the cache {} {}", cache_id, cache_index);
+                }
+            }
+            Err(e) => log_debug!("ORDERS: unable to decode cached bitmap {:?}", e),
+        }
+        Ok(())
+    }
+
+    /// Primary orders are delta encoded against the previous order of the same
+    /// type
+    fn read_primary_order(&mut self, stream: &mut Cursor<&[u8]>, control_flags: u8) -> RdpResult<Vec<RdpEvent>> {
+        if control_flags & ControlFlag::TypeChange as u8 != 0 {
+            let order_type = stream.read_u8()?;
+            self.order_type = PrimaryOrderType::try_from(order_type)
+                .map_err(|_| orders_error(&format!("Primary order not handled {:#x}", order_type)))?;
+        }
+
+        let mut field_bytes = self.order_type.field_bytes();
+        if control_flags & ControlFlag::ZeroFieldByteBit0 as u8 != 0 {
+            field_bytes = field_bytes.saturating_sub(1);
+        }
+        if control_flags & ControlFlag::ZeroFieldByteBit1 as u8 != 0 {
+            field_bytes = field_bytes.saturating_sub(2);
+        }
+        let mut field_flags: u32 = 0;
+        for i in 0..field_bytes {
+            field_flags |= u32::from(stream.read_u8()?) << (8 * i);
+        }
+
+        let has_bounds = control_flags & ControlFlag::Bounds as u8 != 0;
+        if has_bounds && control_flags & ControlFlag::ZeroBoundsDeltas as u8 == 0 {
+            self.read_bounds(stream)?;
+        }
+        let bounds = if has_bounds { Some(self.bounds) } else { None };
+
+        let delta = control_flags & ControlFlag::DeltaCoordinates as u8 != 0;
+        Ok(match self.order_type {
+            PrimaryOrderType::MemBlt => {
+                self.read_mem_blt(stream, field_flags, delta)?;
+                self.draw_mem_blt(&self.mem_blt, bounds).map(RdpEvent::Bitmap).into_iter().collect()
+            }
+            PrimaryOrderType::DrawNineGrid => {
+                let order = Self::read_draw_nine_grid(&mut self.draw_nine_grid, stream, field_flags, delta, false)?;
+                self.draw_nine_grid_order(&order, bounds, None)
+            }
+            PrimaryOrderType::MultiDrawNineGrid => {
+                let order =
+                    Self::read_draw_nine_grid(&mut self.multi_draw_nine_grid, stream, field_flags, delta, true)?;
+                let rects = order.rects.iter().take(usize::from(order.delta_entries)).copied().collect::<Vec<_>>();
+                self.draw_nine_grid_order(&order, bounds, Some(&rects))
+            }
+            PrimaryOrderType::OpaqueRect => {
+                let order = self.read_opaque_rect(stream, field_flags, delta)?;
+                clip(
+                    i32::from(order.left),
+                    i32::from(order.top),
+                    i32::from(order.width),
+                    i32::from(order.height),
+                    bounds,
+                )
+                .map(|rect| {
+                    let [red, green, blue] = order.color;
+                    let color = u32::from_le_bytes([red, green, blue, 0]);
+                    RdpEvent::DrawOrder(DrawOrderEvent::OpaqueRect { rect, color })
+                })
+                .into_iter()
+                .collect()
+            }
+            PrimaryOrderType::PatBlt => {
+                let order = self.read_pat_blt(stream, field_flags, delta)?;
+                clip(
+                    i32::from(order.left),
+                    i32::from(order.top),
+                    i32::from(order.width),
+                    i32::from(order.height),
+                    bounds,
+                )
+                .map(|rect| {
+                    RdpEvent::DrawOrder(DrawOrderEvent::PatBlt {
+                        rect,
+                        rop: order.rop,
+                        back_color: order.back_color,
+                        fore_color: order.fore_color,
+                        brush: order.brush,
+                    })
+                })
+                .into_iter()
+                .collect()
+            }
+        })
+    }
+
+    /// Memory blit order
+    /// See MS-RDPEGDI 2.2.2.2.1.1.2.9
+    fn read_mem_blt(&mut self, stream: &mut dyn Read, field_flags: u32, delta: bool) -> RdpResult<()> {
+        let order = &mut self.mem_blt;
+        if field_flags & 0x0001 != 0 {
+            order.cache_id = stream.read_u16::<LittleEndian>()?;
+        }
+        if field_flags & 0x0002 != 0 {
+            order.left = read_coord(stream, delta, order.left)?;
+        }
+        if field_flags & 0x0004 != 0 {
+            order.top = read_coord(stream, delta, order.top)?;
+        }
+        if field_flags & 0x0008 != 0 {
+            order.width = read_coord(stream, delta, order.width)?;
+        }
+        if field_flags & 0x0010 != 0 {
+            order.height = read_coord(stream, delta, order.height)?;
+        }
+        if field_flags & 0x0020 != 0 {
+            order.rop = stream.read_u8()?;
+        }
+        if field_flags & 0x0040 != 0 {
+            order.x_src = read_coord(stream, delta, order.x_src)?;
+        }
+        if field_flags & 0x0080 != 0 {
+            order.y_src = read_coord(stream, delta, order.y_src)?;
+        }
+        if field_flags & 0x0100 != 0 {
+            order.cache_index = stream.read_u16::<LittleEndian>()?;
+        }
+        Ok(())
+    }
+
+    /// Opaque rectangle order
+    /// See MS-RDPEGDI 2.2.2.2.1.1.2.5
+    fn read_opaque_rect(&mut self, stream: &mut dyn Read, field_flags: u32, delta: bool) -> RdpResult<OpaqueRect> {
+        let order = &mut self.opaque_rect;
+        if field_flags & 0x01 != 0 {
+            order.left = read_coord(stream, delta, order.left)?;
+        }
+        if field_flags & 0x02 != 0 {
+            order.top = read_coord(stream, delta, order.top)?;
+        }
+        if field_flags & 0x04 != 0 {
+            order.width = read_coord(stream, delta, order.width)?;
+        }
+        if field_flags & 0x08 != 0 {
+            order.height = read_coord(stream, delta, order.height)?;
+        }
+        for (i, component) in order.color.iter_mut().enumerate() {
+            if field_flags & (0x10 << i) != 0 {
+                *component = stream.read_u8()?;
+            }
+        }
+        Ok(*order)
+    }
+
+    /// Pattern blit order
+    /// See MS-RDPEGDI 2.2.2.2.1.1.2.3
+    fn read_pat_blt(&mut self, stream: &mut dyn Read, field_flags: u32, delta: bool) -> RdpResult<PatBlt> {
+        let order = &mut self.pat_blt;
+        if field_flags & 0x0001 != 0 {
+            order.left = read_coord(stream, delta, order.left)?;
+        }
+        if field_flags & 0x0002 != 0 {
+            order.top = read_coord(stream, delta, order.top)?;
+        }
+        if field_flags & 0x0004 != 0 {
+            order.width = read_coord(stream, delta, order.width)?;
+        }
+        if field_flags & 0x0008 != 0 {
+            order.height = read_coord(stream, delta, order.height)?;
+        }
+        if field_flags & 0x0010 != 0 {
+            order.rop = stream.read_u8()?;
+        }
+        if field_flags & 0x0020 != 0 {
+            order.back_color = read_color(stream)?;
+        }
+        if field_flags & 0x0040 != 0 {
+            order.fore_color = read_color(stream)?;
+        }
+        if field_flags & 0x0080 != 0 {
+            order.brush.x = stream.read_i8()?;
+        }
+        if field_flags & 0x0100 != 0 {
+            order.brush.y = stream.read_i8()?;
+        }
+        if field_flags & 0x0200 != 0 {
+            order.brush.style = stream.read_u8()?;
+        }
+        if field_flags & 0x0400 != 0 {
+            order.brush.hatch = stream.read_u8()?;
+        }
+        if field_flags & 0x0800 != 0 {
+            stream.read_exact(&mut order.brush.extra)?;
+        }
+        Ok(*order)
+    }
+
+    /// Draw nine grid and multi draw nine grid orders
+    /// Clipping rectangles are only sent by the multi order
+    ///
+    /// See MS-RDPEGDI 2.2.2.2.1.1.2.21 and 2.2.2.2.1.1.2.22
+    fn read_draw_nine_grid(
+        order: &mut DrawNineGrid, stream: &mut dyn Read, field_flags: u32, delta: bool, multi: bool,
+    ) -> RdpResult<DrawNineGrid> {
+        if field_flags & 0x01 != 0 {
+            order.src.left = read_coord(stream, delta, order.src.left)?;
+        }
+        if field_flags & 0x02 != 0 {
+            order.src.top = read_coord(stream, delta, order.src.top)?;
+        }
+        if field_flags & 0x04 != 0 {
+            order.src.right = read_coord(stream, delta, order.src.right)?;
+        }
+        if field_flags & 0x08 != 0 {
+            order.src.bottom = read_coord(stream, delta, order.src.bottom)?;
+        }
+        if field_flags & 0x10 != 0 {
+            order.bitmap_id = stream.read_u16::<LittleEndian>()?;
+        }
+        if multi && field_flags & 0x20 != 0 {
+            order.delta_entries = stream.read_u8()?;
+        }
+        if multi && field_flags & 0x40 != 0 {
+            let length = stream.read_u16::<LittleEndian>()?;
+            let mut data = vec![0; usize::from(length)];
+            stream.read_exact(&mut data)?;
+            order.rects = read_delta_rects(&mut Cursor::new(data), order.delta_entries)?;
+        }
+        Ok(order.clone())
+    }
+
+    /// Stretch the nine grid bitmap in the bounds
+    /// clipped by the rectangles of multi orders
+    fn draw_nine_grid_order(
+        &self, order: &DrawNineGrid, bounds: Option<Bounds>, rects: Option<&[DeltaRect]>,
+    ) -> Vec<RdpEvent> {
+        let bitmap = match self.nine_grid_bitmaps.get(&order.bitmap_id) {
+            Some(bitmap) => bitmap,
+            None => {
+                log_debug!("ORDERS: nine grid bitmap not created {}", order.bitmap_id);
+                return vec![];
+            }
+        };
+        // the destination is the bounding rectangle
+        let dest = match bounds {
+            Some(bounds) => bounds,
+            None => {
+                log_debug!("ORDERS: nine grid order without destination");
+                return vec![];
+            }
+        };
+        let dest_width = i32::from(dest.right) - i32::from(dest.left) + 1;
+        let dest_height = i32::from(dest.bottom) - i32::from(dest.top) + 1;
+        // the size of the whole destination must fit in its delta rectangle
+        let (width, height) = match (i16::try_from(dest_width), i16::try_from(dest_height)) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+            _ => {
+                log_debug!("ORDERS: invalid nine grid destination {}x{}", dest_width, dest_height);
+                return vec![];
+            }
+        };
+        if usize::from(width.unsigned_abs()) * usize::from(height.unsigned_abs()) > MAX_NINE_GRID_PIXELS {
+            log_debug!("ORDERS: nine grid destination too large {}x{}", width, height);
+            return vec![];
+        }
+        let whole = [DeltaRect { left: dest.left, top: dest.top, width, height }];
+        rects
+            .unwrap_or(&whole)
+            .iter()
+            .filter_map(|clip_rect| {
+                let rect = clip(
+                    i32::from(clip_rect.left),
+                    i32::from(clip_rect.top),
+                    i32::from(clip_rect.width),
+                    i32::from(clip_rect.height),
+                    Some(dest),
+                )?;
+                let area = Rect {
+                    left: (i32::from(rect.left) - i32::from(dest.left)) as u16,
+                    top: (i32::from(rect.top) - i32::from(dest.top)) as u16,
+                    right: (i32::from(rect.right) - i32::from(dest.left)) as u16,
+                    bottom: (i32::from(rect.bottom) - i32::from(dest.top)) as u16,
+                };
+                Some(RdpEvent::Bitmap(BitmapEvent {
+                    dest_left: rect.left,
+                    dest_top: rect.top,
+                    dest_right: rect.right,
+                    dest_bottom: rect.bottom,
+                    width: rect.width(),
+                    height: rect.height(),
+                    bpp: 32,
+                    is_compress: false,
+                    codec: None,
+                    monitor: None,
+                    palette: None,
+                    data: draw_nine_grid(bitmap, order.src, dest_width as usize, dest_height as usize, area),
+                }))
+            })
+            .collect()
+    }
+
+    /// Bounds are absolute or relative to the previous ones
+    fn read_bounds(&mut self, stream: &mut Cursor<&[u8]>) -> RdpResult<()> {
+        let flags = stream.read_u8()?;
+        let bounds = &mut self.bounds;
+        for (i, value) in
+            [&mut bounds.left, &mut bounds.top, &mut bounds.right, &mut bounds.bottom].into_iter().enumerate()
+        {
+            if flags & (0x01 << i) != 0 {
+                *value = stream.read_i16::<LittleEndian>()?;
+            } else if flags & (0x10 << i) != 0 {
+                *value = value.wrapping_add(i16::from(stream.read_i8()?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the cached bitmap area to the destination
+    /// clipped by the bounds and the cached bitmap
+    fn draw_mem_blt(&self, order: &MemBlt, bounds: Option<Bounds>) -> Option<BitmapEvent> {
+        let cache_id = (order.cache_id & 0xff) as u8;
+        let bitmap = match self.cache.get(cache_id, order.cache_index) {
+            Some(bitmap) => bitmap,
+            None => {
+                log_debug!("ORDERS: bitmap not cached {} {}", cache_id, order.cache_index);
+                return None;
+            }
+        };
+        if order.x_src < 0 || order.y_src < 0 {
+            log_debug!("ORDERS: negative source of memory blit {} {}", order.x_src, order.y_src);
+            return None;
+        }
+        if order.rop != ROP_SRCCOPY {
+            log_debug!("ORDERS: raster operation drawn as copy {:#x}", order.rop);
+        }
+
+        // destination area limited to the cached bitmap
+        let width = i32::from(order.width).min(i32::from(bitmap.width) - i32::from(order.x_src));
+        let height = i32::from(order.height).min(i32::from(bitmap.height) - i32::from(order.y_src));
+        let rect = clip(i32::from(order.left), i32::from(order.top), width, height, bounds)?;
+
+        let mut data = Vec::with_capacity(usize::from(rect.width()) * usize::from(rect.height()) * 4);
+        for y in rect.top..=rect.bottom {
+            let src_y = (i32::from(y) - i32::from(order.top) + i32::from(order.y_src)) as usize;
+            let src_x = (i32::from(rect.left) - i32::from(order.left) + i32::from(order.x_src)) as usize;
+            let start = (src_y * bitmap.width as usize + src_x) * 4;
+            data.extend_from_slice(bitmap.data.get(start..start + usize::from(rect.width()) * 4)?);
+        }
+
+        Some(BitmapEvent {
+            dest_left: rect.left,
+            dest_top: rect.top,
+            dest_right: rect.right,
+            dest_bottom: rect.bottom,
+            width: rect.width(),
+            height: rect.height(),
+            bpp: 32,
+            is_compress: false,
+            codec: None,
+            monitor: None,
+            palette: None,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::test_utils::mock_server::fixture;
+
+    /// Pixel of the cached 4x2 bitmap of the fixture
+    fn pixel(index: u8) -> [u8; 4] { [index, index, index, 0xff] }
+
+    /// Decode orders and keep the events
+    fn read_events(decoder: &mut OrderDecoder, data: &[u8], number_orders: u16) -> Vec<RdpEvent> {
+        let mut events = Vec::new();
+        decoder.read_orders(data, number_orders, None, |event| events.push(event)).unwrap();
+        events
+    }
+
+    fn bitmaps(events: Vec<RdpEvent>) -> Vec<BitmapEvent> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                RdpEvent::Bitmap(bitmap) => bitmap,
+                _ => panic!("expected a bitmap"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_variable_length_encoding() {
+        assert_eq!(read_two_byte_unsigned(&mut Cursor::new([0x7f])).unwrap(), 0x7f);
+        assert_eq!(read_two_byte_unsigned(&mut Cursor::new([0x81, 0x02])).unwrap(), 0x102);
+        assert_eq!(read_four_byte_unsigned(&mut Cursor::new([0x20])).unwrap(), 0x20);
+        assert_eq!(read_four_byte_unsigned(&mut Cursor::new([0x81, 0x02, 0x03])).unwrap(), 0x10203);
+    }
+
+    /// Capture of a cache bitmap order followed by two memory blits
+    /// the second one only moves the destination
+    #[test]
+    fn test_replay_cache_orders() {
+        let mut decoder = OrderDecoder::new();
+        let bitmaps = bitmaps(read_events(&mut decoder, &fixture("orders_cache_bitmap.bin"), 3));
+        assert_eq!(decoder.cache().len(), 1);
+        assert_eq!(bitmaps.len(), 2);
+
+        let expected = [pixel(1), pixel(2), pixel(5), pixel(6)].concat();
+        for (bitmap, left) in bitmaps.iter().zip([16, 24]) {
+            assert_eq!(
+                (bitmap.dest_left, bitmap.dest_top, bitmap.dest_right, bitmap.dest_bottom),
+                (left, 32, left + 1, 33)
+            );
+            assert_eq!((bitmap.width, bitmap.height), (2, 2));
+            assert_eq!(bitmap.data, expected);
+        }
+    }
+
+    #[test]
+    fn test_persistent_cache() {
+        let mut persistent = PersistentBitmapCache::new();
+        persistent.insert(9, 0, CachedBitmap { width: 1, height: 1, data: pixel(9).to_vec() });
+        persistent.insert(3, 0, CachedBitmap { width: 1, height: 1, data: pixel(3).to_vec() });
+        let mut decoder = OrderDecoder::new();
+        decoder.set_persistent_cache(persistent);
+        // loaded in the order of the keys
+        assert_eq!(decoder.cache().get(0, 1).unwrap().data, pixel(9));
+
+        // uncompressed 1x1 32 bpp bitmap with the key 0x0201 at index 5
+        let order = [0x03, 0x08, 0x00, 0xb0, 0x01, 0x04, 0x01, 0x02, 0, 0, 0, 0, 0, 0, 0x01, 0x04, 0x05, 1, 2, 3, 0xff];
+        read_events(&mut decoder, &order, 1);
+        assert!(decoder.cache().get(0, 5).is_some());
+        let persistent = decoder.persistent_cache().unwrap();
+        assert_eq!(persistent.keys(0), [3, 9, 0x0201]);
+
+        // kept for the next activation
+        decoder.reset();
+        assert_eq!(decoder.cache().len(), 3);
+        assert_eq!(decoder.cache().get(0, 2).unwrap().width, 1);
+    }
+
+    #[test]
+    fn test_mem_blt_bounds() {
+        let mut decoder = OrderDecoder::new();
+        decoder.cache.insert(0, 1, CachedBitmap { width: 2, height: 2, data: (0..4).flat_map(pixel).collect() });
+        // type change, bounds, cache index and coordinates
+        let order = [
+            0x0d, 0x0d, 0xff, 0x01, // control flags, order type and field flags
+            0x0f, 0x05, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x0a, 0x00, // bounds from (5, 0) to (10, 10)
+            0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0xcc, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+        ];
+        let bitmaps = bitmaps(read_events(&mut decoder, &order, 1));
+        assert_eq!((bitmaps[0].dest_left, bitmaps[0].dest_right, bitmaps[0].width), (5, 5, 1));
+        assert_eq!(bitmaps[0].data, [pixel(1), pixel(3)].concat());
+    }
+
+    #[test]
+    fn test_mem_blt_negative_source() {
+        let mut decoder = OrderDecoder::new();
+        decoder.cache.insert(0, 1, CachedBitmap { width: 2, height: 2, data: (0..4).flat_map(pixel).collect() });
+        // source at (-1, 0)
+        let order = [
+            0x09, 0x0d, 0xff, 0x01, // control flags, order type and field flags
+            0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0xcc, 0xff, 0xff, 0x00, 0x00, 0x01, 0x00,
+        ];
+        assert!(read_events(&mut decoder, &order, 1).is_empty());
+    }
+
+    #[test]
+    fn test_cache_limits() {
+        let mut cache = BitmapCache::new();
+        let bitmap = |width: u16, height: u16| CachedBitmap {
+            width,
+            height,
+            data: vec![0; usize::from(width) * usize::from(height) * 4],
+        };
+        assert!(cache.insert(0, 599, bitmap(16, 16)));
+        assert!(!cache.insert(0, 600, bitmap(1, 1)));
+        assert!(!cache.insert(0, 0, bitmap(16, 17)));
+        assert!(cache.insert(2, 0, bitmap(64, 64)));
+        assert!(!cache.insert(3, 0, bitmap(1, 1)));
+        assert!(!cache.insert(1, 0, CachedBitmap { width: 2, height: 2, data: vec![0; 4] }));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_opaque_rect() {
+        let mut decoder = OrderDecoder::new();
+        let orders = [
+            0x09, 0x0a, 0x7f, // type change, all fields
+            0x0a, 0x00, 0x14, 0x00, 0x64, 0x00, 0x32, 0x00, 0x11, 0x22, 0x33, // (10, 20) 100x50
+            0x11, 0x41, 0x05, 0xff, // delta coordinates, only left moves by 5 and blue changes
+        ];
+        let expected = [
+            DrawOrderEvent::OpaqueRect { rect: Rect { left: 10, top: 20, right: 109, bottom: 69 }, color: 0x332211 },
+            DrawOrderEvent::OpaqueRect { rect: Rect { left: 15, top: 20, right: 114, bottom: 69 }, color: 0xff2211 },
+        ];
+        let events = read_events(&mut decoder, &orders, 2);
+        assert_eq!(events, expected.map(RdpEvent::DrawOrder));
+    }
+
+    #[test]
+    fn test_pat_blt_bounds() {
+        let mut decoder = OrderDecoder::new();
+        let orders = [
+            0x0d, 0x01, 0x7f, 0x02, // type change, bounds, fields up to the brush style
+            0x0f, 0x00, 0x00, 0x00, 0x00, 0x09, 0x00, 0x09, 0x00, // bounds from (0, 0) to (9, 9)
+            0xfe, 0xff, 0x05, 0x00, 0x08, 0x00, 0x08, 0x00, 0xf0, // (-2, 5) 8x8 PATCOPY
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, // colors and solid brush
+        ];
+        let events = read_events(&mut decoder, &orders, 1);
+        let expected = DrawOrderEvent::PatBlt {
+            rect: Rect { left: 0, top: 5, right: 5, bottom: 9 },
+            rop: 0xf0,
+            back_color: 0x030201,
+            fore_color: 0x060504,
+            brush: Brush::default(),
+        };
+        assert_eq!(events, [RdpEvent::DrawOrder(expected)]);
+    }
+
+    #[test]
+    fn test_unknown_primary_order() {
+        let mut decoder = OrderDecoder::new();
+        // line to
+        assert!(decoder.read_orders(&[0x09, 0x09, 0x00], 1, None, |_| ()).is_err());
+    }
+
+    #[test]
+    fn test_delta_rects() {
+        // second rectangle keeps the size and moves by (-1, 300)
+        let data = [0x03, 0x0a, 0x14, 0x05, 0x06, 0x7f, 0x81, 0x2c];
+        let rects = read_delta_rects(&mut Cursor::new(data), 2).unwrap();
+        let rects: Vec<_> = rects.iter().map(|rect| (rect.left, rect.top, rect.width, rect.height)).collect();
+        assert_eq!(rects, [(10, 20, 5, 6), (9, 320, 5, 6)]);
+        assert!(read_delta_rects(&mut Cursor::new(data), 46).is_err());
+    }
+
+    #[test]
+    fn test_nine_grid_source() {
+        // 1 pixel borders of a 4 pixels source in 8 pixels
+        let stretch: Vec<_> = (0..8).map(|x| nine_grid_source(x, 8, 4, 1, 1, false)).collect();
+        assert_eq!(stretch, [0, 1, 1, 1, 2, 2, 2, 3]);
+        let tile: Vec<_> = (0..8).map(|x| nine_grid_source(x, 8, 4, 1, 1, true)).collect();
+        assert_eq!(tile, [0, 1, 2, 1, 2, 1, 2, 3]);
+        // borders shrink in a too small destination
+        let shrunk: Vec<_> = (0..3).map(|x| nine_grid_source(x, 3, 6, 2, 2, false)).collect();
+        assert_eq!(shrunk, [0, 4, 5]);
+    }
+
+    /// Orders creating a 4x4 nine grid bitmap with 1 pixel borders
+    /// drawn by opaque rectangles, border 0x112233 and interior 0x445566
+    pub(crate) fn nine_grid_bitmap_orders() -> Vec<u8> {
+        vec![
+            0x12, 0x20, 0x05, 0x00, 0x04, 0x00, 0x04, 0x00, // create bitmap 5 of 4x4
+            0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x00, // stretch
+            0x02, 0x05, 0x00, // switch to bitmap 5
+            0x09, 0x0a, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x33, 0x22, 0x11, // border
+            0x01, 0x7f, 0x01, 0x00, 0x01, 0x00, 0x02, 0x00, 0x02, 0x00, 0x66, 0x55, 0x44, // interior
+            0x02, 0xff, 0xff, // back to the screen
+        ]
+    }
+
+    /// Pixels of the 8x8 nine grid
+    pub(crate) fn nine_grid_pixel(x: u16, y: u16) -> [u8; 4] {
+        if x == 0 || y == 0 || x == 7 || y == 7 {
+            [0x11, 0x22, 0x33, 0xff]
+        } else {
+            [0x44, 0x55, 0x66, 0xff]
+        }
+    }
+
+    #[test]
+    fn test_draw_nine_grid() {
+        let mut decoder = OrderDecoder::new();
+        let mut orders = nine_grid_bitmap_orders();
+        orders.extend([
+            0x0d, 0x07, 0x1f, // type change, bounds, all fields
+            0x0f, 0x0a, 0x00, 0x0a, 0x00, 0x11, 0x00, 0x11, 0x00, // destination from (10, 10) to (17, 17)
+            0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x05, 0x00, // whole bitmap 5
+        ]);
+        let bitmaps = bitmaps(read_events(&mut decoder, &orders, 6));
+        assert_eq!(bitmaps.len(), 1);
+        assert_eq!((bitmaps[0].dest_left, bitmaps[0].dest_top, bitmaps[0].width, bitmaps[0].height), (10, 10, 8, 8));
+        let expected: Vec<u8> = (0..8).flat_map(|y| (0..8).flat_map(move |x| nine_grid_pixel(x, y))).collect();
+        assert_eq!(bitmaps[0].data, expected);
+    }
+
+    #[test]
+    fn test_multi_draw_nine_grid() {
+        let mut decoder = OrderDecoder::new();
+        let mut orders = nine_grid_bitmap_orders();
+        orders.extend([
+            0x0d, 0x08, 0x7f, // type change, bounds, all fields
+            0x0f, 0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x07, 0x00, // destination from (0, 0) to (7, 7)
+            0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x05, 0x00, // whole bitmap 5
+            0x02, 0x09, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, 0x04, 0x04, 0x02, 0x02, // (0, 0) and (4, 4) 2x2
+        ]);
+        let bitmaps = bitmaps(read_events(&mut decoder, &orders, 6));
+        assert_eq!(bitmaps.len(), 2);
+        for (bitmap, origin) in bitmaps.iter().zip([0, 4]) {
+            assert_eq!((bitmap.dest_left, bitmap.dest_top, bitmap.width, bitmap.height), (origin, origin, 2, 2));
+            let expected: Vec<u8> = (origin..origin + 2)
+                .flat_map(|y| (origin..origin + 2).flat_map(move |x| nine_grid_pixel(x, y)))
+                .collect();
+            assert_eq!(bitmap.data, expected);
+        }
+    }
+
+    /// Destinations too large for their area or delta rectangle are ignored
+    #[test]
+    fn test_nine_grid_extreme_bounds() {
+        for bounds in [
+            [0x00, 0x80, 0x00, 0x80, 0xff, 0x7f, 0xff, 0x7f], // (-32768, -32768) to (32767, 32767)
+            [0xff, 0xff, 0x00, 0x00, 0xff, 0x7f, 0x00, 0x00], // 32769x1
+        ] {
+            for order in [0x07, 0x08] {
+                let mut decoder = OrderDecoder::new();
+                let mut orders = nine_grid_bitmap_orders();
+                orders.extend([0x0d, order, 0x7f, 0x0f]);
+                orders.extend(bounds);
+                orders.extend([0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x04, 0x00, 0x05, 0x00]);
+                if order == 0x08 {
+                    orders.extend([0x01, 0x05, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02]);
+                }
+                assert!(bitmaps(read_events(&mut decoder, &orders, 6)).is_empty());
+            }
+        }
+    }
+
+    /// Nine grid bitmaps are bounded by the announced cache
+    #[test]
+    fn test_nine_grid_cache_bounds() {
+        let create = |bitmap_id: u16, width: u16, height: u16| {
+            let mut order = vec![0x12, 0x20];
+            order.extend(bitmap_id.to_le_bytes());
+            order.extend(width.to_le_bytes());
+            order.extend(height.to_le_bytes());
+            order.extend([1, 0, 0, 0, 1, 0, 1, 0, 1, 0, 1, 0, 0, 0, 0, 0]);
+            order
+        };
+        let mut decoder = OrderDecoder::new();
+        assert!(decoder.read_orders(&create(NINE_GRID_CACHE_ENTRIES, 4, 4), 1, None, |_| ()).is_err());
+        assert!(decoder.read_orders(&create(0, 0xffff, 0xffff), 1, None, |_| ()).is_err());
+        // 2 MB then a replacement of the same bitmap
+        decoder.read_orders(&create(0, 1024, 512), 1, None, |_| ()).unwrap();
+        decoder.read_orders(&create(0, 1024, 512), 1, None, |_| ()).unwrap();
+        assert!(decoder.read_orders(&create(1, 1024, 512), 1, None, |_| ()).is_err());
+        decoder.read_orders(&create(1, 128, 128), 1, None, |_| ()).unwrap();
+    }
+
+    #[test]
+    fn test_window_orders() {
+        let mut decoder = OrderDecoder::new();
+        let orders = [
+            0x2e, 0x22, 0x00, 0x14, 0x0c, 0x00, 0x01, 0x05, 0x00, 0x00, 0x00, // new window 5
+            0x05, 0x04, 0x00, b'a', 0x00, b'b', 0x00, // shown, title "ab"
+            0xf6, 0xff, 0xff, 0xff, 0x14, 0x00, 0x00, 0x00, // offset (-10, 20)
+            0x64, 0x00, 0x00, 0x00, 0x32, 0x00, 0x00, 0x00, // size 100x50
+            0x2e, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x41, 0x05, 0x00, 0x00, 0x00, 0xaa, 0xbb, // icon is ignored
+            0x2e, 0x0c, 0x00, 0x10, 0x00, 0x00, 0x01, 0x05, 0x00, 0x00, 0x00, 0x00, // hidden
+            0x2e, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x21, 0x05, 0x00, 0x00, 0x00, // deleted
+        ];
+        let window = |visible| RdpEvent::RemoteWindow {
+            window_id: 5,
+            title: "ab".to_string(),
+            rect: Rect { left: 0, top: 20, right: 89, bottom: 69 },
+            visible,
+        };
+        assert_eq!(read_events(&mut decoder, &orders, 4), [window(true), window(false), window(false)]);
+        assert!(decoder.windows.is_empty());
+        // truncated title
+        let truncated = [0x2e, 0x0e, 0x00, 0x04, 0x00, 0x00, 0x01, 0x05, 0x00, 0x00, 0x00, 0x08, 0x00, b'a'];
+        assert!(decoder.read_orders(&truncated, 1, None, |_| ()).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::model::time::{SystemTime, UNIX_EPOCH};
+
+/// Ethernet link type of the capture
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Size of the Ethernet, IPv4 and TCP headers
+const HEADERS_SIZE: usize = 14 + 20 + 20;
+
+/// Largest TCP payload of an IPv4 packet
+const MAX_SEGMENT_SIZE: usize = 0xffff - 40;
+
+/// Fake addresses of the capture
+/// The server port lets Wireshark use its RDP dissector
+const CLIENT_ADDRESS: [u8; 4] = [10, 0, 0, 1];
+const SERVER_ADDRESS: [u8; 4] = [10, 0, 0, 2];
+const CLIENT_PORT: u16 = 49152;
+const SERVER_PORT: u16 = 3389;
+
+/// Direction of the recorded bytes
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Checksum of the IPv4 header
+fn ip_checksum(header: &[u8]) -> u16 {
+    let sum = header.chunks(2).fold(0_u32, |sum, word| sum + u32::from(u16::from_be_bytes([word[0], word[1]])));
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !(((sum & 0xffff) + (sum >> 16)) as u16)
+}
+
+/// Write a pcap file of a TCP connection
+/// Each read or write becomes a TCP segment
+/// with fake Ethernet, IPv4 and TCP headers
+///
+/// The TCP checksums are left empty
+pub struct PcapWriter {
+    writer: Box<dyn Write + Send>,
+    /// Next sequence number of each side
+    client_seq: u32,
+    server_seq: u32,
+}
+
+impl std::fmt::Debug for PcapWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PcapWriter")
+            .field("client_seq", &self.client_seq)
+            .field("server_seq", &self.server_seq)
+            .finish()
+    }
+}
+
+impl PcapWriter {
+    /// Write the global header of the capture
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&0xa1b2_c3d4_u32.to_le_bytes())?;
+        writer.write_all(&2_u16.to_le_bytes())?;
+        writer.write_all(&4_u16.to_le_bytes())?;
+        // time zone and accuracy
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&0xffff_u32.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        writer.flush()?;
+        Ok(PcapWriter { writer: Box::new(writer), client_seq: 1, server_seq: 1 })
+    }
+
+    /// Create or truncate the capture file
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> { Self::new(BufWriter::new(File::create(path)?)) }
+
+    /// Record bytes sent in one direction
+    /// Large buffers are split into several segments
+    pub fn record(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        for segment in data.chunks(MAX_SEGMENT_SIZE) {
+            self.write_segment(direction, segment)?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_segment(&mut self, direction: Direction, payload: &[u8]) -> io::Result<()> {
+        let (src, dst, src_port, dst_port, seq, ack) = match direction {
+            Direction::ClientToServer => {
+                (CLIENT_ADDRESS, SERVER_ADDRESS, CLIENT_PORT, SERVER_PORT, self.client_seq, self.server_seq)
+            }
+            Direction::ServerToClient => {
+                (SERVER_ADDRESS, CLIENT_ADDRESS, SERVER_PORT, CLIENT_PORT, self.server_seq, self.client_seq)
+            }
+        };
+        let mut packet = Vec::with_capacity(HEADERS_SIZE + payload.len());
+        // Ethernet with the last byte of the address as MAC
+        packet.extend([0, 0, 0, 0, 0, dst[3], 0, 0, 0, 0, 0, src[3], 0x08, 0x00]);
+        // IPv4 without fragmentation
+        let mut ip = vec![0x45, 0];
+        ip.extend(((20 + 20 + payload.len()) as u16).to_be_bytes());
+        ip.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+        ip.extend(src);
+        ip.extend(dst);
+        let checksum = ip_checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend(ip);
+        // TCP with the PSH and ACK flags
+        packet.extend(src_port.to_be_bytes());
+        packet.extend(dst_port.to_be_bytes());
+        packet.extend(seq.to_be_bytes());
+        packet.extend(ack.to_be_bytes());
+        packet.extend([0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        packet.extend_from_slice(payload);
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.writer.write_all(&(time.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&time.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&packet)?;
+
+        let seq = seq.wrapping_add(payload.len() as u32);
+        match direction {
+            Direction::ClientToServer => self.client_seq = seq,
+            Direction::ServerToClient => self.server_seq = seq,
+        }
+        Ok(())
+    }
+}
+
+/// Stream which records every byte into a pcap file
+/// Wrapping the socket records the TLS records as they are
+///
+/// Use `Connector::record_to_pcap` to record the
+/// decrypted RDP PDUs instead
+///
+/// # Example
+/// ```no_run
+/// use std::net::TcpStream;
+/// use rdp::core::client::Connector;
+/// use rdp::core::pcap::PcapRecorder;
+/// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+/// let stream = PcapRecorder::create(tcp, "session.pcap").unwrap();
+/// let mut client = Connector::new().connect(stream).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct PcapRecorder<S> {
+    inner: S,
+    pcap: PcapWriter,
+}
+
+impl<S> PcapRecorder<S> {
+    pub fn new(inner: S, pcap: PcapWriter) -> Self { PcapRecorder { inner, pcap } }
+
+    /// Record into a new file
+    pub fn create<P: AsRef<Path>>(inner: S, path: P) -> io::Result<Self> {
+        Ok(PcapRecorder { inner, pcap: PcapWriter::create(path)? })
+    }
+
+    /// Underlying stream
+    pub fn get_ref(&self) -> &S { &self.inner }
+
+    /// Give back the underlying stream
+    pub fn into_inner(self) -> S { self.inner }
+}
+
+impl<S: Read> Read for PcapRecorder<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        if size > 0 {
+            self.pcap.record(Direction::ServerToClient, &buf[..size])?;
+        }
+        Ok(size)
+    }
+}
+
+impl<S: Write> Write for PcapRecorder<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.pcap.record(Direction::ClientToServer, &buf[..size])?;
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+/// Sent and received payloads of a capture
+#[cfg(test)]
+pub(crate) fn replay(capture: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    assert_eq!(capture[..4], 0xa1b2_c3d4_u32.to_le_bytes());
+    let (mut sent, mut received) = (Vec::new(), Vec::new());
+    let mut records = &capture[24..];
+    while !records.is_empty() {
+        let length = u32::from_le_bytes(records[8..12].try_into().unwrap()) as usize;
+        let packet = &records[16..16 + length];
+        assert_eq!(ip_checksum(&packet[14..34]), 0);
+        let direction = if packet[34..36] == CLIENT_PORT.to_be_bytes() { &mut sent } else { &mut received };
+        direction.extend_from_slice(&packet[HEADERS_SIZE..]);
+        records = &records[16 + length..];
+    }
+    (sent, received)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_utils::mock_server::fixture;
+
+    /// Stream reading the server bytes and keeping the client bytes
+    struct Peer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.input.read(buf) }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.output.write(buf) }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_record_session() {
+        let path = std::env::temp_dir().join(format!("rdp-rs-record-{}.pcap", std::process::id()));
+        let confirm = fixture("x224_confirm_rdp.bin");
+        let peer = Peer { input: Cursor::new(confirm.clone()), output: Vec::new() };
+        let mut recorder = PcapRecorder::create(peer, &path).unwrap();
+        let request = vec![0x42; MAX_SEGMENT_SIZE + 10];
+        recorder.write_all(&request).unwrap();
+        let mut response = Vec::new();
+        recorder.read_to_end(&mut response).unwrap();
+        let peer = recorder.into_inner();
+        assert_eq!((peer.output, response), (request.clone(), confirm.clone()));
+
+        let capture = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (sent, received) = replay(&capture);
+        assert_eq!(sent, request);
+        assert_eq!(received, confirm);
+    }
+}
@@ -1,10 +1,19 @@
 use std::io::{Read, Write};
 
-use crate::model::data::{Message, Trame, U16, U32};
+use crate::model::data::{Message, Trame, U16};
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 
+/// Largest length of the two octets form
+const MAX_TWO_OCTETS_LENGTH: u16 = 0x3fff;
+
+/// Number of units in a fragment of the multi-part form
+const FRAGMENT_UNIT: u32 = 0x4000;
+
 /// PER encoding length
 /// read length of following payload
+///
+/// Lengths above 16383 are split in fragments,
+/// they are read by `read_fragmented`
 /// # Example
 /// ```
 /// use std::io::Cursor;
@@ -13,22 +22,47 @@ use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 /// assert_eq!(read_length(&mut s).unwrap(), 0x10);
 /// let mut s2 = Cursor::new(&[0x81, 0x10]);
 /// assert_eq!(read_length(&mut s2).unwrap(), 0x110);
-/// ```
-pub fn read_length(s: &mut dyn Read) -> RdpResult<u16> {
+/// let mut s3 = Cursor::new(&[0xc1]);
+/// assert!(read_length(&mut s3).is_err());
+/// ```
+pub fn read_length(s: &mut dyn Read) -> RdpResult<u32> {
+    match read_part_length(s)? {
+        PartLength::Last(length) => Ok(length),
+        PartLength::Fragment(_) => {
+            Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "PER fragmented length of a single part")))
+        }
+    }
+}
+
+/// Length of a part of the multi-part form of X.691 10.9
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PartLength {
+    /// Last part, up to 16383
+    Last(u32),
+    /// Fragment of 16K, 32K, 48K or 64K followed by another part
+    Fragment(u32),
+}
+
+fn read_part_length(s: &mut dyn Read) -> RdpResult<PartLength> {
     let mut byte: u8 = 0;
     byte.read(s)?;
-    if byte & 0x80 != 0 {
-        byte &= !0x80;
-        let mut size = u16::from(byte) << 8;
-        byte.read(s)?;
-        size += u16::from(byte);
-        Ok(size)
-    } else {
-        Ok(u16::from(byte))
+    match byte >> 6 {
+        0 | 1 => Ok(PartLength::Last(u32::from(byte))),
+        2 => {
+            let mut size = u32::from(byte & 0x3f) << 8;
+            byte.read(s)?;
+            size += u32::from(byte);
+            Ok(PartLength::Last(size))
+        }
+        _ => match byte & 0x3f {
+            count @ 1..=4 => Ok(PartLength::Fragment(u32::from(count) * FRAGMENT_UNIT)),
+            _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "PER invalid fragment length"))),
+        },
     }
 }
 
 /// Write PER encoded length
+/// Lengths above 16383 need the multi-part form of `write_fragmented`
 /// # Example
 /// ```
 /// use std::io::Cursor;
@@ -40,12 +74,84 @@ pub fn read_length(s: &mut dyn Read) -> RdpResult<u16> {
 /// let mut s2 = Cursor::new(vec![]);
 /// write_length(0x110).unwrap().write(&mut s2).unwrap();
 /// assert_eq!(s2.into_inner(), [0x81, 0x10]);
+/// assert!(write_length(0x4000).is_err());
 /// ```
 pub fn write_length(length: u16) -> RdpResult<Trame> {
+    if length > MAX_TWO_OCTETS_LENGTH {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidSize,
+            "PER length too large for a single part",
+        )));
+    }
+    Ok(single_part_length(length))
+}
+
+/// One or two octets form of a length up to 16383
+fn single_part_length(length: u16) -> Trame {
     if length > 0x7f {
-        Ok(trame![U16::BE(length | 0x8000)])
+        trame![U16::BE(length | 0x8000)]
     } else {
-        Ok(trame![length as u8])
+        trame![length as u8]
+    }
+}
+
+/// Write a payload prefixed by its PER length
+/// Payloads of 16K or more are split in fragments
+/// ended by a length of the remaining part
+///
+/// # Example
+/// ```
+/// use std::io::Cursor;
+/// use rdp::core::per::write_fragmented;
+/// use rdp::model::data::Message;
+/// let mut s = Cursor::new(vec![]);
+/// write_fragmented(&[1, 2]).write(&mut s).unwrap();
+/// assert_eq!(s.into_inner(), [2, 1, 2]);
+/// ```
+pub fn write_fragmented(payload: &[u8]) -> Trame {
+    let mut result = trame![];
+    let mut remaining = payload;
+    while remaining.len() >= FRAGMENT_UNIT as usize {
+        let count = (remaining.len() / FRAGMENT_UNIT as usize).min(4);
+        let (fragment, rest) = remaining.split_at(count * FRAGMENT_UNIT as usize);
+        result.push(Box::new(0xc0 | count as u8));
+        result.push(Box::new(fragment.to_vec()));
+        remaining = rest;
+    }
+    // The remaining part fits in the two octets form, even if empty
+    result.push(Box::new(single_part_length(remaining.len() as u16)));
+    result.push(Box::new(remaining.to_vec()));
+    result
+}
+
+/// Read a payload written by `write_fragmented`
+/// Payloads larger than `maximum` are rejected
+///
+/// # Example
+/// ```
+/// use std::io::Cursor;
+/// use rdp::core::per::read_fragmented;
+/// let mut s = Cursor::new([2, 1, 2]);
+/// assert_eq!(read_fragmented(16, &mut s).unwrap(), [1, 2]);
+/// assert!(read_fragmented(1, &mut Cursor::new([2, 1, 2])).is_err());
+/// ```
+pub fn read_fragmented(maximum: usize, s: &mut dyn Read) -> RdpResult<Vec<u8>> {
+    let mut result = Vec::new();
+    loop {
+        let (length, is_last) = match read_part_length(s)? {
+            PartLength::Last(length) => (length, true),
+            PartLength::Fragment(length) => (length, false),
+        };
+        let start = result.len();
+        let end = start + length as usize;
+        if end > maximum {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "PER fragmented payload too large")));
+        }
+        result.resize(end, 0);
+        s.read_exact(&mut result[start..])?;
+        if is_last {
+            return Ok(result);
+        }
     }
 }
 
@@ -168,6 +274,101 @@ pub fn read_enumerates(s: &mut dyn Read) -> RdpResult<u8> {
 /// ```
 pub fn write_enumerates(enumerate: u8) -> RdpResult<u8> { Ok(enumerate) }
 
+/// Encoding of a constrained whole number
+/// depending on the size of its range
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ConstrainedForm {
+    /// A single value needs no octet
+    Empty,
+    /// One or two octets
+    Fixed(usize),
+    /// Length followed by the minimal number of octets
+    Indefinite,
+}
+
+fn constrained_form(minimum: i64, maximum: i64) -> RdpResult<ConstrainedForm> {
+    if maximum < minimum {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "PER: empty integer range")));
+    }
+    Ok(match i128::from(maximum) - i128::from(minimum) + 1 {
+        1 => ConstrainedForm::Empty,
+        2..=256 => ConstrainedForm::Fixed(1),
+        257..=65536 => ConstrainedForm::Fixed(2),
+        _ => ConstrainedForm::Indefinite,
+    })
+}
+
+/// Read a constrained whole number
+/// The offset from the minimum is encoded on one octet for a range
+/// up to 256, on two octets up to 65536, prefixed by its length otherwise
+///
+/// See X.691 10.5
+///
+/// # Example
+/// ```
+/// use std::io::Cursor;
+/// use rdp::core::per::read_constrained_whole_number;
+/// let mut s = Cursor::new([0x00, 0x02]);
+/// assert_eq!(read_constrained_whole_number(1001, 65535, &mut s).unwrap(), 1003);
+/// let mut s2 = Cursor::new([0x02, 0x01, 0x00]);
+/// assert_eq!(read_constrained_whole_number(0, u32::MAX as i64, &mut s2).unwrap(), 256);
+/// ```
+pub fn read_constrained_whole_number(minimum: i64, maximum: i64, s: &mut dyn Read) -> RdpResult<i64> {
+    let length = match constrained_form(minimum, maximum)? {
+        ConstrainedForm::Empty => return Ok(minimum),
+        ConstrainedForm::Fixed(length) => length,
+        ConstrainedForm::Indefinite => match read_length(s)? {
+            length @ 1..=8 => length as usize,
+            _ => {
+                return Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::InvalidSize,
+                    "PER integer encoded with an invalid size",
+                )))
+            }
+        },
+    };
+    let mut octets = vec![0; length];
+    s.read_exact(&mut octets)?;
+    let offset = octets.iter().fold(0_i128, |value, octet| value << 8 | i128::from(*octet));
+    let value = i128::from(minimum) + offset;
+    if value > i128::from(maximum) {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "PER integer out of range")));
+    }
+    Ok(value as i64)
+}
+
+/// Write a constrained whole number
+/// See `read_constrained_whole_number` for the encoding
+///
+/// # Example
+/// ```
+/// use std::io::Cursor;
+/// use rdp::core::per::write_constrained_whole_number;
+/// let mut s = Cursor::new(vec![]);
+/// write_constrained_whole_number(1003, 1001, 65535, &mut s).unwrap();
+/// assert_eq!(s.into_inner(), [0x00, 0x02]);
+/// let mut s2 = Cursor::new(vec![]);
+/// assert!(write_constrained_whole_number(3, 4, 5, &mut s2).is_err());
+/// ```
+pub fn write_constrained_whole_number(value: i64, minimum: i64, maximum: i64, s: &mut dyn Write) -> RdpResult<()> {
+    let form = constrained_form(minimum, maximum)?;
+    if value < minimum || value > maximum {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "PER integer out of range")));
+    }
+    let offset = (i128::from(value) - i128::from(minimum)) as u64;
+    let length = match form {
+        ConstrainedForm::Empty => return Ok(()),
+        ConstrainedForm::Fixed(length) => length,
+        ConstrainedForm::Indefinite => {
+            let length = (8 - offset.leading_zeros() as usize / 8).max(1);
+            write_length(length as u16)?.write(s)?;
+            length
+        }
+    };
+    offset.to_be_bytes()[8 - length..].to_vec().write(s)?;
+    Ok(())
+}
+
 /// Read an PER encoded integer
 /// Variable sized integer
 ///
@@ -185,28 +386,11 @@ pub fn write_enumerates(enumerate: u8) -> RdpResult<u8> { Ok(enumerate) }
 /// assert!(read_integer(&mut sinvalid).is_err())
 /// ```
 pub fn read_integer(s: &mut dyn Read) -> RdpResult<u32> {
-    let size = read_length(s)?;
-    match size {
-        1 => {
-            let mut result: u8 = 0;
-            result.read(s)?;
-            Ok(u32::from(result))
-        }
-        2 => {
-            let mut result = U16::BE(0);
-            result.read(s)?;
-            Ok(u32::from(result.inner()))
-        }
-        4 => {
-            let mut result = U32::BE(0);
-            result.read(s)?;
-            Ok(result.inner())
-        }
-        _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "PER integer encoded with an invalid size"))),
-    }
+    Ok(read_constrained_whole_number(0, i64::from(u32::MAX), s)? as u32)
 }
 
 /// Write an integer into PER format
+/// Using the minimal number of octets
 ///
 /// # Example
 /// ```
@@ -220,20 +404,10 @@ pub fn read_integer(s: &mut dyn Read) -> RdpResult<u32> {
 /// assert_eq!(su16.into_inner(), [0x2, 0x01, 0x00]);
 /// let mut su32 = Cursor::new(vec![]);
 /// write_integer(65536, &mut su32).unwrap();
-/// assert_eq!(su32.into_inner(), [0x4, 0x00, 0x01, 0x00, 0x00]);
+/// assert_eq!(su32.into_inner(), [0x3, 0x01, 0x00, 0x00]);
 /// ```
 pub fn write_integer(integer: u32, s: &mut dyn Write) -> RdpResult<()> {
-    if integer < 0xFF {
-        write_length(1)?.write(s)?;
-        (integer as u8).write(s)?;
-    } else if integer < 0xFFFF {
-        write_length(2)?.write(s)?;
-        U16::BE(integer as u16).write(s)?;
-    } else {
-        write_length(4)?.write(s)?;
-        U32::BE(integer).write(s)?;
-    };
-    Ok(())
+    write_constrained_whole_number(i64::from(integer), 0, i64::from(u32::MAX), s)
 }
 
 /// Read u16 integer PER encoded
@@ -244,11 +418,11 @@ pub fn write_integer(integer: u32, s: &mut dyn Write) -> RdpResult<()> {
 /// use rdp::core::per::read_integer_16;
 /// let mut s = Cursor::new([0x00, 0x01]);
 /// assert_eq!(read_integer_16(5, &mut s).unwrap(), 6);
+/// let mut s2 = Cursor::new([0xff, 0xff]);
+/// assert!(read_integer_16(5, &mut s2).is_err());
 /// ```
 pub fn read_integer_16(minimum: u16, s: &mut dyn Read) -> RdpResult<u16> {
-    let mut result = U16::BE(0);
-    result.read(s)?;
-    Ok(result.inner() + minimum)
+    Ok(read_constrained_whole_number(i64::from(minimum), i64::from(u16::MAX), s)? as u16)
 }
 
 /// This is a convenient method for PER encoding
@@ -262,11 +436,85 @@ pub fn read_integer_16(minimum: u16, s: &mut dyn Read) -> RdpResult<u16> {
 /// assert_eq!(s.into_inner(), [0x00, 0x02]);
 /// ```
 pub fn write_integer_16(integer: u16, minimum: u16, s: &mut dyn Write) -> RdpResult<()> {
-    U16::BE(integer - minimum).write(s)?;
-    Ok(())
+    write_constrained_whole_number(i64::from(integer), i64::from(minimum), i64::from(u16::MAX), s)
+}
+
+fn oid_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("PER: {}", message)))
+}
+
+/// Encode the arcs of an object identifier as BER contents
+/// The first two arcs are combined as `40 * arc0 + arc1`
+/// then each subidentifier is written in base 128
+///
+/// # Example
+/// ```
+/// use rdp::core::per::encode_object_identifier;
+/// assert_eq!(encode_object_identifier(&[0, 0, 20, 124, 0, 1]).unwrap(), [0, 20, 124, 0, 1]);
+/// assert_eq!(encode_object_identifier(&[1, 2, 840, 113549]).unwrap(), [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]);
+/// assert!(encode_object_identifier(&[1, 40]).is_err());
+/// ```
+pub fn encode_object_identifier(arcs: &[u32]) -> RdpResult<Vec<u8>> {
+    let (first, second, rest) = match arcs {
+        [first, second, rest @ ..] => (u64::from(*first), u64::from(*second), rest),
+        _ => return Err(oid_error("Object identifier needs two arcs")),
+    };
+    if first > 2 || (first < 2 && second >= 40) {
+        return Err(oid_error("Invalid first arcs of object identifier"));
+    }
+
+    let mut result = Vec::new();
+    for subidentifier in std::iter::once(40 * first + second).chain(rest.iter().map(|arc| u64::from(*arc))) {
+        let groups = (1..10).take_while(|i| subidentifier >> (7 * i) != 0).count();
+        for i in (1..=groups).rev() {
+            result.push(0x80 | (subidentifier >> (7 * i)) as u8 & 0x7f);
+        }
+        result.push(subidentifier as u8 & 0x7f);
+    }
+    Ok(result)
+}
+
+/// Decode the BER contents of an object identifier into its arcs
+///
+/// # Example
+/// ```
+/// use rdp::core::per::decode_object_identifier;
+/// assert_eq!(decode_object_identifier(&[0, 20, 124, 0, 1]).unwrap(), [0, 0, 20, 124, 0, 1]);
+/// assert_eq!(decode_object_identifier(&[0x88, 0x37]).unwrap(), [2, 999]);
+/// // truncated subidentifier
+/// assert!(decode_object_identifier(&[0, 0x86]).is_err());
+/// ```
+pub fn decode_object_identifier(data: &[u8]) -> RdpResult<Vec<u32>> {
+    let mut subidentifiers = Vec::new();
+    let mut value: u64 = 0;
+    let mut is_start = true;
+    for byte in data {
+        // Subidentifiers are encoded in the fewest bytes
+        if is_start && *byte == 0x80 {
+            return Err(oid_error("Non minimal object identifier encoding"));
+        }
+        if value >> 57 != 0 {
+            return Err(oid_error("Object identifier arc too large"));
+        }
+        value = (value << 7) | u64::from(byte & 0x7f);
+        is_start = byte & 0x80 == 0;
+        if is_start {
+            subidentifiers.push(value);
+            value = 0;
+        }
+    }
+    if !is_start || subidentifiers.is_empty() {
+        return Err(oid_error("Truncated object identifier"));
+    }
+
+    let first = subidentifiers[0].min(80) / 40;
+    let mut arcs = vec![first, subidentifiers[0] - 40 * first];
+    arcs.extend(&subidentifiers[1..]);
+    arcs.into_iter().map(|arc| u32::try_from(arc).map_err(|_| oid_error("Object identifier arc too large"))).collect()
 }
 
 /// Read an object identifier encoded in PER
+/// Return true if it is the expected one
 ///
 /// # Example
 /// ```
@@ -276,35 +524,19 @@ pub fn write_integer_16(integer: u16, minimum: u16, s: &mut dyn Write) -> RdpRes
 /// assert!(read_object_identifier(&[0, 0, 20, 124, 0, 1], &mut s1).unwrap());
 /// let mut s2 = Cursor::new([6, 0, 20, 124, 0, 1]);
 /// assert!(read_object_identifier(&[0, 0, 20, 124, 0, 1], &mut s2).is_err());
-/// let mut s3 = Cursor::new([5, 0x11, 20, 124, 0, 1]);
-/// assert!(read_object_identifier(&[1, 1, 20, 124, 0, 1], &mut s3).unwrap())
-/// ```
-pub fn read_object_identifier(oid: &[u8], s: &mut dyn Read) -> RdpResult<bool> {
-    if oid.len() != 6 {
-        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Oid to check have an invalid size")));
-    }
-
-    let length = read_length(s)?;
-    if length != 5 {
-        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Oid source have an invalid size")));
+/// let mut s3 = Cursor::new([5, 0x29, 20, 124, 0, 1]);
+/// assert!(read_object_identifier(&[1, 1, 20, 124, 0, 1], &mut s3).unwrap());
+/// let mut s4 = Cursor::new([5, 0, 20, 124, 0, 2]);
+/// assert!(!read_object_identifier(&[0, 0, 20, 124, 0, 1], &mut s4).unwrap())
+/// ```
+pub fn read_object_identifier(oid: &[u32], s: &mut dyn Read) -> RdpResult<bool> {
+    let length = read_length(s)? as usize;
+    let mut data = Vec::new();
+    s.take(length as u64).read_to_end(&mut data)?;
+    if data.len() != length {
+        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "PER: Truncated object identifier")));
     }
-
-    let mut oid_parsed = [0; 6];
-    let mut tmp: u8 = 0;
-
-    tmp.read(s)?;
-    oid_parsed[0] = tmp >> 4;
-    oid_parsed[1] = tmp & 0xf;
-    tmp.read(s)?;
-    oid_parsed[2] = tmp;
-    tmp.read(s)?;
-    oid_parsed[3] = tmp;
-    tmp.read(s)?;
-    oid_parsed[5] = tmp;
-    tmp.read(s)?;
-    oid_parsed[5] = tmp;
-
-    Ok(oid_parsed == oid)
+    Ok(decode_object_identifier(&data)? == oid)
 }
 
 /// Write an object identifier using PER encoder
@@ -315,17 +547,12 @@ pub fn read_object_identifier(oid: &[u8], s: &mut dyn Read) -> RdpResult<bool> {
 /// use rdp::core::per::write_object_identifier;
 /// let mut s = Cursor::new(vec![]);
 /// write_object_identifier(&[1, 2, 3, 4, 5, 6], &mut s).unwrap();
-/// assert_eq!(s.into_inner(), [5, 0x12, 3, 4, 5, 6]);
+/// assert_eq!(s.into_inner(), [5, 0x2a, 3, 4, 5, 6]);
 /// ```
-pub fn write_object_identifier(oid: &[u8], s: &mut dyn Write) -> RdpResult<()> {
-    if oid.len() != 6 {
-        return Err(Error::RdpError(RdpError::new(
-            RdpErrorKind::InvalidSize,
-            "PER: oid source don't have the correct size",
-        )));
-    }
-
-    trame![5_u8, oid[0] << 4 | oid[1] & 0xF, oid[2], oid[3], oid[4], oid[5]].write(s)
+pub fn write_object_identifier(oid: &[u32], s: &mut dyn Write) -> RdpResult<()> {
+    let data = encode_object_identifier(oid)?;
+    write_length(data.len() as u16)?.write(s)?;
+    Ok(s.write_all(&data)?)
 }
 
 /// Read a numeric string
@@ -420,3 +647,173 @@ pub fn write_octet_stream(octet_string: &[u8], minimum: usize, s: &mut dyn Write
     octet_string.to_vec().write(s)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn encode_length(length: u16) -> Vec<u8> {
+        let mut s = Cursor::new(vec![]);
+        write_length(length).unwrap().write(&mut s).unwrap();
+        s.into_inner()
+    }
+
+    fn encode_fragmented(payload: &[u8]) -> Vec<u8> {
+        let mut s = Cursor::new(vec![]);
+        write_fragmented(payload).write(&mut s).unwrap();
+        s.into_inner()
+    }
+
+    fn encode_constrained(value: i64, minimum: i64, maximum: i64) -> Vec<u8> {
+        let mut s = Cursor::new(vec![]);
+        write_constrained_whole_number(value, minimum, maximum, &mut s).unwrap();
+        s.into_inner()
+    }
+
+    fn check_constrained_roundtrip(value: i64, minimum: i64, maximum: i64) {
+        let encoded = encode_constrained(value, minimum, maximum);
+        let mut s = Cursor::new(&encoded);
+        assert_eq!(read_constrained_whole_number(minimum, maximum, &mut s).unwrap(), value);
+        assert_eq!(s.position() as usize, encoded.len());
+    }
+
+    /// Pseudo random values spread over the range
+    fn sample(minimum: i64, maximum: i64) -> impl Iterator<Item = i64> {
+        let span = (i128::from(maximum) - i128::from(minimum)) as u128 + 1;
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..1000).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (i128::from(minimum) + (u128::from(state) % span) as i128) as i64
+        })
+    }
+
+    #[test]
+    fn test_constrained_whole_number_sizes() {
+        assert!(encode_constrained(7, 7, 7).is_empty());
+        assert_eq!(encode_constrained(255, 0, 255), [0xff]);
+        assert_eq!(encode_constrained(-128, -128, 127), [0x00]);
+        assert_eq!(encode_constrained(256, 0, 256), [0x01, 0x00]);
+        assert_eq!(encode_constrained(65535, 0, 65535), [0xff, 0xff]);
+        assert_eq!(encode_constrained(0, 0, 65536), [0x01, 0x00]);
+        assert_eq!(encode_constrained(65536, 0, 65536), [0x03, 0x01, 0x00, 0x00]);
+        assert_eq!(
+            encode_constrained(i64::MAX, i64::MIN, i64::MAX),
+            [0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_constrained_whole_number_exhaustive() {
+        for (minimum, maximum) in [(0, 0), (0, 1), (-5, 250), (0, 255), (1001, 65535), (-32768, 32767), (0, 65535)] {
+            for value in minimum..=maximum {
+                check_constrained_roundtrip(value, minimum, maximum);
+            }
+        }
+    }
+
+    #[test]
+    fn test_constrained_whole_number_large_ranges() {
+        for (minimum, maximum) in [(0, 65536), (0, u32::MAX as i64), (-1, i64::MAX), (i64::MIN, i64::MAX)] {
+            check_constrained_roundtrip(minimum, minimum, maximum);
+            check_constrained_roundtrip(maximum, minimum, maximum);
+            for value in sample(minimum, maximum) {
+                check_constrained_roundtrip(value, minimum, maximum);
+            }
+        }
+    }
+
+    #[test]
+    fn test_constrained_whole_number_invalid() {
+        let mut s = Cursor::new(vec![]);
+        assert!(write_constrained_whole_number(0, 1, 0, &mut s).is_err());
+        assert!(write_constrained_whole_number(300, 0, 255, &mut s).is_err());
+        assert!(write_constrained_whole_number(-1, 0, 255, &mut s).is_err());
+        assert!(s.into_inner().is_empty());
+        assert!(read_constrained_whole_number(0, 1, &mut Cursor::new([0x02])).is_err());
+        assert!(read_constrained_whole_number(0, 65536, &mut Cursor::new([0x00])).is_err());
+        assert!(read_constrained_whole_number(0, 65536, &mut Cursor::new([0x09])).is_err());
+        assert!(read_constrained_whole_number(0, 65536, &mut Cursor::new([0x02, 0x01])).is_err());
+    }
+
+    #[test]
+    fn test_length_boundaries() {
+        for (length, encoded) in
+            [(0_u16, vec![0x00]), (127, vec![0x7f]), (128, vec![0x80, 0x80]), (16383, vec![0xbf, 0xff])]
+        {
+            assert_eq!(encode_length(length), encoded);
+            assert_eq!(read_length(&mut Cursor::new(encoded)).unwrap(), u32::from(length));
+        }
+        assert!(write_length(16384).is_err());
+    }
+
+    #[test]
+    fn test_long_length() {
+        // 16K and more are fragments
+        assert!(read_length(&mut Cursor::new([0xc1])).is_err());
+        assert!(read_length(&mut Cursor::new([0xc0, 0x00])).is_err());
+        // two octets form needs its second octet
+        assert!(read_length(&mut Cursor::new([0x81])).is_err());
+    }
+
+    #[test]
+    fn test_fragment_length() {
+        assert_eq!(read_part_length(&mut Cursor::new([0xc1])).unwrap(), PartLength::Fragment(16384));
+        assert_eq!(read_part_length(&mut Cursor::new([0xc4])).unwrap(), PartLength::Fragment(65536));
+        assert_eq!(read_part_length(&mut Cursor::new([0xbf, 0xff])).unwrap(), PartLength::Last(16383));
+        assert!(read_part_length(&mut Cursor::new([0xc0])).is_err());
+        assert!(read_part_length(&mut Cursor::new([0xc5])).is_err());
+    }
+
+    #[test]
+    fn test_fragmented_payload() {
+        // a multiple of 16K ends with an empty part
+        let payload = vec![0x55; 16384];
+        let encoded = encode_fragmented(&payload);
+        assert_eq!(encoded.len(), 16384 + 2);
+        assert_eq!((encoded[0], encoded[16385]), (0xc1, 0x00));
+        assert_eq!(read_fragmented(payload.len(), &mut Cursor::new(encoded)).unwrap(), payload);
+
+        // 64K fragment, 32K fragment then 200 bytes
+        let payload: Vec<u8> = (0..(65536 + 32768 + 200)).map(|i| i as u8).collect();
+        let encoded = encode_fragmented(&payload);
+        assert_eq!(encoded[0], 0xc4);
+        assert_eq!(encoded[65537], 0xc2);
+        assert_eq!(encoded[65538 + 32768..65538 + 32770], [0x80, 200]);
+        assert_eq!(read_fragmented(payload.len(), &mut Cursor::new(encoded.clone())).unwrap(), payload);
+        // the size is checked before the fragment is read
+        assert!(read_fragmented(65536, &mut Cursor::new(encoded)).is_err());
+
+        assert_eq!(encode_fragmented(&[0; 127])[0], 127);
+        assert_eq!(encode_fragmented(&[0; 128])[..2], [0x80, 0x80]);
+        assert_eq!(encode_fragmented(&[0; 16383])[..2], [0xbf, 0xff]);
+    }
+
+    #[test]
+    fn test_object_identifier_roundtrip() {
+        let oids: [&[u32]; 4] =
+            [&[0, 0, 20, 124, 0, 1], &[1, 3, 6, 1, 4, 1, 311], &[2, 999, 3], &[2, u32::MAX, u32::MAX]];
+        for oid in oids {
+            let encoded = encode_object_identifier(oid).unwrap();
+            assert_eq!(decode_object_identifier(&encoded).unwrap(), oid);
+            let mut s = Cursor::new(vec![]);
+            write_object_identifier(oid, &mut s).unwrap();
+            assert!(read_object_identifier(oid, &mut Cursor::new(s.into_inner())).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_object_identifier_invalid() {
+        assert!(encode_object_identifier(&[1]).is_err());
+        assert!(encode_object_identifier(&[3, 1]).is_err());
+        assert!(encode_object_identifier(&[0, 40, 1]).is_err());
+        assert!(decode_object_identifier(&[]).is_err());
+        assert!(decode_object_identifier(&[0x2a, 0x80, 0x01]).is_err());
+        // larger than 32 bits
+        assert!(decode_object_identifier(&[0x2a, 0x90, 0x80, 0x80, 0x80, 0x00]).is_err());
+        assert!(read_object_identifier(&[0, 0], &mut Cursor::new([3, 0, 1])).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::event::{RdpEvent, SystemPointer};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Message type of a slow path pointer update
+/// See MS-RDPBCGR 2.2.9.1.1.4
+#[repr(u16)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum PointerMessageType {
+    System = 0x0001,
+    Position = 0x0003,
+    Color = 0x0006,
+    Cached = 0x0007,
+    Pointer = 0x0008,
+}
+
+/// System pointer types
+/// See MS-RDPBCGR 2.2.9.1.1.4.3
+const SYSPTR_NULL: u32 = 0x0000_0000;
+const SYSPTR_DEFAULT: u32 = 0x0000_7f00;
+
+/// Largest pointer sent with the large pointer update
+/// See MS-RDPBCGR 2.2.9.1.2.1.11
+const MAX_POINTER_SIZE: u16 = 384;
+
+fn pointer_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("POINTER: {}", message)))
+}
+
+/// Read a mask of the pointer
+fn read_mask(s: &mut dyn Read, length: u32) -> RdpResult<Vec<u8>> {
+    let mut mask = vec![0; length as usize];
+    s.read_exact(&mut mask)?;
+    Ok(mask)
+}
+
+/// Convert the XOR and AND masks of a pointer to RGBA pixels
+///
+/// Both masks are bottom-up with lines padded to two bytes.
+/// Pixels of the AND mask show the screen: they are transparent
+/// with a black XOR pixel and drawn black instead of inverting the screen
+/// otherwise
+/// 32 bpp pointers carry their own alpha channel
+fn decode_masks(width: u16, height: u16, xor_bpp: u16, xor_mask: &[u8], and_mask: &[u8]) -> RdpResult<Vec<u8>> {
+    let (width, height) = (width as usize, height as usize);
+    let bytes_per_pixel = match xor_bpp {
+        24 => 3,
+        32 => 4,
+        _ => return Err(pointer_error(&format!("Unsupported XOR mask depth {}", xor_bpp))),
+    };
+    let xor_stride = (width * bytes_per_pixel).div_ceil(2) * 2;
+    let and_stride = width.div_ceil(16) * 2;
+    if xor_mask.len() < xor_stride * height {
+        return Err(pointer_error("XOR mask too small"));
+    }
+    // The AND mask can be omitted
+    let has_and_mask = !and_mask.is_empty();
+    if has_and_mask && and_mask.len() < and_stride * height {
+        return Err(pointer_error("AND mask too small"));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let xor = &xor_mask[y * xor_stride + x * bytes_per_pixel..][..bytes_per_pixel];
+            let and = has_and_mask && and_mask[y * and_stride + x / 8] & (0x80 >> (x % 8)) != 0;
+            let rgba = match (and, xor[..3] == [0, 0, 0]) {
+                (false, _) if bytes_per_pixel == 4 => [xor[2], xor[1], xor[0], xor[3]],
+                (false, _) => [xor[2], xor[1], xor[0], 0xff],
+                (true, true) => [0, 0, 0, 0],
+                (true, false) => [0, 0, 0, 0xff],
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+    Ok(pixels)
+}
+
+/// Pointer shape sent by the server
+/// Pixels are RGBA and top-down
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CursorShape {
+    pub hotspot_x: u16,
+    pub hotspot_y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u8>,
+}
+
+impl From<CursorShape> for RdpEvent {
+    fn from(shape: CursorShape) -> Self {
+        RdpEvent::PointerShape {
+            hotspot_x: shape.hotspot_x,
+            hotspot_y: shape.hotspot_y,
+            width: shape.width,
+            height: shape.height,
+            pixels: shape.pixels,
+        }
+    }
+}
+
+/// Read the cache index and the shape of a pointer
+/// Large pointers have 32 bits mask lengths
+///
+/// See MS-RDPBCGR 2.2.9.1.1.4.4 and 2.2.9.1.2.1.11
+fn read_shape(stream: &mut dyn Read, xor_bpp: u16, large: bool) -> RdpResult<(u16, CursorShape)> {
+    let cache_index = stream.read_u16::<LittleEndian>()?;
+    let hotspot_x = stream.read_u16::<LittleEndian>()?;
+    let hotspot_y = stream.read_u16::<LittleEndian>()?;
+    let width = stream.read_u16::<LittleEndian>()?;
+    let height = stream.read_u16::<LittleEndian>()?;
+    if width > MAX_POINTER_SIZE || height > MAX_POINTER_SIZE {
+        return Err(pointer_error(&format!("Pointer too large {}x{}", width, height)));
+    }
+    let (length_and_mask, length_xor_mask) = if large {
+        (stream.read_u32::<LittleEndian>()?, stream.read_u32::<LittleEndian>()?)
+    } else {
+        (u32::from(stream.read_u16::<LittleEndian>()?), u32::from(stream.read_u16::<LittleEndian>()?))
+    };
+    let xor_mask = read_mask(stream, length_xor_mask)?;
+    let and_mask = read_mask(stream, length_and_mask)?;
+    Ok((
+        cache_index,
+        CursorShape {
+            hotspot_x,
+            hotspot_y,
+            width,
+            height,
+            pixels: decode_masks(width, height, xor_bpp, &xor_mask, &and_mask)?,
+        },
+    ))
+}
+
+/// Read a color pointer, a 24 bpp shape
+///
+/// See MS-RDPBCGR 2.2.9.1.1.4.4
+///
+/// # Example
+/// ```
+/// use rdp::core::event::RdpEvent;
+/// use rdp::core::pointer::read_color_pointer;
+/// // a single red pixel without AND mask
+/// let data = [0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 4, 0, 0, 0, 0xff, 0];
+/// match read_color_pointer(&data).unwrap() {
+///     RdpEvent::PointerShape { pixels, .. } => assert_eq!(pixels, [0xff, 0, 0, 0xff]),
+///     _ => panic!("expected a pointer shape"),
+/// }
+/// ```
+pub fn read_color_pointer(data: &[u8]) -> RdpResult<RdpEvent> {
+    let (_, shape) = read_shape(&mut Cursor::new(data), 24, false)?;
+    Ok(shape.into())
+}
+
+/// Read a system pointer update
+///
+/// See MS-RDPBCGR 2.2.9.1.1.4.3
+pub fn read_system_pointer(data: &[u8]) -> RdpResult<RdpEvent> {
+    match Cursor::new(data).read_u32::<LittleEndian>()? {
+        SYSPTR_NULL => Ok(RdpEvent::SystemPointer(SystemPointer::Hidden)),
+        SYSPTR_DEFAULT => Ok(RdpEvent::SystemPointer(SystemPointer::Default)),
+        pointer_type => Err(pointer_error(&format!("Unknown system pointer {:#x}", pointer_type))),
+    }
+}
+
+/// Read a pointer position update
+///
+/// See MS-RDPBCGR 2.2.9.1.1.4.2
+///
+/// # Example
+/// ```
+/// use rdp::core::event::RdpEvent;
+/// use rdp::core::pointer::read_pointer_position;
+/// assert_eq!(read_pointer_position(&[10, 0, 20, 0]).unwrap(), RdpEvent::PointerPosition { x: 10, y: 20 });
+/// ```
+pub fn read_pointer_position(data: &[u8]) -> RdpResult<RdpEvent> {
+    let mut stream = Cursor::new(data);
+    let x = stream.read_u16::<LittleEndian>()?;
+    let y = stream.read_u16::<LittleEndian>()?;
+    Ok(RdpEvent::PointerPosition { x, y })
+}
+
+/// Pointer shapes sent by the server
+/// Cached pointers recall a shape by its cache index
+///
+/// See MS-RDPBCGR 3.2.5.9.2.4
+#[derive(Debug, Default)]
+pub struct PointerCache {
+    shapes: HashMap<u16, CursorShape>,
+}
+
+impl PointerCache {
+    pub fn new() -> Self { Self::default() }
+
+    /// Remember the shape and build its event
+    fn insert(&mut self, cache_index: u16, shape: CursorShape) -> RdpEvent {
+        self.shapes.insert(cache_index, shape.clone());
+        shape.into()
+    }
+
+    /// Read a color pointer, a 24 bpp shape
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.1.4.4
+    pub fn read_color_pointer(&mut self, data: &[u8]) -> RdpResult<RdpEvent> {
+        let (cache_index, shape) = read_shape(&mut Cursor::new(data), 24, false)?;
+        Ok(self.insert(cache_index, shape))
+    }
+
+    /// Read a new pointer, a shape of any depth up to 96x96 pixels
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.1.4.5
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::event::RdpEvent;
+    /// use rdp::core::pointer::PointerCache;
+    /// let mut cache = PointerCache::new();
+    /// // a single half transparent blue pixel in slot 3
+    /// let data = [32, 0, 3, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 4, 0, 0xff, 0, 0, 0x80];
+    /// let shape = cache.read_new_pointer(&data).unwrap();
+    /// match &shape {
+    ///     RdpEvent::PointerShape { pixels, .. } => assert_eq!(pixels, &[0, 0, 0xff, 0x80]),
+    ///     _ => panic!("expected a pointer shape"),
+    /// }
+    /// assert_eq!(cache.read_cached_pointer(&[3, 0]).unwrap(), shape);
+    /// ```
+    pub fn read_new_pointer(&mut self, data: &[u8]) -> RdpResult<RdpEvent> {
+        let mut stream = Cursor::new(data);
+        let xor_bpp = stream.read_u16::<LittleEndian>()?;
+        let (cache_index, shape) = read_shape(&mut stream, xor_bpp, false)?;
+        Ok(self.insert(cache_index, shape))
+    }
+
+    /// Read a large pointer, up to 384x384 pixels
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.2.1.11
+    pub fn read_large_pointer(&mut self, data: &[u8]) -> RdpResult<RdpEvent> {
+        let mut stream = Cursor::new(data);
+        let xor_bpp = stream.read_u16::<LittleEndian>()?;
+        let (cache_index, shape) = read_shape(&mut stream, xor_bpp, true)?;
+        Ok(self.insert(cache_index, shape))
+    }
+
+    /// Recall a pointer shape sent before
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.1.4.6
+    pub fn read_cached_pointer(&self, data: &[u8]) -> RdpResult<RdpEvent> {
+        let cache_index = Cursor::new(data).read_u16::<LittleEndian>()?;
+        match self.shapes.get(&cache_index) {
+            Some(shape) => Ok(shape.clone().into()),
+            None => Err(pointer_error(&format!("No pointer in cache entry {}", cache_index))),
+        }
+    }
+
+    /// Read a slow path pointer update
+    ///
+    /// See MS-RDPBCGR 2.2.9.1.1.4
+    pub fn read_pointer_pdu(&mut self, data: &[u8]) -> RdpResult<RdpEvent> {
+        let mut stream = Cursor::new(data);
+        let message_type = stream.read_u16::<LittleEndian>()?;
+        let _pad = stream.read_u16::<LittleEndian>()?;
+        let attribute = &data[stream.position() as usize..];
+        match PointerMessageType::try_from(message_type)
+            .map_err(|_| pointer_error(&format!("Unknown pointer message {:#x}", message_type)))?
+        {
+            PointerMessageType::System => read_system_pointer(attribute),
+            PointerMessageType::Position => read_pointer_position(attribute),
+            PointerMessageType::Color => self.read_color_pointer(attribute),
+            PointerMessageType::Cached => self.read_cached_pointer(attribute),
+            PointerMessageType::Pointer => self.read_new_pointer(attribute),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_masks_transparency() {
+        // 2x1 pointer, a white opaque pixel and a transparent one
+        let xor_mask = [0xff, 0xff, 0xff, 0, 0, 0];
+        let and_mask = [0x40, 0];
+        assert_eq!(decode_masks(2, 1, 24, &xor_mask, &and_mask).unwrap(), [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_masks_bottom_up() {
+        // 1x2 pointer with a red bottom line and a blue top line padded to 4 bytes
+        let xor_mask = [0, 0, 0xff, 0, 0xff, 0, 0, 0];
+        assert_eq!(decode_masks(1, 2, 24, &xor_mask, &[]).unwrap(), [0, 0, 0xff, 0xff, 0xff, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn test_decode_masks_too_small() {
+        assert!(decode_masks(2, 2, 24, &[0; 6], &[]).is_err());
+    }
+
+    #[test]
+    fn test_decode_masks_alpha() {
+        // 32 bpp pointer keeps its alpha channel
+        let xor_mask = [0x10, 0x20, 0x30, 0x40];
+        assert_eq!(decode_masks(1, 1, 32, &xor_mask, &[0, 0]).unwrap(), [0x30, 0x20, 0x10, 0x40]);
+    }
+
+    #[test]
+    fn test_read_pointer_pdu_system() {
+        let mut cache = PointerCache::new();
+        let data = [0x01, 0x00, 0x00, 0x00, 0x00, 0x7f, 0x00, 0x00];
+        assert_eq!(cache.read_pointer_pdu(&data).unwrap(), RdpEvent::SystemPointer(SystemPointer::Default));
+        let data = [0x03, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x14, 0x00];
+        assert_eq!(cache.read_pointer_pdu(&data).unwrap(), RdpEvent::PointerPosition { x: 10, y: 20 });
+        // nothing cached yet
+        let data = [0x07, 0x00, 0x00, 0x00, 0x01, 0x00];
+        assert!(cache.read_pointer_pdu(&data).is_err());
+    }
+
+    #[test]
+    fn test_read_pointer_pdu_cached() {
+        let mut cache = PointerCache::new();
+        // new pointer in slot 1, a 2x1 opaque white and transparent black pointer
+        let data = [
+            0x08, 0x00, 0x00, 0x00, 32, 0, 1, 0, 1, 0, 0, 0, 2, 0, 1, 0, 0, 0, 8, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        let shape = RdpEvent::PointerShape {
+            hotspot_x: 1,
+            hotspot_y: 0,
+            width: 2,
+            height: 1,
+            pixels: vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0],
+        };
+        assert_eq!(cache.read_pointer_pdu(&data).unwrap(), shape);
+        assert_eq!(cache.read_pointer_pdu(&[0x07, 0x00, 0x00, 0x00, 0x01, 0x00]).unwrap(), shape);
+    }
+
+    #[test]
+    fn test_read_large_pointer() {
+        let mut cache = PointerCache::new();
+        // 1x1 pointer with 32 bits mask lengths
+        let data = [32, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 2, 0, 0, 0, 4, 0, 0, 0, 1, 2, 3, 4, 0, 0];
+        match cache.read_large_pointer(&data).unwrap() {
+            RdpEvent::PointerShape { pixels, .. } => assert_eq!(pixels, [3, 2, 1, 4]),
+            event => panic!("expected a pointer shape {:?}", event),
+        }
+        // larger than 384x384
+        let data = [32, 0, 0, 0, 0, 0, 0, 0, 0x81, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(cache.read_large_pointer(&data).is_err());
+    }
+}
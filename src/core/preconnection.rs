@@ -0,0 +1,55 @@
+use crate::model::data::{Component, Message, U16, U32};
+use crate::model::unicode::Unicode;
+
+/// Version of the preconnection PDU carrying a blob
+const TS_PRECONNECTION_PDU_V2: u32 = 0x0000_0002;
+
+/// Preconnection PDU
+/// Sent before the X.224 connection request
+/// to select the target of Hyper-V and Azure hosts
+///
+/// See MS-RDPEPS 2.2.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::preconnection::preconnection_pdu;
+/// use rdp::model::data::to_vec;
+/// assert_eq!(
+///     to_vec(&preconnection_pdu(0, "vm")),
+///     [24, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3, 0, b'v', 0, b'm', 0, 0, 0]
+/// );
+/// ```
+pub fn preconnection_pdu(id: u32, blob: &str) -> Component {
+    let mut pcb = blob.to_utf16_le();
+    pcb.extend_from_slice(&[0, 0]);
+    let body = component![
+        "flags" => U32::LE(0),
+        "version" => U32::LE(TS_PRECONNECTION_PDU_V2),
+        "id" => U32::LE(id),
+        "cchPCB" => U16::LE((pcb.len() / 2) as u16),
+        "wszPCB" => pcb
+    ];
+    component![
+        "cbSize" => U32::LE(body.length() as u32 + 4),
+        "body" => body
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    #[test]
+    fn test_preconnection_pdu() {
+        let pdu = to_vec(&preconnection_pdu(0x1234, "é"));
+        // cbSize
+        assert_eq!(pdu[0..4], [22, 0, 0, 0]);
+        // Id
+        assert_eq!(pdu[12..16], [0x34, 0x12, 0, 0]);
+        // cchPCB count the null terminator
+        assert_eq!(pdu[16..18], [2, 0]);
+        // wszPCB
+        assert_eq!(pdu[18..], [0xe9, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::channel::StaticChannel;
+use crate::core::event::Rect;
+use crate::core::license::read_unicode;
+use crate::core::{mcs, tpkt};
+use crate::model::data::{to_vec, Component, Message, Trame, U16, U32};
+use crate::model::error::RdpResult;
+use crate::model::unicode::Unicode;
+
+/// Name of the RemoteApp static virtual channel
+pub const RAIL_CHANNEL_NAME: &str = "rail";
+
+/// Build number announced in the handshake
+const CLIENT_BUILD_NUMBER: u32 = 7601;
+
+/// RemoteApp PDU type
+/// See MS-RDPERP 2.2.2.1
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum OrderType {
+    Exec = 0x0001,
+    Activate = 0x0002,
+    Sysparam = 0x0003,
+    Syscommand = 0x0004,
+    Handshake = 0x0005,
+    NotifyEvent = 0x0006,
+    WindowMove = 0x0008,
+    LocalMoveSize = 0x0009,
+    MinMaxInfo = 0x000a,
+    ClientStatus = 0x000b,
+    SysMenu = 0x000c,
+    LangBarInfo = 0x000d,
+    GetAppIdReq = 0x000e,
+    GetAppIdResp = 0x000f,
+    ExecResult = 0x0080,
+}
+
+/// System parameters sent by the client
+/// See MS-RDPERP 2.2.2.4.1
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+enum SystemParam {
+    DragFullWindows = 0x0025,
+    KeyboardCues = 0x100b,
+    KeyboardPref = 0x0045,
+    MouseButtonSwap = 0x0021,
+    WorkArea = 0x002f,
+}
+
+/// Application started in the RemoteApp session
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RemoteAppConfig {
+    /// Program or file to open, `||alias` for a published application
+    pub exe_path: String,
+    pub working_dir: String,
+    pub arguments: String,
+}
+
+/// Header of all RemoteApp PDU
+/// The length includes the header
+fn rail_pdu(order_type: OrderType, body: &dyn Message) -> Vec<u8> {
+    let mut pdu = to_vec(&trame![U16::LE(order_type as u16), U16::LE(body.length() as u16 + 4)]);
+    pdu.extend(to_vec(body));
+    pdu
+}
+
+/// Launch the application of the configuration
+/// Strings are not null terminated
+///
+/// See MS-RDPERP 2.2.2.3.1
+fn exec_pdu(config: &RemoteAppConfig) -> Vec<u8> {
+    let exe_path = config.exe_path.to_utf16_le();
+    let working_dir = config.working_dir.to_utf16_le();
+    let arguments = config.arguments.to_utf16_le();
+    rail_pdu(
+        OrderType::Exec,
+        &component![
+            "Flags" => U16::LE(0),
+            "ExeOrFileLength" => U16::LE(exe_path.len() as u16),
+            "WorkingDirLength" => U16::LE(working_dir.len() as u16),
+            "ArgumentsLen" => U16::LE(arguments.len() as u16),
+            "ExeOrFile" => exe_path,
+            "WorkingDir" => working_dir,
+            "Arguments" => arguments
+        ],
+    )
+}
+
+/// Client system parameter
+/// Most values are a single boolean byte
+fn sysparam_pdu(param: SystemParam, value: Vec<u8>) -> Vec<u8> {
+    rail_pdu(OrderType::Sysparam, &component!["SystemParam" => U32::LE(param as u32), "Body" => value])
+}
+
+/// Ask the server to move a window
+///
+/// # Example
+/// ```
+/// use rdp::core::event::Rect;
+/// use rdp::core::rail::window_move_pdu;
+/// let pdu = window_move_pdu(2, Rect { left: 1, top: 2, right: 3, bottom: 4 });
+/// assert_eq!(pdu, [8, 0, 16, 0, 2, 0, 0, 0, 1, 0, 2, 0, 3, 0, 4, 0]);
+/// ```
+pub fn window_move_pdu(window_id: u32, rect: Rect) -> Vec<u8> {
+    rail_pdu(
+        OrderType::WindowMove,
+        &component![
+            "WindowId" => U32::LE(window_id),
+            "Left" => U16::LE(rect.left),
+            "Top" => U16::LE(rect.top),
+            "Right" => U16::LE(rect.right),
+            "Bottom" => U16::LE(rect.bottom)
+        ],
+    )
+}
+
+/// RemoteApp virtual channel (MS-RDPERP)
+///
+/// The application is launched once the server handshake is received,
+/// windows are described by the window orders of the global channel
+#[derive(Debug)]
+pub struct RailChannel {
+    /// Underlying static virtual channel
+    channel: StaticChannel,
+    config: RemoteAppConfig,
+    width: u16,
+    height: u16,
+}
+
+impl RailChannel {
+    pub fn new(config: RemoteAppConfig, width: u16, height: u16) -> Self {
+        RailChannel { channel: StaticChannel::new(RAIL_CHANNEL_NAME), config, width, height }
+    }
+
+    /// Read a PDU from the rail channel
+    /// and send back the responses
+    pub fn read<S: Read + Write>(&mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        if let Some(message) = self.channel.read(payload)? {
+            for response in self.process(&message)? {
+                self.channel.write(&response, mcs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the server to move a window
+    pub fn move_window<S: Read + Write>(&self, window_id: u32, rect: Rect, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        self.channel.write(&window_move_pdu(window_id, rect), mcs)
+    }
+
+    /// Process a complete PDU
+    /// Return the PDU to send back to the server
+    fn process(&mut self, message: &[u8]) -> RdpResult<Vec<Vec<u8>>> {
+        let mut stream = Cursor::new(message);
+        let order_type = stream.read_u16::<LittleEndian>()?;
+        let _order_length = stream.read_u16::<LittleEndian>()?;
+
+        match OrderType::try_from(order_type) {
+            // The client answers with its parameters then starts the application
+            Ok(OrderType::Handshake) => {
+                let _build_number = stream.read_u32::<LittleEndian>()?;
+                let work_area = to_vec(&trame![U16::LE(0), U16::LE(0), U16::LE(self.width), U16::LE(self.height)]);
+                Ok(vec![
+                    rail_pdu(OrderType::Handshake, &U32::LE(CLIENT_BUILD_NUMBER)),
+                    rail_pdu(OrderType::ClientStatus, &U32::LE(0)),
+                    sysparam_pdu(SystemParam::DragFullWindows, vec![0]),
+                    sysparam_pdu(SystemParam::KeyboardCues, vec![0]),
+                    sysparam_pdu(SystemParam::KeyboardPref, vec![0]),
+                    sysparam_pdu(SystemParam::MouseButtonSwap, vec![0]),
+                    sysparam_pdu(SystemParam::WorkArea, work_area),
+                    exec_pdu(&self.config),
+                ])
+            }
+            Ok(OrderType::ExecResult) => {
+                let _flags = stream.read_u16::<LittleEndian>()?;
+                let result = stream.read_u16::<LittleEndian>()?;
+                let raw_result = stream.read_u32::<LittleEndian>()?;
+                let _padding = stream.read_u16::<LittleEndian>()?;
+                let mut exe_path = vec![0; usize::from(stream.read_u16::<LittleEndian>()?)];
+                stream.read_exact(&mut exe_path)?;
+                if result != 0 {
+                    log_warn!(
+                        "RAIL: Unable to start {} error {:#x} ({:#x})",
+                        read_unicode(&exe_path),
+                        result,
+                        raw_result
+                    );
+                }
+                Ok(Vec::new())
+            }
+            _ => {
+                log_debug!("RAIL: order not handled {:#x}", order_type);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handshake_starts_application() {
+        let config =
+            RemoteAppConfig { exe_path: "||calc".to_string(), working_dir: String::new(), arguments: "-a".to_string() };
+        let mut channel = RailChannel::new(config, 1024, 768);
+        let responses = channel.process(&[5, 0, 8, 0, 0xb1, 0x1d, 0, 0]).unwrap();
+        assert_eq!(responses.len(), 8);
+        assert_eq!(responses[0], [5, 0, 8, 0, 0xb1, 0x1d, 0, 0]);
+        assert_eq!(responses[6], [3, 0, 16, 0, 0x2f, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 3]);
+        assert_eq!(
+            responses[7],
+            [
+                1, 0, 28, 0, 0, 0, 12, 0, 0, 0, 4, 0, b'|', 0, b'|', 0, b'c', 0, b'a', 0, b'l', 0, b'c', 0, b'-', 0,
+                b'a', 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exec_result() {
+        let mut channel = RailChannel::new(RemoteAppConfig::default(), 800, 600);
+        let result = [0x80, 0, 20, 0, 0, 0, 3, 0, 2, 0, 0, 0, 0, 0, 4, 0, b'a', 0, b'b', 0];
+        assert!(channel.process(&result).unwrap().is_empty());
+        assert!(channel.process(&result[..10]).is_err());
+    }
+}
@@ -0,0 +1,949 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+
+use num_enum::TryFromPrimitive;
+
+use crate::core::channel::StaticChannel;
+use crate::core::{mcs, tpkt};
+use crate::model::data::{to_vec, Component, DataType, Message, Trame, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::unicode::Unicode;
+
+/// Name of the device redirection static virtual channel
+pub const RDPDR_CHANNEL_NAME: &str = "rdpdr";
+
+/// Name of the redirected drive as seen by the server
+/// Files are available under \\tsclient\RDPRS
+pub const DRIVE_NAME: &str = "RDPRS";
+
+/// Id of the redirected drive
+const DRIVE_DEVICE_ID: u32 = 1;
+
+/// Id of the first redirected printer
+/// Printers are numbered after the drive
+const FIRST_PRINTER_DEVICE_ID: u32 = 2;
+
+/// Device types
+/// See MS-RDPEFS 2.2.1.3
+const RDPDR_DTYP_PRINT: u32 = 0x0000_0004;
+const RDPDR_DTYP_FILESYSTEM: u32 = 0x0000_0008;
+
+/// The printer is the default printer of the session
+/// See MS-RDPEPC 2.2.2.1
+const RDPDR_PRINTER_ANNOUNCE_FLAG_DEFAULTPRINTER: u32 = 0x0000_0002;
+
+/// Component of the core PDU
+const RDPDR_CTYP_CORE: u16 = 0x4472;
+
+/// Type of device redirection PDU
+/// See MS-RDPEFS 2.2.1.1
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum PacketId {
+    ServerAnnounce = 0x496E,
+    ClientIdConfirm = 0x4343,
+    ClientName = 0x434E,
+    DeviceListAnnounce = 0x4441,
+    DeviceReply = 0x6472,
+    DeviceIoRequest = 0x4952,
+    DeviceIoCompletion = 0x4943,
+    ServerCapability = 0x5350,
+    ClientCapability = 0x4350,
+    DeviceListRemove = 0x444D,
+    PrinterCacheData = 0x5043,
+    UserLoggedOn = 0x554C,
+    PrinterUsingXps = 0x5543,
+}
+
+/// IRP major functions
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum MajorFunction {
+    Create = 0x0000_0000,
+    Close = 0x0000_0002,
+    Read = 0x0000_0003,
+    Write = 0x0000_0004,
+    QueryInformation = 0x0000_0005,
+    SetInformation = 0x0000_0006,
+    QueryVolumeInformation = 0x0000_000A,
+    SetVolumeInformation = 0x0000_000B,
+    DirectoryControl = 0x0000_000C,
+    DeviceControl = 0x0000_000E,
+    LockControl = 0x0000_0011,
+}
+
+/// NTSTATUS codes returned to the server
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum NtStatus {
+    Success = 0x0000_0000,
+    Unsuccessful = 0xC000_0001,
+    InvalidParameter = 0xC000_000D,
+    NoSuchFile = 0xC000_000F,
+    ObjectNameNotFound = 0xC000_0034,
+    ObjectNameCollision = 0xC000_0035,
+    AccessDenied = 0xC000_0022,
+    DiskFull = 0xC000_007F,
+    NotSupported = 0xC000_00BB,
+}
+
+/// Disposition of a create request
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum CreateDisposition {
+    Supersede = 0,
+    Open = 1,
+    Create = 2,
+    OpenIf = 3,
+    Overwrite = 4,
+    OverwriteIf = 5,
+}
+
+/// Information returned by a create response
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum CreateInformation {
+    Superseded = 0,
+    Opened = 1,
+    Created = 2,
+    Overwritten = 3,
+}
+
+/// File information classes
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum FileInformationClass {
+    Basic = 4,
+    Standard = 5,
+    AttributeTag = 35,
+}
+
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x0000_0010;
+const FILE_ATTRIBUTE_NORMAL: u32 = 0x0000_0080;
+
+/// Maximum size of a file kept in memory by the drive
+const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Maximum size of all the files written by the server
+const MAX_DRIVE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Maximum number of files on the drive
+const MAX_FILES: usize = 1024;
+
+/// Access of the remote session to the redirected drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriveAccess {
+    /// The session can only open the files written by the client
+    ReadOnly,
+    /// The session can also create and write files
+    ReadWrite,
+}
+
+/// A printer redirected to the session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrinterInfo {
+    /// Name of the printer in the session
+    pub name: String,
+    /// Name of the driver installed by the server
+    pub driver: String,
+    /// Default printer of the session
+    pub is_default: bool,
+}
+
+/// Null terminated UTF-16LE string
+fn unicode_string(value: &str) -> Vec<u8> {
+    let mut result = value.to_utf16_le();
+    result.extend_from_slice(&[0, 0]);
+    result
+}
+
+/// Header of all device redirection PDU
+fn rdpdr_header(packet_id: Option<PacketId>, data: Option<Vec<u8>>) -> Component {
+    component![
+        "component" => U16::LE(RDPDR_CTYP_CORE),
+        "packetId" => U16::LE(packet_id.unwrap_or(PacketId::ServerAnnounce) as u16),
+        "data" => data.unwrap_or_default()
+    ]
+}
+
+/// Reply to the server announce
+fn client_announce_reply(client_id: u32) -> Component {
+    component![
+        "versionMajor" => U16::LE(1),
+        "versionMinor" => U16::LE(0x000C),
+        "clientId" => U32::LE(client_id)
+    ]
+}
+
+/// Computer name of the client
+fn client_name_request(name: &str) -> Component {
+    let computer_name = unicode_string(name);
+    component![
+        "unicodeFlag" => U32::LE(1),
+        "codePage" => U32::LE(0),
+        "computerNameLen" => U32::LE(computer_name.len() as u32),
+        "computerName" => computer_name
+    ]
+}
+
+/// Capabilities supported by the client
+/// General, printer and drive capabilities
+fn client_core_capability() -> Trame {
+    trame![
+        U16::LE(3),
+        U16::LE(0),
+        component![
+            "capabilityType" => U16::LE(1),
+            "capabilityLength" => U16::LE(44),
+            "version" => U32::LE(2),
+            "osType" => U32::LE(0),
+            "osVersion" => U32::LE(0),
+            "protocolMajorVersion" => U16::LE(1),
+            "protocolMinorVersion" => U16::LE(0x000C),
+            "ioCode1" => U32::LE(0xFFFF),
+            "ioCode2" => U32::LE(0),
+            "extendedPdu" => U32::LE(0x7),
+            "extraFlags1" => U32::LE(0),
+            "extraFlags2" => U32::LE(0),
+            "specialTypeDeviceCap" => U32::LE(0)
+        ],
+        component![
+            "capabilityType" => U16::LE(2),
+            "capabilityLength" => U16::LE(8),
+            "version" => U32::LE(1)
+        ],
+        component![
+            "capabilityType" => U16::LE(4),
+            "capabilityLength" => U16::LE(8),
+            "version" => U32::LE(2)
+        ]
+    ]
+}
+
+/// Announce of a single device
+/// See MS-RDPEFS 2.2.1.3
+fn device_announce(device_type: u32, device_id: u32, dos_name: &str, device_data: Vec<u8>) -> Component {
+    let mut dos_name = dos_name.as_bytes().to_vec();
+    dos_name.resize(8, 0);
+    component![
+        "deviceType" => U32::LE(device_type),
+        "deviceId" => U32::LE(device_id),
+        "preferredDosName" => dos_name,
+        "deviceDataLength" => U32::LE(device_data.len() as u32),
+        "deviceData" => device_data
+    ]
+}
+
+/// Device data of a printer announce
+/// See MS-RDPEPC 2.2.2.1
+fn printer_device_data(printer: &PrinterInfo) -> Vec<u8> {
+    let driver_name = unicode_string(&printer.driver);
+    let print_name = unicode_string(&printer.name);
+    let flags = if printer.is_default { RDPDR_PRINTER_ANNOUNCE_FLAG_DEFAULTPRINTER } else { 0 };
+    to_vec(&component![
+        "flags" => U32::LE(flags),
+        "codePage" => U32::LE(0),
+        "pnpNameLen" => U32::LE(0),
+        "driverNameLen" => U32::LE(driver_name.len() as u32),
+        "printNameLen" => U32::LE(print_name.len() as u32),
+        "cachedFieldsLen" => U32::LE(0),
+        "driverName" => driver_name,
+        "printName" => print_name
+    ])
+}
+
+/// Announce a list of devices
+fn device_list_announce(devices: Vec<Component>) -> Trame {
+    let mut announce = trame![U32::LE(devices.len() as u32)];
+    for device in devices {
+        announce.push(Box::new(device));
+    }
+    announce
+}
+
+/// Header of an IRP send by the server
+fn device_io_request() -> Component {
+    component![
+        "deviceId" => U32::LE(0),
+        "fileId" => U32::LE(0),
+        "completionId" => U32::LE(0),
+        "majorFunction" => U32::LE(0),
+        "minorFunction" => U32::LE(0)
+    ]
+}
+
+/// Header of the IRP completion
+fn device_io_response(device_id: u32, completion_id: u32, status: NtStatus, data: Vec<u8>) -> Component {
+    component![
+        "deviceId" => U32::LE(device_id),
+        "completionId" => U32::LE(completion_id),
+        "ioStatus" => U32::LE(status as u32),
+        "data" => data
+    ]
+}
+
+/// Parameters of IRP_MJ_CREATE
+fn create_request() -> Component {
+    component![
+        "desiredAccess" => U32::LE(0),
+        "allocationSize" => vec![0_u8; 8],
+        "fileAttributes" => U32::LE(0),
+        "sharedAccess" => U32::LE(0),
+        "createDisposition" => U32::LE(0),
+        "createOptions" => U32::LE(0),
+        "pathLength" => U32::LE(0)
+    ]
+}
+
+/// Parameters of IRP_MJ_READ and IRP_MJ_WRITE
+fn read_write_request() -> Component {
+    component![
+        "length" => U32::LE(0),
+        "offset" => vec![0_u8; 8],
+        "padding" => vec![0_u8; 20]
+    ]
+}
+
+/// Parameters of IRP_MJ_QUERY_INFORMATION
+fn query_information_request() -> Component {
+    component![
+        "fsInformationClass" => U32::LE(0),
+        "length" => U32::LE(0),
+        "padding" => vec![0_u8; 24]
+    ]
+}
+
+/// Normalize a path of the redirected drive
+/// Windows paths are case insensitive
+fn normalize_path(path: &str) -> String { path.replace('/', "\\").trim_matches('\\').to_lowercase() }
+
+/// Drive redirection channel
+///
+/// Expose an in memory drive to the server under \\tsclient\RDPRS
+/// once enabled with `drive`. Files written by the remote session
+/// can be read by the client and files written by the client
+/// can be read by the remote session
+///
+/// Directory listing is not supported, files must be accessed by path
+///
+/// Printers can be announced on the same channel,
+/// print jobs are not processed
+#[derive(Debug)]
+pub struct DriveRedirector {
+    /// Underlying static virtual channel
+    channel: StaticChannel,
+    /// Computer name send to the server
+    client_name: String,
+    /// Access of the server to the drive, None doesn't announce it
+    access: Option<DriveAccess>,
+    /// Content of the drive by normalized path
+    files: HashMap<String, Vec<u8>>,
+    /// Path of the files opened by the server
+    handles: HashMap<u32, String>,
+    /// Next file id given to the server
+    next_file_id: u32,
+    /// Printers announced to the server
+    printers: Vec<PrinterInfo>,
+    /// Devices are announced once the user is logged on
+    logged_on: bool,
+}
+
+impl DriveRedirector {
+    /// Create a new redirector without drive
+    /// Only the printers are announced
+    pub fn new(client_name: &str) -> Self {
+        DriveRedirector {
+            channel: StaticChannel::new(RDPDR_CHANNEL_NAME),
+            client_name: client_name.to_string(),
+            access: None,
+            files: HashMap::new(),
+            handles: HashMap::new(),
+            next_file_id: 1,
+            printers: Vec::new(),
+            logged_on: false,
+        }
+    }
+
+    /// Announce an empty drive to the server
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::rdpdr::{DriveAccess, DriveRedirector};
+    /// let drive = DriveRedirector::new("rdp-rs").drive(DriveAccess::ReadOnly);
+    /// ```
+    pub fn drive(mut self, access: DriveAccess) -> Self {
+        self.access = Some(access);
+        self
+    }
+
+    /// Announce printers to the server
+    /// They are sent with the drive once the user is logged on
+    /// or immediately after
+    ///
+    /// Print jobs send by the server are refused
+    pub fn announce_printers<S: Read + Write>(
+        &mut self, printers: &[PrinterInfo], mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        if let Some(announce) = self.add_printers(printers) {
+            self.channel.write(&announce, mcs)?;
+        }
+        Ok(())
+    }
+
+    /// Register printers
+    /// Return the announce PDU if devices were already announced
+    fn add_printers(&mut self, printers: &[PrinterInfo]) -> Option<Vec<u8>> {
+        let first_index = self.printers.len();
+        self.printers.extend_from_slice(printers);
+        if !self.logged_on || printers.is_empty() {
+            return None;
+        }
+        let devices = self.printer_announces(first_index);
+        Some(to_vec(&rdpdr_header(Some(PacketId::DeviceListAnnounce), Some(to_vec(&device_list_announce(devices))))))
+    }
+
+    /// Device announces of the printers from an index
+    fn printer_announces(&self, first_index: usize) -> Vec<Component> {
+        self.printers
+            .iter()
+            .enumerate()
+            .skip(first_index)
+            .map(|(index, printer)| {
+                device_announce(
+                    RDPDR_DTYP_PRINT,
+                    FIRST_PRINTER_DEVICE_ID + index as u32,
+                    &format!("PRN{}", index + 1),
+                    printer_device_data(printer),
+                )
+            })
+            .collect()
+    }
+
+    /// Read a file of the redirected drive
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::rdpdr::{DriveAccess, DriveRedirector};
+    /// let mut drive = DriveRedirector::new("rdp-rs").drive(DriveAccess::ReadOnly);
+    /// drive.write_file("\\folder\\foo.txt", b"bar").unwrap();
+    /// assert_eq!(drive.read_file("folder/FOO.txt").unwrap(), b"bar");
+    /// ```
+    pub fn read_file(&mut self, remote_path: &str) -> RdpResult<Vec<u8>> {
+        self.files.get(&normalize_path(remote_path)).cloned().ok_or_else(|| {
+            Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidData,
+                &format!("RDPDR: File {} not found on redirected drive", remote_path),
+            ))
+        })
+    }
+
+    /// Write a file into the redirected drive
+    /// Overwrite any existing file
+    pub fn write_file(&mut self, remote_path: &str, data: &[u8]) -> RdpResult<()> {
+        let path = normalize_path(remote_path);
+        if path.is_empty() {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "RDPDR: Invalid file path")));
+        }
+        self.files.insert(path, data.to_vec());
+        Ok(())
+    }
+
+    /// Read a PDU from the rdpdr channel
+    /// and send back the responses
+    pub fn read<S: Read + Write>(&mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        if let Some(message) = self.channel.read(payload)? {
+            for response in self.process(&message)? {
+                self.channel.write(&response, mcs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process a complete PDU
+    /// Return the PDU to send back to the server
+    fn process(&mut self, message: &[u8]) -> RdpResult<Vec<Vec<u8>>> {
+        let mut pdu = rdpdr_header(None, None);
+        pdu.read_from_bytes(message)?;
+        if cast!(DataType::U16, pdu["component"])? != RDPDR_CTYP_CORE {
+            // printer component is not supported
+            return Ok(Vec::new());
+        }
+        let data = cast!(DataType::Slice, pdu["data"])?;
+
+        let response =
+            |packet_id: PacketId, message: &dyn Message| to_vec(&rdpdr_header(Some(packet_id), Some(to_vec(message))));
+
+        Ok(match PacketId::try_from(cast!(DataType::U16, pdu["packetId"])?)? {
+            PacketId::ServerAnnounce => {
+                let mut announce = client_announce_reply(0);
+                announce.read_from_bytes(data)?;
+                let client_id = cast!(DataType::U32, announce["clientId"])?;
+                vec![
+                    response(PacketId::ClientIdConfirm, &client_announce_reply(client_id)),
+                    response(PacketId::ClientName, &client_name_request(&self.client_name)),
+                ]
+            }
+            PacketId::ServerCapability => vec![response(PacketId::ClientCapability, &client_core_capability())],
+            PacketId::UserLoggedOn => {
+                self.logged_on = true;
+                let mut devices = Vec::new();
+                if self.access.is_some() {
+                    devices.push(device_announce(RDPDR_DTYP_FILESYSTEM, DRIVE_DEVICE_ID, DRIVE_NAME, Vec::new()));
+                }
+                devices.extend(self.printer_announces(0));
+                vec![response(PacketId::DeviceListAnnounce, &device_list_announce(devices))]
+            }
+            PacketId::DeviceIoRequest => {
+                vec![response(PacketId::DeviceIoCompletion, &self.process_irp(&mut Cursor::new(data))?)]
+            }
+            PacketId::ClientIdConfirm | PacketId::DeviceReply => Vec::new(),
+            packet_id => {
+                log_debug!("RDPDR: packet not handled {:?}", packet_id);
+                Vec::new()
+            }
+        })
+    }
+
+    /// Process an IRP and build its completion
+    fn process_irp(&mut self, stream: &mut Cursor<&[u8]>) -> RdpResult<Component> {
+        let mut request = device_io_request();
+        request.read(stream)?;
+        let device_id = cast!(DataType::U32, request["deviceId"])?;
+        let file_id = cast!(DataType::U32, request["fileId"])?;
+        let completion_id = cast!(DataType::U32, request["completionId"])?;
+
+        if device_id != DRIVE_DEVICE_ID || self.access.is_none() {
+            // print jobs are not supported
+            return Ok(device_io_response(device_id, completion_id, NtStatus::NotSupported, Vec::new()));
+        }
+
+        let (status, data) = match MajorFunction::try_from(cast!(DataType::U32, request["majorFunction"])?) {
+            Ok(MajorFunction::Create) => self.irp_create(stream)?,
+            Ok(MajorFunction::Close) => {
+                self.handles.remove(&file_id);
+                (NtStatus::Success, vec![0; 5])
+            }
+            Ok(MajorFunction::Read) => self.irp_read(file_id, stream)?,
+            Ok(MajorFunction::Write) => self.irp_write(file_id, stream)?,
+            Ok(MajorFunction::QueryInformation) => self.irp_query_information(file_id, stream)?,
+            _ => (NtStatus::NotSupported, Vec::new()),
+        };
+        Ok(device_io_response(device_id, completion_id, status, data))
+    }
+
+    /// Open or create a file
+    fn irp_create(&mut self, stream: &mut Cursor<&[u8]>) -> RdpResult<(NtStatus, Vec<u8>)> {
+        let mut request = create_request();
+        request.read(stream)?;
+        let length = cast!(DataType::U32, request["pathLength"])? as usize;
+        let remaining = stream.get_ref().len().saturating_sub(stream.position() as usize);
+        if length > remaining {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidSize,
+                "RDPDR: Path length exceeds the request",
+            )));
+        }
+        let mut path = vec![0; length];
+        stream.read_exact(&mut path)?;
+        let path: Vec<u16> =
+            path.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|c| *c != 0).collect();
+        let path = normalize_path(&String::from_utf16_lossy(&path));
+
+        let disposition = match CreateDisposition::try_from(cast!(DataType::U32, request["createDisposition"])?) {
+            Ok(disposition) => disposition,
+            Err(_) => return Ok((NtStatus::InvalidParameter, to_vec(&trame![U32::LE(0), 0_u8]))),
+        };
+        let exists = path.is_empty() || self.files.contains_key(&path);
+        let opens = matches!(disposition, CreateDisposition::Open | CreateDisposition::OpenIf) && exists;
+        if !opens && disposition != CreateDisposition::Open {
+            if self.access != Some(DriveAccess::ReadWrite) {
+                return Ok((NtStatus::AccessDenied, to_vec(&trame![U32::LE(0), 0_u8])));
+            }
+            if !exists && self.files.len() >= MAX_FILES {
+                return Ok((NtStatus::DiskFull, to_vec(&trame![U32::LE(0), 0_u8])));
+            }
+        }
+        let information = match (disposition, exists) {
+            // the root of the drive is the only directory
+            (_, true) if path.is_empty() => CreateInformation::Opened,
+            (CreateDisposition::Open | CreateDisposition::Overwrite, false) => {
+                return Ok((NtStatus::ObjectNameNotFound, to_vec(&trame![U32::LE(0), 0_u8])))
+            }
+            (CreateDisposition::Create, true) => {
+                return Ok((NtStatus::ObjectNameCollision, to_vec(&trame![U32::LE(0), 0_u8])))
+            }
+            (CreateDisposition::Open | CreateDisposition::OpenIf, true) => CreateInformation::Opened,
+            (CreateDisposition::Supersede, true) => {
+                self.files.insert(path.clone(), Vec::new());
+                CreateInformation::Superseded
+            }
+            (CreateDisposition::Overwrite | CreateDisposition::OverwriteIf, true) => {
+                self.files.insert(path.clone(), Vec::new());
+                CreateInformation::Overwritten
+            }
+            (_, false) => {
+                self.files.insert(path.clone(), Vec::new());
+                CreateInformation::Created
+            }
+        };
+
+        let file_id = self.next_file_id;
+        self.next_file_id = self.next_file_id.wrapping_add(1);
+        self.handles.insert(file_id, path);
+        Ok((NtStatus::Success, to_vec(&trame![U32::LE(file_id), information as u8])))
+    }
+
+    /// Read data from an opened file
+    fn irp_read(&mut self, file_id: u32, stream: &mut Cursor<&[u8]>) -> RdpResult<(NtStatus, Vec<u8>)> {
+        let mut request = read_write_request();
+        request.read(stream)?;
+        let length = cast!(DataType::U32, request["length"])? as usize;
+        let offset = read_offset(&request)?;
+
+        match self.handles.get(&file_id).and_then(|path| self.files.get(path)) {
+            Some(content) => {
+                let start = offset.min(content.len());
+                let end = start.saturating_add(length).min(content.len());
+                let data = &content[start..end];
+                Ok((NtStatus::Success, to_vec(&trame![U32::LE(data.len() as u32), data.to_vec()])))
+            }
+            None => Ok((NtStatus::NoSuchFile, to_vec(&U32::LE(0)))),
+        }
+    }
+
+    /// Write data into an opened file
+    fn irp_write(&mut self, file_id: u32, stream: &mut Cursor<&[u8]>) -> RdpResult<(NtStatus, Vec<u8>)> {
+        let mut request = read_write_request();
+        request.read(stream)?;
+        if self.access != Some(DriveAccess::ReadWrite) {
+            return Ok((NtStatus::AccessDenied, to_vec(&trame![U32::LE(0), 0_u8])));
+        }
+        let length = cast!(DataType::U32, request["length"])? as usize;
+        let remaining = stream.get_ref().len().saturating_sub(stream.position() as usize);
+        if length > remaining {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidSize,
+                "RDPDR: Write length exceeds the request",
+            )));
+        }
+        let mut data = vec![0; length];
+        stream.read_exact(&mut data)?;
+        let offset = read_offset(&request)?;
+        let end = match offset.checked_add(data.len()) {
+            Some(end) if end <= MAX_FILE_SIZE => end,
+            _ => return Ok((NtStatus::DiskFull, to_vec(&trame![U32::LE(0), 0_u8]))),
+        };
+        let drive_size: usize = self.files.values().map(Vec::len).sum();
+
+        match self.handles.get(&file_id).and_then(|path| self.files.get_mut(path)) {
+            Some(content) => {
+                if drive_size + end.saturating_sub(content.len()) > MAX_DRIVE_SIZE {
+                    return Ok((NtStatus::DiskFull, to_vec(&trame![U32::LE(0), 0_u8])));
+                }
+                if content.len() < end {
+                    content.resize(end, 0);
+                }
+                content[offset..end].copy_from_slice(&data);
+                Ok((NtStatus::Success, to_vec(&trame![U32::LE(data.len() as u32), 0_u8])))
+            }
+            None => Ok((NtStatus::NoSuchFile, to_vec(&trame![U32::LE(0), 0_u8]))),
+        }
+    }
+
+    /// Give basic information on an opened file
+    fn irp_query_information(&mut self, file_id: u32, stream: &mut Cursor<&[u8]>) -> RdpResult<(NtStatus, Vec<u8>)> {
+        let mut request = query_information_request();
+        request.read(stream)?;
+
+        let path = match self.handles.get(&file_id) {
+            Some(path) => path,
+            None => return Ok((NtStatus::NoSuchFile, to_vec(&U32::LE(0)))),
+        };
+        let (size, attributes, directory) = match self.files.get(path) {
+            Some(content) => (content.len() as u64, FILE_ATTRIBUTE_NORMAL, 0_u8),
+            None => (0, FILE_ATTRIBUTE_DIRECTORY, 1_u8),
+        };
+
+        let buffer = match FileInformationClass::try_from(cast!(DataType::U32, request["fsInformationClass"])?) {
+            Ok(FileInformationClass::Basic) => to_vec(&trame![vec![0_u8; 32], U32::LE(attributes)]),
+            Ok(FileInformationClass::Standard) => {
+                to_vec(&trame![size.to_le_bytes().to_vec(), size.to_le_bytes().to_vec(), U32::LE(1), 0_u8, directory])
+            }
+            Ok(FileInformationClass::AttributeTag) => to_vec(&trame![U32::LE(attributes), U32::LE(0)]),
+            Err(_) => return Ok((NtStatus::NotSupported, to_vec(&U32::LE(0)))),
+        };
+        Ok((NtStatus::Success, to_vec(&trame![U32::LE(buffer.len() as u32), buffer])))
+    }
+}
+
+/// Read the 64 bits offset of a read or write request
+fn read_offset(request: &Component) -> RdpResult<usize> {
+    let offset = cast!(DataType::Slice, request["offset"])?;
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(offset);
+    usize::try_from(u64::from_le_bytes(bytes))
+        .map_err(|_| Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "RDPDR: Invalid offset")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a server IRP for the redirected drive
+    fn irp(file_id: u32, completion_id: u32, major: MajorFunction, parameters: Vec<u8>) -> Vec<u8> {
+        to_vec(&rdpdr_header(
+            Some(PacketId::DeviceIoRequest),
+            Some(to_vec(&trame![
+                U32::LE(DRIVE_DEVICE_ID),
+                U32::LE(file_id),
+                U32::LE(completion_id),
+                U32::LE(major as u32),
+                U32::LE(0),
+                parameters
+            ])),
+        ))
+    }
+
+    fn create(path: &str, disposition: CreateDisposition) -> Vec<u8> {
+        let mut path = path.to_utf16_le();
+        path.extend_from_slice(&[0, 0]);
+        to_vec(&trame![
+            U32::LE(0),
+            vec![0_u8; 8],
+            U32::LE(0),
+            U32::LE(0),
+            U32::LE(disposition as u32),
+            U32::LE(0),
+            U32::LE(path.len() as u32),
+            path
+        ])
+    }
+
+    fn read_write(length: u32, offset: u64, data: Vec<u8>) -> Vec<u8> {
+        to_vec(&trame![U32::LE(length), offset.to_le_bytes().to_vec(), vec![0_u8; 20], data])
+    }
+
+    /// Parse a completion and return status and data
+    fn completion(response: &[u8]) -> (u32, Vec<u8>) {
+        assert_eq!(&response[..4], [0x72, 0x44, 0x43, 0x49]);
+        let mut stream = Cursor::new(&response[4..]);
+        let mut completion = device_io_response(0, 0, NtStatus::Success, Vec::new());
+        completion.read(&mut stream).unwrap();
+        (
+            cast!(DataType::U32, completion["ioStatus"]).unwrap(),
+            cast!(DataType::Slice, completion["data"]).unwrap().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_server_announce() {
+        let mut drive = DriveRedirector::new("foo");
+        let responses = drive.process(&[0x72, 0x44, 0x6e, 0x49, 1, 0, 0x0c, 0, 2, 0, 0, 0]).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0], [0x72, 0x44, 0x43, 0x43, 1, 0, 0x0c, 0, 2, 0, 0, 0]);
+        assert_eq!(&responses[1][..4], [0x72, 0x44, 0x4e, 0x43]);
+    }
+
+    #[test]
+    fn test_remote_session_write_file() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        let (status, data) = completion(
+            &drive
+                .process(&irp(0, 1, MajorFunction::Create, create("\\Bar.txt", CreateDisposition::OverwriteIf)))
+                .unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::Success as u32);
+        assert_eq!(data, [1, 0, 0, 0, CreateInformation::Created as u8]);
+
+        let (status, data) =
+            completion(&drive.process(&irp(1, 2, MajorFunction::Write, read_write(3, 0, vec![1, 2, 3]))).unwrap()[0]);
+        assert_eq!(status, NtStatus::Success as u32);
+        assert_eq!(data, [3, 0, 0, 0, 0]);
+
+        drive.process(&irp(1, 3, MajorFunction::Close, vec![0; 32])).unwrap();
+        assert_eq!(drive.read_file("bar.txt").unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_remote_session_write_bounds() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        drive.process(&irp(0, 1, MajorFunction::Create, create("\\bar.txt", CreateDisposition::OverwriteIf))).unwrap();
+
+        // the length can't exceed the data of the request
+        assert!(drive.process(&irp(1, 2, MajorFunction::Write, read_write(u32::MAX, 0, vec![1, 2, 3]))).is_err());
+
+        let (status, _) = completion(
+            &drive.process(&irp(1, 3, MajorFunction::Write, read_write(3, u64::MAX, vec![1, 2, 3]))).unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::DiskFull as u32);
+
+        let (status, _) = completion(
+            &drive.process(&irp(1, 4, MajorFunction::Write, read_write(1, MAX_FILE_SIZE as u64, vec![1]))).unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::DiskFull as u32);
+
+        drive.process(&irp(1, 5, MajorFunction::Close, vec![0; 32])).unwrap();
+        assert!(drive.read_file("bar.txt").unwrap().is_empty());
+
+        // the path length can't exceed the request
+        let mut request = irp(0, 6, MajorFunction::Create, create("\\foo.txt", CreateDisposition::OverwriteIf));
+        let length = request.len() - 4;
+        request.truncate(length);
+        assert!(drive.process(&request).is_err());
+    }
+
+    #[test]
+    fn test_remote_session_drive_size() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        for index in 0..MAX_DRIVE_SIZE / MAX_FILE_SIZE {
+            drive.write_file(&format!("{}.bin", index), &vec![0; MAX_FILE_SIZE]).unwrap();
+        }
+        drive.process(&irp(0, 1, MajorFunction::Create, create("\\bar.txt", CreateDisposition::OverwriteIf))).unwrap();
+        let (status, _) =
+            completion(&drive.process(&irp(1, 2, MajorFunction::Write, read_write(1, 0, vec![1]))).unwrap()[0]);
+        assert_eq!(status, NtStatus::DiskFull as u32);
+
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        for index in 0..MAX_FILES {
+            drive.write_file(&format!("{}.bin", index), &[]).unwrap();
+        }
+        let (status, _) = completion(
+            &drive
+                .process(&irp(0, 3, MajorFunction::Create, create("\\bar.txt", CreateDisposition::OverwriteIf)))
+                .unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::DiskFull as u32);
+    }
+
+    #[test]
+    fn test_read_only_drive() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadOnly);
+        drive.write_file("bar.txt", &[1, 2, 3, 4]).unwrap();
+        let (status, _) = completion(
+            &drive
+                .process(&irp(0, 1, MajorFunction::Create, create("\\new.txt", CreateDisposition::OverwriteIf)))
+                .unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::AccessDenied as u32);
+        assert!(drive.read_file("new.txt").is_err());
+
+        let (status, _) = completion(
+            &drive.process(&irp(0, 2, MajorFunction::Create, create("\\bar.txt", CreateDisposition::Open))).unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::Success as u32);
+        let (status, _) =
+            completion(&drive.process(&irp(1, 3, MajorFunction::Write, read_write(1, 0, vec![0]))).unwrap()[0]);
+        assert_eq!(status, NtStatus::AccessDenied as u32);
+        assert_eq!(drive.read_file("bar.txt").unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_no_drive() {
+        let mut drive = DriveRedirector::new("foo");
+        // no device is announced
+        assert_eq!(drive.process(&[0x72, 0x44, 0x4c, 0x55]).unwrap()[0][4..8], [0, 0, 0, 0]);
+        let response = &drive
+            .process(&irp(0, 1, MajorFunction::Create, create("\\bar.txt", CreateDisposition::OverwriteIf)))
+            .unwrap()[0];
+        assert_eq!(completion(response).0, NtStatus::NotSupported as u32);
+    }
+
+    #[test]
+    fn test_remote_session_read_file() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        drive.write_file("bar.txt", &[1, 2, 3, 4]).unwrap();
+
+        let (status, _) = completion(
+            &drive.process(&irp(0, 1, MajorFunction::Create, create("\\bar.txt", CreateDisposition::Open))).unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::Success as u32);
+
+        let (status, data) =
+            completion(&drive.process(&irp(1, 2, MajorFunction::Read, read_write(8, 2, Vec::new()))).unwrap()[0]);
+        assert_eq!(status, NtStatus::Success as u32);
+        assert_eq!(data, [2, 0, 0, 0, 3, 4]);
+
+        let (status, data) = completion(
+            &drive
+                .process(&irp(
+                    1,
+                    3,
+                    MajorFunction::QueryInformation,
+                    to_vec(&trame![U32::LE(5), U32::LE(0), vec![0_u8; 24]]),
+                ))
+                .unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::Success as u32);
+        assert_eq!(data[..12], [22, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_open_missing_file() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        let (status, _) = completion(
+            &drive
+                .process(&irp(0, 1, MajorFunction::Create, create("\\missing.txt", CreateDisposition::Open)))
+                .unwrap()[0],
+        );
+        assert_eq!(status, NtStatus::ObjectNameNotFound as u32);
+        assert!(drive.read_file("missing.txt").is_err());
+    }
+
+    #[test]
+    fn test_create_invalid_disposition() {
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        let mut parameters = create("\\new.txt", CreateDisposition::Create);
+        parameters[20..24].copy_from_slice(&6_u32.to_le_bytes());
+        let (status, _) = completion(&drive.process(&irp(0, 1, MajorFunction::Create, parameters)).unwrap()[0]);
+        assert_eq!(status, NtStatus::InvalidParameter as u32);
+        assert!(drive.read_file("new.txt").is_err());
+    }
+
+    #[test]
+    fn test_announce_printers() {
+        let printer = PrinterInfo { name: "P".to_string(), driver: "D".to_string(), is_default: true };
+        let mut drive = DriveRedirector::new("foo").drive(DriveAccess::ReadWrite);
+        assert_eq!(drive.add_printers(std::slice::from_ref(&printer)), None);
+
+        // drive and printer are announced once the user is logged on
+        let responses = drive.process(&[0x72, 0x44, 0x4c, 0x55]).unwrap();
+        assert_eq!(responses[0][..8], [0x72, 0x44, 0x41, 0x44, 2, 0, 0, 0]);
+        assert_eq!(responses[0][8 + 20..8 + 32], [4, 0, 0, 0, 2, 0, 0, 0, b'P', b'R', b'N', b'1']);
+
+        // a printer added later is announced alone
+
+        let printer_announce = [
+            0x72, 0x44, 0x41, 0x44, // header
+            1, 0, 0, 0, // deviceCount
+            4, 0, 0, 0, // RDPDR_DTYP_PRINT
+            3, 0, 0, 0, // deviceId
+            b'P', b'R', b'N', b'2', 0, 0, 0, 0, // preferredDosName
+            32, 0, 0, 0, // deviceDataLength
+            2, 0, 0, 0, // RDPDR_PRINTER_ANNOUNCE_FLAG_DEFAULTPRINTER
+            0, 0, 0, 0, // codePage
+            0, 0, 0, 0, // pnpNameLen
+            4, 0, 0, 0, // driverNameLen
+            4, 0, 0, 0, // printNameLen
+            0, 0, 0, 0, // cachedFieldsLen
+            b'D', 0, 0, 0, // driverName
+            b'P', 0, 0, 0, // printName
+        ];
+        assert_eq!(drive.add_printers(&[printer]).unwrap(), printer_announce);
+    }
+
+    #[test]
+    fn test_printer_irp_not_supported() {
+        let mut drive = DriveRedirector::new("foo");
+        let mut request = irp(0, 1, MajorFunction::Create, create("\\bar.txt", CreateDisposition::Open));
+        request[4] = FIRST_PRINTER_DEVICE_ID as u8;
+        let response = &drive.process(&request).unwrap()[0];
+        assert_eq!(response[4], FIRST_PRINTER_DEVICE_ID as u8);
+        assert_eq!(completion(response).0, NtStatus::NotSupported as u32);
+    }
+}
@@ -0,0 +1,544 @@
+use std::io::{Cursor, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::channel::StaticChannel;
+use crate::core::{mcs, tpkt};
+use crate::model::data::{to_vec, Component, DataType, Message, Trame, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Name of the audio output static virtual channel
+pub const RDPSND_CHANNEL_NAME: &str = "rdpsnd";
+
+/// Format tag of uncompressed PCM samples
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+
+/// Version announced by the client
+/// Version 8 let the server use the Wave2 PDU
+const CLIENT_VERSION: u16 = 8;
+
+/// Client can play audio and handle volume changes
+const TSSNDCAPS_ALIVE: u32 = 0x0000_0001;
+const TSSNDCAPS_VOLUME: u32 = 0x0000_0002;
+
+/// Quality mode asked by the client
+const HIGH_QUALITY: u16 = 0x0002;
+
+/// Type of audio output PDU
+/// See MS-RDPEA 2.2.1
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum MessageType {
+    Close = 0x01,
+    Wave = 0x02,
+    SetVolume = 0x03,
+    SetPitch = 0x04,
+    WaveConfirm = 0x05,
+    Training = 0x06,
+    Formats = 0x07,
+    CryptKey = 0x08,
+    WaveEncrypt = 0x09,
+    UdpWave = 0x0a,
+    UdpWaveLast = 0x0b,
+    QualityMode = 0x0c,
+    Wave2 = 0x0d,
+}
+
+/// Header of all audio output PDU
+fn rdpsnd_pdu(message_type: MessageType, body: &dyn Message) -> Vec<u8> {
+    to_vec(&component![
+        "msgType" => message_type as u8,
+        "bPad" => 0_u8,
+        "BodySize" => U16::LE(body.length() as u16),
+        "body" => to_vec(body)
+    ])
+}
+
+/// Audio format of the samples
+/// See MS-RDPEA 2.2.2.1.1
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AudioFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// Extra data of compressed formats
+    pub data: Vec<u8>,
+}
+
+impl AudioFormat {
+    fn read(stream: &mut dyn Read) -> RdpResult<Self> {
+        let mut format = component![
+            "wFormatTag" => U16::LE(0),
+            "nChannels" => U16::LE(0),
+            "nSamplesPerSec" => U32::LE(0),
+            "nAvgBytesPerSec" => U32::LE(0),
+            "nBlockAlign" => U16::LE(0),
+            "wBitsPerSample" => U16::LE(0),
+            "cbSize" => U16::LE(0)
+        ];
+        format.read(stream)?;
+        let mut data = vec![0; cast!(DataType::U16, format["cbSize"])? as usize];
+        stream.read_exact(&mut data)?;
+        Ok(AudioFormat {
+            format_tag: cast!(DataType::U16, format["wFormatTag"])?,
+            channels: cast!(DataType::U16, format["nChannels"])?,
+            samples_per_sec: cast!(DataType::U32, format["nSamplesPerSec"])?,
+            avg_bytes_per_sec: cast!(DataType::U32, format["nAvgBytesPerSec"])?,
+            block_align: cast!(DataType::U16, format["nBlockAlign"])?,
+            bits_per_sample: cast!(DataType::U16, format["wBitsPerSample"])?,
+            data,
+        })
+    }
+
+    fn to_component(&self) -> Component {
+        component![
+            "wFormatTag" => U16::LE(self.format_tag),
+            "nChannels" => U16::LE(self.channels),
+            "nSamplesPerSec" => U32::LE(self.samples_per_sec),
+            "nAvgBytesPerSec" => U32::LE(self.avg_bytes_per_sec),
+            "nBlockAlign" => U16::LE(self.block_align),
+            "wBitsPerSample" => U16::LE(self.bits_per_sample),
+            "cbSize" => U16::LE(self.data.len() as u16),
+            "data" => self.data.clone()
+        ]
+    }
+
+    /// Uncompressed 8 or 16 bits samples
+    pub fn is_pcm(&self) -> bool {
+        self.format_tag == WAVE_FORMAT_PCM && (self.bits_per_sample == 8 || self.bits_per_sample == 16)
+    }
+}
+
+/// Backend which plays the samples sent by the server
+///
+/// Only PCM formats are negotiated,
+/// 16 bits samples are signed little endian and 8 bits are unsigned
+pub trait AudioOutput {
+    /// Prepare the output for a new format
+    /// Called before the first samples of each format
+    fn open(&mut self, format: &AudioFormat) -> RdpResult<()>;
+
+    /// Queue interleaved samples for playback
+    fn play(&mut self, data: &[u8]) -> RdpResult<()>;
+
+    /// Volume of each channel, 0xffff is the maximum
+    fn set_volume(&mut self, _left: u16, _right: u16) {}
+
+    /// Called when the server stop playing audio
+    fn close(&mut self) {}
+}
+
+/// Audio output shared between connections
+#[derive(Clone)]
+pub(crate) struct SharedAudioOutput(pub(crate) Arc<Mutex<dyn AudioOutput + Send>>);
+
+impl std::fmt::Debug for SharedAudioOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("SharedAudioOutput") }
+}
+
+/// Wave waiting for its data PDU
+struct PendingWave {
+    timestamp: u16,
+    format: u16,
+    block: u8,
+    /// First bytes of the samples, sent with the wave info
+    head: [u8; 4],
+}
+
+/// Audio output virtual channel (MS-RDPEA)
+///
+/// Samples sent by the server are forwarded to an `AudioOutput`
+pub struct AudioChannel {
+    /// Underlying static virtual channel
+    channel: StaticChannel,
+    output: SharedAudioOutput,
+    /// Formats announced to the server
+    /// Waves reference them by index
+    formats: Vec<AudioFormat>,
+    /// Format opened on the output
+    current_format: Option<u16>,
+    pending_wave: Option<PendingWave>,
+}
+
+impl std::fmt::Debug for AudioChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AudioChannel")
+            .field("formats", &self.formats)
+            .field("current_format", &self.current_format)
+            .finish()
+    }
+}
+
+impl AudioChannel {
+    pub(crate) fn new(output: SharedAudioOutput) -> Self {
+        AudioChannel {
+            channel: StaticChannel::new(RDPSND_CHANNEL_NAME),
+            output,
+            formats: Vec::new(),
+            current_format: None,
+            pending_wave: None,
+        }
+    }
+
+    /// Read a PDU from the rdpsnd channel
+    /// and send back the responses
+    pub fn read<S: Read + Write>(&mut self, payload: tpkt::Payload, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        if let Some(message) = self.channel.read(payload)? {
+            for response in self.process(&message)? {
+                self.channel.write(&response, mcs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lock_output(&self) -> RdpResult<std::sync::MutexGuard<'_, dyn AudioOutput + Send + 'static>> {
+        self.output
+            .0
+            .lock()
+            .map_err(|_| Error::RdpError(RdpError::new(RdpErrorKind::Unknown, "RDPSND: Poisoned audio output")))
+    }
+
+    /// Process a complete PDU
+    /// Return the PDU to send back to the server
+    fn process(&mut self, message: &[u8]) -> RdpResult<Vec<Vec<u8>>> {
+        // The wave data PDU replace its header by padding
+        if let Some(wave) = self.pending_wave.take() {
+            if message.len() < 4 {
+                return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "RDPSND: Wave PDU too small")));
+            }
+            let mut data = wave.head.to_vec();
+            data.extend_from_slice(&message[4..]);
+            return self.play(wave.timestamp, wave.format, wave.block, &data);
+        }
+
+        let mut stream = Cursor::new(message);
+        let message_type = stream.read_u8()?;
+        let _pad = stream.read_u8()?;
+        let _body_size = stream.read_u16::<LittleEndian>()?;
+
+        match MessageType::try_from(message_type) {
+            Ok(MessageType::Formats) => self.process_formats(&mut stream),
+            Ok(MessageType::Training) => {
+                let timestamp = stream.read_u16::<LittleEndian>()?;
+                let pack_size = stream.read_u16::<LittleEndian>()?;
+                Ok(vec![rdpsnd_pdu(
+                    MessageType::Training,
+                    &component!["wTimeStamp" => U16::LE(timestamp), "wPackSize" => U16::LE(pack_size)],
+                )])
+            }
+            Ok(MessageType::Wave) => {
+                let timestamp = stream.read_u16::<LittleEndian>()?;
+                let format = stream.read_u16::<LittleEndian>()?;
+                let block = stream.read_u8()?;
+                let mut pad = [0; 3];
+                stream.read_exact(&mut pad)?;
+                let mut head = [0; 4];
+                stream.read_exact(&mut head)?;
+                self.pending_wave = Some(PendingWave { timestamp, format, block, head });
+                Ok(Vec::new())
+            }
+            Ok(MessageType::Wave2) => {
+                let timestamp = stream.read_u16::<LittleEndian>()?;
+                let format = stream.read_u16::<LittleEndian>()?;
+                let block = stream.read_u8()?;
+                let mut pad = [0; 3];
+                stream.read_exact(&mut pad)?;
+                let _audio_timestamp = stream.read_u32::<LittleEndian>()?;
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                self.play(timestamp, format, block, &data)
+            }
+            Ok(MessageType::SetVolume) => {
+                let volume = stream.read_u32::<LittleEndian>()?;
+                self.lock_output()?.set_volume(volume as u16, (volume >> 16) as u16);
+                Ok(Vec::new())
+            }
+            Ok(MessageType::Close) => {
+                self.current_format = None;
+                self.lock_output()?.close();
+                Ok(Vec::new())
+            }
+            _ => {
+                log_debug!("RDPSND: message not handled {:#x}", message_type);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Answer the server formats with the supported ones
+    /// See MS-RDPEA 2.2.2.1
+    fn process_formats(&mut self, stream: &mut Cursor<&[u8]>) -> RdpResult<Vec<Vec<u8>>> {
+        let mut header = component![
+            "dwFlags" => U32::LE(0),
+            "dwVolume" => U32::LE(0),
+            "dwPitch" => U32::LE(0),
+            "wDGramPort" => U16::LE(0),
+            "wNumberOfFormats" => U16::LE(0),
+            "cLastBlockConfirmed" => 0_u8,
+            "wVersion" => U16::LE(0),
+            "bPad" => 0_u8
+        ];
+        header.read(stream)?;
+        let mut formats = Vec::new();
+        for _ in 0..cast!(DataType::U16, header["wNumberOfFormats"])? {
+            formats.push(AudioFormat::read(stream)?);
+        }
+        self.formats = formats.into_iter().filter(AudioFormat::is_pcm).collect();
+        self.current_format = None;
+
+        let mut client_formats = Trame::new();
+        for format in &self.formats {
+            client_formats.push(Box::new(format.to_component()));
+        }
+        let mut responses = vec![rdpsnd_pdu(
+            MessageType::Formats,
+            &component![
+                "dwFlags" => U32::LE(TSSNDCAPS_ALIVE | TSSNDCAPS_VOLUME),
+                "dwVolume" => U32::LE(0xffff_ffff),
+                "dwPitch" => U32::LE(0),
+                "wDGramPort" => U16::LE(0),
+                "wNumberOfFormats" => U16::LE(self.formats.len() as u16),
+                "cLastBlockConfirmed" => 0_u8,
+                "wVersion" => U16::LE(CLIENT_VERSION),
+                "bPad" => 0_u8,
+                "formats" => client_formats
+            ],
+        )];
+        // Quality mode is only understood from version 6
+        if cast!(DataType::U16, header["wVersion"])? >= 6 {
+            responses.push(rdpsnd_pdu(
+                MessageType::QualityMode,
+                &component!["wQualityMode" => U16::LE(HIGH_QUALITY), "Reserved" => U16::LE(0)],
+            ));
+        }
+        Ok(responses)
+    }
+
+    /// Forward samples to the output and confirm the block
+    fn play(&mut self, timestamp: u16, format: u16, block: u8, data: &[u8]) -> RdpResult<Vec<Vec<u8>>> {
+        let audio_format = self.formats.get(format as usize).ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("RDPSND: Unknown format {}", format)))
+        })?;
+        let mut output = self.lock_output()?;
+        if self.current_format != Some(format) {
+            output.open(audio_format)?;
+        }
+        output.play(data)?;
+        drop(output);
+        self.current_format = Some(format);
+
+        Ok(vec![rdpsnd_pdu(
+            MessageType::WaveConfirm,
+            &component![
+                "wTimeStamp" => U16::LE(timestamp),
+                "cConfirmedBlockNo" => block,
+                "bPad" => 0_u8
+            ],
+        )])
+    }
+}
+
+/// Play audio on the default output device
+/// Available with the `audio` feature
+#[cfg(feature = "audio")]
+pub struct CpalOutput {
+    /// Samples waiting to be played
+    samples: Arc<Mutex<std::collections::VecDeque<f32>>>,
+    /// Stop the playback thread once dropped
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    bits_per_sample: u16,
+    volume: f32,
+}
+
+#[cfg(feature = "audio")]
+impl CpalOutput {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        CpalOutput {
+            samples: Arc::new(Mutex::new(std::collections::VecDeque::new())),
+            stop: None,
+            bits_per_sample: 16,
+            volume: 1.0,
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioOutput for CpalOutput {
+    fn open(&mut self, format: &AudioFormat) -> RdpResult<()> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        self.close();
+        self.bits_per_sample = format.bits_per_sample;
+        let config = cpal::StreamConfig {
+            channels: format.channels,
+            sample_rate: cpal::SampleRate(format.samples_per_sec),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let samples = self.samples.clone();
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let (ready, started) = std::sync::mpsc::channel();
+
+        // cpal streams can't move between threads
+        std::thread::spawn(move || {
+            let stream = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| "no output device".to_string())
+                .and_then(|device| {
+                    device
+                        .build_output_stream(
+                            &config,
+                            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                                if let Ok(mut samples) = samples.lock() {
+                                    for sample in data.iter_mut() {
+                                        *sample = samples.pop_front().unwrap_or(0.0);
+                                    }
+                                }
+                            },
+                            |e| log_error!("RDPSND: playback error {}", e),
+                            None,
+                        )
+                        .map_err(|e| e.to_string())
+                })
+                .and_then(|stream| stream.play().map(|_| stream).map_err(|e| e.to_string()));
+            match stream {
+                Ok(stream) => {
+                    let _ = ready.send(Ok(()));
+                    // Play until the output is closed
+                    let _ = stopped.recv();
+                    drop(stream);
+                }
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                }
+            }
+        });
+
+        started
+            .recv()
+            .unwrap_or_else(|_| Err("playback thread failed".to_string()))
+            .map_err(|e| Error::RdpError(RdpError::new(RdpErrorKind::Unknown, &format!("RDPSND: {}", e))))?;
+        self.stop = Some(stop);
+        Ok(())
+    }
+
+    fn play(&mut self, data: &[u8]) -> RdpResult<()> {
+        let mut samples = self
+            .samples
+            .lock()
+            .map_err(|_| Error::RdpError(RdpError::new(RdpErrorKind::Unknown, "RDPSND: Poisoned sample buffer")))?;
+        if self.bits_per_sample == 8 {
+            samples.extend(data.iter().map(|s| (f32::from(*s) - 128.0) / 128.0 * self.volume));
+        } else {
+            samples.extend(
+                data.chunks_exact(2).map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / 32768.0 * self.volume),
+            );
+        }
+        Ok(())
+    }
+
+    fn set_volume(&mut self, left: u16, right: u16) { self.volume = f32::from(left.max(right)) / 65535.0; }
+
+    fn close(&mut self) {
+        // dropping the sender ends the playback thread
+        self.stop = None;
+        if let Ok(mut samples) = self.samples.lock() {
+            samples.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Record calls made to the output
+    #[derive(Default)]
+    struct Recorder {
+        opened: Vec<AudioFormat>,
+        played: Vec<u8>,
+    }
+
+    impl AudioOutput for Recorder {
+        fn open(&mut self, format: &AudioFormat) -> RdpResult<()> {
+            self.opened.push(format.clone());
+            Ok(())
+        }
+
+        fn play(&mut self, data: &[u8]) -> RdpResult<()> {
+            self.played.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn pcm(channels: u16, rate: u32) -> AudioFormat {
+        AudioFormat {
+            format_tag: WAVE_FORMAT_PCM,
+            channels,
+            samples_per_sec: rate,
+            avg_bytes_per_sec: rate * u32::from(channels) * 2,
+            block_align: channels * 2,
+            bits_per_sample: 16,
+            data: vec![],
+        }
+    }
+
+    fn server_formats() -> Vec<u8> {
+        let adpcm = AudioFormat { format_tag: 0x0002, bits_per_sample: 4, ..pcm(2, 22050) };
+        rdpsnd_pdu(
+            MessageType::Formats,
+            &trame![
+                vec![0_u8; 14],
+                U16::LE(2),
+                0_u8,
+                U16::LE(6),
+                0_u8,
+                adpcm.to_component(),
+                pcm(2, 44100).to_component()
+            ],
+        )
+    }
+
+    fn channel() -> (AudioChannel, Arc<Mutex<Recorder>>) {
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        (AudioChannel::new(SharedAudioOutput(recorder.clone())), recorder)
+    }
+
+    #[test]
+    fn test_formats_keep_pcm_only() {
+        let (mut channel, _) = channel();
+        let responses = channel.process(&server_formats()).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(channel.formats, [pcm(2, 44100)]);
+        // number of formats and version
+        assert_eq!(responses[0][18..20], [1, 0]);
+        assert_eq!(responses[0][21..23], [8, 0]);
+    }
+
+    #[test]
+    fn test_wave_info_and_data() {
+        let (mut channel, recorder) = channel();
+        channel.process(&server_formats()).unwrap();
+        let info =
+            rdpsnd_pdu(MessageType::Wave, &trame![U16::LE(10), U16::LE(0), 3_u8, vec![0_u8; 3], vec![1_u8, 2, 3, 4]]);
+        assert!(channel.process(&info).unwrap().is_empty());
+        let responses = channel.process(&[0, 0, 0, 0, 5, 6]).unwrap();
+        assert_eq!(responses, [vec![0x05, 0, 4, 0, 10, 0, 3, 0]]);
+        let recorder = recorder.lock().unwrap();
+        assert_eq!(recorder.played, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(recorder.opened, [pcm(2, 44100)]);
+    }
+
+    #[test]
+    fn test_wave_unknown_format() {
+        let (mut channel, _) = channel();
+        let wave = rdpsnd_pdu(MessageType::Wave2, &trame![U16::LE(0), U16::LE(1), 0_u8, vec![0_u8; 3], U32::LE(0)]);
+        assert!(channel.process(&wave).is_err());
+    }
+}
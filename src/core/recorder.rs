@@ -0,0 +1,218 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::codec::colorspace::bgra_to_yuv420;
+use crate::core::event::BitmapEvent;
+use crate::core::screenshot::FrameBuffer;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::time::Instant;
+
+/// Frame rate used when none is given
+pub const DEFAULT_FPS: u8 = 15;
+
+/// Record the screen of a session as a YUV4MPEG2 video
+///
+/// Bitmap updates are drawn on a black framebuffer
+/// which is written at a fixed frame rate, the last frame
+/// is repeated while the screen doesn't change
+///
+/// The stream can be encoded by ffmpeg
+/// `ffmpeg -i session.y4m -c:v libx264 session.mp4`
+///
+/// # Example
+/// ```
+/// use std::time::Instant;
+/// use rdp::core::recorder::SessionRecorder;
+/// let mut recorder = SessionRecorder::new(Vec::new()).fps(25);
+/// recorder.start(800, 600, Instant::now()).unwrap();
+/// ```
+pub struct SessionRecorder {
+    output: Box<dyn Write + Send>,
+    fps: u8,
+    /// None until the size of the screen is known
+    framebuffer: Option<FrameBuffer>,
+    /// Time of the first frame
+    start: Instant,
+    /// Number of frames written
+    frames: u64,
+    /// Set after a write error, nothing is written anymore
+    failed: bool,
+}
+
+impl SessionRecorder {
+    /// Record into `output` at the default frame rate
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        SessionRecorder {
+            output: Box::new(output),
+            fps: DEFAULT_FPS,
+            framebuffer: None,
+            start: Instant::now(),
+            frames: 0,
+            failed: false,
+        }
+    }
+
+    /// Number of frames per second
+    /// zero keeps the default of 15
+    pub fn fps(mut self, fps: u8) -> Self {
+        if fps != 0 {
+            self.fps = fps;
+        }
+        self
+    }
+
+    /// Write the stream header and a black first frame
+    /// Nothing is done once started, the size of a video can't change
+    pub fn start(&mut self, width: u16, height: u16, now: Instant) -> RdpResult<()> {
+        if self.framebuffer.is_some() {
+            return Ok(());
+        }
+        self.framebuffer = Some(FrameBuffer::new(width, height));
+        self.start = now;
+        let header = format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg\n", width, height, self.fps);
+        self.write(header.as_bytes())?;
+        self.write_frames(now)
+    }
+
+    /// Apply a bitmap update to the next frames
+    pub fn draw(&mut self, bitmap: BitmapEvent) -> RdpResult<()> {
+        match &mut self.framebuffer {
+            Some(framebuffer) => framebuffer.draw(bitmap),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the frames due at `now`
+    pub fn write_frames(&mut self, now: Instant) -> RdpResult<()> {
+        let Some(framebuffer) = &self.framebuffer else {
+            return Ok(());
+        };
+        let elapsed = now.saturating_duration_since(self.start);
+        let due = (elapsed.as_nanos() * u128::from(self.fps) / Duration::from_secs(1).as_nanos()) as u64 + 1;
+        if self.failed || due <= self.frames {
+            return Ok(());
+        }
+
+        let mut frame = b"FRAME\n".to_vec();
+        frame.extend(bgra_to_yuv420(&framebuffer.data, framebuffer.width, framebuffer.height));
+        while self.frames < due {
+            self.write(&frame)?;
+            self.frames += 1;
+        }
+        self.write_flush()
+    }
+
+    /// Number of frames written
+    pub fn frames(&self) -> u64 { self.frames }
+
+    fn write(&mut self, data: &[u8]) -> RdpResult<()> {
+        if self.failed {
+            return Ok(());
+        }
+        self.output.write_all(data).map_err(|e| {
+            self.failed = true;
+            Error::from(e)
+        })
+    }
+
+    fn write_flush(&mut self) -> RdpResult<()> {
+        if self.failed {
+            return Ok(());
+        }
+        self.output.flush().map_err(|e| {
+            self.failed = true;
+            Error::from(e)
+        })
+    }
+}
+
+/// Recorder shared between the connector and its clients
+#[derive(Clone)]
+pub(crate) struct SharedRecorder(pub(crate) Arc<Mutex<SessionRecorder>>);
+
+impl SharedRecorder {
+    pub(crate) fn lock(&self) -> RdpResult<std::sync::MutexGuard<'_, SessionRecorder>> {
+        self.0
+            .lock()
+            .map_err(|_| Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "RECORDER: Recorder lock poisoned")))
+    }
+}
+
+impl std::fmt::Debug for SharedRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("SharedRecorder") }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Output kept readable by the test
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buffer) }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    fn white(left: u16, top: u16, width: u16, height: u16) -> BitmapEvent {
+        BitmapEvent {
+            dest_left: left,
+            dest_top: top,
+            dest_right: left + width - 1,
+            dest_bottom: top + height - 1,
+            width,
+            height,
+            bpp: 32,
+            is_compress: false,
+            codec: None,
+            monitor: None,
+            palette: None,
+            data: vec![0xff; width as usize * height as usize * 4],
+        }
+    }
+
+    #[test]
+    fn test_session_recorder_y4m() {
+        let output = SharedBuffer::default();
+        let mut recorder = SessionRecorder::new(output.clone());
+        let start = Instant::now();
+        recorder.start(4, 2, start).unwrap();
+        recorder.draw(white(0, 0, 2, 2)).unwrap();
+        recorder.write_frames(start + Duration::from_millis(50)).unwrap();
+        assert_eq!(recorder.frames(), 1);
+        // 3 frames at 15 fps
+        recorder.write_frames(start + Duration::from_millis(200)).unwrap();
+        assert_eq!(recorder.frames(), 4);
+
+        let data = output.0.lock().unwrap().clone();
+        let header = b"YUV4MPEG2 W4 H2 F15:1 Ip A1:1 C420jpeg\n";
+        assert_eq!(&data[..header.len()], header);
+        let frames: Vec<&[u8]> = data[header.len()..].chunks(6 + 8 + 2 + 2).collect();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], b"FRAME\n\x10\x10\x10\x10\x10\x10\x10\x10\x80\x80\x80\x80");
+        for frame in &frames[1..] {
+            assert_eq!(&frame[..6], b"FRAME\n");
+            assert_eq!(frame[6..14], [235, 235, 16, 16, 235, 235, 16, 16]);
+            assert_eq!(frame[14..], [128, 128, 128, 128]);
+        }
+    }
+
+    #[test]
+    fn test_session_recorder_start_once() {
+        let output = SharedBuffer::default();
+        let mut recorder = SessionRecorder::new(output.clone()).fps(0);
+        // nothing is drawn before the start
+        recorder.draw(white(0, 0, 1, 1)).unwrap();
+        let start = Instant::now();
+        recorder.start(2, 2, start).unwrap();
+        recorder.start(8, 8, start + Duration::from_secs(1)).unwrap();
+        assert_eq!(recorder.frames(), 1);
+        let data = output.0.lock().unwrap().clone();
+        let header = b"YUV4MPEG2 W2 H2 F15:1 Ip A1:1 C420jpeg\n";
+        assert_eq!(data[..header.len()], header[..]);
+        assert_eq!(data[header.len()..], b"FRAME\n\x10\x10\x10\x10\x80\x80"[..]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::core::client::Connector;
+#[cfg(feature = "image")]
+use crate::core::event::bgra_to_image;
+use crate::core::event::{BitmapEvent, RdpEvent};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::time::Instant;
+
+/// Default time allowed to receive the whole screen
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Framebuffer filled by bitmap updates
+/// Keep track of pixels already received
+pub(crate) struct FrameBuffer {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// 32 bpp BGRA pixels
+    pub(crate) data: Vec<u8>,
+    covered: Vec<bool>,
+    /// Number of pixels never received
+    remaining: usize,
+}
+
+impl FrameBuffer {
+    pub(crate) fn new(width: u16, height: u16) -> Self {
+        let size = width as usize * height as usize;
+        FrameBuffer {
+            width: width as usize,
+            height: height as usize,
+            data: vec![0; size * 4],
+            covered: vec![false; size],
+            remaining: size,
+        }
+    }
+
+    /// Copy a bitmap update into the framebuffer
+    /// Parts outside of the screen are ignored
+    pub(crate) fn draw(&mut self, bitmap: BitmapEvent) -> RdpResult<()> {
+        let left = bitmap.dest_left as usize;
+        let top = bitmap.dest_top as usize;
+        let right = (bitmap.dest_right as usize).min(self.width.saturating_sub(1));
+        let bottom = (bitmap.dest_bottom as usize).min(self.height.saturating_sub(1));
+        let bitmap_width = bitmap.width as usize;
+        let bitmap_height = bitmap.height as usize;
+        let data = bitmap.decompress()?;
+
+        if left > right || top > bottom {
+            return Ok(());
+        }
+        if right - left >= bitmap_width
+            || bottom - top >= bitmap_height
+            || data.len() < bitmap_width * bitmap_height * 4
+        {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidSize,
+                "SCREENSHOT: Bitmap smaller than its destination",
+            )));
+        }
+
+        let count = right - left + 1;
+        for row in 0..=(bottom - top) {
+            let src = row * bitmap_width * 4;
+            let dest = (top + row) * self.width + left;
+            self.data[dest * 4..(dest + count) * 4].copy_from_slice(&data[src..src + count * 4]);
+            for covered in &mut self.covered[dest..dest + count] {
+                if !*covered {
+                    *covered = true;
+                    self.remaining -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool { self.remaining == 0 }
+}
+
+/// Connect to a server, capture the whole screen
+/// once and disconnect
+///
+/// Blocking reads are not interrupted by the capture timeout,
+/// set a read timeout on the stream to bound them.
+/// Reads which time out are retried until the capture timeout,
+/// which also covers the connection sequence
+///
+/// # Example
+/// ```no_run
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+/// use rdp::core::client::Connector;
+/// use rdp::core::screenshot::ScreenshotClient;
+/// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+/// tcp.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+/// let connector = Connector::new()
+///     .screen(800, 600)
+///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
+/// let bgra = ScreenshotClient::new(connector).timeout(Duration::from_secs(10)).capture(tcp).unwrap();
+/// ```
+pub struct ScreenshotClient {
+    connector: Connector,
+    timeout: Duration,
+}
+
+impl ScreenshotClient {
+    pub fn new(connector: Connector) -> Self { ScreenshotClient { connector, timeout: DEFAULT_TIMEOUT } }
+
+    /// Time allowed to receive the whole screen
+    /// default is 30 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Capture the screen as raw 32 bpp BGRA pixels
+    pub fn capture<S: Read + Write>(self, stream: S) -> RdpResult<Vec<u8>> { Ok(self.capture_frame(stream)?.data) }
+
+    /// Capture the screen encoded as a PNG image
+    #[cfg(feature = "image")]
+    pub fn capture_png<S: Read + Write>(self, stream: S) -> RdpResult<Vec<u8>> {
+        let framebuffer = self.capture_frame(stream)?;
+        encode_png(framebuffer.width as u32, framebuffer.height as u32, framebuffer.data)
+    }
+
+    /// Drive the connection until the whole screen is received
+    fn capture_frame<S: Read + Write>(self, stream: S) -> RdpResult<FrameBuffer> {
+        let deadline = Instant::now() + self.timeout;
+        // The read timeout of the stream is reported as a timeout
+        let mut connector = self.connector.socket_timeouts(true);
+        let mut client = match connector.connect_blocking(stream) {
+            Err(e) if is_no_data(&e) => return Err(capture_timeout()),
+            result => result?,
+        };
+        let (width, height) = client.screen_size();
+        let mut framebuffer = FrameBuffer::new(width, height);
+
+        while !framebuffer.is_complete() {
+            if Instant::now() >= deadline {
+                client.shutdown()?;
+                return Err(capture_timeout());
+            }
+
+            let read_result = client.read(|event| {
+                if let RdpEvent::Bitmap(bitmap) = event {
+                    // Other bitmaps can still cover the same area
+                    if let Err(e) = framebuffer.draw(bitmap) {
+                        log_warn!("SCREENSHOT: Bitmap not drawn {:?}", e);
+                    }
+                }
+            });
+            match read_result {
+                Err(e) if is_no_data(&e) => continue,
+                result => result?,
+            }
+        }
+
+        client.shutdown()?;
+        Ok(framebuffer)
+    }
+}
+
+/// The server sent nothing before the read timeout of the stream
+fn is_no_data(error: &Error) -> bool {
+    match error {
+        Error::RdpError(e) => e.kind() == RdpErrorKind::Timeout,
+        Error::Io(e) => matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut),
+        _ => false,
+    }
+}
+
+fn capture_timeout() -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::Timeout, "SCREENSHOT: Screen not fully received in time"))
+}
+
+/// Encode BGRA pixels as an opaque PNG image
+#[cfg(feature = "image")]
+fn encode_png(width: u32, height: u32, pixels: Vec<u8>) -> RdpResult<Vec<u8>> {
+    let image = bgra_to_image(width, height, pixels)?;
+    let mut result = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut result, image::ImageFormat::Png).map_err(|e| {
+        Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("SCREENSHOT: PNG encoding failed {}", e)))
+    })?;
+    Ok(result.into_inner())
+}
+
+/// Capture the screen as raw 32 bpp BGRA pixels
+/// using the default timeout
+pub fn screenshot<S: Read + Write>(connector: Connector, stream: S) -> RdpResult<Vec<u8>> {
+    ScreenshotClient::new(connector).capture(stream)
+}
+
+/// Capture the screen encoded as a PNG image
+/// using the default timeout
+#[cfg(feature = "image")]
+pub fn screenshot_png<S: Read + Write>(connector: Connector, stream: S) -> RdpResult<Vec<u8>> {
+    ScreenshotClient::new(connector).capture_png(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitmap(left: u16, top: u16, right: u16, bottom: u16, width: u16, height: u16) -> BitmapEvent {
+        BitmapEvent {
+            dest_left: left,
+            dest_top: top,
+            dest_right: right,
+            dest_bottom: bottom,
+            width,
+            height,
+            bpp: 32,
+            is_compress: false,
+            codec: None,
+            monitor: None,
+            palette: None,
+            data: (0..width as usize * height as usize * 4).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn test_framebuffer_coverage() {
+        let mut framebuffer = FrameBuffer::new(4, 2);
+        framebuffer.draw(bitmap(0, 0, 1, 1, 2, 2)).unwrap();
+        assert!(!framebuffer.is_complete());
+        // overlapping update must not be counted twice
+        framebuffer.draw(bitmap(1, 0, 2, 1, 2, 2)).unwrap();
+        assert_eq!(framebuffer.remaining, 2);
+        // bitmap bigger than the screen is clipped
+        framebuffer.draw(bitmap(3, 0, 4, 1, 2, 2)).unwrap();
+        assert!(framebuffer.is_complete());
+        assert_eq!(framebuffer.data[12..16], [0, 1, 2, 3]);
+        assert_eq!(framebuffer.data[16..20], [8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn test_framebuffer_invalid_bitmap() {
+        let mut framebuffer = FrameBuffer::new(4, 2);
+        assert!(framebuffer.draw(bitmap(0, 0, 3, 1, 2, 2)).is_err());
+    }
+
+    #[test]
+    fn test_is_no_data() {
+        assert!(is_no_data(&Error::from(std::io::Error::from(std::io::ErrorKind::WouldBlock))));
+        assert!(is_no_data(&capture_timeout()));
+        assert!(!is_no_data(&Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset))));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_encode_png() {
+        let png = encode_png(1, 1, vec![1, 2, 3, 0]).unwrap();
+        assert_eq!(png[1..4], *b"PNG");
+    }
+}
@@ -1,8 +1,11 @@
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 
+use crate::core::autodetect::AutoDetector;
+use crate::core::license::LicenseStore;
 use crate::core::{license, mcs, tpkt};
-use crate::model::data::{Component, DataType, DynOption, Message, MessageOption, Trame, U16, U32};
-use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::data::{Component, DataType, DynOption, Message, MessageOption, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult, RdpWarning};
+use crate::model::link::{read_when_ready, run_blocking, Blocking, FrameWait};
 use crate::model::unicode::Unicode;
 
 /// Security flag send as header flage in core ptotocol
@@ -10,7 +13,7 @@ use crate::model::unicode::Unicode;
 #[repr(u16)]
 #[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
-enum SecurityFlag {
+pub(crate) enum SecurityFlag {
     SecExchangePkt = 0x0001,
     SecTransportReq = 0x0002,
     RdpSecTransportRsp = 0x0004,
@@ -61,18 +64,159 @@ enum AfInet {
     AfInet6 = 0x0017,
 }
 
+/// Visual effects disabled or enabled by the client
+///
+/// See MS-RDPBCGR 2.2.1.11.1.1.1
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PerformanceFlags(u32);
+
+impl PerformanceFlags {
+    pub const DISABLE_CURSOR_SETTINGS: Self = PerformanceFlags(0x0000_0040);
+    pub const DISABLE_CURSOR_SHADOW: Self = PerformanceFlags(0x0000_0020);
+    pub const DISABLE_FULL_WINDOW_DRAG: Self = PerformanceFlags(0x0000_0002);
+    pub const DISABLE_MENU_ANIMATIONS: Self = PerformanceFlags(0x0000_0004);
+    pub const DISABLE_THEMING: Self = PerformanceFlags(0x0000_0008);
+    pub const DISABLE_WALLPAPER: Self = PerformanceFlags(0x0000_0001);
+    pub const ENABLE_DESKTOP_COMPOSITION: Self = PerformanceFlags(0x0000_0100);
+    pub const ENABLE_FONT_SMOOTHING: Self = PerformanceFlags(0x0000_0080);
+    /// Effects disabled on slow connections
+    /// The flags of the modem profile of mstsc
+    pub const LOW_BANDWIDTH: Self = PerformanceFlags(
+        Self::DISABLE_WALLPAPER.0
+            | Self::DISABLE_FULL_WINDOW_DRAG.0
+            | Self::DISABLE_MENU_ANIMATIONS.0
+            | Self::DISABLE_THEMING.0
+            | Self::DISABLE_CURSOR_SHADOW.0,
+    );
+
+    /// No flag, the server keeps its own settings
+    pub const fn empty() -> Self { PerformanceFlags(0) }
+
+    /// Value sent in the info packet
+    pub const fn bits(self) -> u32 { self.0 }
+
+    /// True if all flags of `other` are set
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::sec::PerformanceFlags;
+    /// let flags = PerformanceFlags::DISABLE_WALLPAPER | PerformanceFlags::DISABLE_THEMING;
+    /// assert!(flags.contains(PerformanceFlags::DISABLE_THEMING));
+    /// assert!(!flags.contains(PerformanceFlags::ENABLE_FONT_SMOOTHING));
+    /// ```
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+impl std::ops::BitOr for PerformanceFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self { PerformanceFlags(self.0 | rhs.0) }
+}
+
+impl std::ops::BitOrAssign for PerformanceFlags {
+    fn bitor_assign(&mut self, rhs: Self) { self.0 |= rhs.0; }
+}
+
+/// Date of a daylight saving transition
+/// Month, day of week and day select a day in the month
+///
+/// See MS-RDPBCGR 2.2.1.11.1.1.1.1
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SystemTime {
+    pub year: u16,
+    pub month: u16,
+    pub day_of_week: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+    pub milliseconds: u16,
+}
+
+/// Time zone of the client
+/// Biases are in minutes, UTC = local time + bias
+///
+/// See MS-RDPBCGR 2.2.1.11.1.1.1.1
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TimeZoneInfo {
+    pub bias: i32,
+    /// At most 31 characters
+    pub standard_name: String,
+    pub standard_date: SystemTime,
+    pub standard_bias: i32,
+    /// At most 31 characters
+    pub daylight_name: String,
+    pub daylight_date: SystemTime,
+    pub daylight_bias: i32,
+}
+
+/// Session settings of the info packet
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ClientInfo {
+    /// Working directory of the shell
+    pub working_dir: Option<String>,
+    /// Program started instead of the desktop shell
+    pub alternate_shell: Option<String>,
+    /// None announces UTC
+    pub time_zone: Option<TimeZoneInfo>,
+    pub performance_flags: PerformanceFlags,
+    /// Start a RemoteApp session instead of a desktop
+    pub remote_app: bool,
+}
+
+/// Null terminated UTF-16 string
+fn unicode_string(value: &str) -> Vec<u8> {
+    let mut result = value.to_utf16_le();
+    result.push(0);
+    result.push(0);
+    result
+}
+
+/// Time zone name on 32 UTF-16 characters
+fn time_zone_name(name: &str) -> Vec<u8> {
+    let mut result: Vec<u8> = name.encode_utf16().take(31).flat_map(|c| c.to_le_bytes()).collect();
+    result.resize(64, 0);
+    result
+}
+
+fn system_time(time: &SystemTime) -> Component {
+    component![
+        "wYear" => U16::LE(time.year),
+        "wMonth" => U16::LE(time.month),
+        "wDayOfWeek" => U16::LE(time.day_of_week),
+        "wDay" => U16::LE(time.day),
+        "wHour" => U16::LE(time.hour),
+        "wMinute" => U16::LE(time.minute),
+        "wSecond" => U16::LE(time.second),
+        "wMilliseconds" => U16::LE(time.milliseconds)
+    ]
+}
+
+/// Time zone information of the extended info packet
+fn time_zone_information(time_zone: &TimeZoneInfo) -> Component {
+    component![
+        "Bias" => U32::LE(time_zone.bias as u32),
+        "StandardName" => time_zone_name(&time_zone.standard_name),
+        "StandardDate" => system_time(&time_zone.standard_date),
+        "StandardBias" => U32::LE(time_zone.standard_bias as u32),
+        "DaylightName" => time_zone_name(&time_zone.daylight_name),
+        "DaylightDate" => system_time(&time_zone.daylight_date),
+        "DaylightBias" => U32::LE(time_zone.daylight_bias as u32)
+    ]
+}
+
 /// On RDP version > 5
 /// Client have to send IP information
-fn rdp_extended_infos() -> Component {
+fn rdp_extended_infos(info: &ClientInfo) -> Component {
     component![
         "clientAddressFamily" => U16::LE(AfInet::AfInet as u16),
         "cbClientAddress" => DynOption::new(U16::LE(0), |x| MessageOption::Size("clientAddress".to_string(), x.inner() as usize + 2)),
         "clientAddress" => b"\x00\x00".to_vec(),
         "cbClientDir" => U16::LE(0),
         "clientDir" => b"\x00\x00".to_vec(),
-        "clientTimeZone" => vec![0; 172],
+        "clientTimeZone" => time_zone_information(info.time_zone.as_ref().unwrap_or(&TimeZoneInfo::default())),
         "clientSessionId" => U32::LE(0),
-        "performanceFlags" => U32::LE(0)
+        "performanceFlags" => U32::LE(info.performance_flags.bits())
     ]
 }
 
@@ -80,19 +224,13 @@ fn rdp_extended_infos() -> Component {
 /// interactive logon used credentials
 /// present in this payload
 fn rdp_infos(
-    is_extended_info: bool, domain: &String, username: &String, password: &String, auto_logon: bool,
+    is_extended_info: bool, domain: &str, username: &str, password: &str, auto_logon: bool, info: &ClientInfo,
 ) -> Component {
-    let mut domain_format = domain.to_utf16_le();
-    domain_format.push(0);
-    domain_format.push(0);
-
-    let mut username_format = username.to_utf16_le();
-    username_format.push(0);
-    username_format.push(0);
-
-    let mut password_format = password.to_utf16_le();
-    password_format.push(0);
-    password_format.push(0);
+    let domain_format = unicode_string(domain);
+    let username_format = unicode_string(username);
+    let password_format = unicode_string(password);
+    let alternate_shell = unicode_string(info.alternate_shell.as_deref().unwrap_or_default());
+    let working_dir = unicode_string(info.working_dir.as_deref().unwrap_or_default());
 
     component![
         "codePage" => U32::LE(0),
@@ -103,19 +241,20 @@ fn rdp_infos(
             InfoFlag::Logonerrors as u32 |
             InfoFlag::Disablectrlaltdel as u32 |
             InfoFlag::Enablewindowskey as u32 |
-            if auto_logon { InfoFlag::Autologon as u32 } else { 0 }
+            if auto_logon { InfoFlag::Autologon as u32 } else { 0 } |
+            if info.remote_app { InfoFlag::Rail as u32 } else { 0 }
         ),
         "cbDomain" => U16::LE((domain_format.len() - 2) as u16),
         "cbUserName" => U16::LE((username_format.len() - 2) as u16),
         "cbPassword" => U16::LE((password_format.len() - 2) as u16),
-        "cbAlternateShell" => U16::LE(0),
-        "cbWorkingDir" => U16::LE(0),
+        "cbAlternateShell" => U16::LE((alternate_shell.len() - 2) as u16),
+        "cbWorkingDir" => U16::LE((working_dir.len() - 2) as u16),
         "domain" => domain_format,
         "userName" => username_format,
         "password" => password_format,
-        "alternateShell" => b"\x00\x00".to_vec(),
-        "workingDir" => b"\x00\x00".to_vec(),
-        "extendedInfos" => if is_extended_info { rdp_extended_infos() } else { component![] }
+        "alternateShell" => alternate_shell,
+        "workingDir" => working_dir,
+        "extendedInfos" => if is_extended_info { rdp_extended_infos(info) } else { component![] }
     ]
 }
 
@@ -127,11 +266,60 @@ fn security_header() -> Component {
     ]
 }
 
+/// Server heartbeat PDU
+/// Sent by the server to signal the connection is alive
+///
+/// See MS-RDPBCGR 2.2.16.1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Heartbeat {
+    /// Delay in seconds between two heartbeats
+    pub period: u8,
+    /// Missed heartbeats before a warning
+    pub warning_count: u8,
+    /// Missed heartbeats before a reconnection
+    pub reconnect_count: u8,
+}
+
+/// Read a server heartbeat PDU
+/// Other payloads are left untouched and None is returned
+///
+/// A heartbeat is the only PDU of the global channel
+/// with a security header once connected,
+/// the high flags field is never null for a share control header
+pub fn read_heartbeat(stream: &mut Cursor<Vec<u8>>) -> RdpResult<Option<Heartbeat>> {
+    let position = stream.position();
+    if stream.get_ref().len() as u64 - position != 8 {
+        return Ok(None);
+    }
+    let mut header = security_header();
+    header.read(stream)?;
+    if cast!(DataType::U16, header["securityFlag"])? & SecurityFlag::SecHeartbeat as u16 == 0
+        || cast!(DataType::U16, header["securityFlagHi"])? != 0
+    {
+        stream.set_position(position);
+        return Ok(None);
+    }
+    let mut heartbeat = component![
+        "reserved" => 0_u8,
+        "period" => 0_u8,
+        "count1" => 0_u8,
+        "count2" => 0_u8
+    ];
+    heartbeat.read(stream)?;
+    Ok(Some(Heartbeat {
+        period: cast!(DataType::U8, heartbeat["period"])?,
+        warning_count: cast!(DataType::U8, heartbeat["count1"])?,
+        reconnect_count: cast!(DataType::U8, heartbeat["count2"])?,
+    }))
+}
+
 /// Security layer need mcs layer and send all message through
 /// the global channel
 ///
 /// This function is called sec because old RDP security
 /// was made here
+/// A license issued by the server is saved in `license_store`
+/// connect-time auto-detection requests are answered by `autodetect`
 ///
 /// # Example
 /// ```rust, ignore
@@ -139,26 +327,148 @@ fn security_header() -> Component {
 /// let mut mcs = mcs::Client(...).unwrap();
 /// sec::connect(&mut mcs).unwrap();
 /// ```
+#[allow(clippy::too_many_arguments)]
 pub fn connect<T: Read + Write>(
-    mcs: &mut mcs::Client<T>, domain: &String, username: &String, password: &String, auto_logon: bool,
+    mcs: &mut mcs::Client<T>, domain: &str, username: &str, password: &str, auto_logon: bool, info: &ClientInfo,
+    license_store: Option<&mut dyn LicenseStore>, autodetect: &mut AutoDetector,
 ) -> RdpResult<()> {
-    mcs.write(
-        &"global".to_string(),
-        trame![
-            U16::LE(SecurityFlag::SecInfoPkt as u16),
-            U16::LE(0),
-            rdp_infos(mcs.is_rdp_version_5_plus(), domain, username, password, auto_logon)
-        ],
-    )?;
-
-    let (_channel_name, payload) = mcs.read()?;
-    let mut stream = try_let!(tpkt::Payload::Raw, payload)?;
-    let mut header = security_header();
-    header.read(&mut stream)?;
-    if cast!(DataType::U16, header["securityFlag"])? & SecurityFlag::SecLicensePkt as u16 == 0 {
-        return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "SEC: Invalid Licence packet")));
-    }
+    run_blocking(connect_with(
+        mcs,
+        domain,
+        username,
+        password,
+        auto_logon,
+        info,
+        license_store,
+        autodetect,
+        &mut Blocking,
+    ))
+}
+
+/// Same as `connect`, each PDU of the server is read once `wait` resolves
+#[allow(clippy::too_many_arguments)]
+pub async fn connect_with<T: Read + Write>(
+    mcs: &mut mcs::Client<T>, domain: &str, username: &str, password: &str, auto_logon: bool, info: &ClientInfo,
+    license_store: Option<&mut dyn LicenseStore>, autodetect: &mut AutoDetector, wait: &mut impl FrameWait,
+) -> RdpResult<()> {
+    let infos = rdp_infos(mcs.is_rdp_version_5_plus(), domain, username, password, auto_logon, info);
+    mcs.write_with_security_header(&"global".to_string(), SecurityFlag::SecInfoPkt as u16, infos)?;
+
+    // Connect-time auto-detection comes before the licensing
+    let mut stream = loop {
+        let (channel_name, payload) = read_when_ready(wait, || mcs.read()).await?;
+        let mut stream = try_let!(tpkt::Payload::Raw, payload)?;
+        let mut header = security_header();
+        header.read(&mut stream)?;
+        let flags = cast!(DataType::U16, header["securityFlag"])?;
+        if flags & SecurityFlag::SecAutodetectReq as u16 != 0 {
+            autodetect.read_request(&channel_name, &mut stream, mcs)?;
+        } else if flags & SecurityFlag::SecLicensePkt as u16 == 0 {
+            return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "SEC: Invalid Licence packet")));
+        } else {
+            break stream;
+        }
+    };
 
-    license::client_connect(&mut stream)?;
+    if let Some(error_code) = license::client_connect(&mut stream, license_store)? {
+        mcs.warn(RdpWarning::LicenseWarning(error_code));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpStream;
+
+    use super::*;
+    use crate::core::x224;
+    use crate::model::data::to_vec;
+    use crate::model::link::{Link, Stream};
+    use crate::test_utils::mock_server::{fixture, is_x224_data, MockRdpServer};
+
+    /// Play the license negotiation against a mock server
+    /// answering the client info PDU with a recorded license PDU
+    fn license_negotiation(license: &str) -> RdpResult<()> {
+        let server = MockRdpServer::new()
+            .expect_receive(|_| true)
+            .send(&fixture("x224_confirm_rdp.bin"))
+            // send data request of user 1007 on the global channel
+            .expect_receive(is_x224_data(&[0x64, 0x00, 0x06, 0x03, 0xeb, 0x70]))
+            .send(&fixture(license));
+        let address = server.address();
+        let server = server.run();
+
+        let link = Link::new(Stream::Raw(TcpStream::connect(address)?));
+        let x224 = x224::Client::connect(tpkt::Client::new(link), 0, false, None, false, false)?;
+        let mut mcs = mcs::Client::connected(x224, 1007, &[("global", 1003)]);
+        let result =
+            connect(&mut mcs, "", "user", "password", false, &ClientInfo::default(), None, &mut AutoDetector::new());
+        server.join().unwrap()?;
+        result
+    }
+
+    #[test]
+    fn test_license_valid_client() { license_negotiation("license_valid_client.bin").unwrap(); }
+
+    #[test]
+    fn test_license_invalid_client() {
+        match license_negotiation("license_invalid_client.bin") {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidRespond),
+            _ => panic!("license must be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_rdp_infos_alternate_shell() {
+        let info = ClientInfo {
+            working_dir: Some("C:\\".to_string()),
+            alternate_shell: Some("app.exe".to_string()),
+            time_zone: None,
+            performance_flags: PerformanceFlags::DISABLE_WALLPAPER | PerformanceFlags::DISABLE_THEMING,
+            remote_app: false,
+        };
+        let infos = rdp_infos(true, "", "user", "", false, &info);
+        assert_eq!(cast!(DataType::U16, infos["cbAlternateShell"]).unwrap(), 14);
+        assert_eq!(cast!(DataType::U16, infos["cbWorkingDir"]).unwrap(), 6);
+        assert_eq!(cast!(DataType::Slice, infos["alternateShell"]).unwrap(), unicode_string("app.exe").as_slice());
+        let extended = cast!(DataType::Component, infos["extendedInfos"]).unwrap();
+        assert_eq!(cast!(DataType::U32, extended["performanceFlags"]).unwrap(), 0x09);
+    }
+
+    #[test]
+    fn test_time_zone_information() {
+        let time_zone = TimeZoneInfo {
+            bias: -60,
+            standard_name: "Romance Standard Time".to_string(),
+            standard_date: SystemTime { month: 10, day_of_week: 0, day: 5, hour: 3, ..Default::default() },
+            standard_bias: 0,
+            daylight_name: "Romance Daylight Time".to_string(),
+            daylight_date: SystemTime { month: 3, day_of_week: 0, day: 5, hour: 2, ..Default::default() },
+            daylight_bias: -60,
+        };
+        let data = to_vec(&time_zone_information(&time_zone));
+        assert_eq!(data.len(), 172);
+        assert_eq!(data[0..4], [0xc4, 0xff, 0xff, 0xff]);
+        assert_eq!(data[4..6], [b'R', 0]);
+        // standard date month
+        assert_eq!(data[70..72], [10, 0]);
+        assert_eq!(data[168..172], [0xc4, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_read_heartbeat() {
+        let mut stream = Cursor::new(vec![0x00, 0x40, 0x00, 0x00, 0x00, 0x05, 0x02, 0x03]);
+        assert_eq!(
+            read_heartbeat(&mut stream).unwrap(),
+            Some(Heartbeat { period: 5, warning_count: 2, reconnect_count: 3 })
+        );
+    }
+
+    #[test]
+    fn test_read_heartbeat_share_control_header() {
+        // share control header of a long data PDU
+        let mut stream = Cursor::new(vec![0x08, 0x40, 0x17, 0x00, 0xea, 0x03, 0x00, 0x00]);
+        assert_eq!(read_heartbeat(&mut stream).unwrap(), None);
+        assert_eq!(stream.position(), 0);
+    }
+}
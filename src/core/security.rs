@@ -0,0 +1,727 @@
+//! Standard RDP security layer
+//!
+//! This layer is deprecated: the server is not authenticated
+//! and RC4 is broken, use TLS or NLA whenever the server supports it.
+//! It's only available for old servers and embedded devices
+//! which don't support TLS
+//!
+//! See MS-RDPBCGR 5.3
+
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use cbc::cipher::generic_array::GenericArray;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use num_bigint::BigUint;
+use num_enum::TryFromPrimitive;
+use sha1::Sha1;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use x509_parser::public_key::PublicKey;
+
+use crate::core::gcc::{EncryptionMethod, ServerSecurityData};
+use crate::core::mcs;
+use crate::model::data::{Message, U16, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::rnd::random;
+use crate::nla::rc4::Rc4;
+
+/// Flags of the basic security header
+/// See MS-RDPBCGR 2.2.8.1.1.2.1
+const SEC_EXCHANGE_PKT: u16 = 0x0001;
+const SEC_ENCRYPT: u16 = 0x0008;
+const SEC_RESET_SEQNO: u16 = 0x0010;
+const SEC_IGNORE_SEQNO: u16 = 0x0020;
+const SEC_SECURE_CHECKSUM: u16 = 0x0800;
+
+/// Fast path output is encrypted
+const FASTPATH_OUTPUT_ENCRYPTED: u8 = 0x2;
+/// Fast path input is encrypted
+pub const FASTPATH_INPUT_ENCRYPTED: u8 = 0x2;
+/// Fast path output use a salted MAC
+const FASTPATH_OUTPUT_SECURE_CHECKSUM: u8 = 0x1;
+
+/// Keys are updated after 4096 packets
+const KEY_UPDATE_INTERVAL: u32 = 4096;
+
+/// Initialization vector of FIPS sessions
+const FIPS_IV: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x90, 0xab, 0xcd, 0xef];
+/// Version of the FIPS security header
+const TSFIPS_VERSION1: u8 = 0x01;
+
+/// Magic of the RSA public key blob
+const RSA1_MAGIC: u32 = 0x3141_5352;
+
+const PAD1: [u8; 40] = [0x36; 40];
+const PAD2: [u8; 48] = [0x5c; 48];
+
+fn security_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("SECURITY: {}", message)))
+}
+
+fn sha1(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn md5(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Md5::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Type of certificate of the server
+/// See MS-RDPBCGR 2.2.1.4.3.1
+#[repr(u32)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum CertificateType {
+    Proprietary = 0x0000_0001,
+    X509 = 0x0000_0002,
+}
+
+/// RSA public key of the server
+/// Numbers are little endian
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsaPublicKey {
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+}
+
+impl RsaPublicKey {
+    /// Raw RSA encryption as the client random is not padded
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::security::RsaPublicKey;
+    /// let key = RsaPublicKey { modulus: vec![33], exponent: vec![3] };
+    /// assert_eq!(key.encrypt(&[4]), [31]);
+    /// ```
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let modulus = BigUint::from_bytes_le(&self.modulus);
+        let exponent = BigUint::from_bytes_le(&self.exponent);
+        let mut result = BigUint::from_bytes_le(data).modpow(&exponent, &modulus).to_bytes_le();
+        result.resize(self.modulus.len(), 0);
+        result
+    }
+}
+
+/// Read the public key of the server certificate
+///
+/// The signature of proprietary certificates is not checked
+/// as the signing key is published in the specification
+///
+/// See MS-RDPBCGR 2.2.1.4.3.1
+pub fn read_server_certificate(data: &[u8]) -> RdpResult<RsaPublicKey> {
+    let mut stream = Cursor::new(data);
+    // The top bit is set for temporary certificates
+    let version = stream.read_u32::<LittleEndian>()? & 0x7fff_ffff;
+    match CertificateType::try_from(version) {
+        Ok(CertificateType::Proprietary) => {
+            let _signature_algorithm = stream.read_u32::<LittleEndian>()?;
+            let _key_algorithm = stream.read_u32::<LittleEndian>()?;
+            let _blob_type = stream.read_u16::<LittleEndian>()?;
+            let _blob_length = stream.read_u16::<LittleEndian>()?;
+            if stream.read_u32::<LittleEndian>()? != RSA1_MAGIC {
+                return Err(security_error(RdpErrorKind::InvalidData, "Invalid RSA public key magic"));
+            }
+            let key_length = stream.read_u32::<LittleEndian>()?;
+            let bit_length = stream.read_u32::<LittleEndian>()?;
+            let _data_length = stream.read_u32::<LittleEndian>()?;
+            let exponent = stream.read_u32::<LittleEndian>()?.to_le_bytes().to_vec();
+            if key_length < bit_length / 8 {
+                return Err(security_error(RdpErrorKind::InvalidSize, "Invalid RSA key length"));
+            }
+            let mut modulus = vec![0; key_length as usize];
+            stream.read_exact(&mut modulus)?;
+            // the modulus is followed by 8 bytes of padding
+            modulus.truncate(bit_length as usize / 8);
+            Ok(RsaPublicKey { modulus, exponent })
+        }
+        Ok(CertificateType::X509) => {
+            let count = stream.read_u32::<LittleEndian>()?;
+            let mut certificate = Vec::new();
+            for _ in 0..count {
+                certificate = vec![0; stream.read_u32::<LittleEndian>()? as usize];
+                stream.read_exact(&mut certificate)?;
+            }
+            // The last certificate of the chain is the server one
+            let (_, certificate) = X509Certificate::from_der(&certificate)
+                .map_err(|_| security_error(RdpErrorKind::CertificateInvalid, "Invalid X.509 certificate"))?;
+            match certificate.public_key().parsed() {
+                Ok(PublicKey::RSA(key)) => {
+                    let le = |bytes: &[u8]| {
+                        let mut bytes = bytes.to_vec();
+                        bytes.reverse();
+                        // drop the sign byte
+                        while bytes.last() == Some(&0) {
+                            bytes.pop();
+                        }
+                        bytes
+                    };
+                    Ok(RsaPublicKey { modulus: le(key.modulus), exponent: le(key.exponent) })
+                }
+                _ => Err(security_error(RdpErrorKind::CertificateInvalid, "Server key is not a RSA key")),
+            }
+        }
+        Err(_) => Err(security_error(RdpErrorKind::CertificateInvalid, "Unknown certificate type")),
+    }
+}
+
+/// Keys of a RC4 session
+struct Rc4Keys {
+    /// Key length in bytes: 8 for 40 and 56 bits, 16 for 128 bits
+    key_length: usize,
+    method: EncryptionMethod,
+    mac_key: Vec<u8>,
+    encrypt_key: Vec<u8>,
+    decrypt_key: Vec<u8>,
+}
+
+/// Reduce a key for 40 and 56 bits sessions
+fn salt_key(method: EncryptionMethod, key: &mut [u8]) {
+    match method {
+        EncryptionMethod::EncryptionFlag40bit => key[..3].copy_from_slice(&[0xd1, 0x26, 0x9e]),
+        EncryptionMethod::EncryptionFlag56bit => key[0] = 0xd1,
+        _ => (),
+    }
+}
+
+/// Derive the RC4 session keys from the randoms
+///
+/// See MS-RDPBCGR 5.3.5.1
+fn rc4_session_keys(method: EncryptionMethod, client_random: &[u8], server_random: &[u8]) -> Rc4Keys {
+    let salted_hash =
+        |secret: &[u8], input: &[u8]| md5(&[secret, &sha1(&[input, secret, client_random, server_random])]);
+    let pre_master_secret = [&client_random[..24], &server_random[..24]].concat();
+    let master_secret =
+        [b"A".as_slice(), b"BB", b"CCC"].iter().flat_map(|i| salted_hash(&pre_master_secret, i)).collect::<Vec<u8>>();
+    let session_key_blob =
+        [b"X".as_slice(), b"YY", b"ZZZ"].iter().flat_map(|i| salted_hash(&master_secret, i)).collect::<Vec<u8>>();
+    let final_hash = |key: &[u8]| md5(&[key, client_random, server_random]);
+
+    let key_length = if method == EncryptionMethod::EncryptionFlag128bit { 16 } else { 8 };
+    let mut keys = Rc4Keys {
+        key_length,
+        method,
+        mac_key: session_key_blob[..key_length].to_vec(),
+        decrypt_key: final_hash(&session_key_blob[16..32])[..key_length].to_vec(),
+        encrypt_key: final_hash(&session_key_blob[32..48])[..key_length].to_vec(),
+    };
+    salt_key(method, &mut keys.mac_key);
+    salt_key(method, &mut keys.encrypt_key);
+    salt_key(method, &mut keys.decrypt_key);
+    keys
+}
+
+/// Compute the key used after 4096 packets
+///
+/// See MS-RDPBCGR 5.3.7.1
+fn update_key(method: EncryptionMethod, initial_key: &[u8], current_key: &[u8]) -> Vec<u8> {
+    let sha_component = sha1(&[initial_key, &PAD1, current_key]);
+    let temp_key = md5(&[initial_key, &PAD2, &sha_component]);
+    let mut new_key = vec![0; initial_key.len()];
+    Rc4::new(&temp_key[..initial_key.len()]).process(&temp_key[..initial_key.len()], &mut new_key);
+    salt_key(method, &mut new_key);
+    new_key
+}
+
+/// MAC of a RC4 session
+/// The salted MAC includes the packet count
+///
+/// See MS-RDPBCGR 5.3.6.1 and 5.3.6.1.1
+fn mac_signature(mac_key: &[u8], data: &[u8], count: Option<u32>) -> Vec<u8> {
+    let count = count.map(u32::to_le_bytes);
+    let sha = sha1(&[mac_key, &PAD1, &(data.len() as u32).to_le_bytes(), data, count.as_ref().map_or(&[], |c| c)]);
+    md5(&[mac_key, &PAD2, &sha])[..8].to_vec()
+}
+
+/// One direction of a RC4 session
+struct Rc4Stream {
+    method: EncryptionMethod,
+    initial_key: Vec<u8>,
+    key: Vec<u8>,
+    rc4: Rc4,
+    /// Packets processed with the current key
+    count: u32,
+    /// Packets processed since the start of the session,
+    /// the key update doesn't reset it
+    total: u32,
+}
+
+impl Rc4Stream {
+    fn new(method: EncryptionMethod, key: Vec<u8>) -> Self {
+        Rc4Stream { method, rc4: Rc4::new(&key), initial_key: key.clone(), key, count: 0, total: 0 }
+    }
+
+    fn process(&mut self, data: &mut [u8]) {
+        if self.count == KEY_UPDATE_INTERVAL {
+            self.key = update_key(self.method, &self.initial_key, &self.key);
+            self.rc4 = Rc4::new(&self.key);
+            self.count = 0;
+        }
+        let input = data.to_vec();
+        self.rc4.process(&input, data);
+        self.count += 1;
+        self.total = self.total.wrapping_add(1);
+    }
+}
+
+/// Expand a 168 bits key into a 3DES key with parity bits
+///
+/// The bits of each byte are read from the lowest one,
+/// as in `fips_expand_key_bits` of FreeRDP and xrdp
+/// See MS-RDPBCGR 5.3.5.2
+fn fips_expand_key(key: &[u8]) -> Vec<u8> {
+    let reversed: Vec<u8> = key[..21].iter().map(|byte| byte.reverse_bits()).collect();
+    (0..24)
+        .map(|i| {
+            let (index, shift) = (i * 7 / 8, i * 7 % 8);
+            let next = reversed.get(index + 1).copied().unwrap_or(0);
+            let window = u16::from(reversed[index]) << 8 | u16::from(next);
+            // seven bits followed by a zero bit, then reversed back
+            let byte = ((window << shift) >> 8) as u8 & 0xfe;
+            let byte = byte.reverse_bits() & 0xfe;
+            // odd parity in the low bit
+            byte | u8::from(byte.count_ones().is_multiple_of(2))
+        })
+        .collect()
+}
+
+type TdesEncryptor = cbc::Encryptor<des::TdesEde3>;
+type TdesDecryptor = cbc::Decryptor<des::TdesEde3>;
+
+/// Cipher of the session
+enum Cipher {
+    Rc4 {
+        mac_key: Vec<u8>,
+        encrypt: Box<Rc4Stream>,
+        decrypt: Box<Rc4Stream>,
+    },
+    Fips {
+        sign_key: Vec<u8>,
+        encrypt: Box<TdesEncryptor>,
+        decrypt: Box<TdesDecryptor>,
+        encrypt_count: u32,
+        decrypt_count: u32,
+    },
+}
+
+/// Encryption of the standard security layer
+/// Each direction keeps its own state
+pub struct SecurityContext {
+    cipher: Cipher,
+}
+
+impl std::fmt::Debug for SecurityContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("SecurityContext") }
+}
+
+impl SecurityContext {
+    /// Derive the session keys from the randoms exchanged during connection
+    pub fn new(method: EncryptionMethod, client_random: &[u8], server_random: &[u8]) -> RdpResult<Self> {
+        if client_random.len() < 32 || server_random.len() < 32 {
+            return Err(security_error(RdpErrorKind::InvalidSize, "Random must be 32 bytes"));
+        }
+        let cipher = match method {
+            EncryptionMethod::FipsEncryptionFlag => {
+                let mut encrypt_key = sha1(&[&client_random[16..32], &server_random[16..32]]);
+                encrypt_key.push(encrypt_key[0]);
+                let mut decrypt_key = sha1(&[&client_random[..16], &server_random[..16]]);
+                decrypt_key.push(decrypt_key[0]);
+                let sign_key = sha1(&[&decrypt_key[..20], &encrypt_key[..20]]);
+                let cipher_error = |_| security_error(RdpErrorKind::InvalidSize, "Invalid 3DES key");
+                Cipher::Fips {
+                    sign_key,
+                    encrypt: Box::new(
+                        TdesEncryptor::new_from_slices(&fips_expand_key(&encrypt_key), &FIPS_IV)
+                            .map_err(cipher_error)?,
+                    ),
+                    decrypt: Box::new(
+                        TdesDecryptor::new_from_slices(&fips_expand_key(&decrypt_key), &FIPS_IV)
+                            .map_err(cipher_error)?,
+                    ),
+                    encrypt_count: 0,
+                    decrypt_count: 0,
+                }
+            }
+            EncryptionMethod::EncryptionFlag40bit
+            | EncryptionMethod::EncryptionFlag56bit
+            | EncryptionMethod::EncryptionFlag128bit => {
+                let keys = rc4_session_keys(method, client_random, server_random);
+                debug_assert_eq!(keys.mac_key.len(), keys.key_length);
+                Cipher::Rc4 {
+                    mac_key: keys.mac_key,
+                    encrypt: Box::new(Rc4Stream::new(keys.method, keys.encrypt_key)),
+                    decrypt: Box::new(Rc4Stream::new(keys.method, keys.decrypt_key)),
+                }
+            }
+            EncryptionMethod::None => {
+                return Err(security_error(RdpErrorKind::InvalidProtocol, "No encryption method selected"))
+            }
+        };
+        Ok(SecurityContext { cipher })
+    }
+
+    /// Encrypt a PDU behind its security header
+    /// `flags` are the other flags of the header
+    pub fn encrypt_pdu(&mut self, flags: u16, data: &[u8]) -> RdpResult<Vec<u8>> {
+        let mut result = Vec::new();
+        U16::LE(flags | SEC_ENCRYPT).write(&mut result)?;
+        U16::LE(0).write(&mut result)?;
+        match &mut self.cipher {
+            Cipher::Rc4 { mac_key, encrypt, .. } => {
+                result.extend(mac_signature(mac_key, data, None));
+                let mut data = data.to_vec();
+                encrypt.process(&mut data);
+                result.extend(data);
+            }
+            Cipher::Fips { sign_key, encrypt, encrypt_count, .. } => {
+                let signature = fips_signature(sign_key, data, *encrypt_count)?;
+                let padding = (8 - data.len() % 8) % 8;
+                U16::LE(0x10).write(&mut result)?;
+                result.extend([TSFIPS_VERSION1, padding as u8]);
+                result.extend(signature);
+                let mut data = data.to_vec();
+                data.resize(data.len() + padding, 0);
+                for block in data.chunks_exact_mut(8) {
+                    encrypt.encrypt_block_mut(GenericArray::from_mut_slice(block));
+                }
+                *encrypt_count = encrypt_count.wrapping_add(1);
+                result.extend(data);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Encrypt a fast path input PDU
+    /// The signature and FIPS information replace the security header
+    pub fn encrypt_fast_path(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> {
+        Ok(self.encrypt_pdu(0, data)?.split_off(4))
+    }
+
+    /// Remove the security header of a PDU and decrypt it
+    ///
+    /// PDU which carry other flags than encryption ones
+    /// keep a security header, as with TLS
+    pub fn decrypt_pdu(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> {
+        let mut stream = Cursor::new(data);
+        let flags = stream.read_u16::<LittleEndian>()?;
+        let flags_hi = stream.read_u16::<LittleEndian>()?;
+        let body = if flags & SEC_ENCRYPT != 0 {
+            let salted = flags & SEC_SECURE_CHECKSUM != 0;
+            self.decrypt(&data[stream.position() as usize..], salted)?
+        } else {
+            data[stream.position() as usize..].to_vec()
+        };
+        let flags = flags & !(SEC_ENCRYPT | SEC_SECURE_CHECKSUM | SEC_RESET_SEQNO | SEC_IGNORE_SEQNO);
+        if flags == 0 {
+            return Ok(body);
+        }
+        let mut result = Vec::new();
+        U16::LE(flags).write(&mut result)?;
+        U16::LE(flags_hi).write(&mut result)?;
+        result.extend(body);
+        Ok(result)
+    }
+
+    /// Decrypt a fast path update
+    /// `data` starts after the length of the update
+    pub fn decrypt_fast_path(&mut self, sec_flag: u8, data: &[u8]) -> RdpResult<Vec<u8>> {
+        if sec_flag & FASTPATH_OUTPUT_ENCRYPTED == 0 {
+            return Ok(data.to_vec());
+        }
+        self.decrypt(data, sec_flag & FASTPATH_OUTPUT_SECURE_CHECKSUM != 0)
+    }
+
+    /// Check the signature and decrypt the data following the security header
+    fn decrypt(&mut self, data: &[u8], salted: bool) -> RdpResult<Vec<u8>> {
+        let mut stream = Cursor::new(data);
+        match &mut self.cipher {
+            Cipher::Rc4 { mac_key, decrypt, .. } => {
+                let mut signature = [0; 8];
+                stream.read_exact(&mut signature)?;
+                let mut body = data[stream.position() as usize..].to_vec();
+                // the salted MAC use the count of all decrypted packets
+                // See MS-RDPBCGR 5.3.6.1.1
+                let count = decrypt.total;
+                decrypt.process(&mut body);
+                if mac_signature(mac_key, &body, salted.then_some(count)) != signature {
+                    return Err(security_error(RdpErrorKind::InvalidChecksum, "Invalid MAC signature"));
+                }
+                Ok(body)
+            }
+            Cipher::Fips { sign_key, decrypt, decrypt_count, .. } => {
+                let _length = stream.read_u16::<LittleEndian>()?;
+                let _version = stream.read_u8()?;
+                let padding = stream.read_u8()? as usize;
+                let mut signature = [0; 8];
+                stream.read_exact(&mut signature)?;
+                let mut body = data[stream.position() as usize..].to_vec();
+                if !body.len().is_multiple_of(8) || padding > body.len() {
+                    return Err(security_error(RdpErrorKind::InvalidSize, "Invalid FIPS encrypted data"));
+                }
+                for block in body.chunks_exact_mut(8) {
+                    decrypt.decrypt_block_mut(GenericArray::from_mut_slice(block));
+                }
+                body.truncate(body.len() - padding);
+                let expected = fips_signature(sign_key, &body, *decrypt_count)?;
+                *decrypt_count = decrypt_count.wrapping_add(1);
+                if expected != signature {
+                    return Err(security_error(RdpErrorKind::InvalidChecksum, "Invalid FIPS signature"));
+                }
+                Ok(body)
+            }
+        }
+    }
+}
+
+/// HMAC of a FIPS session
+///
+/// See MS-RDPBCGR 5.3.6.2
+fn fips_signature(sign_key: &[u8], data: &[u8], count: u32) -> RdpResult<Vec<u8>> {
+    let mut hmac = Hmac::<Sha1>::new_from_slice(sign_key)
+        .map_err(|_| security_error(RdpErrorKind::InvalidSize, "Invalid HMAC key"))?;
+    hmac.update(data);
+    hmac.update(&count.to_le_bytes());
+    Ok(hmac.finalize().into_bytes()[..8].to_vec())
+}
+
+/// Security exchange PDU
+/// Carry the client random encrypted with the server public key
+///
+/// See MS-RDPBCGR 2.2.1.10
+fn security_exchange_pdu(encrypted_client_random: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut append = |message: &dyn Message| message.write(&mut result);
+    let length = encrypted_client_random.len() as u32 + 8;
+    // Writing into a vector can't fail
+    append(&U32::LE(length)).unwrap();
+    result.extend_from_slice(encrypted_client_random);
+    result.extend_from_slice(&[0; 8]);
+    result
+}
+
+/// Exchange the client random and enable encryption
+/// Must be called once the MCS channels are joined
+///
+/// See MS-RDPBCGR 5.3.4
+pub fn connect<S: Read + Write>(mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+    let server_security: &ServerSecurityData = mcs.get_server_security();
+    let method = EncryptionMethod::try_from(server_security.encryption_method)
+        .map_err(|_| security_error(RdpErrorKind::InvalidProtocol, "Unknown encryption method"))?;
+    let server_random = server_security.server_random.clone();
+    let public_key = read_server_certificate(&server_security.server_certificate)?;
+
+    let client_random = random(32);
+    mcs.write_with_security_header(
+        &"global".to_string(),
+        SEC_EXCHANGE_PKT,
+        security_exchange_pdu(&public_key.encrypt(&client_random)),
+    )?;
+    mcs.set_security_context(SecurityContext::new(method, &client_random, &server_random)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLIENT_RANDOM: [u8; 32] = [1; 32];
+    const SERVER_RANDOM: [u8; 32] = [2; 32];
+
+    /// Server side of a session, directions are swapped
+    fn server_context(method: EncryptionMethod) -> SecurityContext {
+        let mut server = SecurityContext::new(method, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        match &mut server.cipher {
+            Cipher::Rc4 { encrypt, decrypt, .. } => std::mem::swap(encrypt, decrypt),
+            Cipher::Fips { sign_key, .. } => {
+                let mut encrypt_key = sha1(&[&CLIENT_RANDOM[16..32], &SERVER_RANDOM[16..32]]);
+                encrypt_key.push(encrypt_key[0]);
+                let mut decrypt_key = sha1(&[&CLIENT_RANDOM[..16], &SERVER_RANDOM[..16]]);
+                decrypt_key.push(decrypt_key[0]);
+                server.cipher = Cipher::Fips {
+                    sign_key: sign_key.clone(),
+                    encrypt: Box::new(
+                        TdesEncryptor::new_from_slices(&fips_expand_key(&decrypt_key), &FIPS_IV).unwrap(),
+                    ),
+                    decrypt: Box::new(
+                        TdesDecryptor::new_from_slices(&fips_expand_key(&encrypt_key), &FIPS_IV).unwrap(),
+                    ),
+                    encrypt_count: 0,
+                    decrypt_count: 0,
+                };
+            }
+        }
+        server
+    }
+
+    fn round_trip(method: EncryptionMethod) {
+        let mut client = SecurityContext::new(method, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        let mut server = server_context(method);
+        for length in [0, 1, 7, 8, 100] {
+            let data: Vec<u8> = (0..length as u8).collect();
+            let encrypted = client.encrypt_pdu(0, &data).unwrap();
+            if length > 0 {
+                assert_ne!(encrypted[encrypted.len() - data.len()..], data[..]);
+            }
+            assert_eq!(server.decrypt_pdu(&encrypted).unwrap(), data);
+            let encrypted = server.encrypt_pdu(0, &data).unwrap();
+            assert_eq!(client.decrypt_pdu(&encrypted).unwrap(), data);
+        }
+    }
+
+    // known answers of the RC4 tests come from an implementation
+    // of MS-RDPBCGR 5.3.5.1, 5.3.6.1.1 and 5.3.7.1 apart from this one
+
+    #[test]
+    fn test_rc4_session_keys() {
+        let keys = rc4_session_keys(EncryptionMethod::EncryptionFlag128bit, &CLIENT_RANDOM, &SERVER_RANDOM);
+        assert_eq!(
+            keys.mac_key,
+            [0x46, 0x81, 0x38, 0x42, 0xe4, 0x04, 0x2e, 0xa3, 0x0e, 0x59, 0x3a, 0xeb, 0x91, 0x27, 0xbe, 0xea]
+        );
+        assert_eq!(
+            keys.decrypt_key,
+            [0x30, 0xe8, 0x74, 0xe9, 0x3c, 0x7d, 0x20, 0x73, 0x19, 0x7a, 0xda, 0xb4, 0x7f, 0x96, 0xf7, 0xe7]
+        );
+        assert_eq!(
+            keys.encrypt_key,
+            [0xfc, 0x98, 0x6b, 0x2b, 0xea, 0x46, 0x24, 0x43, 0x54, 0xe1, 0x6c, 0x46, 0x06, 0x43, 0x79, 0xa4]
+        );
+
+        let keys = rc4_session_keys(EncryptionMethod::EncryptionFlag40bit, &CLIENT_RANDOM, &SERVER_RANDOM);
+        assert_eq!(keys.mac_key, [0xd1, 0x26, 0x9e, 0x42, 0xe4, 0x04, 0x2e, 0xa3]);
+        assert_eq!(keys.decrypt_key, [0xd1, 0x26, 0x9e, 0xe9, 0x3c, 0x7d, 0x20, 0x73]);
+        assert_eq!(keys.encrypt_key, [0xd1, 0x26, 0x9e, 0x2b, 0xea, 0x46, 0x24, 0x43]);
+    }
+
+    #[test]
+    fn test_rc4_encrypt_pdu() {
+        let mut client =
+            SecurityContext::new(EncryptionMethod::EncryptionFlag128bit, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        assert_eq!(
+            client.encrypt_pdu(0, b"foo").unwrap(),
+            [0x08, 0x00, 0x00, 0x00, 0x1c, 0x68, 0x35, 0x40, 0xfa, 0xe1, 0x24, 0xab, 0xd8, 0xac, 0xd9]
+        );
+    }
+
+    #[test]
+    fn test_rc4_salted_mac_after_key_update() {
+        let mut client =
+            SecurityContext::new(EncryptionMethod::EncryptionFlag128bit, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        // the server already sent 4097 PDUs of three bytes
+        let Cipher::Rc4 { decrypt, .. } = &mut client.cipher else { unreachable!() };
+        for _ in 0..KEY_UPDATE_INTERVAL + 1 {
+            decrypt.process(&mut [0; 3]);
+        }
+        assert_eq!(
+            decrypt.key,
+            [0x18, 0x8f, 0xe5, 0x39, 0x08, 0x24, 0x96, 0x90, 0x0d, 0x11, 0xf2, 0x81, 0x75, 0xa6, 0x24, 0xfa]
+        );
+        // salted MAC with the encryption count 4097
+        let pdu = [0x08, 0x08, 0x00, 0x00, 0x5b, 0xab, 0xf7, 0xb0, 0x06, 0xb0, 0x42, 0x04, 0x93, 0xe3, 0x68];
+        assert_eq!(client.decrypt_pdu(&pdu).unwrap(), b"bar");
+    }
+
+    #[test]
+    fn test_fips_round_trip() { round_trip(EncryptionMethod::FipsEncryptionFlag); }
+
+    #[test]
+    fn test_fast_path_round_trip() {
+        let mut client =
+            SecurityContext::new(EncryptionMethod::FipsEncryptionFlag, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        let mut server = server_context(EncryptionMethod::FipsEncryptionFlag);
+        let encrypted = client.encrypt_fast_path(b"foo").unwrap();
+        assert_eq!(server.decrypt_fast_path(FASTPATH_INPUT_ENCRYPTED, &encrypted).unwrap(), b"foo");
+    }
+
+    #[test]
+    fn test_salted_key() {
+        let keys = rc4_session_keys(EncryptionMethod::EncryptionFlag40bit, &CLIENT_RANDOM, &SERVER_RANDOM);
+        assert_eq!(keys.encrypt_key.len(), 8);
+        assert_eq!(keys.encrypt_key[..3], [0xd1, 0x26, 0x9e]);
+        assert_eq!(keys.mac_key[..3], [0xd1, 0x26, 0x9e]);
+    }
+
+    #[test]
+    fn test_invalid_mac() {
+        let mut client =
+            SecurityContext::new(EncryptionMethod::EncryptionFlag128bit, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        let mut server = server_context(EncryptionMethod::EncryptionFlag128bit);
+        let mut encrypted = client.encrypt_pdu(0, b"foo").unwrap();
+        encrypted[4] ^= 1;
+        assert!(server.decrypt_pdu(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_keeps_other_flags() {
+        let mut client =
+            SecurityContext::new(EncryptionMethod::EncryptionFlag128bit, &CLIENT_RANDOM, &SERVER_RANDOM).unwrap();
+        let mut server = server_context(EncryptionMethod::EncryptionFlag128bit);
+        let encrypted = server.encrypt_pdu(0x0080, b"foo").unwrap();
+        assert_eq!(client.decrypt_pdu(&encrypted).unwrap(), [0x80, 0, 0, 0, b'f', b'o', b'o']);
+    }
+
+    #[test]
+    fn test_fips_expand_key() {
+        let key = fips_expand_key(&[0xff; 21]);
+        assert_eq!(key.len(), 24);
+        // six bits set after the zero bit and a set parity bit
+        assert!(key.iter().all(|byte| *byte == 0x7f));
+        assert!(fips_expand_key(&[0; 21]).iter().all(|byte| *byte == 0x01));
+
+        let key: Vec<u8> = (1..=21).collect();
+        assert_eq!(
+            fips_expand_key(&key),
+            [
+                0x01, 0x04, 0x0d, 0x20, 0x51, 0x40, 0x40, 0x02, 0x08, 0x13, 0x29, 0x58, 0x40, 0x20, 0x02, 0x07, 0x0e,
+                0x20, 0x45, 0x10, 0x31, 0x02, 0x45, 0x0b,
+            ]
+        );
+    }
+
+    // known answers of the FIPS tests come from the key expansion of FreeRDP
+    // with the 3DES and HMAC of OpenSSL, not from a capture of a Windows session
+
+    #[test]
+    fn test_fips_session_pdu() {
+        let client_random: Vec<u8> = (0..32).collect();
+        let server_random: Vec<u8> = (32..64).collect();
+        let mut client =
+            SecurityContext::new(EncryptionMethod::FipsEncryptionFlag, &client_random, &server_random).unwrap();
+        assert_eq!(
+            client.encrypt_pdu(0, b"foo").unwrap(),
+            [
+                0x08, 0x00, 0x00, 0x00, 0x10, 0x00, 0x01, 0x05, 0x81, 0x87, 0x93, 0xdd, 0xf4, 0xd8, 0x1c, 0x0c, 0xef,
+                0xdf, 0xd1, 0x0c, 0x7a, 0x6f, 0xdb, 0x76,
+            ]
+        );
+        let pdu = [
+            0x08, 0x00, 0x00, 0x00, 0x10, 0x00, 0x01, 0x05, 0x7f, 0xeb, 0xa5, 0x5d, 0xa6, 0x0a, 0x44, 0x24, 0x76, 0x01,
+            0xb8, 0x50, 0xe5, 0x8d, 0xeb, 0x01,
+        ];
+        assert_eq!(client.decrypt_pdu(&pdu).unwrap(), b"bar");
+    }
+
+    #[test]
+    fn test_read_proprietary_certificate() {
+        let mut certificate = vec![1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 6, 0, 28, 0];
+        certificate.extend_from_slice(b"RSA1");
+        // keylen, bitlen, datalen, exponent
+        certificate.extend_from_slice(&[12, 0, 0, 0, 32, 0, 0, 0, 3, 0, 0, 0, 1, 0, 1, 0]);
+        certificate.extend_from_slice(&[1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let key = read_server_certificate(&certificate).unwrap();
+        assert_eq!(key, RsaPublicKey { modulus: vec![1, 2, 3, 4], exponent: vec![1, 0, 1, 0] });
+    }
+
+    #[test]
+    fn test_security_exchange_pdu() {
+        assert_eq!(security_exchange_pdu(&[1, 2]), [10, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+
+use crate::core::http_proxy::{http_connect, http_connect_with_credentials, ConnectStatus};
+use crate::model::data::{to_vec, Component, DataType, Message, U16};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS5_VERSION: u8 = 5;
+/// Version of the username/password sub negotiation
+/// See RFC 1929
+const SOCKS5_AUTH_VERSION: u8 = 1;
+const CONNECT_COMMAND: u8 = 1;
+/// SOCKS4 request granted
+const SOCKS4_GRANTED: u8 = 0x5a;
+/// Address use by SOCKS4a to ask for a remote name resolution
+const SOCKS4A_ADDRESS: [u8; 4] = [0, 0, 0, 1];
+
+/// Authentication methods of the SOCKS5 handshake
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Method {
+    NoAuthentication = 0x00,
+    UsernamePassword = 0x02,
+    NoAcceptable = 0xff,
+}
+
+/// Address type of the SOCKS5 connect request
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum AddressType {
+    Ipv4 = 0x01,
+    DomainName = 0x03,
+    Ipv6 = 0x04,
+}
+
+/// Proxy use to reach the target server
+#[derive(Debug, Clone)]
+pub enum ProxyConfig {
+    /// SOCKS4 proxy, domain names are resolved by the proxy (SOCKS4a)
+    Socks4 { host: String, port: u16, user_id: Option<String> },
+    /// SOCKS5 proxy with optional username/password authentication
+    Socks5 { host: String, port: u16, username: Option<String>, password: Option<String> },
+    /// HTTP proxy using the CONNECT method
+    /// with optional basic authentication
+    Http { host: String, port: u16, auth: Option<(String, String)> },
+}
+
+impl ProxyConfig {
+    /// Open a TCP connection to the proxy
+    /// and ask it to connect to the target
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::core::socks::ProxyConfig;
+    /// let proxy = ProxyConfig::Socks5 { host: "127.0.0.1".to_string(), port: 1080, username: None, password: None };
+    /// let tcp = proxy.connect("rdp.example.com", 3389).unwrap();
+    /// ```
+    pub fn connect(&self, target_host: &str, target_port: u16) -> RdpResult<TcpStream> {
+        let (host, port) = match self {
+            ProxyConfig::Socks4 { host, port, .. }
+            | ProxyConfig::Socks5 { host, port, .. }
+            | ProxyConfig::Http { host, port, .. } => (host, *port),
+        };
+        let mut tcp = TcpStream::connect((host.as_str(), port))?;
+        if let ProxyConfig::Http { auth: Some((username, password)), .. } = self {
+            // Credentials are sent again on a new connection
+            // when the proxy closes the first one
+            let credentials = Some((username.as_str(), password.as_str()));
+            if http_connect(&mut tcp, target_host, target_port, credentials)?
+                == (ConnectStatus::AuthenticationRequired { keep_alive: false })
+            {
+                tcp = TcpStream::connect((host.as_str(), port))?;
+                http_connect_with_credentials(&mut tcp, target_host, target_port, credentials)?;
+            }
+            return Ok(tcp);
+        }
+        self.handshake(&mut tcp, target_host, target_port)?;
+        Ok(tcp)
+    }
+
+    /// Ask the proxy to connect to the target
+    /// Once done the stream is a tunnel to the target
+    pub fn handshake<S: Read + Write>(&self, stream: &mut S, target_host: &str, target_port: u16) -> RdpResult<()> {
+        match self {
+            ProxyConfig::Socks4 { user_id, .. } => {
+                socks4_connect(stream, user_id.as_deref().unwrap_or(""), target_host, target_port)
+            }
+            ProxyConfig::Socks5 { username, password, .. } => socks5_connect(
+                stream,
+                username.as_deref().map(|username| (username, password.as_deref().unwrap_or(""))),
+                target_host,
+                target_port,
+            ),
+            ProxyConfig::Http { auth, .. } => {
+                let credentials = auth.as_ref().map(|(username, password)| (username.as_str(), password.as_str()));
+                match http_connect(stream, target_host, target_port, credentials)? {
+                    ConnectStatus::Established => Ok(()),
+                    ConnectStatus::AuthenticationRequired { .. } => Err(Error::RdpError(RdpError::new(
+                        RdpErrorKind::AuthenticationFailed,
+                        "HTTP PROXY: Proxy authentication required",
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+fn socks_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::RejectedByServer, &format!("SOCKS: {}", message)))
+}
+
+/// Read a fixed size reply from the proxy
+fn read_reply<S: Read>(stream: &mut S, mut reply: Component) -> RdpResult<Component> {
+    reply.read(stream)?;
+    Ok(reply)
+}
+
+/// SOCKS4 connect request
+/// Only IPv4 addresses are supported, other names use SOCKS4a
+fn socks4_request(user_id: &str, target_host: &str, target_port: u16) -> RdpResult<Component> {
+    let mut user_id = user_id.as_bytes().to_vec();
+    user_id.push(0);
+    let (address, domain) = match target_host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => (ip.octets(), vec![]),
+        Ok(IpAddr::V6(_)) => return Err(socks_error("SOCKS4 doesn't support IPv6 target")),
+        Err(_) => {
+            let mut domain = target_host.as_bytes().to_vec();
+            domain.push(0);
+            (SOCKS4A_ADDRESS, domain)
+        }
+    };
+    Ok(component![
+        "version" => SOCKS4_VERSION,
+        "command" => CONNECT_COMMAND,
+        "port" => U16::BE(target_port),
+        "address" => address.to_vec(),
+        "userId" => user_id,
+        "domain" => domain
+    ])
+}
+
+fn socks4_connect<S: Read + Write>(
+    stream: &mut S, user_id: &str, target_host: &str, target_port: u16,
+) -> RdpResult<()> {
+    stream.write_all(&to_vec(&socks4_request(user_id, target_host, target_port)?))?;
+    let reply = read_reply(
+        stream,
+        component![
+            "version" => 0_u8,
+            "status" => 0_u8,
+            "port" => U16::BE(0),
+            "address" => vec![0_u8; 4]
+        ],
+    )?;
+    let status = cast!(DataType::U8, reply["status"])?;
+    if status != SOCKS4_GRANTED {
+        return Err(socks_error(&format!("connect request rejected with status {:#x}", status)));
+    }
+    Ok(())
+}
+
+/// Encode the target address of a SOCKS5 request
+fn socks5_address(target_host: &str) -> RdpResult<(AddressType, Vec<u8>)> {
+    Ok(match target_host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => (AddressType::Ipv4, ip.octets().to_vec()),
+        Ok(IpAddr::V6(ip)) => (AddressType::Ipv6, ip.octets().to_vec()),
+        Err(_) => {
+            if target_host.len() > 255 {
+                return Err(socks_error("target name too long"));
+            }
+            let mut domain = vec![target_host.len() as u8];
+            domain.extend_from_slice(target_host.as_bytes());
+            (AddressType::DomainName, domain)
+        }
+    })
+}
+
+/// Username/password authentication request
+/// See RFC 1929
+fn socks5_auth_request(username: &str, password: &str) -> RdpResult<Component> {
+    if username.len() > 255 || password.len() > 255 {
+        return Err(socks_error("credentials too long"));
+    }
+    Ok(component![
+        "version" => SOCKS5_AUTH_VERSION,
+        "usernameLength" => username.len() as u8,
+        "username" => username.as_bytes().to_vec(),
+        "passwordLength" => password.len() as u8,
+        "password" => password.as_bytes().to_vec()
+    ])
+}
+
+fn socks5_connect<S: Read + Write>(
+    stream: &mut S, credentials: Option<(&str, &str)>, target_host: &str, target_port: u16,
+) -> RdpResult<()> {
+    // Announce supported authentication methods
+    let methods = match credentials {
+        Some(_) => vec![Method::NoAuthentication as u8, Method::UsernamePassword as u8],
+        None => vec![Method::NoAuthentication as u8],
+    };
+    stream.write_all(&to_vec(&component![
+        "version" => SOCKS5_VERSION,
+        "methodsCount" => methods.len() as u8,
+        "methods" => methods
+    ]))?;
+
+    let reply = read_reply(stream, component!["version" => 0_u8, "method" => 0_u8])?;
+    if cast!(DataType::U8, reply["version"])? != SOCKS5_VERSION {
+        return Err(socks_error("invalid proxy version"));
+    }
+    let method = cast!(DataType::U8, reply["method"])?;
+    match credentials {
+        Some((username, password)) if method == Method::UsernamePassword as u8 => {
+            stream.write_all(&to_vec(&socks5_auth_request(username, password)?))?;
+            let reply = read_reply(stream, component!["version" => 0_u8, "status" => 0_u8])?;
+            if cast!(DataType::U8, reply["status"])? != 0 {
+                return Err(socks_error("authentication failed"));
+            }
+        }
+        _ if method == Method::NoAuthentication as u8 => (),
+        _ => return Err(socks_error("no acceptable authentication method")),
+    }
+
+    // Ask the proxy to connect to the target
+    let (address_type, address) = socks5_address(target_host)?;
+    stream.write_all(&to_vec(&component![
+        "version" => SOCKS5_VERSION,
+        "command" => CONNECT_COMMAND,
+        "reserved" => 0_u8,
+        "addressType" => address_type as u8,
+        "address" => address,
+        "port" => U16::BE(target_port)
+    ]))?;
+
+    let reply = read_reply(
+        stream,
+        component![
+            "version" => 0_u8,
+            "status" => 0_u8,
+            "reserved" => 0_u8,
+            "addressType" => 0_u8
+        ],
+    )?;
+    let status = cast!(DataType::U8, reply["status"])?;
+    if status != 0 {
+        return Err(socks_error(&format!("connect request rejected with status {:#x}", status)));
+    }
+
+    // Skip the address bound by the proxy
+    let bound_length = match cast!(DataType::U8, reply["addressType"])? {
+        t if t == AddressType::Ipv4 as u8 => 4,
+        t if t == AddressType::Ipv6 as u8 => 16,
+        t if t == AddressType::DomainName as u8 => {
+            let mut length = 0_u8;
+            length.read(stream)?;
+            length as usize
+        }
+        _ => return Err(socks_error("invalid bound address type")),
+    };
+    let mut bound = vec![0; bound_length + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Replay proxy replies and record client requests
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { Read::read(&mut self.input, buf) }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { Write::write(&mut self.output, buf) }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_socks5_connect_with_credentials() {
+        let mut stream =
+            MockStream { input: Cursor::new(vec![5, 2, 1, 0, 5, 0, 0, 1, 127, 0, 0, 1, 0x04, 0x38]), output: vec![] };
+        let proxy = ProxyConfig::Socks5 {
+            host: "proxy".to_string(),
+            port: 1080,
+            username: Some("foo".to_string()),
+            password: Some("bar".to_string()),
+        };
+        proxy.handshake(&mut stream, "rdp", 3389).unwrap();
+        assert_eq!(
+            stream.output,
+            [5, 2, 0, 2, 1, 3, 102, 111, 111, 3, 98, 97, 114, 5, 1, 0, 3, 3, 114, 100, 112, 0x0d, 0x3d]
+        );
+    }
+
+    #[test]
+    fn test_socks5_connect_rejected() {
+        let mut stream = MockStream { input: Cursor::new(vec![5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]), output: vec![] };
+        let proxy = ProxyConfig::Socks5 { host: "proxy".to_string(), port: 1080, username: None, password: None };
+        assert!(proxy.handshake(&mut stream, "10.0.0.1", 3389).is_err());
+        assert_eq!(stream.output, [5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0x0d, 0x3d]);
+    }
+
+    #[test]
+    fn test_socks4a_connect() {
+        let mut stream = MockStream { input: Cursor::new(vec![0, 0x5a, 0, 0, 0, 0, 0, 0]), output: vec![] };
+        let proxy = ProxyConfig::Socks4 { host: "proxy".to_string(), port: 1080, user_id: None };
+        proxy.handshake(&mut stream, "rdp", 3389).unwrap();
+        assert_eq!(stream.output, [4, 1, 0x0d, 0x3d, 0, 0, 0, 1, 0, 114, 100, 112, 0]);
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::TryFromPrimitive;
+
+use crate::core::capability::BitmapCodec;
+use crate::core::event::BitmapEvent;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Type of a surface command
+/// See MS-RDPBCGR 2.2.9.1.2.1.10.1
+#[repr(u16)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum SurfaceCommandType {
+    SetSurfaceBits = 0x0001,
+    FrameMarker = 0x0004,
+    StreamSurfaceBits = 0x0006,
+}
+
+/// The bitmap data is preceded by a TS_COMPRESSED_BITMAP_HEADER_EX
+/// See MS-RDPBCGR 2.2.9.2.1.1
+const EX_COMPRESSED_BITMAP_HEADER_PRESENT: u8 = 0x01;
+
+/// Size of TS_COMPRESSED_BITMAP_HEADER_EX
+const EX_COMPRESSED_BITMAP_HEADER_SIZE: usize = 24;
+
+/// Codec id of uncompressed surface bits
+const NONE_CODEC_ID: u8 = 0;
+
+fn surface_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("SURFACE: {}", message)))
+}
+
+/// Read a set or stream surface bits command after its type
+/// The destination rectangle is exclusive,
+/// the codec id must be one of the confirmed `codecs`
+///
+/// See MS-RDPBCGR 2.2.9.2.1 and 2.2.9.2.1.1
+fn read_surface_bits(stream: &mut dyn Read, codecs: &[BitmapCodec]) -> RdpResult<BitmapEvent> {
+    let dest_left = stream.read_u16::<LittleEndian>()?;
+    let dest_top = stream.read_u16::<LittleEndian>()?;
+    let dest_right = stream.read_u16::<LittleEndian>()?;
+    let dest_bottom = stream.read_u16::<LittleEndian>()?;
+    if dest_right <= dest_left || dest_bottom <= dest_top {
+        return Err(surface_error("Empty destination rectangle"));
+    }
+
+    let bpp = stream.read_u8()?;
+    let flags = stream.read_u8()?;
+    let _reserved = stream.read_u8()?;
+    let codec = match stream.read_u8()? {
+        NONE_CODEC_ID => None,
+        codec_id => Some(
+            codecs
+                .iter()
+                .copied()
+                .find(|codec| codec.id() == codec_id)
+                .ok_or_else(|| surface_error(&format!("Codec {} was not confirmed", codec_id)))?,
+        ),
+    };
+    let width = stream.read_u16::<LittleEndian>()?;
+    let height = stream.read_u16::<LittleEndian>()?;
+    let length = stream.read_u32::<LittleEndian>()? as usize;
+
+    // The length of the bitmap data doesn't include the extended header
+    if flags & EX_COMPRESSED_BITMAP_HEADER_PRESENT != 0 {
+        stream.read_exact(&mut [0; EX_COMPRESSED_BITMAP_HEADER_SIZE])?;
+    }
+    let mut data = Vec::new();
+    stream.take(length as u64).read_to_end(&mut data)?;
+    if data.len() != length {
+        return Err(surface_error("Truncated bitmap data"));
+    }
+
+    Ok(BitmapEvent {
+        dest_left,
+        dest_top,
+        dest_right: dest_right - 1,
+        dest_bottom: dest_bottom - 1,
+        width,
+        height,
+        bpp: u16::from(bpp),
+        is_compress: codec.is_some(),
+        codec,
+        monitor: None,
+        palette: None,
+        data,
+    })
+}
+
+/// Read the surface commands of a fast path update
+/// Frame markers are ignored, `codecs` are the bitmap codecs
+/// confirmed to the server
+///
+/// The decoding stops at the first unknown command
+/// as commands don't carry their size
+///
+/// See MS-RDPBCGR 2.2.9.1.2.1.10
+///
+/// # Example
+/// ```
+/// use rdp::core::surface::read_surface_commands;
+/// // frame marker then a 1x1 uncompressed surface bits
+/// let update = [
+///     4, 0, 0, 0, 1, 0, 0, 0,
+///     6, 0, 2, 0, 3, 0, 3, 0, 4, 0, 32, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4,
+/// ];
+/// let bitmaps = read_surface_commands(&update, &[]).unwrap();
+/// assert_eq!(bitmaps.len(), 1);
+/// assert_eq!((bitmaps[0].dest_left, bitmaps[0].dest_right), (2, 2));
+/// assert_eq!(bitmaps[0].data, [1, 2, 3, 4]);
+/// ```
+pub fn read_surface_commands(update: &[u8], codecs: &[BitmapCodec]) -> RdpResult<Vec<BitmapEvent>> {
+    let mut stream = Cursor::new(update);
+    let mut bitmaps = Vec::new();
+    while (stream.position() as usize) < update.len() {
+        let cmd_type = stream.read_u16::<LittleEndian>()?;
+        match SurfaceCommandType::try_from(cmd_type) {
+            Ok(SurfaceCommandType::SetSurfaceBits) | Ok(SurfaceCommandType::StreamSurfaceBits) => {
+                bitmaps.push(read_surface_bits(&mut stream, codecs)?)
+            }
+            Ok(SurfaceCommandType::FrameMarker) => {
+                let _frame_action = stream.read_u16::<LittleEndian>()?;
+                let _frame_id = stream.read_u32::<LittleEndian>()?;
+            }
+            Err(_) => {
+                log_debug!("SURFACE: command not handled {:#x}", cmd_type);
+                break;
+            }
+        }
+    }
+    Ok(bitmaps)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::rfx::RFX_CODEC_ID;
+
+    #[test]
+    fn test_read_stream_surface_bits_ex_header() {
+        let mut update = vec![6, 0, 0, 0, 0, 0, 64, 0, 64, 0, 32, EX_COMPRESSED_BITMAP_HEADER_PRESENT, 0, RFX_CODEC_ID];
+        update.extend([64, 0, 64, 0, 2, 0, 0, 0]);
+        update.extend([0xaa; EX_COMPRESSED_BITMAP_HEADER_SIZE]);
+        update.extend([0xc4, 0xcc]);
+        // the next command follows the bitmap data
+        update.extend([1, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4]);
+        let bitmaps = read_surface_commands(&update, &[BitmapCodec::NsCodec, BitmapCodec::RemoteFx]).unwrap();
+        assert_eq!(bitmaps.len(), 2);
+        assert_eq!(bitmaps[1].data, [1, 2, 3, 4]);
+        let bitmap = &bitmaps[0];
+        assert_eq!(bitmap.codec, Some(BitmapCodec::RemoteFx));
+        assert!(bitmap.is_compress);
+        assert_eq!((bitmap.dest_right, bitmap.dest_bottom, bitmap.width, bitmap.height), (63, 63, 64, 64));
+        assert_eq!(bitmap.data, [0xc4, 0xcc]);
+    }
+
+    #[test]
+    fn test_read_surface_commands_invalid() {
+        // truncated data
+        let codecs = [BitmapCodec::RemoteFx];
+        assert!(read_surface_commands(
+            &[1, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1],
+            &codecs
+        )
+        .is_err());
+        // missing extended header
+        assert!(read_surface_commands(&[1, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 1, 0, 3, 1, 0, 1, 0, 4, 0, 0, 0], &codecs)
+            .is_err());
+        // empty rectangle
+        assert!(read_surface_commands(&[1, 0, 4, 0, 0, 0, 4, 0, 1, 0], &codecs).is_err());
+        // unknown command
+        assert!(read_surface_commands(&[0x20, 0, 1, 2, 3], &codecs).unwrap().is_empty());
+        // codec which wasn't confirmed
+        let update = [1, 0, 0, 0, 0, 0, 1, 0, 1, 0, 32, 0, 0, 1, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4];
+        assert!(read_surface_commands(&update, &codecs).is_err());
+    }
+}
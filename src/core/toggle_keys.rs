@@ -0,0 +1,96 @@
+use crate::core::global::ToggleFlag;
+
+/// Toggle flags from the state of each key
+fn toggle_flags(scroll_lock: bool, num_lock: bool, caps_lock: bool, kana_lock: bool) -> u32 {
+    [
+        (scroll_lock, ToggleFlag::ScrollLock),
+        (num_lock, ToggleFlag::NumLock),
+        (caps_lock, ToggleFlag::CapsLock),
+        (kana_lock, ToggleFlag::KanaLock),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .fold(0, |flags, (_, flag)| flags | *flag as u32)
+}
+
+/// Keyboard LED of a lock key
+/// The LEDs of the input devices are exposed as `inputN::capslock`
+#[cfg(target_os = "linux")]
+fn led_on(root: &std::path::Path, name: &str) -> bool {
+    let suffix = format!("::{}", name);
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().filter(|entry| entry.file_name().to_string_lossy().ends_with(&suffix)).any(|entry| {
+        std::fs::read_to_string(entry.path().join("brightness")).is_ok_and(|brightness| brightness.trim() != "0")
+    })
+}
+
+/// Toggle flags from the LEDs under `root`
+#[cfg(target_os = "linux")]
+fn leds_toggle_flags(root: &std::path::Path) -> u32 {
+    toggle_flags(led_on(root, "scrolllock"), led_on(root, "numlock"), led_on(root, "capslock"), led_on(root, "kana"))
+}
+
+/// Current state of the lock keys of the local keyboard
+/// as flags of the input synchronize event
+///
+/// Linux reads the keyboard LEDs of sysfs,
+/// Windows asks `GetKeyState`,
+/// other platforms report every key off
+///
+/// # Example
+/// ```
+/// use rdp::core::toggle_keys::local_toggle_flags;
+/// let flags = local_toggle_flags();
+/// assert_eq!(flags & !0xf, 0);
+/// ```
+pub fn local_toggle_flags() -> u32 {
+    #[cfg(target_os = "linux")]
+    {
+        leds_toggle_flags(std::path::Path::new("/sys/class/leds"))
+    }
+    #[cfg(windows)]
+    {
+        #[link(name = "user32")]
+        extern "system" {
+            fn GetKeyState(virtual_key: i32) -> i16;
+        }
+        // The low bit is set when the key is toggled
+        let toggled = |virtual_key: i32| unsafe { GetKeyState(virtual_key) } & 1 != 0;
+        // VK_SCROLL, VK_NUMLOCK, VK_CAPITAL and VK_KANA
+        toggle_flags(toggled(0x91), toggled(0x90), toggled(0x14), toggled(0x15))
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        toggle_flags(false, false, false, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_toggle_flags() {
+        assert_eq!(toggle_flags(false, false, false, false), 0);
+        assert_eq!(toggle_flags(false, true, true, false), 0x6);
+        assert_eq!(toggle_flags(true, true, true, true), 0xf);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_leds_toggle_flags() {
+        let root = std::env::temp_dir().join(format!("rdp-rs-leds-{}", std::process::id()));
+        for (name, brightness) in [("input3::capslock", "1\n"), ("input3::numlock", "0\n"), ("input7::numlock", "1\n")]
+        {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            std::fs::write(root.join(name).join("brightness"), brightness).unwrap();
+        }
+        let flags = leds_toggle_flags(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(flags, ToggleFlag::CapsLock as u32 | ToggleFlag::NumLock as u32);
+        assert_eq!(leds_toggle_flags(&root), 0);
+    }
+}
@@ -2,8 +2,8 @@ use std::io::{Cursor, Read, Write};
 
 use crate::model::data::{Component, Message, Trame, U16};
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-use crate::model::link::Link;
-use crate::nla::cssp::cssp_connect;
+use crate::model::link::{run_blocking, Blocking, FrameWait, Link};
+use crate::nla::cssp::cssp_connect_with;
 use crate::nla::sspi::AuthenticationProtocol;
 
 /// TPKT must implement this two kind of payload
@@ -25,12 +25,37 @@ pub enum Action {
 /// TPKT layer header
 ///
 /// This the header layout of any RDP packet
-fn tpkt_header(size: u16) -> Component {
-    component![
+/// Fails if the PDU doesn't fit in 64 KB
+fn tpkt_header(size: u64) -> RdpResult<Component> {
+    Ok(component![
         "action" => Action::FastPathActionX224 as u8,
         "flag" => 0_u8,
-        "size" => U16::BE(size + 4)
-    ]
+        "size" => U16::BE(U16::try_from(size + 4)?.inner())
+    ])
+}
+
+/// Fast path header written in place of the TPKT one
+/// `flags` are the bits above the action, the length
+/// is encoded on one or two bytes
+///
+/// See MS-RDPBCGR 2.2.8.1.2
+///
+/// # Example
+/// ```
+/// use rdp::core::tpkt::fast_path_header;
+/// assert_eq!(fast_path_header(0x04, 7).unwrap(), [0x04, 9]);
+/// assert_eq!(fast_path_header(0x04, 200).unwrap(), [0x04, 0x80, 203]);
+/// ```
+pub fn fast_path_header(flags: u8, size: usize) -> RdpResult<Vec<u8>> {
+    let header = (flags & !0x3) | Action::FastPathActionFastPath as u8;
+    if size + 2 <= 0x7f {
+        Ok(vec![header, size as u8 + 2])
+    } else if size + 3 <= 0x7fff {
+        let length = (size + 3) as u16 | 0x8000;
+        Ok(vec![header, (length >> 8) as u8, length as u8])
+    } else {
+        Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "TPKT: Fast path PDU too large")))
+    }
 }
 
 /// Client Context of TPKT layer
@@ -81,7 +106,18 @@ impl<S: Read + Write> Client<S> {
     where
         T: Message,
     {
-        self.transport.write_msg(&trame![tpkt_header(message.length() as u16), message])
+        self.transport.write_msg(&trame![tpkt_header(message.length())?, message])
+    }
+
+    /// Send fast path PDUs without TPKT header in a single write
+    /// `flags` of each PDU are the bits of its header above the action
+    pub fn write_fast_path<T: Message + 'static>(&mut self, pdus: Vec<(u8, T)>) -> RdpResult<()> {
+        let mut message = Trame::new();
+        for (flags, pdu) in pdus {
+            message.push(Box::new(fast_path_header(flags, pdu.length() as usize)?));
+            message.push(Box::new(pdu));
+        }
+        self.transport.write_msg(&message)
     }
 
     /// Read a payload from the underlying layer
@@ -117,8 +153,11 @@ impl<S: Read + Write> Client<S> {
     ///     panic!("unexpected result")
     /// }
     /// ```
-    pub fn read(&mut self) -> RdpResult<Payload> {
-        let mut buffer = Cursor::new(self.transport.read_exact_to_vec(2)?);
+    pub fn read(&mut self) -> RdpResult<Payload> { self.transport.read_frame(Self::read_payload) }
+
+    /// Read the header and the payload of a frame
+    fn read_payload(transport: &mut Link<S>) -> RdpResult<Payload> {
+        let mut buffer = Cursor::new(transport.read_exact_to_vec(2)?);
         let mut action: u8 = 0;
         action.read(&mut buffer)?;
         if action == Action::FastPathActionX224 as u8 {
@@ -126,7 +165,7 @@ impl<S: Read + Write> Client<S> {
             let mut padding: u8 = 0;
             padding.read(&mut buffer)?;
             // now wait extended header
-            buffer = Cursor::new(self.transport.read_exact_to_vec(2)?);
+            buffer = Cursor::new(transport.read_exact_to_vec(2)?);
 
             let mut size = U16::BE(0);
             size.read(&mut buffer)?;
@@ -137,7 +176,7 @@ impl<S: Read + Write> Client<S> {
                 Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Invalid minimal size for TPKT")))
             } else {
                 // now wait for body
-                Ok(Payload::Raw(Cursor::new(self.transport.read_exact_to_vec(size.inner() as usize - 4)?)))
+                Ok(Payload::Raw(Cursor::new(transport.read_exact_to_vec(size.inner() as usize - 4)?)))
             }
         } else {
             // fast path
@@ -146,20 +185,20 @@ impl<S: Read + Write> Client<S> {
             short_length.read(&mut buffer)?;
             if short_length & 0x80 != 0 {
                 let mut hi_length: u8 = 0;
-                hi_length.read(&mut Cursor::new(self.transport.read_exact_to_vec(1)?))?;
+                hi_length.read_from_bytes(&transport.read_exact_to_vec(1)?)?;
                 let length: u16 = u16::from(short_length & !0x80) << 8;
                 let length = length | u16::from(hi_length);
                 if length < 3 {
                     Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Invalid minimal size for TPKT")))
                 } else {
-                    Ok(Payload::FastPath(sec_flag, Cursor::new(self.transport.read_exact_to_vec(length as usize - 3)?)))
+                    Ok(Payload::FastPath(sec_flag, Cursor::new(transport.read_exact_to_vec(length as usize - 3)?)))
                 }
             } else if short_length < 2 {
                 Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidSize, "Invalid minimal size for TPKT")))
             } else {
                 Ok(Payload::FastPath(
                     sec_flag,
-                    Cursor::new(self.transport.read_exact_to_vec(short_length as usize - 2)?),
+                    Cursor::new(transport.read_exact_to_vec(short_length as usize - 2)?),
                 ))
             }
         }
@@ -179,7 +218,12 @@ impl<S: Read + Write> Client<S> {
     /// let mut tpkt_ssl = tpkt.start_ssl(false).unwrap();
     /// ```
     pub fn start_ssl(self, check_certificate: bool) -> RdpResult<Client<S>> {
-        Ok(Client::new(self.transport.start_ssl(check_certificate)?))
+        run_blocking(self.start_ssl_with(check_certificate, &mut Blocking))
+    }
+
+    /// Same as `start_ssl`, the handshake goes on once `wait` resolves
+    pub async fn start_ssl_with(self, check_certificate: bool, wait: &mut impl FrameWait) -> RdpResult<Client<S>> {
+        Ok(Client::new(self.transport.start_ssl_with(check_certificate, wait).await?))
     }
 
     /// This function is used when NLA (Network Level Authentication)
@@ -200,21 +244,34 @@ impl<S: Read + Write> Client<S> {
         self, check_certificate: bool, authentication_protocol: &mut dyn AuthenticationProtocol,
         restricted_admin_mode: bool,
     ) -> RdpResult<Client<S>> {
-        let mut link = self.transport.start_ssl(check_certificate)?;
-        cssp_connect(&mut link, authentication_protocol, restricted_admin_mode)?;
+        run_blocking(self.start_nla_with(check_certificate, authentication_protocol, restricted_admin_mode, &mut Blocking))
+    }
+
+    /// Same as `start_nla`, each answer of the server is read once `wait`
+    /// resolves
+    pub async fn start_nla_with(
+        self, check_certificate: bool, authentication_protocol: &mut dyn AuthenticationProtocol,
+        restricted_admin_mode: bool, wait: &mut impl FrameWait,
+    ) -> RdpResult<Client<S>> {
+        let mut link = self.transport.start_ssl_with(check_certificate, wait).await?;
+        cssp_connect_with(&mut link, authentication_protocol, restricted_admin_mode, wait).await?;
         Ok(Client::new(link))
     }
 
     /// Shutdown current connection
     pub fn shutdown(&mut self) -> RdpResult<()> { self.transport.shutdown() }
 
+    /// Bytes sent and received by the link layer
+    pub fn get_traffic(&self) -> (u64, u64) { self.transport.get_traffic() }
+
     #[cfg(feature = "integration")]
     pub fn get_link(self) -> Link<S> { self.transport }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::Cursor;
+    use std::io::{Cursor, Read, Write};
+    use std::sync::{Arc, Mutex};
 
     use super::*;
     use crate::model::data::{DataType, U32};
@@ -224,22 +281,54 @@ mod test {
     #[test]
     fn test_write_tpkt_header() {
         let x = U32::BE(1);
-        let message = trame![tpkt_header(x.length() as u16), x];
+        let message = trame![tpkt_header(x.length()).unwrap(), x];
         let mut buffer = Cursor::new(Vec::new());
         message.write(&mut buffer).unwrap();
         assert_eq!(buffer.get_ref().as_slice(), [3, 0, 0, 8, 0, 0, 0, 1]);
     }
 
+    #[test]
+    fn test_tpkt_header_too_large() {
+        assert!(tpkt_header(0xfffb).is_ok());
+        assert!(tpkt_header(0xfffc).is_err());
+    }
+
     /// Test read of TPKT header
     #[test]
     fn test_read_tpkt_header() {
-        let mut message = tpkt_header(0);
+        let mut message = tpkt_header(0).unwrap();
         let mut buffer = Cursor::new([3, 0, 0, 8, 0, 0, 0, 1]);
         message.read(&mut buffer).unwrap();
         assert_eq!(cast!(DataType::U16, message["size"]).unwrap(), 8);
         assert_eq!(cast!(DataType::U8, message["action"]).unwrap(), Action::FastPathActionX224 as u8);
     }
 
+    /// Keep each write call apart
+    #[derive(Clone, Default)]
+    struct Writes(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Read for Writes {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> { Ok(0) }
+    }
+
+    impl Write for Writes {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().push(buffer.to_vec());
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    /// Fast path PDUs are sent in a single write
+    #[test]
+    fn test_write_fast_path() {
+        let writes = Writes::default();
+        let mut client = Client::new(Link::new(Stream::Raw(writes.clone())));
+        client.write_fast_path(vec![(0x3c, vec![1, 2]), (0x04, vec![3])]).unwrap();
+        assert_eq!(*writes.0.lock().unwrap(), [vec![0x3c, 4, 1, 2, 0x04, 3, 3]]);
+    }
+
     fn process(data: &[u8]) {
         let cur = Cursor::new(data.to_vec());
         let link = Link::new(Stream::Raw(cur));
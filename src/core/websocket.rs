@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use js_sys::{ArrayBuffer, Function, Promise, Uint8Array};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+use crate::core::tpkt::Action;
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::link::FrameWait;
+
+fn js_error(value: JsValue) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::Disconnect, &format!("WEBSOCKET: {:?}", value)))
+}
+
+/// Size of the TPKT or fast path frame at the start of the data
+/// None until the header is complete
+fn frame_length(data: &VecDeque<u8>) -> Option<usize> {
+    let byte = |index| data.get(index).map(|byte| usize::from(*byte));
+    if byte(0)? == Action::FastPathActionX224 as usize {
+        Some((byte(2)? << 8) | byte(3)?)
+    } else if byte(1)? & 0x80 != 0 {
+        Some(((byte(1)? & 0x7f) << 8) | byte(2)?)
+    } else {
+        byte(1)
+    }
+}
+
+/// Bytes received from the socket and not read yet
+#[derive(Debug, Default)]
+struct Inbox {
+    data: VecDeque<u8>,
+    /// Bytes left of the frame being read, which is whole in `data`
+    pending: usize,
+    /// Set once the socket is closed
+    closed: bool,
+    /// Resolve function of the promise waiting for a frame
+    waiter: Option<Function>,
+}
+
+impl Inbox {
+    /// A frame is only read once it is whole, so a reader never
+    /// stops in the middle of a frame
+    ///
+    /// Nothing to read is reported as `WouldBlock` until the socket is closed
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.closed {
+            return self.data.read(buffer);
+        }
+        if self.pending == 0 {
+            match frame_length(&self.data) {
+                Some(length) if self.data.len() >= length => self.pending = length,
+                _ if buffer.is_empty() => return Ok(0),
+                _ => return Err(io::Error::new(io::ErrorKind::WouldBlock, "WEBSOCKET: No frame received")),
+            }
+        }
+        let size = buffer.len().min(self.pending);
+        let size = self.data.read(&mut buffer[..size])?;
+        self.pending -= size;
+        Ok(size)
+    }
+
+    fn frame_ready(&self) -> bool {
+        self.pending > 0 || frame_length(&self.data).is_some_and(|length| self.data.len() >= length)
+    }
+
+    /// The waiting promise is resolved once a frame is ready
+    /// or the socket is closed
+    fn waiter_to_wake(&mut self) -> Option<Function> {
+        if self.closed || self.frame_ready() {
+            self.waiter.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Resolve the waiting promise outside of the inbox borrow
+fn wake(inbox: &RefCell<Inbox>) {
+    let waiter = inbox.borrow_mut().waiter_to_wake();
+    if let Some(resolve) = waiter {
+        let _ = resolve.call0(&JsValue::NULL);
+    }
+}
+
+/// Event handlers of the socket, dropped with the last stream
+struct Handlers {
+    socket: WebSocket,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl Drop for Handlers {
+    fn drop(&mut self) {
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+    }
+}
+
+/// Stream over a browser WebSocket for clients built for
+/// `wasm32-unknown-unknown`, each binary message carries
+/// the bytes of the RDP connection
+///
+/// The browser can't block on a read, so a read without a whole
+/// frame returns `WouldBlock`. Connect with `Connector::connect_websocket`,
+/// which waits for each frame of the handshake, and then read the
+/// client once `frame_ready` or `wait_frame` tells that a whole TPKT
+/// or fast path frame is buffered
+///
+/// Security comes from the `wss://` connection, TLS and NLA
+/// are then usually disabled on the RDP side
+#[derive(Clone)]
+pub struct WebSocketStream {
+    socket: WebSocket,
+    inbox: Rc<RefCell<Inbox>>,
+    _handlers: Rc<Handlers>,
+}
+
+impl std::fmt::Debug for WebSocketStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketStream").field("url", &self.socket.url()).field("inbox", &self.inbox).finish()
+    }
+}
+
+impl WebSocketStream {
+    /// Open a WebSocket and wait for the connection
+    pub async fn connect(url: &str) -> RdpResult<Self> {
+        let socket = WebSocket::new(url).map_err(js_error)?;
+        // Handlers are set before the first message can arrive
+        let stream = Self::from_socket(socket.clone());
+        let opened = js_sys::Promise::new(&mut |resolve, reject| {
+            socket.set_onopen(Some(&resolve));
+            socket.set_onerror(Some(&reject));
+        });
+        let result = JsFuture::from(opened).await;
+        socket.set_onopen(None);
+        socket.set_onerror(None);
+        result.map_err(js_error)?;
+        Ok(stream)
+    }
+
+    /// Use a socket opened by the application
+    pub fn from_socket(socket: WebSocket) -> Self {
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let inbox = Rc::new(RefCell::new(Inbox::default()));
+
+        let message_inbox = Rc::clone(&inbox);
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            match event.data().dyn_into::<ArrayBuffer>() {
+                Ok(buffer) => message_inbox.borrow_mut().data.extend(Uint8Array::new(&buffer).to_vec()),
+                Err(_) => log_warn!("WEBSOCKET: Ignore text message"),
+            }
+            wake(&message_inbox);
+        });
+        let close_inbox = Rc::clone(&inbox);
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |_: CloseEvent| {
+            close_inbox.borrow_mut().closed = true;
+            wake(&close_inbox);
+        });
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let handlers = Handlers { socket: socket.clone(), _on_message: on_message, _on_close: on_close };
+        WebSocketStream { socket, inbox, _handlers: Rc::new(handlers) }
+    }
+
+    /// A whole TPKT or fast path frame can be read
+    /// Always true once the socket is closed so the client sees the end
+    pub fn frame_ready(&self) -> bool {
+        let inbox = self.inbox.borrow();
+        inbox.closed || inbox.frame_ready()
+    }
+
+    /// Wait until a whole frame can be read or the socket is closed
+    pub async fn wait_frame(&self) -> RdpResult<()> {
+        if self.frame_ready() {
+            return Ok(());
+        }
+        let inbox = Rc::clone(&self.inbox);
+        let ready = Promise::new(&mut |resolve, _| inbox.borrow_mut().waiter = Some(resolve));
+        JsFuture::from(ready).await.map_err(js_error)?;
+        Ok(())
+    }
+
+    /// Close the socket, the server sees a normal closure
+    pub fn close(&self) -> RdpResult<()> { self.socket.close().map_err(js_error) }
+}
+
+impl FrameWait for WebSocketStream {
+    async fn frame(&mut self) -> RdpResult<()> { self.wait_frame().await }
+}
+
+impl Read for WebSocketStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> { self.inbox.borrow_mut().read(buffer) }
+}
+
+impl Write for WebSocketStream {
+    /// Each write is sent as one binary message
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if self.socket.ready_state() != WebSocket::OPEN {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "WEBSOCKET: Socket is not open"));
+        }
+        self.socket
+            .send_with_u8_array(buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, format!("WEBSOCKET: {:?}", e)))?;
+        Ok(buffer.len())
+    }
+
+    /// Messages are queued by the browser
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_length() {
+        assert_eq!(frame_length(&VecDeque::from(vec![3, 0, 0])), None);
+        assert_eq!(frame_length(&VecDeque::from(vec![3, 0, 1, 2])), Some(0x102));
+        assert_eq!(frame_length(&VecDeque::from(vec![0x04])), None);
+        assert_eq!(frame_length(&VecDeque::from(vec![0x04, 9])), Some(9));
+        assert_eq!(frame_length(&VecDeque::from(vec![0x04, 0x81])), None);
+        assert_eq!(frame_length(&VecDeque::from(vec![0x04, 0x81, 2])), Some(0x102));
+    }
+
+    #[test]
+    fn test_inbox_read() {
+        let mut inbox = Inbox::default();
+        let mut buffer = [0; 4];
+        assert_eq!(inbox.read(&mut buffer).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        inbox.data.extend([0x04, 4, 1]);
+        assert!(!inbox.frame_ready());
+        assert_eq!(inbox.read(&mut buffer).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        inbox.data.extend([2, 5]);
+        assert!(inbox.frame_ready());
+        assert_eq!(inbox.read(&mut buffer[..2]).unwrap(), 2);
+        assert_eq!(inbox.read(&mut buffer[2..]).unwrap(), 2);
+        assert_eq!(buffer, [0x04, 4, 1, 2]);
+
+        // a read stops at the end of the frame
+        inbox.data.extend([2, 0x04, 3]);
+        assert_eq!(inbox.read(&mut buffer).unwrap(), 2);
+        assert_eq!(buffer[..2], [5, 2]);
+        assert_eq!(inbox.read(&mut buffer).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        inbox.closed = true;
+        assert_eq!(inbox.read(&mut buffer).unwrap(), 2);
+        assert_eq!(inbox.read(&mut buffer).unwrap(), 0);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::AsFd;
+#[cfg(windows)]
+use std::os::windows::io::AsSocket;
+use std::time::Duration;
+
+use socket2::SockRef;
+
+/// Socket stream which fails instead of blocking forever
+/// when the server stops reading
+///
+/// The timeout is set on the socket (SO_SNDTIMEO) so it applies
+/// to each write call, an expired write returns `TimedOut`
+/// which is reported as `RdpErrorKind::Timeout`
+/// even without `Connector::socket_timeouts`
+///
+/// # Example
+/// ```no_run
+/// use std::net::TcpStream;
+/// use std::time::Duration;
+/// use rdp::core::client::Connector;
+/// use rdp::core::write_timeout::WriteTimeoutStream;
+/// let tcp = TcpStream::connect("127.0.0.1:3389").unwrap();
+/// let stream = WriteTimeoutStream::new(tcp, Duration::from_secs(10)).unwrap();
+/// let mut client = Connector::new().connect(stream).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct WriteTimeoutStream<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S> WriteTimeoutStream<S> {
+    /// Underlying stream
+    pub fn get_ref(&self) -> &S { &self.inner }
+
+    /// Maximum time spent in a single write
+    pub fn timeout(&self) -> Duration { self.timeout }
+
+    /// Give back the underlying stream
+    /// The socket keeps its write timeout
+    pub fn into_inner(self) -> S { self.inner }
+}
+
+#[cfg(unix)]
+impl<S: AsFd> WriteTimeoutStream<S> {
+    /// Set the write timeout of the socket
+    /// A zero timeout is rejected by the system
+    pub fn new(inner: S, timeout: Duration) -> io::Result<Self> {
+        SockRef::from(&inner).set_write_timeout(Some(timeout))?;
+        Ok(WriteTimeoutStream { inner, timeout })
+    }
+}
+
+#[cfg(windows)]
+impl<S: AsSocket> WriteTimeoutStream<S> {
+    /// Set the write timeout of the socket
+    /// A zero timeout is rejected by the system
+    pub fn new(inner: S, timeout: Duration) -> io::Result<Self> {
+        SockRef::from(&inner).set_write_timeout(Some(timeout))?;
+        Ok(WriteTimeoutStream { inner, timeout })
+    }
+}
+
+impl<S: Read> Read for WriteTimeoutStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.inner.read(buf) }
+}
+
+impl<S: Write> Write for WriteTimeoutStream<S> {
+    /// Unix reports an expired write as `WouldBlock`
+    /// and Windows as `TimedOut`, both platforms report `TimedOut`
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("No data written during {} ms", self.timeout.as_millis()),
+            ),
+            _ => e,
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::model::error::{Error, RdpErrorKind};
+
+    /// Connection to a peer which never reads
+    /// Small buffers fill quickly
+    fn stalled_connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        SockRef::from(&listener).set_recv_buffer_size(4096).unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        SockRef::from(&tcp).set_send_buffer_size(4096).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        (tcp, peer)
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        let (tcp, _peer) = stalled_connection();
+        let mut stream = WriteTimeoutStream::new(tcp, Duration::from_millis(50)).unwrap();
+        let error = loop {
+            if let Err(e) = stream.write(&[0; 1024]) {
+                break e;
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        match Error::from(error) {
+            Error::RdpError(e) => assert_eq!(e.kind(), RdpErrorKind::Timeout),
+            e => panic!("expected a timeout error {:?}", e),
+        }
+    }
+}
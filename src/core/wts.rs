@@ -0,0 +1,201 @@
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use byteorder::{LittleEndian, ReadBytesExt as _};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
+
+use crate::core::dvc::DvcHandler;
+use crate::core::license::read_unicode;
+use crate::model::data::{to_vec, Trame, U32};
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+
+/// Name of the session management dynamic channel
+/// The messages below don't follow a published specification
+pub const WTS_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::SessionManagement";
+
+/// Session management message types
+#[repr(u32)]
+#[derive(Debug, TryFromPrimitive, Copy, Clone, Eq, PartialEq)]
+enum MessageType {
+    EnumerateSessions = 0x0000_0001,
+    SessionList = 0x0000_0002,
+}
+
+fn wts_error(kind: RdpErrorKind, message: &str) -> Error {
+    Error::RdpError(RdpError::new(kind, &format!("WTS: {}", message)))
+}
+
+/// Connection state of a session
+/// Values of WTS_CONNECTSTATE_CLASS
+#[repr(u32)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, FromPrimitive, IntoPrimitive, Copy, Clone, Eq, PartialEq)]
+pub enum SessionState {
+    /// A user is logged on
+    Active = 0,
+    /// A user is logged on without client
+    Disconnected = 4,
+    /// Waiting for a client to connect
+    Idle = 5,
+    /// Down due to an error
+    Down = 8,
+    /// Initializing
+    Init = 9,
+    /// Any other state
+    #[num_enum(catch_all)]
+    Other(u32),
+}
+
+/// Session of the server
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SessionInfo {
+    pub session_id: u32,
+    /// Empty for sessions without user
+    pub username: String,
+    pub state: SessionState,
+}
+
+/// Request of the session list
+///
+/// # Example
+/// ```
+/// use rdp::core::wts::enumerate_sessions_pdu;
+/// assert_eq!(enumerate_sessions_pdu(), [1, 0, 0, 0, 8, 0, 0, 0]);
+/// ```
+pub fn enumerate_sessions_pdu() -> Vec<u8> {
+    to_vec(&trame![U32::LE(MessageType::EnumerateSessions as u32), U32::LE(8)])
+}
+
+/// Parse the body of a session list
+/// Each entry is the session id, its state
+/// and the length prefixed UTF-16 user name
+fn read_session_list(stream: &mut Cursor<&[u8]>) -> RdpResult<Vec<SessionInfo>> {
+    let count = stream.read_u32::<LittleEndian>()?;
+    let mut sessions = Vec::new();
+    for _ in 0..count {
+        let session_id = stream.read_u32::<LittleEndian>()?;
+        let state = SessionState::from(stream.read_u32::<LittleEndian>()?);
+        let mut username = vec![0; usize::from(stream.read_u16::<LittleEndian>()?)];
+        stream.read_exact(&mut username)?;
+        sessions.push(SessionInfo { session_id, username: read_unicode(&username), state });
+    }
+    Ok(sessions)
+}
+
+/// State shared by the channel clones
+#[derive(Debug, Default)]
+struct ChannelState {
+    opened: bool,
+    sessions: Option<Vec<SessionInfo>>,
+}
+
+/// Session management channel
+/// Used to list the sessions of the server
+///
+/// Clones share the same state
+#[derive(Clone, Default)]
+pub struct SessionChannel {
+    state: Arc<Mutex<ChannelState>>,
+}
+
+impl std::fmt::Debug for SessionChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionChannel").finish_non_exhaustive()
+    }
+}
+
+impl SessionChannel {
+    pub fn new() -> Self { Self::default() }
+
+    fn lock(&self) -> RdpResult<MutexGuard<'_, ChannelState>> {
+        self.state.lock().map_err(|_| wts_error(RdpErrorKind::Unknown, "Poisoned state"))
+    }
+
+    /// True once the server opened the channel
+    pub fn is_opened(&self) -> RdpResult<bool> { Ok(self.lock()?.opened) }
+
+    /// Take the last session list received
+    pub fn take_sessions(&self) -> RdpResult<Option<Vec<SessionInfo>>> { Ok(self.lock()?.sessions.take()) }
+}
+
+impl DvcHandler for SessionChannel {
+    fn on_open(&mut self, _channel_id: u32) -> RdpResult<()> {
+        self.lock()?.opened = true;
+        Ok(())
+    }
+
+    fn on_data(&mut self, _channel_id: u32, data: &[u8]) -> RdpResult<()> {
+        let mut stream = Cursor::new(data);
+        let message_type = stream.read_u32::<LittleEndian>()?;
+        let length = stream.read_u32::<LittleEndian>()?;
+        if length as usize != data.len() {
+            return Err(wts_error(RdpErrorKind::InvalidSize, "Invalid message length"));
+        }
+        match MessageType::try_from(message_type) {
+            Ok(MessageType::SessionList) => self.lock()?.sessions = Some(read_session_list(&mut stream)?),
+            _ => log_debug!("WTS: Ignore message type {:#x}", message_type),
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, _channel_id: u32) {
+        if let Ok(mut state) = self.lock() {
+            *state = ChannelState::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::U16;
+
+    /// Session list as sent by the server
+    fn session_list_pdu(sessions: &[(u32, u32, &str)]) -> Vec<u8> {
+        let mut body = to_vec(&U32::LE(sessions.len() as u32));
+        for (session_id, state, username) in sessions {
+            let username: Vec<u8> = username.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+            body.extend(to_vec(&trame![U32::LE(*session_id), U32::LE(*state), U16::LE(username.len() as u16)]));
+            body.extend(username);
+        }
+        let mut pdu = to_vec(&trame![U32::LE(MessageType::SessionList as u32), U32::LE(8 + body.len() as u32)]);
+        pdu.extend(body);
+        pdu
+    }
+
+    #[test]
+    fn test_session_list() {
+        let mut channel = SessionChannel::new();
+        assert!(!channel.is_opened().unwrap());
+        channel.on_open(1).unwrap();
+        assert!(channel.is_opened().unwrap());
+        assert_eq!(channel.take_sessions().unwrap(), None);
+
+        channel.on_data(1, &session_list_pdu(&[(0, 4, ""), (2, 0, "user"), (3, 1, "admin")])).unwrap();
+        assert_eq!(
+            channel.take_sessions().unwrap().unwrap(),
+            [
+                SessionInfo { session_id: 0, username: String::new(), state: SessionState::Disconnected },
+                SessionInfo { session_id: 2, username: "user".to_string(), state: SessionState::Active },
+                SessionInfo { session_id: 3, username: "admin".to_string(), state: SessionState::Other(1) },
+            ]
+        );
+        assert_eq!(channel.take_sessions().unwrap(), None);
+
+        channel.on_close(1);
+        assert!(!channel.is_opened().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_session_list() {
+        let mut channel = SessionChannel::new();
+        let mut pdu = session_list_pdu(&[(2, 0, "user")]);
+        pdu.pop();
+        assert!(channel.on_data(1, &pdu).is_err());
+        // the announced length covers the whole message
+        pdu[4] -= 1;
+        assert!(channel.on_data(1, &pdu).is_err());
+        assert_eq!(channel.take_sessions().unwrap(), None);
+    }
+}
@@ -7,6 +7,7 @@ use num_enum::TryFromPrimitive;
 use crate::core::tpkt;
 use crate::model::data::{Check, Component, DataType, Message, Trame, U16, U32};
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::link::{read_when_ready, run_blocking, Blocking, FrameWait};
 use crate::nla::sspi::AuthenticationProtocol;
 
 #[repr(u8)]
@@ -139,6 +140,11 @@ impl<S: Read + Write> Client<S> {
         self.transport.write(trame![x224_header(), message])
     }
 
+    /// Send fast path PDUs at once, it bypasses the X224 layer
+    pub fn write_fast_path<T: Message + 'static>(&mut self, pdus: Vec<(u8, T)>) -> RdpResult<()> {
+        self.transport.write_fast_path(pdus)
+    }
+
     /// Start reading an entire X224 paylaod
     /// This function act to return a valid x224 payload
     /// or a fastpath payload coming from directly underlying layer
@@ -199,25 +205,46 @@ impl<S: Read + Write> Client<S> {
     /// ).unwrap()
     /// ```
     pub fn connect(
-        mut tpkt: tpkt::Client<S>, security_protocols: u32, check_certificate: bool,
+        tpkt: tpkt::Client<S>, security_protocols: u32, check_certificate: bool,
         authentication_protocol: Option<&mut dyn AuthenticationProtocol>, restricted_admin_mode: bool,
         blank_creds: bool,
+    ) -> RdpResult<Client<S>> {
+        run_blocking(Self::connect_with(
+            tpkt,
+            security_protocols,
+            check_certificate,
+            authentication_protocol,
+            restricted_admin_mode,
+            blank_creds,
+            &mut Blocking,
+        ))
+    }
+
+    /// Same as `connect`, the connection confirm is read once `wait` resolves
+    pub async fn connect_with(
+        mut tpkt: tpkt::Client<S>, security_protocols: u32, check_certificate: bool,
+        authentication_protocol: Option<&mut dyn AuthenticationProtocol>, restricted_admin_mode: bool,
+        blank_creds: bool, wait: &mut impl FrameWait,
     ) -> RdpResult<Client<S>> {
         Self::write_connection_request(
             &mut tpkt,
             security_protocols,
             Some(if restricted_admin_mode { RequestMode::RestrictedAdminModeRequired as u8 } else { 0 }),
         )?;
-        match Self::read_connection_confirm(&mut tpkt)? {
+        match read_when_ready(wait, || Self::read_connection_confirm(&mut tpkt)).await? {
             Protocols::ProtocolHybrid => Ok(Client::new(
-                tpkt.start_nla(
+                tpkt.start_nla_with(
                     check_certificate,
                     authentication_protocol.unwrap(),
                     restricted_admin_mode || blank_creds,
-                )?,
+                    wait,
+                )
+                .await?,
                 Protocols::ProtocolHybrid,
             )),
-            Protocols::ProtocolSSL => Ok(Client::new(tpkt.start_ssl(check_certificate)?, Protocols::ProtocolSSL)),
+            Protocols::ProtocolSSL => {
+                Ok(Client::new(tpkt.start_ssl_with(check_certificate, wait).await?, Protocols::ProtocolSSL))
+            }
             Protocols::ProtocolRDP => Ok(Client::new(tpkt, Protocols::ProtocolRDP)),
             _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidProtocol, "Security protocol not handled"))),
         }
@@ -253,6 +280,9 @@ impl<S: Read + Write> Client<S> {
     pub fn get_selected_protocols(&self) -> Protocols { self.selected_protocol }
 
     pub fn shutdown(&mut self) -> RdpResult<()> { self.transport.shutdown() }
+
+    /// Bytes sent and received by the link layer
+    pub fn get_traffic(&self) -> (u64, u64) { self.transport.get_traffic() }
 }
 
 #[cfg(test)]
@@ -3,3 +3,5 @@ pub mod model;
 pub mod codec;
 pub mod core;
 pub mod nla;
+#[cfg(test)]
+mod test_utils;
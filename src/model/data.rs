@@ -1,5 +1,5 @@
 use std::collections::{HashMap, HashSet};
-use std::io::{Cursor, Read, Write};
+use std::io::{Read, Write};
 
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use indexmap::IndexMap;
@@ -107,6 +107,25 @@ pub trait Message: Send + std::fmt::Debug {
     /// Read and set current variable from readable stream
     fn read(&mut self, reader: &mut dyn Read) -> RdpResult<()>;
 
+    /// Read node from a buffer
+    ///
+    /// Same as `read` without wrapping the buffer into a `Cursor`,
+    /// the remaining bytes are ignored
+    ///
+    /// # Example
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate rdp;
+    /// # use rdp::model::data::{Component, DataType, Message, U16};
+    /// # use rdp::model::error::{Error, RdpError, RdpErrorKind};
+    /// # fn main() {
+    /// let mut header = component!["length" => U16::LE(0)];
+    /// header.read_from_bytes(&[4, 0, 0xff]).unwrap();
+    /// assert_eq!(cast!(DataType::U16, header["length"]).unwrap(), 4);
+    /// # }
+    /// ```
+    fn read_from_bytes(&mut self, mut data: &[u8]) -> RdpResult<()> { self.read(&mut data) }
+
     /// Length in bytes of current element
     fn length(&self) -> u64;
 
@@ -207,6 +226,7 @@ impl Message for u8 {
 }
 
 /// Trame is just a list of boxed Message
+/// As a `Vec` it is built with `collect` and walked with `iter` or `for`
 /// # Example
 ///
 /// ```
@@ -343,6 +363,31 @@ impl Message for Trame {
     fn options(&self) -> MessageOption { MessageOption::None }
 }
 
+/// Iterate over the leaves of a trame
+pub trait TrameExt {
+    /// Visit each message of the trame
+    ///
+    /// # Example
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate rdp;
+    /// # use rdp::model::data::{DataType, Trame, TrameExt, U16};
+    /// # fn main() {
+    ///     let t = trame![U16::LE(1), U16::LE(2), 3_u8];
+    ///     let ids: Vec<u16> = t.visit_iter().filter_map(|x| match x {
+    ///         DataType::U16(id) => Some(id),
+    ///         _ => None,
+    ///     }).collect();
+    ///     assert_eq!(ids, [1, 2]);
+    /// # }
+    /// ```
+    fn visit_iter(&self) -> impl Iterator<Item = DataType<'_>>;
+}
+
+impl TrameExt for Trame {
+    fn visit_iter(&self) -> impl Iterator<Item = DataType<'_>> { self.iter().map(|message| message.visit()) }
+}
+
 /// A component is key value ordered
 pub type Component = IndexMap<String, Box<dyn Message>>;
 
@@ -424,7 +469,7 @@ impl Message for Component {
             if dynamic_size.contains_key(name) {
                 let mut local = vec![0; dynamic_size[name]];
                 reader.read_exact(&mut local)?;
-                value.read(&mut Cursor::new(local))?;
+                value.read_from_bytes(&local)?;
             } else {
                 value.read(reader)?;
             }
@@ -687,6 +732,62 @@ impl Message for U32 {
     fn options(&self) -> MessageOption { MessageOption::None }
 }
 
+/// Checked arithmetic keeping the endianness
+macro_rules! impl_checked_value {
+    ($name:ident, $type:ty) => {
+        impl $name {
+            /// Add `rhs`, None on overflow
+            ///
+            /// # Example
+            /// ```
+            #[doc = concat!("use rdp::model::data::", stringify!($name), ";")]
+            #[doc = concat!("assert_eq!(", stringify!($name), "::BE(1).checked_add(2), Some(", stringify!($name), "::BE(3)));")]
+            #[doc = concat!("assert_eq!(", stringify!($name), "::LE(", stringify!($type), "::MAX).checked_add(1), None);")]
+            /// ```
+            pub fn checked_add(self, rhs: $type) -> Option<$name> {
+                match self {
+                    Value::BE(value) => value.checked_add(rhs).map(Value::BE),
+                    Value::LE(value) => value.checked_add(rhs).map(Value::LE),
+                }
+            }
+
+            /// Subtract `rhs`, zero on underflow
+            pub fn saturating_sub(self, rhs: $type) -> $name {
+                match self {
+                    Value::BE(value) => Value::BE(value.saturating_sub(rhs)),
+                    Value::LE(value) => Value::LE(value.saturating_sub(rhs)),
+                }
+            }
+        }
+    };
+}
+
+impl_checked_value!(U16, u16);
+impl_checked_value!(U32, u32);
+
+/// Narrowing of a length into a little endian value
+macro_rules! impl_try_from_value {
+    ($name:ident, $type:ty, $from:ty) => {
+        impl TryFrom<$from> for $name {
+            type Error = Error;
+
+            /// `RdpErrorKind::InvalidSize` if the value doesn't fit
+            fn try_from(value: $from) -> RdpResult<$name> {
+                <$type>::try_from(value).map(Value::LE).map_err(|_| {
+                    Error::RdpError(RdpError::new(
+                        RdpErrorKind::InvalidSize,
+                        &format!("DATA: {} doesn't fit in {}", value, stringify!($name)),
+                    ))
+                })
+            }
+        }
+    };
+}
+
+impl_try_from_value!(U16, u16, u32);
+impl_try_from_value!(U16, u16, u64);
+impl_try_from_value!(U32, u32, u64);
+
 /// This is a wrapper around
 /// a copyable message to check constness
 #[derive(Debug)]
@@ -1150,4 +1251,18 @@ mod test {
         x.write(&mut stream).unwrap();
         assert_eq!(stream.get_ref().as_slice(), [1]);
     }
+
+    #[test]
+    fn test_checked_values() {
+        assert_eq!(U16::try_from(0xffff_u32).unwrap(), U16::LE(0xffff));
+        assert_eq!(U32::try_from(0x1_0000_u64).unwrap().inner(), 0x1_0000);
+        match U16::try_from(0x1_0000_u64) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidSize),
+            _ => panic!("expected an invalid size error"),
+        }
+        assert!(U32::try_from(u64::from(u32::MAX) + 1).is_err());
+        assert_eq!(U16::BE(3).saturating_sub(4), U16::BE(0));
+        assert_eq!(U32::LE(5).saturating_sub(4), U32::LE(1));
+        assert_eq!(U32::LE(u32::MAX).checked_add(1), None);
+    }
 }
@@ -1,11 +1,19 @@
-use std::io::{Error as IoError, Read, Write};
-use std::string::String;
-
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{Read, Write};
+use std::string::{FromUtf16Error, FromUtf8Error, String};
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
 use native_tls::{Error as SslError, HandshakeError};
 use num_enum::{TryFromPrimitive, TryFromPrimitiveError};
 use thiserror::Error;
 
+use crate::core::license::ErrorCode;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RdpErrorKind {
     /// Unexpected data
     #[error("Invalid data")]
@@ -62,6 +70,19 @@ pub enum RdpErrorKind {
     #[error("Possible man-in-the-middle attack detected")]
     PossibleMITM,
 
+    /// Credentials rejected by the server during NLA
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+
+    /// Server certificate rejected by the client
+    #[error("Invalid server certificate")]
+    CertificateInvalid,
+
+    /// The server asked for a client certificate
+    /// and none was configured
+    #[error("Client certificate required")]
+    CertificateRequired,
+
     /// Some channel or user can be rejected
     /// by server during connection step
     #[error("Server rejected channel or user")]
@@ -80,9 +101,14 @@ pub enum RdpErrorKind {
 
     #[error("Invalid data detected during RLE decode")]
     RleDecode,
+
+    /// An operation didn't complete in time
+    #[error("Timeout")]
+    Timeout,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RdpError {
     /// Kind of error
     kind: RdpErrorKind,
@@ -118,6 +144,72 @@ impl RdpError {
     pub fn kind(&self) -> RdpErrorKind { self.kind }
 }
 
+/// Non fatal message of the server
+/// The session goes on after it
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RdpWarning {
+    /// Error alert accepted during the license exchange
+    #[error("License warning {0:?}")]
+    LicenseWarning(ErrorCode),
+
+    /// Error info PDU, the code explains an upcoming disconnection
+    #[error("Server error info {0:#x}")]
+    ServerErrorInfo(u32),
+
+    /// Informational event of the server
+    #[error("Server message: {0}")]
+    ServerInfoMessage(String),
+
+    /// Unknown block type in the conference create response
+    #[error("Unknown server block {0:#x}")]
+    UnknownBlock(u16),
+
+    /// Static virtual channel refused by the server
+    #[error("Channel {0} rejected")]
+    ChannelRejected(String),
+
+    /// Standard RDP security without any encryption
+    /// everything is sent in clear text
+    #[error("Session is not encrypted")]
+    UnencryptedSession,
+}
+
+type WarningCallback = Arc<Mutex<dyn Fn(RdpWarning) + Send>>;
+
+/// Callback receiving the warnings of a session
+/// Does nothing until a callback is set
+#[derive(Clone, Default)]
+pub struct WarningHandler(Option<WarningCallback>);
+
+impl WarningHandler {
+    /// Create a handler calling `f` for each warning
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::model::error::{RdpWarning, WarningHandler};
+    /// let handler = WarningHandler::new(|warning| println!("{}", warning));
+    /// handler.warn(RdpWarning::UnknownBlock(0x0c04));
+    /// ```
+    pub fn new<F: Fn(RdpWarning) + Send + 'static>(f: F) -> Self { WarningHandler(Some(Arc::new(Mutex::new(f)))) }
+
+    /// Log the warning and forward it to the callback
+    pub fn warn(&self, warning: RdpWarning) {
+        log_warn!("{}", warning);
+        if let Some(f) = &self.0 {
+            if let Ok(f) = f.lock() {
+                f(warning);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for WarningHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WarningHandler").field(&self.0.is_some()).finish()
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     /// RDP error
@@ -125,14 +217,16 @@ pub enum Error {
     RdpError(#[from] RdpError),
 
     /// All kind of IO error
+    /// except timeouts which are reported as `RdpErrorKind::Timeout`
     #[error("IO error: {0}")]
-    Io(#[from] IoError),
+    Io(#[source] IoError),
 
     /// SSL handshake error
     #[error("SSL handshake error")]
     SslHandshakeError,
 
     /// SSL error
+    #[cfg(not(target_arch = "wasm32"))]
     #[error("SSL error")]
     SslError(#[from] SslError),
 
@@ -149,6 +243,51 @@ pub enum Error {
     X509Decoding(String),
 }
 
+impl From<IoError> for Error {
+    /// Timed out operations are reported as an RDP timeout
+    /// `WouldBlock` is kept for nonblocking streams, the link layer
+    /// reports it as a timeout on sockets with timeouts
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::ErrorKind;
+    /// use rdp::model::error::{Error, RdpErrorKind};
+    /// match Error::from(std::io::Error::from(ErrorKind::TimedOut)) {
+    ///     Error::RdpError(e) => assert_eq!(e.kind(), RdpErrorKind::Timeout),
+    ///     _ => panic!("expected a timeout"),
+    /// }
+    /// assert!(matches!(Error::from(std::io::Error::from(ErrorKind::WouldBlock)), Error::Io(_)));
+    /// ```
+    fn from(e: IoError) -> Self {
+        match e.kind() {
+            IoErrorKind::TimedOut => Error::RdpError(RdpError::new(RdpErrorKind::Timeout, &e.to_string())),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Invalid UTF-8 string: {}", e)))
+    }
+}
+
+impl From<FromUtf16Error> for Error {
+    /// # Example
+    /// ```
+    /// use rdp::model::error::{Error, RdpErrorKind, RdpResult};
+    /// fn decode(units: &[u16]) -> RdpResult<String> { Ok(String::from_utf16(units)?) }
+    /// match decode(&[0xd800]) {
+    ///     Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::InvalidData),
+    ///     _ => panic!("expected invalid data"),
+    /// }
+    /// ```
+    fn from(e: FromUtf16Error) -> Self {
+        Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("Invalid UTF-16 string: {}", e)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl<S: Read + Write> From<HandshakeError<S>> for Error {
     fn from(_: HandshakeError<S>) -> Error { Error::SslHandshakeError }
 }
@@ -183,3 +322,36 @@ macro_rules! try_let {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kind(error: Error) -> Option<RdpErrorKind> {
+        match error {
+            Error::RdpError(e) => Some(e.kind()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_from_io_error() {
+        let read = |result: Result<(), IoError>| -> RdpResult<()> { Ok(result?) };
+        match read(Err(IoError::from(IoErrorKind::ConnectionReset))) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), IoErrorKind::ConnectionReset),
+            result => panic!("expected an IO error {:?}", result),
+        }
+        assert_eq!(kind(Error::from(IoError::from(IoErrorKind::TimedOut))), Some(RdpErrorKind::Timeout));
+    }
+
+    #[test]
+    fn test_from_utf_error() {
+        let utf8 = |bytes: Vec<u8>| -> RdpResult<String> { Ok(String::from_utf8(bytes)?) };
+        assert_eq!(utf8(b"foo".to_vec()).unwrap(), "foo");
+        assert_eq!(kind(utf8(vec![0xff]).unwrap_err()), Some(RdpErrorKind::InvalidData));
+
+        let utf16 = |units: &[u16]| -> RdpResult<String> { Ok(String::from_utf16(units)?) };
+        assert_eq!(utf16(&[0x66, 0x6f, 0x6f]).unwrap(), "foo");
+        assert_eq!(kind(utf16(&[0xdc00, 0x66]).unwrap_err()), Some(RdpErrorKind::InvalidData));
+    }
+}
@@ -1,10 +1,190 @@
-use std::io::{Read, Write};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
 
-use native_tls::{Certificate, TlsConnector, TlsStream};
+#[cfg(not(target_arch = "wasm32"))]
+use native_tls::{Certificate, HandshakeError, Identity, TlsConnector, TlsStream};
+#[cfg(not(target_arch = "wasm32"))]
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::gateway::GatewayStream;
+#[cfg(feature = "pcap")]
+use crate::core::pcap::{Direction, PcapWriter};
 use crate::model::data::Message;
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
 
+/// Certificate and private key of the client
+/// used when the server asks for a client certificate
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct TlsIdentity(pub Identity);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl std::fmt::Debug for TlsIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("TlsIdentity") }
+}
+
+/// A server requesting a client certificate
+/// aborts the handshake with a certificate required alert
+#[cfg(not(target_arch = "wasm32"))]
+fn handshake_error<S: Read + Write>(error: HandshakeError<S>, has_identity: bool) -> Error {
+    match &error {
+        HandshakeError::Failure(e) if !has_identity && e.to_string().contains("certificate required") => {
+            Error::RdpError(RdpError::new(
+                RdpErrorKind::CertificateRequired,
+                "LINK: Server requires a client certificate",
+            ))
+        }
+        _ => error.into(),
+    }
+}
+
+/// Compare a DNS name of a certificate with the host
+/// A wildcard only replaces the whole leftmost label
+fn dns_name_matches(name: &str, host: &str) -> bool {
+    let (name, host) =
+        (name.trim_end_matches('.').to_ascii_lowercase(), host.trim_end_matches('.').to_ascii_lowercase());
+    match name.strip_prefix("*.") {
+        Some(suffix) => host.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => name == host,
+    }
+}
+
+/// Check the host against the subject alternative names
+/// of a DER encoded certificate, the common name is only used
+/// when there is no DNS or IP address alternative name
+///
+/// # Example
+/// ```
+/// use rdp::model::link::certificate_matches_host;
+/// let certified = rcgen::generate_simple_self_signed(vec!["*.example.com".to_string()]).unwrap();
+/// assert!(certificate_matches_host(certified.cert.der(), "rdp.example.com").unwrap());
+/// assert!(!certificate_matches_host(certified.cert.der(), "example.com").unwrap());
+/// ```
+pub fn certificate_matches_host(der: &[u8], host: &str) -> RdpResult<bool> {
+    let (_, certificate) = parse_x509_certificate(der).map_err(|e| {
+        Error::RdpError(RdpError::new(RdpErrorKind::CertificateInvalid, &format!("LINK: Invalid certificate {}", e)))
+    })?;
+    let ip = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+    let alt_names = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension.value.general_names.clone(),
+        _ => Vec::new(),
+    };
+    let mut has_alt_name = false;
+    for alt_name in alt_names {
+        let matches = match (alt_name, ip) {
+            (GeneralName::DNSName(name), None) => dns_name_matches(name, host),
+            (GeneralName::IPAddress(address), Some(IpAddr::V4(ip))) => address == ip.octets(),
+            (GeneralName::IPAddress(address), Some(IpAddr::V6(ip))) => address == ip.octets(),
+            (GeneralName::DNSName(_) | GeneralName::IPAddress(_), _) => false,
+            _ => continue,
+        };
+        if matches {
+            return Ok(true);
+        }
+        has_alt_name = true;
+    }
+    if has_alt_name {
+        return Ok(false);
+    }
+    let matches = certificate.subject().iter_common_name().filter_map(|common_name| common_name.as_str().ok()).any(
+        |common_name| match ip {
+            Some(ip) => common_name.parse::<IpAddr>() == Ok(ip),
+            None => dns_name_matches(common_name, host),
+        },
+    );
+    Ok(matches)
+}
+
+/// Readiness of the next frame of a stream
+///
+/// The connection sequence waits on it before each read,
+/// so a non-blocking stream can be connected from an event loop
+pub trait FrameWait {
+    /// Resolve once a whole TPKT or fast path frame can be read
+    fn frame(&mut self) -> impl Future<Output = RdpResult<()>>;
+
+    /// Resolve once more bytes can be read
+    /// Called when a read returned `WouldBlock` in the middle of a frame,
+    /// the frame is then read again from its start
+    fn data(&mut self) -> impl Future<Output = RdpResult<()>> { self.frame() }
+
+    /// Reads of the stream block until the data is there
+    /// Frames are then read only once
+    fn is_blocking(&self) -> bool { false }
+}
+
+/// A stream which blocks on read always has its next frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Blocking;
+
+impl FrameWait for Blocking {
+    fn frame(&mut self) -> impl Future<Output = RdpResult<()>> { std::future::ready(Ok(())) }
+
+    /// A nonblocking stream given to a blocking connection keeps its error
+    fn data(&mut self) -> impl Future<Output = RdpResult<()>> {
+        std::future::ready(Err(Error::Io(io::ErrorKind::WouldBlock.into())))
+    }
+
+    fn is_blocking(&self) -> bool { true }
+}
+
+/// A read of a nonblocking stream is missing data
+pub(crate) fn is_would_block<T>(result: &RdpResult<T>) -> bool {
+    matches!(result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Wait for the next frame and read it with `read`
+/// The read is done again each time more data is received
+/// until it doesn't return `WouldBlock`
+pub(crate) async fn read_when_ready<T>(
+    wait: &mut impl FrameWait, mut read: impl FnMut() -> RdpResult<T>,
+) -> RdpResult<T> {
+    wait.frame().await?;
+    loop {
+        let result = read();
+        if !is_would_block(&result) {
+            return result;
+        }
+        wait.data().await?;
+    }
+}
+
+/// TLS handshake going on each time more data is received
+#[cfg(not(target_arch = "wasm32"))]
+async fn tls_handshake<T: Read + Write>(
+    connector: &TlsConnector, stream: T, has_identity: bool, wait: &mut impl FrameWait,
+) -> RdpResult<TlsStream<T>> {
+    let mut result = connector.connect("", stream);
+    loop {
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(HandshakeError::WouldBlock(handshake)) if !wait.is_blocking() => {
+                wait.data().await?;
+                result = handshake.handshake();
+            }
+            Err(e) => return Err(handshake_error(e, has_identity)),
+        }
+    }
+}
+
+/// Run a connection sequence waiting with `Blocking`
+/// It completes in a single poll
+pub(crate) fn run_blocking<T>(sequence: impl Future<Output = RdpResult<T>>) -> RdpResult<T> {
+    match pin!(sequence).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidAutomata,
+            "LINK: Connection sequence waits on a blocking stream",
+        ))),
+    }
+}
+
 /// This a wrapper to work equals
 /// for a stream and a TLS stream
 #[derive(Debug)]
@@ -12,38 +192,52 @@ pub enum Stream<S> {
     /// Raw stream that implement Read + Write
     Raw(S),
     /// TLS Stream
+    #[cfg(not(target_arch = "wasm32"))]
     Ssl(TlsStream<S>),
+    /// Stream tunneled through a gateway
+    #[cfg(not(target_arch = "wasm32"))]
+    Gateway(Box<GatewayStream<S>>),
+    /// TLS Stream inside a gateway tunnel
+    #[cfg(not(target_arch = "wasm32"))]
+    GatewaySsl(Box<TlsStream<GatewayStream<S>>>),
 }
 
-impl<S> Write for Stream<S>
-where
-    S: Write,
-    TlsStream<S>: Write,
-{
+impl<S: Read + Write> Write for Stream<S> {
     fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
         match self {
             Stream::Raw(e) => e.write(buffer),
+            #[cfg(not(target_arch = "wasm32"))]
             Stream::Ssl(e) => e.write(buffer),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Gateway(e) => e.write(buffer),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::GatewaySsl(e) => e.write(buffer),
         }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
             Stream::Raw(e) => e.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
             Stream::Ssl(e) => e.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Gateway(e) => e.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::GatewaySsl(e) => e.flush(),
         }
     }
 }
 
-impl<S> Read for Stream<S>
-where
-    S: Read,
-    TlsStream<S>: Read,
-{
+impl<S: Read + Write> Read for Stream<S> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         match self {
             Stream::Raw(e) => e.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
             Stream::Ssl(e) => e.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Gateway(e) => e.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::GatewaySsl(e) => e.read(buf),
         }
     }
 }
@@ -51,9 +245,19 @@ where
 impl<S: Read + Write> Stream<S> {
     /// Shutdown the stream
     /// Only works when stream is a SSL stream
+    /// or a gateway tunnel
     pub fn shutdown(&mut self) -> std::io::Result<()> {
-        if let Stream::Ssl(e) = self {
-            e.shutdown()?;
+        match self {
+            Stream::Raw(_) => (),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Ssl(e) => e.shutdown()?,
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Gateway(e) => e.shutdown()?,
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::GatewaySsl(e) => {
+                e.shutdown()?;
+                e.get_mut().shutdown()?;
+            }
         }
         Ok(())
     }
@@ -65,6 +269,30 @@ impl<S: Read + Write> Stream<S> {
 pub struct Link<S> {
     stream: Stream<S>,
     serialization_buffer: Vec<u8>,
+    /// Expected SHA-256 fingerprint of the server certificate
+    certificate_pin: Option<Vec<u8>>,
+    /// Host name the server certificate must match
+    expected_hostname: Option<String>,
+    /// Certificate and key presented to the server
+    #[cfg(not(target_arch = "wasm32"))]
+    client_identity: Option<TlsIdentity>,
+    /// Bytes written to and read from the stream
+    /// TLS records are counted once decrypted
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// The stream has read or write timeouts,
+    /// an operation which would block has timed out
+    socket_timeouts: bool,
+    /// Keep the bytes of the frame being read
+    /// so it can be read again once more data is received
+    resumable: bool,
+    /// Bytes of the frame being read
+    replay: Vec<u8>,
+    /// Bytes of `replay` already read again
+    replay_position: usize,
+    /// Capture of the bytes once decrypted
+    #[cfg(feature = "pcap")]
+    recorder: Option<PcapWriter>,
 }
 
 impl<S: Read + Write> Link<S> {
@@ -79,7 +307,46 @@ impl<S: Read + Write> Link<S> {
     /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
     /// let link_tcp = Link::new(Stream::Raw(TcpStream::connect(&addr).unwrap()));
     /// ```
-    pub fn new(stream: Stream<S>) -> Self { Link { stream, serialization_buffer: Vec::new() } }
+    pub fn new(stream: Stream<S>) -> Self {
+        Link {
+            stream,
+            serialization_buffer: Vec::new(),
+            certificate_pin: None,
+            expected_hostname: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            client_identity: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            socket_timeouts: false,
+            resumable: false,
+            replay: Vec::new(),
+            replay_position: 0,
+            #[cfg(feature = "pcap")]
+            recorder: None,
+        }
+    }
+
+    /// Pin the SHA-256 fingerprint of the server certificate
+    /// The TLS handshake fails on any other certificate,
+    /// even if the certificate chain is not checked
+    pub fn set_certificate_pin(&mut self, sha256: Vec<u8>) { self.certificate_pin = Some(sha256); }
+
+    /// Check the server certificate is issued for this host
+    /// The check is done even if the certificate chain is not checked
+    pub fn set_expected_hostname(&mut self, host: String) { self.expected_hostname = Some(host); }
+
+    /// Report `WouldBlock` errors of the stream as `RdpErrorKind::Timeout`
+    /// Sockets with a read or write timeout report it as `WouldBlock` on unix,
+    /// nonblocking streams keep their `WouldBlock` errors
+    pub fn set_socket_timeouts(&mut self, socket_timeouts: bool) { self.socket_timeouts = socket_timeouts; }
+
+    /// Keep the bytes of each frame until it is whole
+    /// A frame cut by `WouldBlock` is read again by the next `read_frame`
+    pub fn set_resumable_reads(&mut self, resumable: bool) { self.resumable = resumable; }
+
+    /// Present a client certificate during the TLS handshake
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_client_identity(&mut self, identity: TlsIdentity) { self.client_identity = Some(identity); }
 
     /// This method is designed to write a Message
     /// either for TCP or SSL stream
@@ -108,7 +375,12 @@ impl<S: Read + Write> Link<S> {
     pub fn write_msg(&mut self, message: &dyn Message) -> RdpResult<()> {
         self.serialization_buffer.clear();
         message.write(&mut self.serialization_buffer)?;
-        self.stream.write_all(&self.serialization_buffer)?;
+        self.stream.write_all(&self.serialization_buffer).map_err(|e| self.timed_out(e))?;
+        self.bytes_sent += self.serialization_buffer.len() as u64;
+        #[cfg(feature = "pcap")]
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::ClientToServer, &self.serialization_buffer)?;
+        }
         Ok(())
     }
 
@@ -123,10 +395,30 @@ impl<S: Read + Write> Link<S> {
     /// ```
     pub fn read_exact_to_vec(&mut self, expected_size: usize) -> RdpResult<Vec<u8>> {
         let mut buffer = vec![0; expected_size];
-        self.stream.read_exact(&mut buffer)?;
+        self.read_exact(&mut buffer)?;
         Ok(buffer)
     }
 
+    /// Read a whole frame with `read`
+    /// With resumable reads, a frame cut by `WouldBlock`
+    /// is read again from its start by the next call
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::model::link::{Link, Stream};
+    /// use std::io::Cursor;
+    /// let mut link = Link::new(Stream::Raw(Cursor::new(vec![0, 1, 2])));
+    /// assert_eq!(link.read_frame(|link| link.read_exact_to_vec(2)).unwrap(), [0, 1])
+    /// ```
+    pub fn read_frame<T>(&mut self, read: impl FnOnce(&mut Self) -> RdpResult<T>) -> RdpResult<T> {
+        let result = read(self);
+        if !is_would_block(&result) {
+            self.replay.clear();
+        }
+        self.replay_position = 0;
+        result
+    }
+
     /// Start a ssl connection from a raw stream
     ///
     /// # Example
@@ -137,17 +429,112 @@ impl<S: Read + Write> Link<S> {
     /// let link_tcp = Link::new(Stream::Raw(TcpStream::connect(&addr).unwrap()));
     /// let link_ssl = link_tcp.start_ssl(false).unwrap();
     /// ```
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn start_ssl(self, check_certificate: bool) -> RdpResult<Link<S>> {
+        run_blocking(self.start_ssl_with(check_certificate, &mut Blocking))
+    }
+
+    /// Same as `start_ssl`, the handshake goes on once `wait` resolves
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn start_ssl_with(self, check_certificate: bool, wait: &mut impl FrameWait) -> RdpResult<Link<S>> {
         let mut builder = TlsConnector::builder();
         builder.danger_accept_invalid_certs(!check_certificate);
         builder.use_sni(false);
+        if let Some(identity) = &self.client_identity {
+            builder.identity(identity.0.clone());
+        }
 
         let connector = builder.build()?;
+        let has_identity = self.client_identity.is_some();
+
+        let stream = match self.stream {
+            Stream::Raw(stream) => Stream::Ssl(tls_handshake(&connector, stream, has_identity, wait).await?),
+            Stream::Gateway(stream) => {
+                Stream::GatewaySsl(Box::new(tls_handshake(&connector, *stream, has_identity, wait).await?))
+            }
+            _ => {
+                return Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::NotImplemented,
+                    "start_ssl on ssl stream is forbidden",
+                )))
+            }
+        };
+        let link = Link {
+            stream,
+            serialization_buffer: Vec::new(),
+            certificate_pin: self.certificate_pin,
+            expected_hostname: self.expected_hostname,
+            client_identity: self.client_identity,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            socket_timeouts: self.socket_timeouts,
+            resumable: self.resumable,
+            replay: Vec::new(),
+            replay_position: 0,
+            #[cfg(feature = "pcap")]
+            recorder: self.recorder,
+        };
+        link.check_certificate_pin()?;
+        // A pinned certificate is trusted whatever its host
+        if link.certificate_pin.is_none() {
+            link.check_hostname()?;
+        }
+        Ok(link)
+    }
 
-        if let Stream::Raw(stream) = self.stream {
-            return Ok(Link::new(Stream::Ssl(connector.connect("", stream)?)));
+    /// TLS is left to the `wss://` connection of the browser
+    #[cfg(target_arch = "wasm32")]
+    pub fn start_ssl(self, _check_certificate: bool) -> RdpResult<Link<S>> {
+        Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::NotImplemented,
+            "LINK: TLS is not available on wasm32, disable TLS and NLA behind a wss:// socket",
+        )))
+    }
+
+    /// TLS is left to the `wss://` connection of the browser
+    #[cfg(target_arch = "wasm32")]
+    pub async fn start_ssl_with(self, check_certificate: bool, _wait: &mut impl FrameWait) -> RdpResult<Link<S>> {
+        self.start_ssl(check_certificate)
+    }
+
+    /// Compare the server certificate with the expected host name if any
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_hostname(&self) -> RdpResult<()> {
+        let Some(host) = &self.expected_hostname else {
+            return Ok(());
+        };
+        let certificate = self.get_peer_certificate()?.ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::CertificateInvalid, "LINK: No server certificate"))
+        })?;
+        if !certificate_matches_host(&certificate.to_der()?, host)? {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::CertificateInvalid,
+                &format!(
+                    "LINK: Server certificate doesn't match host {}, use Connector::verify_hostname(false) or \
+                     Connector::certificate_pinning for a self-signed certificate",
+                    host
+                ),
+            )));
         }
-        Err(Error::RdpError(RdpError::new(RdpErrorKind::NotImplemented, "start_ssl on ssl stream is forbidden")))
+        Ok(())
+    }
+
+    /// Compare the server certificate with the pinned fingerprint if any
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_certificate_pin(&self) -> RdpResult<()> {
+        let Some(expected) = &self.certificate_pin else {
+            return Ok(());
+        };
+        let certificate = self.get_peer_certificate()?.ok_or_else(|| {
+            Error::RdpError(RdpError::new(RdpErrorKind::CertificateInvalid, "LINK: No server certificate"))
+        })?;
+        if Sha256::digest(certificate.to_der()?).as_slice() != expected.as_slice() {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::CertificateInvalid,
+                "LINK: Server certificate doesn't match the pinned fingerprint",
+            )));
+        }
+        Ok(())
     }
 
     /// Retrive the peer certificate
@@ -162,17 +549,47 @@ impl<S: Read + Write> Link<S> {
     /// let link_ssl = link_tcp.start_ssl(false).unwrap();
     /// let certificate = link_ssl.get_peer_certificate().unwrap().unwrap();
     /// ```
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn get_peer_certificate(&self) -> RdpResult<Option<Certificate>> {
-        if let Stream::Ssl(stream) = &self.stream {
-            Ok(stream.peer_certificate()?)
-        } else {
-            Err(Error::RdpError(RdpError::new(
+        match &self.stream {
+            Stream::Ssl(stream) => Ok(stream.peer_certificate()?),
+            Stream::GatewaySsl(stream) => Ok(stream.peer_certificate()?),
+            _ => Err(Error::RdpError(RdpError::new(
                 RdpErrorKind::InvalidData,
                 "get peer certificate on non ssl link is impossible",
-            )))
+            ))),
         }
     }
 
+    /// DER encoding of the peer certificate
+    pub(crate) fn get_peer_certificate_der(&self) -> RdpResult<Option<Vec<u8>>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return Ok(self.get_peer_certificate()?.map(|certificate| certificate.to_der()).transpose()?);
+        #[cfg(target_arch = "wasm32")]
+        Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::InvalidData,
+            "get peer certificate on non ssl link is impossible",
+        )))
+    }
+
+    /// Bytes sent and received since the link was opened
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::model::link::{Link, Stream};
+    /// use std::io::Cursor;
+    /// let mut link = Link::new(Stream::Raw(Cursor::new(vec![0, 1, 2])));
+    /// link.read_exact_to_vec(2).unwrap();
+    /// link.write_msg(&vec![0_u8; 5]).unwrap();
+    /// assert_eq!(link.get_traffic(), (5, 2));
+    /// ```
+    pub fn get_traffic(&self) -> (u64, u64) { (self.bytes_sent, self.bytes_received) }
+
+    /// Record the traffic into a pcap capture
+    /// TLS records are recorded once decrypted
+    #[cfg(feature = "pcap")]
+    pub fn set_recorder(&mut self, recorder: PcapWriter) { self.recorder = Some(recorder) }
+
     /// Close the stream
     /// Only works on SSL Stream
     pub fn shutdown(&mut self) -> RdpResult<()> { Ok(self.stream.shutdown()?) }
@@ -181,9 +598,176 @@ impl<S: Read + Write> Link<S> {
     pub fn get_stream(self) -> Stream<S> { self.stream }
 }
 
+impl<S> Link<S> {
+    /// An operation which would block on a stream with timeouts has timed out
+    fn timed_out(&self, error: io::Error) -> io::Error {
+        if self.socket_timeouts && error.kind() == io::ErrorKind::WouldBlock {
+            io::Error::new(io::ErrorKind::TimedOut, error)
+        } else {
+            error
+        }
+    }
+}
+
 impl<S> Read for Link<S>
 where
     Stream<S>: Read,
 {
-    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, std::io::Error> { self.stream.read(buffer) }
+    /// Bytes of a frame read again come first
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, std::io::Error> {
+        if self.replay_position < self.replay.len() {
+            let size = (&self.replay[self.replay_position..]).read(buffer)?;
+            self.replay_position += size;
+            return Ok(size);
+        }
+        let size = self.stream.read(buffer).map_err(|e| self.timed_out(e))?;
+        if self.resumable {
+            self.replay.extend_from_slice(&buffer[..size]);
+            self.replay_position = self.replay.len();
+        }
+        self.bytes_received += size as u64;
+        #[cfg(feature = "pcap")]
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::ServerToClient, &buffer[..size])?;
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use native_tls::{Identity, TlsAcceptor};
+
+    use super::*;
+
+    /// Connect to a TLS server using a self-signed certificate
+    /// generated for the test, the pin is computed from its DER encoding
+    fn connect_pinned(pin: impl FnOnce(&[u8]) -> Vec<u8>) -> RdpResult<Link<TcpStream>> {
+        connect_self_signed(|link, der| link.set_certificate_pin(pin(der)))
+    }
+
+    /// Connect to a TLS server using a self-signed certificate for localhost
+    /// the link is set up with the DER encoding of the certificate
+    fn connect_self_signed(setup: impl FnOnce(&mut Link<TcpStream>, &[u8])) -> RdpResult<Link<TcpStream>> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let identity =
+            Identity::from_pkcs8(certified.cert.pem().as_bytes(), certified.key_pair.serialize_pem().as_bytes())
+                .unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // the client may close the connection during the handshake
+            let _ = acceptor.accept(stream);
+        });
+
+        let mut link = Link::new(Stream::Raw(TcpStream::connect(address).unwrap()));
+        setup(&mut link, certified.cert.der());
+        let result = link.start_ssl(false);
+        server.join().unwrap();
+        result
+    }
+
+    /// The client certificate is self-signed too,
+    /// `rcgen` gives the PEM encoded certificate and PKCS #8 key
+    #[test]
+    fn test_client_identity() {
+        let client = rcgen::generate_simple_self_signed(vec!["rdp-rs".to_string()]).unwrap();
+        let identity =
+            Identity::from_pkcs8(client.cert.pem().as_bytes(), client.key_pair.serialize_pem().as_bytes()).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let acceptor = TlsAcceptor::new(
+            Identity::from_pkcs8(certified.cert.pem().as_bytes(), certified.key_pair.serialize_pem().as_bytes())
+                .unwrap(),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || acceptor.accept(listener.accept().unwrap().0).is_ok());
+
+        let mut link = Link::new(Stream::Raw(TcpStream::connect(address).unwrap()));
+        link.set_client_identity(TlsIdentity(identity));
+        assert!(link.start_ssl(false).is_ok());
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn test_certificate_pin_match() {
+        assert!(connect_pinned(|der| Sha256::digest(der).to_vec()).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_pin_mismatch() {
+        match connect_pinned(|_| vec![0; 32]) {
+            Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::CertificateInvalid),
+            _ => panic!("pinned certificate must be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_hostname_match() {
+        assert!(connect_self_signed(|link, _| link.set_expected_hostname("LOCALHOST".to_string())).is_ok());
+    }
+
+    #[test]
+    fn test_hostname_mismatch() {
+        match connect_self_signed(|link, _| link.set_expected_hostname("rdp.example.com".to_string())) {
+            Err(Error::RdpError(e)) => {
+                assert_eq!(e.kind(), RdpErrorKind::CertificateInvalid);
+                assert!(e.to_string().contains("verify_hostname(false)"));
+            }
+            _ => panic!("certificate of another host must be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_hostname_pinned() {
+        assert!(connect_self_signed(|link, der| {
+            link.set_certificate_pin(Sha256::digest(der).to_vec());
+            link.set_expected_hostname("rdp.example.com".to_string());
+        })
+        .is_ok());
+    }
+
+    /// Stream of a socket whose timeout expired
+    struct Stalled;
+
+    impl Read for Stalled {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Err(io::ErrorKind::WouldBlock.into()) }
+    }
+
+    impl Write for Stalled {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> { Err(io::ErrorKind::WouldBlock.into()) }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_socket_timeouts() {
+        let mut link = Link::new(Stream::Raw(Stalled));
+        assert!(matches!(link.read_exact_to_vec(1), Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock));
+
+        link.set_socket_timeouts(true);
+        assert!(matches!(link.read_exact_to_vec(1), Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::Timeout));
+        assert!(matches!(link.write_msg(&vec![0_u8]), Err(Error::RdpError(e)) if e.kind() == RdpErrorKind::Timeout));
+    }
+
+    #[test]
+    fn test_dns_name_matches() {
+        assert!(dns_name_matches("*.example.com", "rdp.example.com"));
+        assert!(dns_name_matches("rdp.example.com.", "RDP.example.com"));
+        assert!(!dns_name_matches("*.example.com", "a.rdp.example.com"));
+        assert!(!dns_name_matches("*.example.com", ".example.com"));
+    }
+
+    #[test]
+    fn test_certificate_matches_ip() {
+        let certified = rcgen::generate_simple_self_signed(vec!["10.0.0.1".to_string()]).unwrap();
+        assert!(certificate_matches_host(certified.cert.der(), "10.0.0.1").unwrap());
+        assert!(!certificate_matches_host(certified.cert.der(), "10.0.0.2").unwrap());
+    }
 }
@@ -0,0 +1,36 @@
+//! Diagnostic messages of the protocol layers
+//!
+//! Messages are emitted through `tracing` when the `tracing` feature is enabled
+//! and discarded otherwise
+
+/// Detail of the protocol progress, like an ignored PDU
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Unexpected behavior of the server which doesn't break the session
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Failure of a local resource
+/// Only optional resources like the audio output use it
+#[allow(unused_macros)]
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
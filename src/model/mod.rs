@@ -1,7 +1,10 @@
 #[macro_use]
+pub mod log;
+#[macro_use]
 pub mod data;
 pub mod link;
 #[macro_use]
 pub mod error;
 pub mod rnd;
+pub mod time;
 pub mod unicode;
@@ -0,0 +1,9 @@
+//! Clock of the client
+//!
+//! `std::time::Instant` and `SystemTime` panic on
+//! `wasm32-unknown-unknown`, the browser clock is used there
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
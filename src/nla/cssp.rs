@@ -7,7 +7,7 @@ use x509_parser::certificate::X509Certificate;
 use x509_parser::parse_x509_certificate;
 
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-use crate::model::link::Link;
+use crate::model::link::{read_when_ready, run_blocking, Blocking, FrameWait, Link};
 use crate::nla::sspi::AuthenticationProtocol;
 
 #[derive(Debug, AsnType, rasn::Encode, rasn::Decode)]
@@ -31,6 +31,11 @@ struct TsRequest {
 
     #[rasn(tag(explicit(3)))]
     pub_key_auth: Option<rasn::types::OctetString>,
+
+    /// NTSTATUS of a failed authentication, sent from version 3
+    /// Encoded as a signed integer, failures are negative
+    #[rasn(tag(explicit(4)))]
+    error_code: Option<i32>,
 }
 
 #[derive(Debug, AsnType, rasn::Encode)]
@@ -71,6 +76,7 @@ pub fn create_ts_request(nego: Vec<u8>) -> RdpResult<Vec<u8>> {
         nego_tokens: Some(vec![NegoDatum { nego_token: nego.into() }]),
         auth_info: None,
         pub_key_auth: None,
+        error_code: None,
     };
     Ok(rasn::der::encode(&ts_request)?)
 }
@@ -121,9 +127,11 @@ pub fn read_ts_server_challenge(stream: &[u8]) -> RdpResult<Vec<u8>> {
 pub fn create_ts_authenticate(nego: Vec<u8>, pub_key_auth: Vec<u8>) -> RdpResult<Vec<u8>> {
     let ts_authenticate = TsRequest {
         version: 2,
-        nego_tokens: Some(vec![NegoDatum { nego_token: nego.into() }]),
+        // Kerberos may complete without any last token
+        nego_tokens: if nego.is_empty() { None } else { Some(vec![NegoDatum { nego_token: nego.into() }]) },
         auth_info: None,
         pub_key_auth: Some(pub_key_auth.into()),
+        error_code: None,
     };
     Ok(rasn::der::encode(&ts_authenticate)?)
 }
@@ -147,6 +155,12 @@ pub fn read_public_certificate(stream: &[u8]) -> RdpResult<X509Certificate> {
 /// ```
 pub fn read_ts_validate(request: &[u8]) -> RdpResult<Vec<u8>> {
     let ts_validate: TsRequest = rasn::ber::decode(request)?;
+    if let Some(error_code) = ts_validate.error_code {
+        return Err(Error::RdpError(RdpError::new(
+            RdpErrorKind::AuthenticationFailed,
+            &format!("NLA: Server rejected credentials with status {:#010x}", error_code as u32),
+        )));
+    }
     let pub_key: Vec<u8> = ts_validate
         .pub_key_auth
         .ok_or_else(|| RdpError::new(RdpErrorKind::InvalidOptionalField, "public key missing"))?
@@ -163,8 +177,13 @@ fn create_ts_credentials(domain: Vec<u8>, user: Vec<u8>, password: Vec<u8>) -> R
 }
 
 fn create_ts_authinfo(auth_info: Vec<u8>) -> RdpResult<Vec<u8>> {
-    let ts_auth_info =
-        TsRequest { version: 2, nego_tokens: None, auth_info: Some(auth_info.into()), pub_key_auth: None };
+    let ts_auth_info = TsRequest {
+        version: 2,
+        nego_tokens: None,
+        auth_info: Some(auth_info.into()),
+        pub_key_auth: None,
+        error_code: None,
+    };
     Ok(rasn::der::encode(&ts_auth_info)?)
 }
 
@@ -204,11 +223,29 @@ fn read_asn1_tlv<R: Read>(reader: &mut R) -> RdpResult<Vec<u8>> {
     Ok(buffer)
 }
 
+/// Connection closed by the peer
+fn is_connection_closed(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted
+    )
+}
+
 /// This the main function for CSSP protocol
 /// It will use the raw link layer and the selected authenticate protocol
 /// to perform the NLA authenticate
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
 pub fn cssp_connect<S: Read + Write>(
     link: &mut Link<S>, authentication_protocol: &mut dyn AuthenticationProtocol, restricted_admin_mode: bool,
+) -> RdpResult<()> {
+    run_blocking(cssp_connect_with(link, authentication_protocol, restricted_admin_mode, &mut Blocking))
+}
+
+/// Same as `cssp_connect`, each answer of the server is read once `wait`
+/// resolves
+pub async fn cssp_connect_with<S: Read + Write>(
+    link: &mut Link<S>, authentication_protocol: &mut dyn AuthenticationProtocol, restricted_admin_mode: bool,
+    wait: &mut impl FrameWait,
 ) -> RdpResult<()> {
     // first step is to send the negotiate message from authentication protocol
     let negotiate_message = create_ts_request(authentication_protocol.create_negotiate_message()?)?;
@@ -216,7 +253,7 @@ pub fn cssp_connect<S: Read + Write>(
 
     // now receive server challenge
     let server_challenge = {
-        let message = read_asn1_tlv(link)?;
+        let message = read_when_ready(wait, || link.read_frame(read_asn1_tlv)).await?;
         read_ts_server_challenge(&message)?
     };
 
@@ -227,7 +264,7 @@ pub fn cssp_connect<S: Read + Write>(
     let mut security_interface = authentication_protocol.build_security_interface();
 
     // Get the peer public certificate
-    let certificate_der = try_option!(link.get_peer_certificate()?, "No public certificate available")?.to_der()?;
+    let certificate_der = try_option!(link.get_peer_certificate_der()?, "No public certificate available")?;
     let certificate = read_public_certificate(&certificate_der)?;
 
     // Now we can send back our challenge payload wit the public key encoded
@@ -238,8 +275,16 @@ pub fn cssp_connect<S: Read + Write>(
     link.write_msg(&challenge)?;
 
     // now server respond normally with the original public key incremented by one
+    // Servers which don't send an error code close the connection on bad
+    // credentials
     let inc_pub_key = {
-        let message = read_asn1_tlv(link)?;
+        let message = read_when_ready(wait, || link.read_frame(read_asn1_tlv)).await.map_err(|e| match e {
+            Error::Io(e) if is_connection_closed(&e) => Error::RdpError(RdpError::new(
+                RdpErrorKind::AuthenticationFailed,
+                "NLA: Server closed the connection after authentication",
+            )),
+            e => e,
+        })?;
         security_interface.gss_unwrapex(&(read_ts_validate(&message)?))?
     };
 
@@ -285,4 +330,25 @@ mod test {
             [48, 12, 160, 3, 2, 1, 2, 162, 5, 4, 3, 102, 111, 111]
         );
     }
+
+    #[test]
+    fn test_read_ts_validate_error_code() {
+        // version 3 with STATUS_LOGON_FAILURE as sent by Windows
+        let request = [48, 13, 160, 3, 2, 1, 3, 164, 6, 2, 4, 0xc0, 0, 0, 0x6d];
+        match read_ts_validate(&request) {
+            Err(Error::RdpError(e)) => {
+                assert_eq!(e.kind(), RdpErrorKind::AuthenticationFailed);
+                assert!(e.to_string().contains("0xc000006d"), "{}", e);
+            }
+            _ => panic!("expected an authentication failure"),
+        }
+    }
+
+    #[test]
+    fn test_create_ts_authenticate_without_nego_token() {
+        assert_eq!(
+            create_ts_authenticate(vec![], vec![0, 1, 2]).expect("Unable to create authenticate"),
+            [48, 12, 160, 3, 2, 1, 2, 163, 5, 4, 3, 0, 1, 2]
+        );
+    }
 }
@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::unicode::Unicode as _;
+use crate::nla::sspi::{AuthenticationProtocol, GenericSecurityService};
+
+/// Minimal binding of the GSSAPI C interface
+/// as exposed by MIT Kerberos (RFC 2744)
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_int, c_void};
+
+    pub type OM_uint32 = u32;
+
+    #[repr(C)]
+    pub struct gss_OID_desc {
+        pub length: OM_uint32,
+        pub elements: *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct gss_buffer_desc {
+        pub length: usize,
+        pub value: *mut c_void,
+    }
+
+    pub type gss_name_t = *mut c_void;
+    pub type gss_ctx_id_t = *mut c_void;
+    pub type gss_cred_id_t = *mut c_void;
+    pub type gss_channel_bindings_t = *mut c_void;
+
+    pub const GSS_S_COMPLETE: OM_uint32 = 0;
+
+    pub const GSS_C_MUTUAL_FLAG: OM_uint32 = 2;
+    pub const GSS_C_REPLAY_FLAG: OM_uint32 = 4;
+    pub const GSS_C_SEQUENCE_FLAG: OM_uint32 = 8;
+    pub const GSS_C_CONF_FLAG: OM_uint32 = 16;
+    pub const GSS_C_INTEG_FLAG: OM_uint32 = 32;
+
+    #[link(name = "gssapi_krb5")]
+    extern "C" {
+        pub fn gss_import_name(
+            minor_status: *mut OM_uint32, input_name_buffer: *mut gss_buffer_desc, input_name_type: *mut gss_OID_desc,
+            output_name: *mut gss_name_t,
+        ) -> OM_uint32;
+
+        pub fn gss_release_name(minor_status: *mut OM_uint32, name: *mut gss_name_t) -> OM_uint32;
+
+        pub fn gss_init_sec_context(
+            minor_status: *mut OM_uint32, claimant_cred_handle: gss_cred_id_t, context_handle: *mut gss_ctx_id_t,
+            target_name: gss_name_t, mech_type: *mut gss_OID_desc, req_flags: OM_uint32, time_req: OM_uint32,
+            input_chan_bindings: gss_channel_bindings_t, input_token: *mut gss_buffer_desc,
+            actual_mech_type: *mut *mut gss_OID_desc, output_token: *mut gss_buffer_desc, ret_flags: *mut OM_uint32,
+            time_rec: *mut OM_uint32,
+        ) -> OM_uint32;
+
+        pub fn gss_delete_sec_context(
+            minor_status: *mut OM_uint32, context_handle: *mut gss_ctx_id_t, output_token: *mut gss_buffer_desc,
+        ) -> OM_uint32;
+
+        pub fn gss_wrap(
+            minor_status: *mut OM_uint32, context_handle: gss_ctx_id_t, conf_req_flag: c_int, qop_req: OM_uint32,
+            input_message_buffer: *mut gss_buffer_desc, conf_state: *mut c_int,
+            output_message_buffer: *mut gss_buffer_desc,
+        ) -> OM_uint32;
+
+        pub fn gss_unwrap(
+            minor_status: *mut OM_uint32, context_handle: gss_ctx_id_t, input_message_buffer: *mut gss_buffer_desc,
+            output_message_buffer: *mut gss_buffer_desc, conf_state: *mut c_int, qop_state: *mut OM_uint32,
+        ) -> OM_uint32;
+
+        pub fn gss_release_buffer(minor_status: *mut OM_uint32, buffer: *mut gss_buffer_desc) -> OM_uint32;
+    }
+}
+
+/// GSS_KRB5_NT_PRINCIPAL_NAME 1.2.840.113554.1.2.2.1
+const KRB5_NT_PRINCIPAL_NAME: [u8; 10] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02, 0x01];
+
+/// SPNEGO mechanism 1.3.6.1.5.5.2
+/// Windows expect a Negotiate token inside CredSSP
+const SPNEGO_MECHANISM: [u8; 6] = [0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+
+/// Build a GSSAPI error from major and minor status
+fn gss_error(function: &str, major: ffi::OM_uint32, minor: ffi::OM_uint32) -> Error {
+    Error::RdpError(RdpError::new(
+        RdpErrorKind::InvalidAutomata,
+        &format!("KERBEROS: {} failed with major status {:#x} minor status {:#x}", function, major, minor),
+    ))
+}
+
+/// A GSSAPI routine failed if any calling or routine error bit is set
+fn is_gss_error(major: ffi::OM_uint32) -> bool { major & 0xffff_0000 != 0 }
+
+/// Borrow a slice as an input GSSAPI buffer
+fn input_buffer(data: &[u8]) -> ffi::gss_buffer_desc {
+    ffi::gss_buffer_desc { length: data.len(), value: data.as_ptr() as *mut c_void }
+}
+
+/// Copy then release a buffer allocated by GSSAPI
+fn take_buffer(buffer: &mut ffi::gss_buffer_desc) -> Vec<u8> {
+    let result = if buffer.value.is_null() || buffer.length == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(buffer.value as *const u8, buffer.length) }.to_vec()
+    };
+    let mut minor = 0;
+    unsafe { ffi::gss_release_buffer(&mut minor, buffer) };
+    result
+}
+
+/// Security context established with the KDC ticket
+/// shared between the authentication layer and the security interface
+struct Context {
+    handle: ffi::gss_ctx_id_t,
+    target: ffi::gss_name_t,
+}
+
+impl Context {
+    fn new(spn: &str) -> RdpResult<Self> {
+        let mut minor = 0;
+        let mut target = ptr::null_mut();
+        let mut name = input_buffer(spn.as_bytes());
+        let mut name_type = ffi::gss_OID_desc {
+            length: KRB5_NT_PRINCIPAL_NAME.len() as u32,
+            elements: KRB5_NT_PRINCIPAL_NAME.as_ptr() as *mut c_void,
+        };
+        let major = unsafe { ffi::gss_import_name(&mut minor, &mut name, &mut name_type, &mut target) };
+        if is_gss_error(major) {
+            return Err(gss_error("gss_import_name", major, minor));
+        }
+        Ok(Context { handle: ptr::null_mut(), target })
+    }
+
+    /// Run one step of the context establishment
+    /// Return the token to send and if the context is complete
+    fn step(&mut self, input: Option<&[u8]>) -> RdpResult<(Vec<u8>, bool)> {
+        let mut minor = 0;
+        let mut mechanism = ffi::gss_OID_desc {
+            length: SPNEGO_MECHANISM.len() as u32,
+            elements: SPNEGO_MECHANISM.as_ptr() as *mut c_void,
+        };
+        let mut input = input.map(input_buffer);
+        let mut output = ffi::gss_buffer_desc { length: 0, value: ptr::null_mut() };
+        let major = unsafe {
+            ffi::gss_init_sec_context(
+                &mut minor,
+                ptr::null_mut(),
+                &mut self.handle,
+                self.target,
+                &mut mechanism,
+                ffi::GSS_C_MUTUAL_FLAG
+                    | ffi::GSS_C_REPLAY_FLAG
+                    | ffi::GSS_C_SEQUENCE_FLAG
+                    | ffi::GSS_C_CONF_FLAG
+                    | ffi::GSS_C_INTEG_FLAG,
+                0,
+                ptr::null_mut(),
+                input.as_mut().map_or(ptr::null_mut(), |buffer| buffer as *mut _),
+                ptr::null_mut(),
+                &mut output,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        let token = take_buffer(&mut output);
+        if is_gss_error(major) {
+            return Err(gss_error("gss_init_sec_context", major, minor));
+        }
+        Ok((token, major & 0xffff == ffi::GSS_S_COMPLETE))
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> {
+        let mut minor = 0;
+        let mut conf_state: c_int = 0;
+        let mut input = input_buffer(data);
+        let mut output = ffi::gss_buffer_desc { length: 0, value: ptr::null_mut() };
+        let major = unsafe { ffi::gss_wrap(&mut minor, self.handle, 1, 0, &mut input, &mut conf_state, &mut output) };
+        let result = take_buffer(&mut output);
+        if is_gss_error(major) {
+            return Err(gss_error("gss_wrap", major, minor));
+        }
+        Ok(result)
+    }
+
+    fn unwrap(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> {
+        let mut minor = 0;
+        let mut conf_state: c_int = 0;
+        let mut qop_state = 0;
+        let mut input = input_buffer(data);
+        let mut output = ffi::gss_buffer_desc { length: 0, value: ptr::null_mut() };
+        let major = unsafe {
+            ffi::gss_unwrap(&mut minor, self.handle, &mut input, &mut output, &mut conf_state, &mut qop_state)
+        };
+        let result = take_buffer(&mut output);
+        if is_gss_error(major) {
+            return Err(gss_error("gss_unwrap", major, minor));
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        let mut minor = 0;
+        unsafe {
+            if !self.handle.is_null() {
+                ffi::gss_delete_sec_context(&mut minor, &mut self.handle, ptr::null_mut());
+            }
+            if !self.target.is_null() {
+                ffi::gss_release_name(&mut minor, &mut self.target);
+            }
+        }
+    }
+}
+
+/// Kerberos authentication through the system GSSAPI library
+/// Tickets are taken from the default credential cache
+/// so a `kinit` must be done before connecting
+pub struct Kerberos {
+    /// Security context shared with the security interface
+    context: Rc<RefCell<Context>>,
+    /// First token computed during construction
+    /// to detect early that a ticket can be obtained
+    initial_token: Option<Vec<u8>>,
+    /// Credentials forwarded to the server at the end of CredSSP
+    domain: String,
+    user: String,
+    password: String,
+}
+
+impl Kerberos {
+    /// Ctor of the Kerberos authentication layer
+    /// The service principal name is usually `TERMSRV/<hostname>`
+    ///
+    /// Fail if no service ticket can be obtained for the principal
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rdp::nla::kerberos::Kerberos;
+    /// let auth_layer = Kerberos::new("TERMSRV/server.example.com", "domain".to_string(), "user".to_string(), "password".to_string()).unwrap();
+    /// ```
+    pub fn new(spn: &str, domain: String, user: String, password: String) -> RdpResult<Self> {
+        let mut context = Context::new(spn)?;
+        let (initial_token, _) = context.step(None)?;
+        Ok(Kerberos {
+            context: Rc::new(RefCell::new(context)),
+            initial_token: Some(initial_token),
+            domain,
+            user,
+            password,
+        })
+    }
+}
+
+impl AuthenticationProtocol for Kerberos {
+    /// The SPNEGO token containing the AP-REQ
+    fn create_negotiate_message(&mut self) -> RdpResult<Vec<u8>> {
+        match self.initial_token.take() {
+            Some(token) => Ok(token),
+            None => Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "KERBEROS: Negotiate message already sent",
+            ))),
+        }
+    }
+
+    /// Process the AP-REP from server
+    /// The returned token may be empty when context is complete
+    fn read_challenge_message(&mut self, request: &[u8]) -> RdpResult<Vec<u8>> {
+        let (token, complete) = self.context.borrow_mut().step(Some(request))?;
+        if !complete {
+            return Err(Error::RdpError(RdpError::new(
+                RdpErrorKind::InvalidAutomata,
+                "KERBEROS: Security context not established after server response",
+            )));
+        }
+        Ok(token)
+    }
+
+    fn build_security_interface(&self) -> Box<dyn GenericSecurityService> {
+        Box::new(KerberosSecurityInterface { context: Rc::clone(&self.context) })
+    }
+
+    fn get_domain_name(&self) -> Vec<u8> { self.domain.to_utf16_le() }
+
+    fn get_user_name(&self) -> Vec<u8> { self.user.to_utf16_le() }
+
+    fn get_password(&self) -> Vec<u8> { self.password.to_utf16_le() }
+}
+
+/// Seal and unseal CredSSP payloads with the Kerberos session key
+struct KerberosSecurityInterface {
+    context: Rc<RefCell<Context>>,
+}
+
+impl GenericSecurityService for KerberosSecurityInterface {
+    fn gss_wrapex(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> { self.context.borrow_mut().wrap(data) }
+
+    fn gss_unwrapex(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> { self.context.borrow_mut().unwrap(data) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Without any configured realm no ticket can be obtained
+    #[test]
+    fn test_kerberos_without_ticket() {
+        assert!(Kerberos::new("TERMSRV/unknown.invalid", String::new(), String::new(), String::new()).is_err())
+    }
+
+    #[test]
+    fn test_is_gss_error() {
+        assert!(!is_gss_error(ffi::GSS_S_COMPLETE));
+        assert!(!is_gss_error(1));
+        assert!(is_gss_error(0x0007_0000));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod cssp;
+#[cfg(all(feature = "kerberos", unix))]
+pub mod kerberos;
 pub mod ntlm;
 pub mod rc4;
 pub mod sspi;
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::Cursor;
 
+use byteorder::{LittleEndian, ReadBytesExt as _};
 use hmac::{Hmac, Mac};
 use md4::digest::FixedOutput as _;
 use md4::{Digest, Md4};
@@ -257,6 +258,48 @@ fn read_target_info(data: &[u8]) -> RdpResult<HashMap<AvId, Vec<u8>>> {
     Ok(result)
 }
 
+/// MsvAvFlags bit telling the server a MIC is provided
+const MSV_AV_FLAG_MIC_PROVIDED: u32 = 0x0000_0002;
+
+/// Copy the target info of the challenge
+/// with the MIC provided flag set in MsvAvFlags
+///
+/// Without this flag a server requiring a MIC rejects the authentication
+/// See MS-NLMP 3.1.5.1.2
+fn target_info_with_mic(data: &[u8]) -> RdpResult<Vec<u8>> {
+    let mut stream = Cursor::new(data);
+    let mut result = Vec::new();
+    let mut has_flags = false;
+    let write_pair = |result: &mut Vec<u8>, av_id: u16, value: &[u8]| {
+        result.extend(to_vec(&component![
+            "AvId" => U16::LE(av_id),
+            "AvLen" => U16::LE(value.len() as u16),
+            "Value" => value.to_vec()
+        ]))
+    };
+    loop {
+        let mut element = av_pair();
+        element.read(&mut stream)?;
+        let av_id = cast!(DataType::U16, element["AvId"])?;
+        let value = cast!(DataType::Slice, element["Value"])?;
+        if av_id == AvId::MsvAvEOL as u16 {
+            break;
+        }
+        if av_id == AvId::MsvAvFlags as u16 {
+            let flags = Cursor::new(value).read_u32::<LittleEndian>()? | MSV_AV_FLAG_MIC_PROVIDED;
+            write_pair(&mut result, av_id, &flags.to_le_bytes());
+            has_flags = true;
+        } else {
+            write_pair(&mut result, av_id, value);
+        }
+    }
+    if !has_flags {
+        write_pair(&mut result, AvId::MsvAvFlags as u16, &MSV_AV_FLAG_MIC_PROVIDED.to_le_bytes());
+    }
+    write_pair(&mut result, AvId::MsvAvEOL as u16, &[]);
+    Ok(result)
+}
+
 /// Zero filled array
 ///
 /// This is a convenience method
@@ -290,7 +333,7 @@ fn md4(data: &[u8]) -> Vec<u8> {
 ///
 /// # Example
 /// ```rust, ignore
-/// let hash = md((b"foo");
+/// let hash = md5(b"foo");
 /// ```
 fn md5(data: &[u8]) -> Vec<u8> {
     let mut hasher = Md5::new();
@@ -304,12 +347,13 @@ fn md5(data: &[u8]) -> Vec<u8> {
 /// algorithm like in specification
 /// # Example
 /// ```rust, ignore
-/// let signature = hmac_md5(b"foo", b"bar");
+/// let signature = hmac_md5(b"foo", b"bar")?;
 /// ```
-fn hmac_md5(key: &[u8], data: &[u8]) -> Vec<u8> {
-    let mut stream = Hmac::<Md5>::new_from_slice(key).unwrap();
+fn hmac_md5(key: &[u8], data: &[u8]) -> RdpResult<Vec<u8>> {
+    let mut stream = Hmac::<Md5>::new_from_slice(key)
+        .map_err(|_| Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, "NTLM: Invalid HMAC key length")))?;
     stream.update(data);
-    stream.finalize_fixed().to_vec()
+    Ok(stream.finalize_fixed().to_vec())
 }
 
 /// This function is used to compute init key of another hmac_md5
@@ -321,7 +365,7 @@ fn hmac_md5(key: &[u8], data: &[u8]) -> Vec<u8> {
 /// ```rust, ignore
 /// let key = ntowfv2("hello123".to_string(), "user".to_string(), "domain".to_string())
 /// ```
-fn ntowfv2(password: &str, user: &str, domain: &str) -> Vec<u8> {
+fn ntowfv2(password: &str, user: &str, domain: &str) -> RdpResult<Vec<u8>> {
     hmac_md5(&md4(&password.to_utf16_le()), &(user.to_uppercase() + domain).to_utf16_le())
 }
 
@@ -335,7 +379,7 @@ fn ntowfv2(password: &str, user: &str, domain: &str) -> Vec<u8> {
 /// ```rust, ignore
 /// let key = ntowfv2("hello123".to_string(), "user".to_string(), "domain".to_string())
 /// ```
-fn ntowfv2_hash(hash: &[u8], user: &str, domain: &str) -> Vec<u8> {
+fn ntowfv2_hash(hash: &[u8], user: &str, domain: &str) -> RdpResult<Vec<u8>> {
     hmac_md5(hash, &(user.to_uppercase() + domain).to_utf16_le())
 }
 
@@ -346,7 +390,7 @@ fn ntowfv2_hash(hash: &[u8], user: &str, domain: &str) -> Vec<u8> {
 /// ```rust, ignore
 /// let key = lmowfv2("hello123".to_string(), "user".to_string(), "domain".to_string())
 /// ```
-fn lmowfv2(password: &str, user: &str, domain: &str) -> Vec<u8> { ntowfv2(password, user, domain) }
+fn lmowfv2(password: &str, user: &str, domain: &str) -> RdpResult<Vec<u8>> { ntowfv2(password, user, domain) }
 
 /// Compute all necessary response for NTLMv2 authentication
 ///
@@ -362,7 +406,7 @@ fn lmowfv2(password: &str, user: &str, domain: &str) -> Vec<u8> { ntowfv2(passwo
 fn compute_response_v2(
     response_key_nt: &[u8], response_key_lm: &[u8], server_challenge: &[u8], client_challenge: &[u8], time: &[u8],
     server_name: &[u8],
-) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+) -> RdpResult<(Vec<u8>, Vec<u8>, Vec<u8>)> {
     let response_version = b"\x01";
     let hi_response_version = b"\x01";
 
@@ -376,17 +420,17 @@ fn compute_response_v2(
         server_name.to_vec(),
     ]
     .concat();
-    let nt_proof_str = hmac_md5(response_key_nt, &[server_challenge.to_vec(), temp.clone()].concat());
+    let nt_proof_str = hmac_md5(response_key_nt, &[server_challenge.to_vec(), temp.clone()].concat())?;
     let nt_challenge_response = [nt_proof_str.clone(), temp.clone()].concat();
     let lm_challenge_response = [
-        hmac_md5(response_key_lm, &[server_challenge.to_vec(), client_challenge.to_vec()].concat()),
+        hmac_md5(response_key_lm, &[server_challenge.to_vec(), client_challenge.to_vec()].concat())?,
         client_challenge.to_vec(),
     ]
     .concat();
 
-    let session_base_key = hmac_md5(response_key_nt, &nt_proof_str);
+    let session_base_key = hmac_md5(response_key_nt, &nt_proof_str)?;
 
-    (nt_challenge_response, lm_challenge_response, session_base_key)
+    Ok((nt_challenge_response, lm_challenge_response, session_base_key))
 }
 
 /// This is a function described in specification
@@ -407,13 +451,13 @@ fn rc4k(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
 }
 
 /// Compute a signature of all data exchange during NTLMv2 handshake
-fn mic(
-    exported_session_key: &[u8], negotiate_message: &[u8], challenge_message: &[u8], authenticate_message: &[u8],
-) -> Vec<u8> {
-    hmac_md5(
-        exported_session_key,
-        &[negotiate_message.to_vec(), challenge_message.to_vec(), authenticate_message.to_vec()].concat(),
-    )
+/// The authenticate message is given with a zero MIC field
+///
+/// See MS-NLMP 3.1.5.1.2
+fn compute_mic(session_key: &[u8], negotiate: &[u8], challenge: &[u8], authenticate: &[u8]) -> RdpResult<[u8; 16]> {
+    let mut mic = [0; 16];
+    mic.copy_from_slice(&hmac_md5(session_key, &[negotiate, challenge, authenticate].concat())?);
+    Ok(mic)
 }
 
 /// NTLMv2 security interface generate a sign key
@@ -440,15 +484,15 @@ fn seal_key(exported_session_key: &[u8], is_client: bool) -> Vec<u8> {
 ///
 /// # Example
 /// ```rust, ignore
-/// let signature = mac(&mut Rc4::new(b"foo"), b"bar", 0, b"data");
+/// let signature = mac(&mut Rc4::new(b"foo"), b"bar", 0, b"data")?;
 /// ```
-fn mac(rc4_handle: &mut Rc4, signing_key: &[u8], seq_num: u32, data: &[u8]) -> Vec<u8> {
-    let signature = hmac_md5(signing_key, &[to_vec(&U32::LE(seq_num)).as_slice(), data].concat());
+fn mac(rc4_handle: &mut Rc4, signing_key: &[u8], seq_num: u32, data: &[u8]) -> RdpResult<Vec<u8>> {
+    let signature = hmac_md5(signing_key, &[to_vec(&U32::LE(seq_num)).as_slice(), data].concat())?;
     let mut encryped_signature = vec![0; 8];
 
     rc4_handle.process(&signature[0..8], &mut encryped_signature);
 
-    to_vec(&message_signature_ex(Some(&encryped_signature), Some(seq_num)))
+    Ok(to_vec(&message_signature_ex(Some(&encryped_signature), Some(seq_num))))
 }
 
 pub struct Ntlm {
@@ -458,10 +502,8 @@ pub struct Ntlm {
     user: String,
     /// Password
     password: String,
-    /// Key generated from NTLM hash
-    response_key_nt: Vec<u8>,
-    /// Key generated from NTLM hash
-    response_key_lm: Vec<u8>,
+    /// NT hash given in place of the password
+    password_hash: Option<Vec<u8>>,
     /// Keep trace of each messages to compute a final hash
     negotiate_message: Option<Vec<u8>>,
     /// Key use to ciphering messages
@@ -483,11 +525,10 @@ impl Ntlm {
     /// ```
     pub fn new(domain: String, user: String, password: String) -> Self {
         Ntlm {
-            response_key_nt: ntowfv2(&password, &user, &domain),
-            response_key_lm: lmowfv2(&password, &user, &domain),
             domain,
             user,
             password,
+            password_hash: None,
             negotiate_message: None,
             exported_session_key: None,
             is_unicode: false,
@@ -497,6 +538,10 @@ impl Ntlm {
     /// When you have in restricted mode
     /// You can use directly NTLM hash
     ///
+    /// `password_hash` is the NT hash, MD4 of the UTF-16 password.
+    /// It replaces the MD4 step of NTOWFv2 so the password is never needed,
+    /// the session base key and the MIC are then derived as usual
+    ///
     /// # Example
     /// ```no_run
     /// use rdp::nla::ntlm::Ntlm;
@@ -504,16 +549,28 @@ impl Ntlm {
     /// ```
     pub fn from_hash(domain: String, user: String, password_hash: &[u8]) -> Self {
         Ntlm {
-            response_key_nt: ntowfv2_hash(password_hash, &user, &domain),
-            response_key_lm: ntowfv2_hash(password_hash, &user, &domain),
             domain,
             user,
             password: String::new(),
+            password_hash: Some(password_hash.to_vec()),
             negotiate_message: None,
             exported_session_key: None,
             is_unicode: false,
         }
     }
+
+    /// NT and LM response keys of the credentials
+    fn response_keys(&self) -> RdpResult<(Vec<u8>, Vec<u8>)> {
+        match &self.password_hash {
+            Some(hash) => {
+                Ok((ntowfv2_hash(hash, &self.user, &self.domain)?, ntowfv2_hash(hash, &self.user, &self.domain)?))
+            }
+            None => Ok((
+                ntowfv2(&self.password, &self.user, &self.domain)?,
+                lmowfv2(&self.password, &self.user, &self.domain)?,
+            )),
+        }
+    }
 }
 
 impl AuthenticationProtocol for Ntlm {
@@ -557,25 +614,31 @@ impl AuthenticationProtocol for Ntlm {
             cast!(DataType::U32, result["TargetInfoBufferOffset"])?,
         )?)?;
 
-        let timestamp = if target_info.contains_key(&AvId::MsvAvTimestamp) {
-            target_info[&AvId::MsvAvTimestamp].clone()
-        } else {
-            panic!("no timestamp available")
+        let timestamp = match target_info.get(&AvId::MsvAvTimestamp) {
+            Some(timestamp) => timestamp.clone(),
+            None => {
+                return Err(Error::RdpError(RdpError::new(
+                    RdpErrorKind::InvalidRespond,
+                    "NTLM: No timestamp in the challenge target info",
+                )))
+            }
         };
 
         // generate client challenge
         let client_challenge = random(8);
 
+        let (response_key_nt, response_key_lm) = self.response_keys()?;
         let response = compute_response_v2(
-            &self.response_key_nt,
-            &self.response_key_lm,
+            &response_key_nt,
+            &response_key_lm,
             server_challenge,
             &client_challenge,
             &timestamp,
-            target_name,
-        );
+            &target_info_with_mic(target_name)?,
+        )?;
         let nt_challenge_response = response.0;
-        let lm_challenge_response = response.1;
+        // The MIC replaces the LMv2 response when the server sends a timestamp
+        let lm_challenge_response = z(24);
         let session_base_key = response.2;
         let key_exchange_key = kx_key_v2(&session_base_key, &lm_challenge_response, server_challenge);
         self.exported_session_key = Some(random(16));
@@ -602,13 +665,13 @@ impl AuthenticationProtocol for Ntlm {
         let tmp_final_auth_message =
             to_vec(&trame![to_vec(&auth_message_compute.0), vec![0; 16], auth_message_compute.1.clone()]);
 
-        let signature = mic(
+        let signature = compute_mic(
             self.exported_session_key.as_ref().unwrap(),
             self.negotiate_message.as_ref().unwrap(),
             request,
             &tmp_final_auth_message,
-        );
-        Ok(to_vec(&trame![auth_message_compute.0, signature, auth_message_compute.1]))
+        )?;
+        Ok(to_vec(&trame![auth_message_compute.0, signature.to_vec(), auth_message_compute.1]))
     }
 
     /// We are now able to build a security interface
@@ -702,7 +765,7 @@ impl GenericSecurityService for NTLMv2SecurityInterface {
     fn gss_wrapex(&mut self, data: &[u8]) -> RdpResult<Vec<u8>> {
         let mut encrypted_data = vec![0; data.len()];
         self.encrypt.process(data, &mut encrypted_data);
-        let signature = mac(&mut self.encrypt, &self.signing_key, self.seq_num, data);
+        let signature = mac(&mut self.encrypt, &self.signing_key, self.seq_num, data)?;
         self.seq_num += 1;
         Ok(to_vec(&trame![signature, encrypted_data]))
     }
@@ -737,7 +800,7 @@ impl GenericSecurityService for NTLMv2SecurityInterface {
         // compute signature
         let seq_num = to_vec(&U32::LE(cast!(DataType::U32, signature["SeqNum"])?));
 
-        let computed_checksum = hmac_md5(&self.verify_key, &[seq_num, plaintext_payload.clone()].concat());
+        let computed_checksum = hmac_md5(&self.verify_key, &[seq_num, plaintext_payload.clone()].concat())?;
 
         if plaintext_checksum.as_slice() != &(computed_checksum[0..8]) {
             return Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidChecksum, "Invalid checksum on NTLMv2")));
@@ -776,6 +839,15 @@ mod test {
         );
     }
 
+    /// Test of md5 hash function
+    #[test]
+    fn test_md5() {
+        assert_eq!(
+            md5(b"foo"),
+            [0xac, 0xbd, 0x18, 0xdb, 0x4c, 0xc2, 0xf8, 0x5c, 0xed, 0xef, 0x65, 0x4f, 0xcc, 0xc4, 0xa4, 0xd8]
+        );
+    }
+
     /// Test of the unicode function
     #[test]
     fn test_unicode() {
@@ -786,7 +858,7 @@ mod test {
     #[test]
     fn test_hmacmd5() {
         assert_eq!(
-            hmac_md5(b"foo", b"bar"),
+            hmac_md5(b"foo", b"bar").unwrap(),
             [0x0c, 0x7a, 0x25, 0x02, 0x81, 0x31, 0x5a, 0xb8, 0x63, 0x54, 0x9f, 0x66, 0xcd, 0x8a, 0x3a, 0x53]
         );
     }
@@ -795,7 +867,7 @@ mod test {
     #[test]
     fn test_ntowfv2() {
         assert_eq!(
-            ntowfv2("foo", "user", "domain"),
+            ntowfv2("foo", "user", "domain").unwrap(),
             [0x6e, 0x53, 0xb9, 0x0, 0x97, 0x8c, 0x87, 0x1f, 0x91, 0xde, 0x6, 0x44, 0x9d, 0x8b, 0x8b, 0x81]
         );
     }
@@ -803,13 +875,13 @@ mod test {
     /// Test LMOWFv2 function
     #[test]
     fn test_lmowfv2() {
-        assert_eq!(lmowfv2("foo", "user", "domain"), ntowfv2("foo", "user", "domain"));
+        assert_eq!(lmowfv2("foo", "user", "domain").unwrap(), ntowfv2("foo", "user", "domain").unwrap());
     }
 
     /// Test compute response v2 function
     #[test]
     fn test_compute_response_v2() {
-        let response = compute_response_v2(b"a", b"b", b"c", b"d", b"e", b"f");
+        let response = compute_response_v2(b"a", b"b", b"c", b"d", b"e", b"f").unwrap();
         assert_eq!(
             response.0,
             [
@@ -854,7 +926,7 @@ mod test {
     #[test]
     fn test_mac() {
         assert_eq!(
-            mac(&mut Rc4::new(b"foo"), b"bar", 0, b"data"),
+            mac(&mut Rc4::new(b"foo"), b"bar", 0, b"data").unwrap(),
             [1, 0, 0, 0, 77, 211, 144, 84, 51, 242, 202, 176, 0, 0, 0, 0]
         );
     }
@@ -882,6 +954,156 @@ mod test {
         assert_eq!(compare_result[96..110], [119, 111, 114, 107, 115, 116, 97, 116, 105, 111, 110, 102, 111, 111]);
     }
 
+    /// NTLMv2 values of MS-NLMP 4.2.4
+    const SPEC_NT_HASH: [u8; 16] =
+        [0xa4, 0xf4, 0x9c, 0x40, 0x65, 0x10, 0xbd, 0xca, 0xb6, 0x82, 0x4e, 0xe7, 0xc3, 0x0f, 0xd8, 0x52];
+    const SPEC_RESPONSE_KEY: [u8; 16] =
+        [0x0c, 0x86, 0x8a, 0x40, 0x3b, 0xfd, 0x7a, 0x93, 0xa3, 0x00, 0x1e, 0xf2, 0x2e, 0xf0, 0x2e, 0x3f];
+    const SPEC_SERVER_CHALLENGE: [u8; 8] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+    const SPEC_CLIENT_CHALLENGE: [u8; 8] = [0xaa; 8];
+
+    /// AV pairs of the challenge followed by the reserved bytes of the blob
+    fn spec_server_name() -> Vec<u8> {
+        [
+            &[0x02, 0x00, 0x0c, 0x00][..],
+            &"Domain".to_utf16_le(),
+            &[0x01, 0x00, 0x0c, 0x00],
+            &"Server".to_utf16_le(),
+            &[0x00; 8],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_ntowfv2_spec() {
+        assert_eq!(md4(&"Password".to_utf16_le()), SPEC_NT_HASH);
+        assert_eq!(ntowfv2("Password", "User", "Domain").unwrap(), SPEC_RESPONSE_KEY);
+        assert_eq!(ntowfv2_hash(&SPEC_NT_HASH, "User", "Domain").unwrap(), SPEC_RESPONSE_KEY);
+    }
+
+    #[test]
+    fn test_from_hash_response_keys() {
+        let ntlm = Ntlm::from_hash("Domain".to_string(), "User".to_string(), &SPEC_NT_HASH);
+        let (response_key_nt, response_key_lm) = ntlm.response_keys().unwrap();
+        assert_eq!(response_key_nt, SPEC_RESPONSE_KEY);
+        assert_eq!(response_key_lm, SPEC_RESPONSE_KEY);
+        assert!(ntlm.get_password().is_empty());
+    }
+
+    #[test]
+    fn test_compute_response_v2_spec() {
+        let (nt_challenge_response, lm_challenge_response, session_base_key) = compute_response_v2(
+            &SPEC_RESPONSE_KEY,
+            &SPEC_RESPONSE_KEY,
+            &SPEC_SERVER_CHALLENGE,
+            &SPEC_CLIENT_CHALLENGE,
+            &[0; 8],
+            &spec_server_name(),
+        )
+        .unwrap();
+        assert_eq!(
+            nt_challenge_response[..16],
+            [0x68, 0xcd, 0x0a, 0xb8, 0x51, 0xe5, 0x1c, 0x96, 0xaa, 0xbc, 0x92, 0x7b, 0xeb, 0xef, 0x6a, 0x1c]
+        );
+        assert_eq!(
+            lm_challenge_response,
+            [
+                0x86, 0xc3, 0x50, 0x97, 0xac, 0x9c, 0xec, 0x10, 0x25, 0x54, 0x76, 0x4a, 0x57, 0xcc, 0xcc, 0x19, 0xaa,
+                0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa
+            ]
+        );
+        assert_eq!(
+            session_base_key,
+            [0x8d, 0xe4, 0x0c, 0xca, 0xdb, 0xc1, 0x4a, 0x82, 0xf1, 0x5c, 0xb0, 0xad, 0x0d, 0xe9, 0x5c, 0xa3]
+        );
+
+        // The exported session key, also the MIC key, is protected by the session base
+        // key
+        let key_exchange_key = kx_key_v2(&session_base_key, &lm_challenge_response, &SPEC_SERVER_CHALLENGE);
+        assert_eq!(
+            rc4k(&key_exchange_key, &[0x55; 16]),
+            [0xc5, 0xda, 0xd2, 0x54, 0x4f, 0xc9, 0x79, 0x90, 0x94, 0xce, 0x1c, 0xe9, 0x0b, 0xc9, 0xd0, 0x3e]
+        );
+    }
+
+    /// Keys and GSS_WrapEx output of MS-NLMP 4.2.4.4
+    #[test]
+    fn test_gss_wrapex_spec() {
+        let exported_session_key = [0x55; 16];
+        let sealing_key = seal_key(&exported_session_key, true);
+        assert_eq!(
+            sealing_key,
+            [0x59, 0xf6, 0x00, 0x97, 0x3c, 0xc4, 0x96, 0x0a, 0x25, 0x48, 0x0a, 0x7c, 0x19, 0x6e, 0x4c, 0x58]
+        );
+        let signing_key = sign_key(&exported_session_key, true);
+        assert_eq!(
+            signing_key,
+            [0x47, 0x88, 0xdc, 0x86, 0x1b, 0x47, 0x82, 0xf3, 0x5d, 0x43, 0xfd, 0x98, 0xfe, 0x1a, 0x2d, 0x39]
+        );
+
+        let mut interface = NTLMv2SecurityInterface::new(
+            Rc4::new(&sealing_key),
+            Rc4::new(&seal_key(&exported_session_key, false)),
+            signing_key,
+            sign_key(&exported_session_key, false),
+        );
+        let wrapped = interface.gss_wrapex(&"Plaintext".to_utf16_le()).unwrap();
+        assert_eq!(wrapped[..16], [0x01, 0x00, 0x00, 0x00, 0x7f, 0xb3, 0x8e, 0xc5, 0xc5, 0x5d, 0x49, 0x76, 0, 0, 0, 0]);
+        assert_eq!(
+            wrapped[16..],
+            [
+                0x54, 0xe5, 0x01, 0x65, 0xbf, 0x19, 0x36, 0xdc, 0x99, 0x60, 0x20, 0xc1, 0x81, 0x1b, 0x0f, 0x06, 0xfb,
+                0x5f
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compute_mic() {
+        assert_eq!(
+            compute_mic(&[0x55; 16], b"negotiate", b"challenge", b"authenticate").unwrap(),
+            [98, 198, 21, 197, 195, 118, 201, 250, 150, 0, 162, 25, 145, 182, 192, 40]
+        );
+    }
+
+    #[test]
+    fn test_target_info_with_mic() {
+        let pairs = &spec_server_name()[..spec_server_name().len() - 8];
+        let eol = [0x00, 0x00, 0x00, 0x00];
+        let target_info = [pairs, &[6, 0, 4, 0, 1, 0, 0, 0], &eol].concat();
+        assert_eq!(target_info_with_mic(&target_info).unwrap(), [pairs, &[6, 0, 4, 0, 3, 0, 0, 0], &eol].concat());
+        // the flags are added when the server sends none
+        assert_eq!(
+            target_info_with_mic(&spec_server_name()).unwrap(),
+            [pairs, &[6, 0, 4, 0, 2, 0, 0, 0], &eol].concat()
+        );
+    }
+
+    /// The MIC signs the three messages with a zero MIC field
+    /// MS-NLMP has no test vector with a MIC, the key is random
+    #[test]
+    fn test_authenticate_message_mic() {
+        let mut ntlm = Ntlm::new("Domain".to_string(), "User".to_string(), "Password".to_string());
+        let negotiate = ntlm.create_negotiate_message().unwrap();
+        let target_info =
+            [&spec_server_name()[..spec_server_name().len() - 8], &[7, 0, 8, 0], &[0; 8], &[0; 4]].concat();
+        let mut challenge = [&b"NTLMSSP\0"[..], &[2, 0, 0, 0, 0, 0, 0, 0, 48, 0, 0, 0, 1, 0, 0, 0]].concat();
+        challenge.extend(SPEC_SERVER_CHALLENGE);
+        challenge.extend([0; 8]);
+        challenge.extend((target_info.len() as u16).to_le_bytes().repeat(2));
+        challenge.extend(48_u32.to_le_bytes());
+        challenge.extend(&target_info);
+
+        let mut authenticate = ntlm.read_challenge_message(&challenge).unwrap();
+        // no version, the MIC follows the fixed fields and the LMv2 response is empty
+        let mic = authenticate[64..80].to_vec();
+        assert_eq!(authenticate[80..104], [0; 24]);
+        assert!(authenticate.windows(8).any(|pair| pair == [6, 0, 4, 0, 2, 0, 0, 0]));
+        authenticate[64..80].fill(0);
+        let session_key = ntlm.exported_session_key.as_ref().unwrap();
+        assert_eq!(mic, compute_mic(session_key, &negotiate, &challenge, &authenticate).unwrap());
+    }
+
     #[test]
     fn test_rc4() {
         let mut key = Rc4::new(b"foo");
@@ -1,5 +1,15 @@
 use crate::model::error::RdpResult;
 
+/// Authentication mechanism used during NLA
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AuthProtocol {
+    /// NTLMv2 with the credentials given to the connector
+    Ntlm,
+    /// Kerberos through the system GSSAPI library
+    /// Need the `kerberos` feature
+    Kerberos,
+}
+
 /// This is a trait use by authentication
 /// protocol to provide a context
 /// abstract for CSSP
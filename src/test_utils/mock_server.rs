@@ -0,0 +1,173 @@
+use std::io::{Read, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::TcpStream;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+
+#[cfg(not(target_arch = "wasm32"))]
+use native_tls::{Identity, TlsAcceptor};
+
+use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use crate::model::link::Stream;
+
+fn mock_error(message: &str) -> Error {
+    Error::RdpError(RdpError::new(RdpErrorKind::InvalidData, &format!("MOCK: {}", message)))
+}
+
+/// Load a PDU recorded in `tests/fixtures`
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Unable to read fixture {:?} [{}]", path, e))
+}
+
+/// Check that a PDU is a TPKT carrying a X224 data PDU
+/// followed by the given payload prefix
+pub fn is_x224_data(prefix: &[u8]) -> impl Fn(&[u8]) -> bool + Send + 'static {
+    let prefix = prefix.to_vec();
+    move |pdu| pdu.len() >= 7 && pdu[0] == 3 && pdu[4..7] == [2, 0xf0, 0x80] && pdu[7..].starts_with(&prefix)
+}
+
+type Matcher = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+/// Step of the server script
+enum Step {
+    /// Read a PDU and check it
+    Receive(Matcher),
+    /// Write raw bytes
+    Send(Vec<u8>),
+    /// Switch to TLS with a self-signed certificate
+    #[cfg(not(target_arch = "wasm32"))]
+    StartTls,
+    /// Keep the connection open without reading
+    /// until the sender is dropped
+    #[cfg(not(target_arch = "wasm32"))]
+    Hold(Receiver<()>),
+}
+
+/// Read a full PDU from the client
+/// TPKT, DER encoded CredSSP and fast path framing are handled
+fn read_pdu(stream: &mut dyn Read) -> std::io::Result<Vec<u8>> {
+    let mut pdu = vec![0; 2];
+    stream.read_exact(&mut pdu)?;
+    let length = match pdu[0] {
+        3 => {
+            pdu.resize(4, 0);
+            stream.read_exact(&mut pdu[2..])?;
+            usize::from(u16::from_be_bytes([pdu[2], pdu[3]]))
+        }
+        0x30 if pdu[1] & 0x80 == 0 => 2 + usize::from(pdu[1]),
+        0x30 => {
+            let size = usize::from(pdu[1] & 0x7f);
+            pdu.resize(2 + size, 0);
+            stream.read_exact(&mut pdu[2..])?;
+            2 + size + pdu[2..].iter().fold(0, |length, byte| (length << 8) | usize::from(*byte))
+        }
+        _ if pdu[1] & 0x80 != 0 => {
+            pdu.resize(3, 0);
+            stream.read_exact(&mut pdu[2..])?;
+            usize::from(u16::from_be_bytes([pdu[1] & 0x7f, pdu[2]]))
+        }
+        _ => usize::from(pdu[1]),
+    };
+    if length < pdu.len() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid PDU length"));
+    }
+    let start = pdu.len();
+    pdu.resize(length, 0);
+    stream.read_exact(&mut pdu[start..])?;
+    Ok(pdu)
+}
+
+/// Synthetic RDP server playing a script
+/// Used to test the client state machine without a Windows server
+///
+/// The server accepts a single connection on a random loopback port
+/// and closes it at the end of the script
+pub struct MockRdpServer {
+    listener: TcpListener,
+    steps: Vec<Step>,
+}
+
+impl MockRdpServer {
+    pub fn new() -> Self {
+        MockRdpServer {
+            listener: TcpListener::bind("127.0.0.1:0").expect("Unable to bind mock server"),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Address to connect to
+    pub fn address(&self) -> SocketAddr { self.listener.local_addr().expect("Mock server without address") }
+
+    /// Expect a PDU from the client
+    pub fn expect_receive(mut self, matcher: impl Fn(&[u8]) -> bool + Send + 'static) -> Self {
+        self.steps.push(Step::Receive(Box::new(matcher)));
+        self
+    }
+
+    /// Send bytes to the client
+    pub fn send(mut self, bytes: &[u8]) -> Self {
+        self.steps.push(Step::Send(bytes.to_vec()));
+        self
+    }
+
+    /// Upgrade the connection to TLS like after a security negotiation
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_tls(mut self) -> Self {
+        self.steps.push(Step::StartTls);
+        self
+    }
+
+    /// Stop reading like a stalled server
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn hold(mut self, release: Receiver<()>) -> Self {
+        self.steps.push(Step::Hold(release));
+        self
+    }
+
+    /// Play the script in a background thread
+    /// The result tells if the client behaved as expected
+    pub fn run(self) -> JoinHandle<RdpResult<()>> {
+        thread::spawn(move || {
+            let (tcp, _) = self.listener.accept()?;
+            let mut stream = Stream::Raw(tcp);
+            for (index, step) in self.steps.into_iter().enumerate() {
+                match step {
+                    Step::Receive(matcher) => {
+                        let pdu = read_pdu(&mut stream)?;
+                        if !matcher(&pdu) {
+                            return Err(mock_error(&format!("Unexpected PDU at step {} {:02x?}", index, pdu)));
+                        }
+                    }
+                    Step::Send(bytes) => stream.write_all(&bytes)?,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Step::StartTls => stream = Stream::Ssl(tls_acceptor()?.accept(raw_stream(stream)?)?),
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Step::Hold(release) => while release.recv().is_ok() {},
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn raw_stream(stream: Stream<TcpStream>) -> RdpResult<TcpStream> {
+    match stream {
+        Stream::Raw(tcp) => Ok(tcp),
+        _ => Err(mock_error("TLS is already started")),
+    }
+}
+
+/// TLS acceptor with a certificate generated for the test
+#[cfg(not(target_arch = "wasm32"))]
+fn tls_acceptor() -> RdpResult<TlsAcceptor> {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .map_err(|e| mock_error(&format!("Unable to generate certificate [{}]", e)))?;
+    let identity =
+        Identity::from_pkcs8(certified.cert.pem().as_bytes(), certified.key_pair.serialize_pem().as_bytes())?;
+    Ok(TlsAcceptor::new(identity)?)
+}
@@ -0,0 +1,2 @@
+//! Helpers shared by the protocol unit tests
+pub mod mock_server;
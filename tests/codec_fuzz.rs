@@ -0,0 +1,103 @@
+//! Decompression functions read untrusted data from the network,
+//! any input must give a result or an error, never a panic
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::sync::Arc;
+
+use proptest::prelude::*;
+use rdp::codec::jpeg::jpeg_decompress;
+use rdp::codec::nscodec::nscodec_decompress;
+use rdp::codec::rfx::rfx_decompress;
+use rdp::codec::rle::{
+    rgb24torgb32, rgb555torgb32, rgb565torgb32, rle_16_decompress, rle_24_decompress, rle_32_decompress,
+    rle_8_decompress,
+};
+use rdp::codec::zgfx::Zgfx;
+use rdp::core::capability::BitmapCodec;
+use rdp::core::event::BitmapEvent;
+
+/// Untrusted payload of a bitmap
+fn payload() -> impl Strategy<Value = Vec<u8>> { prop::collection::vec(any::<u8>(), 0..8192) }
+
+/// Bitmap size, empty bitmaps included
+fn size() -> impl Strategy<Value = (u16, u16)> { (0_u16..=64, 0_u16..=64) }
+
+proptest! {
+    #[test]
+    fn rle_never_panics(input in payload(), (width, height) in size()) {
+        let (width, height) = (usize::from(width), usize::from(height));
+        let _ = rle_8_decompress(&input, width, height, &mut vec![0; width * height]);
+        let _ = rle_16_decompress(&input, width, height, &mut vec![0; width * height]);
+        let _ = rle_24_decompress(&input, width, height, &mut vec![0; width * height * 3]);
+        let _ = rle_32_decompress(&input, width as u32, height as u32, &mut vec![0; width * height * 4]);
+    }
+
+    #[test]
+    fn rle_32_never_panics_with_header(mut input in payload(), (width, height) in size()) {
+        // past the header check
+        input.insert(0, 0x10);
+        let mut output = vec![0; width as usize * height as usize * 4];
+        let _ = rle_32_decompress(&input, u32::from(width), u32::from(height), &mut output);
+    }
+
+    #[test]
+    fn codecs_never_panic(input in payload(), (width, height) in size()) {
+        let mut output = vec![0; width as usize * height as usize * 4];
+        let _ = nscodec_decompress(&input, u32::from(width), u32::from(height), &mut output);
+        let _ = rfx_decompress(&input, u32::from(width), u32::from(height), &mut output);
+        let _ = jpeg_decompress(&input, u32::from(width), u32::from(height), &mut output);
+        let _ = Zgfx::new().decompress(&input);
+    }
+
+    #[test]
+    fn bitmap_event_never_panics(
+        data in payload(),
+        (width, height) in size(),
+        bpp in prop::sample::select(vec![8_u16, 15, 16, 24, 32]),
+        is_compress in any::<bool>(),
+        codec in prop::option::of(prop::sample::select(vec![BitmapCodec::NsCodec, BitmapCodec::RemoteFx, BitmapCodec::Jpeg])),
+    ) {
+        let bitmap = BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: width.saturating_sub(1),
+            dest_bottom: height.saturating_sub(1),
+            width,
+            height,
+            bpp,
+            is_compress,
+            codec,
+            monitor: None,
+            palette: Some(Arc::new([[0; 3]; 256])),
+            data,
+        };
+        let _ = bitmap.decompress();
+    }
+
+    #[test]
+    fn pixel_conversion_size(input in prop::collection::vec(any::<u16>(), 0..4096)) {
+        prop_assert_eq!(rgb565torgb32(&input).len(), input.len() * 4);
+        prop_assert_eq!(rgb555torgb32(&input).len(), input.len() * 4);
+        let bytes: Vec<u8> = input.iter().flat_map(|v| v.to_le_bytes()).collect();
+        prop_assert_eq!(rgb24torgb32(&bytes).len(), bytes.len() / 3 * 4);
+    }
+
+    /// A raw plane of each color without run length
+    /// is the smallest valid input and fills the whole output
+    #[test]
+    fn rle_32_minimal_input((width, height) in (1_u32..=15, 1_u32..=64), color in any::<[u8; 4]>()) {
+        let mut input = vec![0x10];
+        for plane in color.iter().rev() {
+            // first line as raw colors, others as zero deltas
+            input.push((width as u8) << 4);
+            input.extend(std::iter::repeat_n(*plane, width as usize));
+            for _ in 1..height {
+                input.push((width as u8) << 4);
+                input.extend(std::iter::repeat_n(0, width as usize));
+            }
+        }
+        let mut output = vec![0; (width * height * 4) as usize];
+        rle_32_decompress(&input, width, height, &mut output).unwrap();
+        prop_assert!(output.chunks_exact(4).all(|pixel| pixel == color));
+    }
+}
@@ -0,0 +1,127 @@
+//! WebSocket stream of the wasm clients, run with
+//! `wasm-pack test --node --features wasm --test wasm`
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use std::io::{Cursor, ErrorKind, Read, Write};
+
+use js_sys::{Array, Function, Object, Reflect, Uint8Array};
+use rdp::core::client::Connector;
+use rdp::core::websocket::WebSocketStream;
+use rdp::model::error::{Error, RdpErrorKind};
+use rdp::model::link::{Link, Stream};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::wasm_bindgen_test;
+use web_sys::{MessageEvent, MessageEventInit, WebSocket};
+
+/// Open socket keeping the sent messages in `sent`,
+/// node has no WebSocket to connect to a server
+fn socket() -> WebSocket {
+    let socket = Object::new();
+    Reflect::set(&socket, &"readyState".into(), &JsValue::from(WebSocket::OPEN)).unwrap();
+    Reflect::set(&socket, &"sent".into(), &Array::new()).unwrap();
+    let send = Function::new_with_args("data", "this.sent.push(Array.from(data))");
+    Reflect::set(&socket, &"send".into(), &send).unwrap();
+    socket.unchecked_into()
+}
+
+/// Deliver a binary message as the browser does
+fn receive(socket: &WebSocket, data: &[u8]) {
+    let init = MessageEventInit::new();
+    init.set_data(&Uint8Array::from(data).buffer());
+    let event = MessageEvent::new_with_event_init_dict("message", &init).unwrap();
+    socket.onmessage().unwrap().call1(socket, &event).unwrap();
+}
+
+/// Socket of a mock server which answers the n-th sent message with
+/// the n-th reply, from the event loop as a browser does
+fn scripted_socket(replies: &[&[u8]]) -> WebSocket {
+    let socket = socket();
+    let script = replies.iter().map(|reply| JsValue::from(Uint8Array::from(*reply))).collect::<Array>();
+    Reflect::set(&socket, &"replies".into(), &script).unwrap();
+    let send = Function::new_with_args(
+        "data",
+        "this.sent.push(Array.from(data));
+        const reply = this.replies.shift();
+        if (reply && reply.length > 0) {
+            setTimeout(() => this.onmessage(new MessageEvent('message', { data: reply.buffer })), 0);
+        }",
+    );
+    Reflect::set(&socket, &"send".into(), &send).unwrap();
+    socket
+}
+
+fn sent(socket: &WebSocket) -> Vec<Vec<u8>> {
+    let sent = Reflect::get(socket, &"sent".into()).unwrap();
+    sent.unchecked_into::<Array>().iter().map(|message| Uint8Array::new(&message).to_vec()).collect()
+}
+
+#[wasm_bindgen_test]
+fn test_read_frames() {
+    let socket = socket();
+    let mut stream = WebSocketStream::from_socket(socket.clone());
+    let mut buffer = [0; 4];
+    assert!(!stream.frame_ready());
+    assert_eq!(stream.read(&mut buffer).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+    receive(&socket, &[0x04, 4, 1]);
+    assert!(!stream.frame_ready());
+    receive(&socket, &[2, 3]);
+    assert!(stream.frame_ready());
+    assert_eq!(stream.read(&mut buffer).unwrap(), 4);
+    assert_eq!(buffer, [0x04, 4, 1, 2]);
+
+    socket.onclose().unwrap().call1(&socket, &Object::new()).unwrap();
+    assert!(stream.frame_ready());
+    assert_eq!(stream.read(&mut buffer).unwrap(), 1);
+    assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+}
+
+#[wasm_bindgen_test]
+fn test_write_messages() {
+    let socket = socket();
+    let mut stream = WebSocketStream::from_socket(socket.clone());
+    stream.write_all(&[3, 0, 0, 4]).unwrap();
+    stream.write_all(&[0x04, 2]).unwrap();
+    assert_eq!(sent(&socket), [vec![3, 0, 0, 4], vec![0x04, 2]]);
+
+    Reflect::set(&socket, &"readyState".into(), &JsValue::from(WebSocket::CLOSED)).unwrap();
+    assert_eq!(stream.write(&[0]).unwrap_err().kind(), ErrorKind::NotConnected);
+}
+
+#[wasm_bindgen_test]
+fn test_no_tls() {
+    let link = Link::new(Stream::Raw(Cursor::new(vec![])));
+    match link.start_ssl(false) {
+        Err(Error::RdpError(e)) => assert_eq!(e.kind(), RdpErrorKind::NotImplemented),
+        _ => panic!("expected no TLS on wasm32"),
+    }
+}
+
+#[wasm_bindgen_test]
+async fn test_connect_mock_server() {
+    let mut licensing = include_bytes!("fixtures/license_valid_client.bin").to_vec();
+    licensing.extend(include_bytes!("fixtures/server_activation.bin"));
+    let socket = scripted_socket(&[
+        include_bytes!("fixtures/x224_confirm_rdp.bin"),
+        include_bytes!("fixtures/mcs_connect_response_no_encryption.bin"),
+        // erect domain request has no answer
+        &[],
+        &[3, 0, 0, 11, 2, 0xf0, 0x80, 0x2e, 0, 0, 6],
+        &[3, 0, 0, 15, 2, 0xf0, 0x80, 0x3e, 0, 0, 6, 3, 0xef, 3, 0xef],
+        &[3, 0, 0, 15, 2, 0xf0, 0x80, 0x3e, 0, 0, 6, 3, 0xeb, 3, 0xeb],
+        &licensing,
+    ]);
+    let stream = WebSocketStream::from_socket(socket.clone());
+    let mut client = Connector::new().disable_encryption().connect_websocket(stream.clone()).await.unwrap();
+    // x224 request, connect initial, erect domain, attach user,
+    // two join requests and client info
+    assert_eq!(sent(&socket).len(), 7);
+
+    // demand active, synchronize, cooperate, granted control and font map
+    for _ in 0..5 {
+        assert!(stream.frame_ready());
+        client.read(|_| ()).unwrap();
+    }
+    assert!(!stream.frame_ready());
+    assert!(sent(&socket).len() > 7);
+}